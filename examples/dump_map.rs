@@ -0,0 +1,34 @@
+//! Prints a stage as text, solid tiles as `#`, spikes as `^`, slopes as `/` and water as `~`.
+//!
+//! `cargo run --example dump_map -- data/Stage/Cave.pxm data/Stage/Cave.pxa`
+
+use std::env;
+use std::fs::File;
+
+use doukutsu_rs::{GameResult, Map};
+
+fn main() -> GameResult {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 3 {
+        eprintln!("Usage: {} <map.pxm> <tileset.pxa>", args[0]);
+        return Ok(());
+    }
+
+    let map = Map::load_from(File::open(&args[1])?, File::open(&args[2])?)?;
+    println!("{}x{}", map.width, map.height);
+
+    for y in 0..map.height {
+        let row: String = (0..map.width)
+            .map(|x| match map.get_attribute(x as isize, y as isize) {
+                0x41 | 0x43 | 0x46 | 0x61 => '#',
+                0x42 | 0x62 => '^',
+                0x50..=0x57 | 0x70..=0x77 => '/',
+                0x60..=0x7f => '~',
+                _ => ' ',
+            })
+            .collect();
+        println!("{}", row);
+    }
+
+    Ok(())
+}
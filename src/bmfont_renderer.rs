@@ -1,17 +1,30 @@
+use std::cell::RefCell;
 use std::collections::HashSet;
 use std::path::PathBuf;
 
-use crate::bmfont::BMFont;
+use crate::bmfont::{BMFont, BmChar};
 use crate::common::{FILE_TYPES, Rect};
 use crate::engine_constants::EngineConstants;
 use crate::ggez::{Context, filesystem, GameResult};
 use crate::ggez::GameError::ResourceLoadError;
+use crate::glyph_atlas::{AtlasGlyph, GlyphAtlas};
 use crate::str;
 use crate::texture_set::TextureSet;
 
+/// Drawn in place of the characters missing from both the bitmap font and the fallback fonts.
+const MISSING_CHAR: char = '?';
+
+/// Where the glyph of a character comes from, the bitmap font is preferred so the text keeps the vanilla look.
+enum Glyph<'a> {
+    Bitmap(&'a BmChar),
+    Atlas(AtlasGlyph),
+}
+
 pub struct BMFontRenderer {
     font: BMFont,
     pages: Vec<String>,
+    /// Characters the bitmap font doesn't have, rasterized as they show up.
+    fallback: RefCell<GlyphAtlas>,
 }
 
 impl BMFontRenderer {
@@ -26,7 +39,7 @@ impl BMFontRenderer {
         let mut pages = Vec::new();
 
         println!("stem: {:?}", stem);
-        let (zeros, ext, format) = FILE_TYPES
+        let (zeros, _, _) = FILE_TYPES
             .iter()
             .map(|ext| (1, ext, format!("{}_0{}", stem.to_string_lossy(), ext)))
             .find(|(_, _, path)| filesystem::exists(ctx, &path))
@@ -46,15 +59,62 @@ impl BMFontRenderer {
         Ok(Self {
             font,
             pages,
+            fallback: RefCell::new(GlyphAtlas::new(Vec::new(), 0.0, 0.0)),
         })
     }
 
+    /// Font without any characters, for tests which don't draw anything.
+    #[cfg(test)]
+    pub fn empty() -> BMFontRenderer {
+        BMFontRenderer {
+            font: BMFont { pages: 0, font_size: 0, line_height: 0, base: 0, chars: Default::default() },
+            pages: Vec::new(),
+            fallback: RefCell::new(GlyphAtlas::new(Vec::new(), 0.0, 0.0)),
+        }
+    }
+
+    /// Loads the TrueType fonts used for the characters missing from the bitmap font.
+    pub fn load_fallback(&mut self, constants: &EngineConstants, ctx: &mut Context) {
+        self.fallback = RefCell::new(GlyphAtlas::load(constants, self.font.base as f32, ctx));
+    }
+
+    /// Names of the glyph atlas textures.
+    pub fn pages(&self) -> &[String] {
+        &self.pages
+    }
+
+    /// Characters neither font has are drawn as '?', so missing text is noticed.
+    fn glyph(&self, chr: char) -> Option<Glyph<'_>> {
+        if let Some(glyph) = self.font.chars.get(&chr) {
+            return Some(Glyph::Bitmap(glyph));
+        }
+
+        if let Some(glyph) = self.fallback.borrow_mut().glyph(chr) {
+            return Some(Glyph::Atlas(glyph));
+        }
+
+        if chr.is_whitespace() {
+            return None;
+        }
+
+        self.font.chars.get(&MISSING_CHAR).map(Glyph::Bitmap)
+    }
+
+    fn advance(chr: char, glyph: &Glyph, font_scale: f32, scale: f32, space_offset: f32) -> f32 {
+        match glyph {
+            Glyph::Bitmap(glyph) => {
+                ((glyph.width as f32 + glyph.xoffset as f32) * font_scale).floor() + if chr != ' ' { scale } else { space_offset }
+            }
+            Glyph::Atlas(glyph) => (glyph.advance * font_scale).ceil(),
+        }
+    }
+
     pub fn text_width<I: Iterator<Item=char>>(&self, iter: I, constants: &EngineConstants) -> f32 {
         let mut offset_x = 0.0;
 
         for chr in iter {
-            if let Some(glyph) = self.font.chars.get(&chr) {
-                offset_x += ((glyph.width as f32 + glyph.xoffset as f32) * constants.font_scale).floor() + if chr != ' ' { 1.0 } else { constants.font_space_offset };
+            if let Some(glyph) = self.glyph(chr) {
+                offset_x += Self::advance(chr, &glyph, constants.font_scale, 1.0, constants.font_space_offset);
             }
         }
 
@@ -62,62 +122,90 @@ impl BMFontRenderer {
     }
 
     pub fn draw_text<I: Iterator<Item=char>>(&self, iter: I, x: f32, y: f32, constants: &EngineConstants, texture_set: &mut TextureSet, ctx: &mut Context) -> GameResult {
-        if self.pages.len() == 1 {
-            let batch = texture_set.get_or_load_batch(ctx, constants, self.pages.get(0).unwrap())?;
-            let mut offset_x = x;
-
-            for chr in iter {
-                if let Some(glyph) = self.font.chars.get(&chr) {
-                    batch.add_rect_scaled(offset_x, y + (glyph.yoffset as f32 * constants.font_scale).floor(),
-                                          constants.font_scale, constants.font_scale,
-                                          &Rect::<usize>::new_size(
-                                              glyph.x as usize, glyph.y as usize,
-                                              glyph.width as usize, glyph.height as usize,
-                                          ));
-
-                    offset_x += ((glyph.width as f32 + glyph.xoffset as f32) * constants.font_scale).floor() + if chr != ' ' { 1.0 } else { constants.font_space_offset };
-                }
+        self.draw_text_scaled(iter, x, y, 1.0, constants, texture_set, ctx)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw_text_scaled<I: Iterator<Item=char>>(&self, iter: I, x: f32, y: f32, scale: f32, constants: &EngineConstants, texture_set: &mut TextureSet, ctx: &mut Context) -> GameResult {
+        let font_scale = constants.font_scale * scale;
+        let space_offset = constants.font_space_offset * scale;
+
+        // laid out first, so the fallback glyphs are all rasterized before their pages are uploaded
+        let mut glyphs = Vec::new();
+        let mut offset_x = x;
+
+        for chr in iter {
+            if let Some(glyph) = self.glyph(chr) {
+                let advance = Self::advance(chr, &glyph, font_scale, scale, space_offset);
+                glyphs.push((offset_x, glyph));
+                offset_x += advance;
             }
+        }
 
-            batch.draw(ctx)?;
-        } else {
-            let mut pages = HashSet::new();
-            let mut chars = Vec::new();
-
-            for chr in iter {
-                if let Some(glyph) = self.font.chars.get(&chr) {
-                    pages.insert(glyph.page);
-                    chars.push((chr, glyph));
-                }
+        let mut pages = HashSet::new();
+        let mut atlas_pages = HashSet::new();
+        for (_, glyph) in glyphs.iter() {
+            match glyph {
+                Glyph::Bitmap(glyph) => { pages.insert(glyph.page as usize); }
+                Glyph::Atlas(glyph) if glyph.rect.width() > 0 => { atlas_pages.insert(glyph.page); }
+                Glyph::Atlas(_) => {}
             }
+        }
 
-            for page in pages {
-                let page_tex = if let Some(p) = self.pages.get(page as usize) {
-                    p
-                } else {
-                    continue;
-                };
+        for page in pages {
+            let page_tex = if let Some(p) = self.pages.get(page) {
+                p
+            } else {
+                continue;
+            };
 
-                let batch = texture_set.get_or_load_batch(ctx, constants, page_tex)?;
-                let mut offset_x = x;
+            let batch = texture_set.get_or_load_batch(ctx, constants, page_tex)?;
 
-                for (chr, glyph) in chars.iter() {
-                    if glyph.page == page {
-                        batch.add_rect_scaled(offset_x, y + (glyph.yoffset as f32 * constants.font_scale).floor(),
-                                              constants.font_scale, constants.font_scale,
+            for (offset_x, glyph) in glyphs.iter() {
+                if let Glyph::Bitmap(glyph) = glyph {
+                    if glyph.page as usize == page {
+                        batch.add_rect_scaled(*offset_x, y + (glyph.yoffset as f32 * font_scale).floor(),
+                                              font_scale, font_scale,
                                               &Rect::<usize>::new_size(
                                                   glyph.x as usize, glyph.y as usize,
                                                   glyph.width as usize, glyph.height as usize,
                                               ));
                     }
-
-                    offset_x += ((glyph.width as f32 + glyph.xoffset as f32) * constants.font_scale).floor() + if *chr != ' ' { 1.0 } else { constants.font_space_offset };
                 }
+            }
 
-                batch.draw(ctx)?;
+            batch.draw(ctx)?;
+        }
+
+        let mut atlas = self.fallback.borrow_mut();
+        for page in atlas_pages {
+            let batch = atlas.batch(page, ctx)?;
+
+            for (offset_x, glyph) in glyphs.iter() {
+                if let Glyph::Atlas(glyph) = glyph {
+                    if glyph.page == page {
+                        batch.add_rect_scaled(*offset_x + (glyph.xoffset * font_scale).floor(),
+                                              y + (glyph.yoffset * font_scale).floor(),
+                                              font_scale, font_scale,
+                                              &glyph.rect);
+                    }
+                }
             }
+
+            batch.draw(ctx)?;
         }
 
         Ok(())
     }
 }
+
+#[test]
+fn test_missing_char() {
+    let constants = EngineConstants::defaults();
+    let mut font = BMFontRenderer::empty();
+    let glyph = BmChar { x: 0, y: 0, width: 5, height: 8, xoffset: 0, yoffset: 0, xadvance: 6, page: 0, chnl: 0 };
+    font.font.chars.insert(MISSING_CHAR, glyph);
+
+    assert_eq!(font.text_width("日本".chars(), &constants), font.text_width("??".chars(), &constants));
+    assert_eq!(font.text_width(" ".chars(), &constants), 0.0);
+}
@@ -0,0 +1,379 @@
+use std::collections::VecDeque;
+
+use crate::common::{DIRECTION_FACE_PLAYER, FadeState};
+use crate::debug_arena::{arena_scene, floor_row, give_loadout, Loadout};
+use crate::ggez::{Context, GameResult};
+use crate::ggez::GameError::InvalidValue;
+use crate::life_bar::BossTarget;
+use crate::npc::{NPC, NPCMap, SCRIPT_NPC_START};
+use crate::save_state::SaveState;
+use crate::scene::game_scene::GameScene;
+use crate::SharedGameState;
+use crate::stats::BossFightRecord;
+use crate::str;
+
+/// Bosses of the original game which are a single NPC, in the order they're fought.
+pub static VANILLA_BOSSES: [(u16, &str); 7] = [
+    (68, "Balrog"),
+    (88, "Igor"),
+    (118, "Curly"),
+    (140, "Frenzied Toroko"),
+    (169, "Balrog (missiles)"),
+    (247, "Misery"),
+    (263, "Doctor"),
+];
+
+/// Dropped by every death, not something the boss forgot to clean up.
+const NOT_LEFTOVERS: [u16; 5] = [1, 3, 4, 86, 87];
+/// Ticks between a boss going down and the next one showing up.
+const NEXT_BOSS_DELAY: u16 = 100;
+
+/// Whether the NPC can be fought, bosses without sprites or an AI aren't implemented yet.
+// todo: none of `VANILLA_BOSSES` are, until then the rush is fought against stand-ins
+pub fn is_supported(npc_type: u16, state: &SharedGameState) -> bool {
+    NPC::has_ai(npc_type) && !state.constants.npc.rects(npc_type).is_empty()
+}
+
+pub struct BossRushConfig {
+    /// Stage whose tileset, background and NPC sheets the arena uses.
+    pub base_stage: usize,
+    /// Arena size in tiles.
+    pub width: usize,
+    pub height: usize,
+    pub loadout: Loadout,
+    /// NPC types and names, fought in this order.
+    pub bosses: Vec<(u16, String)>,
+}
+
+struct Fight {
+    npc_type: u16,
+    name: String,
+    npc_id: u16,
+    ticks: u64,
+    damage_taken: u64,
+    last_life: u16,
+}
+
+/// Debug mode fighting bosses one after another in a generated arena. The scene it was started from is kept
+/// as a save state in memory and restored once the rush is over or left.
+pub struct BossRush {
+    queue: VecDeque<(u16, String)>,
+    fight: Option<Fight>,
+    delay: u16,
+    results: Vec<BossFightRecord>,
+    loadout: Loadout,
+    width: usize,
+    height: usize,
+    total: usize,
+    snapshot: Vec<u8>,
+    /// Of the scene the rush was started from.
+    temporary_profile: bool,
+    /// Set by the debugger, ends the current fight as not defeated.
+    pub skip_requested: bool,
+    pub leave_requested: bool,
+}
+
+impl BossRush {
+    pub fn start(config: BossRushConfig, game_scene: &GameScene, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
+        let (base_stage, width, height) = (config.base_stage, config.width, config.height);
+        let rush = BossRush::new(config, game_scene, state)?;
+
+        let mut scene = arena_scene(state, ctx, base_stage, width, height)?;
+        give_loadout(&mut scene, &rush.loadout);
+
+        rush.enter(state);
+        state.next_scene = Some(Box::new(scene));
+
+        Ok(())
+    }
+
+    /// Checks the config and takes the snapshot of the scene to go back to.
+    fn new(config: BossRushConfig, game_scene: &GameScene, state: &SharedGameState) -> GameResult<BossRush> {
+        if config.bosses.is_empty() {
+            return Err(InvalidValue(str!("No bosses selected.")));
+        }
+        if state.boss_rush.is_some() {
+            return Err(InvalidValue(str!("A boss rush is already running.")));
+        }
+        if state.challenge.is_some() {
+            return Err(InvalidValue(str!("A boss rush can't be started during a challenge.")));
+        }
+
+        let mut snapshot = Vec::new();
+        SaveState::capture_into(game_scene, state, &mut snapshot)?;
+
+        Ok(BossRush {
+            total: config.bosses.len(),
+            queue: config.bosses.into_iter().collect(),
+            fight: None,
+            delay: NEXT_BOSS_DELAY,
+            results: Vec::new(),
+            loadout: config.loadout,
+            width: config.width,
+            height: config.height,
+            snapshot,
+            temporary_profile: state.temporary_profile,
+            skip_requested: false,
+            leave_requested: false,
+        })
+    }
+
+    /// Hands the game over to the rush, the caller switches to the arena.
+    fn enter(self, state: &mut SharedGameState) {
+        log::info!("Starting a boss rush of {} bosses.", self.total);
+
+        state.textscript_vm.reset();
+        state.control_flags.set_tick_world(true);
+        state.control_flags.set_control_enabled(true);
+        state.control_flags.set_interactions_disabled(false);
+        state.fade_state = FadeState::Visible;
+        state.carets.clear();
+        state.quake_counter = 0;
+        // nothing is saved and the stats stay off until the previous scene is back
+        state.temporary_profile = true;
+        state.boss_rush = Some(self);
+    }
+
+    /// One line for the debugger.
+    pub fn status(&self) -> String {
+        let number = self.results.len() + 1;
+        match self.fight.as_ref() {
+            Some(fight) => format!("Boss {}/{}: {}, {} damage taken", number, self.total, fight.name, fight.damage_taken),
+            None => format!("Boss {}/{} coming up", number.min(self.total), self.total),
+        }
+    }
+
+    /// Returns false once the rush is over, then `finish` has to be called.
+    pub fn tick(&mut self, game_scene: &mut GameScene, state: &mut SharedGameState) -> bool {
+        if self.leave_requested || !game_scene.player.cond.alive() || game_scene.player.life == 0 {
+            self.end_fight(game_scene, state, false);
+            return false;
+        }
+
+        if let Some(fight) = self.fight.as_mut() {
+            let life = game_scene.player.life;
+            fight.ticks += 1;
+            if life < fight.last_life {
+                fight.damage_taken += (fight.last_life - life) as u64;
+            }
+            fight.last_life = life;
+
+            let defeated = game_scene.boss_life(Some(BossTarget::NPC(fight.npc_id))).is_none();
+            if defeated || self.skip_requested {
+                self.skip_requested = false;
+                self.end_fight(game_scene, state, defeated);
+            }
+            return true;
+        }
+
+        if self.delay > 0 {
+            self.delay -= 1;
+            return true;
+        }
+
+        match self.queue.pop_front() {
+            Some((npc_type, name)) => {
+                self.spawn_boss(npc_type, name, game_scene, state);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn spawn_boss(&mut self, npc_type: u16, name: String, game_scene: &mut GameScene, state: &mut SharedGameState) {
+        // against the right wall, turned towards the player
+        let npc = NPCMap::create_script_npc(npc_type, self.width as isize - 4, floor_row(self.height) as isize,
+                                            DIRECTION_FACE_PLAYER, game_scene.player.x, &state.npc_table);
+        let life = npc.life;
+
+        match game_scene.npc_map.spawn(npc, SCRIPT_NPC_START) {
+            Some(npc_id) => {
+                log::info!("Boss rush: {} (#{}) enters the arena.", name, npc_type);
+                game_scene.boss_life_bar.attach(BossTarget::NPC(npc_id), Some(life));
+                self.fight = Some(Fight {
+                    npc_type,
+                    name,
+                    npc_id,
+                    ticks: 0,
+                    damage_taken: 0,
+                    last_life: game_scene.player.life,
+                });
+            }
+            None => {
+                log::warn!("Boss rush: no room for {} (#{}), skipping it.", name, npc_type);
+                self.results.push(BossFightRecord { npc_type, name, ticks: 0, damage_taken: 0, defeated: false, leftovers: Vec::new() });
+                self.delay = NEXT_BOSS_DELAY;
+            }
+        }
+    }
+
+    /// Records the fight and clears the arena for the next one.
+    fn end_fight(&mut self, game_scene: &mut GameScene, state: &mut SharedGameState, defeated: bool) {
+        let fight = match self.fight.take() {
+            Some(fight) => fight,
+            None => { return; }
+        };
+
+        let mut leftovers: Vec<u16> = game_scene.npc_map.npcs.values()
+            .filter_map(|npc| {
+                let npc = npc.borrow();
+                if npc.cond.alive() && npc.id != fight.npc_id && !NOT_LEFTOVERS.contains(&npc.npc_type) {
+                    Some(npc.npc_type)
+                } else {
+                    None
+                }
+            })
+            .collect();
+        leftovers.sort_unstable();
+        leftovers.dedup();
+
+        let record = BossFightRecord {
+            npc_type: fight.npc_type,
+            name: fight.name,
+            ticks: fight.ticks,
+            damage_taken: fight.damage_taken,
+            defeated,
+            leftovers,
+        };
+        log::info!("Boss rush: {}", record);
+        self.results.push(record);
+
+        game_scene.npc_map.clear();
+        game_scene.bullet_manager.bullets.clear();
+        game_scene.boss_life_bar.attach(BossTarget::NPC(fight.npc_id), None);
+        state.carets.clear();
+        state.quake_counter = 0;
+
+        if let Some(max_life) = self.loadout.max_life {
+            game_scene.player.max_life = max_life;
+        }
+        game_scene.player.life = game_scene.player.max_life;
+        game_scene.inventory.refill_all_ammo();
+        self.delay = NEXT_BOSS_DELAY;
+    }
+
+    /// Logs and records the results, then goes back to the scene the rush was started from.
+    pub fn finish(self, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
+        let scene = self.leave(state)?.into_scene(state, ctx)?;
+        state.next_scene = Some(Box::new(scene));
+
+        Ok(())
+    }
+
+    /// Logs and records the results, returns the snapshot of the scene the rush was started from.
+    fn leave(self, state: &mut SharedGameState) -> GameResult<SaveState> {
+        let defeated = self.results.iter().filter(|record| record.defeated).count();
+        log::info!("Boss rush over, {} of {} bosses defeated:", defeated, self.total);
+        for record in self.results.iter() {
+            log::info!("  {}", record);
+        }
+
+        state.stats.record_boss_rush(&self.results);
+        state.notifications.push(format!("Boss rush over, {} of {} bosses defeated.", defeated, self.total));
+
+        state.temporary_profile = self.temporary_profile;
+        SaveState::deserialize(&self.snapshot)
+    }
+}
+
+#[test]
+fn test_leave_boss_rush() {
+    use crate::map::Map;
+    use crate::stage::Stage;
+    use crate::weapon::WeaponType;
+
+    let map = || Map { width: 8, height: 8, tiles: vec![0; 64], attrib: [0; 0x100], revision: 0 };
+    let mut state = SharedGameState::for_tests();
+    let mut scene = GameScene::for_tests(&mut state, 0, Stage::for_tests(map()));
+    scene.player.x = 3 * 16 * 0x200;
+    scene.player.life = 2;
+    scene.inventory.add_weapon(WeaponType::PolarStar, 0);
+    scene.stage.map.change_tile(1, 1, 0x11);
+    let mut npc = NPCMap::create_npc(1, &state.npc_table);
+    npc.cond.set_alive(true);
+    scene.npc_map.spawn(npc, 1).unwrap();
+    state.game_flags.set(42, true);
+
+    let mut original = Vec::new();
+    SaveState::capture_into(&scene, &state, &mut original).unwrap();
+
+    let loadout = Loadout { weapons: Vec::new(), equip: 0, max_life: Some(50) };
+    let config = BossRushConfig { base_stage: 0, width: 8, height: 8, loadout, bosses: vec![(68, str!("Balrog"))] };
+    BossRush::new(config, &scene, &state).unwrap().enter(&mut state);
+    assert!(state.temporary_profile);
+
+    // left from the arena before the first boss shows up
+    let mut arena = GameScene::for_tests(&mut state, 0, Stage::for_tests(map()));
+    arena.player.cond.set_alive(true);
+    arena.player.life = 3;
+    state.game_flags.set(7, true);
+    let mut rush = state.boss_rush.take().unwrap();
+    assert!(rush.tick(&mut arena, &mut state));
+    rush.leave_requested = true;
+    assert!(!rush.tick(&mut arena, &mut state));
+
+    let restored = rush.leave(&mut state).unwrap();
+    assert!(!state.temporary_profile);
+
+    let mut back = GameScene::for_tests(&mut state, 0, Stage::for_tests(map()));
+    restored.restore(&mut back, &mut state);
+    let mut data = Vec::new();
+    SaveState::capture_into(&back, &state, &mut data).unwrap();
+    assert_eq!(data, original);
+}
+
+#[test]
+fn test_clear_boss_rush() {
+    use crate::bullet::Bullet;
+    use crate::common::{Direction, Rect};
+    use crate::debug_arena::arena_map;
+    use crate::stage::Stage;
+
+    let mut state = SharedGameState::for_tests();
+    assert!(!VANILLA_BOSSES.iter().any(|&(npc_type, _)| is_supported(npc_type, &state)));
+    // the Behemoth stands in for a boss
+    assert!(is_supported(2, &state));
+
+    let mut attrib = [0; 0x100];
+    // solid
+    attrib[1] = 0x41;
+    let map = || arena_map(attrib, 20, 10).unwrap();
+    let scene = GameScene::for_tests(&mut state, 0, Stage::for_tests(map()));
+    let loadout = Loadout { weapons: Vec::new(), equip: 0, max_life: Some(50) };
+    let config = BossRushConfig { base_stage: 0, width: 20, height: 10, loadout, bosses: vec![(2, str!("Behemoth"))] };
+    BossRush::new(config, &scene, &state).unwrap().enter(&mut state);
+
+    let mut arena = GameScene::for_tests(&mut state, 0, Stage::for_tests(map()));
+    arena.player.cond.set_alive(true);
+    arena.player.life = 3;
+    let mut rush = state.boss_rush.take().unwrap();
+    for _ in 0..=NEXT_BOSS_DELAY {
+        assert!(rush.tick(&mut arena, &mut state));
+    }
+
+    let npc_id = rush.fight.as_ref().unwrap().npc_id;
+    let (x, y) = {
+        let mut boss = arena.npc_map.npcs[&npc_id].borrow_mut();
+        boss.npc_flags.set_shootable(true);
+        boss.hit_bounds = Rect::new(0x1000, 0x1000, 0x1000, 0x1000);
+        boss.life = 1;
+        (boss.x, boss.y)
+    };
+    assert_eq!(arena.boss_life(arena.boss_life_bar.target), Some(1));
+
+    // Polar Star
+    let bullet = Bullet::new(x, y, 4, Direction::Left, &state.constants);
+    arena.bullet_manager.bullets.push(bullet);
+    arena.tick_npc_bullet_collissions(&mut state);
+    assert!(rush.tick(&mut arena, &mut state));
+    for _ in 0..NEXT_BOSS_DELAY {
+        assert!(rush.tick(&mut arena, &mut state));
+    }
+    assert!(!rush.tick(&mut arena, &mut state), "no bosses left");
+
+    rush.leave(&mut state).unwrap();
+    let results = &state.stats.last_boss_rush;
+    assert_eq!(results.len(), 1);
+    assert!(results[0].defeated);
+    assert_eq!(results[0].damage_taken, 0);
+}
@@ -1,4 +1,5 @@
 use std::{fmt, io};
+use std::borrow::Cow;
 use std::fmt::Debug;
 use std::io::Cursor;
 use std::io::ErrorKind;
@@ -9,11 +10,16 @@ use crate::ggez::{GameError, GameResult};
 use crate::ggez::GameError::FilesystemError;
 use crate::ggez::vfs::{OpenOptions, VFile, VFS, VMetadata};
 
+/// Inflates zlib compressed builtin data, which is only done when it's actually used.
+pub fn decompress(data: &[u8]) -> GameResult<Vec<u8>> {
+    inflate::inflate_bytes_zlib(data).map_err(|e| FilesystemError(format!("Corrupted builtin data: {}", e)))
+}
+
 #[derive(Debug)]
-pub struct BuiltinFile(Cursor<&'static [u8]>);
+pub struct BuiltinFile(Cursor<Cow<'static, [u8]>>);
 
 impl BuiltinFile {
-    pub fn from(buf: &'static [u8]) -> Box<dyn VFile> {
+    pub fn from(buf: Cow<'static, [u8]>) -> Box<dyn VFile> {
         Box::new(BuiltinFile(Cursor::new(buf)))
     }
 }
@@ -62,6 +68,8 @@ impl VMetadata for BuiltinMetadata {
 #[derive(Clone)]
 enum FSNode {
     File(&'static str, &'static [u8]),
+    /// zlib compressed, inflated every time it's opened.
+    Compressed(&'static str, &'static [u8]),
     Directory(&'static str, Vec<FSNode>),
 }
 
@@ -69,13 +77,15 @@ impl FSNode {
     fn get_name(&self) -> &'static str {
         match self {
             FSNode::File(name, _) => { name }
+            FSNode::Compressed(name, _) => { name }
             FSNode::Directory(name, _) => { name }
         }
     }
 
     fn to_file(&self) -> GameResult<Box<dyn VFile>> {
         match self {
-            FSNode::File(_, buf) => { Ok(BuiltinFile::from(buf)) }
+            FSNode::File(_, buf) => { Ok(BuiltinFile::from(Cow::Borrowed(*buf))) }
+            FSNode::Compressed(_, buf) => { Ok(BuiltinFile::from(Cow::Owned(decompress(buf)?))) }
             FSNode::Directory(name, _) => { Err(FilesystemError(format!("{} is a directory.", name))) }
         }
     }
@@ -88,6 +98,12 @@ impl FSNode {
                     size: buf.len() as u64,
                 })
             }
+            FSNode::Compressed(_, buf) => {
+                Box::new(BuiltinMetadata {
+                    is_dir: false,
+                    size: decompress(buf).map(|data| data.len() as u64).unwrap_or(0),
+                })
+            }
             FSNode::Directory(_, _) => {
                 Box::new(BuiltinMetadata {
                     is_dir: true,
@@ -107,10 +123,13 @@ impl BuiltinFS {
         Self {
             root: vec![
                 FSNode::Directory("builtin", vec![
-                    FSNode::File("builtin_font.fnt", include_bytes!("builtin/builtin_font.fnt")),
+                    FSNode::Compressed("builtin_font.fnt", include_bytes!("builtin/builtin_font.fnt.z")),
                     FSNode::File("builtin_font_0.png", include_bytes!("builtin/builtin_font_0.png")),
                     FSNode::File("builtin_font_1.png", include_bytes!("builtin/builtin_font_1.png")),
-                    FSNode::File("pixtone.pcm", include_bytes!("builtin/pixtone.pcm")),
+                    FSNode::File("demo.rep", include_bytes!("builtin/demo.rep")),
+                    FSNode::File("icon.png", include_bytes!("builtin/icon.png")),
+                    FSNode::Compressed("pixtone.pcm", include_bytes!("builtin/pixtone.pcm.z")),
+                    FSNode::File("prompts.png", include_bytes!("builtin/prompts.png")),
                 ])
             ],
         }
@@ -131,7 +150,7 @@ impl BuiltinFS {
 
                 for file in curr_dir {
                     match file {
-                        FSNode::File(name, _) if comp_name.eq(name) => {
+                        FSNode::File(name, _) | FSNode::Compressed(name, _) if comp_name.eq(name) => {
                             return if iter.peek().is_some() {
                                 Err(FilesystemError(format!("Expected a directory, found a file: {:?}", path)))
                             } else {
@@ -207,7 +226,7 @@ impl VFS for BuiltinFS {
 
                 Ok(Box::new(vec.into_iter()))
             }
-            Ok(FSNode::File(_, _)) => {
+            Ok(FSNode::File(_, _)) | Ok(FSNode::Compressed(_, _)) => {
                 Err(FilesystemError(format!("Expected a directory, found a file: {:?}", path)))
             }
             Err(e) => {
@@ -1,8 +1,9 @@
 use num_traits::clamp;
 
 use crate::caret::CaretType;
-use crate::common::{Condition, Direction, Flag, Rect};
+use crate::common::{Condition, Direction, Flag, Rect, to_tile};
 use crate::engine_constants::{BulletData, EngineConstants};
+use crate::map::Map;
 use crate::npc::NPCMap;
 use crate::physics::{OFF_X, OFF_Y, PhysicalEntity};
 use crate::SharedGameState;
@@ -48,6 +49,7 @@ impl BulletManager {
     }
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Bullet {
     pub btype: u16,
     pub x: isize,
@@ -335,6 +337,55 @@ impl Bullet {
         state.create_caret(self.x, self.y, CaretType::ProjectileDissipation, Direction::Right);
     }
 
+    /// Whether the bullet touches the block at given tile coordinates.
+    fn hits_block(&self, x: isize, y: isize) -> bool {
+        (self.x - self.hit_bounds.left as isize) < (x * 16 + 8) * 0x200
+            && (self.x + self.hit_bounds.right as isize) > (x * 16 - 8) * 0x200
+            && (self.y - self.hit_bounds.top as isize) < (y * 16 + 8) * 0x200
+            && (self.y + self.hit_bounds.bottom as isize) > (y * 16 - 8) * 0x200
+    }
+
+    /// Breaks the breakable block at given tile coordinates if the bullet touches it and is allowed to,
+    /// 0x20 in the bullet table lets it break blocks and 0x40 also lets it fly through them.
+    /// The block turns into the tile right before it in the tileset, so maps choose what's revealed.
+    /// Without per block breaking any block hit earlier in the tick counts, like in the original.
+    /// Returns whether the block has been broken.
+    fn break_block(&mut self, state: &mut SharedGameState, map: &mut Map, x: isize, y: isize) -> bool {
+        let hit = if state.settings.allow_per_block_breaking() { self.hits_block(x, y) } else { self.hit_flags.0 != 0 };
+        if !hit || !(self.flags.hit_left_slope() || self.flags.snack_destroy()) {
+            return false;
+        }
+
+        if !self.flags.snack_destroy() {
+            self.cond.set_alive(false);
+        }
+
+        let (tile_x, tile_y) = (x as usize, y as usize);
+        if let Some(&tile) = map.tiles.get(map.width * tile_y + tile_x) {
+            map.change_tile(tile_x, tile_y, tile.wrapping_sub(1));
+        }
+
+        state.create_caret(self.x, self.y, CaretType::ProjectileDissipation, Direction::Left);
+        state.sound_manager.play_sfx(12);
+
+        // the smoke comes out of the bullet's tile rather than the block's, like in the original
+        let (smoke_x, smoke_y) = (to_tile(self.x), to_tile(self.y));
+        for _ in 0..4 {
+            let mut npc = NPCMap::create_npc(4, &state.npc_table);
+
+            npc.cond.set_alive(true);
+            npc.direction = Direction::Left;
+            npc.x = smoke_x * 16 * 0x200;
+            npc.y = smoke_y * 16 * 0x200;
+            npc.vel_x = state.game_rng.range(-0x200..=0x200) as isize;
+            npc.vel_y = state.game_rng.range(-0x200..=0x200) as isize;
+
+            state.new_npcs.push(npc);
+        }
+
+        true
+    }
+
     fn judge_hit_block_destroy(&mut self, x: isize, y: isize, hit_attribs: &[u8; 4], state: &mut SharedGameState) {
         let mut hits = [false; 4];
         let block_x = (x * 16 + 8) * 0x200;
@@ -500,19 +551,15 @@ impl PhysicalEntity for Bullet {
         false
     }
 
-    fn judge_hit_block(&mut self, state: &mut SharedGameState, x: isize, y: isize) {
-        if (self.x - self.hit_bounds.left as isize) < (x * 16 + 8) * 0x200
-            && (self.x + self.hit_bounds.right as isize) > (x * 16 - 8) * 0x200
-            && (self.y - self.hit_bounds.top as isize) < (y * 16 + 8) * 0x200
-            && (self.y + self.hit_bounds.bottom as isize) > (y * 16 - 8) * 0x200
-        {
+    fn judge_hit_block(&mut self, _state: &mut SharedGameState, x: isize, y: isize) {
+        if self.hits_block(x, y) {
             self.hit_flags.set_weapon_hit_block(true);
         }
     }
 
     fn tick_map_collisions(&mut self, state: &mut SharedGameState, stage: &mut Stage) {
-        let x = clamp(self.x() / 16 / 0x200, 0, stage.map.width as isize);
-        let y = clamp(self.y() / 16 / 0x200, 0, stage.map.height as isize);
+        let x = clamp(to_tile(self.x()), 0, stage.map.width as isize);
+        let y = clamp(to_tile(self.y()), 0, stage.map.height as isize);
         let mut hit_attribs = [0u8; 4];
 
         if self.flags.hit_right_wall() { // ???
@@ -524,7 +571,7 @@ impl PhysicalEntity for Bullet {
                 break;
             }
 
-            let attrib = stage.map.get_attribute((x + ox) as usize, (y + oy) as usize);
+            let attrib = stage.map.get_attribute(x + ox, y + oy);
             hit_attribs[idx] = attrib;
 
             match attrib {
@@ -535,31 +582,7 @@ impl PhysicalEntity for Bullet {
                 0x43 => {
                     self.judge_hit_block(state, x + ox, y + oy);
 
-                    if self.hit_flags.0 != 0 && (self.flags.hit_left_slope() || self.flags.snack_destroy()) {
-                        if !self.flags.snack_destroy() {
-                            self.cond.set_alive(false);
-                        }
-
-                        state.create_caret(self.x, self.y, CaretType::ProjectileDissipation, Direction::Left);
-                        state.sound_manager.play_sfx(12);
-
-                        for _ in 0..4 {
-                            let mut npc = NPCMap::create_npc(4, &state.npc_table);
-
-                            npc.cond.set_alive(true);
-                            npc.direction = Direction::Left;
-                            npc.x = x * 16 * 0x200;
-                            npc.y = y * 16 * 0x200;
-                            npc.vel_x = state.game_rng.range(-0x200..0x200) as isize;
-                            npc.vel_y = state.game_rng.range(-0x200..0x200) as isize;
-
-                            state.new_npcs.push(npc);
-                        }
-
-                        if let Some(tile) = stage.map.tiles.get_mut(stage.map.width * (y + oy) as usize + (x + ox) as usize) {
-                            *tile = tile.wrapping_sub(1);
-                        }
-                    }
+                    self.break_block(state, &mut stage.map, x + ox, y + oy);
                 }
                 // Slopes
                 0x50 | 0x70 => {
@@ -593,3 +616,53 @@ impl PhysicalEntity for Bullet {
         self.judge_hit_block_destroy(x, y, &hit_attribs, state);
     }
 }
+
+#[test]
+fn test_break_block() {
+    use crate::settings::CompatMode;
+
+    let mut state = SharedGameState::for_tests();
+    state.settings.compat_mode = CompatMode::Enhanced;
+    let constants = EngineConstants::defaults();
+    let mut attrib = [0u8; 0x100];
+    attrib[0x11] = 0x43;
+
+    // (bullet type, breaks the block, still alive after)
+    let cases = [
+        (4, true, false), // Polar Star
+        (13, true, false), // Missile Launcher
+        (19, false, true), // Bubbler level 1
+        (25, true, false), // Blade
+        (37, true, true), // Spur, flies through
+    ];
+
+    for &(btype, breaks, alive) in cases.iter() {
+        let mut map = Map { width: 3, height: 3, tiles: vec![0, 0, 0, 0, 0x11, 0, 0, 0, 0], attrib, revision: 0 };
+        let mut bullet = Bullet::new(0x10 * 0x200, 0x10 * 0x200, btype, Direction::Right, &constants);
+
+        state.carets.clear();
+        state.new_npcs.clear();
+
+        assert_eq!(bullet.break_block(&mut state, &mut map, 1, 1), breaks, "bullet type {}", btype);
+        assert_eq!(map.tiles[4], if breaks { 0x10 } else { 0x11 }, "bullet type {}", btype);
+        assert_eq!(map.revision, if breaks { 1 } else { 0 });
+        assert_eq!(bullet.cond.alive(), alive, "bullet type {}", btype);
+        assert_eq!(!state.carets.is_empty(), breaks, "bullet type {}", btype);
+        assert_eq!(state.new_npcs.len(), if breaks { 4 } else { 0 }, "bullet type {}", btype);
+        // the bullet is in the block's own tile here
+        assert!(state.new_npcs.iter().all(|npc| npc.npc_type == 4 && npc.x == 0x2000 && npc.y == 0x2000));
+    }
+
+    // too far away
+    let mut map = Map { width: 3, height: 3, tiles: vec![0, 0, 0, 0, 0x11, 0, 0, 0, 0], attrib, revision: 0 };
+    let mut bullet = Bullet::new(0x30 * 0x200, 0x10 * 0x200, 4, Direction::Right, &constants);
+    assert!(!bullet.break_block(&mut state, &mut map, 1, 1));
+    assert_eq!(map.tiles[4], 0x11);
+
+    // the original breaks it anyway once another block has been hit
+    bullet.hit_flags.set_weapon_hit_block(true);
+    assert!(!bullet.break_block(&mut state, &mut map, 1, 1));
+    state.settings.compat_mode = CompatMode::Vanilla;
+    assert!(bullet.break_block(&mut state, &mut map, 1, 1));
+    assert_eq!(map.tiles[4], 0x10);
+}
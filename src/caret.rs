@@ -1,8 +1,7 @@
 use crate::bitfield;
-use crate::common::{Condition, Direction, Rect};
+use crate::common::{Condition, Direction, fix9_mul_div, Rect};
 use crate::engine_constants::EngineConstants;
-use crate::rng::RNG;
-use std::fs::read_to_string;
+use crate::rng::EffectRNG;
 
 #[derive(Debug, EnumIter, PartialEq, Eq, Hash, Copy, Clone)]
 pub enum CaretType {
@@ -26,6 +25,15 @@ pub enum CaretType {
     PushJumpKey,
 }
 
+/// Where the caret is drawn relative to the other entities.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum CaretLayer {
+    /// Behind NPCs, bullets and the player.
+    Behind,
+    /// On top of everything, including the foreground tiles.
+    Front,
+}
+
 pub struct Caret {
     pub ctype: CaretType,
     pub x: isize,
@@ -61,10 +69,45 @@ impl Caret {
         }
     }
 
-    pub fn tick(&mut self, rng: &RNG, constants: &EngineConstants) {
+    pub fn layer(&self, constants: &EngineConstants) -> CaretLayer {
+        constants.caret.layers[self.ctype as usize]
+    }
+
+    /// Picks the frame facing the caret's direction, anything other than left counts as right.
+    fn directional_rect(&self, left: &[Rect<usize>], right: &[Rect<usize>], index: usize) -> Rect<usize> {
+        match self.direction {
+            Direction::Left => left[index % left.len()],
+            _ => right[index % right.len()],
+        }
+    }
+
+    pub fn tick(&mut self, rng: &EffectRNG, constants: &EngineConstants) {
         match self.ctype {
             CaretType::None => {}
-            CaretType::Bubble => {}
+            CaretType::Bubble => {
+                if self.anim_num == 0 && self.anim_counter == 0 {
+                    self.vel_x = rng.range(-0x400..=0x400) as isize; // -2.0fix9..2.0fix9
+                    self.vel_y = rng.range(-0x400..=0) as isize;
+                }
+
+                self.vel_y += 0x40;
+                self.x += self.vel_x;
+                self.y += self.vel_y;
+
+                self.anim_counter += 1;
+                if self.anim_counter > 5 {
+                    self.anim_counter = 0;
+                    self.anim_num += 1;
+
+                    if self.anim_num as usize >= constants.caret.bubble_left_rects.len() {
+                        self.cond.set_alive(false);
+                        return;
+                    }
+                }
+
+                self.anim_rect = self.directional_rect(&constants.caret.bubble_left_rects,
+                                                       &constants.caret.bubble_right_rects, self.anim_num as usize);
+            }
             CaretType::ProjectileDissipation => {
                 match self.direction {
                     Direction::Left => {
@@ -174,12 +217,8 @@ impl Caret {
             CaretType::DrownedQuote => {
                 if self.anim_counter == 0 {
                     self.anim_counter = 1;
-
-                    match self.direction {
-                        Direction::Left => { self.anim_rect = constants.caret.drowned_quote_left_rect; }
-                        Direction::Right => { self.anim_rect = constants.caret.drowned_quote_right_rect; }
-                        _ => {}
-                    }
+                    self.anim_rect = self.directional_rect(&[constants.caret.drowned_quote_left_rect],
+                                                           &[constants.caret.drowned_quote_right_rect], 0);
                 }
             }
             CaretType::QuestionMark => {
@@ -192,13 +231,23 @@ impl Caret {
                     self.cond.set_alive(false);
                 }
 
-                self.anim_rect = match self.direction {
-                    Direction::Left => { constants.caret.question_left_rect }
-                    Direction::Right => { constants.caret.question_right_rect }
-                    _ => { self.anim_rect }
+                self.anim_rect = self.directional_rect(&[constants.caret.question_left_rect],
+                                                       &[constants.caret.question_right_rect], 0);
+            }
+            CaretType::LevelUp => {
+                self.anim_counter += 1;
+                if self.anim_counter < 20 {
+                    self.y -= 0x400; // 2.0fix9
+                }
+
+                if self.anim_counter == 80 {
+                    self.cond.set_alive(false);
                 }
+
+                // facing right shows "Level Down"
+                self.anim_rect = self.directional_rect(&constants.caret.level_up_rects,
+                                                       &constants.caret.level_down_rects, self.anim_counter as usize / 2);
             }
-            CaretType::LevelUp => {}
             CaretType::HurtParticles => {}
             CaretType::Explosion => {
                 if self.anim_counter == 0 {
@@ -219,11 +268,11 @@ impl Caret {
                 if self.anim_num == 0 {
                     match self.direction {
                         Direction::Left => {
-                            self.vel_x = rng.range(-0x300..0x300) as isize; // -1.5fix9..1.5fix9
-                            self.vel_y = rng.range(-0x100..0x100) as isize; // -0.5fix9..0.5fix9
+                            self.vel_x = rng.range(-0x300..=0x300) as isize; // -1.5fix9..1.5fix9
+                            self.vel_y = rng.range(-0x100..=0x100) as isize; // -0.5fix9..0.5fix9
                         }
                         Direction::Up => {
-                            self.vel_y = rng.range(1..3) as isize * 0x100;
+                            self.vel_y = rng.range(1..=3) as isize * 0x100;
                         }
                         _ => {}
                     }
@@ -232,8 +281,8 @@ impl Caret {
                 self.anim_num += 1;
 
                 if self.direction == Direction::Left {
-                    self.vel_x = (self.vel_x * 4) / 5;
-                    self.vel_y = (self.vel_y * 4) / 5;
+                    self.vel_x = fix9_mul_div(self.vel_x, 4, 5);
+                    self.vel_y = fix9_mul_div(self.vel_y, 4, 5);
                 }
 
                 self.x += self.vel_x;
@@ -266,3 +315,18 @@ impl Caret {
         !self.cond.alive()
     }
 }
+
+#[test]
+fn test_directional_rects() {
+    let constants = EngineConstants::defaults();
+    let rng = EffectRNG::new(0);
+
+    let mut left = Caret::new(0, 0, CaretType::LevelUp, Direction::Left, &constants);
+    let mut right = Caret::new(0, 0, CaretType::LevelUp, Direction::Right, &constants);
+    left.tick(&rng, &constants);
+    right.tick(&rng, &constants);
+
+    assert_eq!(left.anim_rect.top, constants.caret.level_up_rects[0].top);
+    assert_eq!(right.anim_rect.top, constants.caret.level_down_rects[0].top);
+    assert_eq!(left.layer(&constants), CaretLayer::Front);
+}
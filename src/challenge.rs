@@ -0,0 +1,129 @@
+use std::fs;
+use std::io::Read;
+use std::path::PathBuf;
+
+use crate::common::FadeState;
+use crate::ggez::{Context, filesystem, GameError, GameResult};
+use crate::ggez::GameError::InvalidValue;
+use crate::scene::game_scene::GameScene;
+use crate::SharedGameState;
+use crate::text_script::TextScriptExecutionState;
+
+/// Single entry of `challenges.json` in the data directory.
+// todo: read the challenge list from CS+ data files too
+#[derive(Deserialize, Clone, Debug)]
+pub struct Challenge {
+    /// Used as the name of the best time record file.
+    pub id: String,
+    pub name: String,
+    pub stage: usize,
+    /// Starting position, in tiles.
+    pub x: isize,
+    pub y: isize,
+    pub start_event: u16,
+    /// The challenge is completed as soon as this event starts running.
+    pub end_event: u16,
+}
+
+/// State of a challenge being played.
+pub struct ChallengeRun {
+    pub challenge: Challenge,
+    pub ticks: usize,
+    pub finished: bool,
+}
+
+impl Challenge {
+    pub fn load_list(state: &SharedGameState, ctx: &mut Context) -> GameResult<Vec<Challenge>> {
+        let path = [&state.base_path, "challenges.json"].join("");
+        if !filesystem::exists(ctx, &path) {
+            return Ok(Vec::new());
+        }
+
+        let mut data = String::new();
+        filesystem::open(ctx, &path)?.read_to_string(&mut data)?;
+
+        serde_json::from_str(&data)
+            .map_err(|e| GameError::from(e).in_file(&path))
+    }
+
+    /// Boots into the challenge with a temporary profile.
+    pub fn start(&self, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
+        if self.stage >= state.stages.len() {
+            return Err(InvalidValue(format!("Challenge {} refers to nonexistent stage {}.", self.id, self.stage)));
+        }
+
+        state.reset_game_state();
+        state.temporary_profile = true;
+
+        let mut next_scene = GameScene::new(state, ctx, self.stage)?;
+        next_scene.player.x = self.x * 16 * 0x200;
+        next_scene.player.y = self.y * 16 * 0x200;
+        state.fade_state = FadeState::Hidden;
+        state.textscript_vm.state = TextScriptExecutionState::Running(self.start_event, 0);
+        state.challenge = Some(ChallengeRun {
+            challenge: self.clone(),
+            ticks: 0,
+            finished: false,
+        });
+
+        state.next_scene = Some(Box::new(next_scene));
+        Ok(())
+    }
+
+    fn record_path(&self, state: &SharedGameState) -> GameResult<PathBuf> {
+        let mod_id = state.current_mod.as_ref().map(|m| m.id.as_str());
+        Ok(filesystem::user_dirs()?.save_dir(mod_id).join("challenges").join(format!("{}.rec", self.id)))
+    }
+
+    /// Best time in ticks, if the challenge has been completed before.
+    pub fn best_time(&self, state: &SharedGameState) -> Option<usize> {
+        let data = fs::read_to_string(self.record_path(state).ok()?).ok()?;
+        data.trim().parse().ok()
+    }
+
+    /// Stores the time if it's better than the current record, returns true if it was.
+    pub fn submit_time(&self, ticks: usize, state: &SharedGameState) -> GameResult<bool> {
+        if let Some(best) = self.best_time(state) {
+            if best <= ticks {
+                return Ok(false);
+            }
+        }
+
+        let path = self.record_path(state)?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, ticks.to_string())?;
+
+        Ok(true)
+    }
+}
+
+impl ChallengeRun {
+    /// Called by the text script VM whenever an event starts.
+    pub fn event_started(&mut self, event: u16) {
+        if !self.finished && event == self.challenge.end_event {
+            self.finished = true;
+        }
+    }
+}
+
+/// Challenges and boss rushes always run at the original tick rate, so their times stay comparable.
+pub const CHALLENGE_TPS: u32 = 50;
+
+/// Formats a tick count the same way the Nikumaru counter does, m:ss.t
+pub fn format_time(ticks: usize, tps: u32) -> String {
+    let tenths = ticks * 10 / tps.max(1) as usize;
+    format!("{}:{:02}.{}", tenths / 600, (tenths / 10) % 60, tenths % 10)
+}
+
+#[test]
+fn test_format_time() {
+    assert_eq!(format_time(0, 50), "0:00.0");
+    assert_eq!(format_time(49, 50), "0:00.9");
+    assert_eq!(format_time(50 * 61 + 25, 50), "1:01.5");
+    assert_eq!(format_time(50 * 60 * 12, 50), "12:00.0");
+    assert_eq!(format_time(3 * 3000 + 7 * 50 + 45, 50), "3:07.9");
+    assert_eq!(format_time(60 * 61 + 30, 60), "1:01.5");
+    assert_eq!(format_time(59, 60), "0:00.9");
+}
@@ -1,9 +1,11 @@
 use num_traits::{AsPrimitive, Num};
 
 use crate::bitfield;
+use crate::settings::Settings;
+use crate::transition::TransitionType;
 
 bitfield! {
-  #[derive(Clone, Copy)]
+  #[derive(Clone, Copy, Serialize, Deserialize)]
   pub struct Flag(u32);
   impl Debug;
 
@@ -30,7 +32,69 @@ bitfield! {
 }
 
 bitfield! {
-  #[derive(Clone, Copy)]
+  /// What a map tile attribute does to the player or an NPC, see `TileFlags::decode`.
+  #[derive(Clone, Copy, PartialEq, Eq)]
+  pub struct TileFlags(u16);
+  impl Debug;
+
+  pub solid, set_solid: 0;
+  pub solid_unless_ignored, set_solid_unless_ignored: 1; // tile 0x44, see PhysicalEntity::ignore_tile_44
+  pub water, set_water: 2;
+  pub spike, set_spike: 3;
+  pub slope, set_slope: 4;
+  pub current, set_current: 5;
+  pub u8, variant, set_variant: 10, 8; // triangle of a slope (a - h) or the direction of a current
+}
+
+impl TileFlags {
+    /// Decodes a tile attribute the way the original does, which differs between the player and the NPCs.
+    /// Bit 0x20 adds water to the slopes and currents.
+    pub fn decode(attrib: u8, is_player: bool) -> TileFlags {
+        let mut flags = TileFlags(0);
+
+        match attrib {
+            0x05 | 0x41 | 0x43 | 0x46 if is_player => flags.set_solid(true),
+            0x03 | 0x05 | 0x41 | 0x43 if !is_player => flags.set_solid(true),
+            0x44 => flags.set_solid_unless_ignored(true),
+            0x42 if is_player => flags.set_spike(true),
+            0x62 if is_player => {
+                flags.set_spike(true);
+                flags.set_water(true);
+            }
+            0x02 | 0x60 => flags.set_water(true),
+            0x62 if !is_player => flags.set_water(true),
+            0x61 => {
+                flags.set_water(true);
+                flags.set_solid(true);
+            }
+            0x04 | 0x64 if !is_player => {
+                flags.set_water(true);
+                flags.set_solid(true);
+            }
+            0x50..=0x57 | 0x70..=0x77 => {
+                flags.set_slope(true);
+                flags.set_variant(attrib & 0x07);
+                flags.set_water(attrib & 0x20 != 0);
+            }
+            0x80..=0x83 | 0xa0..=0xa3 => {
+                flags.set_current(true);
+                flags.set_variant(attrib & 0x03);
+                flags.set_water(attrib & 0x20 != 0);
+            }
+            _ => {}
+        }
+
+        flags
+    }
+
+    /// Direction a current tile pushes towards.
+    pub fn current_direction(&self) -> Option<Direction> {
+        if self.current() { Direction::from_int(self.variant() as usize) } else { None }
+    }
+}
+
+bitfield! {
+  #[derive(Clone, Copy, Serialize, Deserialize)]
   pub struct Equipment(u16);
   impl Debug;
 
@@ -47,7 +111,7 @@ bitfield! {
 }
 
 bitfield! {
-  #[derive(Clone, Copy)]
+  #[derive(Clone, Copy, Serialize, Deserialize)]
   pub struct Condition(u16);
   impl Debug;
 
@@ -75,22 +139,67 @@ bitfield! {
   pub fire, set_fire: 6;
   pub weapon_next, set_weapon_next: 7;
   pub weapon_prev, set_weapon_prev: 8;
+  pub rewind, set_rewind: 9;
+  pub menu, set_menu: 10;
+  pub inventory, set_inventory: 11;
 }
 
 bitfield! {
-  #[derive(Clone, Copy)]
+  #[derive(Clone, Copy, Serialize, Deserialize)]
   pub struct ControlFlags(u16);
   impl Debug;
 
-  pub flag_x01, set_flag_x01: 0;
+  // 0x01 in the original, the player, NPCs, bullets and the camera move
+  pub tick_world, set_tick_world: 0;
+  // 0x02, the keys reach the player
   pub control_enabled, set_control_enabled: 1;
+  // 0x04, set while an event runs so touching NPCs doesn't start another one
   pub interactions_disabled, set_interactions_disabled: 2;
 
   // engine specific flags
   pub wind, set_wind: 15;
 }
 
+impl ControlFlags {
+    /// `<PRI`, everything but the script stands still.
+    pub fn freeze_all(&mut self) {
+        self.set_tick_world(false);
+        self.set_control_enabled(false);
+    }
+
+    /// `<KEY`, the world moves on without the player's input.
+    pub fn freeze_input(&mut self) {
+        self.set_tick_world(true);
+        self.set_control_enabled(false);
+    }
+
+    /// `<FRE`
+    pub fn free(&mut self) {
+        self.set_tick_world(true);
+        self.set_control_enabled(true);
+    }
+
+    pub fn tick_gates(self, settings: &Settings) -> TickGates {
+        TickGates {
+            world: self.tick_world(),
+            // a frozen player can't shoot or switch weapons either
+            player_input: self.tick_world() && self.control_enabled(),
+            carets: self.tick_world() || settings.allow_frozen_carets(),
+        }
+    }
+}
+
+/// Parts of the game scene which advance on a tick, the script runs on every one of them.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TickGates {
+    /// The player, NPCs, bullets, the camera and the stage effects.
+    pub world: bool,
+    /// Weapon switching, shooting and the health bar.
+    pub player_input: bool,
+    pub carets: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[repr(u8)]
 pub enum FadeDirection {
     Left = 0,
@@ -123,16 +232,36 @@ impl FadeDirection {
     }
 }
 
-#[derive(Debug, PartialEq, Copy, Clone)]
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
 #[repr(u8)]
 pub enum FadeState {
     Visible,
-    FadeIn(i8, FadeDirection),
+    FadeIn(i8, FadeDirection, TransitionType),
     Hidden,
-    FadeOut(i8, FadeDirection),
+    FadeOut(i8, FadeDirection, TransitionType),
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// A fade goes from -FADE_TICKS to FADE_TICKS.
+pub const FADE_TICKS: i8 = 15;
+
+impl FadeState {
+    /// Whether the screen is fully covered or uncovered, `<WAI`ting scripts carry on then.
+    pub fn is_done(self) -> bool {
+        matches!(self, FadeState::Visible | FadeState::Hidden)
+    }
+
+    pub fn tick(self) -> FadeState {
+        match self {
+            FadeState::FadeOut(tick, direction, ty) if tick < FADE_TICKS => FadeState::FadeOut(tick + 1, direction, ty),
+            FadeState::FadeOut(_, _, _) => FadeState::Hidden,
+            FadeState::FadeIn(tick, direction, ty) if tick > -FADE_TICKS => FadeState::FadeIn(tick - 1, direction, ty),
+            FadeState::FadeIn(_, _, _) => FadeState::Visible,
+            state => state,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[repr(u8)]
 pub enum Direction {
     Left = 0,
@@ -141,8 +270,30 @@ pub enum Direction {
     Bottom,
 }
 
+/// Horizontal direction the player wants to move in. Holding both left and right resolves to neither
+/// (the player stops, like in vanilla), key states from all devices are expected to be merged beforehand.
+pub fn resolve_movement(key_state: KeyState) -> Option<Direction> {
+    match (key_state.left(), key_state.right()) {
+        (true, false) => Some(Direction::Left),
+        (false, true) => Some(Direction::Right),
+        _ => None,
+    }
+}
+
+/// Same as `resolve_movement`, for up and down.
+pub fn resolve_vertical(key_state: KeyState) -> Option<Direction> {
+    match (key_state.up(), key_state.down()) {
+        (true, false) => Some(Direction::Up),
+        (false, true) => Some(Direction::Bottom),
+        _ => None,
+    }
+}
+
 pub const FILE_TYPES: [&str; 3] = [".png", ".bmp", ".pbm"];
 
+/// Direction operand of the TSC commands which makes the NPC face the player.
+pub const DIRECTION_FACE_PLAYER: usize = 4;
+
 impl Direction {
     pub fn from_int(val: usize) -> Option<Direction> {
         match val {
@@ -154,6 +305,15 @@ impl Direction {
         }
     }
 
+    /// Like `from_int`, but also handles `DIRECTION_FACE_PLAYER`, turning an entity at `x` towards `player_x`.
+    pub fn from_int_facing(val: usize, x: isize, player_x: isize) -> Option<Direction> {
+        if val == DIRECTION_FACE_PLAYER {
+            return Some(if x < player_x { Direction::Right } else { Direction::Left });
+        }
+
+        Direction::from_int(val)
+    }
+
     pub fn opposite(&self) -> Direction {
         match self {
             Direction::Left => { Direction::Right }
@@ -180,9 +340,154 @@ impl Direction {
             Direction::Bottom => { 1 }
         }
     }
+
+    #[inline]
+    pub fn vector(&self) -> (isize, isize) {
+        (self.vector_x(), self.vector_y())
+    }
+}
+
+/// Fixed point units per pixel, the game logic uses 9 fractional bits.
+pub const FIX9_ONE: isize = 0x200;
+/// Fixed point units per tile.
+pub const FIX9_TILE: isize = 16 * FIX9_ONE;
+
+/// Narrows a fixed point intermediate back to a world coordinate. World coordinates have to fit in an i32
+/// like in the original game, so that 32-bit builds behave the same as 64-bit ones.
+// todo: store entity positions and velocities as i32 and drop the isize <-> i64 casts
+#[inline]
+pub fn fix9_from_i64(value: i64) -> isize {
+    debug_assert!(value >= i32::MIN as i64 && value <= i32::MAX as i64, "fixed point value out of range: {:#x}", value);
+    value as isize
+}
+
+/// Product of two fixed point values, rounded toward zero. The intermediate product is widened to i64.
+#[inline]
+pub fn fix9_mul(a: isize, b: isize) -> isize {
+    fix9_from_i64(a as i64 * b as i64 / FIX9_ONE as i64)
+}
+
+/// `value * mul / div` rounded toward zero, without overflowing the intermediate product.
+#[inline]
+pub fn fix9_mul_div(value: isize, mul: isize, div: isize) -> isize {
+    fix9_from_i64(value as i64 * mul as i64 / div as i64)
 }
 
-#[derive(Debug, Clone, Copy)]
+#[inline]
+pub fn to_fix(pixels: isize) -> isize {
+    fix9_from_i64(pixels as i64 * FIX9_ONE as i64)
+}
+
+/// Rounds toward zero, like the integer division in the original game. Use it in the game logic.
+#[inline]
+pub fn to_pixels(fix: isize) -> isize {
+    fix / FIX9_ONE
+}
+
+/// Rounds toward negative infinity, so there's no doubled pixel around 0. Use it for drawing.
+#[inline]
+pub fn to_pixels_floor(fix: isize) -> isize {
+    fix.div_euclid(FIX9_ONE)
+}
+
+/// Tile coordinate of a fixed point position, rounded toward zero like in the original game.
+#[inline]
+pub fn to_tile(fix: isize) -> isize {
+    fix / FIX9_TILE
+}
+
+#[inline]
+pub fn tile_to_fix(tile: isize) -> isize {
+    fix9_from_i64(tile as i64 * FIX9_TILE as i64)
+}
+
+/// Position of the last tile of a map `tiles` long. A map with no tiles (from a corrupt PXM) is treated as a single tile.
+#[inline]
+pub fn map_extent(tiles: usize) -> isize {
+    tile_to_fix(tiles.max(1) as isize - 1)
+}
+
+/// Converts a fixed point value to pixels, snapped to the physical pixel grid of the given scale.
+#[inline]
+pub fn fix9_scale(val: isize, scale: f32) -> f32 {
+    (val as f64 * scale as f64 / 512.0).floor() as f32 / scale
+}
+
+#[inline]
+pub fn lerp_f64(v1: f64, v2: f64, t: f64) -> f64 {
+    v1 * (1.0 - t) + v2 * t
+}
+
+/// Position between two ticks in pixels, `frame_delta` is how far into the current tick the frame is (0.0 to 1.0).
+/// Large jumps (teleports) aren't interpolated.
+#[inline]
+pub fn interpolate_fix9_scale(old_val: isize, val: isize, frame_delta: f64) -> f32 {
+    if (old_val - val).abs() > 0x1800 {
+        return val as f32 / 512.0;
+    }
+
+    (lerp_f64(old_val as f64, val as f64, frame_delta.clamp(0.0, 1.0)) / 512.0) as f32
+}
+
+/// Walks the tiles crossed by a line between two fixed point positions (tiles are centered on multiples of 16 pixels)
+/// and tells whether none of them is `blocked`, the tiles at both ends included. A line going exactly through
+/// a corner is blocked by either of the tiles touching it, so it can't slip between two diagonal walls.
+pub fn tile_line_of_sight<F: FnMut(isize, isize) -> bool>(x0: isize, y0: isize, x1: isize, y1: isize, mut blocked: F) -> bool {
+    let tile_of = |val: isize| (val + FIX9_TILE / 2).div_euclid(FIX9_TILE);
+    let (mut tx, mut ty) = (tile_of(x0), tile_of(y0));
+    let (end_x, end_y) = (tile_of(x1), tile_of(y1));
+    let (step_x, step_y) = ((x1 - x0).signum(), (y1 - y0).signum());
+
+    // part of the line (0.0 - 1.0) where it crosses the next tile edge, and the part between two edges
+    let crossing = |from: isize, to: isize, tile: isize, step: isize| {
+        if step == 0 {
+            return (f64::INFINITY, f64::INFINITY);
+        }
+
+        let edge = tile_to_fix(tile) + step * FIX9_TILE / 2;
+        let len = (to - from) as f64;
+        ((edge - from) as f64 / len, FIX9_TILE as f64 / len.abs())
+    };
+    let (mut next_x, delta_x) = crossing(x0, x1, tx, step_x);
+    let (mut next_y, delta_y) = crossing(y0, y1, ty, step_y);
+
+    if blocked(tx, ty) {
+        return false;
+    }
+
+    // a corner step moves on both axes, so this is an upper bound which also stops rounding errors from running away
+    let steps = (end_x - tx).abs() + (end_y - ty).abs();
+    for _ in 0..steps {
+        if next_x < next_y {
+            tx += step_x;
+            next_x += delta_x;
+        } else if next_y < next_x {
+            ty += step_y;
+            next_y += delta_y;
+        } else {
+            if blocked(tx + step_x, ty) || blocked(tx, ty + step_y) {
+                return false;
+            }
+
+            tx += step_x;
+            ty += step_y;
+            next_x += delta_x;
+            next_y += delta_y;
+        }
+
+        if blocked(tx, ty) {
+            return false;
+        }
+
+        if tx == end_x && ty == end_y {
+            break;
+        }
+    }
+
+    true
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Rect<T: Num + Copy = isize> {
     pub left: T,
     pub top: T,
@@ -209,6 +514,16 @@ impl<T: Num + Copy> Rect<T> {
         }
     }
 
+    #[inline]
+    pub fn width(&self) -> T {
+        self.right.sub(self.left)
+    }
+
+    #[inline]
+    pub fn height(&self) -> T {
+        self.bottom.sub(self.top)
+    }
+
     pub fn from(rect: crate::ggez::graphics::Rect) -> Rect<f32> {
         Rect {
             left: rect.x,
@@ -219,11 +534,240 @@ impl<T: Num + Copy> Rect<T> {
     }
 }
 
+impl<T: Num + Copy + PartialOrd> Rect<T> {
+    /// Whether the point is inside, the right and bottom edges are exclusive.
+    #[inline]
+    pub fn contains(&self, x: T, y: T) -> bool {
+        x >= self.left && x < self.right && y >= self.top && y < self.bottom
+    }
+
+    #[inline]
+    pub fn intersects(&self, other: &Rect<T>) -> bool {
+        self.left < other.right && other.left < self.right && self.top < other.bottom && other.top < self.bottom
+    }
+
+    /// Overlapping area of both rects, `None` if they don't intersect.
+    pub fn intersection(&self, other: &Rect<T>) -> Option<Rect<T>> {
+        if !self.intersects(other) {
+            return None;
+        }
+
+        let max = |a: T, b: T| if a > b { a } else { b };
+        let min = |a: T, b: T| if a < b { a } else { b };
+
+        Some(Rect {
+            left: max(self.left, other.left),
+            top: max(self.top, other.top),
+            right: min(self.right, other.right),
+            bottom: min(self.bottom, other.bottom),
+        })
+    }
+}
+
 impl<T: Num + Copy + AsPrimitive<f32>> Into<crate::ggez::graphics::Rect> for Rect<T> {
     fn into(self) -> crate::ggez::graphics::Rect {
-        crate::ggez::graphics::Rect::new(self.top.as_(),
-                                         self.left.as_(),
-                                         self.bottom.sub(self.top).as_(),
-                                         self.right.sub(self.left).as_())
+        crate::ggez::graphics::Rect::new(self.left.as_(),
+                                         self.top.as_(),
+                                         self.right.sub(self.left).as_(),
+                                         self.bottom.sub(self.top).as_())
+    }
+}
+
+#[test]
+fn test_fix9_rounding() {
+    assert_eq!(to_pixels(0x3ff), 1);
+    assert_eq!(to_pixels(-0x3ff), -1);
+    assert_eq!(to_pixels_floor(-0x3ff), -2);
+    assert_eq!(to_pixels_floor(-0x400), -2);
+    assert_eq!(to_pixels_floor(-1), -1);
+    assert_eq!(to_pixels(-1), 0);
+
+    assert_eq!(to_tile(-1), 0);
+    assert_eq!(to_tile(-FIX9_TILE - 1), -1);
+    assert_eq!(to_tile(tile_to_fix(3) + 0x1ff), 3);
+    assert_eq!(to_fix(-2), -0x400);
+
+    assert_eq!(fix9_scale(0x300, 1.0), 1.0);
+    assert_eq!(fix9_scale(0x300, 2.0), 1.5);
+    assert_eq!(fix9_scale(-0x300, 1.0), -2.0);
+}
+
+#[test]
+fn test_fix9_range() {
+    // the far corner of a 500x500 map, same as with i32 math
+    assert_eq!(tile_to_fix(499), (499i32 * 16 * 0x200) as isize);
+    assert_eq!(map_extent(500), tile_to_fix(499));
+    assert_eq!(to_fix(500 * 16), (500i32 * 16 * 0x200) as isize);
+    assert_eq!(map_extent(1), 0);
+    assert_eq!(map_extent(0), 0);
+
+    // the product of a velocity and a position overflows i32, the result doesn't
+    let (vel, x) = (0x5ff, tile_to_fix(499));
+    assert_eq!((vel as i32).checked_mul(x as i32), None);
+    assert_eq!(fix9_mul(vel, x), (vel as i64 * x as i64 / 0x200) as isize);
+    assert_eq!(fix9_mul(-0x300, 0x300), -0x480);
+    assert_eq!(fix9_mul_div(x, 3, 4), (x as i64 * 3 / 4) as isize);
+    // damping rounds toward zero like in the original game
+    assert_eq!(fix9_mul_div(-0x5ff, 7, 8), -0x53f);
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic]
+fn test_fix9_overflow() {
+    tile_to_fix(0x10_0000);
+}
+
+#[test]
+fn test_tile_line_of_sight() {
+    let walls = |walls: &'static [(isize, isize)]| move |x: isize, y: isize| walls.contains(&(x, y));
+    let t = FIX9_TILE;
+
+    // straight lines
+    assert!(tile_line_of_sight(0, 0, 4 * t, 0, walls(&[(2, 1)])));
+    assert!(!tile_line_of_sight(0, 0, 4 * t, 0, walls(&[(2, 0)])));
+    assert!(!tile_line_of_sight(4 * t, 0, 0, 0, walls(&[(2, 0)])));
+    assert!(!tile_line_of_sight(0, -3 * t, 0, 0, walls(&[(0, -1)])));
+
+    // zero length, only the tile it's in
+    assert!(tile_line_of_sight(t, t, t, t, walls(&[(0, 0)])));
+    assert!(!tile_line_of_sight(t, t, t, t, walls(&[(1, 1)])));
+
+    // diagonal, crossing (0, 0), (1, 0), (1, 1) and (2, 1)
+    assert!(tile_line_of_sight(0, 0, 2 * t, t, walls(&[(0, 1), (2, 0)])));
+    assert!(!tile_line_of_sight(0, 0, 2 * t, t, walls(&[(1, 1)])));
+    assert!(!tile_line_of_sight(2 * t, t, 0, 0, walls(&[(1, 0)])));
+
+    // exactly through a corner, doesn't slip between diagonal walls
+    assert!(tile_line_of_sight(0, 0, 2 * t, 2 * t, walls(&[])));
+    assert!(!tile_line_of_sight(0, 0, 2 * t, 2 * t, walls(&[(1, 0), (0, 1)])));
+    assert!(!tile_line_of_sight(0, 0, 2 * t, 2 * t, walls(&[(0, 1)])));
+    assert!(!tile_line_of_sight(-t, t, t, -t, walls(&[(-1, 0)])));
+}
+
+#[test]
+fn test_interpolation() {
+    assert_eq!(interpolate_fix9_scale(0, 0x400, 0.5), 1.0);
+    assert_eq!(interpolate_fix9_scale(-0x400, 0, 0.25), -1.5);
+    // teleports are not interpolated
+    assert_eq!(interpolate_fix9_scale(0, 0x10000, 0.5), 128.0);
+}
+
+#[test]
+fn test_rect() {
+    let a = Rect::new(-16, -16, 16, 16);
+    let b = Rect::new_size(8, -32, 16, 24);
+
+    assert!(a.contains(-16, 15));
+    assert!(!a.contains(16, 0));
+    assert!(a.intersects(&b));
+    assert!(!a.intersects(&Rect::new(16, 0, 32, 8)));
+
+    let i = a.intersection(&b).unwrap();
+    assert_eq!((i.left, i.top, i.right, i.bottom), (8, -16, 16, -8));
+    assert_eq!((i.width(), i.height()), (8, 8));
+}
+
+#[test]
+fn test_direction() {
+    assert_eq!(Direction::from_int_facing(DIRECTION_FACE_PLAYER, -0x200, 0x200), Some(Direction::Right));
+    assert_eq!(Direction::from_int_facing(DIRECTION_FACE_PLAYER, 0x200, -0x200), Some(Direction::Left));
+    assert_eq!(Direction::from_int_facing(3, 0, 0), Some(Direction::Bottom));
+    assert_eq!(Direction::from_int_facing(5, 0, 0), None);
+    assert_eq!(Direction::Up.vector(), (0, -1));
+    assert_eq!(Direction::Left.opposite(), Direction::Right);
+}
+
+#[test]
+fn test_resolve_movement() {
+    let mut keys = KeyState(0);
+    assert_eq!(resolve_movement(keys), None);
+
+    keys.set_left(true);
+    keys.set_up(true);
+    assert_eq!(resolve_movement(keys), Some(Direction::Left));
+    assert_eq!(resolve_vertical(keys), Some(Direction::Up));
+
+    keys.set_right(true);
+    keys.set_down(true);
+    assert_eq!(resolve_movement(keys), None);
+    assert_eq!(resolve_vertical(keys), None);
+
+    keys.set_left(false);
+    assert_eq!(resolve_movement(keys), Some(Direction::Right));
+}
+
+#[test]
+fn test_tile_flags() {
+    use std::collections::HashMap;
+
+    const S: u16 = 0x01; // solid
+    const U: u16 = 0x02; // solid unless ignored
+    const W: u16 = 0x04; // water
+    const P: u16 = 0x08; // spike
+    const L: u16 = 0x10; // slope
+    const C: u16 = 0x20; // current
+
+    // attribute => (player, NPC), anything missing does nothing
+    let mut table: HashMap<u8, (u16, u16)> = [
+        (0x02, (W, W)),
+        (0x03, (0, S)),
+        (0x04, (0, W | S)),
+        (0x05, (S, S)),
+        (0x41, (S, S)),
+        (0x42, (P, 0)),
+        (0x43, (S, S)),
+        (0x44, (U, U)),
+        (0x46, (S, 0)),
+        (0x60, (W, W)),
+        (0x61, (W | S, W | S)),
+        (0x62, (P | W, W)),
+        (0x64, (0, W | S)),
+    ].iter().copied().collect();
+
+    for i in 0..8u8 {
+        let variant = (i as u16) << 8;
+        table.insert(0x50 + i, (L | variant, L | variant));
+        table.insert(0x70 + i, (L | W | variant, L | W | variant));
+    }
+
+    for i in 0..4u8 {
+        let variant = (i as u16) << 8;
+        table.insert(0x80 + i, (C | variant, C | variant));
+        table.insert(0xa0 + i, (C | W | variant, C | W | variant));
     }
+
+    for attrib in 0..=255u8 {
+        let (player, npc) = table.get(&attrib).copied().unwrap_or((0, 0));
+        assert_eq!(TileFlags::decode(attrib, true).0, player, "attribute {:#04x} for the player", attrib);
+        assert_eq!(TileFlags::decode(attrib, false).0, npc, "attribute {:#04x} for NPCs", attrib);
+    }
+
+    assert_eq!(TileFlags::decode(0xa2, true).current_direction(), Some(Direction::Right));
+    assert_eq!(TileFlags::decode(0x72, true).current_direction(), None);
+}
+
+#[test]
+fn test_tick_gates() {
+    use crate::settings::CompatMode;
+
+    let mut settings = Settings::default();
+    let mut flags = ControlFlags(0);
+    flags.free();
+    assert_eq!(flags.tick_gates(&settings), TickGates { world: true, player_input: true, carets: true });
+
+    flags.freeze_input();
+    assert_eq!(flags.tick_gates(&settings), TickGates { world: true, player_input: false, carets: true });
+
+    flags.freeze_all();
+    assert_eq!(flags.tick_gates(&settings), TickGates { world: false, player_input: false, carets: true });
+    // the original stops the carets as well
+    settings.compat_mode = CompatMode::Vanilla;
+    assert_eq!(flags.tick_gates(&settings), TickGates { world: false, player_input: false, carets: false });
+
+    // the other flags aren't touched
+    flags.set_interactions_disabled(true);
+    flags.set_wind(true);
+    flags.free();
+    assert!(flags.interactions_disabled() && flags.wind());
 }
@@ -0,0 +1,164 @@
+//! Header shared by the replays and the save states: magic, format version, then `Metadata`, then the payload.
+//! The metadata layout is the same for every format, changing it needs a version bump of all of them.
+
+use std::fmt;
+use std::io::{Read, Write};
+
+use byteorder::{LE, ReadBytesExt, WriteBytesExt};
+
+use crate::ggez::{Context, filesystem, GameResult};
+use crate::ggez::GameError::ResourceLoadError;
+use crate::settings::CompatMode;
+use crate::SharedGameState;
+
+/// Passed in by the build environment, so bug reports can be traced back to the exact commit.
+const GIT_HASH: Option<&str> = option_env!("DRS_GIT_HASH");
+
+/// Stage tables in the order `StageData::load_stage_table` looks for them, the first one found is used.
+const STAGE_TABLES: [&str; 3] = ["stage.tbl", "stage.dat", "mrmap.bin"];
+
+pub struct Format {
+    pub magic: &'static [u8; 4],
+    pub name: &'static str,
+    /// Bumped every time anything in the payload changes layout.
+    pub version: u32,
+    /// First version with the metadata, the older ones are up to the format to migrate or reject.
+    pub metadata_since: u32,
+}
+
+/// Where a file comes from, shown in the debugger so it ends up in bug reports.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Metadata {
+    /// Engine version which wrote the file, with the git commit if the build knew it.
+    pub engine_version: String,
+    pub compat_mode: CompatMode,
+    /// `data_fingerprint` of the game data the file was written with, None for files migrated from older versions.
+    pub data_fingerprint: Option<u64>,
+}
+
+impl Metadata {
+    pub fn current(state: &SharedGameState) -> Metadata {
+        Metadata {
+            engine_version: engine_version(),
+            compat_mode: state.settings.compat_mode,
+            data_fingerprint: Some(state.data_fingerprint),
+        }
+    }
+
+    /// Whether the file has been written with the same game data as the one loaded.
+    pub fn matches_data(&self, state: &SharedGameState) -> bool {
+        self.data_fingerprint.is_none_or(|fingerprint| fingerprint == state.data_fingerprint)
+    }
+}
+
+impl fmt::Display for Metadata {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "engine {}, {} mode, data ", self.engine_version, self.compat_mode.name())?;
+        match self.data_fingerprint {
+            Some(fingerprint) => write!(f, "{:016x}", fingerprint),
+            None => write!(f, "unknown"),
+        }
+    }
+}
+
+pub fn engine_version() -> String {
+    match GIT_HASH {
+        Some(hash) => format!("{}+{}", env!("CARGO_PKG_VERSION"), hash),
+        None => env!("CARGO_PKG_VERSION").to_owned(),
+    }
+}
+
+/// Initial value of `fnv1a`.
+pub const FNV1A_BASIS: u64 = 0xcbf29ce484222325;
+
+/// 64-bit FNV-1a, unlike the std hashers it's the same across Rust versions and platforms.
+pub fn fnv1a(hash: u64, data: &[u8]) -> u64 {
+    data.iter().fold(hash, |hash, &b| (hash ^ b as u64).wrapping_mul(0x100000001b3))
+}
+
+fn read_file(ctx: &mut Context, path: &str) -> Option<Vec<u8>> {
+    let mut data = Vec::new();
+    filesystem::open(ctx, path).and_then(|mut file| Ok(file.read_to_end(&mut data)?)).ok()?;
+    Some(data)
+}
+
+/// Hash of the stage table and npc.tbl, the files which make replays desync when they're different.
+/// Files recorded on a mod don't play back the same on other data.
+pub fn data_fingerprint(ctx: &mut Context, base_path: &str) -> u64 {
+    let mut hash = FNV1A_BASIS;
+
+    let stage_table = STAGE_TABLES.iter().find_map(|name| read_file(ctx, &[base_path, name].join("")));
+    if let Some(data) = stage_table {
+        hash = fnv1a(hash, &data);
+    }
+
+    if let Some(data) = read_file(ctx, &[base_path, "/npc.tbl"].join("")) {
+        hash = fnv1a(hash, &data);
+    }
+
+    hash
+}
+
+pub fn write_header<W: Write>(format: &Format, metadata: &Metadata, mut writer: W) -> GameResult {
+    writer.write_all(format.magic)?;
+    writer.write_u32::<LE>(format.version)?;
+    Ok(bincode::serialize_into(writer, metadata)?)
+}
+
+/// Checks the magic and the version, returns the version and the metadata if it has any.
+/// Files from a newer version of the engine are rejected, the older ones have to be handled by the caller.
+pub fn read_header<R: Read>(format: &Format, mut reader: R) -> GameResult<(u32, Option<Metadata>)> {
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != format.magic {
+        return Err(ResourceLoadError(format!("Not a {} file.", format.name)));
+    }
+
+    let version = reader.read_u32::<LE>()?;
+    if version > format.version {
+        return Err(ResourceLoadError(format!("The {} is from a newer version of the engine (format {}, this one reads up to {}).",
+                                             format.name, version, format.version)));
+    }
+
+    if version < format.metadata_since {
+        return Ok((version, None));
+    }
+
+    let metadata = bincode::deserialize_from(reader)
+        .map_err(|e| ResourceLoadError(format!("Corrupted {} header: {}", format.name, e)))?;
+
+    Ok((version, Some(metadata)))
+}
+
+#[test]
+fn test_header() {
+    const FORMAT: Format = Format { magic: b"TEST", name: "test", version: 3, metadata_since: 2 };
+    let metadata = Metadata { engine_version: engine_version(), compat_mode: CompatMode::Enhanced, data_fingerprint: Some(0x1234) };
+
+    let mut data = Vec::new();
+    write_header(&FORMAT, &metadata, &mut data).unwrap();
+    data.extend_from_slice(b"payload");
+
+    let mut reader = &data[..];
+    assert_eq!(read_header(&FORMAT, &mut reader).unwrap(), (3, Some(metadata.clone())));
+    assert_eq!(reader, b"payload");
+
+    // older files without the metadata are left to the caller
+    let old = [&b"TEST"[..], &[1, 0, 0, 0]].concat();
+    assert_eq!(read_header(&FORMAT, &old[..]).unwrap(), (1, None));
+
+    // newer files
+    let mut newer = data.clone();
+    newer[4] = 4;
+    assert!(read_header(&FORMAT, &newer[..]).is_err());
+
+    // corrupted ones
+    assert!(read_header(&FORMAT, &b"NOPE"[..]).is_err());
+    assert!(read_header(&FORMAT, &data[..10]).is_err());
+    let mut corrupted = data.clone();
+    corrupted[8] = 0xff;
+    assert!(read_header(&FORMAT, &corrupted[..]).is_err());
+
+    assert_ne!(fnv1a(FNV1A_BASIS, b"npc.tbl"), fnv1a(FNV1A_BASIS, b"npc.tb1"));
+    assert!(metadata.to_string().contains("Enhanced mode, data 0000000000001234"));
+}
@@ -0,0 +1,156 @@
+use std::any::Any;
+use std::fs;
+use std::panic;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+
+use crate::ggez::filesystem::user_dirs;
+use crate::ggez::GameError;
+use crate::log_sink;
+
+lazy_static! {
+    /// Game state appended to crash reports, the panic hook can't reach `SharedGameState`.
+    static ref CONTEXT: Mutex<String> = Mutex::new(String::new());
+}
+
+/// Replaces the game state appended to crash reports.
+pub fn set_context(context: String) {
+    if let Ok(mut current) = CONTEXT.lock() {
+        *current = context;
+    }
+}
+
+/// Doesn't wait for the lock, the panic might have happened while holding it.
+fn context() -> String {
+    CONTEXT.try_lock().map(|context| context.clone()).unwrap_or_default()
+}
+
+/// Writes panics to `crash.log` in the user data directory and to stderr, or shows them in a message box
+/// with the `crash-dialog` feature (on by default), most players never see the console so otherwise the game would
+/// just close.
+pub fn install_panic_hook() {
+    let default_hook = panic::take_hook();
+
+    panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+
+        let message = panic_message(info.payload());
+        let location = info.location().map(|l| format!("{}:{}", l.file(), l.line()));
+        let backtrace = format!("{:?}", backtrace::Backtrace::new());
+
+        report_crash(&crash_report(message, location.as_deref(), &context(), Some(&backtrace)));
+    }));
+}
+
+/// Saves the crash log and lets the player know where it is, also used for errors the game can't recover from.
+pub fn report_crash(report: &str) {
+    let log_path = write_crash_log(report);
+    log_sink::flush();
+
+    let mut message = format!("doukutsu-rs has crashed.\n\n{}", report.lines().next().unwrap_or(""));
+    match log_path {
+        Some(path) => message.push_str(&format!("\n\nDetails have been saved to {}.", path.to_string_lossy())),
+        None => message.push_str("\n\nThe crash log couldn't be saved."),
+    }
+    if let Some(path) = log_sink::log_path() {
+        message.push_str(&format!("\nPlease attach {} as well when reporting it.", path.to_string_lossy()));
+    }
+
+    show_dialog(&message);
+}
+
+fn write_crash_log(report: &str) -> Option<PathBuf> {
+    let path = user_dirs().ok()?.crash_log_path();
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).ok()?;
+    }
+
+    match fs::write(&path, report) {
+        Ok(_) => Some(path),
+        Err(e) => {
+            log::error!("Cannot write the crash log: {}", e);
+            None
+        }
+    }
+}
+
+/// Reports an error the game loop couldn't recover from.
+pub fn report_error(error: &GameError) {
+    report_crash(&crash_report(&error.to_string(), None, &context(), None));
+}
+
+#[cfg(all(feature = "crash-dialog", any(windows, target_os = "macos")))]
+fn show_dialog(message: &str) {
+    if let Err(e) = msgbox::create("doukutsu-rs", message, msgbox::IconType::Error) {
+        log::error!("Cannot show the crash dialog: {}", e);
+    }
+}
+
+// the desktop's own dialog tools, stderr is all that's left without them
+#[cfg(all(feature = "crash-dialog", not(any(windows, target_os = "macos"))))]
+fn show_dialog(message: &str) {
+    use std::process::Command;
+
+    let status = Command::new("zenity")
+        .args(["--error", "--no-markup", "--title", "doukutsu-rs", "--text", message])
+        .status()
+        .or_else(|_| Command::new("kdialog").args(["--title", "doukutsu-rs", "--error", message]).status());
+
+    if !matches!(status, Ok(status) if status.success()) {
+        eprintln!("{}", message);
+    }
+}
+
+// the logger might not be installed yet
+#[cfg(not(feature = "crash-dialog"))]
+fn show_dialog(message: &str) {
+    eprintln!("{}", message);
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.as_str()
+    } else {
+        "unknown panic"
+    }
+}
+
+fn crash_report(message: &str, location: Option<&str>, context: &str, backtrace: Option<&str>) -> String {
+    let mut report = format!("{}\n\n", message);
+
+    if let Some(location) = location {
+        report.push_str(&format!("at {}\n", location));
+    }
+
+    report.push_str(&format!("version: {}\n", env!("CARGO_PKG_VERSION")));
+
+    if !context.is_empty() {
+        report.push_str(context);
+        report.push('\n');
+    }
+
+    if let Some(backtrace) = backtrace {
+        report.push('\n');
+        report.push_str(backtrace);
+    }
+
+    report
+}
+
+#[test]
+fn test_crash_report() {
+    let payload: Box<dyn Any + Send> = Box::new("index out of bounds");
+    assert_eq!(panic_message(payload.as_ref()), "index out of bounds");
+    let payload: Box<dyn Any + Send> = Box::new(format!("no NPC #{}", 12));
+    assert_eq!(panic_message(payload.as_ref()), "no NPC #12");
+    let payload: Box<dyn Any + Send> = Box::new(12);
+    assert_eq!(panic_message(payload.as_ref()), "unknown panic");
+
+    let report = crash_report("no NPC #12", Some("src/npc/mod.rs:10"), "Stage history: empty", None);
+    assert!(report.starts_with("no NPC #12\n\nat src/npc/mod.rs:10\n"));
+    assert!(report.ends_with("Stage history: empty\n"));
+}
@@ -0,0 +1,185 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::io::Read;
+
+use crate::engine_constants::{EngineConstants, ExpectedFile};
+use crate::ggez::{Context, filesystem};
+use crate::stage::StageData;
+use crate::str;
+
+// Startup check of the data directory, most "the game doesn't start" reports come down to missing files.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    Missing,
+    /// Size and the expected minimum.
+    TooSmall(u64, u64),
+    /// The file doesn't start with the expected bytes.
+    WrongFormat,
+    Unreadable(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct DataIssue {
+    pub path: String,
+    pub problem: Problem,
+    pub essential: bool,
+}
+
+impl DataIssue {
+    /// The game can't run at all, as opposed to running with something missing or looking broken.
+    pub fn is_fatal(&self) -> bool {
+        match self.problem {
+            Problem::Missing | Problem::Unreadable(_) => self.essential,
+            Problem::TooSmall(..) | Problem::WrongFormat => false,
+        }
+    }
+}
+
+impl fmt::Display for DataIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.problem {
+            Problem::Missing => write!(f, "{} is missing", self.path),
+            Problem::TooSmall(size, min_size) => write!(f, "{} is truncated ({} bytes, at least {} expected)", self.path, size, min_size),
+            Problem::WrongFormat => write!(f, "{} is not in the expected format", self.path),
+            Problem::Unreadable(e) => write!(f, "{} can't be read: {}", self.path, e),
+        }
+    }
+}
+
+pub struct DataReport {
+    /// Files checked, missing ones included.
+    pub checked: usize,
+    pub issues: Vec<DataIssue>,
+}
+
+impl DataReport {
+    pub fn is_fatal(&self) -> bool {
+        self.issues.iter().any(DataIssue::is_fatal)
+    }
+
+    /// Shown before the title screen, missing optional files are only logged.
+    pub fn needs_attention(&self) -> bool {
+        self.issues.iter().any(|issue| issue.essential)
+    }
+
+    pub fn log(&self) {
+        if self.issues.is_empty() {
+            log::info!("Data directory: {} files checked, nothing missing.", self.checked);
+            return;
+        }
+
+        log::warn!("Data directory: {} files checked, {} problems{}:", self.checked, self.issues.len(),
+                   if self.is_fatal() { ", the game can't run" } else { "" });
+        for issue in self.issues.iter() {
+            if issue.is_fatal() {
+                log::error!("  {}", issue);
+            } else {
+                log::warn!("  {}{}", issue, if issue.essential { "" } else { " (optional)" });
+            }
+        }
+    }
+}
+
+/// Problem with a file of `size` bytes starting with `header`, `None` if it looks fine.
+fn check_contents(size: u64, header: &[u8], expected: &ExpectedFile) -> Option<Problem> {
+    if size < expected.min_size {
+        Some(Problem::TooSmall(size, expected.min_size))
+    } else if !header.starts_with(&expected.magic) {
+        Some(Problem::WrongFormat)
+    } else {
+        None
+    }
+}
+
+fn check_file(ctx: &mut Context, base_path: &str, expected: &ExpectedFile) -> Option<DataIssue> {
+    let paths: Vec<String> = expected.paths.iter()
+        .map(|path| if path.starts_with('/') { path.clone() } else { [base_path, path].join("") })
+        .collect();
+
+    let issue = |path: &str, problem: Problem| Some(DataIssue { path: path.to_owned(), problem, essential: expected.essential });
+    let path = match paths.iter().find(|path| filesystem::exists(ctx, path)) {
+        Some(path) => path,
+        None => { return issue(&paths.join(" or "), Problem::Missing); }
+    };
+
+    // directories only have to be there
+    if path.ends_with('/') {
+        return None;
+    }
+
+    let size = match filesystem::file_size(ctx, path) {
+        Ok(size) => size,
+        Err(e) => { return issue(path, Problem::Unreadable(e.to_string())); }
+    };
+    let mut header = Vec::with_capacity(expected.magic.len());
+    let read = filesystem::open(ctx, path)
+        .and_then(|file| Ok(file.take(expected.magic.len() as u64).read_to_end(&mut header)?));
+    if let Err(e) = read {
+        return issue(path, Problem::Unreadable(e.to_string()));
+    }
+
+    check_contents(size, &header, expected).and_then(|problem| issue(path, problem))
+}
+
+/// Checks the files of the manifest in `constants`, the songs and the files of every stage in the stage table.
+pub fn validate(ctx: &mut Context, base_path: &str, constants: &EngineConstants) -> DataReport {
+    let manifest = &constants.data_manifest;
+    let mut expected: Vec<ExpectedFile> = manifest.files.clone();
+
+    for song in manifest.songs.iter() {
+        expected.push(ExpectedFile {
+            paths: constants.organya_paths.iter().map(|prefix| [prefix, song, ".org"].join("")).collect(),
+            min_size: 16,
+            magic: b"Org-".to_vec(),
+            essential: true,
+        });
+    }
+
+    let mut issues = Vec::new();
+    if manifest.check_stages {
+        match StageData::load_stage_table(ctx, base_path) {
+            Ok(stages) => {
+                let mut maps = BTreeSet::new();
+                let mut tilesets = BTreeSet::new();
+                for stage in stages.iter().filter(|stage| !stage.map.is_empty()) {
+                    maps.insert(stage.map.clone());
+                    tilesets.insert(stage.tileset.name().to_owned());
+                }
+
+                // a missing stage only breaks the game once it's entered
+                for map in maps {
+                    expected.push(ExpectedFile { paths: vec![format!("Stage/{}.pxm", map)], min_size: 8, magic: b"PXM".to_vec(), essential: false });
+                    expected.push(ExpectedFile { paths: vec![format!("Stage/{}.tsc", map)], min_size: 0, magic: Vec::new(), essential: false });
+                }
+                for tileset in tilesets.into_iter().filter(|name| !name.is_empty()) {
+                    expected.push(ExpectedFile { paths: vec![format!("Stage/{}.pxa", tileset)], min_size: 0, magic: Vec::new(), essential: false });
+                }
+            }
+            Err(e) => issues.push(DataIssue { path: str!("stage table"), problem: Problem::Unreadable(e.to_string()), essential: true }),
+        }
+    }
+
+    issues.extend(expected.iter().filter_map(|file| check_file(ctx, base_path, file)));
+    DataReport { checked: expected.len(), issues }
+}
+
+#[test]
+fn test_check_contents() {
+    let org = ExpectedFile { paths: vec![str!("/org/curly.org")], min_size: 16, magic: b"Org-".to_vec(), essential: true };
+    assert_eq!(check_contents(4000, b"Org-", &org), None);
+    assert_eq!(check_contents(10, b"Org-", &org), Some(Problem::TooSmall(10, 16)));
+    assert_eq!(check_contents(4000, b"RIFF", &org), Some(Problem::WrongFormat));
+
+    let missing = DataIssue { path: str!("/npc.tbl"), problem: Problem::Missing, essential: true };
+    let optional = DataIssue { path: str!("/base/Ogg/"), problem: Problem::Missing, essential: false };
+    let truncated = DataIssue { path: str!("/MyChar.pbm"), problem: Problem::TooSmall(10, 1024), essential: true };
+
+    let report = DataReport { checked: 3, issues: vec![optional.clone()] };
+    assert!(!report.is_fatal() && !report.needs_attention());
+    let report = DataReport { checked: 3, issues: vec![optional, truncated] };
+    assert!(!report.is_fatal() && report.needs_attention());
+    let report = DataReport { checked: 3, issues: vec![missing] };
+    assert!(report.is_fatal());
+    assert_eq!(report.issues[0].to_string(), "/npc.tbl is missing");
+}
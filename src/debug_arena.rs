@@ -0,0 +1,119 @@
+use crate::ggez::{Context, GameResult};
+use crate::ggez::GameError::InvalidValue;
+use crate::map::Map;
+use crate::scene::game_scene::GameScene;
+use crate::SharedGameState;
+use crate::stage::Stage;
+use crate::str;
+use crate::weapon::{Weapon, WeaponType};
+
+// Debug helpers setting up a situation from scratch, shared by the boss rush and the repro scenarios.
+
+/// Solid for everything and drawn in front of the entities.
+const SOLID_ATTRIBUTE: u8 = 0x41;
+/// The floor is this many tiles thick, so nothing falls through it at high speeds.
+const FLOOR_THICKNESS: usize = 2;
+
+/// Weapons and equipment given to the player, replacing what they had.
+#[derive(Clone)]
+pub struct Loadout {
+    pub weapons: Vec<Weapon>,
+    /// Equipment bits, same as the <EQ+ argument.
+    pub equip: u16,
+    /// Also refills the life, `None` keeps it as it is.
+    pub max_life: Option<u16>,
+}
+
+pub fn give_loadout(game_scene: &mut GameScene, loadout: &Loadout) {
+    let owned: Vec<WeaponType> = (0..game_scene.inventory.get_weapon_count())
+        .filter_map(|i| game_scene.inventory.get_weapon(i))
+        .map(|weapon| weapon.wtype)
+        .collect();
+    for wtype in owned {
+        game_scene.inventory.remove_weapon(wtype);
+    }
+    for weapon in loadout.weapons.iter() {
+        game_scene.inventory.push_weapon(weapon.clone());
+    }
+    game_scene.inventory.set_current_weapon_idx(0);
+
+    game_scene.player.equip.0 = loadout.equip;
+    if let Some(max_life) = loadout.max_life {
+        game_scene.player.max_life = max_life;
+        game_scene.player.life = max_life;
+    }
+}
+
+/// Box of `width` x `height` tiles with a flat floor, walls on the sides and a ceiling, made of the first empty
+/// and the first solid tile found in the tileset attributes.
+pub fn arena_map(attrib: [u8; 0x100], width: usize, height: usize) -> GameResult<Map> {
+    if width < 8 || height < 6 {
+        return Err(InvalidValue(format!("The arena has to be at least 8x6 tiles, {}x{} is too small.", width, height)));
+    }
+
+    let empty = attrib.iter().position(|&attr| attr == 0)
+        .ok_or_else(|| InvalidValue(str!("The tileset has no empty tile.")))? as u8;
+    let solid = attrib.iter().position(|&attr| attr == SOLID_ATTRIBUTE)
+        .ok_or_else(|| InvalidValue(str!("The tileset has no solid tile.")))? as u8;
+
+    let mut tiles = vec![empty; width * height];
+    for y in 0..height {
+        for x in 0..width {
+            if x == 0 || x == width - 1 || y == 0 || y >= height - FLOOR_THICKNESS {
+                tiles[y * width + x] = solid;
+            }
+        }
+    }
+
+    Ok(Map {
+        width,
+        height,
+        tiles,
+        attrib,
+        revision: 0,
+    })
+}
+
+/// Tile row right above the floor of an arena `height` tiles high.
+pub fn floor_row(height: usize) -> usize {
+    height - FLOOR_THICKNESS - 1
+}
+
+/// Arena with the tileset, background and NPC sheets of stage `base_stage`, the player stands on the floor
+/// next to the left wall.
+pub fn arena_scene(state: &mut SharedGameState, ctx: &mut Context, base_stage: usize, width: usize, height: usize) -> GameResult<GameScene> {
+    if base_stage >= state.stages.len() {
+        return Err(InvalidValue(format!("Stage {} does not exist.", base_stage)));
+    }
+
+    let data = state.stages[base_stage].clone();
+    // only for the tile attributes of the tileset
+    let base = Stage::load(&state.base_path, &data, ctx)?;
+    let map = arena_map(base.map.attrib, width, height)?;
+    log::info!("Generated a {}x{} arena on the tileset of {}.", width, height, data.map);
+
+    let mut scene = GameScene::with_stage(state, ctx, base_stage, Stage::generated(map, &data))?;
+    scene.player.x = 3 * 16 * 0x200;
+    scene.player.y = (floor_row(height) * 16 * 0x200) as isize;
+
+    Ok(scene)
+}
+
+#[test]
+fn test_arena_map() {
+    let mut attrib = [0x41u8; 0x100];
+    attrib[2] = 0;
+    attrib[0] = 0x02;
+
+    let map = arena_map(attrib, 10, 6).unwrap();
+    assert_eq!((map.width, map.height), (10, 6));
+    // walls, ceiling and the two floor rows are the first solid tile, the rest the first empty one
+    assert!(map.is_solid(0, 3) && map.is_solid(9, 3) && map.is_solid(5, 0));
+    assert!(map.is_solid(5, 4) && map.is_solid(5, 5));
+    assert!(!map.is_solid(1, 1) && !map.is_solid(8, floor_row(6) as isize));
+    assert_eq!(map.tiles[3 * 10 + 5], 2);
+    assert_eq!(map.tiles[0], 1);
+
+    assert!(arena_map(attrib, 4, 4).is_err());
+    assert!(arena_map([0x41; 0x100], 10, 6).is_err());
+}
@@ -0,0 +1,108 @@
+/// Discord Rich Presence integration, enabled with the `discord` crate feature.
+/// Without the feature this is a no-op with the same API, so callers don't need any cfg attributes.
+pub struct DiscordRPC {
+    #[cfg(feature = "discord")]
+    tx: Option<std::sync::mpsc::Sender<PresenceMessage>>,
+}
+
+#[cfg(feature = "discord")]
+enum PresenceMessage {
+    Update { stage_name: String, life: u16, max_life: u16, play_secs: u64 },
+    Stop,
+}
+
+/// Discord application id, provided at build time.
+#[cfg(feature = "discord")]
+const CLIENT_ID: Option<&str> = option_env!("DISCORD_CLIENT_ID");
+
+impl DiscordRPC {
+    pub fn new() -> Self {
+        Self {
+            #[cfg(feature = "discord")]
+            tx: None,
+        }
+    }
+
+    #[cfg(not(feature = "discord"))]
+    pub fn update(&mut self, _enabled: bool, _stage_name: &str, _life: u16, _max_life: u16, _play_ticks: u64) {}
+
+    /// Publishes the current state. Never blocks, all IPC happens on a background thread which
+    /// quits on its own if Discord isn't running.
+    #[cfg(feature = "discord")]
+    pub fn update(&mut self, enabled: bool, stage_name: &str, life: u16, max_life: u16, play_ticks: u64) {
+        if !enabled {
+            if let Some(tx) = self.tx.take() {
+                let _ = tx.send(PresenceMessage::Stop);
+            }
+            return;
+        }
+
+        if self.tx.is_none() {
+            self.tx = Some(spawn_presence_thread());
+        }
+
+        let message = PresenceMessage::Update {
+            stage_name: stage_name.to_owned(),
+            life,
+            max_life,
+            play_secs: play_ticks / 50,
+        };
+
+        if let Some(tx) = self.tx.as_ref() {
+            // the thread exits when Discord is unavailable, after that all updates are dropped.
+            let _ = tx.send(message);
+        }
+    }
+}
+
+#[cfg(feature = "discord")]
+fn spawn_presence_thread() -> std::sync::mpsc::Sender<PresenceMessage> {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use discord_rich_presence::{activity, DiscordIpc, DiscordIpcClient};
+
+    let (tx, rx) = std::sync::mpsc::channel();
+
+    std::thread::spawn(move || {
+        let client_id = match CLIENT_ID {
+            Some(id) => id,
+            None => {
+                log::info!("Discord client id not set at build time, rich presence disabled.");
+                return;
+            }
+        };
+
+        let mut client = match DiscordIpcClient::new(client_id) {
+            Ok(client) => client,
+            Err(_) => { return; }
+        };
+
+        if client.connect().is_err() {
+            log::info!("Discord is not running, rich presence disabled.");
+            return;
+        }
+
+        while let Ok(message) = rx.recv() {
+            match message {
+                PresenceMessage::Update { stage_name, life, max_life, play_secs } => {
+                    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+                    let hp = format!("HP: {}/{}", life, max_life);
+                    let activity = activity::Activity::new()
+                        .details(&stage_name)
+                        .state(&hp)
+                        .timestamps(activity::Timestamps::new().start(now.saturating_sub(play_secs) as i64));
+
+                    if client.set_activity(activity).is_err() {
+                        log::warn!("Lost connection to Discord, rich presence disabled.");
+                        break;
+                    }
+                }
+                PresenceMessage::Stop => { break; }
+            }
+        }
+
+        let _ = client.close();
+    });
+
+    tx
+}
@@ -1,12 +1,23 @@
+use std::collections::HashMap;
+use std::slice;
+
 use log::info;
 
 use case_insensitive_hashmap::CaseInsensitiveHashMap;
 
+use crate::anim_rects;
+use crate::caret::CaretLayer;
 use crate::case_insensitive_hashmap;
-use crate::common::{Flag, Rect};
+use crate::common::{FILE_TYPES, Flag, Rect};
+use crate::ggez::event::Button;
+use crate::map::{TilePass, TilePassRange};
 use crate::player::ControlMode;
+use crate::sound::SfxPriority;
+use crate::stage_effect::StageEffectType;
 use crate::str;
-use crate::text_script::TextScriptEncoding;
+use crate::text_script::{InstantTextCommand, OpCode, TextScriptEncoding};
+use crate::texture_set::NinePatch;
+use crate::transition::TransitionType;
 
 #[derive(Debug, Copy, Clone)]
 pub struct PhysicsConsts {
@@ -28,6 +39,20 @@ pub struct BoosterConsts {
     pub b2_0_down: isize,
     pub b2_0_left: isize,
     pub b2_0_right: isize,
+    /// Fuel gauge drawn above the player while the booster isn't full, from the TextBox sheet.
+    pub fuel_bar_empty: Rect<usize>,
+    /// Cut to the remaining fuel and drawn over the empty one.
+    pub fuel_bar_full: Rect<usize>,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct ExperienceConsts {
+    /// How far past the player's center crystals are still collected, the hitbox of the crystal is added to it.
+    pub collect_margin: isize,
+    /// Vertical speed of the bounce off the floor.
+    pub bounce_vel_y: isize,
+    /// Part of the horizontal speed kept after a bounce, as a fraction.
+    pub bounce_vel_x_kept: (isize, isize),
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -41,11 +66,26 @@ pub struct MyCharConsts {
     pub water_physics: PhysicsConsts,
     pub animations_left: [Rect<usize>; 12],
     pub animations_right: [Rect<usize>; 12],
+    /// Offset between the rows of the player skins (e.g. the Mimiga mask one).
+    pub skin_row_height: usize,
+    /// Skin row Curly starts at, followed by her Mimiga mask row. None if MyChar has no Curly sprites.
+    pub curly_skin_row: Option<usize>,
+    /// Drawn around the player underwater with the Air Tank, from the Caret sheet.
+    pub air_tank_bubble: [Rect<usize>; 2],
+    /// Size of a frame on the Arms sheet, every weapon has a column of 6 frames.
+    pub arms_frame_size: (usize, usize),
+    /// Position of the held weapon relative to the player sprite.
+    pub arms_offset_left: (isize, isize),
+    pub arms_offset_right: (isize, isize),
+    /// The weapon moves up by this much when aiming up, and down when aiming down.
+    pub arms_aim_offset: isize,
 }
 
 #[derive(Debug)]
 pub struct CaretConsts {
     pub offsets: [(isize, isize); 18],
+    /// Indexed by `CaretType`, like the offsets.
+    pub layers: [CaretLayer; 18],
     pub bubble_left_rects: Vec<Rect<usize>>,
     pub bubble_right_rects: Vec<Rect<usize>>,
     pub projectile_dissipation_left_rects: Vec<Rect<usize>>,
@@ -68,6 +108,7 @@ impl Clone for CaretConsts {
     fn clone(&self) -> Self {
         Self {
             offsets: self.offsets,
+            layers: self.layers,
             bubble_left_rects: self.bubble_left_rects.clone(),
             bubble_right_rects: self.bubble_right_rects.clone(),
             projectile_dissipation_left_rects: self.projectile_dissipation_left_rects.clone(),
@@ -132,9 +173,11 @@ impl Clone for WeaponConsts {
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Clone)]
 pub struct WorldConsts {
     pub snack_rect: Rect<usize>,
+    /// Attribute ranges of the tile draw passes, checked in order.
+    pub tile_passes: Vec<TilePassRange>,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -193,12 +236,84 @@ pub struct NPCConsts {
     pub n211_small_spikes: [Rect<usize>; 4],
 }
 
+impl NPCConsts {
+    /// Animation rects of an NPC type, empty if it doesn't have any in the constants.
+    pub fn rects(&self, npc_type: u16) -> &[Rect<usize>] {
+        match npc_type {
+            1 => &self.n001_experience,
+            2 => &self.n002_behemoth,
+            4 => &self.n004_smoke,
+            5 => &self.n005_green_critter,
+            6 => &self.n006_green_beetle,
+            7 => &self.n007_basil,
+            8 => &self.n008_blue_beetle,
+            15 => &self.n015_closed_chest,
+            16 => &self.n016_save_point,
+            17 => &self.n017_health_refill,
+            18 => &self.n018_door,
+            20 => &self.n020_computer,
+            21 => slice::from_ref(&self.n021_chest_open),
+            22 => &self.n022_teleporter,
+            23 => &self.n023_teleporter_lights,
+            27 => slice::from_ref(&self.n027_death_trap),
+            29 => &self.n029_cthulhu,
+            30 => &self.n030_hermit_gunsmith,
+            32 => &self.n032_life_capsule,
+            34 => &self.n034_bed,
+            35 => &self.n035_mannan,
+            37 => &self.n037_sign,
+            38 => &self.n038_fireplace,
+            39 => &self.n039_save_sign,
+            40 => &self.n040_santa,
+            41 => slice::from_ref(&self.n041_busted_door),
+            42 => &self.n042_sue,
+            43 => &self.n043_chalkboard,
+            44 => &self.n044_polish,
+            45 => &self.n045_baby,
+            47 => &self.n047_sandcroc,
+            48 => &self.n048_omega_projectiles,
+            49 => &self.n049_skullhead,
+            52 => slice::from_ref(&self.n052_sitting_blue_robot),
+            55 => &self.n055_kazuma,
+            59 => &self.n059_eye_door,
+            60 => &self.n060_toroko,
+            61 => &self.n061_king,
+            62 => &self.n062_kazuma_computer,
+            63 => &self.n063_toroko_stick,
+            64 => &self.n064_first_cave_critter,
+            65 => &self.n065_first_cave_bat,
+            70 => &self.n070_sparkle,
+            71 => &self.n071_chinfish,
+            72 => &self.n072_sprinkler,
+            73 => &self.n073_water_droplet,
+            74 => &self.n074_jack,
+            75 => &self.n075_kanpachi,
+            77 => &self.n077_yamashita,
+            78 => &self.n078_pot,
+            79 => &self.n079_mahin,
+            211 => &self.n211_small_spikes,
+            _ => &[],
+        }
+    }
+}
+
+/// Bounds the original engine uses for an NPC type instead of the wrong ones in npc.tbl.
+#[derive(Debug, Copy, Clone)]
+pub struct NPCBoundsOverride {
+    pub npc_type: u16,
+    /// Bounds in the vanilla npc.tbl, tables with anything else have been fixed already and are left alone.
+    pub vanilla_hit_bounds: Rect<u8>,
+    pub vanilla_display_bounds: Rect<u8>,
+    pub hit_bounds: Rect<u8>,
+    pub display_bounds: Rect<u8>,
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct TextScriptConsts {
     pub encoding: TextScriptEncoding,
-    pub textbox_rect_top: Rect<usize>,
-    pub textbox_rect_middle: Rect<usize>,
-    pub textbox_rect_bottom: Rect<usize>,
+    /// Frame of the message box, also used by menus.
+    pub textbox_frame: NinePatch,
+    pub inventory_frame: NinePatch,
     pub textbox_rect_yes_no: Rect<usize>,
     pub textbox_rect_cursor: Rect<usize>,
     pub get_item_top_left: Rect<usize>,
@@ -206,6 +321,84 @@ pub struct TextScriptConsts {
     pub get_item_top_right: Rect<usize>,
     pub get_item_right: Rect<usize>,
     pub get_item_bottom_right: Rect<usize>,
+    /// What each of the instant text commands does, so quirks of engines based on the original can be emulated.
+    pub instant_text_commands: [(OpCode, InstantTextCommand); 3],
+    /// Sound played for every character printed in a message.
+    pub text_blip_sfx: u8,
+    /// Ticks between the characters of a message.
+    pub text_speed: u8,
+}
+
+impl TextScriptConsts {
+    pub fn instant_text_command(&self, op: OpCode) -> InstantTextCommand {
+        self.instant_text_commands.iter()
+            .find(|(code, _)| *code == op)
+            .map(|(_, command)| *command)
+            .unwrap_or(InstantTextCommand::Ignored)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LightingConsts {
+    /// Darkness level (0.0 - no lighting pass, 1.0 - pitch black) of stages, by map name.
+    pub stage_darkness: CaseInsensitiveHashMap<f32>,
+    /// NPC types emitting light and the radius of the light in pixels.
+    pub npc_lights: Vec<(u16, f32)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SoundConsts {
+    /// Priority classes of the sound effects, `SfxPriority::Normal` if not listed.
+    pub sfx_priorities: HashMap<u8, SfxPriority>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PromptConsts {
+    pub texture: String,
+    /// Glyphs of the gamepad buttons, buttons without one are drawn as their name in a box.
+    pub button_rects: Vec<(Button, Rect<usize>)>,
+}
+
+/// A file the data directory should have, checked at startup.
+#[derive(Debug, Clone)]
+pub struct ExpectedFile {
+    /// Relative to the base path unless they start with a slash, the first one found is checked.
+    pub paths: Vec<String>,
+    /// Smaller files are reported as truncated.
+    pub min_size: u64,
+    /// Leading bytes, not checked if empty.
+    pub magic: Vec<u8>,
+    /// The game can't start without it, otherwise it only misses a feature.
+    pub essential: bool,
+}
+
+impl ExpectedFile {
+    fn essential(path: &str, min_size: u64, magic: &[u8]) -> ExpectedFile {
+        ExpectedFile { paths: vec![path.to_owned()], min_size, magic: magic.to_vec(), essential: true }
+    }
+
+    /// Any of the image formats the texture loader takes.
+    fn texture(name: &str, min_size: u64) -> ExpectedFile {
+        let paths = FILE_TYPES.iter().map(|ext| [name, ext].join("")).collect();
+        ExpectedFile { paths, min_size, magic: Vec::new(), essential: true }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DataManifestConsts {
+    pub files: Vec<ExpectedFile>,
+    /// Songs of the first hour of the game, looked up in `organya_paths`.
+    pub songs: Vec<String>,
+    /// Checks the map, the tile attributes and the script of every stage in the stage table.
+    pub check_stages: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct StageEffectConsts {
+    /// Stage effects active from the moment a stage is entered, by map name.
+    pub stage_effects: CaseInsensitiveHashMap<StageEffectType>,
+    /// Force added to the velocity of the player and NPCs in water every tick, on top of the current tiles, by map name.
+    pub stage_currents: CaseInsensitiveHashMap<(isize, isize)>,
 }
 
 #[derive(Debug)]
@@ -213,16 +406,30 @@ pub struct EngineConstants {
     pub is_cs_plus: bool,
     pub my_char: MyCharConsts,
     pub booster: BoosterConsts,
+    pub experience: ExperienceConsts,
     pub caret: CaretConsts,
     pub world: WorldConsts,
     pub npc: NPCConsts,
+    pub npc_bounds_overrides: Vec<NPCBoundsOverride>,
     pub weapon: WeaponConsts,
     pub tex_sizes: CaseInsensitiveHashMap<(usize, usize)>,
     pub textscript: TextScriptConsts,
+    pub lighting: LightingConsts,
+    pub sound: SoundConsts,
+    pub prompts: PromptConsts,
+    pub stage_effect: StageEffectConsts,
     pub font_path: String,
     pub font_scale: f32,
     pub font_space_offset: f32,
+    /// TrueType fonts used for the characters the bitmap font doesn't have, tried in order.
+    pub font_fallback_paths: Vec<String>,
+    /// Size of the fallback font on the screen.
+    pub font_fallback_size: f32,
+    /// Used by the fades unless a script or the settings ask for another one.
+    pub transition: TransitionType,
     pub organya_paths: Vec<String>,
+    /// Checked at startup, see `data_check`.
+    pub data_manifest: DataManifestConsts,
 }
 
 impl Clone for EngineConstants {
@@ -231,16 +438,26 @@ impl Clone for EngineConstants {
             is_cs_plus: self.is_cs_plus,
             my_char: self.my_char,
             booster: self.booster,
+            experience: self.experience,
             caret: self.caret.clone(),
             world: self.world.clone(),
             npc: self.npc.clone(),
+            npc_bounds_overrides: self.npc_bounds_overrides.clone(),
             weapon: self.weapon.clone(),
             tex_sizes: self.tex_sizes.clone(),
             textscript: self.textscript.clone(),
+            lighting: self.lighting.clone(),
+            sound: self.sound.clone(),
+            prompts: self.prompts.clone(),
+            stage_effect: self.stage_effect.clone(),
             font_path: self.font_path.clone(),
             font_scale: self.font_scale,
             font_space_offset: self.font_space_offset,
+            font_fallback_paths: self.font_fallback_paths.clone(),
+            font_fallback_size: self.font_fallback_size,
+            transition: self.transition,
             organya_paths: self.organya_paths.clone(),
+            data_manifest: self.data_manifest.clone(),
         }
     }
 }
@@ -303,6 +520,16 @@ impl EngineConstants {
                     Rect { left: 96, top: 16, right: 112, bottom: 32 },
                     Rect { left: 112, top: 16, right: 128, bottom: 32 },
                 ],
+                skin_row_height: 32,
+                curly_skin_row: None,
+                air_tank_bubble: [
+                    Rect { left: 56, top: 96, right: 80, bottom: 120 },
+                    Rect { left: 80, top: 96, right: 104, bottom: 120 },
+                ],
+                arms_frame_size: (24, 16),
+                arms_offset_left: (-8, 0),
+                arms_offset_right: (0, 0),
+                arms_aim_offset: 4,
             },
             booster: BoosterConsts {
                 fuel: 50,
@@ -311,6 +538,13 @@ impl EngineConstants {
                 b2_0_down: 0x5ff,
                 b2_0_left: -0x5ff,
                 b2_0_right: 0x5ff,
+                fuel_bar_empty: Rect { left: 0, top: 72, right: 40, bottom: 80 },
+                fuel_bar_full: Rect { left: 0, top: 80, right: 40, bottom: 88 },
+            },
+            experience: ExperienceConsts {
+                collect_margin: 2 * 0x200,
+                bounce_vel_y: -0x280,
+                bounce_vel_x_kept: (2, 3),
             },
             caret: CaretConsts {
                 offsets: [
@@ -333,6 +567,26 @@ impl EngineConstants {
                     (20 * 0x200, 4 * 0x200),
                     (52 * 0x200, 4 * 0x200),
                 ],
+                layers: [
+                    CaretLayer::Front,
+                    CaretLayer::Front,
+                    CaretLayer::Front,
+                    CaretLayer::Front,
+                    CaretLayer::Front,
+                    CaretLayer::Front,
+                    CaretLayer::Front,
+                    CaretLayer::Behind, // booster exhaust
+                    CaretLayer::Front,
+                    CaretLayer::Front,
+                    CaretLayer::Front,
+                    CaretLayer::Front,
+                    CaretLayer::Front,
+                    CaretLayer::Behind, // landing and dash smoke
+                    CaretLayer::Front,
+                    CaretLayer::Front,
+                    CaretLayer::Front,
+                    CaretLayer::Front,
+                ],
                 bubble_left_rects: vec![
                     Rect { left: 0, top: 64, right: 8, bottom: 72 },
                     Rect { left: 8, top: 64, right: 16, bottom: 72 },
@@ -409,16 +663,15 @@ impl EngineConstants {
             },
             world: WorldConsts {
                 snack_rect: Rect { left: 256, top: 48, right: 272, bottom: 64 },
+                // same as PutStage_Back and PutStage_Front, water (0x60-0x7f) is drawn over the entities
+                tile_passes: vec![
+                    TilePassRange { first: 0x00, last: 0x1f, pass: TilePass::Background },
+                    TilePassRange { first: 0x43, last: 0x43, pass: TilePass::Snack },
+                    TilePassRange { first: 0x40, last: 0x7f, pass: TilePass::Foreground },
+                ],
             },
             npc: NPCConsts {
-                n001_experience: [
-                    Rect { left: 0, top: 16, right: 16, bottom: 32 },
-                    Rect { left: 16, top: 16, right: 32, bottom: 32 },
-                    Rect { left: 32, top: 16, right: 48, bottom: 32 },
-                    Rect { left: 48, top: 16, right: 64, bottom: 32 },
-                    Rect { left: 64, top: 16, right: 80, bottom: 32 },
-                    Rect { left: 80, top: 16, right: 96, bottom: 32 },
-                ],
+                n001_experience: anim_rects!(sheet_y = 16, frame_w = 16, frame_h = 16, frames = 6, directions = 1),
                 n002_behemoth: [
                     Rect { left: 32, top: 0, right: 64, bottom: 24 }, // left
                     Rect { left: 0, top: 0, right: 32, bottom: 24 },
@@ -453,26 +706,8 @@ impl EngineConstants {
                     Rect { left: 64, top: 128, right: 80, bottom: 144 },
                     Rect { left: 80, top: 128, right: 96, bottom: 144 },
                 ],
-                n005_green_critter: [
-                    Rect { left: 0, top: 48, right: 16, bottom: 64 }, // left
-                    Rect { left: 16, top: 48, right: 32, bottom: 64 },
-                    Rect { left: 32, top: 48, right: 48, bottom: 64 },
-                    Rect { left: 0, top: 64, right: 16, bottom: 80 }, // right
-                    Rect { left: 16, top: 64, right: 32, bottom: 80 },
-                    Rect { left: 32, top: 64, right: 48, bottom: 80 },
-                ],
-                n006_green_beetle: [
-                    Rect { left: 0, top: 80, right: 16, bottom: 96 }, // left
-                    Rect { left: 16, top: 80, right: 32, bottom: 96 },
-                    Rect { left: 32, top: 80, right: 48, bottom: 96 },
-                    Rect { left: 48, top: 80, right: 64, bottom: 96 },
-                    Rect { left: 64, top: 80, right: 80, bottom: 96 },
-                    Rect { left: 0, top: 96, right: 16, bottom: 112 }, // right
-                    Rect { left: 16, top: 96, right: 32, bottom: 112 },
-                    Rect { left: 32, top: 96, right: 48, bottom: 112 },
-                    Rect { left: 48, top: 96, right: 64, bottom: 112 },
-                    Rect { left: 64, top: 96, right: 80, bottom: 112 },
-                ],
+                n005_green_critter: anim_rects!(sheet_y = 48, frame_w = 16, frame_h = 16, frames = 3, directions = 2),
+                n006_green_beetle: anim_rects!(sheet_y = 80, frame_w = 16, frame_h = 16, frames = 5, directions = 2),
                 n007_basil: [
                     Rect { left: 256, top: 64, right: 288, bottom: 80 }, // left
                     Rect { left: 256, top: 80, right: 288, bottom: 96 },
@@ -481,31 +716,10 @@ impl EngineConstants {
                     Rect { left: 288, top: 80, right: 320, bottom: 96 },
                     Rect { left: 288, top: 96, right: 320, bottom: 112 },
                 ],
-                n008_blue_beetle: [
-                    Rect { left: 80, top: 80, right: 96, bottom: 96 },// left
-                    Rect { left: 96, top: 80, right: 112, bottom: 96 },
-                    Rect { left: 80, top: 96, right: 96, bottom: 112 }, // right
-                    Rect { left: 96, top: 96, right: 112, bottom: 112 },
-                ],
-                n015_closed_chest: [
-                    Rect { left: 240, top: 0, right: 256, bottom: 16 },
-                    Rect { left: 256, top: 0, right: 272, bottom: 16 },
-                    Rect { left: 272, top: 0, right: 288, bottom: 16 },
-                ],
-                n016_save_point: [
-                    Rect { left: 96, top: 16, right: 112, bottom: 32 },
-                    Rect { left: 112, top: 16, right: 128, bottom: 32 },
-                    Rect { left: 128, top: 16, right: 144, bottom: 32 },
-                    Rect { left: 144, top: 16, right: 160, bottom: 32 },
-                    Rect { left: 160, top: 16, right: 176, bottom: 32 },
-                    Rect { left: 176, top: 16, right: 192, bottom: 32 },
-                    Rect { left: 192, top: 16, right: 208, bottom: 32 },
-                    Rect { left: 208, top: 16, right: 224, bottom: 32 },
-                ],
-                n017_health_refill: [
-                    Rect { left: 288, top: 0, right: 304, bottom: 16 },
-                    Rect { left: 304, top: 0, right: 320, bottom: 16 },
-                ],
+                n008_blue_beetle: anim_rects!(sheet_x = 80, sheet_y = 80, frame_w = 16, frame_h = 16, frames = 2, directions = 2),
+                n015_closed_chest: anim_rects!(sheet_x = 240, sheet_y = 0, frame_w = 16, frame_h = 16, frames = 3, directions = 1),
+                n016_save_point: anim_rects!(sheet_x = 96, sheet_y = 16, frame_w = 16, frame_h = 16, frames = 8, directions = 1),
+                n017_health_refill: anim_rects!(sheet_x = 288, sheet_y = 0, frame_w = 16, frame_h = 16, frames = 2, directions = 1),
                 n018_door: [
                     Rect { left: 224, top: 16, right: 240, bottom: 40 },
                     Rect { left: 192, top: 112, right: 208, bottom: 136 },
@@ -533,49 +747,21 @@ impl EngineConstants {
                     Rect { left: 264, top: 44, right: 288, bottom: 48 },
                 ],
                 n027_death_trap: Rect { left: 96, top: 64, right: 128, bottom: 88 },
-                n029_cthulhu: [
-                    Rect { left: 0, top: 192, right: 16, bottom: 216 }, // left
-                    Rect { left: 16, top: 192, right: 32, bottom: 216 },
-                    Rect { left: 0, top: 216, right: 16, bottom: 240 }, // right
-                    Rect { left: 16, top: 216, right: 32, bottom: 240 },
-                ],
+                n029_cthulhu: anim_rects!(sheet_y = 192, frame_w = 16, frame_h = 24, frames = 2, directions = 2),
                 n030_hermit_gunsmith: [
                     Rect { left: 48, top: 0, right: 64, bottom: 16 },
                     Rect { left: 48, top: 16, right: 64, bottom: 32 },
                     Rect { left: 0, top: 32, right: 16, bottom: 48 },
                 ],
-                n032_life_capsule: [
-                    Rect { left: 32, top: 96, right: 48, bottom: 112 },
-                    Rect { left: 48, top: 96, right: 64, bottom: 112 },
-                ],
+                n032_life_capsule: anim_rects!(sheet_x = 32, sheet_y = 96, frame_w = 16, frame_h = 16, frames = 2, directions = 1),
                 n034_bed: [
                     Rect { left: 192, top: 48, right: 224, bottom: 64 },
                     Rect { left: 192, top: 184, right: 224, bottom: 200 },
                 ],
-                n035_mannan: [
-                    Rect { left: 96, top: 64, right: 120, bottom: 96 }, // left
-                    Rect { left: 120, top: 64, right: 144, bottom: 96 },
-                    Rect { left: 144, top: 64, right: 168, bottom: 96 },
-                    Rect { left: 168, top: 64, right: 192, bottom: 96 },
-                    Rect { left: 96, top: 96, right: 120, bottom: 128 }, // right
-                    Rect { left: 120, top: 96, right: 144, bottom: 128 },
-                    Rect { left: 144, top: 96, right: 168, bottom: 128 },
-                    Rect { left: 168, top: 96, right: 192, bottom: 128 },
-                ],
-                n037_sign: [
-                    Rect { left: 192, top: 64, right: 208, bottom: 80 },
-                    Rect { left: 208, top: 64, right: 224, bottom: 80 },
-                ],
-                n038_fireplace: [
-                    Rect { left: 128, top: 64, right: 144, bottom: 80 },
-                    Rect { left: 144, top: 64, right: 160, bottom: 80 },
-                    Rect { left: 160, top: 64, right: 176, bottom: 80 },
-                    Rect { left: 176, top: 64, right: 192, bottom: 80 },
-                ],
-                n039_save_sign: [
-                    Rect { left: 224, top: 64, right: 240, bottom: 80 },
-                    Rect { left: 240, top: 64, right: 256, bottom: 80 },
-                ],
+                n035_mannan: anim_rects!(sheet_x = 96, sheet_y = 64, frame_w = 24, frame_h = 32, frames = 4, directions = 2),
+                n037_sign: anim_rects!(sheet_x = 192, sheet_y = 64, frame_w = 16, frame_h = 16, frames = 2, directions = 1),
+                n038_fireplace: anim_rects!(sheet_x = 128, sheet_y = 64, frame_w = 16, frame_h = 16, frames = 4, directions = 1),
+                n039_save_sign: anim_rects!(sheet_x = 224, sheet_y = 64, frame_w = 16, frame_h = 16, frames = 2, directions = 1),
                 n040_santa: [
                     Rect { left: 0, top: 32, right: 16, bottom: 48 }, // left
                     Rect { left: 16, top: 32, right: 32, bottom: 48 },
@@ -621,10 +807,7 @@ impl EngineConstants {
                     Rect { left: 112, top: 48, right: 128, bottom: 64 },
                     Rect { left: 160, top: 48, right: 176, bottom: 64 },
                 ],
-                n043_chalkboard: [
-                    Rect { left: 128, top: 80, right: 168, bottom: 112 },
-                    Rect { left: 168, top: 80, right: 208, bottom: 112 },
-                ],
+                n043_chalkboard: anim_rects!(sheet_x = 128, sheet_y = 80, frame_w = 40, frame_h = 32, frames = 2, directions = 1),
                 n044_polish: [
                     Rect { left: 0, top: 0, right: 32, bottom: 32 }, // left
                     Rect { left: 96, top: 0, right: 128, bottom: 32 },
@@ -633,32 +816,10 @@ impl EngineConstants {
                     Rect { left: 32, top: 0, right: 64, bottom: 32 },
                     Rect { left: 64, top: 0, right: 96, bottom: 32 },
                 ],
-                n045_baby: [
-                    Rect { left: 0, top: 32, right: 16, bottom: 48 },
-                    Rect { left: 16, top: 32, right: 32, bottom: 48 },
-                    Rect { left: 32, top: 32, right: 48, bottom: 48 },
-                ],
-                n047_sandcroc: [
-                    Rect { left: 0, top: 48, right: 48, bottom: 80 },
-                    Rect { left: 48, top: 48, right: 96, bottom: 80 },
-                    Rect { left: 96, top: 48, right: 144, bottom: 80 },
-                    Rect { left: 144, top: 48, right: 192, bottom: 80 },
-                    Rect { left: 192, top: 48, right: 240, bottom: 80 },
-                ],
-                n048_omega_projectiles: [
-                    Rect { left: 288, top: 88, right: 304, bottom: 104 }, // left
-                    Rect { left: 304, top: 88, right: 320, bottom: 104 },
-                    Rect { left: 288, top: 104, right: 304, bottom: 120 }, // right
-                    Rect { left: 304, top: 104, right: 320, bottom: 120 },
-                ],
-                n049_skullhead: [
-                    Rect { left: 0, top: 80, right: 32, bottom: 104 }, // left
-                    Rect { left: 32, top: 80, right: 64, bottom: 104 },
-                    Rect { left: 64, top: 80, right: 96, bottom: 104 },
-                    Rect { left: 0, top: 104, right: 32, bottom: 128 }, // right
-                    Rect { left: 32, top: 104, right: 64, bottom: 128 },
-                    Rect { left: 64, top: 104, right: 96, bottom: 128 },
-                ],
+                n045_baby: anim_rects!(sheet_y = 32, frame_w = 16, frame_h = 16, frames = 3, directions = 1),
+                n047_sandcroc: anim_rects!(sheet_y = 48, frame_w = 48, frame_h = 32, frames = 5, directions = 1),
+                n048_omega_projectiles: anim_rects!(sheet_x = 288, sheet_y = 88, frame_w = 16, frame_h = 16, frames = 2, directions = 2),
+                n049_skullhead: anim_rects!(sheet_y = 80, frame_w = 32, frame_h = 24, frames = 3, directions = 2),
                 n052_sitting_blue_robot: Rect { left: 240, top: 96, right: 256, bottom: 112 },
                 n055_kazuma: [
                     Rect { left: 192, top: 192, right: 208, bottom: 216 }, // left
@@ -720,11 +881,7 @@ impl EngineConstants {
                     Rect { left: 272, top: 48, right: 288, bottom: 64 },
                     Rect { left: 0, top: 0, right: 0, bottom: 0 },
                 ],
-                n062_kazuma_computer: [
-                    Rect { left: 272, top: 192, right: 288, bottom: 216 },
-                    Rect { left: 288, top: 192, right: 304, bottom: 216 },
-                    Rect { left: 304, top: 192, right: 320, bottom: 216 },
-                ],
+                n062_kazuma_computer: anim_rects!(sheet_x = 272, sheet_y = 192, frame_w = 16, frame_h = 24, frames = 3, directions = 1),
                 n063_toroko_stick: [
                     Rect { left: 64, top: 64, right: 80, bottom: 80 }, // left
                     Rect { left: 80, top: 64, right: 96, bottom: 80 },
@@ -739,49 +896,12 @@ impl EngineConstants {
                     Rect { left: 112, top: 80, right: 128, bottom: 96 },
                     Rect { left: 128, top: 80, right: 144, bottom: 96 },
                 ],
-                n064_first_cave_critter: [
-                    Rect { left: 0, top: 0, right: 16, bottom: 16 },
-                    Rect { left: 16, top: 0, right: 32, bottom: 16 },
-                    Rect { left: 32, top: 0, right: 48, bottom: 16 },
-                    Rect { left: 0, top: 16, right: 16, bottom: 32 },
-                    Rect { left: 16, top: 16, right: 32, bottom: 32 },
-                    Rect { left: 32, top: 16, right: 48, bottom: 32 },
-                ],
-                n065_first_cave_bat: [
-                    Rect { left: 32, top: 32, right: 48, bottom: 48 },
-                    Rect { left: 48, top: 32, right: 64, bottom: 48 },
-                    Rect { left: 64, top: 32, right: 80, bottom: 48 },
-                    Rect { left: 80, top: 32, right: 96, bottom: 48 },
-                    Rect { left: 32, top: 48, right: 48, bottom: 64 },
-                    Rect { left: 48, top: 48, right: 64, bottom: 64 },
-                    Rect { left: 64, top: 48, right: 80, bottom: 64 },
-                    Rect { left: 80, top: 48, right: 96, bottom: 64 },
-                ],
-                n070_sparkle: [
-                    Rect { left: 96, top: 48, right: 112, bottom: 64 },
-                    Rect { left: 112, top: 48, right: 128, bottom: 64 },
-                    Rect { left: 128, top: 48, right: 144, bottom: 64 },
-                    Rect { left: 144, top: 48, right: 160, bottom: 64 },
-                ],
-                n071_chinfish: [
-                    Rect { left: 64, top: 32, right: 80, bottom: 48 }, // left
-                    Rect { left: 80, top: 32, right: 96, bottom: 48 },
-                    Rect { left: 96, top: 32, right: 112, bottom: 48 },
-                    Rect { left: 64, top: 48, right: 80, bottom: 64 }, // right
-                    Rect { left: 80, top: 48, right: 96, bottom: 64 },
-                    Rect { left: 96, top: 48, right: 112, bottom: 64 },
-                ],
-                n072_sprinkler: [
-                    Rect { left: 224, top: 48, right: 240, bottom: 64 },
-                    Rect { left: 240, top: 48, right: 256, bottom: 64 },
-                ],
-                n073_water_droplet: [
-                    Rect { left: 72, top: 16, right: 74, bottom: 18 },
-                    Rect { left: 74, top: 16, right: 76, bottom: 18 },
-                    Rect { left: 76, top: 16, right: 78, bottom: 18 },
-                    Rect { left: 78, top: 16, right: 80, bottom: 18 },
-                    Rect { left: 80, top: 16, right: 82, bottom: 18 },
-                ],
+                n064_first_cave_critter: anim_rects!(sheet_y = 0, frame_w = 16, frame_h = 16, frames = 3, directions = 2),
+                n065_first_cave_bat: anim_rects!(sheet_x = 32, sheet_y = 32, frame_w = 16, frame_h = 16, frames = 4, directions = 2),
+                n070_sparkle: anim_rects!(sheet_x = 96, sheet_y = 48, frame_w = 16, frame_h = 16, frames = 4, directions = 1),
+                n071_chinfish: anim_rects!(sheet_x = 64, sheet_y = 32, frame_w = 16, frame_h = 16, frames = 3, directions = 2),
+                n072_sprinkler: anim_rects!(sheet_x = 224, sheet_y = 48, frame_w = 16, frame_h = 16, frames = 2, directions = 1),
+                n073_water_droplet: anim_rects!(sheet_x = 72, sheet_y = 16, frame_w = 2, frame_h = 2, frames = 5, directions = 1),
                 n074_jack: [
                     Rect { left: 64, top: 0, right: 80, bottom: 16 }, // left
                     Rect { left: 80, top: 0, right: 96, bottom: 16 },
@@ -796,34 +916,23 @@ impl EngineConstants {
                     Rect { left: 112, top: 16, right: 128, bottom: 32 },
                     Rect { left: 64, top: 16, right: 80, bottom: 32 },
                 ],
-                n075_kanpachi: [
-                    Rect { left: 272, top: 32, right: 296, bottom: 56 },
-                    Rect { left: 296, top: 32, right: 320, bottom: 56 },
-                ],
-                n077_yamashita: [
-                    Rect { left: 0, top: 16, right: 48, bottom: 48 },
-                    Rect { left: 48, top: 16, right: 96, bottom: 48 },
-                    Rect { left: 96, top: 16, right: 144, bottom: 48 },
-                ],
-                n078_pot: [
-                    Rect { left: 160, top: 48, right: 176, bottom: 64 },
-                    Rect { left: 176, top: 48, right: 192, bottom: 64 },
-                ],
-                n079_mahin: [
-                    Rect { left: 0, top: 0, right: 16, bottom: 16 }, // left
-                    Rect { left: 16, top: 0, right: 32, bottom: 16 },
-                    Rect { left: 32, top: 0, right: 48, bottom: 16 },
-                    Rect { left: 0, top: 16, right: 16, bottom: 32 }, // right
-                    Rect { left: 16, top: 16, right: 32, bottom: 32 },
-                    Rect { left: 32, top: 16, right: 48, bottom: 32 },
-                ],
-                n211_small_spikes: [
-                    Rect { left: 256, top: 200, right: 272, bottom: 216 },
-                    Rect { left: 272, top: 200, right: 288, bottom: 216 },
-                    Rect { left: 288, top: 200, right: 304, bottom: 216 },
-                    Rect { left: 304, top: 200, right: 320, bottom: 216 },
-                ],
+                n075_kanpachi: anim_rects!(sheet_x = 272, sheet_y = 32, frame_w = 24, frame_h = 24, frames = 2, directions = 1),
+                n077_yamashita: anim_rects!(sheet_y = 16, frame_w = 48, frame_h = 32, frames = 3, directions = 1),
+                n078_pot: anim_rects!(sheet_x = 160, sheet_y = 48, frame_w = 16, frame_h = 16, frames = 2, directions = 1),
+                n079_mahin: anim_rects!(sheet_y = 0, frame_w = 16, frame_h = 16, frames = 3, directions = 2),
+                n211_small_spikes: anim_rects!(sheet_x = 256, sheet_y = 200, frame_w = 16, frame_h = 16, frames = 4, directions = 1),
             },
+            // todo: only the spikes are known so far, check the rest of the table against the original executable
+            npc_bounds_overrides: vec![
+                // the table covers the whole tile, the spikes only take the bottom of it
+                NPCBoundsOverride {
+                    npc_type: 211,
+                    vanilla_hit_bounds: Rect { left: 8, top: 8, right: 8, bottom: 8 },
+                    vanilla_display_bounds: Rect { left: 8, top: 8, right: 8, bottom: 8 },
+                    hit_bounds: Rect { left: 4, top: 0, right: 4, bottom: 8 },
+                    display_bounds: Rect { left: 8, top: 8, right: 8, bottom: 8 },
+                },
+            ],
             weapon: WeaponConsts {
                 bullet_table: vec![
                     // Null
@@ -1078,9 +1187,8 @@ impl EngineConstants {
             },
             textscript: TextScriptConsts {
                 encoding: TextScriptEncoding::UTF8,
-                textbox_rect_top: Rect { left: 0, top: 0, right: 244, bottom: 8 },
-                textbox_rect_middle: Rect { left: 0, top: 8, right: 244, bottom: 16 },
-                textbox_rect_bottom: Rect { left: 0, top: 16, right: 244, bottom: 24 },
+                textbox_frame: NinePatch { rect: Rect { left: 0, top: 0, right: 244, bottom: 24 }, border: 8 },
+                inventory_frame: NinePatch { rect: Rect { left: 0, top: 0, right: 244, bottom: 24 }, border: 8 },
                 textbox_rect_yes_no: Rect { left: 152, top: 48, right: 244, bottom: 80 },
                 textbox_rect_cursor: Rect { left: 112, top: 88, right: 128, bottom: 104 },
                 get_item_top_left: Rect { left: 0, top: 0, right: 72, bottom: 16 },
@@ -1088,15 +1196,99 @@ impl EngineConstants {
                 get_item_top_right: Rect { left: 240, top: 0, right: 244, bottom: 8 },
                 get_item_right: Rect { left: 240, top: 8, right: 244, bottom: 16 },
                 get_item_bottom_right: Rect { left: 240, top: 16, right: 244, bottom: 24 },
+                // all three are aliases in vanilla
+                instant_text_commands: [
+                    (OpCode::SAT, InstantTextCommand::InstantText),
+                    (OpCode::CAT, InstantTextCommand::InstantText),
+                    (OpCode::TUR, InstantTextCommand::InstantText),
+                ],
+                text_blip_sfx: 2,
+                text_speed: 4,
+            },
+            lighting: LightingConsts {
+                // freeware draws the darkness into the tilesets, only CS+ darkens the stages
+                stage_darkness: case_insensitive_hashmap! {},
+                npc_lights: vec![
+                    (23, 48.0), // teleporter lights
+                    (85, 32.0), // terminal
+                ],
+            },
+            sound: SoundConsts {
+                sfx_priorities: [
+                    (2, SfxPriority::Low), // message blip
+                    (14, SfxPriority::Low), // experience pickup
+                    (16, SfxPriority::High), // player hurt
+                    (17, SfxPriority::High), // player death
+                    (24, SfxPriority::Low), // footstep
+                    (26, SfxPriority::High), // heavy landing, boss stomps and quakes
+                    (27, SfxPriority::High), // level up
+                    (31, SfxPriority::Low), // shot bouncing off a wall
+                    (45, SfxPriority::Low), // experience bounce
+                    (52, SfxPriority::High), // large enemy hurt, boss roars
+                ].iter().copied().collect(),
+            },
+            prompts: PromptConsts {
+                texture: str!("/builtin/prompts"),
+                button_rects: vec![
+                    (Button::South, Rect { left: 0, top: 0, right: 16, bottom: 16 }),
+                    (Button::East, Rect { left: 16, top: 0, right: 32, bottom: 16 }),
+                    (Button::West, Rect { left: 32, top: 0, right: 48, bottom: 16 }),
+                    (Button::North, Rect { left: 48, top: 0, right: 64, bottom: 16 }),
+                    (Button::LeftTrigger, Rect { left: 64, top: 0, right: 80, bottom: 16 }),
+                    (Button::RightTrigger, Rect { left: 80, top: 0, right: 96, bottom: 16 }),
+                    (Button::LeftTrigger2, Rect { left: 96, top: 0, right: 112, bottom: 16 }),
+                    (Button::RightTrigger2, Rect { left: 112, top: 0, right: 128, bottom: 16 }),
+                    (Button::Start, Rect { left: 128, top: 0, right: 144, bottom: 16 }),
+                    (Button::Select, Rect { left: 144, top: 0, right: 160, bottom: 16 }),
+                    (Button::DPadUp, Rect { left: 160, top: 0, right: 176, bottom: 16 }),
+                    (Button::DPadDown, Rect { left: 176, top: 0, right: 192, bottom: 16 }),
+                    (Button::DPadLeft, Rect { left: 192, top: 0, right: 208, bottom: 16 }),
+                    (Button::DPadRight, Rect { left: 208, top: 0, right: 224, bottom: 16 }),
+                ],
+            },
+            stage_effect: StageEffectConsts {
+                stage_effects: case_insensitive_hashmap! {
+                    "Blcny2" => StageEffectType::Debris, // Balcony, island collapse
+                },
+                // the vanilla Waterway gets its current from the map tiles alone, this is for mods
+                stage_currents: CaseInsensitiveHashMap::new(),
             },
             font_path: str!("builtin/builtin_font.fnt"),
             font_scale: 1.0,
             font_space_offset: -3.0,
+            // no font is bundled, a CJK translation has to ship one of these, see `GlyphAtlas::load`
+            font_fallback_paths: vec![str!("/font.ttf"), str!("/font.otf")],
+            font_fallback_size: 12.0,
+            transition: TransitionType::Tiles,
             organya_paths: vec![
                 str!("/org/"), // NXEngine
                 str!("/base/Org/"), // CS+
                 str!("/Resource/ORG/"), // CSE2E
             ],
+            data_manifest: DataManifestConsts {
+                files: vec![
+                    // 361 entries of 24 bytes
+                    ExpectedFile::essential("npc.tbl", 8664, b""),
+                    ExpectedFile {
+                        paths: vec![str!("stage.tbl"), str!("stage.dat"), str!("mrmap.bin")],
+                        min_size: 4,
+                        magic: Vec::new(),
+                        essential: true,
+                    },
+                    ExpectedFile::essential("Head.tsc", 16, b""),
+                    ExpectedFile::essential("ArmsItem.tsc", 16, b""),
+                    ExpectedFile::texture("MyChar", 1024),
+                    ExpectedFile::texture("Caret", 1024),
+                    ExpectedFile::texture("TextBox", 1024),
+                    ExpectedFile::texture("ArmsImage", 256),
+                    ExpectedFile::texture("Fade", 256),
+                ],
+                songs: vec![
+                    str!("curly"), str!("vivi"), str!("mura"), str!("anzen"), str!("gameover"), str!("ginsuke"),
+                    str!("cemetery"), str!("kodou"), str!("wanpaku"), str!("fanfale1"), str!("fanfale2"), str!("fanfale3"),
+                ],
+                check_stages: true,
+            },
         }
     }
 
@@ -1106,10 +1298,21 @@ impl EngineConstants {
         self.is_cs_plus = true;
         self.tex_sizes.insert(str!("Caret"), (320, 320));
         self.tex_sizes.insert(str!("MyChar"), (200, 384));
+        // Curly mode
+        self.my_char.curly_skin_row = Some(2);
         self.tex_sizes.insert(str!("Npc/NpcRegu"), (320, 410));
         self.font_path = str!("csfont.fnt");
         self.font_scale = 0.5;
         self.font_space_offset = 2.0;
+        self.lighting.stage_darkness.insert("Priso1", 0.7); // Last Cave
+        self.lighting.stage_darkness.insert("Priso2", 0.7); // Last Cave (hidden)
+        self.lighting.stage_darkness.insert("MazeM", 0.5); // Labyrinth M
+        self.data_manifest.files.push(ExpectedFile::essential("Stage/", 0, b""));
+        self.data_manifest.files.push(ExpectedFile::essential("Npc/", 0, b""));
+        // todo: the remastered soundtrack isn't played yet, only reported
+        self.data_manifest.files.push(ExpectedFile { paths: vec![str!("Ogg/")], min_size: 0, magic: Vec::new(), essential: false });
+        // todo: CS+ tweaked the experience collection margin and bounce, find out the values
+        // todo: CS+ uses its own text blip, find out which one
     }
 
 
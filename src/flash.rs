@@ -0,0 +1,155 @@
+use crate::common::Rect;
+use crate::entity::GameEntity;
+use crate::frame::Frame;
+use crate::ggez::{Context, GameResult};
+use crate::render::primitives::Primitives;
+use crate::SharedGameState;
+
+const FLASH_COLOR: [f32; 3] = [0.996, 1.0, 1.0];
+/// Alpha used instead of full white when reduced flash mode is on.
+const REDUCED_FLASH_ALPHA: f32 = 0.35;
+/// Ticks after a flash during which reduced flash mode ignores the next one, keeps back to back flashes
+/// (the Core's and Ballos's death sequences) under 3 a second.
+const STROBE_COOLDOWN: u16 = 20;
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum FlashState {
+    None,
+    /// Whole screen strobe used by <FLA, (tick).
+    Blink(u16),
+    /// Cross shaped flash expanding from a point, used by boss deaths, (x, y, phase, speed, width).
+    Explosion(isize, isize, u8, isize, isize),
+}
+
+pub struct Flash {
+    pub state: FlashState,
+    /// Ticks since the last flash started.
+    since_last: u16,
+}
+
+impl Flash {
+    pub fn new() -> Self {
+        Self {
+            state: FlashState::None,
+            since_last: STROBE_COOLDOWN,
+        }
+    }
+
+    pub fn set_blink(&mut self, state: &SharedGameState) {
+        self.start(FlashState::Blink(0), state);
+    }
+
+    pub fn set_explosion(&mut self, x: isize, y: isize, state: &SharedGameState) {
+        self.start(FlashState::Explosion(x, y, 0, 0, 0), state);
+    }
+
+    fn start(&mut self, flash: FlashState, state: &SharedGameState) {
+        if state.settings.accessibility.reduced_flash && self.since_last < STROBE_COOLDOWN {
+            return;
+        }
+
+        self.state = flash;
+        self.since_last = 0;
+    }
+
+    fn color(alpha: f32) -> [f32; 4] {
+        [FLASH_COLOR[0], FLASH_COLOR[1], FLASH_COLOR[2], alpha]
+    }
+}
+
+impl GameEntity<()> for Flash {
+    fn tick(&mut self, state: &mut SharedGameState, _custom: ()) -> GameResult {
+        self.since_last = self.since_last.saturating_add(1);
+
+        match self.state {
+            FlashState::None => {}
+            FlashState::Blink(tick) => {
+                self.state = if tick >= 20 { FlashState::None } else { FlashState::Blink(tick + 1) };
+            }
+            FlashState::Explosion(x, y, 0, speed, width) => {
+                let speed = speed + 0x200;
+                let width = width + speed;
+
+                self.state = if width > state.canvas_size.0 as isize * 0x200 * 4 {
+                    FlashState::Explosion(x, y, 1, 0, 0x1e000)
+                } else {
+                    FlashState::Explosion(x, y, 0, speed, width)
+                };
+            }
+            FlashState::Explosion(x, y, phase, speed, width) => {
+                let width = width - width / 8;
+
+                self.state = if width / 0x100 == 0 {
+                    FlashState::None
+                } else {
+                    FlashState::Explosion(x, y, phase, speed, width)
+                };
+            }
+        }
+
+        Ok(())
+    }
+
+    fn draw(&self, state: &mut SharedGameState, ctx: &mut Context, frame: &Frame) -> GameResult {
+        let reduced = state.settings.accessibility.reduced_flash;
+        let (canvas_w, canvas_h) = (state.canvas_size.0 as isize, state.canvas_size.1 as isize);
+        let mut primitives = Primitives::new();
+
+        match self.state {
+            FlashState::None => {}
+            FlashState::Blink(tick) => {
+                let screen = Rect::new(0, 0, canvas_w, canvas_h);
+
+                if reduced {
+                    // steady, fading tint instead of a strobe
+                    let alpha = REDUCED_FLASH_ALPHA * (1.0 - tick as f32 / 20.0);
+                    primitives.rect(screen, Flash::color(alpha));
+                } else if tick / 2 % 2 != 0 {
+                    primitives.rect(screen, Flash::color(1.0));
+                }
+            }
+            FlashState::Explosion(x, y, phase, _, width) => {
+                let alpha = if reduced { REDUCED_FLASH_ALPHA } else { 1.0 };
+                let center_x = x - frame.x;
+                let center_y = y - frame.y;
+
+                if phase == 0 {
+                    let vertical = Rect::new(((center_x - width) / 0x200).max(0), 0,
+                                             ((center_x + width) / 0x200).min(canvas_w), canvas_h);
+                    primitives.rect(vertical, Flash::color(alpha));
+                }
+
+                let horizontal = Rect::new(0, ((center_y - width) / 0x200).max(0),
+                                           canvas_w, ((center_y + width) / 0x200).min(canvas_h));
+                primitives.rect(horizontal, Flash::color(alpha));
+            }
+        }
+
+        state.texture_set.draw_primitives(ctx, &primitives)
+    }
+}
+
+#[test]
+fn test_strobe_limiter() {
+    let mut state = SharedGameState::for_tests();
+    let mut flash = Flash::new();
+
+    flash.set_blink(&state);
+    assert_eq!(flash.state, FlashState::Blink(0));
+    flash.tick(&mut state, ()).unwrap();
+    flash.set_explosion(0, 0, &state);
+    assert_eq!(flash.state, FlashState::Explosion(0, 0, 0, 0, 0));
+
+    state.settings.accessibility.reduced_flash = true;
+    let mut flash = Flash::new();
+    flash.set_explosion(0, 0, &state);
+    for _ in 1..STROBE_COOLDOWN {
+        flash.tick(&mut state, ()).unwrap();
+        flash.set_blink(&state);
+        assert!(matches!(flash.state, FlashState::Explosion(..)), "too soon after the last one");
+    }
+
+    flash.tick(&mut state, ()).unwrap();
+    flash.set_blink(&state);
+    assert_eq!(flash.state, FlashState::Blink(0));
+}
@@ -1,82 +1,198 @@
-use crate::player::Player;
+use crate::common::{map_extent, Rect, to_fix};
+use crate::player::{ControlMode, Player};
 use crate::SharedGameState;
 use crate::stage::Stage;
 
+/// Camera smoothing used outside of cutscenes, also restored by `<FOM` without a wait.
+pub const DEFAULT_FRAME_WAIT: isize = 16;
+/// Entities this far outside of the screen are still drawn, a tile.
+const CULL_MARGIN: isize = 16 * 0x200;
+
+/// Area an entity at (x, y) can draw into, wide enough to cover any direction it faces or is rotated in.
+pub fn display_rect(x: isize, y: isize, bounds: &Rect<usize>) -> Rect<isize> {
+    let extent = bounds.left.max(bounds.right).max(bounds.top).max(bounds.bottom) as isize;
+    Rect::new(x - extent, y - extent, x + extent, y + extent)
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
 pub struct Frame {
     pub x: isize,
     pub y: isize,
+    /// The camera moves by 1/wait of the distance to the target every tick.
     pub wait: isize,
 }
 
 impl Frame {
-    pub fn immediate_update(&mut self, state: &mut SharedGameState, player: &Player, stage: &Stage) {
-        if (stage.map.width - 1) * 16 < state.canvas_size.0 as usize {
-            self.x = -(((state.canvas_size.0 as isize - ((stage.map.width - 1) * 16) as isize) * 0x200) / 2);
-        } else {
-            self.x = player.target_x - (state.canvas_size.0 as isize * 0x200 / 2);
+    pub fn new() -> Frame {
+        Frame {
+            x: 0,
+            y: 0,
+            wait: DEFAULT_FRAME_WAIT,
+        }
+    }
 
-            if self.x < 0 {
-                self.x = 0;
-            }
+    /// Used by `<FOM` and `<FON`, a wait of 0 would make the camera divide by zero so it means the default.
+    pub fn set_wait(&mut self, wait: isize) {
+        self.wait = if wait > 0 { wait } else { DEFAULT_FRAME_WAIT };
+    }
 
-            let max_x = (((stage.map.width as isize - 1) * 16) - state.canvas_size.0 as isize) * 0x200;
-            if self.x > max_x {
-                self.x = max_x;
-            }
-        }
+    /// Camera view in fixed point world coordinates, expanded by `CULL_MARGIN` on every side.
+    pub fn view_rect(&self, canvas_size: (f32, f32)) -> Rect<isize> {
+        Rect::new(self.x - CULL_MARGIN,
+                  self.y - CULL_MARGIN,
+                  self.x + to_fix(canvas_size.0 as isize) + CULL_MARGIN,
+                  self.y + to_fix(canvas_size.1 as isize) + CULL_MARGIN)
+    }
 
-        if (stage.map.height - 1) * 16 < state.canvas_size.1 as usize {
-            self.y = -(((state.canvas_size.1 as isize - ((stage.map.height - 1) * 16) as isize) * 0x200) / 2);
-        } else {
-            self.y = player.target_y - (state.canvas_size.1 as isize * 0x200 / 2);
+    /// Camera view expanded by `screens` times the screen size on every side, in fixed point world coordinates.
+    pub fn screens_around(&self, canvas_size: (f32, f32), screens: f32) -> Rect<isize> {
+        let (width, height) = (to_fix(canvas_size.0 as isize), to_fix(canvas_size.1 as isize));
+        let (margin_x, margin_y) = ((width as f32 * screens) as isize, (height as f32 * screens) as isize);
 
-            if self.y < 0 {
-                self.y = 0;
-            }
+        Rect::new(self.x - margin_x, self.y - margin_y, self.x + width + margin_x, self.y + height + margin_y)
+    }
 
-            let max_y = (((stage.map.height as isize - 1) * 16) - state.canvas_size.1 as isize) * 0x200;
-            if self.y > max_y {
-                self.y = max_y;
-            }
-        }
+    /// Whether anything within `rect` (fixed point world coordinates) can end up on the screen,
+    /// used to skip drawing entities which are far away.
+    pub fn is_visible(&self, canvas_size: (f32, f32), rect: &Rect<isize>) -> bool {
+        self.view_rect(canvas_size).intersects(rect)
     }
 
-    pub fn update(&mut self, state: &mut SharedGameState, player: &Player, stage: &Stage) {
-        if (stage.map.width - 1) * 16 < state.canvas_size.0 as usize {
-            self.x = -(((state.canvas_size.0 as isize - ((stage.map.width - 1) * 16) as isize) * 0x200) / 2);
-        } else {
-            self.x += (player.target_x - (state.canvas_size.0 as isize * 0x200 / 2) - self.x) / self.wait;
+    /// One step of the smoothed camera movement.
+    fn approach(current: isize, target: isize, wait: isize) -> isize {
+        current + (target - current) / wait.max(1)
+    }
 
-            if self.x < 0 {
-                self.x = 0;
-            }
+    /// Keeps the camera within the map, maps smaller than the screen are centered instead.
+    fn clamp_to_map(pos: isize, map_tiles: usize, screen_size: f32) -> isize {
+        let map_size = map_extent(map_tiles);
+        let screen_size = to_fix(screen_size as isize);
 
-            let max_x = (((stage.map.width as isize - 1) * 16) - state.canvas_size.0 as isize) * 0x200;
-            if self.x > max_x {
-                self.x = max_x;
-            }
+        if map_size < screen_size {
+            -(screen_size - map_size) / 2
+        } else {
+            pos.max(0).min(map_size - screen_size)
         }
+    }
 
-        if (stage.map.height - 1) * 16 < state.canvas_size.1 as usize {
-            self.y = -(((state.canvas_size.1 as isize - ((stage.map.height - 1) * 16) as isize) * 0x200) / 2);
+    /// Next camera position on one axis, `<UNI0002` keeps the player in the same spot of the screen
+    /// without smoothing, even past the edges of the map.
+    fn follow(current: isize, target: isize, wait: isize, map_tiles: usize, screen_size: f32, fixed: bool) -> isize {
+        if fixed {
+            target
         } else {
-            self.y += (player.target_y - (state.canvas_size.1 as isize * 0x200 / 2) - self.y) / self.wait;
+            Frame::clamp_to_map(Frame::approach(current, target, wait), map_tiles, screen_size)
+        }
+    }
 
-            if self.y < 0 {
-                self.y = 0;
-            }
+    pub fn immediate_update(&mut self, state: &mut SharedGameState, player: &Player, stage: &Stage) {
+        let (width, height) = state.canvas_size;
 
-            let max_y = (((stage.map.height as isize - 1) * 16) - state.canvas_size.1 as isize) * 0x200;
-            if self.y > max_y {
-                self.y = max_y;
-            }
-        }
+        let target_x = player.target_x - to_fix(width as isize) / 2;
+        let target_y = player.target_y - to_fix(height as isize) / 2;
+        let fixed = player.control_mode == ControlMode::Fixed;
+        self.x = Frame::follow(self.x, target_x, 1, stage.map.width, width, fixed);
+        self.y = Frame::follow(self.y, target_y, 1, stage.map.height, height, fixed);
+    }
 
+    pub fn update(&mut self, state: &mut SharedGameState, player: &Player, stage: &Stage) {
+        let (width, height) = state.canvas_size;
+
+        let target_x = player.target_x - to_fix(width as isize) / 2;
+        let target_y = player.target_y - to_fix(height as isize) / 2;
+        let fixed = player.control_mode == ControlMode::Fixed;
+        self.x = Frame::follow(self.x, target_x, self.wait, stage.map.width, width, fixed);
+        self.y = Frame::follow(self.y, target_y, self.wait, stage.map.height, height, fixed);
+
+        // the shake moves the camera itself like in vanilla, the canvas space layers (HUD, text boxes, fades)
+        // don't use the frame so they stay put
         if state.quake_counter > 0 {
             state.quake_counter -= 1;
 
-            self.x += state.effect_rng.range(-0x300..0x300) as isize;
-            self.y += state.effect_rng.range(-0x300..0x300) as isize;
+            self.x += state.effect_rng.range(-0x300..=0x300) as isize;
+            self.y += state.effect_rng.range(-0x300..=0x300) as isize;
         }
     }
 }
+
+#[test]
+fn test_walking_look_ahead() {
+    use crate::common::Direction;
+    use crate::player::{LOOK_OFFSET_MAX, step_look_offset};
+
+    let mut frame = Frame::new();
+    let (mut x, mut index_x, mut index_y) = (0x10000isize, 0isize, 0isize);
+    frame.x = x;
+
+    for _ in 0..100 {
+        x += 0x32c; // walking speed
+        let (ix, iy) = step_look_offset(index_x, index_y, Direction::Right, false, false);
+        index_x = ix;
+        index_y = iy;
+        frame.x = Frame::approach(frame.x, x + index_x, frame.wait);
+    }
+    assert_eq!(index_x, LOOK_OFFSET_MAX);
+    assert_eq!(index_y, 0);
+
+    // the camera trails behind by about (wait - 1) * speed while walking, and catches up once the player stops
+    let lag = x + index_x - frame.x;
+    assert!((0x32c * (DEFAULT_FRAME_WAIT - 1)..0x32c * (DEFAULT_FRAME_WAIT + 1)).contains(&lag), "lag: {:#x}", lag);
+
+    for _ in 0..200 {
+        frame.x = Frame::approach(frame.x, x + index_x, frame.wait);
+    }
+    assert!(x + LOOK_OFFSET_MAX - frame.x < DEFAULT_FRAME_WAIT);
+
+    frame.set_wait(0);
+    assert_eq!(frame.wait, DEFAULT_FRAME_WAIT);
+}
+
+#[test]
+fn test_is_visible() {
+    let canvas_size = (320.0, 240.0);
+    let bounds = Rect::new(0x1000, 0x1000, 0x1000, 0x1000);
+    let mut frame = Frame::new();
+    frame.x = 0x20000;
+    frame.y = 0x10000;
+
+    // the left edge of the view is at 0x20000 - 0x2000
+    assert!(frame.is_visible(canvas_size, &display_rect(0x1e000 - 0x1000 + 1, 0x18000, &bounds)));
+    assert!(!frame.is_visible(canvas_size, &display_rect(0x1e000 - 0x1000, 0x18000, &bounds)));
+    // the bottom one at 0x10000 + 240 * 0x200 + 0x2000
+    assert!(frame.is_visible(canvas_size, &display_rect(0x24000, 0x30000 + 0x1000 - 1, &bounds)));
+    assert!(!frame.is_visible(canvas_size, &display_rect(0x24000, 0x30000 + 0x1000, &bounds)));
+
+    // maps smaller than the screen are centered with a negative camera position
+    frame.x = -0x4000;
+    frame.y = -0x2000;
+    assert!(frame.is_visible(canvas_size, &display_rect(-0x5000, -0x3000, &bounds)));
+    assert!(!frame.is_visible(canvas_size, &display_rect(-0x7000, 0, &bounds)));
+    assert!(frame.is_visible(canvas_size, &display_rect(0x1000, 0x1000, &bounds)));
+}
+
+#[test]
+fn test_clamp_to_map() {
+    use crate::common::tile_to_fix;
+
+    // 500x500 tiles, the camera stops at the last tile
+    let max_x = tile_to_fix(499) - to_fix(320);
+    assert_eq!(Frame::clamp_to_map(tile_to_fix(600), 500, 320.0), max_x);
+    assert_eq!(Frame::clamp_to_map(-0x1000, 500, 320.0), 0);
+    assert_eq!(Frame::clamp_to_map(0x40000, 500, 240.0), 0x40000);
+
+    // 1x1 and empty (corrupt) maps are centered
+    assert_eq!(Frame::clamp_to_map(0x40000, 1, 320.0), -to_fix(160));
+    assert_eq!(Frame::clamp_to_map(0x40000, 0, 240.0), -to_fix(120));
+}
+
+#[test]
+fn test_fixed_follow() {
+    use crate::common::tile_to_fix;
+
+    // past the right edge of a 40 tile wide map
+    let target = tile_to_fix(50);
+    let max_x = tile_to_fix(39) - to_fix(320);
+    assert_eq!(Frame::follow(0, target, 1, 40, 320.0, false), max_x);
+    assert_eq!(Frame::follow(0, target, DEFAULT_FRAME_WAIT, 40, 320.0, true), target);
+    assert_eq!(Frame::follow(0, -0x4000, DEFAULT_FRAME_WAIT, 40, 320.0, true), -0x4000);
+}
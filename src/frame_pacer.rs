@@ -0,0 +1,170 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Presents closer together than this mean the swap doesn't wait for vsync, the fastest common monitors run at 360 Hz.
+const MIN_VSYNC_INTERVAL: Duration = Duration::from_micros(2500);
+/// Consecutive too fast presents before vsync is given up on, a few can happen when the compositor drops frames.
+const VSYNC_FAILURE_FRAMES: u32 = 60;
+/// Limit used when vsync doesn't work and the frame limit is off, the player asked for bounded frame rates after all.
+// todo: the refresh rate of the monitor the window is on, winit 0.19 doesn't report it
+const FALLBACK_FPS: u32 = 60;
+/// Lower limits would need more catch up ticks per frame than the main loop allows.
+const MIN_FPS: u32 = 10;
+/// Sleeps end this much before the deadline at least and the rest is spun, on top of the worst oversleep seen lately.
+/// Windows' default timer can oversleep by a whole 15.6 ms period.
+const MIN_SPIN: Duration = Duration::from_micros(500);
+const MAX_SPIN: Duration = Duration::from_millis(20);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PacingMode {
+    /// The swap blocks until the next refresh, frames are drawn back to back.
+    VSync,
+    /// Frames per second, the main loop sleeps between them.
+    Limiter(u32),
+    Unlimited,
+}
+
+impl PacingMode {
+    pub fn name(self) -> String {
+        match self {
+            PacingMode::VSync => "vsync".to_owned(),
+            PacingMode::Limiter(fps) => format!("limited to {} fps", fps),
+            PacingMode::Unlimited => "unlimited".to_owned(),
+        }
+    }
+}
+
+/// Decides when frames are drawn, independently of the fixed rate the ticks run at.
+pub struct FramePacer {
+    /// Asked for when the window was created, vsync can't be switched on or off without creating it again.
+    vsync: bool,
+    /// The context was created without it or the swaps turned out not to wait.
+    vsync_failed: bool,
+    fast_presents: u32,
+    last_present: Option<Instant>,
+    next_frame: Instant,
+    /// Worst oversleep of `thread::sleep` seen lately.
+    oversleep: Duration,
+}
+
+impl FramePacer {
+    pub fn new(vsync: bool) -> FramePacer {
+        FramePacer {
+            vsync,
+            vsync_failed: false,
+            fast_presents: 0,
+            last_present: None,
+            next_frame: Instant::now(),
+            oversleep: Duration::from_secs(0),
+        }
+    }
+
+    /// The backend couldn't set vsync up, the frames are paced by the limiter instead.
+    pub fn vsync_unavailable(&mut self) {
+        self.vsync_failed = true;
+    }
+
+    pub fn mode(&self, frame_limit: Option<u32>) -> PacingMode {
+        if self.vsync && !self.vsync_failed {
+            return PacingMode::VSync;
+        }
+
+        match frame_limit {
+            Some(fps) => PacingMode::Limiter(fps.max(MIN_FPS)),
+            None if self.vsync => PacingMode::Limiter(FALLBACK_FPS),
+            None => PacingMode::Unlimited,
+        }
+    }
+
+    /// When the next frame should be drawn, `None` if right away.
+    pub fn next_frame(&self, frame_limit: Option<u32>) -> Option<Instant> {
+        match self.mode(frame_limit) {
+            PacingMode::Limiter(_) => Some(self.next_frame),
+            _ => None,
+        }
+    }
+
+    /// Called when a frame starts being drawn, schedules the next one.
+    pub fn begin_frame(&mut self, now: Instant, frame_limit: Option<u32>) {
+        if let PacingMode::Limiter(fps) = self.mode(frame_limit) {
+            let frame = Duration::from_secs(1) / fps;
+            // keeps the cadence, unless it's more than a frame behind
+            self.next_frame = if self.next_frame + frame < now { now + frame } else { self.next_frame + frame };
+        }
+    }
+
+    /// Called right after the swap, returns the time since the previous one.
+    pub fn presented(&mut self, now: Instant) -> Duration {
+        let interval = self.last_present.map_or(Duration::from_secs(0), |last| now - last);
+        self.last_present = Some(now);
+
+        if self.vsync && !self.vsync_failed {
+            self.fast_presents = if interval < MIN_VSYNC_INTERVAL { self.fast_presents + 1 } else { 0 };
+
+            if self.fast_presents >= VSYNC_FAILURE_FRAMES {
+                log::warn!("Frames are presented every {} us even though vsync is on, the driver doesn't support it or has it \
+                            forced off. Falling back to the frame limiter.", interval.as_micros());
+                self.vsync_failed = true;
+                self.next_frame = now;
+            }
+        }
+
+        interval
+    }
+
+    /// Sleeps until `deadline`, spinning through the end of it so the timer's granularity doesn't make it late.
+    pub fn sleep_until(&mut self, deadline: Instant) {
+        let now = Instant::now();
+        if deadline <= now {
+            return;
+        }
+
+        let margin = (self.oversleep + MIN_SPIN).min(MAX_SPIN);
+        if deadline - now > margin {
+            let request = deadline - now - margin;
+            let start = Instant::now();
+            thread::sleep(request);
+
+            // slowly forgotten, in case something made the timer more precise in the meantime
+            let overslept = start.elapsed().checked_sub(request).unwrap_or_default();
+            self.oversleep = (self.oversleep * 15 / 16).max(overslept);
+        }
+
+        while Instant::now() < deadline {
+            thread::yield_now();
+        }
+    }
+}
+
+#[test]
+fn test_pacing_modes() {
+    let mut pacer = FramePacer::new(false);
+    assert_eq!(pacer.mode(Some(60)), PacingMode::Limiter(60));
+    assert_eq!(pacer.mode(Some(1)), PacingMode::Limiter(MIN_FPS));
+    assert_eq!(pacer.mode(None), PacingMode::Unlimited);
+
+    // 3000 fps with vsync on, it doesn't work
+    pacer = FramePacer::new(true);
+    assert_eq!(pacer.mode(None), PacingMode::VSync);
+    let start = Instant::now();
+    for i in 0..VSYNC_FAILURE_FRAMES {
+        pacer.presented(start + Duration::from_micros(333) * i);
+    }
+    assert_eq!(pacer.mode(None), PacingMode::Limiter(FALLBACK_FPS));
+    assert_eq!(pacer.mode(Some(144)), PacingMode::Limiter(144));
+}
+
+#[test]
+fn test_limiter_cadence() {
+    let mut pacer = FramePacer::new(false);
+    let start = pacer.next_frame(Some(50)).unwrap();
+
+    // a bit late, the next one is still a frame after the previous deadline
+    pacer.begin_frame(start + Duration::from_millis(3), Some(50));
+    assert_eq!(pacer.next_frame(Some(50)), Some(start + Duration::from_millis(20)));
+
+    // way behind, starts over instead of drawing the missed frames back to back
+    pacer.begin_frame(start + Duration::from_millis(100), Some(50));
+    assert_eq!(pacer.next_frame(Some(50)), Some(start + Duration::from_millis(120)));
+    assert_eq!(pacer.next_frame(None), None);
+}
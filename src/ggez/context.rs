@@ -159,8 +159,13 @@ impl Context {
                         .set_modifiers(keyboard::KeyMods::from(modifiers));
                     self.keyboard_context.set_key(keycode, pressed);
                 }
-                winit_event::WindowEvent::HiDpiFactorChanged(_) => {
-                    // Nope.
+                winit_event::WindowEvent::HiDpiFactorChanged(hidpi_factor) => {
+                    // keep the logical size, otherwise the drawable gets stretched when the window
+                    // is moved to a monitor with different DPI.
+                    if let Some(logical_size) = self.gfx_context.window.get_inner_size() {
+                        self.gfx_context.window.resize(logical_size.to_physical(hidpi_factor));
+                        self.gfx_context.resize_viewport();
+                    }
                 }
                 _ => (),
             },
@@ -28,7 +28,7 @@ pub enum GameError {
     /// Something went wrong trying to set or get window properties.
     WindowError(String),
     /// Something went wrong trying to create a window
-    WindowCreationError(Arc<glutin::CreationError>),
+    WindowCreationError(String),
     /// Something went wrong trying to read from a file
     IOError(Arc<std::io::Error>),
     /// Something went wrong trying to load/render a font
@@ -41,30 +41,71 @@ pub enum GameError {
     GamepadError(String),
     /// Something went wrong with the `lyon` shape-tesselation library.
     LyonError(String),
-    ParseError(String),
+    /// A data file is malformed. `offset` is in bytes from the start of the file, `file` can be empty
+    /// if the data doesn't come from a file.
+    ParseError {
+        /// Name of the file being parsed.
+        file: String,
+        /// Where the error was found.
+        offset: u64,
+        /// What's wrong with the data.
+        message: String,
+    },
+    /// A value is out of range or refers to something that doesn't exist.
     InvalidValue(String),
 }
 
+impl GameError {
+    /// Creates a `ParseError` which isn't bound to a file yet, see `in_file`.
+    pub fn parse_error(offset: u64, message: String) -> GameError {
+        GameError::ParseError { file: String::new(), offset, message }
+    }
+
+    /// Attaches the path of the file a `ParseError` comes from, other errors are returned unchanged.
+    pub fn in_file(self, path: &str) -> GameError {
+        match self {
+            GameError::ParseError { offset, message, .. } => GameError::ParseError { file: path.to_owned(), offset, message },
+            other => other,
+        }
+    }
+}
+
 impl fmt::Display for GameError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
+            GameError::FilesystemError(ref s) => write!(f, "Filesystem error: {}", s),
             GameError::ConfigError(ref s) => write!(f, "Config error: {}", s),
+            GameError::EventLoopError(ref s) => write!(f, "Event loop error: {}", s),
             GameError::ResourceLoadError(ref s) => write!(f, "Error loading resource: {}", s),
             GameError::ResourceNotFound(ref s, ref paths) => write!(
                 f,
                 "Resource not found: {}, searched in paths {:?}",
                 s, paths
             ),
-            GameError::WindowError(ref e) => write!(f, "Window creation error: {}", e),
-            _ => write!(f, "GameError {:?}", self),
+            GameError::RenderError(ref s) => write!(f, "Render error: {}", s),
+            GameError::AudioError(ref s) => write!(f, "Audio error: {}", s),
+            GameError::WindowError(ref s) => write!(f, "Window error: {}", s),
+            GameError::WindowCreationError(ref s) => write!(f, "Window creation error: {}", s),
+            GameError::IOError(ref e) => write!(f, "IO error: {}", e),
+            GameError::FontError(ref s) => write!(f, "Font error: {}", s),
+            GameError::VideoError(ref s) => write!(f, "Video error: {}", s),
+            GameError::ShaderProgramError(ref e) => write!(f, "Shader program error: {}", e),
+            GameError::GamepadError(ref s) => write!(f, "Gamepad error: {}", s),
+            GameError::LyonError(ref s) => write!(f, "Lyon error: {}", s),
+            GameError::ParseError { ref file, offset, ref message } if file.is_empty() => {
+                write!(f, "Parse error at offset {:#x}: {}", offset, message)
+            }
+            GameError::ParseError { ref file, offset, ref message } => {
+                write!(f, "Parse error in {} at offset {:#x}: {}", file, offset, message)
+            }
+            GameError::InvalidValue(ref s) => write!(f, "Invalid value: {}", s),
         }
     }
 }
 
 impl Error for GameError {
-    fn cause(&self) -> Option<&dyn Error> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
         match *self {
-            GameError::WindowCreationError(ref e) => Some(&**e),
             GameError::IOError(ref e) => Some(&**e),
             GameError::ShaderProgramError(ref e) => Some(e),
             _ => None,
@@ -191,7 +232,7 @@ impl From<winit::EventsLoopClosed> for GameError {
 
 impl From<glutin::CreationError> for GameError {
     fn from(s: glutin::CreationError) -> GameError {
-        GameError::WindowCreationError(Arc::new(s))
+        GameError::WindowCreationError(s.to_string())
     }
 }
 
@@ -230,8 +271,23 @@ impl From<lyon::lyon_tessellation::geometry_builder::GeometryBuilderError> for G
 
 impl From<strum::ParseError> for GameError {
     fn from(s: strum::ParseError) -> GameError {
-        let errstr = format!("Strum parse error: {}", s);
-        GameError::ParseError(errstr)
+        GameError::InvalidValue(format!("Strum parse error: {}", s))
+    }
+}
+
+impl From<serde_json::Error> for GameError {
+    fn from(s: serde_json::Error) -> GameError {
+        // serde_json only reports the line and column, which are part of the message
+        GameError::parse_error(0, format!("JSON error: {}", s))
+    }
+}
+
+impl From<bincode::Error> for GameError {
+    fn from(s: bincode::Error) -> GameError {
+        match *s {
+            bincode::ErrorKind::Io(e) => GameError::from(e),
+            e => GameError::ResourceLoadError(format!("Binary encoding error: {}", e)),
+        }
     }
 }
 
@@ -242,6 +298,13 @@ impl From<cpal::DefaultStreamConfigError> for GameError {
     }
 }
 
+impl From<cpal::SupportedStreamConfigsError> for GameError {
+    fn from(s: cpal::SupportedStreamConfigsError) -> GameError {
+        let errstr = format!("Supported stream configs error: {}", s);
+        GameError::AudioError(errstr)
+    }
+}
+
 impl From<cpal::PlayStreamError> for GameError {
     fn from(s: cpal::PlayStreamError) -> GameError {
         let errstr = format!("Play stream error: {}", s);
@@ -269,3 +332,46 @@ impl<T> From<SendError<T>> for GameError {
         GameError::EventLoopError(errstr)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let io_error = std::io::Error::new(std::io::ErrorKind::NotFound, "no such file");
+        let cases = vec![
+            (GameError::FilesystemError("a".to_owned()), "Filesystem error: a"),
+            (GameError::ConfigError("a".to_owned()), "Config error: a"),
+            (GameError::EventLoopError("a".to_owned()), "Event loop error: a"),
+            (GameError::ResourceLoadError("a".to_owned()), "Error loading resource: a"),
+            (GameError::ResourceNotFound("a".to_owned(), Vec::new()), "Resource not found: a, searched in paths []"),
+            (GameError::RenderError("a".to_owned()), "Render error: a"),
+            (GameError::AudioError("a".to_owned()), "Audio error: a"),
+            (GameError::WindowError("a".to_owned()), "Window error: a"),
+            (GameError::WindowCreationError("a".to_owned()), "Window creation error: a"),
+            (GameError::from(io_error), "IO error: no such file"),
+            (GameError::FontError("a".to_owned()), "Font error: a"),
+            (GameError::VideoError("a".to_owned()), "Video error: a"),
+            (GameError::GamepadError("a".to_owned()), "Gamepad error: a"),
+            (GameError::LyonError("a".to_owned()), "Lyon error: a"),
+            (GameError::parse_error(0x1f, "a".to_owned()), "Parse error at offset 0x1f: a"),
+            (GameError::parse_error(0x1f, "a".to_owned()).in_file("Stage/Cave.tsc"), "Parse error in Stage/Cave.tsc at offset 0x1f: a"),
+            (GameError::InvalidValue("a".to_owned()), "Invalid value: a"),
+        ];
+
+        for (error, expected) in cases {
+            assert_eq!(error.to_string(), expected);
+        }
+
+        let shader_error = GameError::ShaderProgramError(gfx::shade::ProgramError::Link("a".into()));
+        assert!(shader_error.to_string().starts_with("Shader program error: "));
+    }
+
+    #[test]
+    fn test_source() {
+        let io_error = std::io::Error::new(std::io::ErrorKind::NotFound, "no such file");
+        assert!(GameError::from(io_error).source().is_some());
+        assert!(GameError::AudioError("a".to_owned()).source().is_none());
+    }
+}
@@ -28,10 +28,14 @@
 
 use std::env;
 use std::fmt;
+use std::fs;
 use std::io;
 use std::io::SeekFrom;
 use std::path;
 
+use directories::ProjectDirs;
+use lazy_static::lazy_static;
+
 use crate::ggez::{Context, GameError, GameResult};
 use crate::ggez::conf;
 use crate::ggez::vfs::{self, VFS};
@@ -240,6 +244,11 @@ impl Filesystem {
             .unwrap_or(false)
     }
 
+    /// Size of a file in bytes.
+    pub(crate) fn file_size<P: AsRef<path::Path>>(&self, path: P) -> GameResult<u64> {
+        self.vfs.metadata(path.as_ref()).map(|m| m.len())
+    }
+
     /// Check whether a path points at a directory.
     pub(crate) fn is_dir<P: AsRef<path::Path>>(&self, path: P) -> bool {
         self.vfs
@@ -311,6 +320,20 @@ impl Filesystem {
         self.vfs.push_back(vfs);
     }
 
+    pub(crate) fn mount_overlay(&mut self, vfs: Box<dyn vfs::VFS>) {
+        trace!("Mounting overlay: {:?}", vfs);
+        self.vfs.push_front(vfs);
+    }
+
+    pub(crate) fn unmount_overlay(&mut self) {
+        let vfs = self.vfs.pop_front();
+        trace!("Unmounted overlay: {:?}", vfs);
+    }
+
+    pub(crate) fn physical_roots(&self) -> Vec<path::PathBuf> {
+        self.vfs.roots().iter().filter_map(|vfs| vfs.to_path_buf()).collect()
+    }
+
     /// Looks for a file named `/conf.toml` in any resource directory and
     /// loads it if it finds it.
     /// If it can't read it for some reason, returns an error.
@@ -395,6 +418,11 @@ pub fn is_file<P: AsRef<path::Path>>(ctx: &Context, path: P) -> bool {
     ctx.filesystem.is_file(path)
 }
 
+/// Size of a file in bytes.
+pub fn file_size<P: AsRef<path::Path>>(ctx: &Context, path: P) -> GameResult<u64> {
+    ctx.filesystem.file_size(path)
+}
+
 /// Check whether a path points at a directory.
 pub fn is_dir<P: AsRef<path::Path>>(ctx: &Context, path: P) -> bool {
     ctx.filesystem.is_dir(path)
@@ -438,10 +466,26 @@ pub fn mount(ctx: &mut Context, path: &path::Path, readonly: bool) {
     ctx.filesystem.mount(path, readonly)
 }
 
+/// Adds a virtual filesystem to the end of the lookup list.
 pub fn mount_vfs(ctx: &mut Context, vfs: Box<dyn vfs::VFS>) {
     ctx.filesystem.mount_vfs(vfs)
 }
 
+/// Mounts the given VFS in front of all other ones, so its files take precedence.
+pub fn mount_overlay(ctx: &mut Context, vfs: Box<dyn vfs::VFS>) {
+    ctx.filesystem.mount_overlay(vfs)
+}
+
+/// Removes the VFS mounted with `mount_overlay`.
+pub fn unmount_overlay(ctx: &mut Context) {
+    ctx.filesystem.unmount_overlay()
+}
+
+/// Returns the physical directories backing the filesystem, if there are any.
+pub fn physical_roots(ctx: &Context) -> Vec<path::PathBuf> {
+    ctx.filesystem.physical_roots()
+}
+
 /// Looks for a file named `/conf.toml` in any resource directory and
 /// loads it if it finds it.
 /// If it can't read it for some reason, returns an error.
@@ -455,6 +499,137 @@ pub fn write_config(ctx: &mut Context, conf: &conf::Conf) -> GameResult {
     ctx.filesystem.write_config(conf)
 }
 
+/// Name of the file which forces the portable mode when put beside the executable.
+const PORTABLE_MARKER: &str = "portable.txt";
+/// Files of the original game which are moved over from the game directory.
+const MIGRATED_FILES: [&str; 2] = ["Profile.dat", "290.rec"];
+
+lazy_static! {
+    static ref USER_DIRS: Result<UserDirs, String> = UserDirs::detect().map_err(|e| e.to_string());
+}
+
+/// Locations of everything the game writes: profiles, settings, screenshots and records.
+/// Every path written to has to come from here, so nothing ends up next to the executable
+/// (which might be read-only) unless the portable mode is on.
+#[derive(Debug, Clone)]
+pub struct UserDirs {
+    data_dir: path::PathBuf,
+    config_dir: path::PathBuf,
+    portable: bool,
+}
+
+impl UserDirs {
+    /// Uses the platform's user directories, unless `--portable` has been passed
+    /// or there's a `portable.txt` file beside the executable.
+    fn detect() -> GameResult<UserDirs> {
+        let mut exe_dir = env::current_exe()?;
+        let _ = exe_dir.pop();
+
+        if env::args().any(|arg| arg == "--portable") || exe_dir.join(PORTABLE_MARKER).is_file() {
+            return Ok(UserDirs::portable(&exe_dir));
+        }
+
+        let project_dirs = ProjectDirs::from("", "", "doukutsu-rs")
+            .ok_or_else(|| GameError::FilesystemError("No valid home directory path could be retrieved.".to_string()))?;
+
+        Ok(UserDirs {
+            data_dir: project_dirs.data_local_dir().to_path_buf(),
+            config_dir: project_dirs.config_dir().to_path_buf(),
+            portable: false,
+        })
+    }
+
+    /// Keeps everything in a `user` directory under the given one.
+    pub fn portable(root: &path::Path) -> UserDirs {
+        let dir = root.join("user");
+
+        UserDirs {
+            data_dir: dir.clone(),
+            config_dir: dir,
+            portable: true,
+        }
+    }
+
+    /// Whether the saves and settings are kept beside the executable.
+    pub fn is_portable(&self) -> bool {
+        self.portable
+    }
+
+    /// Directory for save files, each mod gets its own one so the saves don't collide with the base game.
+    pub fn save_dir(&self, mod_id: Option<&str>) -> path::PathBuf {
+        match mod_id {
+            Some(id) => self.data_dir.join("mods").join(id),
+            None => self.data_dir.clone(),
+        }
+    }
+
+    /// The `Profile.dat` of the base game or of a mod.
+    pub fn profile_path(&self, mod_id: Option<&str>) -> path::PathBuf {
+        self.save_dir(mod_id).join("Profile.dat")
+    }
+
+    /// Nikumaru counter record.
+    pub fn record_path(&self, mod_id: Option<&str>) -> path::PathBuf {
+        self.save_dir(mod_id).join("290.rec")
+    }
+
+    /// Where the screenshots are saved.
+    pub fn screenshot_dir(&self) -> path::PathBuf {
+        self.data_dir.join("screenshots")
+    }
+
+    /// Cached map thumbnails, safe to delete.
+    pub fn thumbnail_dir(&self) -> path::PathBuf {
+        self.data_dir.join("thumbnails")
+    }
+
+    /// Written by the panic hook.
+    pub fn crash_log_path(&self) -> path::PathBuf {
+        self.data_dir.join("crash.log")
+    }
+
+    /// Log file written when logging to a file is enabled.
+    pub fn log_path(&self) -> path::PathBuf {
+        self.data_dir.join("doukutsu.log")
+    }
+
+    /// The `settings.toml` file.
+    pub fn settings_path(&self) -> path::PathBuf {
+        self.config_dir.join("settings.toml")
+    }
+
+    /// Copies the profile and the Nikumaru record of a vanilla install (found beside the data directory)
+    /// to the save directory, unless they already exist there. Returns the names of the copied files.
+    pub fn migrate_saves(&self, data_dir: &path::Path) -> GameResult<Vec<&'static str>> {
+        let mut migrated = Vec::new();
+        let game_dir = match data_dir.parent() {
+            Some(dir) => dir,
+            None => { return Ok(migrated); }
+        };
+
+        for name in MIGRATED_FILES.iter() {
+            let source = game_dir.join(name);
+            let target = self.save_dir(None).join(name);
+
+            // comparing the paths doesn't catch symlinks, but good enough to skip the portable mode case
+            if !source.is_file() || target.exists() || source == target {
+                continue;
+            }
+
+            fs::create_dir_all(self.save_dir(None))?;
+            let _ = fs::copy(&source, &target)?;
+            migrated.push(*name);
+        }
+
+        Ok(migrated)
+    }
+}
+
+/// Returns the user directories, resolved once on the first call.
+pub fn user_dirs() -> GameResult<&'static UserDirs> {
+    USER_DIRS.as_ref().map_err(|e| GameError::FilesystemError(e.clone()))
+}
+
 #[cfg(test)]
 mod tests {
     use std::io::{Read, Write};
@@ -554,4 +729,25 @@ mod tests {
         // Remove the config file!
         f.delete(CONFIG_NAME).unwrap();
     }
+
+    #[test]
+    fn headless_test_migrate_saves() {
+        use std::{env, fs};
+
+        let root = env::temp_dir().join(format!("doukutsu-rs-test-{}", std::process::id()));
+        let data_dir = root.join("data");
+        fs::create_dir_all(&data_dir).unwrap();
+        fs::write(root.join("Profile.dat"), b"Do041220").unwrap();
+
+        let dirs = UserDirs::portable(&root.join("exe"));
+        assert_eq!(dirs.migrate_saves(&data_dir).unwrap(), vec!["Profile.dat"]);
+        assert_eq!(fs::read(dirs.profile_path(None)).unwrap(), b"Do041220");
+
+        // only happens once, the migrated profile isn't overwritten later
+        fs::write(root.join("Profile.dat"), b"Do041115").unwrap();
+        assert!(dirs.migrate_saves(&data_dir).unwrap().is_empty());
+        assert_eq!(fs::read(dirs.profile_path(None)).unwrap(), b"Do041220");
+
+        fs::remove_dir_all(&root).unwrap();
+    }
 }
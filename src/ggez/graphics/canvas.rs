@@ -162,3 +162,17 @@ pub fn set_canvas(ctx: &mut Context, target: Option<&Canvas>) {
         }
     };
 }
+
+/// Render target saved with `save_canvas`.
+#[derive(Debug)]
+pub struct SavedCanvas(RawRenderTargetView<<GlBackendSpec as BackendSpec>::Resources>);
+
+/// Remembers the current render target, so it can be restored after drawing into another canvas.
+pub fn save_canvas(ctx: &Context) -> SavedCanvas {
+    SavedCanvas(ctx.gfx_context.data.out.clone())
+}
+
+/// Restores a render target saved with `save_canvas`.
+pub fn restore_canvas(ctx: &mut Context, saved: SavedCanvas) {
+    ctx.gfx_context.data.out = saved.0;
+}
@@ -56,6 +56,9 @@ where
     pub(crate) glyph_brush: GlyphBrush<'static, DrawParam>,
     pub(crate) glyph_cache: ImageGeneric<B>,
     pub(crate) glyph_state: Rc<RefCell<spritebatch::SpriteBatch>>,
+
+    /// Number of draw calls since the last `present`.
+    pub(crate) draw_calls: usize,
 }
 
 impl<B> fmt::Debug for GraphicsContextGeneric<B>
@@ -297,6 +300,7 @@ impl GraphicsContextGeneric<GlBackendSpec> {
             glyph_brush,
             glyph_cache,
             glyph_state,
+            draw_calls: 0,
         };
         gfx.set_window_mode(window_mode)?;
 
@@ -420,6 +424,7 @@ where
         let shader_handle = &self.shaders[id];
 
         shader_handle.draw(&mut self.encoder, slice, &self.data)?;
+        self.draw_calls += 1;
         Ok(())
     }
 
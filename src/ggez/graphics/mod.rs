@@ -498,9 +498,15 @@ pub fn present(ctx: &mut Context) -> GameResult<()> {
     gfx.encoder.flush(&mut *gfx.device);
     gfx.window.swap_buffers()?;
     gfx.device.cleanup();
+    gfx.draw_calls = 0;
     Ok(())
 }
 
+/// Returns the number of draw calls issued since the last `present`.
+pub fn draw_calls(context: &Context) -> usize {
+    context.gfx_context.draw_calls
+}
+
 /// Take a screenshot by outputting the current render surface
 /// (screen or selected canvas) to an `Image`.
 pub fn screenshot(ctx: &mut Context) -> GameResult<Image> {
@@ -4,8 +4,10 @@
 //! gets fleshed out.  The `gilrs` crate needs help to add better
 //! cross-platform support.  Why not give it a hand?
 use std::fmt;
+use std::time::{Duration, Instant};
 
 pub use gilrs::{self, Event, Gamepad, Gilrs};
+use gilrs::ff::{BaseEffect, BaseEffectType, Effect, EffectBuilder, Replay, Ticks};
 
 /// A unique identifier for a particular GamePad
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
@@ -14,6 +16,37 @@ pub struct GamepadId(pub(crate) gilrs::GamepadId);
 use crate::ggez::context::Context;
 use crate::ggez::error::GameResult;
 
+/// A force feedback request. Intensities are in the 0.0 - 1.0 range.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Rumble {
+    /// Intensity of the low frequency (strong) motor.
+    pub low_freq: f32,
+    /// Intensity of the high frequency (weak) motor.
+    pub high_freq: f32,
+    /// How long the motors keep running.
+    pub duration: Duration,
+}
+
+impl Rumble {
+    /// Creates a new rumble effect, the intensities are clamped to `0.0..=1.0`.
+    pub fn new(low_freq: f32, high_freq: f32, duration: Duration) -> Rumble {
+        Rumble {
+            low_freq: low_freq.clamp(0.0, 1.0),
+            high_freq: high_freq.clamp(0.0, 1.0),
+            duration,
+        }
+    }
+
+    /// Combines two overlapping requests, taking the max of both.
+    pub fn merge(self, other: Rumble) -> Rumble {
+        Rumble {
+            low_freq: self.low_freq.max(other.low_freq),
+            high_freq: self.high_freq.max(other.high_freq),
+            duration: self.duration.max(other.duration),
+        }
+    }
+}
+
 /// Trait object defining a gamepad/joystick context.
 pub trait GamepadContext {
     /// Returns a gamepad event.
@@ -21,11 +54,21 @@ pub trait GamepadContext {
 
     /// returns the `Gamepad` associated with an id.
     fn gamepad(&self, id: GamepadId) -> Gamepad;
+
+    /// Starts rumbling all connected gamepads supporting force feedback.
+    /// If a rumble is already playing, the requests are merged instead of queued.
+    fn rumble(&mut self, rumble: Rumble);
+
+    /// Stops any rumble immediately.
+    fn stop_rumble(&mut self);
 }
 
 /// A structure that contains gamepad state using `gilrs`.
 pub struct GilrsGamepadContext {
     pub(crate) gilrs: Gilrs,
+    rumble_effect: Option<Effect>,
+    /// Currently playing rumble and the time it has started at.
+    current_rumble: Option<(Rumble, Instant)>,
 }
 
 impl fmt::Debug for GilrsGamepadContext {
@@ -37,7 +80,46 @@ impl fmt::Debug for GilrsGamepadContext {
 impl GilrsGamepadContext {
     pub(crate) fn new() -> GameResult<Self> {
         let gilrs = Gilrs::new()?;
-        Ok(GilrsGamepadContext { gilrs })
+        Ok(GilrsGamepadContext {
+            gilrs,
+            rumble_effect: None,
+            current_rumble: None,
+        })
+    }
+
+    fn play_rumble(&mut self, rumble: Rumble) -> Result<(), gilrs::ff::Error> {
+        let ids: Vec<gilrs::GamepadId> = self.gilrs.gamepads()
+            .filter(|(_, gamepad)| gamepad.is_ff_supported())
+            .map(|(id, _)| id)
+            .collect();
+
+        if ids.is_empty() {
+            return Ok(());
+        }
+
+        let replay = Replay {
+            play_for: Ticks::from_ms(rumble.duration.as_millis() as u32),
+            ..Default::default()
+        };
+
+        let effect = EffectBuilder::new()
+            .add_effect(BaseEffect {
+                kind: BaseEffectType::Strong { magnitude: (rumble.low_freq * u16::MAX as f32) as u16 },
+                scheduling: replay,
+                envelope: Default::default(),
+            })
+            .add_effect(BaseEffect {
+                kind: BaseEffectType::Weak { magnitude: (rumble.high_freq * u16::MAX as f32) as u16 },
+                scheduling: replay,
+                envelope: Default::default(),
+            })
+            .gamepads(&ids)
+            .finish(&mut self.gilrs)?;
+        effect.play()?;
+
+        // dropping the old effect stops it
+        self.rumble_effect = Some(effect);
+        Ok(())
     }
 }
 
@@ -49,6 +131,39 @@ impl GamepadContext for GilrsGamepadContext {
     fn gamepad(&self, id: GamepadId) -> Gamepad {
         self.gilrs.gamepad(id.0)
     }
+
+    fn rumble(&mut self, rumble: Rumble) {
+        let now = Instant::now();
+        let merged = match self.current_rumble {
+            Some((current, started)) if now.duration_since(started) < current.duration => {
+                let remaining = Rumble { duration: current.duration - now.duration_since(started), ..current };
+                let merged = remaining.merge(rumble);
+
+                // weaker and shorter than what's already playing
+                if merged == remaining {
+                    return;
+                }
+
+                merged
+            }
+            _ => rumble,
+        };
+
+        // force feedback support varies a lot between platforms, so failures are ignored
+        if self.play_rumble(merged).is_ok() {
+            self.current_rumble = Some((merged, now));
+        } else {
+            self.rumble_effect = None;
+            self.current_rumble = None;
+        }
+    }
+
+    fn stop_rumble(&mut self) {
+        if let Some(effect) = self.rumble_effect.take() {
+            let _ = effect.stop();
+        }
+        self.current_rumble = None;
+    }
 }
 
 /// A structure that implements [`GamepadContext`](trait.GamepadContext.html)
@@ -65,6 +180,10 @@ impl GamepadContext for NullGamepadContext {
     fn gamepad(&self, _id: GamepadId) -> Gamepad {
         panic!("Gamepad module disabled")
     }
+
+    fn rumble(&mut self, _rumble: Rumble) {}
+
+    fn stop_rumble(&mut self) {}
 }
 
 /// Returns the `Gamepad` associated with an `id`.
@@ -72,12 +191,21 @@ pub fn gamepad(ctx: &Context, id: GamepadId) -> Gamepad {
     ctx.gamepad_context.gamepad(id)
 }
 
+/// Rumbles all connected gamepads, see [`Rumble`](struct.Rumble.html).
+pub fn rumble(ctx: &mut Context, rumble: Rumble) {
+    ctx.gamepad_context.rumble(rumble)
+}
+
+/// Stops rumbling all gamepads.
+pub fn stop_rumble(ctx: &mut Context) {
+    ctx.gamepad_context.stop_rumble()
+}
+
 // Properties gamepads might want:
 // Number of buttons
 // Number of axes
 // Name/ID
 // Is it connected?  (For consoles?)
-// Whether or not they support vibration
 
 /*
 /// Lists all gamepads.  With metainfo, maybe?
@@ -104,4 +232,12 @@ mod tests {
     fn gilrs_init() {
         assert!(GilrsGamepadContext::new().is_ok());
     }
+
+    #[test]
+    fn rumble_merge() {
+        let a = Rumble::new(0.5, 0.1, Duration::from_millis(100));
+        let b = Rumble::new(0.2, 0.8, Duration::from_millis(300));
+        assert_eq!(a.merge(b), Rumble::new(0.5, 0.8, Duration::from_millis(300)));
+        assert_eq!(Rumble::new(2.0, -1.0, Duration::from_millis(1)).low_freq, 1.0);
+    }
 }
@@ -25,6 +25,7 @@ fn convenient_path_to_str(path: &path::Path) -> GameResult<&str> {
     })
 }
 
+/// A file that can be read, written and seeked.
 pub trait VFile: Read + Write + Seek + Debug {}
 
 impl<T> VFile for T where T: Read + Write + Seek + Debug {}
@@ -93,6 +94,7 @@ impl OpenOptions {
     }
 }
 
+/// A virtual filesystem that can be mounted into the game's filesystem.
 pub trait VFS: Debug {
     /// Open the file at this path with the given options
     fn open_options(&self, path: &Path, open_options: OpenOptions) -> GameResult<Box<dyn VFile>>;
@@ -136,6 +138,8 @@ pub trait VFS: Debug {
     fn to_path_buf(&self) -> Option<PathBuf>;
 }
 
+/// Metadata of a file or directory in a virtual filesystem.
+#[allow(clippy::len_without_is_empty)]
 pub trait VMetadata {
     /// Returns whether or not it is a directory.
     /// Note that zip files don't actually have directories, awkwardly,
@@ -159,6 +163,7 @@ pub struct PhysicalFS {
     readonly: bool,
 }
 
+/// Metadata of a file on the real filesystem.
 #[derive(Debug, Clone)]
 pub struct PhysicalMetadata(fs::Metadata);
 
@@ -212,6 +217,7 @@ fn sanitize_path(path: &path::Path) -> Option<PathBuf> {
 }
 
 impl PhysicalFS {
+    /// Creates a filesystem rooted at `root`.
     pub fn new(root: &Path, readonly: bool) -> Self {
         PhysicalFS {
             root: root.into(),
@@ -393,6 +399,8 @@ pub struct OverlayFS {
 }
 
 impl OverlayFS {
+    /// Creates an overlay with no filesystems in it.
+    #[allow(clippy::new_without_default)]
     pub fn new() -> Self {
         Self {
             roots: VecDeque::new(),
@@ -400,18 +408,21 @@ impl OverlayFS {
     }
 
     /// Adds a new VFS to the front of the list.
-    /// Currently unused, I suppose, but good to
-    /// have at least for tests.
-    #[allow(dead_code)]
     pub fn push_front(&mut self, fs: Box<dyn VFS>) {
         self.roots.push_front(fs);
     }
 
+    /// Removes the VFS at the front of the list.
+    pub fn pop_front(&mut self) -> Option<Box<dyn VFS>> {
+        self.roots.pop_front()
+    }
+
     /// Adds a new VFS to the end of the list.
     pub fn push_back(&mut self, fs: Box<dyn VFS>) {
         self.roots.push_back(fs);
     }
 
+    /// Filesystems in the overlay, in lookup order.
     pub fn roots(&self) -> &VecDeque<Box<dyn VFS>> {
         &self.roots
     }
@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+use std::io::Read;
+
+use image::RgbaImage;
+use log::info;
+
+use crate::common::Rect;
+use crate::engine_constants::EngineConstants;
+use crate::ggez::{Context, filesystem, GameResult};
+use crate::texture_set::SizedBatch;
+
+/// Size of an atlas page. A new page is added once it's full, so the glyphs already in use stay where they are.
+const PAGE_SIZE: u32 = 512;
+/// Gap between the glyphs, so they don't bleed into each other.
+const PADDING: u32 = 1;
+
+/// Glyph rasterized from a TrueType font, in font pixels before the font scale is applied.
+#[derive(Debug, Clone, Copy)]
+pub struct AtlasGlyph {
+    pub page: usize,
+    /// Empty for whitespace, which has nothing to draw.
+    pub rect: Rect<usize>,
+    pub xoffset: f32,
+    /// From the top of the line, like the bitmap font's.
+    pub yoffset: f32,
+    pub advance: f32,
+}
+
+struct Page {
+    image: RgbaImage,
+    /// Uploaded copy of the image, None until it's drawn for the first time and after a glyph has been added to it.
+    batch: Option<SizedBatch>,
+    /// Shelf packing, glyphs go left to right in rows as tall as the tallest one in them.
+    cursor_x: u32,
+    cursor_y: u32,
+    row_height: u32,
+}
+
+impl Page {
+    fn new() -> Page {
+        Page {
+            image: RgbaImage::new(PAGE_SIZE, PAGE_SIZE),
+            batch: None,
+            cursor_x: 0,
+            cursor_y: 0,
+            row_height: 0,
+        }
+    }
+
+    /// Finds a place for a glyph, None if the page is full.
+    fn allocate(&mut self, width: u32, height: u32) -> Option<(u32, u32)> {
+        if self.cursor_x + width + PADDING > PAGE_SIZE {
+            self.cursor_x = 0;
+            self.cursor_y += self.row_height;
+            self.row_height = 0;
+        }
+
+        if self.cursor_y + height + PADDING > PAGE_SIZE {
+            return None;
+        }
+
+        let position = (self.cursor_x, self.cursor_y);
+        self.cursor_x += width + PADDING;
+        self.row_height = self.row_height.max(height + PADDING);
+
+        Some(position)
+    }
+}
+
+/// Glyphs the bitmap font doesn't have, rasterized from TrueType fonts the first time they're needed.
+/// Meant for translations into languages with large character sets, which don't fit in a pre-baked sheet.
+pub struct GlyphAtlas {
+    /// Tried in order, the first one with the glyph is used.
+    fonts: Vec<fontdue::Font>,
+    /// Size the glyphs are rasterized at.
+    size: f32,
+    /// Baseline of the bitmap font, the glyphs are lined up with it.
+    base: f32,
+    pages: Vec<Page>,
+    /// Glyphs none of the fonts have are remembered too.
+    glyphs: HashMap<char, Option<AtlasGlyph>>,
+}
+
+impl GlyphAtlas {
+    pub fn new(fonts: Vec<fontdue::Font>, size: f32, base: f32) -> GlyphAtlas {
+        GlyphAtlas {
+            fonts,
+            size,
+            base,
+            pages: Vec::new(),
+            glyphs: HashMap::new(),
+        }
+    }
+
+    /// Loads the fonts listed in `EngineConstants::font_fallback_paths`. Missing fonts are skipped,
+    /// an error is logged if there's none at all since no fallback font is bundled.
+    /// The fonts are rasterized to match the size of a bitmap font with the given baseline.
+    pub fn load(constants: &EngineConstants, base: f32, ctx: &mut Context) -> GlyphAtlas {
+        let mut fonts = Vec::new();
+
+        for path in constants.font_fallback_paths.iter() {
+            if !filesystem::exists(ctx, path) {
+                continue;
+            }
+
+            let mut data = Vec::new();
+            let font = filesystem::open(ctx, path)
+                .and_then(|mut file| Ok(file.read_to_end(&mut data)?))
+                .map_err(|e| e.to_string())
+                .and_then(|_| fontdue::Font::from_bytes(data, fontdue::FontSettings::default()).map_err(|e| e.to_string()));
+
+            match font {
+                Ok(font) => {
+                    info!("Loaded fallback font: {}", path);
+                    fonts.push(font);
+                }
+                Err(e) => log::warn!("Cannot load the fallback font {}: {}", path, e),
+            }
+        }
+
+        if fonts.is_empty() {
+            log::error!("None of the fallback fonts ({}) could be loaded, characters missing from the bitmap font will be drawn as '?'.",
+                        constants.font_fallback_paths.join(", "));
+        }
+
+        GlyphAtlas::new(fonts, constants.font_fallback_size / constants.font_scale, base)
+    }
+
+    /// Looks up the glyph, rasterizing it if it's needed for the first time.
+    pub fn glyph(&mut self, chr: char) -> Option<AtlasGlyph> {
+        if let Some(glyph) = self.glyphs.get(&chr) {
+            return *glyph;
+        }
+
+        let glyph = self.rasterize(chr);
+        if glyph.is_none() && !self.fonts.is_empty() {
+            log::warn!("None of the fallback fonts has a glyph for {:?}.", chr);
+        }
+
+        self.glyphs.insert(chr, glyph);
+        glyph
+    }
+
+    fn rasterize(&mut self, chr: char) -> Option<AtlasGlyph> {
+        let font = self.fonts.iter().find(|font| font.lookup_glyph_index(chr) != 0)?;
+        let (metrics, coverage) = font.rasterize(chr, self.size);
+        let (width, height) = (metrics.width as u32, metrics.height as u32);
+
+        let mut glyph = AtlasGlyph {
+            page: 0,
+            rect: Rect::new(0, 0, 0, 0),
+            xoffset: metrics.xmin as f32,
+            yoffset: self.base - (metrics.ymin as f32 + height as f32),
+            advance: metrics.advance_width,
+        };
+
+        if width == 0 || height == 0 {
+            return Some(glyph);
+        }
+
+        if width + PADDING > PAGE_SIZE || height + PADDING > PAGE_SIZE {
+            log::warn!("Glyph {:?} is too large for the atlas.", chr);
+            return None;
+        }
+
+        let (x, y) = match self.pages.last_mut().and_then(|page| page.allocate(width, height)) {
+            Some(position) => position,
+            None => {
+                self.pages.push(Page::new());
+                self.pages.last_mut().unwrap().allocate(width, height)?
+            }
+        };
+
+        glyph.page = self.pages.len() - 1;
+        glyph.rect = Rect::new_size(x as usize, y as usize, width as usize, height as usize);
+
+        let page = &mut self.pages[glyph.page];
+        for (i, alpha) in coverage.iter().enumerate() {
+            let i = i as u32;
+            page.image.put_pixel(x + i % width, y + i / width, image::Rgba([255, 255, 255, *alpha]));
+        }
+
+        // uploaded again on the next draw, the page size doesn't change so the other glyphs keep their coordinates
+        page.batch = None;
+
+        Some(glyph)
+    }
+
+    /// Batch of a page, uploads it first if glyphs have been added to it since it was drawn.
+    pub fn batch(&mut self, page: usize, ctx: &mut Context) -> GameResult<&mut SizedBatch> {
+        let page = &mut self.pages[page];
+
+        if page.batch.is_none() {
+            page.batch = Some(SizedBatch::from_rgba(ctx, "<glyph atlas>", &page.image)?);
+        }
+
+        Ok(page.batch.as_mut().unwrap())
+    }
+}
+
+#[test]
+fn test_atlas_page() {
+    let mut page = Page::new();
+
+    assert_eq!(page.allocate(10, 12), Some((0, 0)));
+    assert_eq!(page.allocate(10, 8), Some((10 + PADDING, 0)));
+
+    // next row under the tallest glyph
+    assert_eq!(page.allocate(PAGE_SIZE - 30, 8), Some((2 * (10 + PADDING), 0)));
+    assert_eq!(page.allocate(10, 8), Some((0, 12 + PADDING)));
+
+    // full
+    assert_eq!(page.allocate(100, PAGE_SIZE), None);
+
+    // no fonts, no glyphs
+    let mut atlas = GlyphAtlas::new(Vec::new(), 12.0, 10.0);
+    assert!(atlas.glyph('あ').is_none());
+    assert!(atlas.pages.is_empty());
+}
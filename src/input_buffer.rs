@@ -0,0 +1,181 @@
+use std::collections::VecDeque;
+use std::time::Instant;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum InputDevice {
+    Keyboard,
+    Gamepad(usize),
+}
+
+/// Queues key transitions with the time they happened, so that when several ticks are run at once
+/// to catch up after a hitch, every tick sees the transitions from its own time slice.
+pub struct InputBuffer {
+    events: VecDeque<(Instant, InputDevice, u16, bool)>,
+    /// Keys held on every device seen so far, a release on one device doesn't affect the others.
+    devices: Vec<(InputDevice, u16)>,
+    last_device: InputDevice,
+    /// Time of the oldest event applied since the last `take_oldest_applied`.
+    oldest_applied: Option<Instant>,
+}
+
+fn apply(devices: &mut Vec<(InputDevice, u16)>, device: InputDevice, mask: u16, pressed: bool) {
+    let index = match devices.iter().position(|&(d, _)| d == device) {
+        Some(index) => index,
+        None => {
+            devices.push((device, 0));
+            devices.len() - 1
+        }
+    };
+
+    if pressed {
+        devices[index].1 |= mask;
+    } else {
+        devices[index].1 &= !mask;
+    }
+}
+
+impl InputBuffer {
+    pub fn new() -> Self {
+        Self {
+            events: VecDeque::with_capacity(16),
+            devices: Vec::new(),
+            last_device: InputDevice::Keyboard,
+            oldest_applied: None,
+        }
+    }
+
+    /// Records that keys in `mask` have been pressed or released on `device` at `time`.
+    pub fn push(&mut self, time: Instant, device: InputDevice, mask: u16, pressed: bool) {
+        if pressed {
+            self.last_device = device;
+        }
+
+        self.events.push_back((time, device, mask, pressed));
+    }
+
+    /// Device which had a key pressed most recently, prompts show its buttons.
+    pub fn last_device(&self) -> InputDevice {
+        self.last_device
+    }
+
+    /// Keys held on any of the devices.
+    pub fn state(&self) -> u16 {
+        self.devices.iter().fold(0, |state, &(_, keys)| state | keys)
+    }
+
+    /// Applies events which happened before `until` and returns the resulting key state for a tick.
+    /// A key can change only once per tick, if it's pressed and released within the same slice,
+    /// the release (and everything after it) is left for the next tick so the press isn't lost.
+    pub fn drain_until(&mut self, until: Instant) -> u16 {
+        let mut changed = 0u16;
+
+        while let Some(&(time, device, mask, pressed)) = self.events.front() {
+            if time >= until || changed & mask != 0 {
+                break;
+            }
+
+            apply(&mut self.devices, device, mask, pressed);
+            if self.oldest_applied.is_none() {
+                self.oldest_applied = Some(time);
+            }
+
+            changed |= mask;
+            self.events.pop_front();
+        }
+
+        self.state()
+    }
+
+    /// Keys held once every queued event is applied, without applying them. The ticks never see this state.
+    pub fn peek_state(&self) -> u16 {
+        let mut devices = self.devices.clone();
+        for &(_, device, mask, pressed) in self.events.iter() {
+            apply(&mut devices, device, mask, pressed);
+        }
+
+        devices.iter().fold(0, |state, &(_, keys)| state | keys)
+    }
+
+    /// When the oldest event applied since the last call happened, the input latency is measured from it.
+    pub fn take_oldest_applied(&mut self) -> Option<Instant> {
+        self.oldest_applied.take()
+    }
+}
+
+#[test]
+fn test_input_buffer() {
+    use std::time::Duration;
+
+    let start = Instant::now();
+    let ms = |n| start + Duration::from_millis(n);
+    let mut buffer = InputBuffer::new();
+
+    // jump (0x20) tapped quickly within a single 20ms slice, fire (0x40) held across two slices
+    buffer.push(ms(2), InputDevice::Keyboard, 0x20, true);
+    buffer.push(ms(5), InputDevice::Keyboard, 0x40, true);
+    buffer.push(ms(8), InputDevice::Keyboard, 0x20, false);
+    buffer.push(ms(50), InputDevice::Keyboard, 0x40, false);
+
+    let mut old = 0u16;
+    let mut triggers = Vec::new();
+    let mut states = Vec::new();
+
+    // all of the ticks are run at once, like after a hitch
+    for tick in 1..=4 {
+        let state = buffer.drain_until(ms(tick * 20));
+        triggers.push((state ^ old) & state);
+        states.push(state);
+        old = state;
+    }
+
+    assert_eq!(states, vec![0x60, 0x40, 0x00, 0x00]);
+    assert_eq!(triggers, vec![0x60, 0x00, 0x00, 0x00]);
+    assert_eq!(buffer.take_oldest_applied(), Some(ms(2)));
+    assert_eq!(buffer.take_oldest_applied(), None);
+
+    // double tap within a single slice is spread over the following ticks
+    let mut buffer = InputBuffer::new();
+    buffer.push(ms(2), InputDevice::Keyboard, 0x20, true);
+    buffer.push(ms(4), InputDevice::Keyboard, 0x20, false);
+    buffer.push(ms(6), InputDevice::Keyboard, 0x20, true);
+    buffer.push(ms(8), InputDevice::Keyboard, 0x20, false);
+
+    // late latching sees the last state right away, the ticks still get every tap
+    assert_eq!(buffer.peek_state(), 0x00);
+    assert_eq!(buffer.drain_until(ms(20)), 0x20);
+    assert_eq!(buffer.peek_state(), 0x00);
+    buffer.push(ms(30), InputDevice::Keyboard, 0x20, true);
+    assert_eq!(buffer.peek_state(), 0x20);
+
+    let states: Vec<u16> = (2..=5).map(|tick| buffer.drain_until(ms(tick * 20))).collect();
+    assert_eq!(states, vec![0x00, 0x20, 0x00, 0x20]);
+}
+
+#[test]
+fn test_input_buffer_devices() {
+    use std::time::Duration;
+
+    use crate::common::{Direction, KeyState, resolve_movement};
+
+    let start = Instant::now();
+    let ms = |n| start + Duration::from_millis(n);
+    let mut buffer = InputBuffer::new();
+    let pad = InputDevice::Gamepad(0);
+
+    // right held on the pad, left (A) pressed and released on the keyboard meanwhile
+    buffer.push(ms(2), pad, 0x02, true);
+    buffer.push(ms(22), InputDevice::Keyboard, 0x01, true);
+    buffer.push(ms(42), InputDevice::Keyboard, 0x01, false);
+
+    let states: Vec<u16> = (1..=3).map(|tick| buffer.drain_until(ms(tick * 20))).collect();
+    assert_eq!(states, vec![0x02, 0x03, 0x02]);
+
+    let resolved: Vec<Option<Direction>> = states.iter().map(|&keys| resolve_movement(KeyState(keys))).collect();
+    assert_eq!(resolved, vec![Some(Direction::Right), None, Some(Direction::Right)]);
+
+    // releasing right on the keyboard doesn't release it on the pad
+    buffer.push(ms(62), InputDevice::Keyboard, 0x02, false);
+    assert_eq!(buffer.drain_until(ms(80)), 0x02);
+    buffer.push(ms(82), pad, 0x02, false);
+    assert_eq!(buffer.drain_until(ms(100)), 0x00);
+}
@@ -0,0 +1,109 @@
+use crate::common::{KeyState, Rect};
+use crate::ggez::{Context, GameResult};
+use crate::{HUD_SAFE_MARGIN, SharedGameState};
+use crate::render::primitives::Primitives;
+
+/// Ticks a button flashes for after being pressed.
+const FLASH_TICKS: u8 = 8;
+const WIDTH: isize = 46;
+const HEIGHT: isize = 20;
+
+const IDLE_COLOR: [f32; 4] = [0.3, 0.3, 0.3, 0.8];
+const HELD_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+const FLASH_COLOR: [f32; 4] = [1.0, 0.8, 0.2, 1.0];
+
+/// Key state bit and position of every button, relative to the top left corner of the widget.
+const BUTTONS: [(u16, Rect<isize>); 8] = [
+    // d-pad
+    (1 << 0, Rect { left: 0, top: 7, right: 6, bottom: 13 }),
+    (1 << 1, Rect { left: 12, top: 7, right: 18, bottom: 13 }),
+    (1 << 2, Rect { left: 6, top: 1, right: 12, bottom: 7 }),
+    (1 << 3, Rect { left: 6, top: 13, right: 12, bottom: 19 }),
+    // weapon prev / next, like shoulder buttons
+    (1 << 8, Rect { left: 22, top: 0, right: 32, bottom: 4 }),
+    (1 << 7, Rect { left: 34, top: 0, right: 44, bottom: 4 }),
+    // fire, jump
+    (1 << 6, Rect { left: 24, top: 8, right: 32, bottom: 16 }),
+    (1 << 5, Rect { left: 36, top: 10, right: 44, bottom: 18 }),
+];
+
+/// On-screen display of the keys held, toggled with F4. Shows the key state the game actually sees,
+/// so replayed inputs during replay playback.
+pub struct InputDisplay {
+    held: u16,
+    flash: [u8; 8],
+}
+
+impl InputDisplay {
+    pub fn new() -> Self {
+        Self {
+            held: 0,
+            flash: [0; 8],
+        }
+    }
+
+    /// Has to be called after the scene tick, once `key_state` and `key_trigger` are up to date.
+    pub fn tick(&mut self, key_state: KeyState, key_trigger: KeyState) {
+        self.held = key_state.0;
+
+        for (flash, (mask, _)) in self.flash.iter_mut().zip(BUTTONS.iter()) {
+            if key_trigger.0 & mask != 0 {
+                *flash = FLASH_TICKS;
+            } else {
+                *flash = flash.saturating_sub(1);
+            }
+        }
+    }
+
+    fn button_color(&self, index: usize) -> [f32; 4] {
+        let (mask, _) = BUTTONS[index];
+        let flash = self.flash[index];
+
+        if flash > 0 && (flash / 2).is_multiple_of(2) {
+            FLASH_COLOR
+        } else if self.held & mask != 0 {
+            HELD_COLOR
+        } else {
+            IDLE_COLOR
+        }
+    }
+
+    pub fn draw(&self, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
+        if !state.settings.input_display {
+            return Ok(());
+        }
+
+        let hud = state.hud_rect();
+        let x = (hud.right - HUD_SAFE_MARGIN) as isize - WIDTH;
+        let y = (hud.bottom - HUD_SAFE_MARGIN) as isize - HEIGHT;
+
+        let mut primitives = Primitives::new();
+        primitives.rect(Rect::new_size(x - 2, y - 2, WIDTH + 4, HEIGHT + 4), [0.0, 0.0, 0.0, 0.5]);
+
+        for (index, (_, rect)) in BUTTONS.iter().enumerate() {
+            let rect = Rect::new(x + rect.left, y + rect.top, x + rect.right, y + rect.bottom);
+            primitives.rect(rect, self.button_color(index));
+        }
+
+        state.texture_set.draw_primitives(ctx, &primitives)
+    }
+}
+
+#[test]
+fn test_input_display() {
+    let mut display = InputDisplay::new();
+    let jump = 7;
+
+    // pressed, flashes first and stays lit while held
+    display.tick(KeyState(0x20), KeyState(0x20));
+    assert_eq!(display.button_color(jump), FLASH_COLOR);
+    for _ in 0..FLASH_TICKS {
+        display.tick(KeyState(0x20), KeyState(0));
+    }
+    assert_eq!(display.button_color(jump), HELD_COLOR);
+
+    // released
+    display.tick(KeyState(0), KeyState(0));
+    assert_eq!(display.button_color(jump), IDLE_COLOR);
+    assert_eq!(display.button_color(0), IDLE_COLOR);
+}
@@ -2,10 +2,24 @@ use crate::engine_constants::EngineConstants;
 use crate::SharedGameState;
 use crate::weapon::{Weapon, WeaponLevel, WeaponType};
 
-#[derive(Clone, Copy)]
-pub struct Item(u16);
+/// ArmsItem.tsc events, shown when the inventory cursor lands on a weapon or an item and run when an item is used.
+pub fn weapon_event(wtype: WeaponType) -> u16 {
+    1000 + wtype as u16
+}
+
+pub fn item_description_event(item_id: u16) -> u16 {
+    5000 + item_id
+}
+
+pub fn item_use_event(item_id: u16) -> u16 {
+    6000 + item_id
+}
+
+/// Item id and how many of it the player has, more than 1 only with items stacking in CS+ challenges.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct Item(u16, u16);
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Inventory {
     current_item: u16,
     current_weapon: u16,
@@ -33,12 +47,76 @@ impl Inventory {
 
     pub fn add_item(&mut self, item_id: u16) {
         if !self.has_item(item_id) {
-            self.items.push(Item(item_id));
+            self.items.push(Item(item_id, 1));
         }
     }
 
+    /// Like `add_item`, but adds one more if the player already has the item.
+    pub fn stack_item(&mut self, item_id: u16) {
+        match self.items.iter_mut().find(|item| item.0 == item_id) {
+            Some(item) => { item.1 = item.1.saturating_add(1); }
+            None => self.items.push(Item(item_id, 1)),
+        }
+    }
+
+    /// Removes the item whatever the count.
     pub fn remove_item(&mut self, item_id: u16) {
-        self.items.retain(|item| item.0 != item_id);
+        if let Some(idx) = self.items.iter().position(|item| item.0 == item_id) {
+            self.remove_item_slot(idx);
+        }
+    }
+
+    /// Takes one of a stacked item, the last one removes it.
+    pub fn consume_item(&mut self, item_id: u16) {
+        if let Some(idx) = self.items.iter().position(|item| item.0 == item_id) {
+            self.items[idx].1 = self.items[idx].1.saturating_sub(1);
+            if self.items[idx].1 == 0 {
+                self.remove_item_slot(idx);
+            }
+        }
+    }
+
+    /// The items after it shift left like in the original, there are never gaps in the grid.
+    /// The cursor stays on the item it was on, or on the slot which took the place of the removed one.
+    fn remove_item_slot(&mut self, idx: usize) {
+        self.items.remove(idx);
+
+        let current = self.current_item as usize;
+        if idx < current {
+            self.current_item -= 1;
+        }
+        self.clamp_item_cursor();
+    }
+
+    /// Keeps the cursor on an existing slot, profiles can come with any index.
+    pub fn clamp_item_cursor(&mut self) {
+        if self.current_item as usize >= self.items.len() {
+            self.current_item = self.items.len().saturating_sub(1) as u16;
+        }
+    }
+
+    pub fn get_item(&self, idx: usize) -> Option<u16> {
+        self.items.get(idx).map(|item| item.0)
+    }
+
+    pub fn get_item_count(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn item_count(&self, item_id: u16) -> u16 {
+        self.items.iter().find(|item| item.0 == item_id).map_or(0, |item| item.1)
+    }
+
+    pub fn item_ids(&self) -> impl Iterator<Item=u16> + '_ {
+        self.items.iter().map(|item| item.0)
+    }
+
+    pub fn get_current_item_idx(&self) -> u16 {
+        self.current_item
+    }
+
+    pub fn set_current_item_idx(&mut self, idx: u16) {
+        self.current_item = idx;
     }
 
     pub fn has_item(&self, item_id: u16) -> bool {
@@ -57,8 +135,56 @@ impl Inventory {
         }
     }
 
+    /// Adds a weapon as is, used when loading profiles.
+    pub fn push_weapon(&mut self, weapon: Weapon) {
+        if !self.has_weapon(weapon.wtype) {
+            self.weapons.push(weapon);
+        }
+    }
+
+    /// The weapons after it move up a slot, like in the original the first weapon is selected afterwards.
     pub fn remove_weapon(&mut self, wtype: WeaponType) {
-        self.weapons.retain(|weapon| weapon.wtype != wtype);
+        if self.has_weapon(wtype) {
+            self.weapons.retain(|weapon| weapon.wtype != wtype);
+            self.current_weapon = 0;
+        }
+    }
+
+    /// <TAM, the new weapon takes the old one's slot at level 1 with `max_ammo` more ammo.
+    /// Returns false if the player doesn't have the old weapon.
+    pub fn trade_weapon(&mut self, old: WeaponType, new: WeaponType, max_ammo: u16) -> bool {
+        match self.weapons.iter_mut().find(|weapon| weapon.wtype == old) {
+            Some(weapon) => {
+                weapon.wtype = new;
+                weapon.level = WeaponLevel::Level1;
+                weapon.experience = 0;
+                weapon.max_ammo = weapon.max_ammo.saturating_add(max_ammo);
+                weapon.ammo = weapon.ammo.saturating_add(max_ammo);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Selects the next or the previous weapon in the order they were acquired, wrapping around.
+    /// The Spur's charge is kept as its experience and is lost when switching away from it.
+    /// Returns false if there's nothing to switch to.
+    pub fn switch_weapon(&mut self, forward: bool) -> bool {
+        let count = self.weapons.len() as u16;
+        if count == 0 {
+            return false;
+        }
+
+        if let Some(weapon) = self.get_current_weapon_mut() {
+            if weapon.wtype == WeaponType::Spur {
+                weapon.level = WeaponLevel::Level1;
+                weapon.experience = 0;
+            }
+        }
+
+        let current = self.current_weapon % count;
+        self.current_weapon = if forward { (current + 1) % count } else { (current + count - 1) % count };
+        true
     }
 
     pub fn get_weapon(&self, idx: usize) -> Option<&Weapon> {
@@ -115,6 +241,34 @@ impl Inventory {
         result
     }
 
+    /// Takes experience from the current weapon on damage, dropping levels as needed (vanilla's DamageArmsExp).
+    /// Returns true if the weapon has lost a level.
+    pub fn take_xp(&mut self, exp: u16, constants: &EngineConstants) -> bool {
+        let mut level_down = false;
+
+        if let Some(weapon) = self.get_current_weapon_mut() {
+            let lvl_table = constants.weapon.level_table[weapon.wtype as usize];
+            let mut experience = weapon.experience as isize - exp as isize;
+
+            while experience < 0 {
+                if weapon.level == WeaponLevel::Level2 || weapon.level == WeaponLevel::Level3 {
+                    weapon.level = weapon.level.prev();
+                    experience += lvl_table[weapon.level as usize - 1] as isize;
+
+                    if weapon.wtype != WeaponType::Spur {
+                        level_down = true;
+                    }
+                } else {
+                    experience = 0;
+                }
+            }
+
+            weapon.experience = experience as u16;
+        }
+
+        level_down
+    }
+
     /// Get current experience state. Returns a (exp, max exp, max level/exp) tuple.
     pub fn get_current_max_exp(&self, constants: &EngineConstants) -> (u16, u16, bool) {
         if let Some(weapon) = self.weapons.get(self.current_weapon as usize) {
@@ -153,6 +307,10 @@ impl Inventory {
         self.current_weapon
     }
 
+    pub fn set_current_weapon_idx(&mut self, idx: u16) {
+        self.current_weapon = if (idx as usize) < self.weapons.len() { idx } else { 0 };
+    }
+
     pub fn get_weapon_count(&self) -> usize {
         self.weapons.len()
     }
@@ -161,3 +319,165 @@ impl Inventory {
         self.weapons.iter().any(|weapon| weapon.wtype == wtype)
     }
 }
+
+#[test]
+fn test_item_stacking() {
+    let mut inventory = Inventory::new();
+
+    // freeware, having an item is all there is to it
+    inventory.add_item(5);
+    inventory.add_item(5);
+    assert_eq!(inventory.item_count(5), 1);
+    inventory.remove_item(5);
+    assert_eq!(inventory.item_count(5), 0);
+
+    inventory.stack_item(5);
+    inventory.stack_item(5);
+    inventory.add_item(5);
+    assert_eq!(inventory.item_count(5), 2);
+    assert_eq!(inventory.item_ids().collect::<Vec<u16>>(), vec![5]);
+
+    inventory.consume_item(5);
+    assert!(inventory.has_item(5));
+    inventory.consume_item(5);
+    assert!(!inventory.has_item(5));
+    inventory.consume_item(5);
+    assert_eq!(inventory.item_count(5), 0);
+}
+
+#[test]
+fn test_weapon_cycling() {
+    let mut inventory = Inventory::new();
+    assert!(!inventory.switch_weapon(true));
+
+    for &wtype in [WeaponType::PolarStar, WeaponType::Fireball, WeaponType::Snake, WeaponType::Spur, WeaponType::Blade].iter() {
+        inventory.add_weapon(wtype, 0);
+    }
+
+    let current = |inventory: &Inventory| inventory.get_current_weapon().unwrap().wtype;
+
+    // <AM- in the middle, the order of the rest stays as acquired rather than sorted by type
+    inventory.set_current_weapon_idx(3);
+    inventory.remove_weapon(WeaponType::Fireball);
+    assert_eq!(current(&inventory), WeaponType::PolarStar);
+    let order: Vec<WeaponType> = (0..4).map(|_| {
+        inventory.switch_weapon(true);
+        current(&inventory)
+    }).collect();
+    assert_eq!(order, vec![WeaponType::Snake, WeaponType::Spur, WeaponType::Blade, WeaponType::PolarStar]);
+
+    // wraps backwards too
+    assert!(inventory.switch_weapon(false));
+    assert_eq!(current(&inventory), WeaponType::Blade);
+
+    // removing a weapon the player doesn't have keeps the selection
+    inventory.remove_weapon(WeaponType::Fireball);
+    assert_eq!(current(&inventory), WeaponType::Blade);
+
+    // mashing both keys ends where the switches add up to
+    for i in 0..25 {
+        inventory.switch_weapon(i % 3 != 0);
+    }
+    assert_eq!(inventory.get_current_weapon_idx(), (3 + 25 - 2 * 9) % 4);
+
+    // switching away from the Spur loses its charge
+    inventory.set_current_weapon_idx(2);
+    if let Some(weapon) = inventory.get_current_weapon_mut() {
+        weapon.level = WeaponLevel::Level2;
+        weapon.experience = 10;
+    }
+    inventory.switch_weapon(true);
+    let spur = inventory.get_weapon(2).unwrap();
+    assert_eq!((spur.level, spur.experience), (WeaponLevel::Level1, 0));
+
+    // a single weapon switches to itself
+    let mut inventory = Inventory::new();
+    inventory.add_weapon(WeaponType::PolarStar, 0);
+    assert!(inventory.switch_weapon(false));
+    assert_eq!(inventory.get_current_weapon_idx(), 0);
+}
+
+#[test]
+fn test_take_xp() {
+    use crate::common::Equipment;
+    use crate::player::exp_loss;
+
+    let constants = EngineConstants::defaults();
+    let table = constants.weapon.level_table[WeaponType::PolarStar as usize];
+    let mut inventory = Inventory::new();
+    inventory.push_weapon(Weapon::new(WeaponType::PolarStar, WeaponLevel::Level2, 4, 0, 0));
+
+    // 3 damage with the Arms Barrier
+    let mut equip = Equipment(0);
+    equip.set_arms_barrier(true);
+    assert!(!inventory.take_xp(exp_loss(3, equip), &constants));
+    assert_eq!(inventory.get_current_weapon().unwrap().experience, 1);
+
+    // and without, drops a level and carries the rest over
+    assert!(inventory.take_xp(exp_loss(3, Equipment(0)), &constants));
+    let weapon = inventory.get_current_weapon().unwrap();
+    assert_eq!(weapon.level, WeaponLevel::Level1);
+    assert_eq!(weapon.experience, table[0] - 5);
+
+    // level 1 bottoms out at 0
+    assert!(!inventory.take_xp(1000, &constants));
+    assert_eq!(inventory.get_current_weapon().unwrap().experience, 0);
+}
+
+#[test]
+fn test_item_compaction() {
+    let mut inventory = Inventory::new();
+    for &item_id in [2, 3, 5, 7, 11].iter() {
+        inventory.add_item(item_id);
+    }
+
+    // taken from before the cursor, it keeps pointing at the same item
+    inventory.set_current_item_idx(3);
+    inventory.remove_item(3);
+    assert_eq!(inventory.item_ids().collect::<Vec<u16>>(), vec![2, 5, 7, 11]);
+    assert_eq!(inventory.get_item(inventory.get_current_item_idx() as usize), Some(7));
+
+    // <IT- of the very item being described, the next one shifts under the cursor
+    inventory.remove_item(7);
+    assert_eq!(inventory.item_ids().collect::<Vec<u16>>(), vec![2, 5, 11]);
+    assert_eq!(inventory.get_item(inventory.get_current_item_idx() as usize), Some(11));
+
+    // the last slot, the cursor moves back onto the new last one
+    inventory.remove_item(11);
+    assert_eq!(inventory.get_current_item_idx(), 1);
+
+    // after it, nothing moves
+    inventory.add_item(13);
+    inventory.set_current_item_idx(0);
+    inventory.remove_item(13);
+    assert_eq!(inventory.get_current_item_idx(), 0);
+
+    inventory.remove_item(2);
+    inventory.remove_item(5);
+    assert_eq!(inventory.get_item_count(), 0);
+    assert_eq!(inventory.get_current_item_idx(), 0);
+}
+
+#[test]
+fn test_trade_weapon() {
+    let mut inventory = Inventory::new();
+    inventory.add_weapon(WeaponType::PolarStar, 0);
+    inventory.add_weapon(WeaponType::Blade, 10);
+    inventory.set_current_weapon_idx(1);
+    {
+        let blade = inventory.get_current_weapon_mut().unwrap();
+        blade.level = WeaponLevel::Level3;
+        blade.experience = 20;
+        blade.ammo = 4;
+    }
+
+    // the Nemesis takes the Blade's slot and stays selected, back at level 1 with the ammo added
+    assert!(inventory.trade_weapon(WeaponType::Blade, WeaponType::Nemesis, 5));
+    let nemesis = inventory.get_current_weapon().unwrap();
+    assert_eq!(nemesis.wtype, WeaponType::Nemesis);
+    assert_eq!(nemesis.level, WeaponLevel::Level1);
+    assert_eq!(nemesis.experience, 0);
+    assert_eq!((nemesis.ammo, nemesis.max_ammo), (9, 15));
+    assert_eq!(inventory.get_weapon_count(), 2);
+    assert!(!inventory.trade_weapon(WeaponType::Blade, WeaponType::Nemesis, 0));
+}
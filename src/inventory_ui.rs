@@ -0,0 +1,262 @@
+use crate::common::Rect;
+use crate::ggez::{Context, GameResult};
+use crate::inventory::{item_description_event, item_use_event, weapon_event};
+use crate::render::primitives::Primitives;
+use crate::scene::game_scene::GameScene;
+use crate::SharedGameState;
+use crate::text_script::{EventTrigger, TextScriptExecutionState};
+
+/// Items per row of the grid, like in the original.
+const ITEM_COLUMNS: usize = 6;
+const PANEL_WIDTH: isize = 244;
+const PANEL_HEIGHT: isize = 152;
+const WEAPON_SPACING: isize = 40;
+
+const PANEL_COLOR: [f32; 4] = [0.0, 0.0, 0.13, 0.9];
+const CURSOR_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+const INACTIVE_CURSOR_COLOR: [f32; 4] = [0.5, 0.5, 0.5, 1.0];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Focus {
+    Weapons,
+    Items,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Move {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+/// Item slot the cursor ends up on, `None` if it leaves the grid for the weapons row.
+/// Left and right wrap around within the row, up from the first row and down from the last one leave the grid.
+fn move_item_cursor(idx: usize, count: usize, dir: Move) -> Option<usize> {
+    let row = idx / ITEM_COLUMNS;
+    let last_row = count.saturating_sub(1) / ITEM_COLUMNS;
+
+    let idx = match dir {
+        Move::Left if idx.is_multiple_of(ITEM_COLUMNS) => idx + ITEM_COLUMNS - 1,
+        Move::Left => idx - 1,
+        // the last item of a partly empty row wraps around too
+        Move::Right if idx % ITEM_COLUMNS == ITEM_COLUMNS - 1 || idx + 1 >= count => row * ITEM_COLUMNS,
+        Move::Right => idx + 1,
+        Move::Up if row == 0 => { return None; }
+        Move::Up => idx - ITEM_COLUMNS,
+        Move::Down if row == last_row => { return None; }
+        Move::Down => idx + ITEM_COLUMNS,
+    };
+
+    // the last row can be partly empty
+    Some(idx.min(count.saturating_sub(1)))
+}
+
+/// The inventory screen, the world stands still while it's open and events are looked up in ArmsItem.tsc:
+/// the selected weapon's or item's description is shown when the cursor lands on it and jump uses the item.
+/// The item cursor is kept in the inventory, so it reopens on the last selected item.
+pub struct InventoryUI {
+    pub active: bool,
+    focus: Focus,
+}
+
+impl InventoryUI {
+    pub fn new() -> InventoryUI {
+        InventoryUI {
+            active: false,
+            focus: Focus::Weapons,
+        }
+    }
+
+    pub fn tick(&mut self, game_scene: &mut GameScene, state: &mut SharedGameState) {
+        if !self.active {
+            if state.key_trigger.inventory() && state.control_flags.control_enabled() && !state.textscript_vm.is_running() {
+                self.open(game_scene, state);
+            }
+            return;
+        }
+
+        // item events can take the selected item or weapon away, the cursor moves onto one that's still there
+        game_scene.inventory.clamp_item_cursor();
+        let weapon_idx = game_scene.inventory.get_current_weapon_idx();
+        game_scene.inventory.set_current_weapon_idx(weapon_idx);
+        if self.focus == Focus::Items && game_scene.inventory.get_item_count() == 0 {
+            self.focus = Focus::Weapons;
+        }
+
+        // an item event is running or the message box waits for the keys
+        if !state.control_flags.control_enabled() {
+            return;
+        }
+        match state.textscript_vm.state {
+            TextScriptExecutionState::WaitInput(..) | TextScriptExecutionState::WaitConfirmation(..) => { return; }
+            _ => {}
+        }
+
+        if state.key_trigger.inventory() || state.key_trigger.fire() {
+            self.close(state);
+            return;
+        }
+
+        let dir = if state.key_trigger.left() {
+            Some(Move::Left)
+        } else if state.key_trigger.right() {
+            Some(Move::Right)
+        } else if state.key_trigger.up() {
+            Some(Move::Up)
+        } else if state.key_trigger.down() {
+            Some(Move::Down)
+        } else {
+            None
+        };
+
+        if let Some(dir) = dir {
+            if self.move_cursor(game_scene, dir) {
+                state.sound_manager.play_sfx(1);
+                self.show_description(game_scene, state);
+            }
+        }
+
+        if self.focus == Focus::Items && state.key_trigger.jump() {
+            let idx = game_scene.inventory.get_current_item_idx() as usize;
+            if let Some(item_id) = game_scene.inventory.get_item(idx) {
+                state.textscript_vm.start_event(item_use_event(item_id), EventTrigger::Forced, &mut state.control_flags);
+            }
+        }
+    }
+
+    fn open(&mut self, game_scene: &mut GameScene, state: &mut SharedGameState) {
+        self.active = true;
+        state.textscript_vm.scripts.inventory_active = true;
+        state.sound_manager.play_sfx(1);
+
+        game_scene.inventory.clamp_item_cursor();
+        if game_scene.inventory.get_weapon_count() == 0 && game_scene.inventory.get_item_count() != 0 {
+            self.focus = Focus::Items;
+        } else if game_scene.inventory.get_item_count() == 0 {
+            self.focus = Focus::Weapons;
+        }
+        self.show_description(game_scene, state);
+    }
+
+    fn close(&mut self, state: &mut SharedGameState) {
+        self.active = false;
+        state.textscript_vm.scripts.inventory_active = false;
+        state.textscript_vm.reset();
+
+        state.control_flags.set_tick_world(true);
+        state.control_flags.set_control_enabled(true);
+        state.control_flags.set_interactions_disabled(false);
+    }
+
+    /// Returns true if the selection has changed.
+    fn move_cursor(&mut self, game_scene: &mut GameScene, dir: Move) -> bool {
+        let inventory = &mut game_scene.inventory;
+        let item_count = inventory.get_item_count();
+
+        match self.focus {
+            Focus::Weapons => match dir {
+                Move::Left | Move::Right => inventory.switch_weapon(dir == Move::Right),
+                Move::Up | Move::Down if item_count != 0 => {
+                    self.focus = Focus::Items;
+                    true
+                }
+                _ => false,
+            },
+            Focus::Items => {
+                let idx = inventory.get_current_item_idx() as usize;
+                match move_item_cursor(idx, item_count, dir) {
+                    Some(new_idx) => {
+                        inventory.set_current_item_idx(new_idx as u16);
+                        new_idx != idx
+                    }
+                    None => {
+                        self.focus = Focus::Weapons;
+                        true
+                    }
+                }
+            }
+        }
+    }
+
+    fn show_description(&self, game_scene: &GameScene, state: &mut SharedGameState) {
+        let event = match self.focus {
+            Focus::Weapons => game_scene.inventory.get_current_weapon().map(|weapon| weapon_event(weapon.wtype)),
+            Focus::Items => game_scene.inventory.get_item(game_scene.inventory.get_current_item_idx() as usize)
+                .map(item_description_event),
+        };
+
+        match event {
+            Some(event) => { state.textscript_vm.start_event(event, EventTrigger::Forced, &mut state.control_flags); }
+            None => state.textscript_vm.reset(),
+        }
+    }
+
+    pub fn draw(&self, game_scene: &GameScene, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
+        if !self.active {
+            return Ok(());
+        }
+
+        let inventory = &game_scene.inventory;
+        let x = ((state.canvas_size.0 as isize) - PANEL_WIDTH) / 2;
+        let y = 8;
+        let weapons_y = y + 16;
+        let items_y = y + 68;
+        // blinks while an item event is running
+        let cursor_color = if state.control_flags.control_enabled() || (game_scene.tick / 2).is_multiple_of(2) { CURSOR_COLOR } else { INACTIVE_CURSOR_COLOR };
+
+        let mut primitives = Primitives::new();
+        primitives.rect(Rect::new_size(x, y, PANEL_WIDTH, PANEL_HEIGHT), PANEL_COLOR);
+        match self.focus {
+            Focus::Weapons if inventory.get_weapon_count() != 0 => {
+                let slot_x = x + 8 + inventory.get_current_weapon_idx() as isize * WEAPON_SPACING;
+                primitives.outline(Rect::new_size(slot_x - 2, weapons_y - 2, 20, 20), cursor_color, 1.0);
+            }
+            Focus::Items => {
+                let idx = inventory.get_current_item_idx() as isize;
+                let cols = ITEM_COLUMNS as isize;
+                primitives.outline(Rect::new_size(x + 8 + (idx % cols) * 32, items_y + (idx / cols) * 16, 32, 16), cursor_color, 1.0);
+            }
+            _ => {}
+        }
+        state.texture_set.draw_primitives(ctx, &primitives)?;
+
+        state.font.draw_text("ARMS".chars(), (x + 8) as f32, (y + 4) as f32, &state.constants, &mut state.texture_set, ctx)?;
+        state.font.draw_text("ITEM".chars(), (x + 8) as f32, (items_y - 12) as f32, &state.constants, &mut state.texture_set, ctx)?;
+
+        let batch = state.texture_set.get_or_load_batch(ctx, &state.constants, "ArmsImage")?;
+        for idx in 0..inventory.get_weapon_count() {
+            if let Some(weapon) = inventory.get_weapon(idx) {
+                let left = weapon.wtype as usize * 16;
+                batch.add_rect((x + 8 + idx as isize * WEAPON_SPACING) as f32, weapons_y as f32, &Rect::new(left, 0, left + 16, 16));
+            }
+        }
+        batch.draw(ctx)?;
+
+        let batch = state.texture_set.get_or_load_batch(ctx, &state.constants, "ItemImage")?;
+        for idx in 0..inventory.get_item_count() {
+            if let Some(item_id) = inventory.get_item(idx) {
+                let item_id = item_id as usize;
+                let rect = Rect::new_size((item_id % 8) * 32, (item_id / 8) * 16, 32, 16);
+                batch.add_rect((x + 8 + (idx % ITEM_COLUMNS) as isize * 32) as f32, (items_y + (idx / ITEM_COLUMNS) as isize * 16) as f32, &rect);
+            }
+        }
+        batch.draw(ctx)?;
+
+        Ok(())
+    }
+}
+
+#[test]
+fn test_item_cursor() {
+    // two rows, the second one with two items
+    assert_eq!(move_item_cursor(0, 8, Move::Left), Some(5));
+    assert_eq!(move_item_cursor(5, 8, Move::Right), Some(0));
+    assert_eq!(move_item_cursor(6, 8, Move::Left), Some(7));
+    assert_eq!(move_item_cursor(7, 8, Move::Right), Some(6));
+    assert_eq!(move_item_cursor(0, 1, Move::Right), Some(0));
+    assert_eq!(move_item_cursor(2, 8, Move::Down), Some(7));
+    assert_eq!(move_item_cursor(7, 8, Move::Up), Some(1));
+    assert_eq!(move_item_cursor(3, 8, Move::Up), None);
+    assert_eq!(move_item_cursor(7, 8, Move::Down), None);
+}
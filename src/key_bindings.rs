@@ -0,0 +1,89 @@
+use crate::common::KeyState;
+use crate::ggez::event::{Button, KeyCode};
+
+/// Key state bit set by the given `KeyState` setter.
+pub fn bit(set: fn(&mut KeyState, bool)) -> u16 {
+    let mut state = KeyState(0);
+    set(&mut state, true);
+    state.0
+}
+
+/// Keys and gamepad buttons mapped to the game's key state bits.
+/// Prompts look the bindings up every time they're drawn, so they follow any change right away.
+// todo: rebinding from the settings menu
+pub struct KeyBindings {
+    pub keyboard: Vec<(KeyCode, u16)>,
+    pub gamepad: Vec<(Button, u16)>,
+    /// Debug key going back to the previous stage, see `GameScene::warp_back`.
+    pub warp_back: Option<KeyCode>,
+}
+
+impl KeyBindings {
+    pub fn new() -> KeyBindings {
+        KeyBindings {
+            keyboard: vec![
+                (KeyCode::Left, bit(KeyState::set_left)),
+                (KeyCode::Right, bit(KeyState::set_right)),
+                (KeyCode::Up, bit(KeyState::set_up)),
+                (KeyCode::Down, bit(KeyState::set_down)),
+                (KeyCode::Z, bit(KeyState::set_jump)),
+                (KeyCode::X, bit(KeyState::set_fire)),
+                (KeyCode::Q, bit(KeyState::set_inventory)),
+                (KeyCode::A, bit(KeyState::set_weapon_prev)),
+                (KeyCode::S, bit(KeyState::set_weapon_next)),
+                (KeyCode::Back, bit(KeyState::set_rewind)),
+                (KeyCode::Escape, bit(KeyState::set_menu)),
+            ],
+            // todo: analog sticks
+            gamepad: vec![
+                (Button::DPadLeft, bit(KeyState::set_left)),
+                (Button::DPadRight, bit(KeyState::set_right)),
+                (Button::DPadUp, bit(KeyState::set_up)),
+                (Button::DPadDown, bit(KeyState::set_down)),
+                (Button::South, bit(KeyState::set_jump)),
+                (Button::West, bit(KeyState::set_fire)),
+                (Button::LeftTrigger, bit(KeyState::set_weapon_prev)),
+                (Button::RightTrigger, bit(KeyState::set_weapon_next)),
+                (Button::North, bit(KeyState::set_map)),
+                (Button::Select, bit(KeyState::set_inventory)),
+                (Button::Start, bit(KeyState::set_menu)),
+            ],
+            warp_back: Some(KeyCode::F6),
+        }
+    }
+
+    /// Key state bits set by a key.
+    pub fn key_mask(&self, key: KeyCode) -> u16 {
+        self.keyboard.iter().filter(|&&(k, _)| k == key).fold(0, |mask, &(_, bits)| mask | bits)
+    }
+
+    /// Key state bits set by a gamepad button.
+    pub fn button_mask(&self, button: Button) -> u16 {
+        self.gamepad.iter().filter(|&&(b, _)| b == button).fold(0, |mask, &(_, bits)| mask | bits)
+    }
+
+    /// First key bound to any of the bits in `mask`.
+    pub fn key_for(&self, mask: u16) -> Option<KeyCode> {
+        self.keyboard.iter().find(|&&(_, bits)| bits & mask != 0).map(|&(key, _)| key)
+    }
+
+    /// First gamepad button bound to any of the bits in `mask`.
+    pub fn button_for(&self, mask: u16) -> Option<Button> {
+        self.gamepad.iter().find(|&&(_, bits)| bits & mask != 0).map(|&(button, _)| button)
+    }
+}
+
+#[test]
+fn test_key_bindings() {
+    let mut bindings = KeyBindings::new();
+    assert_eq!(bindings.key_mask(KeyCode::Z), 0x20);
+    assert_eq!(bindings.button_mask(Button::South), 0x20);
+    assert_eq!(bindings.key_mask(KeyCode::P), 0);
+    assert_eq!(bindings.key_for(0x20), Some(KeyCode::Z));
+    assert_eq!(bindings.key_for(bit(KeyState::set_map)), None);
+
+    bindings.keyboard.retain(|&(_, bits)| bits != 0x20);
+    bindings.keyboard.push((KeyCode::Space, 0x20));
+    assert_eq!(bindings.key_for(0x20), Some(KeyCode::Space));
+    assert_eq!(bindings.key_mask(KeyCode::Z), 0);
+}
@@ -0,0 +1,1007 @@
+//! Cave Story engine core.
+//!
+//! The parsers of the game data (`map::Map`, `profile::GameProfile`, `text_script::TextScript`)
+//! can be used on their own, `Game` runs the whole game and is driven by the binary's event loop.
+
+#[macro_use]
+extern crate bitflags;
+#[macro_use]
+extern crate gfx;
+#[macro_use]
+extern crate log;
+#[macro_use]
+extern crate serde_derive;
+#[macro_use]
+extern crate smart_default;
+extern crate strum;
+#[macro_use]
+extern crate strum_macros;
+
+use std::collections::VecDeque;
+use std::mem;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use bitvec::vec::BitVec;
+use log::*;
+use winit::{ElementState, Event, KeyboardInput, WindowEvent};
+use winit::dpi::LogicalPosition;
+
+use crate::bmfont_renderer::BMFontRenderer;
+use crate::builtin_fs::BuiltinFS;
+use crate::boss_rush::BossRush;
+use crate::caret::{Caret, CaretType};
+use crate::challenge::ChallengeRun;
+use crate::common::{ControlFlags, Direction, FadeState, KeyState, Rect, resolve_movement, resolve_vertical};
+use crate::container::Metadata;
+use crate::data_check::DataReport;
+use crate::discord::DiscordRPC;
+use crate::engine_constants::EngineConstants;
+use crate::frame_pacer::FramePacer;
+use crate::ggez::{Context, event};
+use crate::ggez::event::{Button, KeyCode, KeyMods};
+use crate::ggez::graphics;
+use crate::ggez::input::{gamepad, keyboard};
+use crate::ggez::input::gamepad::{GamepadId, Rumble};
+use crate::input_buffer::{InputBuffer, InputDevice};
+use crate::key_bindings::KeyBindings;
+use crate::input_display::InputDisplay;
+use crate::npc::{NPCTable, NPC};
+use crate::perf_hud::FrameTiming;
+use crate::player::{PlayableCharacter, PlayerPose};
+use crate::profile::{PlayRecord, UntrackedProfileData};
+use crate::render::{DrawSpace, GameCanvas};
+use crate::repro::Scenario;
+use crate::mods::ModInfo;
+use crate::rng::{EffectRNG, RNG};
+use crate::save_file::PendingWrite;
+use crate::save_state::QuickSaveAction;
+use crate::scene::data_report_scene::DataReportScene;
+use crate::scene::error_scene::ErrorScene;
+use crate::scene::loading_scene::LoadingScene;
+use crate::scene::mod_menu_scene::ModMenuScene;
+use crate::settings::{Presentation, Settings, WindowSettings};
+use crate::sound::SoundManager;
+use crate::stage::StageData;
+use crate::stage_history::StageHistory;
+use crate::startup::StartupTimings;
+use crate::stats::Stats;
+use crate::text_script::{TextScriptExtensions, TextScriptVM, TSC_VARIABLE_COUNT};
+use crate::texture_set::TextureSet;
+use crate::transition::TransitionType;
+use crate::ui::{Notifications, UI};
+
+mod bmfont;
+mod bmfont_renderer;
+mod boss_rush;
+mod builtin_fs;
+mod bullet;
+mod caret;
+mod challenge;
+pub mod common;
+mod container;
+mod crash;
+mod data_check;
+mod debug_arena;
+mod discord;
+mod encoding;
+mod engine_constants;
+mod entity;
+mod flash;
+mod frame;
+mod frame_pacer;
+mod glyph_atlas;
+mod input_buffer;
+mod input_display;
+mod inventory;
+mod inventory_ui;
+mod key_bindings;
+pub mod ggez;
+mod life_bar;
+mod lighting;
+mod live_debugger;
+mod log_sink;
+mod macros;
+mod memory;
+pub mod map;
+mod mods;
+mod npc;
+mod perf_hud;
+mod physics;
+mod player;
+mod player_hit;
+pub mod profile;
+mod prompts;
+mod render;
+mod replay;
+mod repro;
+mod rewind;
+mod rng;
+mod save_file;
+mod save_state;
+mod scene;
+pub mod settings;
+mod stage;
+mod stage_effect;
+mod stage_history;
+pub mod startup;
+mod stats;
+pub mod sound;
+pub mod text_script;
+mod texture_set;
+mod thumbnail;
+mod transition;
+pub mod tsc_tool;
+mod ui;
+mod watchdog;
+mod weapon;
+
+pub use crate::crash::{install_panic_hook, report_error};
+pub use crate::log_sink::init_logger;
+pub use crate::ggez::{error, filesystem, GameError, GameResult};
+pub use crate::map::Map;
+pub use crate::profile::GameProfile;
+pub use crate::scene::Scene;
+pub use crate::text_script::TextScript;
+
+/// The whole game, fed with window events and run once per frame by the binary's event loop.
+pub struct Game {
+    scene: Option<Box<dyn Scene>>,
+    state: SharedGameState,
+    ui: UI,
+    canvas: GameCanvas,
+    input_buffer: InputBuffer,
+    input_display: InputDisplay,
+    focused: bool,
+    next_tick: Instant,
+    last_frame: Instant,
+    pacer: FramePacer,
+    /// Longest time from a key event to the tick which applied it, over the ticks of the current frame.
+    input_latency: Option<Duration>,
+    /// Presentation mode the canvas has been laid out for.
+    presentation: Presentation,
+    /// Of the data directory, shown before the first scene if the game misses essential files.
+    data_report: Option<DataReport>,
+}
+
+/// Command line options.
+#[derive(Default, Debug)]
+pub struct LaunchOptions {
+    /// Scenario started right after loading, see `--repro`.
+    pub repro: Option<PathBuf>,
+    /// Where the final state of the scenario is written, see `--dump`.
+    pub dump: Option<PathBuf>,
+    /// Logs every executed TSC command, see `--trace-tsc`.
+    pub trace_tsc: bool,
+    /// Doesn't open the audio device, see `--no-audio`.
+    pub no_audio: bool,
+}
+
+impl LaunchOptions {
+    pub fn from_args<I: Iterator<Item=String>>(mut args: I) -> LaunchOptions {
+        let mut options = LaunchOptions::default();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--repro" => options.repro = args.next().map(PathBuf::from),
+                "--dump" => options.dump = args.next().map(PathBuf::from),
+                "--trace-tsc" => options.trace_tsc = true,
+                "--no-audio" => options.no_audio = true,
+                _ => warn!("Unknown argument: {}", arg),
+            }
+        }
+
+        options
+    }
+}
+
+pub const WINDOW_TITLE: &str = "doukutsu-rs";
+/// Limit of ticks run in a single frame to catch up after a hitch, the rest is dropped.
+const MAX_CATCHUP_TICKS: usize = 5;
+/// Time between frames drawn while the window is unfocused and `power_saving` is on.
+const UNFOCUSED_FRAME_DURATION: Duration = Duration::from_millis(100);
+
+/// HUD elements are kept within this aspect ratio, so they don't end up in the far corners of ultrawide windows.
+const HUD_MAX_ASPECT: f32 = 16.0 / 9.0;
+/// Minimal distance of the HUD elements anchored to the right or the bottom from the edge of the HUD area.
+const HUD_SAFE_MARGIN: f32 = 8.0;
+/// Number of the last flag changes kept for the softlock diagnostics.
+const FLAG_LOG_SIZE: usize = 16;
+/// Flags backed by the engine state instead of `game_flags`, read-only for scripts outside of the vanilla mode.
+pub const RESERVED_FLAGS: Range<usize> = 7990..8000;
+/// Set while playing as Curly, for `<FLJ` in mods reacting to the character select.
+pub const FLAG_PLAYING_AS_CURLY: usize = 7990;
+
+pub struct SharedGameState {
+    pub control_flags: ControlFlags,
+    pub game_flags: BitVec,
+    /// Last flags changed by scripts and NPCs, newest at the back.
+    pub flag_log: VecDeque<(usize, bool)>,
+    /// Last stage transitions, for the debug warp back and the softlock diagnostics.
+    pub stage_history: StageHistory,
+    /// Variables of the TSC `variables` extension, kept in save states and the profile.
+    pub tsc_variables: Vec<u16>,
+    pub fade_state: FadeState,
+    pub game_rng: RNG,
+    pub effect_rng: EffectRNG,
+    pub quake_counter: u16,
+    /// Ticks on the Nikumaru counter, carried across stages and reset whenever it's unequipped.
+    pub nikumaru_counter: usize,
+    /// Current of the stage, pushing the player and NPCs in water every tick, see `StageEffectConsts::stage_currents`.
+    pub ambient_force: (isize, isize),
+    /// Set by `<PSO`, outlives the stage until a script clears it.
+    pub player_pose: Option<PlayerPose>,
+    /// Picked on New game, kept in the profile.
+    pub character: PlayableCharacter,
+    /// Play time and life capsules of the current game, None if it was loaded from a profile saved without them.
+    pub play_record: Option<PlayRecord>,
+    /// Written back by the next save, see `UntrackedProfileData`.
+    pub untracked_profile: UntrackedProfileData,
+    pub carets: Vec<Caret>,
+    pub key_state: KeyState,
+    pub key_trigger: KeyState,
+    pub key_bindings: KeyBindings,
+    /// Device the player used last, prompts show its keys or buttons.
+    pub last_input_device: InputDevice,
+    /// Keys held right before the frame is drawn when late latching, the player's sprite turns to them.
+    /// For drawing only, the ticks never see it.
+    pub latched_key_state: Option<KeyState>,
+    pub font: BMFontRenderer,
+    pub texture_set: TextureSet,
+    pub base_path: String,
+    pub npc_table: NPCTable,
+    pub stages: Vec<StageData>,
+    /// Hash of the stage table and npc.tbl, written into replays and save states.
+    pub data_fingerprint: u64,
+    /// Metadata of the last replay or save state loaded and the name of its format, shown in the debugger.
+    pub loaded_metadata: Option<(&'static str, Metadata)>,
+    pub sound_manager: SoundManager,
+    pub constants: EngineConstants,
+    /// Logged by the loading scene.
+    pub startup: StartupTimings,
+    pub new_npcs: Vec<NPC>,
+    pub scale: f32,
+    pub god_mode: bool,
+    pub speed_hack: bool,
+    pub canvas_size: (f32, f32),
+    /// Where the canvas is drawn in the window, in physical pixels.
+    pub canvas_offset: (f32, f32),
+    pub screen_size: (f32, f32),
+    pub next_scene: Option<Box<dyn Scene>>,
+    pub textscript_vm: TextScriptVM,
+    pub settings: Settings,
+    pub discord_rpc: DiscordRPC,
+    /// Quick save or load requested by the player, handled by the game scene.
+    pub quick_save_action: Option<QuickSaveAction>,
+    /// Set by the debug key, handled by the game scene, see `GameScene::warp_back`.
+    pub warp_back_requested: bool,
+    pub current_mod: Option<ModInfo>,
+    /// Set while playing without a real save (challenges), nothing should be saved to the disk.
+    pub temporary_profile: bool,
+    pub challenge: Option<ChallengeRun>,
+    /// Debug boss rush, only runs in its generated arena.
+    pub boss_rush: Option<BossRush>,
+    /// Rumble requested during the current tick, sent to the gamepads once it's over.
+    pub pending_rumble: Option<Rumble>,
+    /// Profile being written by `<SVP`, the script waits until it's done.
+    pub pending_save: Option<PendingWrite>,
+    pub stats: Stats,
+    pub notifications: Notifications,
+    /// Ticks per second, read by the main loop before every tick. Reset to the setting whenever the scene
+    /// changes, scenes which need a fixed rate set it in `init`.
+    tps: u32,
+    key_old: u16,
+}
+
+impl SharedGameState {
+    /// State before the first scene, the parts depending on the window (scale, canvas size) are set by `Game::new`.
+    pub fn new(settings: Settings, constants: EngineConstants, font: BMFontRenderer, base_path: &str, startup: StartupTimings) -> SharedGameState {
+        let tps = settings.tps();
+        let mut texture_set = TextureSet::new(base_path);
+        texture_set.strict = settings.strict_assets;
+        texture_set.set_memory_cap(settings.texture_memory_cap());
+        // the audio device is opened in Game::start, unless disabled
+        let mut sound_manager = SoundManager::new(&constants);
+        sound_manager.set_sfx_memory_cap(settings.sfx_memory_cap());
+
+        SharedGameState {
+            control_flags: ControlFlags(0),
+            game_flags: bitvec::bitvec![0; 8000],
+            flag_log: VecDeque::with_capacity(FLAG_LOG_SIZE),
+            stage_history: StageHistory::new(),
+            tsc_variables: vec![0; TSC_VARIABLE_COUNT],
+            fade_state: FadeState::Hidden,
+            game_rng: RNG::new(0),
+            effect_rng: EffectRNG::new(SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as i32).unwrap_or(0)),
+            quake_counter: 0,
+            nikumaru_counter: 0,
+            ambient_force: (0, 0),
+            player_pose: None,
+            character: PlayableCharacter::Quote,
+            play_record: None,
+            untracked_profile: UntrackedProfileData::default(),
+            carets: Vec::with_capacity(32),
+            key_state: KeyState(0),
+            key_trigger: KeyState(0),
+            key_bindings: KeyBindings::new(),
+            last_input_device: InputDevice::Keyboard,
+            latched_key_state: None,
+            font,
+            texture_set,
+            base_path: str!(base_path),
+            npc_table: NPCTable::new(),
+            stages: Vec::with_capacity(96),
+            data_fingerprint: 0,
+            loaded_metadata: None,
+            sound_manager,
+            constants,
+            startup,
+            new_npcs: Vec::with_capacity(8),
+            scale: 2.0,
+            god_mode: false,
+            speed_hack: false,
+            screen_size: (0.0, 0.0),
+            canvas_size: (0.0, 0.0),
+            canvas_offset: (0.0, 0.0),
+            next_scene: None,
+            textscript_vm: TextScriptVM::new(),
+            settings,
+            discord_rpc: DiscordRPC::new(),
+            quick_save_action: None,
+            warp_back_requested: false,
+            current_mod: None,
+            temporary_profile: false,
+            challenge: None,
+            boss_rush: None,
+            pending_rumble: None,
+            pending_save: None,
+            stats: Stats::load(None),
+            notifications: Notifications::new(),
+            tps,
+            key_old: 0,
+        }
+    }
+
+    /// Vanilla state without a window or any data files, tests fill in what they need.
+    #[cfg(test)]
+    pub fn for_tests() -> SharedGameState {
+        SharedGameState::new(Settings::default(), EngineConstants::defaults(), BMFontRenderer::empty(), "/", StartupTimings::default())
+    }
+
+    pub fn update_key_trigger(&mut self) {
+        let mut trigger = self.key_state.0 ^ self.key_old;
+        trigger &= self.key_state.0;
+        self.key_old = self.key_state.0;
+        self.key_trigger = KeyState(trigger);
+
+        // pressing a direction while holding the opposite one doesn't count, menus stay put like the player does
+        if resolve_movement(self.key_state).is_none() {
+            self.key_trigger.set_left(false);
+            self.key_trigger.set_right(false);
+        }
+        if resolve_vertical(self.key_state).is_none() {
+            self.key_trigger.set_up(false);
+            self.key_trigger.set_down(false);
+        }
+    }
+
+    /// Reads a game flag, the reserved ones come from the engine state.
+    pub fn get_flag(&self, flag: usize) -> bool {
+        if self.settings.allow_mod_tsc() && RESERVED_FLAGS.contains(&flag) {
+            return match flag {
+                FLAG_PLAYING_AS_CURLY => self.playing_as_curly(),
+                _ => false,
+            };
+        }
+
+        self.game_flags.get(flag).is_some_and(|b| *b)
+    }
+
+    /// Sets a game flag and remembers the change in `flag_log`.
+    /// Writes to the reserved flags are dropped, with a notification so mod authors notice.
+    pub fn set_flag(&mut self, flag: usize, value: bool) {
+        if self.settings.allow_mod_tsc() && RESERVED_FLAGS.contains(&flag) {
+            let message = format!("Flag {} is reserved by the engine and can't be set.", flag);
+            warn!("{}", message);
+            self.notifications.push(message);
+            return;
+        }
+
+        self.game_flags.set(flag, value);
+
+        if self.flag_log.len() >= FLAG_LOG_SIZE {
+            self.flag_log.pop_front();
+        }
+        self.flag_log.push_back((flag, value));
+    }
+
+    /// Requests a gamepad rumble, overlapping requests take the max intensity.
+    pub fn rumble(&mut self, low_freq: f32, high_freq: f32, duration_ms: u64) {
+        if !self.settings.rumble {
+            return;
+        }
+
+        let intensity = self.settings.rumble_intensity;
+        let rumble = Rumble::new(low_freq * intensity, high_freq * intensity, Duration::from_millis(duration_ms));
+        self.pending_rumble = Some(match self.pending_rumble {
+            Some(pending) => pending.merge(rumble),
+            None => rumble,
+        });
+    }
+
+    /// Resets the state a new game starts with, setting up the scene is up to the caller.
+    pub fn reset_game_state(&mut self) {
+        self.game_flags = bitvec::bitvec![0; 8000];
+        self.flag_log.clear();
+        self.stage_history.clear();
+        self.tsc_variables = vec![0; TSC_VARIABLE_COUNT];
+        self.carets.clear();
+        self.quake_counter = 0;
+        self.nikumaru_counter = 0;
+        self.player_pose = None;
+        self.character = PlayableCharacter::Quote;
+        self.play_record = Some(PlayRecord::default());
+        self.untracked_profile = UntrackedProfileData::default();
+        self.temporary_profile = false;
+        self.challenge = None;
+        self.boss_rush = None;
+    }
+
+    /// Writes the statistics of the current game or mod in the background, at save points and scene changes.
+    pub fn flush_stats(&mut self) {
+        let mod_id = self.current_mod.as_ref().map(|m| m.id.as_str());
+        self.stats.flush(mod_id);
+    }
+
+    pub fn playing_as_curly(&self) -> bool {
+        self.character == PlayableCharacter::Curly
+    }
+
+    /// Custom TSC opcodes enabled by the current mod, none in the vanilla compatibility mode.
+    pub fn tsc_extensions(&self) -> TextScriptExtensions {
+        if self.settings.allow_mod_tsc() {
+            self.textscript_vm.extensions
+        } else {
+            TextScriptExtensions(0)
+        }
+    }
+
+    /// CS+ commands (<ACH, <2MV, <INJ) and stacking items, there on CS+ data or with the `csplus` extension.
+    pub fn csplus_tsc(&self) -> bool {
+        self.constants.is_cs_plus || self.tsc_extensions().csplus()
+    }
+
+    /// Transition for a fade, the settings override goes over the one asked for by a script,
+    /// which goes over the default from the constants.
+    pub fn transition_type(&self, requested: Option<TransitionType>) -> TransitionType {
+        self.settings.transition().or(requested).unwrap_or(self.constants.transition)
+    }
+
+    /// Drops what the game scene left behind in the shared state, for scripts leaving it for good (<ESC, <INI, <LDP).
+    /// Everything owned by the scene itself (NPCs, bullets, bosses, stage effects) goes away with it.
+    pub fn teardown_game(&mut self) {
+        self.carets.clear();
+        self.new_npcs.clear();
+        self.quake_counter = 0;
+        self.textscript_vm.reset();
+        self.textscript_vm.suspend = true;
+        self.texture_set.unload_stage_textures();
+        // todo: stop <SSS/<SPS looping sounds once they're implemented
+    }
+
+    /// Area of the canvas the HUD is anchored to, the middle `HUD_MAX_ASPECT` part of it on very wide windows.
+    pub fn hud_rect(&self) -> Rect<f32> {
+        let (width, height) = self.canvas_size;
+        let hud_width = width.min((height * HUD_MAX_ASPECT).floor());
+        let left = ((width - hud_width) / 2.0).floor();
+
+        Rect::new(left, 0.0, left + hud_width, height)
+    }
+
+    pub fn tick_carets(&mut self) {
+        for caret in self.carets.iter_mut() {
+            caret.tick(&self.effect_rng, &self.constants);
+        }
+
+        self.carets.retain(|c| !c.is_dead());
+    }
+
+    pub fn create_caret(&mut self, x: isize, y: isize, ctype: CaretType, direct: Direction) {
+        self.carets.push(Caret::new(x, y, ctype, direct, &self.constants));
+    }
+
+    /// Changes how many ticks run per second, every duration is in ticks so the game just speeds up or slows down.
+    pub fn set_tps(&mut self, tps: u32) {
+        self.tps = tps.max(1);
+    }
+
+    /// Goes back to the tick rate from the settings, unless the scene pins its own.
+    pub fn reset_tps(&mut self, scene: &dyn Scene) {
+        let tps = scene.tps(self).unwrap_or_else(|| self.settings.tps());
+        self.set_tps(tps);
+    }
+
+    pub fn tps(&self) -> u32 {
+        self.tps
+    }
+
+    pub fn tick_duration(&self) -> Duration {
+        Duration::from_secs(1) / self.tps
+    }
+
+    pub fn set_speed_hack(&mut self, toggle: bool) {
+        self.speed_hack = toggle;
+
+        if let Err(err) = self.sound_manager.set_speed(if toggle { 2.0 } else { 1.0 }) {
+            log::error!("Error while sending a message to sound manager: {}", err);
+        }
+    }
+}
+
+impl Game {
+    /// `startup` comes with the time the window took, the rest of the phases are timed along the way.
+    pub fn new(ctx: &mut Context, settings: Settings, mut startup: StartupTimings) -> GameResult<Game> {
+        let started = Instant::now();
+        ctx.filesystem.mount_vfs(Box::new(BuiltinFS::new()));
+
+        if let Err(e) = graphics::set_window_icon(ctx, Some("/builtin/icon.png")) {
+            warn!("Cannot set window icon: {}", e);
+        }
+        restore_window_position(ctx, &settings.window);
+
+        let scale = 2.0;
+        let screen_size = graphics::drawable_size(ctx);
+        let (canvas_size, canvas_offset) = GameCanvas::layout(screen_size, scale, settings.presentation);
+        let mut constants = EngineConstants::defaults();
+        let mut base_path = "/";
+
+        if filesystem::exists(ctx, "/base/Nicalis.bmp") {
+            info!("Cave Story+ (PC) data files detected.");
+            constants.apply_csplus_patches();
+            base_path = "/base/";
+        } else if filesystem::exists(ctx, "/base/lighting.tbl") {
+            info!("Cave Story+ (Switch) data files detected.");
+            constants.apply_csplus_patches();
+            constants.apply_csplus_nx_patches();
+            base_path = "/base/";
+        } else if filesystem::exists(ctx, "/mrmap.bin") {
+            info!("CSE2E data files detected.");
+        } else if filesystem::exists(ctx, "/stage.dat") {
+            info!("NXEngine-evo data files detected.");
+        }
+        let data_report = data_check::validate(ctx, base_path, &constants);
+        data_report.log();
+
+        let mut font = BMFontRenderer::load(base_path, &constants.font_path, ctx)?;
+        //.or_else(|| Some(BMFontRenderer::load("/", "builtin/builtin_font.fnt", ctx)?))
+        //.ok_or_else(|| ResourceLoadError(str!("Cannot load game font.")))?;
+        font.load_fallback(&constants, ctx);
+
+        startup.constants = started.elapsed();
+        let presentation = settings.presentation;
+        let pacer = FramePacer::new(settings.vsync);
+
+        let mut state = SharedGameState::new(settings, constants, font, base_path, startup);
+        state.scale = scale;
+        state.screen_size = screen_size;
+        state.canvas_size = canvas_size;
+        state.canvas_offset = canvas_offset;
+
+        let s = Game {
+            scene: None,
+            canvas: GameCanvas::new(),
+            ui: UI::new(ctx)?,
+            input_buffer: InputBuffer::new(),
+            input_display: InputDisplay::new(),
+            focused: true,
+            next_tick: Instant::now(),
+            last_frame: Instant::now(),
+            pacer,
+            input_latency: None,
+            presentation,
+            data_report: Some(data_report),
+            state,
+        };
+
+        Ok(s)
+    }
+
+    /// Picks the first scene, has to be called once before the first frame.
+    pub fn start(&mut self, ctx: &mut Context, options: &LaunchOptions) -> GameResult {
+        self.state.textscript_vm.trace_enabled = options.trace_tsc;
+
+        let audio_start = Instant::now();
+        if options.no_audio {
+            info!("Audio disabled with --no-audio.");
+        } else {
+            self.state.sound_manager.open_device(ctx, &self.state.constants);
+        }
+        self.state.startup.audio = audio_start.elapsed();
+
+        if let Some(path) = &options.repro {
+            let scenario = Scenario::load(path)?;
+            self.state.next_scene = Some(Box::new(LoadingScene::with_repro(scenario, options.dump.clone())));
+        } else if mods::scan_mods(ctx).is_empty() {
+            self.state.next_scene = Some(Box::new(LoadingScene::new()));
+        } else {
+            self.state.next_scene = Some(Box::new(ModMenuScene::new(ctx)));
+        }
+
+        if let Some(report) = self.data_report.take() {
+            if report.needs_attention() {
+                if let Some(next_scene) = self.state.next_scene.take() {
+                    self.state.next_scene = Some(Box::new(DataReportScene::new(&report, next_scene)));
+                }
+            }
+        }
+
+        self.next_tick = Instant::now();
+        self.last_frame = self.next_tick;
+        Ok(())
+    }
+
+    pub fn handle_event(&mut self, ctx: &mut Context, event: Event) {
+        ctx.process_event(&event);
+        self.ui.handle_events(ctx, &event);
+
+        if let Event::WindowEvent { event, .. } = event {
+            match event {
+                WindowEvent::CloseRequested => event::quit(ctx),
+                WindowEvent::Focused(focused) => {
+                    self.focused = focused;
+                    if !focused {
+                        gamepad::stop_rumble(ctx);
+                    }
+                }
+                WindowEvent::Moved(position) => {
+                    self.state.settings.window.position = Some((position.x, position.y));
+                }
+                WindowEvent::Resized(size) => {
+                    self.state.settings.window.size = Some((size.width, size.height));
+
+                    if let Err(e) = self.handle_resize(ctx) {
+                        error!("Error while resizing the canvas: {}", e);
+                    }
+                }
+                WindowEvent::HiDpiFactorChanged(_) => {
+                    if let Err(e) = self.handle_resize(ctx) {
+                        error!("Error while resizing the canvas: {}", e);
+                    }
+                }
+                WindowEvent::KeyboardInput {
+                    input:
+                    KeyboardInput {
+                        state: el_state,
+                        virtual_keycode: Some(keycode),
+                        modifiers,
+                        ..
+                    },
+                    ..
+                } => {
+                    match el_state {
+                        ElementState::Pressed => {
+                            let repeat = keyboard::is_key_repeated(ctx);
+                            self.key_down_event(ctx, keycode, modifiers.into(), repeat);
+                        }
+                        ElementState::Released => {
+                            self.key_up_event(ctx, keycode, modifiers.into());
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    pub fn poll_gamepads(&mut self, ctx: &mut Context) {
+        while let Some(gamepad::gilrs::Event { id, event, .. }) = ctx.gamepad_context.next_event() {
+            match event {
+                gamepad::gilrs::EventType::ButtonPressed(button, _) => self.gamepad_button_down_event(ctx, button, GamepadId(id)),
+                gamepad::gilrs::EventType::ButtonReleased(button, _) => self.gamepad_button_up_event(ctx, button, GamepadId(id)),
+                _ => {}
+            }
+        }
+    }
+
+    /// Ticks are paused and frames are drawn less often while in the background.
+    fn is_power_saving(&self) -> bool {
+        !self.focused && self.state.settings.power_saving
+    }
+
+    /// The window was created without vsync even though the settings ask for it.
+    pub fn vsync_unavailable(&mut self) {
+        self.pacer.vsync_unavailable();
+    }
+
+    /// When `run_frame` has something to do next, the event loop can sleep until then.
+    fn next_deadline(&self) -> Instant {
+        if self.is_power_saving() {
+            self.last_frame + UNFOCUSED_FRAME_DURATION
+        } else {
+            // vsync blocks in the swap instead
+            self.pacer.next_frame(self.state.settings.frame_limit).unwrap_or_else(Instant::now)
+        }
+    }
+
+    /// Sleeps until `run_frame` has something to do, without oversleeping the deadline.
+    pub fn wait_for_next_frame(&mut self) {
+        let deadline = self.next_deadline();
+        self.pacer.sleep_until(deadline);
+    }
+
+    /// Runs the ticks which are due, draws a frame when one is due and switches scenes if one was requested.
+    /// `poll_input` feeds the pending window and gamepad events to the game, it's called right before every tick
+    /// so the input isn't up to a whole tick stale by the time the tick runs.
+    pub fn run_frame<F: FnMut(&mut Game, &mut Context)>(&mut self, ctx: &mut Context, mut poll_input: F) -> GameResult {
+        poll_input(self, ctx);
+        let now = Instant::now();
+
+        if self.is_power_saving() {
+            if now < self.last_frame + UNFOCUSED_FRAME_DURATION {
+                return Ok(());
+            }
+
+            // paused, the time spent in the background isn't caught up on
+            self.next_tick = now + self.state.tick_duration();
+            return self.draw_frame(ctx, now, Duration::from_secs(0), poll_input);
+        }
+
+        // frames are paced by vsync or the frame limiter, the ticks keep their own fixed rate whatever it is
+        let frame_limit = self.state.settings.frame_limit;
+        if self.pacer.next_frame(frame_limit).is_some_and(|next| now < next) && self.state.next_scene.is_none() {
+            return Ok(());
+        }
+        self.pacer.begin_frame(now, frame_limit);
+
+        // fixed timestep, catching up (up to a limit) after hitches
+        let mut ticks = 0;
+        let mut tick_time = Duration::from_secs(0);
+        while self.next_tick <= now && ticks < MAX_CATCHUP_TICKS {
+            if ticks > 0 {
+                poll_input(self, ctx);
+            }
+
+            self.next_tick += self.state.tick_duration();
+            let tick_start = Instant::now();
+            if let Err(err) = self.update(ctx, self.next_tick) {
+                // the scene is in an unknown state now, don't keep ticking it
+                self.state.next_scene = Some(Box::new(ErrorScene::new(err)));
+            }
+            tick_time += tick_start.elapsed();
+            ticks += 1;
+
+            // the new scene has to be initialized first
+            if self.state.next_scene.is_some() { break; }
+        }
+
+        if ticks == MAX_CATCHUP_TICKS && self.next_tick <= now {
+            self.next_tick = now + self.state.tick_duration();
+        }
+
+        // todo: frames drawn between two ticks are the same until they're interpolated
+        self.draw_frame(ctx, now, tick_time, poll_input)
+    }
+
+    /// Drawing the last frame took more than half of a tick, late latching only pays off then.
+    fn is_gpu_bound(&self) -> bool {
+        self.ui.components.perf_hud.last().draw_ms > self.state.tick_duration().as_secs_f32() * 1000.0 / 2.0
+    }
+
+    fn draw_frame<F: FnMut(&mut Game, &mut Context)>(&mut self, ctx: &mut Context, now: Instant, tick_time: Duration,
+                                                    mut poll_input: F) -> GameResult {
+        if self.state.settings.presentation != self.presentation {
+            self.handle_resize(ctx)?;
+        }
+
+        // keys pressed while the ticks ran show up in this frame instead of the next one, the ticks still get them later
+        self.state.latched_key_state = if self.state.settings.late_latch && self.is_gpu_bound() {
+            poll_input(self, ctx);
+            Some(KeyState(self.input_buffer.peek_state()))
+        } else {
+            None
+        };
+
+        let draw_start = Instant::now();
+        if let Err(err) = self.draw(ctx) {
+            error!("Error while drawing, recreating the renderer: {}", err);
+            self.reset_renderer(ctx)?;
+            self.draw(ctx)?;
+        }
+
+        let present_interval = self.pacer.presented(Instant::now());
+        self.ui.components.perf_hud.set_pacing_mode(self.pacer.mode(self.state.settings.frame_limit));
+
+        self.ui.components.perf_hud.push(FrameTiming {
+            frame_ms: (now - self.last_frame).as_secs_f32() * 1000.0,
+            tick_ms: tick_time.as_secs_f32() * 1000.0,
+            draw_ms: draw_start.elapsed().as_secs_f32() * 1000.0,
+            present_ms: present_interval.as_secs_f32() * 1000.0,
+            input_latency_ms: self.input_latency.take().map(|latency| latency.as_secs_f32() * 1000.0),
+        });
+        self.last_frame = now;
+
+        if self.state.next_scene.is_some() {
+            mem::swap(&mut self.scene, &mut self.state.next_scene);
+            self.state.next_scene = None;
+            // the game scene sets it again once it's initialized
+            log_sink::set_stage(None);
+            self.state.flush_stats();
+            // menus and result screens act as a pause
+            gamepad::stop_rumble(ctx);
+            self.state.reset_tps(self.scene.as_ref().unwrap().as_ref());
+
+            if let Err(err) = self.scene.as_mut().unwrap().init(&mut self.state, ctx) {
+                self.scene = Some(Box::new(ErrorScene::new(err)));
+                self.scene.as_mut().unwrap().init(&mut self.state, ctx)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Has to be called once the event loop is over.
+    pub fn shutdown(&mut self) {
+        let mod_id = self.state.current_mod.as_ref().map(|m| m.id.as_str());
+        self.state.stats.flush_on_exit(mod_id);
+
+        if let Err(e) = self.state.settings.save() {
+            error!("Error saving settings: {}", e);
+        }
+    }
+
+    /// Drops the canvas and the textures, so they're created again on the next frame.
+    fn reset_renderer(&mut self, ctx: &mut Context) -> GameResult {
+        graphics::set_canvas(ctx, None);
+        self.canvas = GameCanvas::new();
+        self.state.texture_set.clear();
+        self.handle_resize(ctx)
+    }
+
+    /// Recalculates the canvas after the window has been resized or its DPI has changed.
+    fn handle_resize(&mut self, ctx: &mut Context) -> GameResult {
+        self.state.screen_size = graphics::drawable_size(ctx);
+        let (canvas_size, canvas_offset) = GameCanvas::layout(self.state.screen_size, self.state.scale, self.state.settings.presentation);
+        self.state.canvas_size = canvas_size;
+        self.state.canvas_offset = canvas_offset;
+        self.presentation = self.state.settings.presentation;
+        graphics::set_screen_coordinates(ctx, graphics::Rect::new(0.0, 0.0, self.state.screen_size.0, self.state.screen_size.1))?;
+
+        Ok(())
+    }
+
+    /// Runs a single game tick, using input which happened before `tick_end`.
+    fn update(&mut self, ctx: &mut Context, tick_end: Instant) -> GameResult {
+        self.state.key_state = KeyState(self.input_buffer.drain_until(tick_end));
+        self.state.last_input_device = self.input_buffer.last_device();
+        if let Some(time) = self.input_buffer.take_oldest_applied() {
+            let latency = Instant::now().duration_since(time);
+            self.input_latency = Some(self.input_latency.map_or(latency, |longest| longest.max(latency)));
+        }
+
+        log_sink::advance_tick();
+        if let Some(scene) = self.scene.as_mut() {
+            scene.tick(&mut self.state, ctx)?;
+            if self.state.speed_hack {
+                scene.tick(&mut self.state, ctx)?;
+            }
+        }
+
+        // after the scene tick, so replays show the replayed keys
+        self.input_display.tick(self.state.key_state, self.state.key_trigger);
+
+        // only the game scene handles these, don't let them linger until the next one
+        self.state.quick_save_action = None;
+        self.state.warp_back_requested = false;
+
+        if let Some(rumble) = self.state.pending_rumble.take() {
+            if self.focused {
+                gamepad::rumble(ctx, rumble);
+            }
+        }
+        Ok(())
+    }
+
+    fn draw(&mut self, ctx: &mut Context) -> GameResult {
+        graphics::clear(ctx, [0.0, 0.0, 0.0, 1.0].into());
+        DrawSpace::Window.enter(&self.state, ctx)?;
+
+        if let Some(scene) = self.scene.as_mut() {
+            // drawn at the internal resolution and scaled up at once, so tiles don't get seams at odd scales
+            self.canvas.begin(&self.state, ctx)?;
+            graphics::clear(ctx, [0.0, 0.0, 0.0, 1.0].into());
+            scene.draw(&mut self.state, ctx)?;
+            self.input_display.draw(&mut self.state, ctx)?;
+            self.canvas.finish(&self.state, ctx)?;
+
+            for (name, error) in self.state.texture_set.take_missing() {
+                self.state.notifications.report_missing_asset(&name, &error);
+            }
+
+            // the debug UI stays at the window resolution
+            DrawSpace::Window.enter(&self.state, ctx)?;
+            self.ui.draw(&mut self.state, ctx, scene)?;
+        }
+
+        graphics::present(ctx)?;
+        Ok(())
+    }
+
+    fn key_down_event(&mut self, _ctx: &mut Context, key_code: KeyCode, _key_mod: KeyMods, repeat: bool) {
+        if repeat { return; }
+
+        let state = &mut self.state;
+        match key_code {
+            KeyCode::F3 => { self.ui.components.perf_hud.visible = !self.ui.components.perf_hud.visible }
+            KeyCode::F4 => { state.settings.input_display = !state.settings.input_display }
+            KeyCode::F11 => { state.god_mode = !state.god_mode }
+            KeyCode::F12 => { state.set_speed_hack(!state.speed_hack) }
+            KeyCode::F5 => { state.quick_save_action = Some(QuickSaveAction::Save) }
+            KeyCode::F9 => { state.quick_save_action = Some(QuickSaveAction::Load) }
+            _ if state.key_bindings.warp_back == Some(key_code) => { state.warp_back_requested = true }
+            _ => {
+                let mask = self.state.key_bindings.key_mask(key_code);
+                if mask != 0 {
+                    self.input_buffer.push(Instant::now(), InputDevice::Keyboard, mask, true);
+                }
+            }
+        }
+    }
+
+    fn key_up_event(&mut self, _ctx: &mut Context, key_code: KeyCode, _key_mod: KeyMods) {
+        let mask = self.state.key_bindings.key_mask(key_code);
+        if mask != 0 {
+            self.input_buffer.push(Instant::now(), InputDevice::Keyboard, mask, false);
+        }
+    }
+
+    fn gamepad_button_down_event(&mut self, _ctx: &mut Context, button: Button, id: GamepadId) {
+        let mask = self.state.key_bindings.button_mask(button);
+        if mask != 0 {
+            self.input_buffer.push(Instant::now(), InputDevice::Gamepad(id.0.into()), mask, true);
+        }
+    }
+
+    fn gamepad_button_up_event(&mut self, _ctx: &mut Context, button: Button, id: GamepadId) {
+        let mask = self.state.key_bindings.button_mask(button);
+        if mask != 0 {
+            self.input_buffer.push(Instant::now(), InputDevice::Gamepad(id.0.into()), mask, false);
+        }
+    }
+}
+
+/// Moves the window to where it was last time, as long as that's still (mostly) on one of the monitors.
+fn restore_window_position(ctx: &Context, settings: &WindowSettings) {
+    let window = graphics::window(ctx);
+    let (x, y) = match settings.position {
+        Some(position) => position,
+        None => { return; }
+    };
+    let (width, height) = settings.size.unwrap_or((854.0, 480.0));
+
+    // require the title bar area to be reachable
+    let visible = window.get_available_monitors().any(|monitor| {
+        let factor = monitor.get_hidpi_factor();
+        let mon_pos = monitor.get_position().to_logical(factor);
+        let mon_size = monitor.get_dimensions().to_logical(factor);
+
+        x + width - 64.0 >= mon_pos.x && x + 64.0 <= mon_pos.x + mon_size.width
+            && y >= mon_pos.y && y + 32.0 <= mon_pos.y + mon_size.height
+    });
+
+    if visible {
+        window.set_position(LogicalPosition::new(x, y));
+        return;
+    }
+
+    // clamp to the primary monitor instead
+    let monitor = window.get_primary_monitor();
+    let factor = monitor.get_hidpi_factor();
+    let mon_pos = monitor.get_position().to_logical(factor);
+    let mon_size = monitor.get_dimensions().to_logical(factor);
+
+    let x = x.min(mon_pos.x + mon_size.width - width).max(mon_pos.x);
+    let y = y.min(mon_pos.y + mon_size.height - height).max(mon_pos.y);
+    info!("Saved window position is off-screen, moving to ({}, {})", x, y);
+    window.set_position(LogicalPosition::new(x, y));
+}
@@ -0,0 +1,166 @@
+/// Ticks the lost HP stays visible for before the chaser starts catching up.
+const CHASER_DELAY: u16 = 30;
+/// How far the boss bar slides down before it's gone, in pixels.
+pub const BOSS_BAR_SLIDE: u16 = 24;
+
+/// HP bar with the "damage chaser", a segment showing the lost HP for a while after a hit.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LifeBar {
+    pub life: u16,
+    pub max_life: u16,
+    /// HP shown by the chaser segment, never below `life`.
+    pub chaser: u16,
+    counter: u16,
+}
+
+impl LifeBar {
+    pub fn new(life: u16, max_life: u16) -> LifeBar {
+        LifeBar {
+            life,
+            max_life,
+            chaser: life,
+            counter: 0,
+        }
+    }
+
+    /// Has to be called every tick with the current HP, healing shows up at once.
+    pub fn tick(&mut self, life: u16) {
+        self.life = life;
+
+        if self.chaser < life {
+            self.chaser = life;
+        }
+
+        if self.chaser > life {
+            self.counter += 1;
+            if self.counter > CHASER_DELAY {
+                self.chaser -= 1;
+            }
+        } else {
+            self.counter = 0;
+        }
+    }
+
+    /// Width of a segment showing `value` on a bar `width` pixels long.
+    pub fn width(&self, value: u16, width: usize) -> usize {
+        if self.max_life == 0 {
+            return 0;
+        }
+
+        value.min(self.max_life) as usize * width / self.max_life as usize
+    }
+}
+
+/// What the boss HP bar follows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[allow(clippy::upper_case_acronyms)]
+pub enum BossTarget {
+    /// The stage's multi-part boss, picked by the boss number of the stage, attached with `<BSL0000`.
+    StageBoss,
+    /// NPC ID.
+    NPC(u16),
+}
+
+/// Boss HP bar shown by `<BSL`, slides out once the target dies.
+#[derive(Clone, Copy, Debug)]
+pub struct BossLifeBar {
+    pub target: Option<BossTarget>,
+    pub bar: LifeBar,
+    /// How far the bar is slid down, `BOSS_BAR_SLIDE` once it's gone.
+    pub offset: u16,
+}
+
+impl BossLifeBar {
+    pub fn new() -> BossLifeBar {
+        BossLifeBar {
+            target: None,
+            bar: LifeBar::default(),
+            offset: BOSS_BAR_SLIDE,
+        }
+    }
+
+    /// Starts following the target, `life` is `None` if it's already dead or gone, then the bar only slides out.
+    pub fn attach(&mut self, target: BossTarget, life: Option<u16>) {
+        match life {
+            Some(life) => {
+                self.target = Some(target);
+                self.bar = LifeBar::new(life, life);
+                self.offset = 0;
+            }
+            None => {
+                self.target = None;
+            }
+        }
+    }
+
+    /// Has to be called every tick with the current HP of the target, `None` if it died.
+    pub fn tick(&mut self, life: Option<u16>) {
+        match (self.target, life) {
+            (Some(_), Some(life)) => {
+                self.bar.tick(life);
+            }
+            (Some(_), None) => {
+                self.bar.tick(0);
+                self.target = None;
+            }
+            _ => {}
+        }
+
+        if self.target.is_none() && self.offset < BOSS_BAR_SLIDE {
+            self.offset = (self.offset + 2).min(BOSS_BAR_SLIDE);
+        }
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.offset < BOSS_BAR_SLIDE
+    }
+}
+
+#[test]
+fn test_life_bar_chaser() {
+    let mut bar = LifeBar::new(10, 10);
+    bar.tick(7);
+    assert_eq!((bar.life, bar.chaser), (7, 10));
+
+    // stays for the delay, then drains a point per tick
+    for _ in 1..CHASER_DELAY {
+        bar.tick(7);
+    }
+    assert_eq!(bar.chaser, 10);
+    bar.tick(7);
+    assert_eq!(bar.chaser, 9);
+    bar.tick(7);
+    bar.tick(7);
+    assert_eq!(bar.chaser, 7);
+
+    // healing catches up at once
+    bar.tick(10);
+    assert_eq!(bar.chaser, 10);
+    assert_eq!(bar.width(5, 40), 20);
+}
+
+#[test]
+fn test_boss_life_bar() {
+    let mut boss = BossLifeBar::new();
+    assert!(!boss.is_visible());
+
+    // <BSL on a dead target doesn't show anything
+    boss.attach(BossTarget::NPC(170), None);
+    boss.tick(None);
+    assert!(!boss.is_visible());
+
+    boss.attach(BossTarget::StageBoss, Some(300));
+    assert!(boss.is_visible());
+    boss.tick(Some(280));
+    assert_eq!((boss.bar.life, boss.bar.chaser, boss.offset), (280, 300, 0));
+
+    // killed, slides out over a few ticks
+    boss.tick(None);
+    assert_eq!(boss.bar.life, 0);
+    assert!(boss.is_visible());
+    for _ in 0..BOSS_BAR_SLIDE / 2 {
+        boss.tick(None);
+    }
+    assert!(!boss.is_visible());
+    assert_eq!(boss.target, None);
+}
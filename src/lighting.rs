@@ -0,0 +1,190 @@
+use crate::bullet::BulletManager;
+use crate::common;
+use crate::frame::{display_rect, Frame};
+use crate::ggez::{Context, GameResult, graphics};
+use crate::ggez::conf::NumSamples;
+use crate::ggez::graphics::{BlendMode, Canvas, Color, DrawParam, Drawable, FilterMode, Image, Rect};
+use crate::ggez::nalgebra::{Point2, Vector2};
+use crate::npc::NPCMap;
+use crate::player::Player;
+use crate::SharedGameState;
+
+const GRADIENT_SIZE: u16 = 64;
+
+pub struct LightSource {
+    /// Position in world coordinates.
+    pub x: isize,
+    pub y: isize,
+    /// Radius in pixels.
+    pub radius: f32,
+    pub color: [f32; 3],
+}
+
+/// Entities lit up by the light manager.
+pub struct LightEmitters<'a> {
+    pub player: &'a Player,
+    pub npc_map: &'a NPCMap,
+    pub bullet_manager: &'a BulletManager,
+}
+
+/// Darkens the stage except for the areas around light emitting entities.
+/// Lights are collected every tick and accumulated into an offscreen light map,
+/// which is then multiplied over the scene.
+pub struct LightManager {
+    lights: Vec<LightSource>,
+    darkness: f32,
+    canvas: Option<Canvas>,
+    gradient: Option<Image>,
+}
+
+impl LightManager {
+    pub fn new() -> Self {
+        Self {
+            lights: Vec::new(),
+            darkness: 0.0,
+            canvas: None,
+            gradient: None,
+        }
+    }
+
+    pub fn add_light(&mut self, x: isize, y: isize, radius: f32, color: [f32; 3]) {
+        self.lights.push(LightSource { x, y, radius, color });
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.darkness > 0.0
+    }
+
+    pub fn tick(&mut self, map_name: &str, emitters: LightEmitters, frame: &Frame, state: &SharedGameState,
+                ctx: &mut Context) -> GameResult {
+        self.lights.clear();
+        self.darkness = if state.settings.allow_lighting() {
+            state.constants.lighting.stage_darkness.get(map_name).copied().unwrap_or(0.0)
+        } else {
+            0.0
+        };
+
+        if !self.is_enabled() {
+            return Ok(());
+        }
+
+        self.create_targets(state, ctx)?;
+
+        // lights off the screen don't reach it
+        let visible = |x: isize, y: isize, radius: f32| {
+            let extent = (radius * 512.0) as usize;
+            frame.is_visible(state.canvas_size, &display_rect(x, y, &common::Rect::new(extent, extent, extent, extent)))
+        };
+
+        let LightEmitters { player, npc_map, bullet_manager } = emitters;
+        if !player.cond.hidden() {
+            self.add_light(player.x, player.y, 72.0, [1.0, 0.95, 0.8]);
+        }
+
+        for bullet in bullet_manager.bullets.iter() {
+            // fireball
+            if (7..=9).contains(&bullet.btype) && visible(bullet.x, bullet.y, 32.0) {
+                self.add_light(bullet.x, bullet.y, 32.0, [1.0, 0.6, 0.3]);
+            }
+        }
+
+        for npc_cell in npc_map.npcs.values() {
+            let npc = npc_cell.borrow();
+            if !npc.cond.alive() {
+                continue;
+            }
+
+            if let Some((_, radius)) = state.constants.lighting.npc_lights.iter().find(|(t, _)| *t == npc.npc_type) {
+                if visible(npc.x, npc.y, *radius) {
+                    self.add_light(npc.x, npc.y, *radius, [0.8, 0.9, 1.0]);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn create_targets(&mut self, state: &SharedGameState, ctx: &mut Context) -> GameResult {
+        let width = state.canvas_size.0.ceil().max(1.0) as u16;
+        let height = state.canvas_size.1.ceil().max(1.0) as u16;
+
+        let recreate = match self.canvas.as_ref() {
+            Some(canvas) => canvas.image().width() != width || canvas.image().height() != height,
+            None => true,
+        };
+
+        if recreate {
+            let mut canvas = Canvas::new(ctx, width, height, NumSamples::One)?;
+            canvas.set_filter(FilterMode::Linear);
+            canvas.set_blend_mode(Some(BlendMode::Multiply));
+            self.canvas = Some(canvas);
+        }
+
+        if self.gradient.is_none() {
+            let size = GRADIENT_SIZE as usize;
+            let mut pixels = Vec::with_capacity(size * size * 4);
+            let center = size as f32 / 2.0;
+
+            for y in 0..size {
+                for x in 0..size {
+                    let dx = (x as f32 + 0.5 - center) / center;
+                    let dy = (y as f32 + 0.5 - center) / center;
+                    let falloff = (1.0 - (dx * dx + dy * dy).sqrt()).max(0.0);
+
+                    pixels.extend_from_slice(&[255, 255, 255, (falloff * falloff * 255.0) as u8]);
+                }
+            }
+
+            let mut gradient = Image::from_rgba8(ctx, GRADIENT_SIZE, GRADIENT_SIZE, &pixels)?;
+            gradient.set_filter(FilterMode::Linear);
+            gradient.set_blend_mode(Some(BlendMode::Add));
+            self.gradient = Some(gradient);
+        }
+
+        Ok(())
+    }
+
+    pub fn draw(&self, ctx: &mut Context, frame: &Frame) -> GameResult {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+
+        let (canvas, gradient) = match (self.canvas.as_ref(), self.gradient.as_ref()) {
+            (Some(canvas), Some(gradient)) => (canvas, gradient),
+            _ => { return Ok(()); }
+        };
+
+        let saved_canvas = graphics::save_canvas(ctx);
+        let saved_coords = graphics::screen_coordinates(ctx);
+
+        let (width, height) = (canvas.image().width() as f32, canvas.image().height() as f32);
+        graphics::set_canvas(ctx, Some(canvas));
+        graphics::set_screen_coordinates(ctx, Rect::new(0.0, 0.0, width, height))?;
+
+        let ambient = 1.0 - self.darkness;
+        graphics::clear(ctx, Color::new(ambient, ambient, ambient, 1.0));
+
+        for light in self.lights.iter() {
+            let x = (light.x - frame.x) as f32 / 512.0;
+            let y = (light.y - frame.y) as f32 / 512.0;
+
+            if x + light.radius < 0.0 || y + light.radius < 0.0 || x - light.radius > width || y - light.radius > height {
+                continue;
+            }
+
+            let scale = light.radius * 2.0 / GRADIENT_SIZE as f32;
+            let param = DrawParam::new()
+                .dest(Point2::new(x - light.radius, y - light.radius))
+                .scale(Vector2::new(scale, scale))
+                .color(Color::new(light.color[0], light.color[1], light.color[2], 1.0));
+
+            graphics::draw(ctx, gradient, param)?;
+        }
+
+        graphics::restore_canvas(ctx, saved_canvas);
+        graphics::set_screen_coordinates(ctx, saved_coords)?;
+        graphics::draw(ctx, canvas, DrawParam::new())?;
+
+        Ok(())
+    }
+}
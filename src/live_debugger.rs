@@ -1,21 +1,124 @@
-use imgui::{CollapsingHeader, Condition, im_str, ImStr, ImString, Window};
+use imgui::{ChildWindow, CollapsingHeader, Condition, im_str, Image, ImStr, ImString, Slider, StyleVar, TextureId, Window};
 use itertools::Itertools;
+use log::Level;
+use num_traits::FromPrimitive;
+use strum::IntoEnumIterator;
 
-use crate::ggez::{Context, GameResult};
+use crate::boss_rush::{BossRush, BossRushConfig, is_supported, VANILLA_BOSSES};
+use crate::caret::CaretType;
+use crate::challenge::format_time;
+use crate::common::Direction;
+use crate::debug_arena::Loadout;
+use crate::ggez::{Context, filesystem, GameResult};
+use crate::log_sink;
+use crate::map::{attribute_name, KNOWN_ATTRIBUTES};
+use crate::replay::{Replay, ReplayMode};
+use crate::repro::Scenario;
+use crate::scene::challenge_menu_scene::ChallengeMenuScene;
 use crate::scene::game_scene::GameScene;
+use crate::scene::mod_menu_scene::ModMenuScene;
+use crate::settings::{CompatMode, Presentation};
 use crate::SharedGameState;
+use crate::sound::SoundManager;
+use crate::stats::{ACHIEVEMENTS, SCRIPTED_ACHIEVEMENT_PREFIX};
+use crate::text_script::EventTrigger;
+use crate::thumbnail::Thumbnails;
+use crate::transition::TransitionType;
+use crate::weapon::{Weapon, WeaponLevel, WeaponType};
+
+/// Weapons which can be picked for the boss rush loadout.
+const LOADOUT_WEAPONS: [WeaponType; 10] = [
+    WeaponType::Snake, WeaponType::PolarStar, WeaponType::Fireball, WeaponType::MachineGun, WeaponType::MissileLauncher,
+    WeaponType::Bubbler, WeaponType::Blade, WeaponType::SuperMissileLauncher, WeaponType::Nemesis, WeaponType::Spur,
+];
+
+/// Boss rush settings, kept between rushes.
+struct BossRushForm {
+    width: i32,
+    height: i32,
+    weapons: [bool; 10],
+    level: i32,
+    /// 0 until the window is opened, then the max life of the player.
+    max_life: i32,
+    /// NPC type, name and whether it's fought.
+    bosses: Vec<(u16, String, bool)>,
+    extra_npc_type: i32,
+}
+
+impl BossRushForm {
+    fn new() -> BossRushForm {
+        BossRushForm {
+            width: 40,
+            height: 16,
+            weapons: [false, true, false, false, false, false, false, false, false, false],
+            level: 1,
+            max_life: 0,
+            bosses: VANILLA_BOSSES.iter().map(|&(npc_type, name)| (npc_type, name.to_owned(), true)).collect(),
+            extra_npc_type: 0,
+        }
+    }
+
+    /// The equipment is the current one, bosses which aren't implemented are left out.
+    fn config(&self, game_scene: &GameScene, state: &SharedGameState) -> BossRushConfig {
+        let level = WeaponLevel::from_i32(self.level.clamp(1, 3)).unwrap_or(WeaponLevel::Level1);
+        let weapons = LOADOUT_WEAPONS.iter().zip(self.weapons.iter())
+            .filter(|&(_, &enabled)| enabled)
+            .map(|(&wtype, _)| {
+                // the rest have no ammo
+                let ammo = match wtype {
+                    WeaponType::MissileLauncher | WeaponType::SuperMissileLauncher => 50,
+                    _ => 0,
+                };
+                Weapon::new(wtype, level, 0, ammo, ammo)
+            })
+            .collect();
+
+        BossRushConfig {
+            base_stage: game_scene.stage_id,
+            width: self.width.max(0) as usize,
+            height: self.height.max(0) as usize,
+            loadout: Loadout {
+                weapons,
+                equip: game_scene.player.equip.0,
+                max_life: Some(self.max_life.clamp(1, 999) as u16),
+            },
+            bosses: self.bosses.iter()
+                .filter(|&&(npc_type, _, selected)| selected && is_supported(npc_type, state))
+                .map(|(npc_type, name, _)| (*npc_type, name.clone()))
+                .collect(),
+        }
+    }
+}
 
 pub struct LiveDebugger {
     map_selector_visible: bool,
     events_visible: bool,
     hacks_visible: bool,
     flags_visible: bool,
+    settings_visible: bool,
+    textures_visible: bool,
+    npcs_visible: bool,
+    sound_test_visible: bool,
+    attributes_visible: bool,
+    stats_visible: bool,
+    boss_rush_visible: bool,
+    log_visible: bool,
+    /// Least severe level shown in the log window.
+    log_level: Level,
     last_stage_id: usize,
     stages: Vec<ImString>,
     selected_stage: i32,
+    /// Thumbnails of the stages picked in the map selector by stage index, uploaded by `UI::draw`.
+    pub map_thumbnails: Thumbnails<(TextureId, [f32; 2])>,
     events: Vec<ImString>,
     event_ids: Vec<u16>,
     selected_event: i32,
+    selected_npc: Option<u16>,
+    songs: Vec<ImString>,
+    selected_song: i32,
+    selected_tile: u8,
+    seek_measure: i32,
+    boss_rush: BossRushForm,
     error: Option<ImString>,
 }
 
@@ -26,12 +129,28 @@ impl LiveDebugger {
             events_visible: false,
             hacks_visible: false,
             flags_visible: false,
+            settings_visible: false,
+            textures_visible: false,
+            npcs_visible: false,
+            sound_test_visible: false,
+            attributes_visible: false,
+            stats_visible: false,
+            boss_rush_visible: false,
+            log_visible: false,
+            log_level: Level::Info,
             last_stage_id: usize::MAX,
             stages: Vec::new(),
             selected_stage: -1,
+            map_thumbnails: Thumbnails::new(),
             events: Vec::new(),
             event_ids: Vec::new(),
             selected_event: -1,
+            selected_npc: None,
+            songs: Vec::new(),
+            selected_song: -1,
+            selected_tile: 0,
+            seek_measure: 0,
+            boss_rush: BossRushForm::new(),
             error: None,
         }
     }
@@ -43,6 +162,13 @@ impl LiveDebugger {
             self.selected_event = -1;
         }
 
+        let recording = game_scene.replay.is_recording();
+        let mut toggle_recording = false;
+        let mut spawn_carets = false;
+        let mut save_scenario = false;
+        let mut load_scenario = false;
+        let mut retry_audio = false;
+
         Window::new(im_str!("Debugger"))
             .position([5.0, 5.0], Condition::FirstUseEver)
             .size([300.0, 120.0], Condition::FirstUseEver)
@@ -63,6 +189,11 @@ impl LiveDebugger {
                     "Booster fuel: ({})", game_scene.player.booster_fuel
                 ));
 
+                // goes into bug reports along with the file
+                if let Some((format, metadata)) = &state.loaded_metadata {
+                    ui.text_wrapped(&ImString::new(format!("Loaded {}: {}", format, metadata)));
+                }
+
                 if ui.button(im_str!("Map Selector"), [0.0, 0.0]) {
                     self.map_selector_visible = !self.map_selector_visible;
                 }
@@ -81,8 +212,128 @@ impl LiveDebugger {
                 if ui.button(im_str!("Flags"), [0.0, 0.0]) {
                     self.flags_visible = !self.flags_visible;
                 }
+
+                ui.same_line(0.0);
+                if ui.button(im_str!("Settings"), [0.0, 0.0]) {
+                    self.settings_visible = !self.settings_visible;
+                    // a headset might have been plugged in since the start
+                    retry_audio = self.settings_visible;
+                }
+
+                ui.same_line(0.0);
+                if ui.button(im_str!("Textures"), [0.0, 0.0]) {
+                    self.textures_visible = !self.textures_visible;
+                }
+
+                ui.same_line(0.0);
+                if ui.button(im_str!("NPCs"), [0.0, 0.0]) {
+                    self.npcs_visible = !self.npcs_visible;
+                }
+
+                ui.same_line(0.0);
+                let token = ui.push_style_var(StyleVar::Alpha(if state.sound_manager.is_available() { 1.0 } else { 0.5 }));
+                if ui.button(im_str!("Sound test"), [0.0, 0.0]) {
+                    self.sound_test_visible = !self.sound_test_visible;
+                }
+                token.pop(ui);
+
+                ui.same_line(0.0);
+                if ui.button(im_str!("Tile attributes"), [0.0, 0.0]) {
+                    self.attributes_visible = !self.attributes_visible;
+                }
+
+                ui.same_line(0.0);
+                if ui.button(im_str!("Statistics"), [0.0, 0.0]) {
+                    self.stats_visible = !self.stats_visible;
+                }
+
+                ui.same_line(0.0);
+                if ui.button(im_str!("Boss rush"), [0.0, 0.0]) {
+                    self.boss_rush_visible = !self.boss_rush_visible;
+                }
+
+                let label = if recording { im_str!("Stop recording") } else { im_str!("Record replay") };
+                if ui.button(label, [0.0, 0.0]) {
+                    toggle_recording = true;
+                }
+
+                ui.same_line(0.0);
+                if ui.button(im_str!("Spawn carets"), [0.0, 0.0]) {
+                    spawn_carets = true;
+                }
+
+                if ui.button(im_str!("Save scenario"), [0.0, 0.0]) {
+                    save_scenario = true;
+                }
+
+                ui.same_line(0.0);
+                if ui.button(im_str!("Load scenario"), [0.0, 0.0]) {
+                    load_scenario = true;
+                }
+
+                if ui.button(im_str!("Log"), [0.0, 0.0]) {
+                    self.log_visible = !self.log_visible;
+                }
             });
 
+        if save_scenario {
+            match Scenario::capture(game_scene, state).save(state) {
+                Ok(path) => {
+                    log::info!("Scenario saved to {:?}", path);
+                    state.notifications.push(format!("Scenario saved to {}", path.to_string_lossy()));
+                }
+                Err(e) => self.error = Some(ImString::new(e.to_string())),
+            }
+        }
+
+        if load_scenario {
+            // the last one saved, scenarios written by hand can be played with --repro
+            let result = Scenario::latest_saved(state)
+                .and_then(|path| {
+                    log::info!("Loading scenario {:?}", path);
+                    Scenario::load(&path)
+                })
+                .and_then(|scenario| scenario.restore(game_scene, state));
+
+            if let Err(e) = result {
+                self.error = Some(ImString::new(e.to_string()));
+            }
+        }
+
+        if spawn_carets {
+            // one of every type above the player, facing left in the upper row and right in the lower one
+            let types = CaretType::iter().filter(|&t| t != CaretType::None).collect_vec();
+            let start_x = game_scene.player.x - (types.len() as isize * 24 * 0x200) / 2;
+
+            for (i, &ctype) in types.iter().enumerate() {
+                let x = start_x + i as isize * 24 * 0x200;
+                state.create_caret(x, game_scene.player.y - 64 * 0x200, ctype, Direction::Left);
+                state.create_caret(x, game_scene.player.y - 32 * 0x200, ctype, Direction::Right);
+            }
+        }
+
+        if toggle_recording {
+            if let Some(replay) = game_scene.replay.finish_recording() {
+                match replay.save(state) {
+                    Ok(path) => log::info!("Replay saved to {:?}", path),
+                    Err(e) => self.error = Some(ImString::new(e.to_string())),
+                }
+            } else {
+                // restarts the current stage from a fresh game, so the replay can be played back from the title screen
+                let seed = state.effect_rng.range(0..=0x7fff);
+                let replay = Replay::new(game_scene.stage_id, game_scene.player.x, game_scene.player.y, 0, seed,
+                                         state.settings.compat_mode, state.character);
+                match replay.create_scene(state, ctx) {
+                    Ok(mut scene) => {
+                        state.temporary_profile = false;
+                        scene.replay = ReplayMode::record(replay);
+                        state.next_scene = Some(Box::new(scene));
+                    }
+                    Err(e) => self.error = Some(ImString::new(e.to_string())),
+                }
+            }
+        }
+
         if self.error.is_some() {
             Window::new(im_str!("Error!"))
                 .resizable(false)
@@ -103,7 +354,7 @@ impl LiveDebugger {
             Window::new(im_str!("Map selector"))
                 .resizable(false)
                 .position([80.0, 80.0], Condition::FirstUseEver)
-                .size([240.0, 280.0], Condition::FirstUseEver)
+                .size([240.0, 420.0], Condition::FirstUseEver)
                 .build(ui, || {
                     if self.stages.is_empty() {
                         for s in state.stages.iter() {
@@ -120,6 +371,16 @@ impl LiveDebugger {
                     ui.push_item_width(-1.0);
                     ui.list_box(im_str!(""), &mut self.selected_stage, &stages, 10);
 
+                    if let Some(stage) = state.stages.get(self.selected_stage as usize) {
+                        let key = self.selected_stage.to_string();
+                        self.map_thumbnails.request(&key, stage, None, state, ctx);
+
+                        match self.map_thumbnails.get(&key) {
+                            Some(&(texture, size)) => Image::new(texture, size).build(ui),
+                            None => ui.text("Rendering the thumbnail..."),
+                        }
+                    }
+
                     if ui.button(im_str!("Load"), [0.0, 0.0]) {
                         match GameScene::new(state, ctx, self.selected_stage as usize) {
                             Ok(mut scene) => {
@@ -140,6 +401,18 @@ impl LiveDebugger {
                             }
                         }
                     }
+
+                    // goes through the transition like <TRA, unlike Load
+                    if state.settings.allow_stage_history_warp() && !state.stage_history.is_empty() {
+                        ui.same_line(0.0);
+                        if ui.button(im_str!("Back"), [0.0, 0.0]) {
+                            game_scene.warp_back(state);
+                        }
+
+                        for entry in state.stage_history.iter().rev() {
+                            ui.text_wrapped(&ImString::new(entry.to_string()));
+                        }
+                    }
                 });
         }
 
@@ -166,6 +439,28 @@ impl LiveDebugger {
                     let events: Vec<&ImStr> = self.events.iter().map(|e| e.as_ref()).collect();
 
                     ui.text_wrapped(&ImString::new(format!("Execution state: {:?}", state.textscript_vm.state)));
+                    if let Some((event, ip, op)) = state.textscript_vm.last_executed {
+                        ui.text(format!("Last executed: #{:04} @ {} ({:?})", event, ip, op));
+                    }
+
+                    ui.checkbox(im_str!("Trace commands"), &mut state.textscript_vm.trace_enabled);
+                    ui.same_line(0.0);
+                    if ui.button(im_str!("Dump VM state"), [0.0, 0.0]) {
+                        log::info!("TSC VM state:\n{}", state.textscript_vm.dump_state());
+                    }
+
+                    if CollapsingHeader::new(im_str!("Trace")).build(ui) {
+                        if ui.button(im_str!("Clear"), [0.0, 0.0]) {
+                            state.textscript_vm.trace.clear();
+                        }
+
+                        // newest first, so it doesn't need scrolling to follow
+                        ChildWindow::new(im_str!("trace")).size([0.0, 150.0]).build(ui, || {
+                            for entry in state.textscript_vm.trace.iter().rev() {
+                                ui.text(entry.to_string());
+                            }
+                        });
+                    }
 
                     ui.push_item_width(-1.0);
                     ui.list_box(im_str!(""), &mut self.selected_event, &events, 10);
@@ -174,7 +469,7 @@ impl LiveDebugger {
                         assert_eq!(self.event_ids.len(), self.events.len());
 
                         if let Some(&event_num) = self.event_ids.get(self.selected_event as usize) {
-                            state.textscript_vm.start_script(event_num);
+                            state.textscript_vm.start_event(event_num, EventTrigger::Forced, &mut state.control_flags);
                         }
                     }
                 });
@@ -186,7 +481,7 @@ impl LiveDebugger {
                 .position([80.0, 80.0], Condition::FirstUseEver)
                 .size([280.0, 300.0], Condition::FirstUseEver)
                 .build(ui, || {
-                    if CollapsingHeader::new(im_str!("Control flags")).default_open(true).build(&ui)
+                    if CollapsingHeader::new(im_str!("Control flags")).default_open(true).build(ui)
                     {
                         ui.checkbox_flags(im_str!("Flag 0x01"), &mut state.control_flags.0, 1);
                         ui.checkbox_flags(im_str!("Control enabled"), &mut state.control_flags.0, 2);
@@ -195,6 +490,476 @@ impl LiveDebugger {
                 });
         }
 
+        if self.textures_visible {
+            Window::new(im_str!("Loaded textures"))
+                .position([80.0, 80.0], Condition::FirstUseEver)
+                .size([400.0, 300.0], Condition::FirstUseEver)
+                .build(ui, || {
+                    for (name, batch) in state.texture_set.tex_map.iter().sorted_by(|a, b| a.0.cmp(b.0)) {
+                        let (width, height) = batch.real_dimensions();
+                        ui.text(format!("{}: {} ({}x{}, {}x)", name, batch.path(), width, height, batch.resolution_multiplier()));
+                    }
+                });
+        }
+
+        if self.npcs_visible {
+            Window::new(im_str!("NPCs"))
+                .position([80.0, 80.0], Condition::FirstUseEver)
+                .size([300.0, 300.0], Condition::FirstUseEver)
+                .build(ui, || {
+                    let npc_map = &game_scene.npc_map;
+                    let ids = npc_map.npc_ids.iter().copied().filter(|&id| npc_map.is_alive(id)).collect_vec();
+                    let labels = ids.iter().map(|id| {
+                        let npc = npc_map.npcs[id].borrow();
+                        ImString::new(format!("#{}: type {}, event {}", id, npc.npc_type, npc.event_num))
+                    }).collect_vec();
+                    let labels: Vec<&ImStr> = labels.iter().map(|e| e.as_ref()).collect();
+
+                    let mut selected = ids.iter().position(|&id| Some(id) == self.selected_npc).map_or(-1, |pos| pos as i32);
+                    ui.push_item_width(-1.0);
+                    if ui.list_box(im_str!(""), &mut selected, &labels, 10) {
+                        self.selected_npc = ids.get(selected as usize).copied();
+                    }
+
+                    // which sheet and rect it's drawn from, for NPCs showing up with wrong sprites
+                    if let Some(npc_cell) = self.selected_npc.and_then(|id| npc_map.npcs.get(&id)) {
+                        let npc = npc_cell.borrow();
+                        let sheet = state.npc_table.get_sheet(npc.npc_type);
+                        let rect = &npc.anim_rect;
+                        ui.text(format!("Sheet: {:?} ({})", sheet, state.npc_table.sheet_texture_name(sheet)));
+                        ui.text(format!("Rect: ({}, {}) - ({}, {})", rect.left, rect.top, rect.right, rect.bottom));
+
+                        let hit = &npc.hit_bounds;
+                        let overridden = if state.npc_table.has_bounds_override(npc.npc_type) { " (overridden)" } else { "" };
+                        ui.text(format!("Hit bounds: {} {} {} {}{}", hit.left / 0x200, hit.top / 0x200, hit.right / 0x200, hit.bottom / 0x200, overridden));
+                        let display = &npc.display_bounds;
+                        ui.text(format!("Display bounds: {} {} {} {}", display.left / 0x200, display.top / 0x200, display.right / 0x200, display.bottom / 0x200));
+
+                        ui.text(format!("Off-screen: {}", npc.off_screen_policy().name()));
+                    }
+                });
+        }
+
+        if self.sound_test_visible {
+            Window::new(im_str!("Sound test"))
+                .position([80.0, 80.0], Condition::FirstUseEver)
+                .size([300.0, 420.0], Condition::FirstUseEver)
+                .build(ui, || {
+                    if !state.sound_manager.is_available() {
+                        ui.text_disabled("No audio device, nothing will be heard.");
+                    }
+
+                    let song_names = SoundManager::song_names();
+                    if self.songs.is_empty() {
+                        for (id, name) in song_names.iter().enumerate() {
+                            self.songs.push(ImString::new(format!("{:02}: {}", id, name)));
+                        }
+
+                        self.selected_song = state.sound_manager.song_id() as i32;
+                    }
+                    let songs: Vec<&ImStr> = self.songs.iter().map(|e| e.as_ref()).collect();
+
+                    let song_name = song_names.get(state.sound_manager.song_id()).unwrap_or(&"???");
+                    match state.sound_manager.song_position() {
+                        Some((measure, step)) => ui.text(format!("Now playing: {} ({}:{:02})", song_name, measure, step)),
+                        None => ui.text(format!("Now playing: {} (stopped)", song_name)),
+                    }
+
+                    ui.push_item_width(-1.0);
+                    ui.list_box(im_str!(""), &mut self.selected_song, &songs, 10);
+
+                    if ui.button(im_str!("Play"), [0.0, 0.0]) && self.selected_song >= 0 {
+                        let song_id = self.selected_song as usize;
+                        // play_song ignores the song that's already playing
+                        let result = if song_id == state.sound_manager.song_id() {
+                            state.sound_manager.seek(0)
+                        } else {
+                            state.sound_manager.play_song(song_id, &state.constants, ctx)
+                        };
+
+                        if let Err(e) = result {
+                            self.error = Some(ImString::new(e.to_string()));
+                        }
+                    }
+
+                    ui.same_line(0.0);
+                    if ui.button(im_str!("Stop"), [0.0, 0.0]) {
+                        if let Err(e) = state.sound_manager.play_song(0, &state.constants, ctx) {
+                            self.error = Some(ImString::new(e.to_string()));
+                        }
+                    }
+
+                    ui.push_item_width(100.0);
+                    ui.input_int(im_str!("Measure"), &mut self.seek_measure).build();
+                    ui.same_line(0.0);
+                    if ui.button(im_str!("Seek"), [0.0, 0.0]) {
+                        if let Err(e) = state.sound_manager.seek(self.seek_measure.max(0) as u32) {
+                            self.error = Some(ImString::new(e.to_string()));
+                        }
+                    }
+
+                    if CollapsingHeader::new(im_str!("Sound effects")).default_open(true).build(ui) {
+                        for id in 0..SoundManager::sfx_count() {
+                            if id % 8 != 0 {
+                                ui.same_line(0.0);
+                            }
+
+                            if ui.button(&ImString::new(format!("{:03}", id)), [30.0, 0.0]) {
+                                state.sound_manager.play_sfx(id as u8);
+                            }
+                        }
+                    }
+                });
+        }
+
+        if self.attributes_visible {
+            let mut export = false;
+
+            Window::new(im_str!("Tile attributes"))
+                .position([80.0, 80.0], Condition::FirstUseEver)
+                .size([440.0, 560.0], Condition::FirstUseEver)
+                .build(ui, || {
+                    ui.checkbox(im_str!("Show on the map"), &mut game_scene.attribute_overlay);
+
+                    let map = &mut game_scene.stage.map;
+                    for tile in 0..0x100usize {
+                        if tile % 16 != 0 {
+                            ui.same_line(0.0);
+                        }
+
+                        let label = if tile == self.selected_tile as usize { format!("[{:02x}]##{}", map.attrib[tile], tile) } else { format!("{:02x}##{}", map.attrib[tile], tile) };
+                        if ui.button(&ImString::new(label), [24.0, 0.0]) {
+                            self.selected_tile = tile as u8;
+                        }
+                    }
+
+                    let attr = map.attrib[self.selected_tile as usize];
+                    ui.text(format!("Tile {:#04x}: {:#04x} ({})", self.selected_tile, attr, attribute_name(attr).unwrap_or("unknown")));
+
+                    let labels = KNOWN_ATTRIBUTES.iter()
+                        .map(|(value, name)| ImString::new(format!("{:02x}: {}", value, name)))
+                        .collect_vec();
+                    let labels: Vec<&ImStr> = labels.iter().map(|e| e.as_ref()).collect();
+
+                    let mut selected = KNOWN_ATTRIBUTES.iter().position(|&(value, _)| value == attr).map_or(-1, |pos| pos as i32);
+                    ui.push_item_width(-1.0);
+                    if ui.list_box(im_str!(""), &mut selected, &labels, 10) {
+                        if let Some(&(value, _)) = KNOWN_ATTRIBUTES.get(selected as usize) {
+                            map.set_attribute(self.selected_tile, value);
+                        }
+                    }
+
+                    // edits only last until the stage is reloaded
+                    if ui.button(im_str!("Export PXA"), [0.0, 0.0]) {
+                        export = true;
+                    }
+                });
+
+            if export {
+                let path = [state.base_path.as_str(), "Stage/", game_scene.stage.data.tileset.name(), ".pxa"].join("");
+                match filesystem::create(ctx, &path).and_then(|file| game_scene.stage.map.write_attributes(file)) {
+                    Ok(()) => state.notifications.push(format!("Saved {}", path)),
+                    Err(e) => self.error = Some(ImString::new(e.to_string())),
+                }
+            }
+        }
+
+        if self.log_visible {
+            Window::new(im_str!("Log"))
+                .position([80.0, 80.0], Condition::FirstUseEver)
+                .size([520.0, 300.0], Condition::FirstUseEver)
+                .build(ui, || {
+                    for &level in [Level::Error, Level::Warn, Level::Info, Level::Debug].iter() {
+                        ui.radio_button(&ImString::new(level.to_string()), &mut self.log_level, level);
+                        ui.same_line(0.0);
+                    }
+
+                    let entries = log_sink::recent_entries(self.log_level);
+                    // imgui only has a clipboard backend of its own on Windows
+                    if cfg!(target_os = "windows") && ui.button(im_str!("Copy"), [0.0, 0.0]) {
+                        let text = entries.iter().map(|entry| entry.to_string()).join("\n");
+                        ui.set_clipboard_text(&ImString::new(text));
+                    }
+                    if let Some(path) = log_sink::log_path() {
+                        ui.text_disabled(format!("Everything is saved to {}", path.to_string_lossy()));
+                    }
+
+                    // newest first, like the TSC trace
+                    ChildWindow::new(im_str!("log")).size([0.0, 0.0]).horizontal_scrollbar(true).build(ui, || {
+                        for entry in entries.iter().rev() {
+                            let color = match entry.level {
+                                Level::Error => [1.0, 0.4, 0.4, 1.0],
+                                Level::Warn => [1.0, 0.8, 0.3, 1.0],
+                                _ => [0.9, 0.9, 0.9, 1.0],
+                            };
+                            ui.text_colored(color, entry.to_string());
+                        }
+                    });
+                });
+        }
+
+        if self.stats_visible {
+            Window::new(im_str!("Statistics"))
+                .position([80.0, 80.0], Condition::FirstUseEver)
+                .size([300.0, 400.0], Condition::FirstUseEver)
+                .build(ui, || {
+                    let stats = &state.stats;
+                    if !stats.enabled {
+                        ui.text("Not recording (replay or challenge).");
+                    }
+
+                    ui.text(format!("Damage taken: {}", stats.damage_taken));
+                    ui.text(format!("Deaths: {}", stats.deaths));
+                    ui.text(format!("Jumps: {}", stats.jumps));
+                    ui.text(format!("Life Capsules: {}", stats.life_capsules));
+
+                    if CollapsingHeader::new(im_str!("Enemies defeated")).build(ui) {
+                        for (npc_type, count) in stats.enemies_defeated.iter() {
+                            ui.text(format!("Type {}: {}", npc_type, count));
+                        }
+                    }
+
+                    if CollapsingHeader::new(im_str!("Shots fired")).build(ui) {
+                        for (weapon, count) in stats.shots_fired.iter() {
+                            ui.text(format!("Weapon {}: {}", weapon, count));
+                        }
+                    }
+
+                    if CollapsingHeader::new(im_str!("Time per stage")).build(ui) {
+                        for (map, ticks) in stats.stage_ticks.iter() {
+                            ui.text(format!("{}: {}", map, format_time(*ticks as usize, state.tps())));
+                        }
+                    }
+
+                    if CollapsingHeader::new(im_str!("Achievements")).default_open(true).build(ui) {
+                        for achievement in ACHIEVEMENTS.iter() {
+                            let mark = if stats.unlocked.contains(achievement.id) { "[x]" } else { "[ ]" };
+                            ui.text(format!("{} {}", mark, achievement.name));
+                            ui.text_disabled(achievement.description);
+                        }
+
+                        for id in stats.unlocked.iter().filter(|id| id.starts_with(SCRIPTED_ACHIEVEMENT_PREFIX)) {
+                            ui.text(format!("[x] <ACH{}", &id[SCRIPTED_ACHIEVEMENT_PREFIX.len()..]));
+                        }
+                    }
+                });
+        }
+
+        if self.boss_rush_visible {
+            let mut start_rush = false;
+
+            Window::new(im_str!("Boss rush"))
+                .position([80.0, 80.0], Condition::FirstUseEver)
+                .size([300.0, 460.0], Condition::FirstUseEver)
+                .build(ui, || {
+                    if let Some(rush) = state.boss_rush.as_mut() {
+                        ui.text(rush.status());
+                        if ui.button(im_str!("Skip boss"), [0.0, 0.0]) {
+                            rush.skip_requested = true;
+                        }
+                        ui.same_line(0.0);
+                        if ui.button(im_str!("Leave"), [0.0, 0.0]) {
+                            rush.leave_requested = true;
+                        }
+                    } else {
+                        let form = &mut self.boss_rush;
+                        if form.max_life == 0 {
+                            form.max_life = game_scene.player.max_life as i32;
+                        }
+
+                        ui.text_wrapped(im_str!("The arena uses the tileset of the current stage, the equipment stays as it is."));
+                        ui.push_item_width(100.0);
+                        ui.input_int(im_str!("Width"), &mut form.width).build();
+                        ui.input_int(im_str!("Height"), &mut form.height).build();
+                        ui.input_int(im_str!("Weapon level"), &mut form.level).build();
+                        ui.input_int(im_str!("Max life"), &mut form.max_life).build();
+
+                        if CollapsingHeader::new(im_str!("Weapons")).default_open(true).build(ui) {
+                            for (i, wtype) in LOADOUT_WEAPONS.iter().enumerate() {
+                                if i % 2 != 0 {
+                                    ui.same_line(140.0);
+                                }
+                                ui.checkbox(&ImString::new(format!("{:?}", wtype)), &mut form.weapons[i]);
+                            }
+                        }
+
+                        if CollapsingHeader::new(im_str!("Bosses")).default_open(true).build(ui) {
+                            for (npc_type, name, selected) in form.bosses.iter_mut() {
+                                let supported = is_supported(*npc_type, state);
+                                let label = if supported { format!("{} (#{})", name, npc_type) } else { format!("{} (#{}, not implemented)", name, npc_type) };
+
+                                let token = ui.push_style_var(StyleVar::Alpha(if supported { 1.0 } else { 0.5 }));
+                                ui.checkbox(&ImString::new(label), selected);
+                                token.pop(ui);
+                            }
+
+                            // any NPC can stand in for a boss
+                            ui.input_int(im_str!("NPC type"), &mut form.extra_npc_type).build();
+                            ui.same_line(0.0);
+                            if ui.button(im_str!("Add"), [0.0, 0.0]) && form.extra_npc_type > 0 {
+                                let npc_type = form.extra_npc_type as u16;
+                                form.bosses.push((npc_type, format!("NPC {}", npc_type), true));
+                            }
+                        }
+
+                        if !form.bosses.iter().any(|&(npc_type, _, selected)| selected && is_supported(npc_type, state)) {
+                            ui.text_disabled(im_str!("None of the selected bosses are implemented yet."));
+                        } else if ui.button(im_str!("Start"), [0.0, 0.0]) {
+                            start_rush = true;
+                        }
+                    }
+
+                    if !state.stats.last_boss_rush.is_empty() && CollapsingHeader::new(im_str!("Last results")).default_open(true).build(ui) {
+                        for record in state.stats.last_boss_rush.iter() {
+                            ui.text_wrapped(&ImString::new(record.to_string()));
+                        }
+                    }
+                });
+
+            if start_rush {
+                let config = self.boss_rush.config(game_scene, state);
+                if let Err(e) = BossRush::start(config, game_scene, state, ctx) {
+                    self.error = Some(ImString::new(e.to_string()));
+                }
+            }
+        }
+
+        if self.settings_visible {
+            let mut changed = false;
+            let mut open_mod_menu = false;
+            let mut open_challenges = false;
+
+            Window::new(im_str!("Settings"))
+                .position([80.0, 80.0], Condition::FirstUseEver)
+                .size([280.0, 180.0], Condition::FirstUseEver)
+                .build(ui, || {
+                    if ui.button(im_str!("Switch mod..."), [0.0, 0.0]) {
+                        open_mod_menu = true;
+                    }
+                    ui.same_line(0.0);
+                    if ui.button(im_str!("Challenges..."), [0.0, 0.0]) {
+                        open_challenges = true;
+                    }
+
+                    if state.sound_manager.is_available() {
+                        ui.text("Audio: OK");
+                    } else {
+                        ui.text("Audio: no device");
+                        ui.same_line(0.0);
+                        if ui.button(im_str!("Retry"), [0.0, 0.0]) {
+                            retry_audio = true;
+                        }
+                    }
+
+                    changed |= ui.checkbox(im_str!("Discord Rich Presence"), &mut state.settings.discord_rpc);
+                    changed |= ui.checkbox(im_str!("CRT filter"), &mut state.settings.crt_filter);
+
+                    let mut pillarbox = state.settings.presentation == Presentation::Pillarbox;
+                    if ui.checkbox(im_str!("Pillarbox to 4:3"), &mut pillarbox) {
+                        state.settings.presentation = if pillarbox { Presentation::Pillarbox } else { Presentation::ExpandView };
+                        changed = true;
+                    }
+
+                    changed |= ui.checkbox(im_str!("Gamepad rumble"), &mut state.settings.rumble);
+                    changed |= Slider::new(im_str!("Rumble intensity"), 0.0..=1.0)
+                        .build(ui, &mut state.settings.rumble_intensity);
+                    changed |= ui.checkbox(im_str!("VSync (after a restart)"), &mut state.settings.vsync);
+
+                    // when vsync is off or doesn't work
+                    let mut limit_frames = state.settings.frame_limit.is_some();
+                    if ui.checkbox(im_str!("Limit FPS"), &mut limit_frames) {
+                        state.settings.frame_limit = if limit_frames { Some(60) } else { None };
+                        changed = true;
+                    }
+                    if let Some(frame_limit) = state.settings.frame_limit {
+                        let mut fps = frame_limit as i32;
+                        if ui.input_int(im_str!("FPS"), &mut fps).build() {
+                            state.settings.frame_limit = Some(fps.clamp(10, 1000) as u32);
+                            changed = true;
+                        }
+                    }
+
+                    changed |= ui.checkbox(im_str!("Reduce power usage when unfocused"), &mut state.settings.power_saving);
+                    changed |= ui.checkbox(im_str!("Late input latch"), &mut state.settings.late_latch);
+
+                    ui.text("Compatibility mode:");
+                    for &mode in [CompatMode::Vanilla, CompatMode::Enhanced, CompatMode::Custom].iter() {
+                        ui.same_line(0.0);
+                        changed |= ui.radio_button(&ImString::new(mode.name()), &mut state.settings.compat_mode, mode);
+                    }
+                    ui.text_wrapped(im_str!("Greyed out options are decided by the mode, NPC bounds fixes apply after a restart."));
+
+                    // greyed out when the mode overrides them
+                    let fixes_forced = state.settings.compat_mode != CompatMode::Custom;
+                    let assists_forced = state.settings.compat_mode == CompatMode::Vanilla;
+
+                    let token = ui.push_style_var(StyleVar::Alpha(if fixes_forced { 0.5 } else { 1.0 }));
+                    changed |= ui.checkbox(im_str!("Stage lighting"), &mut state.settings.lighting);
+                    changed |= ui.checkbox(im_str!("No crystals through walls"), &mut state.settings.exp_line_of_sight);
+                    changed |= ui.checkbox(im_str!("Offer recovery when stuck"), &mut state.settings.softlock_recovery);
+                    token.pop(ui);
+
+                    let token = ui.push_style_var(StyleVar::Alpha(if assists_forced { 0.5 } else { 1.0 }));
+                    changed |= ui.checkbox(im_str!("Quick save (F5) / load (F9)"), &mut state.settings.quick_save);
+                    changed |= ui.checkbox(im_str!("Rewind (hold Backspace)"), &mut state.settings.rewind);
+                    changed |= ui.checkbox(im_str!("60 ticks per second (CS+)"), &mut state.settings.tps_60);
+
+                    ui.text("Transitions:");
+                    ui.same_line(0.0);
+                    changed |= ui.radio_button(im_str!("As scripted"), &mut state.settings.transition, None);
+                    for &ty in [TransitionType::Tiles, TransitionType::Alpha, TransitionType::Diamond, TransitionType::Cut].iter() {
+                        ui.same_line(0.0);
+                        changed |= ui.radio_button(&ImString::new(ty.name()), &mut state.settings.transition, Some(ty));
+                    }
+                    token.pop(ui);
+
+                    let mut rewind_seconds = state.settings.rewind_seconds as i32;
+                    if ui.input_int(im_str!("Rewind seconds"), &mut rewind_seconds).build() {
+                        state.settings.rewind_seconds = rewind_seconds.clamp(1, 60) as u16;
+                        changed = true;
+                    }
+
+                    if CollapsingHeader::new(im_str!("Accessibility")).default_open(true).build(ui)
+                    {
+                        let settings = &mut state.settings.accessibility;
+                        changed |= ui.checkbox(im_str!("High contrast HUD"), &mut settings.high_contrast_hud);
+                        changed |= ui.checkbox(im_str!("Large message text"), &mut settings.large_text);
+                        changed |= ui.checkbox(im_str!("Reduced flashing"), &mut settings.reduced_flash);
+
+                        let token = ui.push_style_var(StyleVar::Alpha(if assists_forced { 0.5 } else { 1.0 }));
+                        changed |= ui.checkbox(im_str!("Auto fire (hold to fire)"), &mut settings.auto_fire);
+                        changed |= Slider::new(im_str!("Message delay"), 0.25..=4.0)
+                            .build(ui, &mut settings.message_delay);
+                        token.pop(ui);
+                    }
+                });
+
+            if open_mod_menu {
+                state.next_scene = Some(Box::new(ModMenuScene::new(ctx)));
+            }
+
+            if open_challenges {
+                match ChallengeMenuScene::new(state, ctx) {
+                    Ok(scene) => state.next_scene = Some(Box::new(scene)),
+                    Err(e) => self.error = Some(ImString::new(e.to_string())),
+                }
+            }
+
+            if changed {
+                state.reset_tps(game_scene);
+
+                if let Err(e) = state.settings.save() {
+                    log::error!("Error saving settings: {:?}", e);
+                    self.error = Some(ImString::new(e.to_string()));
+                }
+            }
+        }
+
+        // after the settings window, so its Retry button is picked up in the same frame
+        if retry_audio && !state.sound_manager.is_available() {
+            state.sound_manager.open_device(ctx, &state.constants);
+        }
+
         Ok(())
     }
 }
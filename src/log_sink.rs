@@ -0,0 +1,265 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use lazy_static::lazy_static;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use pretty_env_logger::env_logger::{self, Env};
+
+use crate::ggez::filesystem::user_dirs;
+
+/// The log file is rotated once it gets bigger than this, only the previous one is kept.
+const MAX_LOG_SIZE: u64 = 4 * 1024 * 1024;
+/// Entries kept in memory for the debugger's log window.
+pub const RECENT_CAPACITY: usize = 500;
+const NO_STAGE: usize = usize::MAX;
+
+// the logger can't reach `SharedGameState`, the game loop and the scenes keep these up to date
+static TICK: AtomicUsize = AtomicUsize::new(0);
+static STAGE: AtomicUsize = AtomicUsize::new(NO_STAGE);
+
+lazy_static! {
+    static ref RECENT: Mutex<VecDeque<LogEntry>> = Mutex::new(VecDeque::with_capacity(RECENT_CAPACITY));
+    static ref FILE: Mutex<Option<LogFile>> = Mutex::new(None);
+}
+
+#[derive(Clone)]
+pub struct LogEntry {
+    pub level: Level,
+    /// Milliseconds since the logger was installed.
+    pub time: u64,
+    pub tick: usize,
+    pub stage: Option<usize>,
+    pub target: String,
+    pub message: String,
+}
+
+impl LogEntry {
+    /// One line of JSON, the format of the log file.
+    pub fn to_json(&self) -> String {
+        serde_json::json!({
+            "time": self.time,
+            "level": self.level.to_string(),
+            "tick": self.tick,
+            "stage": self.stage,
+            "target": self.target,
+            "message": self.message,
+        }).to_string()
+    }
+}
+
+impl fmt::Display for LogEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.stage {
+            Some(stage) => write!(f, "[{} #{} stage {}] ", self.level, self.tick, stage)?,
+            None => write!(f, "[{} #{}] ", self.level, self.tick)?,
+        }
+        write!(f, "{}: {}", self.target, self.message)
+    }
+}
+
+struct LogFile {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    size: u64,
+}
+
+impl LogFile {
+    /// The log of the previous run becomes the rotated one.
+    fn open(path: &Path) -> std::io::Result<LogFile> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        LogFile::rotate(path)?;
+
+        Ok(LogFile {
+            path: path.to_path_buf(),
+            writer: BufWriter::new(File::create(path)?),
+            size: 0,
+        })
+    }
+
+    fn rotate(path: &Path) -> std::io::Result<()> {
+        if path.exists() {
+            fs::rename(path, rotated_path(path))?;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, line: &str, flush: bool) -> std::io::Result<()> {
+        let len = line.len() as u64 + 1;
+        if self.size + len > MAX_LOG_SIZE {
+            self.writer.flush()?;
+            LogFile::rotate(&self.path)?;
+            self.writer = BufWriter::new(File::create(&self.path)?);
+            self.size = 0;
+        }
+
+        writeln!(self.writer, "{}", line)?;
+        self.size += len;
+        if flush {
+            self.writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+fn rotated_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".1");
+    PathBuf::from(name)
+}
+
+/// Most detailed level written to the file and the log window, the other crates (gfx, winit, gilrs...)
+/// would fill them up at debug level.
+fn captured_level(target: &str) -> Level {
+    if target.starts_with("doukutsu_rs") { Level::Debug } else { Level::Info }
+}
+
+/// Prints to the console with the usual `RUST_LOG` filter, and writes everything down to debug level
+/// (info for other crates) to `doukutsu.log` and the debugger's log window whatever the filter is.
+struct Logger {
+    console: env_logger::Logger,
+    start: Instant,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= captured_level(metadata.target()) || self.console.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.console.matches(record) {
+            self.console.log(record);
+        }
+
+        if record.level() > captured_level(record.target()) {
+            return;
+        }
+
+        let stage = STAGE.load(Ordering::Relaxed);
+        let entry = LogEntry {
+            level: record.level(),
+            time: self.start.elapsed().as_millis() as u64,
+            tick: TICK.load(Ordering::Relaxed),
+            stage: if stage == NO_STAGE { None } else { Some(stage) },
+            target: record.target().to_owned(),
+            message: record.args().to_string(),
+        };
+
+        // nothing in here may log, the locks are held
+        if let Ok(mut file) = FILE.lock() {
+            let failed = match file.as_mut() {
+                // warnings and errors are the lines most likely to be followed by a crash
+                Some(log_file) => log_file.write(&entry.to_json(), entry.level <= Level::Warn).err(),
+                None => None,
+            };
+            if let Some(e) = failed {
+                eprintln!("Cannot write the log file, it's disabled: {}", e);
+                *file = None;
+            }
+        }
+
+        if let Ok(mut recent) = RECENT.lock() {
+            if recent.len() == RECENT_CAPACITY {
+                recent.pop_front();
+            }
+            recent.push_back(entry);
+        }
+    }
+
+    fn flush(&self) {
+        self.console.flush();
+        flush();
+    }
+}
+
+/// Installs the logger, replaces `env_logger::init`. The log file isn't written if the user data directory is unavailable.
+pub fn init_logger() {
+    let console = env_logger::Logger::from_env(Env::default().default_filter_or("info"));
+    let max_level = console.filter().max(LevelFilter::Debug);
+
+    let file_error = match user_dirs() {
+        Ok(dirs) => match LogFile::open(&dirs.log_path()) {
+            Ok(log_file) => {
+                if let Ok(mut file) = FILE.lock() {
+                    *file = Some(log_file);
+                }
+                None
+            }
+            Err(e) => Some(e.to_string()),
+        },
+        Err(e) => Some(e.to_string()),
+    };
+
+    let logger = Logger { console, start: Instant::now() };
+    if log::set_boxed_logger(Box::new(logger)).is_ok() {
+        log::set_max_level(max_level);
+    }
+
+    match (log_path(), file_error) {
+        (Some(path), _) => log::info!("Log file: {:?}", path),
+        (None, Some(e)) => log::warn!("Cannot open the log file: {}", e),
+        (None, None) => {}
+    }
+}
+
+/// Has to be called once per game tick.
+pub fn advance_tick() {
+    TICK.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Stage the following lines are tagged with, `None` outside of the game scene.
+pub fn set_stage(stage: Option<usize>) {
+    STAGE.store(stage.unwrap_or(NO_STAGE), Ordering::Relaxed);
+}
+
+pub fn log_path() -> Option<PathBuf> {
+    FILE.try_lock().ok()?.as_ref().map(|file| file.path.clone())
+}
+
+/// Writes out the buffered lines. Doesn't wait for the lock, a panic might have happened while logging.
+pub fn flush() {
+    if let Ok(mut file) = FILE.try_lock() {
+        if let Some(log_file) = file.as_mut() {
+            let _ = log_file.writer.flush();
+        }
+    }
+}
+
+/// The last `RECENT_CAPACITY` entries at `level` or more severe, oldest first.
+pub fn recent_entries(level: Level) -> Vec<LogEntry> {
+    match RECENT.lock() {
+        Ok(recent) => recent.iter().filter(|entry| entry.level <= level).cloned().collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+#[test]
+fn test_log_entry() {
+    let entry = LogEntry {
+        level: Level::Warn,
+        time: 1500,
+        tick: 42,
+        stage: Some(13),
+        target: "doukutsu_rs::npc".to_owned(),
+        message: "no \"rects\"".to_owned(),
+    };
+
+    assert_eq!(entry.to_string(), "[WARN #42 stage 13] doukutsu_rs::npc: no \"rects\"");
+    let json: serde_json::Value = serde_json::from_str(&entry.to_json()).unwrap();
+    assert_eq!(json["tick"], 42);
+    assert_eq!(json["stage"], 13);
+    assert_eq!(json["level"], "WARN");
+    assert_eq!(json["message"], "no \"rects\"");
+
+    assert_eq!(rotated_path(Path::new("/tmp/doukutsu.log")), PathBuf::from("/tmp/doukutsu.log.1"));
+
+    assert_eq!(captured_level("doukutsu_rs::npc"), Level::Debug);
+    assert_eq!(captured_level("gfx_device_gl::factory"), Level::Info);
+}
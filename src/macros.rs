@@ -639,3 +639,33 @@ macro_rules! case_insensitive_hashmap {
         }
     };
 }
+
+/// Animation frames laid out in a grid on a sheet, a row of `frames` frames per direction,
+/// `[left..., right...]` for `directions = 2`. The length matching the field it's assigned to is checked by the compiler.
+#[macro_export]
+macro_rules! anim_rects {
+    (sheet_y = $y:expr, frame_w = $w:expr, frame_h = $h:expr, frames = $frames:expr, directions = $directions:expr) => {
+        $crate::anim_rects!(sheet_x = 0, sheet_y = $y, frame_w = $w, frame_h = $h, frames = $frames, directions = $directions)
+    };
+    (sheet_x = $x:expr, sheet_y = $y:expr, frame_w = $w:expr, frame_h = $h:expr, frames = $frames:expr, directions = $directions:expr) => {
+        {
+            let mut _rects = [$crate::common::Rect::<usize> { left: 0, top: 0, right: 0, bottom: 0 }; $frames * $directions];
+            for (_i, _rect) in _rects.iter_mut().enumerate() {
+                let (_frame, _direction) = (_i % $frames, _i / $frames);
+                *_rect = $crate::common::Rect::new_size($x + _frame * $w, $y + _direction * $h, $w, $h);
+            }
+            _rects
+        }
+    };
+}
+
+#[test]
+fn test_anim_rects() {
+    use crate::common::Rect;
+
+    let rects: [Rect<usize>; 6] = anim_rects!(sheet_x = 32, sheet_y = 48, frame_w = 16, frame_h = 24, frames = 3, directions = 2);
+    assert_eq!(rects[0], Rect::new(32, 48, 48, 72));
+    assert_eq!(rects[2], Rect::new(64, 48, 80, 72));
+    assert_eq!(rects[3], Rect::new(32, 72, 48, 96));
+    assert_eq!(rects[5], Rect::new(64, 72, 80, 96));
+}
@@ -1,8 +1,12 @@
+use std::collections::HashMap;
 use std::io;
-use std::io::{Error, ErrorKind};
+use std::io::Write;
+use std::ops::Range;
 
 use byteorder::{LE, ReadBytesExt};
+use image::RgbaImage;
 
+use crate::common::Rect;
 use crate::ggez::GameError::ResourceLoadError;
 use crate::ggez::GameResult;
 use crate::str;
@@ -10,14 +14,148 @@ use crate::str;
 static SUPPORTED_PXM_VERSIONS: [u8; 1] = [0x10];
 static SUPPORTED_PXE_VERSIONS: [u8; 2] = [0, 0x10];
 
+/// Tile attributes the engine knows about, with the names shown in the debugger.
+pub static KNOWN_ATTRIBUTES: [(u8, &str); 38] = [
+    (0x00, "none"),
+    (0x01, "background"),
+    (0x02, "background, water"),
+    (0x05, "solid, invisible"),
+    (0x40, "foreground"),
+    (0x41, "solid"),
+    (0x42, "spike"),
+    (0x43, "solid, breakable"),
+    (0x44, "solid to NPCs"),
+    (0x46, "solid to player"),
+    (0x50, "slope LT, large"),
+    (0x51, "slope LT, small"),
+    (0x52, "slope RT, small"),
+    (0x53, "slope RT, large"),
+    (0x54, "slope LB, large"),
+    (0x55, "slope LB, small"),
+    (0x56, "slope RB, small"),
+    (0x57, "slope RB, large"),
+    (0x60, "water"),
+    (0x61, "water, solid"),
+    (0x62, "water, spike"),
+    (0x70, "water, slope LT, large"),
+    (0x71, "water, slope LT, small"),
+    (0x72, "water, slope RT, small"),
+    (0x73, "water, slope RT, large"),
+    (0x74, "water, slope LB, large"),
+    (0x75, "water, slope LB, small"),
+    (0x76, "water, slope RB, small"),
+    (0x77, "water, slope RB, large"),
+    (0x80, "wind left"),
+    (0x81, "wind up"),
+    (0x82, "wind right"),
+    (0x83, "wind down"),
+    (0xa0, "water, current left"),
+    (0xa1, "water, current up"),
+    (0xa2, "water, current right"),
+    (0xa3, "water, current down"),
+    (0xff, "unused"),
+];
+
+/// Name of a tile attribute, `None` for the ones the engine doesn't know.
+pub fn attribute_name(attr: u8) -> Option<&'static str> {
+    KNOWN_ATTRIBUTES.iter().find(|&&(value, _)| value == attr).map(|&(_, name)| name)
+}
+
+/// Tile map of a stage, tiles are stored row by row.
 pub struct Map {
     pub width: usize,
     pub height: usize,
     pub tiles: Vec<u8>,
     pub attrib: [u8; 0x100],
+    /// Bumped every time the tiles change, tells the tile mesh to rebuild.
+    pub revision: usize,
+}
+
+/// Tile draw passes, `Background` goes behind the entities and the rest in front of them.
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
+pub enum TilePass {
+    Background,
+    Foreground,
+    /// Breakable blocks, drawn from the NPC sheet instead of the tileset.
+    Snack,
+}
+
+/// Tiles with attributes in `first..=last` are drawn in `pass`.
+#[derive(Debug, Copy, Clone)]
+pub struct TilePassRange {
+    pub first: u8,
+    pub last: u8,
+    pub pass: TilePass,
+}
+
+/// The first matching range wins, tiles outside of every range aren't drawn at all.
+pub fn tile_pass(ranges: &[TilePassRange], attr: u8) -> Option<TilePass> {
+    ranges.iter()
+        .find(|range| range.first <= attr && attr <= range.last)
+        .map(|range| range.pass)
+}
+
+/// Source rect of a tile on the tileset, tilesets are 16 tiles wide.
+/// Used by both the renderer and the thumbnails, so they always pick the same part of the tileset.
+pub fn tile_rect(tile: u8) -> Rect<usize> {
+    Rect::new_size((tile as usize % 16) * 16, (tile as usize / 16) * 16, 16, 16)
+}
+
+/// Tiles of the map sorted into the draw passes row by row, so attributes aren't checked every frame.
+pub struct TileMesh {
+    revision: Option<usize>,
+    /// Rows of (x, tile) pairs for every pass.
+    passes: HashMap<TilePass, Vec<Vec<(usize, u8)>>>,
+}
+
+impl TileMesh {
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> TileMesh {
+        TileMesh {
+            revision: None,
+            passes: HashMap::new(),
+        }
+    }
+
+    /// Rebuilds the mesh if the map has changed since the last time.
+    pub fn update(&mut self, map: &Map, ranges: &[TilePassRange]) {
+        if self.revision == Some(map.revision) {
+            return;
+        }
+
+        self.passes.clear();
+        for y in 0..map.height {
+            for x in 0..map.width {
+                let tile = map.tiles[y * map.width + x];
+                if let Some(pass) = tile_pass(ranges, map.attrib[tile as usize]) {
+                    let rows = self.passes.entry(pass).or_insert_with(|| vec![Vec::new(); map.height]);
+                    rows[y].push((x, tile));
+                }
+            }
+        }
+
+        self.revision = Some(map.revision);
+    }
+
+    /// Tiles of given pass within the given tile coordinates, as (x, y, tile).
+    pub fn tiles(&self, pass: TilePass, xs: Range<usize>, ys: Range<usize>) -> impl Iterator<Item=(usize, usize, u8)> + '_ {
+        let rows = match self.passes.get(&pass) {
+            Some(rows) => &rows[ys.start.min(rows.len())..ys.end.min(rows.len())],
+            None => &[][..],
+        };
+        let start_y = ys.start;
+
+        rows.iter().enumerate().flat_map(move |(i, row)| {
+            let xs = xs.clone();
+            row.iter()
+                .filter(move |(x, _)| xs.contains(x))
+                .map(move |&(x, tile)| (x, start_y + i, tile))
+        })
+    }
 }
 
 impl Map {
+    /// Loads a map from the PXM tile data and the PXA tile attributes of its tileset.
     pub fn load_from<R: io::Read>(mut map_data: R, mut attrib_data: R) -> GameResult<Map> {
         let mut magic = [0; 3];
 
@@ -51,11 +189,95 @@ impl Map {
             height,
             tiles,
             attrib,
+            revision: 0,
         })
     }
 
-    pub fn get_attribute(&self, x: usize, y: usize) -> u8 {
-        self.attrib[*self.tiles.get(self.width * y + x).unwrap_or_else(|| &0u8) as usize]
+    /// Changes the tile at given tile coordinates, does nothing outside of the map.
+    pub fn change_tile(&mut self, x: usize, y: usize, tile: u8) {
+        if x >= self.width || y >= self.height {
+            return;
+        }
+
+        self.tiles[y * self.width + x] = tile;
+        self.revision += 1;
+    }
+
+    pub fn set_tiles(&mut self, tiles: Vec<u8>) {
+        self.tiles = tiles;
+        self.revision += 1;
+    }
+
+    /// Changes the attribute of a tileset tile, takes effect on the collision right away.
+    pub fn set_attribute(&mut self, tile: u8, attr: u8) {
+        self.attrib[tile as usize] = attr;
+        self.revision += 1;
+    }
+
+    /// Writes the tile attributes back in the PXA format.
+    pub fn write_attributes<W: Write>(&self, mut out: W) -> GameResult {
+        out.write_all(&self.attrib)?;
+        Ok(())
+    }
+
+    /// Rasterizes the tiles into a preview fitting in `max_width` x `max_height`, on the CPU from the decoded tileset.
+    /// Tiles outside of the draw passes are left out, like in the game, the empty space is black.
+    pub fn render_thumbnail(&self, tileset: &RgbaImage, ranges: &[TilePassRange], max_width: u32, max_height: u32) -> RgbaImage {
+        let full_width = (self.width * 16) as f32;
+        let full_height = (self.height * 16) as f32;
+        let scale = (max_width as f32 / full_width).min(max_height as f32 / full_height).min(1.0);
+        let width = ((full_width * scale) as u32).max(1);
+        let height = ((full_height * scale) as u32).max(1);
+
+        let (tileset_width, tileset_height) = tileset.dimensions();
+        let source: &[u8] = tileset;
+        let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+
+        for y in 0..height {
+            for x in 0..width {
+                let px = ((x as f32 + 0.5) / scale) as usize;
+                let py = ((y as f32 + 0.5) / scale) as usize;
+                let (tx, ty) = (px / 16, py / 16);
+
+                let mut color = [0, 0, 0, 255];
+                if tx < self.width && ty < self.height {
+                    let tile = self.tiles[ty * self.width + tx];
+
+                    if tile_pass(ranges, self.attrib[tile as usize]).is_some() {
+                        let rect = tile_rect(tile);
+                        let (sx, sy) = ((rect.left + px % 16) as u32, (rect.top + py % 16) as u32);
+
+                        if sx < tileset_width && sy < tileset_height {
+                            let offset = ((sy * tileset_width + sx) * 4) as usize;
+                            if source[offset + 3] != 0 {
+                                color.copy_from_slice(&source[offset..offset + 4]);
+                            }
+                        }
+                    }
+                }
+
+                pixels.extend_from_slice(&color);
+            }
+        }
+
+        RgbaImage::from_raw(width, height, pixels).unwrap()
+    }
+
+    /// Whether the tile blocks both the player and NPCs.
+    pub fn is_solid(&self, x: isize, y: isize) -> bool {
+        matches!(self.get_attribute(x, y), 0x05 | 0x41 | 0x43 | 0x61)
+    }
+
+    /// Attribute of the tile at given tile coordinates, 0 outside of the map like in the original game.
+    pub fn get_attribute(&self, x: isize, y: isize) -> u8 {
+        if x < 0 || y < 0 || x as usize >= self.width || y as usize >= self.height {
+            return 0;
+        }
+
+        match self.tiles.get(self.width * y as usize + x as usize) {
+            Some(&tile) => self.attrib[tile as usize],
+            None => 0,
+        }
     }
 }
 
@@ -115,3 +337,97 @@ impl NPCData {
         Ok(npcs)
     }
 }
+
+#[test]
+fn test_tile_mesh() {
+    let ranges = crate::engine_constants::EngineConstants::defaults().world.tile_passes;
+    assert_eq!(tile_pass(&ranges, 0x00), Some(TilePass::Background));
+    assert_eq!(tile_pass(&ranges, 0x41), Some(TilePass::Foreground));
+    assert_eq!(tile_pass(&ranges, 0x43), Some(TilePass::Snack));
+    assert_eq!(tile_pass(&ranges, 0x61), Some(TilePass::Foreground));
+    assert_eq!(tile_pass(&ranges, 0x21), None);
+    assert_eq!(tile_pass(&ranges, 0x80), None);
+
+    let mut attrib = [0u8; 0x100];
+    attrib[1] = 0x41;
+    attrib[2] = 0x61;
+    attrib[3] = 0x43;
+    let mut map = Map { width: 3, height: 2, tiles: vec![0, 1, 2, 3, 0, 1], attrib, revision: 0 };
+
+    let mut mesh = TileMesh::new();
+    mesh.update(&map, &ranges);
+    let front: Vec<_> = mesh.tiles(TilePass::Foreground, 0..3, 0..2).collect();
+    assert_eq!(front, vec![(1, 0, 1), (2, 0, 2), (2, 1, 1)]);
+    let front: Vec<_> = mesh.tiles(TilePass::Foreground, 2..3, 1..5).collect();
+    assert_eq!(front, vec![(2, 1, 1)]);
+
+    // <CMP turns the snack into a water tile
+    map.change_tile(0, 1, 2);
+    mesh.update(&map, &ranges);
+    assert_eq!(mesh.tiles(TilePass::Snack, 0..3, 0..2).count(), 0);
+    assert_eq!(mesh.tiles(TilePass::Foreground, 0..1, 0..2).collect::<Vec<_>>(), vec![(0, 1, 2)]);
+}
+
+#[test]
+fn test_attribute_bounds() {
+    let mut attrib = [0u8; 0x100];
+    attrib[1] = 0x41;
+
+    let mut tiles = vec![0u8; 500 * 500];
+    tiles[500 * 500 - 1] = 1;
+    let map = Map { width: 500, height: 500, tiles, attrib, revision: 0 };
+    assert_eq!(map.get_attribute(499, 499), 0x41);
+    assert_eq!(map.get_attribute(500, 499), 0);
+    assert_eq!(map.get_attribute(499, 500), 0);
+
+    let map = Map { width: 1, height: 1, tiles: vec![1], attrib, revision: 0 };
+    assert_eq!(map.get_attribute(0, 0), 0x41);
+    assert_eq!(map.get_attribute(-1, 0), 0);
+    assert_eq!(map.get_attribute(1, 1), 0);
+
+    // corrupt PXM, no tiles at all
+    let map = Map { width: 0, height: 0, tiles: Vec::new(), attrib, revision: 0 };
+    assert_eq!(map.get_attribute(0, 0), 0);
+}
+
+#[test]
+fn test_attribute_edit() {
+    let mut map = Map { width: 2, height: 1, tiles: vec![0, 1], attrib: [0u8; 0x100], revision: 0 };
+    assert!(!map.is_solid(1, 0));
+
+    map.set_attribute(1, 0x41);
+    assert!(map.is_solid(1, 0));
+    assert_eq!(map.revision, 1);
+
+    let mut pxa = Vec::new();
+    map.write_attributes(&mut pxa).unwrap();
+    assert_eq!(pxa.len(), 0x100);
+    assert_eq!(pxa[1], 0x41);
+
+    assert_eq!(attribute_name(0x42), Some("spike"));
+    assert_eq!(attribute_name(0x21), None);
+}
+
+#[test]
+fn test_thumbnail() {
+    // tile 0 is red and tile 1 green, tile 2 has no draw pass
+    let mut tileset = RgbaImage::new(48, 16);
+    for (x, _, pixel) in tileset.enumerate_pixels_mut() {
+        let color = if x < 16 { [255, 0, 0, 255] } else { [0, 255, 0, 255] };
+        pixel.0.copy_from_slice(&color);
+    }
+
+    let mut attrib = [0u8; 0x100];
+    attrib[2] = 0x10;
+    let map = Map { width: 30, height: 2, tiles: [vec![0u8; 15], vec![1u8; 15], vec![2u8; 30]].concat(), attrib, revision: 0 };
+    let ranges = [TilePassRange { first: 0, last: 0, pass: TilePass::Background }];
+
+    let thumbnail = map.render_thumbnail(&tileset, &ranges, 160, 120);
+    assert_eq!(thumbnail.dimensions(), (160, 10));
+    assert_eq!(thumbnail.get_pixel(0, 0).0, [255, 0, 0, 255]);
+    assert_eq!(thumbnail.get_pixel(159, 0).0, [0, 255, 0, 255]);
+    assert_eq!(thumbnail.get_pixel(0, 9).0, [0, 0, 0, 255], "no draw pass for tile 2");
+
+    let small = Map { width: 2, height: 1, tiles: vec![0, 1], attrib: [0; 0x100], revision: 0 };
+    assert_eq!(small.render_thumbnail(&tileset, &ranges, 160, 120).dimensions(), (32, 16), "never scaled up");
+}
@@ -0,0 +1,142 @@
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+pub const KIB: usize = 1024;
+pub const MIB: usize = 1024 * 1024;
+
+struct Entry {
+    bytes: usize,
+    /// Value of the use clock when the entry was used last.
+    last_used: u64,
+    /// Pinned entries are never picked for eviction.
+    pinned: bool,
+}
+
+/// Bytes held by the entries of a cache, updated by the cache's insert/remove paths.
+/// Picks the least recently used unpinned entries to evict once the total goes over the soft cap.
+pub struct MemoryTracker<K> {
+    entries: HashMap<K, Entry>,
+    total: usize,
+    /// Counts uses, so the entries can be ordered without looking at the time.
+    clock: u64,
+}
+
+impl<K: Hash + Eq + Clone> MemoryTracker<K> {
+    pub fn new() -> MemoryTracker<K> {
+        MemoryTracker {
+            entries: HashMap::new(),
+            total: 0,
+            clock: 0,
+        }
+    }
+
+    /// Total bytes of all entries.
+    pub fn total(&self) -> usize {
+        self.total
+    }
+
+    /// Current value of the use clock, see `last_used`.
+    pub fn clock(&self) -> u64 {
+        self.clock
+    }
+
+    pub fn last_used<Q: ?Sized + Hash + Eq>(&self, key: &Q) -> Option<u64> where K: Borrow<Q> {
+        self.entries.get(key).map(|entry| entry.last_used)
+    }
+
+    /// Adds an entry as the most recently used one, replacing the previous entry of the key.
+    pub fn insert(&mut self, key: K, bytes: usize, pinned: bool) {
+        self.clock += 1;
+        let entry = Entry { bytes, last_used: self.clock, pinned };
+
+        if let Some(old) = self.entries.insert(key, entry) {
+            self.total -= old.bytes;
+        }
+        self.total += bytes;
+    }
+
+    pub fn remove<Q: ?Sized + Hash + Eq>(&mut self, key: &Q) where K: Borrow<Q> {
+        if let Some(old) = self.entries.remove(key) {
+            self.total -= old.bytes;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.total = 0;
+    }
+
+    /// Marks the entry as the most recently used one.
+    pub fn touch<Q: ?Sized + Hash + Eq>(&mut self, key: &Q) where K: Borrow<Q> {
+        self.clock += 1;
+        if let Some(entry) = self.entries.get_mut(key) {
+            entry.last_used = self.clock;
+        }
+    }
+
+    /// Entries to evict to get the total down to `cap`, least recently used first. Entries `keep` returns true for
+    /// are skipped like the pinned ones, if only those are left it stops short since the cap is a soft one.
+    pub fn over_cap<F: Fn(&K) -> bool>(&self, cap: usize, keep: F) -> Vec<K> {
+        if self.total <= cap {
+            return Vec::new();
+        }
+
+        let mut candidates: Vec<(&K, &Entry)> = self.entries.iter()
+            .filter(|(key, entry)| !entry.pinned && !keep(key))
+            .collect();
+        candidates.sort_by_key(|(_, entry)| entry.last_used);
+
+        let mut total = self.total;
+        let mut victims = Vec::new();
+        for (key, entry) in candidates {
+            if total <= cap {
+                break;
+            }
+
+            total -= entry.bytes;
+            victims.push(key.clone());
+        }
+
+        victims
+    }
+}
+
+/// Formats a byte count for the performance HUD.
+pub fn format_bytes(bytes: usize) -> String {
+    if bytes >= MIB {
+        format!("{:.1} MiB", bytes as f32 / MIB as f32)
+    } else {
+        format!("{:.1} KiB", bytes as f32 / KIB as f32)
+    }
+}
+
+#[test]
+fn test_eviction_order() {
+    let mut tracker = MemoryTracker::new();
+    tracker.insert("MyChar", 100, true);
+    tracker.insert("Stage/PrtCave", 100, false);
+    tracker.insert("Npc/NpcCemet", 100, false);
+    tracker.insert("bkBlue", 100, false);
+    assert_eq!(tracker.total(), 400);
+
+    // used since, so it goes after the others
+    tracker.touch(&"Stage/PrtCave");
+
+    assert!(tracker.over_cap(400, |_| false).is_empty());
+    assert_eq!(tracker.over_cap(250, |_| false), vec!["Npc/NpcCemet", "bkBlue"]);
+    assert_eq!(tracker.over_cap(250, |&name| name == "Npc/NpcCemet"), vec!["bkBlue", "Stage/PrtCave"]);
+
+    // a tiny cap evicts everything but the pinned entry
+    let victims = tracker.over_cap(10, |_| false);
+    assert_eq!(victims, vec!["Npc/NpcCemet", "bkBlue", "Stage/PrtCave"]);
+    for victim in victims.iter() {
+        tracker.remove(victim);
+    }
+    assert_eq!(tracker.total(), 100);
+    assert!(tracker.over_cap(10, |_| false).is_empty());
+
+    // replacing an entry doesn't count it twice
+    tracker.insert("MyChar", 50, true);
+    assert_eq!(tracker.total(), 50);
+}
@@ -0,0 +1,125 @@
+use std::fs;
+use std::path::PathBuf;
+
+use crate::ggez::{Context, filesystem, GameError};
+use crate::ggez::vfs::PhysicalFS;
+use crate::SharedGameState;
+use crate::stats::Stats;
+use crate::text_script::TextScriptExtensions;
+
+/// Contents of an optional `mod.json` file in the root of a mod directory.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct ModManifest {
+    pub name: String,
+    pub author: String,
+    pub description: String,
+    /// Event run when starting the mod, instead of the intro event.
+    pub start_event: Option<u16>,
+    /// Enables custom TSC opcodes, see `TextScriptVM::extensions`.
+    pub tsc_extensions: bool,
+    /// Names of the extensions left disabled even with `tsc_extensions` set, see `TextScriptExtensions`.
+    pub disabled_tsc_extensions: Vec<String>,
+    // todo: custom title song and graphic, once there's a title screen to show them on
+}
+
+#[derive(Clone, Debug)]
+pub struct ModInfo {
+    /// Name of the mod directory, used to namespace the saves.
+    pub id: String,
+    pub path: PathBuf,
+    pub manifest: ModManifest,
+}
+
+impl ModManifest {
+    pub fn tsc_extensions(&self) -> TextScriptExtensions {
+        if !self.tsc_extensions {
+            return TextScriptExtensions(0);
+        }
+
+        let mut extensions = TextScriptExtensions::all();
+        for name in self.disabled_tsc_extensions.iter() {
+            if !extensions.set_by_name(name, false) {
+                log::warn!("Unknown TSC extension: {}", name);
+            }
+        }
+
+        extensions
+    }
+}
+
+impl ModInfo {
+    fn load(path: PathBuf) -> Option<ModInfo> {
+        let id = path.file_name()?.to_string_lossy().to_string();
+        let manifest_path = path.join("mod.json");
+
+        let mut manifest = if manifest_path.is_file() {
+            match fs::read_to_string(&manifest_path).map_err(GameError::from)
+                .and_then(|data| serde_json::from_str(&data)
+                    .map_err(|e| GameError::from(e).in_file(&manifest_path.to_string_lossy()))) {
+                Ok(manifest) => manifest,
+                Err(e) => {
+                    log::warn!("Invalid manifest {:?}: {}", manifest_path, e);
+                    ModManifest::default()
+                }
+            }
+        } else {
+            ModManifest::default()
+        };
+
+        if manifest.name.is_empty() {
+            manifest.name = id.clone();
+        }
+
+        Some(ModInfo { id, path, manifest })
+    }
+}
+
+/// Lists the directories in `mods/` of every physical data directory, sorted by name.
+pub fn scan_mods(ctx: &Context) -> Vec<ModInfo> {
+    let mut mods = Vec::new();
+
+    for root in filesystem::physical_roots(ctx) {
+        let entries = match fs::read_dir(root.join("mods")) {
+            Ok(entries) => entries,
+            Err(_) => { continue; }
+        };
+
+        for entry in entries.filter_map(|e| e.ok()) {
+            let path = entry.path();
+            if path.is_dir() {
+                if let Some(info) = ModInfo::load(path) {
+                    mods.push(info);
+                }
+            }
+        }
+    }
+
+    mods.sort_by_key(|m| m.manifest.name.to_lowercase());
+    mods
+}
+
+/// Unmounts the current mod (if any) and mounts the given one, `None` switches back to the base game.
+/// Everything loaded from the data files has to be reloaded afterwards, see `LoadingScene`.
+pub fn switch_mod(state: &mut SharedGameState, ctx: &mut Context, new_mod: Option<ModInfo>) {
+    state.flush_stats();
+
+    if let Some(old_mod) = state.current_mod.take() {
+        log::info!("Unmounting mod: {}", old_mod.manifest.name);
+        filesystem::unmount_overlay(ctx);
+    }
+
+    state.textscript_vm.extensions = new_mod.as_ref().map_or(TextScriptExtensions(0), |m| m.manifest.tsc_extensions());
+
+    if let Some(new_mod) = new_mod {
+        log::info!("Mounting mod: {} ({:?})", new_mod.manifest.name, new_mod.path);
+        filesystem::mount_overlay(ctx, Box::new(PhysicalFS::new(&new_mod.path, true)));
+        state.current_mod = Some(new_mod);
+    }
+
+    // every mod keeps its own statistics
+    state.stats = Stats::load(state.current_mod.as_ref().map(|m| m.id.as_str()));
+
+    // cached textures might come from the previous mod
+    state.texture_set.clear();
+}
@@ -0,0 +1,66 @@
+/// HP each stage boss starts with, by boss number. Set in the first action of the boss in the original.
+const BOSS_LIFE: [u16; 10] = [
+    0,
+    400, // Omega
+    300, // Balfrog
+    700, // Monster X
+    650, // Core
+    400, // Ironhead
+    500, // Dragon Sisters
+    700, // Undead Core
+    700, // Heavy Press
+    800, // Ballos
+];
+
+/// The stage's multi-part boss (`gBoss` in the original), picked by the boss number of the stage.
+/// Only the main part is tracked so far, for `<BOA` and the boss bar of `<BSL0000`.
+// todo: the boss AIs, until then the bosses don't move or take damage
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StageBoss {
+    pub boss_no: usize,
+    pub action_num: u16,
+    pub life: u16,
+    pub alive: bool,
+    /// Position of the main part.
+    pub x: isize,
+    pub y: isize,
+}
+
+impl StageBoss {
+    /// Stage entry, 0 and unknown boss numbers are stages without a boss.
+    pub fn new(boss_no: usize) -> StageBoss {
+        let life = BOSS_LIFE.get(boss_no).copied().unwrap_or(0);
+
+        StageBoss {
+            boss_no,
+            action_num: 0,
+            life,
+            alive: life > 0,
+            x: 0,
+            y: 0,
+        }
+    }
+
+    /// The Core, the Undead Core and Ballos go out with an explosion flash.
+    pub fn flashes_on_death(&self) -> bool {
+        matches!(self.boss_no, 4 | 7 | 9)
+    }
+
+    /// HP of the main part, None once it's dead or if there's no boss.
+    pub fn life(&self) -> Option<u16> {
+        if self.alive { Some(self.life) } else { None }
+    }
+}
+
+#[test]
+fn test_stage_boss() {
+    let balfrog = StageBoss::new(2);
+    assert_eq!(balfrog.life(), Some(300));
+
+    assert_eq!(StageBoss::new(0).life(), None);
+    assert_eq!(StageBoss::new(40).life(), None);
+
+    let mut omega = StageBoss::new(1);
+    omega.alive = false;
+    assert_eq!(omega.life(), None);
+}
@@ -75,7 +75,7 @@ impl NPC {
                     self.vel_x = 0;
                 }
 
-                if state.game_rng.range(0..120) == 10 {
+                if state.game_rng.range(0..=120) == 10 {
                     self.action_num = 2;
                     self.action_counter = 0;
                     self.anim_num = 1;
@@ -232,7 +232,7 @@ impl NPC {
                     self.vel_x = 0;
                 }
 
-                if state.game_rng.range(0..120) == 10 {
+                if state.game_rng.range(0..=120) == 10 {
                     self.action_num = 2;
                     self.action_counter = 0;
                     self.anim_num = 1;
@@ -371,14 +371,14 @@ impl NPC {
                     self.anim_rect = state.constants.npc.n062_kazuma_computer[self.anim_num as usize];
                 }
 
-                if state.game_rng.range(0..80) == 1 {
+                if state.game_rng.range(0..=80) == 1 {
                     self.action_num = 2;
                     self.action_counter = 0;
                     self.anim_num = 1;
                     self.anim_rect = state.constants.npc.n062_kazuma_computer[self.anim_num as usize];
                 }
 
-                if state.game_rng.range(0..120) == 10 {
+                if state.game_rng.range(0..=120) == 10 {
                     self.action_num = 3;
                     self.action_counter = 0;
                     self.anim_num = 2;
@@ -418,7 +418,7 @@ impl NPC {
 
         match self.action_num {
             1 => {
-                if state.game_rng.range(0..120) == 10 {
+                if state.game_rng.range(0..=120) == 10 {
                     self.action_num = 2;
                     self.action_counter = 0;
                     self.anim_num = 1;
@@ -1,6 +1,6 @@
 use nalgebra::clamp;
 
-use crate::common::Direction;
+use crate::common::{Direction, fix9_mul_div};
 use crate::ggez::GameResult;
 use crate::npc::NPC;
 use crate::player::Player;
@@ -37,7 +37,7 @@ impl NPC {
                 }
             }
             1 => {
-                self.vel_x = (self.vel_x * 7) / 8;
+                self.vel_x = fix9_mul_div(self.vel_x, 7, 8);
 
                 self.action_counter += 1;
                 if self.action_counter > 40 {
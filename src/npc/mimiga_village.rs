@@ -187,7 +187,7 @@ impl NPC {
 
         match self.action_num {
             1 => {
-                if state.game_rng.range(0..120) == 10 {
+                if state.game_rng.range(0..=120) == 10 {
                     self.action_num = 2;
                     self.action_counter = 0;
                     self.anim_num = 1;
@@ -222,7 +222,7 @@ impl NPC {
             }
             2 => {
                 self.anim_num = 0;
-                if state.game_rng.range(0..120) == 10 {
+                if state.game_rng.range(0..=120) == 10 {
                     self.action_num = 3;
                     self.action_counter = 0;
                     self.anim_num = 1;
@@ -1,5 +1,5 @@
 use crate::caret::CaretType;
-use crate::common::Direction;
+use crate::common::{Direction, fix9_mul_div};
 use crate::ggez::GameResult;
 use crate::npc::{NPC, NPCMap};
 use crate::player::Player;
@@ -38,17 +38,17 @@ impl NPC {
     pub(crate) fn tick_n004_smoke(&mut self, state: &mut SharedGameState) -> GameResult {
         if self.action_num == 0 {
             self.action_num = 1;
-            self.anim_num = state.game_rng.range(0..4) as u16;
-            self.anim_counter = state.game_rng.range(0..3) as u16;
+            self.anim_num = state.game_rng.range(0..=4) as u16;
+            self.anim_counter = state.game_rng.range(0..=3) as u16;
 
             if self.direction == Direction::Left || self.direction == Direction::Up {
-                let angle = state.game_rng.range(0..31415) as f32 / 5000.0;
-                self.vel_x = (angle.cos() * state.game_rng.range(0x200..0x5ff) as f32) as isize;
-                self.vel_y = (angle.sin() * state.game_rng.range(0x200..0x5ff) as f32) as isize;
+                let angle = state.game_rng.range(0..=31415) as f32 / 5000.0;
+                self.vel_x = (angle.cos() * state.game_rng.range(0x200..=0x5ff) as f32) as isize;
+                self.vel_y = (angle.sin() * state.game_rng.range(0x200..=0x5ff) as f32) as isize;
             }
         } else {
-            self.vel_x = (self.vel_x * 20) / 21;
-            self.vel_y = (self.vel_y * 20) / 21;
+            self.vel_x = fix9_mul_div(self.vel_x, 20, 21);
+            self.vel_y = fix9_mul_div(self.vel_y, 20, 21);
 
             self.x += self.vel_x;
             self.y += self.vel_y;
@@ -92,10 +92,10 @@ impl NPC {
 
                             npc.cond.set_alive(true);
                             npc.direction = Direction::Left;
-                            npc.x = self.x + state.game_rng.range(-12..12) as isize * 0x200;
-                            npc.y = self.y + state.game_rng.range(-12..12) as isize * 0x200;
-                            npc.vel_x = state.game_rng.range(-0x155..0x155) as isize;
-                            npc.vel_y = state.game_rng.range(-0x600..0) as isize;
+                            npc.x = self.x + state.game_rng.range(-12..=12) as isize * 0x200;
+                            npc.y = self.y + state.game_rng.range(-12..=12) as isize * 0x200;
+                            npc.vel_x = state.game_rng.range(-0x155..=0x155) as isize;
+                            npc.vel_y = state.game_rng.range(-0x600..=0) as isize;
 
                             state.new_npcs.push(npc);
                         }
@@ -105,7 +105,7 @@ impl NPC {
                 }
 
                 self.anim_num = 0;
-                if state.game_rng.range(0..30) == 0 {
+                if state.game_rng.range(0..=30) == 0 {
                     self.action_num = 2;
                 }
             }
@@ -173,7 +173,7 @@ impl NPC {
 
         match self.action_num {
             1 => {
-                let rand = state.game_rng.range(0..30);
+                let rand = state.game_rng.range(0..=30);
 
                 if rand < 10 {
                     self.action_num = 2;
@@ -183,7 +183,7 @@ impl NPC {
                     self.action_num = 4;
                 }
 
-                self.action_counter = state.game_rng.range(0x10..0x40) as u16;
+                self.action_counter = state.game_rng.range(0x10..=0x40) as u16;
                 self.anim_counter = 0;
             }
             2 => {
@@ -249,8 +249,8 @@ impl NPC {
                     npc.direction = Direction::Left;
                     npc.x = self.x;
                     npc.y = self.y;
-                    npc.vel_x = state.game_rng.range(-0x155..0x155) as isize;
-                    npc.vel_y = state.game_rng.range(-0x600..0) as isize;
+                    npc.vel_x = state.game_rng.range(-0x155..=0x155) as isize;
+                    npc.vel_y = state.game_rng.range(-0x600..=0) as isize;
 
                     state.new_npcs.push(npc);
                 }
@@ -332,7 +332,7 @@ impl NPC {
                     self.action_num = 1;
                     self.anim_counter = 0;
 
-                    if state.game_rng.range(0..120) == 10 {
+                    if state.game_rng.range(0..=120) == 10 {
                         self.action_num = 2;
                         self.action_counter = 8;
                         self.anim_rect = state.constants.npc.n030_hermit_gunsmith[1];
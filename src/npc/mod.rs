@@ -6,12 +6,14 @@ use std::io::Cursor;
 use bitvec::vec::BitVec;
 use byteorder::{LE, ReadBytesExt};
 use itertools::Itertools;
+use smallvec::SmallVec;
 
 use crate::{bitfield, SharedGameState};
 use crate::caret::CaretType;
 use crate::common::{Condition, Rect};
 use crate::common::Direction;
 use crate::common::Flag;
+use crate::engine_constants::NPCBoundsOverride;
 use crate::entity::GameEntity;
 use crate::frame::Frame;
 use crate::ggez::{Context, GameResult};
@@ -20,6 +22,7 @@ use crate::physics::PhysicalEntity;
 use crate::player::Player;
 use crate::str;
 
+pub mod boss;
 pub mod characters;
 pub mod egg_corridor;
 pub mod first_cave;
@@ -28,7 +31,7 @@ pub mod misc;
 pub mod pickups;
 
 bitfield! {
-  #[derive(Clone, Copy)]
+  #[derive(Clone, Copy, Serialize, Deserialize)]
   pub struct NPCFlag(u16);
   impl Debug;
 
@@ -42,7 +45,7 @@ bitfield! {
   pub rear_and_top_not_hurt, set_rear_and_top_not_hurt: 7;
   pub event_when_touched, set_event_when_touched: 8;
   pub event_when_killed, set_event_when_killed: 9;
-  pub flag_x400, set_flag_x400: 10;
+  pub buoyant, set_buoyant: 10; // 0x400, unused by the original, floats up in water
   pub appear_when_flag_set, set_appear_when_flag_set: 11;
   pub spawn_facing_right, set_spawn_facing_right: 12;
   pub interactable, set_interactable: 13;
@@ -50,7 +53,53 @@ bitfield! {
   pub show_damage, set_show_damage: 15;
 }
 
-#[derive(Debug, Clone, Copy)]
+/// Upward acceleration of the buoyant NPCs in water, outweighs the usual gravity of 0x40.
+const BUOYANCY: isize = 0x60;
+/// Buoyancy doesn't make the NPCs rise faster than this.
+const MAX_RISE_SPEED: isize = 0x200;
+
+/// Size of the NPC list, same as vanilla. NPCs spawned while there's no free slot from their start index up
+/// are dropped with a warning, the ones already in the list are never replaced.
+pub const NPC_LIST_MAX_CAP: usize = 0x200;
+/// First slot tried for the NPCs spawned by `<SNP`, like vanilla.
+pub const SCRIPT_NPC_START: u16 = 0x100;
+
+/// NPCs which activate near the camera start their AI within half a screen of its edges.
+const ACTIVATION_SCREENS: f32 = 0.5;
+/// NPCs which are deleted off-screen go once they're a whole screen past its edges.
+const DESPAWN_SCREENS: f32 = 1.0;
+
+/// What happens to an NPC away from the camera, evaluated by `NPCMap::apply_off_screen_policies`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OffScreenPolicy {
+    /// Alive and ticking wherever it is, most map NPCs. Some puzzles rely on them wandering off-screen.
+    Persistent,
+    /// Doesn't start its AI until it gets near the camera, ticks wherever it is afterwards.
+    ActivateNearCamera,
+    /// Projectiles and effects, deleted once they get too far from the camera so they don't pile up on large maps.
+    DeleteOffScreen,
+}
+
+impl OffScreenPolicy {
+    pub fn name(self) -> &'static str {
+        match self {
+            OffScreenPolicy::Persistent => "Persistent",
+            OffScreenPolicy::ActivateNearCamera => "Activates near camera",
+            OffScreenPolicy::DeleteOffScreen => "Deleted off-screen",
+        }
+    }
+}
+
+/// Despawn timer handled by the NPC base, the NPC blinks for a while before it disappears.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct NPCLifetime {
+    /// Age from which the NPC is drawn every other 2 ticks.
+    pub blink_at: u16,
+    /// Age after which the NPC is removed, see `Settings::allow_despawn_limit`.
+    pub despawn_at: u16,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct NPC {
     pub id: u16,
     pub npc_type: u16,
@@ -80,6 +129,14 @@ pub struct NPC {
     pub action_counter2: u16,
     pub anim_counter: u16,
     pub anim_rect: Rect<usize>,
+    /// Ticks the NPC has been active for.
+    pub age: u16,
+    pub lifetime: Option<NPCLifetime>,
+    /// Set once an NPC which waits to be on screen has started its AI.
+    pub activated: bool,
+    /// Set at spawn for the types from `NPC::deletes_off_screen`, AIs can change it.
+    #[serde(default)]
+    pub delete_off_screen: bool,
 }
 
 impl NPC {
@@ -90,10 +147,83 @@ impl NPC {
             0
         }
     }
+
+    /// Types which despawn by themselves after a while.
+    pub fn default_lifetime(npc_type: u16) -> Option<NPCLifetime> {
+        match npc_type {
+            // despawns on its animation loop after 500 ticks, the limit is for the ones whose animation is stuck
+            1 => Some(NPCLifetime { blink_at: 400, despawn_at: 518 }),
+            _ => None,
+        }
+    }
+
+    /// Types which don't start their AI until they get within a screen of the camera.
+    /// Ones which use the RNG on their first tick are left out, it would shift the game RNG sequence.
+    pub fn activates_in_view_only(npc_type: u16) -> bool {
+        matches!(npc_type, 5 | 6 | 64)
+    }
+
+    /// Types whose AI is implemented, the rest stand still. Has to match `tick`.
+    pub fn has_ai(npc_type: u16) -> bool {
+        matches!(npc_type, 0..=8 | 15..=18 | 20..=22 | 27 | 30 | 32 | 34 | 37..=39 | 41 | 43 | 46 | 52 | 55
+            | 59..=65 | 70..=72 | 74 | 75 | 77..=79 | 211)
+    }
+
+    /// Projectiles and effects, which are deleted once they leave the area around the camera.
+    // todo: add the other projectiles as their AIs get implemented
+    pub fn deletes_off_screen(npc_type: u16) -> bool {
+        match npc_type {
+            // smoke, Balrog's energy shot, Omega's projectiles, Basu's projectile, Balfrog's projectile
+            4 | 11 | 48 | 84 | 108 => true,
+            _ => false,
+        }
+    }
+
+    pub fn off_screen_policy(&self) -> OffScreenPolicy {
+        if self.delete_off_screen {
+            OffScreenPolicy::DeleteOffScreen
+        } else if NPC::activates_in_view_only(self.npc_type) {
+            OffScreenPolicy::ActivateNearCamera
+        } else {
+            OffScreenPolicy::Persistent
+        }
+    }
+
+    /// Floats the NPCs flagged as buoyant up while they're in water, the others sink as their AI has them.
+    pub fn apply_buoyancy(&mut self) {
+        if self.npc_flags.buoyant() && self.flags.in_water() && self.vel_y > -MAX_RISE_SPEED {
+            self.vel_y = (self.vel_y - BUOYANCY).max(-MAX_RISE_SPEED);
+        }
+    }
+
+    pub fn is_blinking(&self) -> bool {
+        match self.lifetime {
+            Some(lifetime) => self.age > lifetime.blink_at && !(self.age / 2).is_multiple_of(2),
+            None => false,
+        }
+    }
+
+    pub fn lifetime_expired(&self) -> bool {
+        match self.lifetime {
+            Some(lifetime) => self.age > lifetime.despawn_at,
+            None => false,
+        }
+    }
 }
 
 impl GameEntity<&mut Player> for NPC {
     fn tick(&mut self, state: &mut SharedGameState, player: &mut Player) -> GameResult {
+        if !self.activated {
+            // activated by `NPCMap::apply_off_screen_policies` once it's near the camera
+            if NPC::activates_in_view_only(self.npc_type) && state.settings.allow_view_activation() {
+                return Ok(());
+            }
+
+            self.activated = true;
+        }
+
+        self.age = self.age.saturating_add(1);
+
         match self.npc_type {
             0 => { self.tick_n000_null() }
             1 => { self.tick_n001_experience(state) }
@@ -142,6 +272,10 @@ impl GameEntity<&mut Player> for NPC {
             _ => { Ok(()) }
         }?;
 
+        if self.lifetime_expired() && state.settings.allow_despawn_limit() {
+            self.cond.set_alive(false);
+        }
+
         if self.shock > 0 {
             self.shock -= 1;
         }
@@ -150,7 +284,7 @@ impl GameEntity<&mut Player> for NPC {
     }
 
     fn draw(&self, state: &mut SharedGameState, ctx: &mut Context, frame: &Frame) -> GameResult {
-        if !self.cond.alive() || self.cond.hidden() {
+        if !self.cond.alive() || self.cond.hidden() || self.is_blinking() {
             return Ok(());
         }
 
@@ -253,7 +387,10 @@ pub struct NPCMap {
     /// A sorted pool of used IDs to used to iterate over NPCs in order, as original game does.
     pub npc_ids: BTreeSet<u16>,
     /// Do not iterate over this directly outside render pipeline.
+    /// NPCs have to be added with `insert` and removed with `garbage_collect` to keep the event index valid.
     pub npcs: HashMap<u16, RefCell<NPC>>,
+    /// IDs of the NPCs with given event number, sorted like `npc_ids`. Event numbers never change after spawning.
+    event_index: HashMap<u16, SmallVec<[u16; 4]>>,
 }
 
 impl NPCMap {
@@ -261,13 +398,66 @@ impl NPCMap {
     pub fn new() -> NPCMap {
         NPCMap {
             npc_ids: BTreeSet::new(),
-            npcs: HashMap::with_capacity(256),
+            npcs: HashMap::with_capacity(NPC_LIST_MAX_CAP),
+            event_index: HashMap::new(),
         }
     }
 
     pub fn clear(&mut self) {
         self.npc_ids.clear();
         self.npcs.clear();
+        self.event_index.clear();
+    }
+
+    /// Adds the NPC under its ID, replacing the NPC which had the same ID before.
+    pub fn insert(&mut self, npc: NPC) {
+        let id = npc.id;
+        if let Some(old) = self.npcs.remove(&id) {
+            self.unindex(id, old.borrow().event_num);
+        }
+
+        let ids = self.event_index.entry(npc.event_num).or_default();
+        if let Err(pos) = ids.binary_search(&id) {
+            ids.insert(pos, id);
+        }
+
+        self.npc_ids.insert(id);
+        self.npcs.insert(id, RefCell::new(npc));
+
+        #[cfg(test)]
+        self.check_event_index();
+    }
+
+    fn unindex(&mut self, id: u16, event_num: u16) {
+        if let Some(ids) = self.event_index.get_mut(&event_num) {
+            if let Ok(pos) = ids.binary_search(&id) {
+                ids.remove(pos);
+            }
+
+            if ids.is_empty() {
+                self.event_index.remove(&event_num);
+            }
+        }
+    }
+
+    /// IDs of the NPCs with given event number in the order the NPC list is processed, dead ones included.
+    pub fn npcs_by_event(&self, event_num: u16) -> &[u16] {
+        self.event_index.get(&event_num).map_or(&[], |ids| ids.as_slice())
+    }
+
+    /// Compares the event index against a scan of the whole list.
+    #[cfg(test)]
+    fn check_event_index(&self) {
+        let mut scanned: HashMap<u16, Vec<u16>> = HashMap::new();
+        for id in self.npc_ids.iter() {
+            let event_num = self.npcs[id].borrow().event_num;
+            scanned.entry(event_num).or_default().push(*id);
+        }
+
+        assert_eq!(scanned.len(), self.event_index.len());
+        for (event_num, ids) in scanned.iter() {
+            assert_eq!(self.npcs_by_event(*event_num), ids.as_slice());
+        }
     }
 
     pub fn create_npc_from_data(&mut self, table: &NPCTable, data: &NPCData) -> &mut NPC {
@@ -308,10 +498,13 @@ impl NPCMap {
             action_counter2: 0,
             anim_counter: 0,
             anim_rect: Rect::new(0, 0, 0, 0),
+            age: 0,
+            lifetime: NPC::default_lifetime(data.npc_type),
+            activated: false,
+            delete_off_screen: NPC::deletes_off_screen(data.npc_type),
         };
 
-        self.npc_ids.insert(data.id);
-        self.npcs.insert(data.id, RefCell::new(npc));
+        self.insert(npc);
 
         self.npcs.get_mut(&data.id).unwrap().get_mut()
     }
@@ -354,9 +547,28 @@ impl NPCMap {
             action_counter2: 0,
             anim_counter: 0,
             anim_rect: Rect::new(0, 0, 0, 0),
+            age: 0,
+            lifetime: NPC::default_lifetime(npc_type),
+            activated: false,
+            delete_off_screen: NPC::deletes_off_screen(npc_type),
         }
     }
 
+    /// `<SNP`, at the center of the tile and facing `direction` (4 turns it towards the player).
+    /// Has neither an event number nor a flag, the NPC flags come from npc.tbl.
+    pub fn create_script_npc(npc_type: u16, tile_x: isize, tile_y: isize, direction: usize, player_x: isize, table: &NPCTable) -> NPC {
+        let mut npc = NPCMap::create_npc(npc_type, table);
+        npc.cond.set_alive(true);
+        npc.x = tile_x * 16 * 0x200;
+        npc.y = tile_y * 16 * 0x200;
+
+        if let Some(dir) = Direction::from_int_facing(direction, npc.x, player_x) {
+            npc.direction = dir;
+        }
+
+        npc
+    }
+
     pub fn garbage_collect(&mut self) {
         let dead_npcs = self.npcs.iter().filter_map(|(&id, npc_cell)| {
             if !npc_cell.borrow().cond.alive() {
@@ -368,37 +580,52 @@ impl NPCMap {
 
         for npc_id in dead_npcs.iter() {
             self.npc_ids.remove(npc_id);
-            self.npcs.remove(npc_id);
+            if let Some(npc) = self.npcs.remove(npc_id) {
+                self.unindex(*npc_id, npc.borrow().event_num);
+            }
         }
+
+        #[cfg(test)]
+        self.check_event_index();
     }
 
     pub fn remove_by_event(&mut self, event_num: u16, game_flags: &mut BitVec) {
-        for npc_cell in self.npcs.values_mut() {
-            let mut npc = npc_cell.borrow_mut();
+        for npc_id in self.npcs_by_event(event_num) {
+            if let Some(npc_cell) = self.npcs.get(npc_id) {
+                let mut npc = npc_cell.borrow_mut();
 
-            if npc.event_num == event_num {
                 npc.cond.set_alive(false);
                 game_flags.set(npc.flag_num as usize, true);
             }
         }
     }
 
-    pub fn allocate_id(&mut self, start: u16) -> u16 {
-        for i in start..(u16::MAX) {
-            if !self.npc_ids.contains(&i) {
-                return i;
+    /// First free ID from `start`, None when the list is full from there.
+    pub fn allocate_id(&mut self, start: u16) -> Option<u16> {
+        (start..NPC_LIST_MAX_CAP as u16).find(|id| !self.npc_ids.contains(id))
+    }
+
+    /// Adds the NPC under the first free ID from `start`, see `NPC_LIST_MAX_CAP` for what happens when there's none.
+    pub fn spawn(&mut self, mut npc: NPC, start: u16) -> Option<u16> {
+        match self.allocate_id(start) {
+            Some(id) => {
+                npc.id = id;
+                self.insert(npc);
+                Some(id)
+            }
+            None => {
+                log::warn!("NPC list is full, type {} has not been spawned.", npc.npc_type);
+                None
             }
         }
-
-        unreachable!()
     }
 
     pub fn create_death_effect(&self, x: isize, y: isize, radius: usize, count: usize, state: &mut SharedGameState) {
         let radius = radius as i32 / 0x200;
 
         for _ in 0..count {
-            let off_x = state.game_rng.range(-radius..radius) as isize * 0x200;
-            let off_y = state.game_rng.range(-radius..radius) as isize * 0x200;
+            let off_x = state.game_rng.range(-radius..=radius) as isize * 0x200;
+            let off_y = state.game_rng.range(-radius..=radius) as isize * 0x200;
 
             let mut npc = NPCMap::create_npc(4, &state.npc_table);
 
@@ -427,7 +654,7 @@ impl NPCMap {
                 };
 
                 if npc.exp != 0 {
-                    //if state.game_rng.range(0..4) == 0 {
+                    //if state.game_rng.range(0..=4) == 0 {
                     // health
 
                     //} else {
@@ -457,7 +684,7 @@ impl NPCMap {
                     //}
                 }
 
-                state.game_flags.set(npc.flag_num as usize, true);
+                state.set_flag(npc.flag_num as usize, true);
 
                 // todo vanish / show damage
 
@@ -468,21 +695,67 @@ impl NPCMap {
         self.process_npc_changes(state);
     }
 
-    pub fn process_npc_changes(&mut self, state: &mut SharedGameState) {
-        if !state.new_npcs.is_empty() {
-            for mut npc in state.new_npcs.iter_mut() {
-                let id = if npc.id == 0 {
-                    self.allocate_id(npc.get_start_index())
-                } else {
-                    npc.id
-                };
+    /// Ticks every alive NPC in ID order. The list can't change during the pass, so:
+    /// - NPCs spawned by the ticked ones go through `state.new_npcs` and are added by `process_npc_changes`
+    ///   after the pass, they tick for the first time on the next one (like in vanilla).
+    /// - NPCs killed during the pass stay in the list marked as dead and aren't ticked anymore if they haven't been yet,
+    ///   `garbage_collect` removes them afterwards.
+    ///
+    /// Other NPCs can be borrowed from the callback, but not the one being ticked.
+    pub fn tick_npcs<F: FnMut(&mut NPC) -> GameResult>(&self, mut tick: F) -> GameResult {
+        for npc_id in self.npc_ids.iter() {
+            if let Some(npc_cell) = self.npcs.get(npc_id) {
+                let mut npc = npc_cell.borrow_mut();
+
+                if npc.cond.alive() {
+                    tick(&mut npc)?;
+                }
+            }
+        }
 
-                npc.id = id;
-                self.npc_ids.insert(id);
-                self.npcs.insert(id, RefCell::new(*npc));
+        Ok(())
+    }
+
+    /// Deletes and activates the NPCs depending on how far they are from the camera, has to be called right before
+    /// `tick_npcs`. The NPCs which are deleted stay in the list until `garbage_collect`.
+    pub fn apply_off_screen_policies(&self, frame: &Frame, canvas_size: (f32, f32)) {
+        let activation_area = frame.screens_around(canvas_size, ACTIVATION_SCREENS);
+        let despawn_area = frame.screens_around(canvas_size, DESPAWN_SCREENS);
+
+        for npc_id in self.npc_ids.iter() {
+            if let Some(npc_cell) = self.npcs.get(npc_id) {
+                let mut npc = npc_cell.borrow_mut();
+                if !npc.cond.alive() {
+                    continue;
+                }
+
+                match npc.off_screen_policy() {
+                    OffScreenPolicy::DeleteOffScreen if !despawn_area.contains(npc.x, npc.y) => {
+                        npc.cond.set_alive(false);
+                    }
+                    OffScreenPolicy::ActivateNearCamera if !npc.activated && activation_area.contains(npc.x, npc.y) => {
+                        npc.activated = true;
+                    }
+                    _ => {}
+                }
             }
+        }
+    }
+
+    /// Adds the NPCs queued in `state.new_npcs`.
+    pub fn process_npc_changes(&mut self, state: &mut SharedGameState) {
+        self.spawn_queued(&mut state.new_npcs);
+    }
 
-            state.new_npcs.clear();
+    /// NPCs without an ID get the first free one from their type's start index.
+    fn spawn_queued(&mut self, queue: &mut Vec<NPC>) {
+        for npc in queue.drain(..) {
+            if npc.id == 0 {
+                let start = npc.get_start_index();
+                self.spawn(npc, start);
+            } else {
+                self.insert(npc);
+            }
         }
     }
 
@@ -495,14 +768,41 @@ impl NPCMap {
     }
 
     pub fn is_alive_by_event(&self, event_num: u16) -> bool {
-        for npc_cell in self.npcs.values() {
-            let npc = npc_cell.borrow();
-            if npc.cond.alive() && npc.event_num == event_num {
-                return true;
-            }
-        }
+        self.npcs_by_event(event_num).iter().any(|id| self.is_alive(*id))
+    }
+}
 
-        false
+/// Spritesheets shared by every stage.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GlobalSheet {
+    Sym,
+    Regu,
+}
+
+/// Spritesheet slots picked per stage, the names come from the stage table.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StageSheet {
+    Npc1,
+    Npc2,
+}
+
+/// Spritesheet an NPC type draws from, so the same type can use a different sheet on every map.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NPCSheet {
+    Global(GlobalSheet),
+    Stage(StageSheet),
+}
+
+impl NPCSheet {
+    /// Maps the surface ID from npc.tbl, `None` for surfaces which aren't NPC sheets.
+    pub fn from_surface_id(id: u8) -> Option<NPCSheet> {
+        match id {
+            20 => Some(NPCSheet::Global(GlobalSheet::Sym)),
+            21 => Some(NPCSheet::Stage(StageSheet::Npc1)),
+            22 => Some(NPCSheet::Stage(StageSheet::Npc2)),
+            23 => Some(NPCSheet::Global(GlobalSheet::Regu)),
+            _ => None,
+        }
     }
 }
 
@@ -519,10 +819,18 @@ pub struct NPCTableEntry {
     pub hit_bounds: Rect<u8>,
 }
 
+impl NPCTableEntry {
+    pub fn sheet(&self) -> Option<NPCSheet> {
+        NPCSheet::from_surface_id(self.spritesheet_id)
+    }
+}
+
 pub struct NPCTable {
     entries: Vec<NPCTableEntry>,
-    pub tex_npc1_name: String,
-    pub tex_npc2_name: String,
+    /// Hit and display bounds replacing the ones in the table, see `apply_bounds_overrides`.
+    bounds_overrides: HashMap<u16, (Rect<u8>, Rect<u8>)>,
+    /// Texture names of the `StageSheet` slots, set when a stage loads.
+    stage_sheets: [String; 2],
 }
 
 impl NPCTable {
@@ -530,8 +838,8 @@ impl NPCTable {
     pub fn new() -> NPCTable {
         NPCTable {
             entries: Vec::new(),
-            tex_npc1_name: str!("Npc/Npc0"),
-            tex_npc2_name: str!("Npc/Npc0"),
+            bounds_overrides: HashMap::new(),
+            stage_sheets: [str!("Npc/Npc0"), str!("Npc/Npc0")],
         }
     }
 
@@ -612,43 +920,327 @@ impl NPCTable {
         self.entries.get(npc_type as usize)
     }
 
-    pub fn get_display_bounds(&self, npc_type: u16) -> Rect<usize> {
-        if let Some(npc) = self.entries.get(npc_type as usize) {
-            Rect {
-                left: npc.display_bounds.left as usize * 0x200,
-                top: npc.display_bounds.top as usize * 0x200,
-                right: npc.display_bounds.right as usize * 0x200,
-                bottom: npc.display_bounds.bottom as usize * 0x200,
+    /// Replaces the bounds of the NPC types the original engine doesn't take from the table. Entries which
+    /// differ from the vanilla table are left alone, mods shipping a fixed table don't get them patched twice.
+    /// The entries keep the values from the file either way.
+    pub fn apply_bounds_overrides(&mut self, overrides: &[NPCBoundsOverride]) {
+        self.bounds_overrides.clear();
+
+        for bounds in overrides.iter() {
+            if let Some(entry) = self.entries.get(bounds.npc_type as usize) {
+                if entry.hit_bounds == bounds.vanilla_hit_bounds && entry.display_bounds == bounds.vanilla_display_bounds {
+                    self.bounds_overrides.insert(bounds.npc_type, (bounds.hit_bounds, bounds.display_bounds));
+                } else {
+                    log::info!("NPC type {} has non-vanilla bounds in the table, not overriding them.", bounds.npc_type);
+                }
             }
+        }
+    }
+
+    pub fn has_bounds_override(&self, npc_type: u16) -> bool {
+        self.bounds_overrides.contains_key(&npc_type)
+    }
+
+    fn fix_bounds(bounds: &Rect<u8>) -> Rect<usize> {
+        Rect {
+            left: bounds.left as usize * 0x200,
+            top: bounds.top as usize * 0x200,
+            right: bounds.right as usize * 0x200,
+            bottom: bounds.bottom as usize * 0x200,
+        }
+    }
+
+    pub fn get_display_bounds(&self, npc_type: u16) -> Rect<usize> {
+        if let Some((_, bounds)) = self.bounds_overrides.get(&npc_type) {
+            NPCTable::fix_bounds(bounds)
+        } else if let Some(npc) = self.entries.get(npc_type as usize) {
+            NPCTable::fix_bounds(&npc.display_bounds)
         } else {
             Rect { left: 0, top: 0, right: 0, bottom: 0 }
         }
     }
 
     pub fn get_hit_bounds(&self, npc_type: u16) -> Rect<usize> {
-        if let Some(npc) = self.entries.get(npc_type as usize) {
-            Rect {
-                left: npc.hit_bounds.left as usize * 0x200,
-                top: npc.hit_bounds.top as usize * 0x200,
-                right: npc.hit_bounds.right as usize * 0x200,
-                bottom: npc.hit_bounds.bottom as usize * 0x200,
-            }
+        if let Some((bounds, _)) = self.bounds_overrides.get(&npc_type) {
+            NPCTable::fix_bounds(bounds)
+        } else if let Some(npc) = self.entries.get(npc_type as usize) {
+            NPCTable::fix_bounds(&npc.hit_bounds)
         } else {
             Rect { left: 0, top: 0, right: 0, bottom: 0 }
         }
     }
 
+    /// Binds the `StageSheet` slots to the sheets of a stage, file names as in the stage table.
+    pub fn set_stage_sheets(&mut self, npc1: &str, npc2: &str) {
+        self.stage_sheets = [["Npc/", npc1].join(""), ["Npc/", npc2].join("")];
+    }
+
+    pub fn sheet_texture_name(&self, sheet: Option<NPCSheet>) -> &str {
+        match sheet {
+            Some(NPCSheet::Global(GlobalSheet::Sym)) => "Npc/NpcSym",
+            Some(NPCSheet::Global(GlobalSheet::Regu)) => "Npc/NpcRegu",
+            Some(NPCSheet::Stage(StageSheet::Npc1)) => self.stage_sheets[0].as_str(),
+            Some(NPCSheet::Stage(StageSheet::Npc2)) => self.stage_sheets[1].as_str(),
+            None => "Npc/Npc0",
+        }
+    }
+
+    pub fn get_sheet(&self, npc_type: u16) -> Option<NPCSheet> {
+        self.entries.get(npc_type as usize).and_then(|npc| npc.sheet())
+    }
+
     pub fn get_texture_name(&self, npc_type: u16) -> &str {
-        if let Some(npc) = self.entries.get(npc_type as usize) {
-            match npc.spritesheet_id {
-                20 => "Npc/NpcSym",
-                21 => self.tex_npc1_name.as_str(),
-                22 => self.tex_npc2_name.as_str(),
-                23 => "Npc/NpcRegu",
-                _ => "Npc/Npc0"
-            }
-        } else {
-            "Npc/Npc0"
+        self.sheet_texture_name(self.get_sheet(npc_type))
+    }
+}
+
+#[test]
+fn test_event_index() {
+    let table = NPCTable::new();
+    let mut map = NPCMap::new();
+
+    for (id, event_num) in [(3, 500), (1, 500), (2, 600), (4, 0)].iter() {
+        let data = NPCData { id: *id, x: 0, y: 0, flag_num: 0, event_num: *event_num, npc_type: 0, flags: 0, layer: 0 };
+        map.create_npc_from_data(&table, &data).cond.set_alive(true);
+    }
+
+    assert_eq!(map.npcs_by_event(500), &[1, 3]);
+    assert_eq!(map.npcs_by_event(600), &[2]);
+    assert!(map.npcs_by_event(700).is_empty());
+
+    let mut game_flags = bitvec::bitvec![0; 8];
+    map.remove_by_event(500, &mut game_flags);
+    assert!(!map.is_alive_by_event(500));
+    assert!(map.is_alive_by_event(600));
+
+    map.garbage_collect();
+    assert!(map.npcs_by_event(500).is_empty());
+
+    // recycled id with another event number
+    let mut npc = NPCMap::create_npc(0, &table);
+    npc.id = map.allocate_id(0).unwrap();
+    npc.event_num = 600;
+    assert_eq!(npc.id, 0);
+    map.insert(npc);
+    assert_eq!(map.npcs_by_event(600), &[0, 2]);
+
+    // replacing an NPC moves it to the new event number
+    npc.id = 2;
+    npc.event_num = 700;
+    map.insert(npc);
+    assert_eq!(map.npcs_by_event(600), &[0]);
+    assert_eq!(map.npcs_by_event(700), &[2]);
+}
+
+#[test]
+fn test_lifetime() {
+    let mut npc = NPCMap::create_npc(1, &NPCTable::new());
+    assert!(npc.lifetime.is_some());
+
+    npc.age = 400;
+    assert!(!npc.is_blinking());
+    let blinks = (401..=410).filter(|&age| {
+        npc.age = age;
+        npc.is_blinking()
+    }).count();
+    assert_eq!(blinks, 5);
+
+    npc.age = 518;
+    assert!(!npc.lifetime_expired());
+    npc.age = 519;
+    assert!(npc.lifetime_expired());
+
+    let npc = NPCMap::create_npc(64, &NPCTable::new());
+    assert!(npc.lifetime.is_none() && !npc.lifetime_expired());
+}
+
+#[test]
+fn test_tick_pass_mutation() {
+    let table = NPCTable::new();
+    let mut map = NPCMap::new();
+
+    for id in 1..=3 {
+        let data = NPCData { id, x: 0, y: 0, flag_num: 0, event_num: 0, npc_type: 0, flags: 0, layer: 0 };
+        map.create_npc_from_data(&table, &data).cond.set_alive(true);
+    }
+
+    let mut ticked = Vec::new();
+    let mut queue = Vec::new();
+    map.tick_npcs(|npc| {
+        ticked.push(npc.id);
+
+        if npc.id == 1 {
+            // spawn a child and kill a neighbor which hasn't been ticked yet
+            let mut child = NPCMap::create_npc(4, &table);
+            child.cond.set_alive(true);
+            queue.push(child);
+
+            map.npcs[&2].borrow_mut().cond.set_alive(false);
         }
+
+        if npc.id == 3 {
+            // and kill itself
+            npc.cond.set_alive(false);
+        }
+
+        Ok(())
+    }).unwrap();
+
+    assert_eq!(ticked, vec![1, 3]);
+
+    map.garbage_collect();
+    map.spawn_queued(&mut queue);
+    assert!(queue.is_empty());
+    assert_eq!(map.npc_ids.iter().copied().collect_vec(), vec![1, 0x100]);
+
+    ticked.clear();
+    map.tick_npcs(|npc| {
+        ticked.push(npc.id);
+        Ok(())
+    }).unwrap();
+    assert_eq!(ticked, vec![1, 0x100]);
+}
+
+#[test]
+fn test_list_capacity() {
+    let table = NPCTable::new();
+    let mut map = NPCMap::new();
+
+    for i in 0..(NPC_LIST_MAX_CAP - SCRIPT_NPC_START as usize) {
+        assert_eq!(map.spawn(NPCMap::create_npc(46, &table), SCRIPT_NPC_START), Some(SCRIPT_NPC_START + i as u16));
     }
+
+    // full from 0x100 up, the lower slots are still free for the NPCs starting there
+    assert_eq!(map.spawn(NPCMap::create_npc(46, &table), SCRIPT_NPC_START), None);
+    let mut queue = vec![NPCMap::create_npc(1, &table), NPCMap::create_npc(46, &table)];
+    map.spawn_queued(&mut queue);
+    assert_eq!(map.npc_ids.len(), NPC_LIST_MAX_CAP - SCRIPT_NPC_START as usize + 1);
+    assert_eq!(map.npcs[&0].borrow().npc_type, 46);
+}
+
+#[test]
+fn test_stage_sheets() {
+    let mut table = NPCTable::new();
+    for &surface in [21u8, 23, 2].iter() {
+        table.entries.push(NPCTableEntry {
+            npc_flags: NPCFlag(0),
+            life: 0,
+            spritesheet_id: surface,
+            death_sound: 0,
+            hurt_sound: 0,
+            size: 0,
+            experience: 0,
+            damage: 0,
+            display_bounds: Rect::new(0, 0, 0, 0),
+            hit_bounds: Rect::new(0, 0, 0, 0),
+        });
+    }
+
+    assert_eq!(table.get_sheet(0), Some(NPCSheet::Stage(StageSheet::Npc1)));
+    assert_eq!(table.get_sheet(2), None);
+
+    // the same type follows the sheets of every stage
+    table.set_stage_sheets("NpcGuest", "NpcMaze");
+    assert_eq!(table.get_texture_name(0), "Npc/NpcGuest");
+    table.set_stage_sheets("NpcCemet", "NpcMaze");
+    assert_eq!(table.get_texture_name(0), "Npc/NpcCemet");
+
+    assert_eq!(table.get_texture_name(1), "Npc/NpcRegu");
+    assert_eq!(table.get_texture_name(2), "Npc/Npc0");
+    assert_eq!(table.get_texture_name(100), "Npc/Npc0");
+}
+
+#[test]
+fn test_bounds_override() {
+    let entry = |hit: u8| NPCTableEntry {
+        npc_flags: NPCFlag(0),
+        life: 1,
+        spritesheet_id: 0,
+        death_sound: 0,
+        hurt_sound: 0,
+        size: 1,
+        experience: 0,
+        damage: 0,
+        display_bounds: Rect::new(8, 8, 8, 8),
+        hit_bounds: Rect::new(hit, hit, hit, hit),
+    };
+    let overrides = [
+        NPCBoundsOverride {
+            npc_type: 1,
+            vanilla_hit_bounds: Rect::new(8, 8, 8, 8),
+            vanilla_display_bounds: Rect::new(8, 8, 8, 8),
+            hit_bounds: Rect::new(4, 0, 4, 8),
+            display_bounds: Rect::new(8, 8, 8, 8),
+        },
+    ];
+
+    let mut table = NPCTable::new();
+    table.entries = vec![entry(8), entry(8)];
+    table.apply_bounds_overrides(&overrides);
+    assert!(table.has_bounds_override(1));
+    assert_eq!(table.get_hit_bounds(1), Rect::new(4 * 0x200, 0, 4 * 0x200, 8 * 0x200));
+    assert_eq!(table.get_hit_bounds(0), Rect::new(8 * 0x200, 8 * 0x200, 8 * 0x200, 8 * 0x200));
+    assert_eq!(table.get_entry(1).unwrap().hit_bounds, Rect::new(8, 8, 8, 8), "the entry keeps the file's values");
+
+    // fixed in the table already
+    table.entries[1] = entry(6);
+    table.apply_bounds_overrides(&overrides);
+    assert!(!table.has_bounds_override(1));
+    assert_eq!(table.get_hit_bounds(1), Rect::new(6 * 0x200, 6 * 0x200, 6 * 0x200, 6 * 0x200));
+}
+
+#[test]
+fn test_off_screen_policies() {
+    let table = NPCTable::new();
+    let mut map = NPCMap::new();
+    let frame = Frame::new();
+    let canvas_size = (320.0, 240.0);
+
+    // a projectile fired to the right across a 1000 tiles wide map, from the middle of the screen
+    let mut projectile = NPCMap::create_npc(84, &table);
+    projectile.id = 1;
+    projectile.cond.set_alive(true);
+    projectile.x = 160 * 0x200;
+    projectile.y = 120 * 0x200;
+    map.insert(projectile);
+
+    let data = NPCData { id: 2, x: 500, y: 0, flag_num: 0, event_num: 0, npc_type: 0, flags: 0, layer: 0 };
+    map.create_npc_from_data(&table, &data).cond.set_alive(true);
+    assert_eq!(map.npcs[&2].borrow().off_screen_policy(), OffScreenPolicy::Persistent);
+
+    let mut last_x = 0;
+    for _ in 0..(1000 * 16) {
+        map.apply_off_screen_policies(&frame, canvas_size);
+        if !map.is_alive(1) {
+            break;
+        }
+
+        map.tick_npcs(|npc| {
+            if npc.id == 1 {
+                npc.x += 0x200;
+                last_x = npc.x;
+            }
+            Ok(())
+        }).unwrap();
+    }
+
+    // a screen past the right edge of the screen
+    assert!(!map.is_alive(1));
+    assert_eq!(last_x, 640 * 0x200);
+    // far away map NPCs stay
+    assert!(map.is_alive(2));
+
+    let mut critter = NPCMap::create_npc(64, &table);
+    critter.id = 3;
+    critter.cond.set_alive(true);
+    critter.x = 600 * 0x200;
+    map.insert(critter);
+    assert_eq!(map.npcs[&3].borrow().off_screen_policy(), OffScreenPolicy::ActivateNearCamera);
+
+    map.apply_off_screen_policies(&frame, canvas_size);
+    assert!(!map.npcs[&3].borrow().activated);
+
+    map.npcs[&3].borrow_mut().x = 400 * 0x200;
+    map.apply_off_screen_policies(&frame, canvas_size);
+    assert!(map.npcs[&3].borrow().activated);
 }
@@ -1,6 +1,6 @@
 use nalgebra::clamp;
 
-use crate::common::Direction;
+use crate::common::{Direction, fix9_mul_div};
 use crate::ggez::GameResult;
 use crate::npc::NPC;
 use crate::SharedGameState;
@@ -11,8 +11,8 @@ impl NPC {
             if self.action_num == 0 {
                 self.action_num = 1;
 
-                self.vel_x = state.game_rng.range(-0x80..0x80) as isize;
-                self.vel_y = state.game_rng.range(-0x7f..0x100) as isize;
+                self.vel_x = state.game_rng.range(-0x80..=0x80) as isize;
+                self.vel_y = state.game_rng.range(-0x7f..=0x100) as isize;
             }
 
             self.vel_x -= 0x8;
@@ -40,12 +40,12 @@ impl NPC {
         } else {
             if self.action_num == 0 {
                 self.action_num = 1;
-                self.anim_num = state.game_rng.range(0..4) as u16;
+                self.anim_num = state.game_rng.range(0..=4) as u16;
 
-                self.vel_x = state.game_rng.range(-0x200..0x200) as isize;
-                self.vel_y = state.game_rng.range(-0x400..0) as isize;
+                self.vel_x = state.game_rng.range(-0x200..=0x200) as isize;
+                self.vel_y = state.game_rng.range(-0x400..=0) as isize;
 
-                self.direction = if state.game_rng.range(0..1) != 0 {
+                self.direction = if state.game_rng.range(0..=1) != 0 {
                     Direction::Left
                 } else {
                     Direction::Right
@@ -73,8 +73,9 @@ impl NPC {
             if self.flags.hit_bottom_wall() {
                 state.sound_manager.play_sfx(45);
 
-                self.vel_y = -0x280;
-                self.vel_x = 2 * self.vel_x / 3;
+                let (kept, of) = state.constants.experience.bounce_vel_x_kept;
+                self.vel_y = state.constants.experience.bounce_vel_y;
+                self.vel_x = fix9_mul_div(self.vel_x, kept, of);
             }
 
             if self.flags.hit_left_wall() || self.flags.hit_right_wall() || self.flags.hit_bottom_wall() {
@@ -128,19 +129,12 @@ impl NPC {
             }
         }
 
-        self.action_counter += 1;
-        if self.action_counter > 500 && self.anim_num == 5 && self.anim_counter == 2 {
+        // blinking and the hard despawn limit are handled by the NPC lifetime
+        if self.age > 500 && self.anim_num == 5 && self.anim_counter == 2 {
             self.cond.set_alive(false);
             return Ok(());
         }
 
-        if self.action_counter > 400 && (self.action_counter / 2 % 2) != 0 {
-            self.anim_rect.left = 0;
-            self.anim_rect.top = 0;
-            self.anim_rect.right = 0;
-            self.anim_rect.bottom = 0;
-        }
-
         Ok(())
     }
 }
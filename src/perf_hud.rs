@@ -0,0 +1,184 @@
+use imgui::{Condition, im_str, ImString, ProgressBar, Window};
+
+use crate::frame_pacer::PacingMode;
+use crate::ggez::{Context, graphics};
+use crate::memory::format_bytes;
+use crate::SharedGameState;
+use crate::str;
+
+/// Number of frames kept in the frame time graph.
+const HISTORY_LEN: usize = 200;
+/// Frames taking longer than this (two ticks) are highlighted in the graph.
+const SPIKE_THRESHOLD_MS: f32 = 40.0;
+/// Height of the graph in pixels, the top of it equals to `GRAPH_SCALE_MS`.
+const GRAPH_HEIGHT: f32 = 60.0;
+const GRAPH_SCALE_MS: f32 = 50.0;
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct FrameTiming {
+    /// Time since the start of the previous frame.
+    pub frame_ms: f32,
+    /// Time spent running game ticks, summed if there was more than one in the frame.
+    pub tick_ms: f32,
+    pub draw_ms: f32,
+    /// Time between the last two swaps, measured right after the swap so the vsync wait is included.
+    pub present_ms: f32,
+    /// Longest time from a key event to the tick which applied it, None if no input reached the ticks of the frame.
+    pub input_latency_ms: Option<f32>,
+}
+
+/// Frame times and entity counts, toggled with F3.
+pub struct PerfHud {
+    pub visible: bool,
+    history: Vec<FrameTiming>,
+    // index of the oldest entry once the ring buffer is full
+    head: usize,
+    npc_count: usize,
+    bullet_count: usize,
+    pacing_mode: Option<PacingMode>,
+}
+
+impl PerfHud {
+    pub fn new() -> Self {
+        Self {
+            visible: false,
+            history: Vec::with_capacity(HISTORY_LEN),
+            head: 0,
+            npc_count: 0,
+            bullet_count: 0,
+            pacing_mode: None,
+        }
+    }
+
+    pub fn push(&mut self, timing: FrameTiming) {
+        if self.history.len() < HISTORY_LEN {
+            self.history.push(timing);
+        } else {
+            self.history[self.head] = timing;
+            self.head = (self.head + 1) % HISTORY_LEN;
+        }
+    }
+
+    /// Timings from the oldest to the newest.
+    pub fn timings(&self) -> impl Iterator<Item=&FrameTiming> {
+        self.history[self.head..].iter().chain(self.history[..self.head].iter())
+    }
+
+    pub fn last(&self) -> FrameTiming {
+        match self.head {
+            0 => self.history.last().copied().unwrap_or_default(),
+            head => self.history[head - 1],
+        }
+    }
+
+    /// Average and worst input latency over the history, None if there's been no input.
+    pub fn input_latency(&self) -> Option<(f32, f32)> {
+        let latencies: Vec<f32> = self.timings().filter_map(|t| t.input_latency_ms).collect();
+        if latencies.is_empty() {
+            return None;
+        }
+
+        let avg = latencies.iter().sum::<f32>() / latencies.len() as f32;
+        let max = latencies.iter().cloned().fold(0.0, f32::max);
+        Some((avg, max))
+    }
+
+    pub fn set_pacing_mode(&mut self, mode: PacingMode) {
+        self.pacing_mode = Some(mode);
+    }
+
+    /// Called at the start of every UI frame, scenes without entities show zero.
+    pub fn clear_entity_counts(&mut self) {
+        self.npc_count = 0;
+        self.bullet_count = 0;
+    }
+
+    /// Called by the scenes which have entities from `debug_overlay_draw`, after the counts were cleared.
+    pub fn set_entity_counts(&mut self, npcs: usize, bullets: usize) {
+        self.npc_count = npcs;
+        self.bullet_count = bullets;
+    }
+
+    pub fn draw(&self, state: &SharedGameState, ctx: &Context, ui: &imgui::Ui) {
+        if !self.visible {
+            return;
+        }
+
+        let last = self.last();
+        let avg_frame_ms = self.timings().map(|t| t.frame_ms).sum::<f32>() / self.history.len().max(1) as f32;
+        let fps = if avg_frame_ms > 0.0 { 1000.0 / avg_frame_ms } else { 0.0 };
+        let avg_present_ms = self.timings().map(|t| t.present_ms).sum::<f32>() / self.history.len().max(1) as f32;
+        let draw_calls = graphics::draw_calls(ctx);
+        let (npcs, bullets) = (self.npc_count, self.bullet_count);
+
+        Window::new(im_str!("Performance"))
+            .position([state.screen_size.0 - 225.0, 5.0], Condition::FirstUseEver)
+            .size([220.0, 320.0], Condition::FirstUseEver)
+            .build(ui, || {
+                ui.text(format!("FPS: {:.1} ({:.2} ms)", fps, avg_frame_ms));
+                ui.text(format!("Tick: {:.2} ms, draw: {:.2} ms", last.tick_ms, last.draw_ms));
+                if let Some(mode) = self.pacing_mode {
+                    ui.text(format!("Present: {:.2} ms, {}", avg_present_ms, mode.name()));
+                }
+                match self.input_latency() {
+                    Some((avg, max)) => ui.text(format!("Input latency: {:.2} ms (max {:.2} ms)", avg, max)),
+                    None => ui.text("Input latency: no input yet"),
+                }
+                ui.text(format!("NPCs: {}, bullets: {}, carets: {}", npcs, bullets, state.carets.len()));
+                ui.text(format!("Draw calls: {}", draw_calls));
+                ui.text(format!("Audio underruns: {}", state.sound_manager.underruns()));
+
+                let voices = state.sound_manager.voice_usage();
+                ProgressBar::new(voices.active as f32 / voices.max as f32)
+                    .overlay_text(&ImString::new(format!("Voices: {}/{} (peak {})", voices.active, voices.max, voices.peak)))
+                    .size([HISTORY_LEN as f32, 0.0])
+                    .build(ui);
+                ui.text(format!("Stolen: {}, dropped: {}", voices.stolen, voices.dropped));
+
+                let texture_set = &state.texture_set;
+                let cap = texture_set.memory_cap().map_or(str!("none"), format_bytes);
+                ui.text(format!("Textures: {} (decoded {}), cap {}",
+                                format_bytes(texture_set.gpu_bytes()), format_bytes(texture_set.decoded_bytes()), cap));
+                let (sfx_bytes, stream_bytes) = state.sound_manager.memory_usage();
+                ui.text(format!("SFX: {}, song buffers: {}", format_bytes(sfx_bytes), format_bytes(stream_bytes)));
+
+                let [x, y] = ui.cursor_screen_pos();
+                let width = HISTORY_LEN as f32;
+                let draw_list = ui.get_window_draw_list();
+                draw_list.add_rect([x, y], [x + width, y + GRAPH_HEIGHT], [0.0, 0.0, 0.0, 0.6])
+                    .filled(true).build();
+
+                // marks one tick
+                let tick_ms = state.tick_duration().as_secs_f32() * 1000.0;
+                let tick_y = y + GRAPH_HEIGHT * (1.0 - tick_ms / GRAPH_SCALE_MS);
+                draw_list.add_line([x, tick_y], [x + width, tick_y], [0.5, 0.5, 0.5, 0.5]).build();
+
+                for (i, timing) in self.timings().enumerate() {
+                    let height = (timing.frame_ms / GRAPH_SCALE_MS).min(1.0) * GRAPH_HEIGHT;
+                    let color = if timing.frame_ms > SPIKE_THRESHOLD_MS { [1.0, 0.2, 0.2, 1.0] } else { [0.3, 0.9, 0.3, 1.0] };
+                    let bar_x = x + i as f32;
+                    draw_list.add_line([bar_x, y + GRAPH_HEIGHT], [bar_x, y + GRAPH_HEIGHT - height], color).build();
+                }
+
+                ui.dummy([width, GRAPH_HEIGHT]);
+            });
+    }
+}
+
+#[test]
+fn test_ring_buffer() {
+    let mut hud = PerfHud::new();
+    for i in 0..(HISTORY_LEN + 5) {
+        let input_latency_ms = if i % 2 == 0 { Some(i as f32) } else { None };
+        hud.push(FrameTiming { frame_ms: i as f32, tick_ms: 0.0, draw_ms: 0.0, present_ms: 0.0, input_latency_ms });
+    }
+
+    let frames: Vec<f32> = hud.timings().map(|t| t.frame_ms).collect();
+    assert_eq!(frames.len(), HISTORY_LEN);
+    assert_eq!(frames[0], 5.0);
+    assert_eq!(hud.last().frame_ms, (HISTORY_LEN + 4) as f32);
+
+    // frames without input don't count
+    assert_eq!(hud.input_latency(), Some((((6 + HISTORY_LEN + 4) / 2) as f32, (HISTORY_LEN + 4) as f32)));
+    assert_eq!(PerfHud::new().input_latency(), None);
+}
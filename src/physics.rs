@@ -1,12 +1,53 @@
 use num_traits::clamp;
 
 use crate::caret::CaretType;
-use crate::common::{Condition, Direction, Flag, Rect};
+use crate::common::{Condition, Direction, fix9_mul, Flag, Rect, resolve_movement, tile_to_fix, TileFlags, to_fix, to_tile};
+use crate::map::Map;
 use crate::SharedGameState;
 use crate::stage::Stage;
 
 pub const OFF_X: [isize; 9] = [0, 1, 0, 1, 2, 2, 2, 0, 1];
 pub const OFF_Y: [isize; 9] = [0, 0, 1, 1, 0, 1, 2, 2, 2];
+/// Furthest an entity which stood on the ground on the previous tick is pulled down onto it,
+/// walking down a slope at the top speed drops about 0x1a0 a tick.
+pub const GROUND_SNAP_DISTANCE: isize = 0x400;
+/// Rise of the lower slopes per unit of run, half a pixel per pixel.
+const SLOPE_GRADIENT: isize = 0x100;
+
+/// Height of the surface of a lower slope (variants 4 to 7) at `x`, the same one `judge_hit_triangle_e` to `_h` push out to.
+pub fn slope_surface(variant: u8, tx: isize, ty: isize, x: isize) -> isize {
+    let rise = fix9_mul(x - tile_to_fix(tx), SLOPE_GRADIENT);
+
+    match variant {
+        4 => tile_to_fix(ty) + rise - 0x800,
+        5 => tile_to_fix(ty) + rise + 0x800,
+        6 => tile_to_fix(ty) - rise + 0x800,
+        _ => tile_to_fix(ty) - rise - 0x800,
+    }
+}
+
+/// Floor or lower slope right under `x` with its surface between `feet` and `max_drop` below them, and the surface height.
+pub fn ground_below(map: &Map, x: isize, feet: isize, max_drop: isize, is_player: bool) -> Option<(isize, TileFlags)> {
+    // tiles are centered on multiples of 16 pixels
+    let tx = to_tile(x + 0x1000);
+
+    for ty in to_tile(feet + 0x1000)..=to_tile(feet + max_drop + 0x1000) {
+        let tile = TileFlags::decode(map.get_attribute(tx, ty), is_player);
+        let surface = if tile.slope() && tile.variant() >= 4 {
+            slope_surface(tile.variant(), tx, ty, x)
+        } else if tile.solid() {
+            tile_to_fix(ty) - to_fix(8)
+        } else {
+            continue;
+        };
+
+        if surface >= feet && surface <= feet + max_drop {
+            return Some((surface, tile));
+        }
+    }
+
+    None
+}
 
 pub trait PhysicalEntity {
     fn x(&self) -> isize;
@@ -44,7 +85,7 @@ pub trait PhysicalEntity {
                     self.set_vel_x(-0x180);
                 }
 
-                if !state.key_state.left() && self.vel_x() < 0 {
+                if resolve_movement(state.key_state) != Some(Direction::Left) && self.vel_x() < 0 {
                     self.set_vel_x(0);
                 }
             }
@@ -64,7 +105,7 @@ pub trait PhysicalEntity {
                     self.set_vel_x(0x180);
                 }
 
-                if !state.key_state.right() && self.vel_x() > 0 {
+                if resolve_movement(state.key_state) != Some(Direction::Right) && self.vel_x() > 0 {
                     self.set_vel_x(0);
                 }
             }
@@ -343,113 +384,232 @@ pub trait PhysicalEntity {
 
     fn tick_map_collisions(&mut self, state: &mut SharedGameState, stage: &mut Stage) {
         let big = self.size() >= 3;
-        let x = clamp((self.x() - if big { 0x1000 } else { 0 }) / 16 / 0x200, 0, stage.map.width as isize);
-        let y = clamp((self.y() - if big { 0x1000 } else { 0 }) / 16 / 0x200, 0, stage.map.height as isize);
+        let x = clamp(to_tile(self.x() - if big { 0x1000 } else { 0 }), 0, stage.map.width as isize);
+        let y = clamp(to_tile(self.y() - if big { 0x1000 } else { 0 }), 0, stage.map.height as isize);
 
         for (idx, (&ox, &oy)) in OFF_X.iter().zip(OFF_Y.iter()).enumerate() {
             if idx == 4 && !big {
                 break;
             }
 
-            let attrib = stage.map.get_attribute((x + ox) as usize, (y + oy) as usize);
-            match attrib {
-                // Spikes
-                0x62 | 0x42 if self.is_player() => {
-                    self.judge_hit_spike(x + ox, y + oy, attrib & 0x20 != 0);
-                }
-
-                // Blocks
-                0x02 | 0x60 => {
-                    self.judge_hit_water(x + ox, y + oy);
-                }
-                0x62 if !self.is_player() => {
-                    self.judge_hit_water(x + ox, y + oy);
-                }
-                0x61 => {
-                    self.judge_hit_water(x + ox, y + oy);
-                    self.judge_hit_block(state, x + ox, y + oy);
-                }
-                0x04 | 0x64 if !self.is_player() => {
-                    self.judge_hit_water(x + ox, y + oy);
-                    self.judge_hit_block(state, x + ox, y + oy);
-                }
-                0x05 | 0x41 | 0x43 | 0x46 if self.is_player() => {
-                    self.judge_hit_block(state, x + ox, y + oy);
-                }
-                0x03 | 0x05 | 0x41 | 0x43 if !self.is_player() => {
-                    self.judge_hit_block(state, x + ox, y + oy);
-                }
-                0x44 => {
-                    if !self.ignore_tile_44() {
-                        self.judge_hit_block(state, x + ox, y + oy);
+            let (tx, ty) = (x + ox, y + oy);
+            let tile = TileFlags::decode(stage.map.get_attribute(tx, ty), self.is_player());
+
+            if let Some(direction) = tile.current_direction() {
+                if self.is_player() {
+                    self.judge_hit_force(tx, ty, direction, tile.water());
+                } else {
+                    // NPCs are pushed by any current tile around them
+                    match direction {
+                        Direction::Left => { self.flags().set_force_left(true); }
+                        Direction::Up => { self.flags().set_force_up(true); }
+                        Direction::Right => { self.flags().set_force_right(true); }
+                        Direction::Bottom => { self.flags().set_force_down(true); }
                     }
-                }
 
-                // Slopes
-                0x50 | 0x70 => {
-                    self.judge_hit_triangle_a(state, x + ox, y + oy);
-                    if attrib & 0x20 != 0 { self.judge_hit_water(x + ox, y + oy); }
-                }
-                0x51 | 0x71 => {
-                    self.judge_hit_triangle_b(state, x + ox, y + oy);
-                    if attrib & 0x20 != 0 { self.judge_hit_water(x + ox, y + oy); }
-                }
-                0x52 | 0x72 => {
-                    self.judge_hit_triangle_c(state, x + ox, y + oy);
-                    if attrib & 0x20 != 0 { self.judge_hit_water(x + ox, y + oy); }
-                }
-                0x53 | 0x73 => {
-                    self.judge_hit_triangle_d(state, x + ox, y + oy);
-                    if attrib & 0x20 != 0 { self.judge_hit_water(x + ox, y + oy); }
-                }
-                0x54 | 0x74 => {
-                    self.judge_hit_triangle_e(state, x + ox, y + oy);
-                    if attrib & 0x20 != 0 { self.judge_hit_water(x + ox, y + oy); }
-                }
-                0x55 | 0x75 => {
-                    self.judge_hit_triangle_f(state, x + ox, y + oy);
-                    if attrib & 0x20 != 0 { self.judge_hit_water(x + ox, y + oy); }
-                }
-                0x56 | 0x76 => {
-                    self.judge_hit_triangle_g(state, x + ox, y + oy);
-                    if attrib & 0x20 != 0 { self.judge_hit_water(x + ox, y + oy); }
+                    if tile.water() {
+                        self.flags().set_in_water(true);
+                    }
                 }
-                0x57 | 0x77 => {
-                    self.judge_hit_triangle_h(state, x + ox, y + oy);
-                    if attrib & 0x20 != 0 { self.judge_hit_water(x + ox, y + oy); }
+            } else if tile.spike() {
+                // water spikes put the player in water within the spike bounds only
+                self.judge_hit_spike(tx, ty, tile.water());
+            } else if tile.slope() {
+                match tile.variant() {
+                    0 => self.judge_hit_triangle_a(state, tx, ty),
+                    1 => self.judge_hit_triangle_b(state, tx, ty),
+                    2 => self.judge_hit_triangle_c(state, tx, ty),
+                    3 => self.judge_hit_triangle_d(state, tx, ty),
+                    4 => self.judge_hit_triangle_e(state, tx, ty),
+                    5 => self.judge_hit_triangle_f(state, tx, ty),
+                    6 => self.judge_hit_triangle_g(state, tx, ty),
+                    _ => self.judge_hit_triangle_h(state, tx, ty),
                 }
 
-                // Forces
-                0x80 | 0xa0 if self.is_player() => {
-                    self.judge_hit_force(x + ox, y + ox, Direction::Left, attrib & 0x20 != 0);
-                }
-                0x81 | 0xa1 if self.is_player() => {
-                    self.judge_hit_force(x + ox, y + ox, Direction::Up, attrib & 0x20 != 0);
-                }
-                0x82 | 0xa2 if self.is_player() => {
-                    self.judge_hit_force(x + ox, y + ox, Direction::Right, attrib & 0x20 != 0);
+                if tile.water() {
+                    self.judge_hit_water(tx, ty);
                 }
-                0x83 | 0xa3 if self.is_player() => {
-                    self.judge_hit_force(x + ox, y + ox, Direction::Bottom, attrib & 0x20 != 0);
-                }
-                0x80 | 0xa0 if !self.is_player() => {
-                    self.flags().set_force_left(true);
-                    if attrib & 0x20 != 0 { self.flags().set_in_water(true); }
-                }
-                0x81 | 0xa1 if !self.is_player() => {
-                    self.flags().set_force_up(true);
-                    if attrib & 0x20 != 0 { self.flags().set_in_water(true); }
-                }
-                0x82 | 0xa2 if !self.is_player() => {
-                    self.flags().set_force_right(true);
-                    if attrib & 0x20 != 0 { self.flags().set_in_water(true); }
+            } else {
+                if tile.water() {
+                    self.judge_hit_water(tx, ty);
                 }
-                0x83 | 0xa3 if !self.is_player() => {
-                    self.flags().set_force_down(true);
-                    if attrib & 0x20 != 0 { self.flags().set_in_water(true); }
+
+                if tile.solid() || (tile.solid_unless_ignored() && !self.ignore_tile_44()) {
+                    self.judge_hit_block(state, tx, ty);
                 }
-                _ => {}
             }
         }
     }
+
+    /// Keeps an entity which stood on the ground on the previous tick on it when the ground drops away under it
+    /// while walking down a slope or off of one, the collisions only push out so it'd be in the air for a tick
+    /// every now and then. Called after the collisions, doesn't do anything to jumps.
+    fn snap_to_ground(&mut self, map: &Map, was_grounded: bool) {
+        if !was_grounded || self.flags().hit_bottom_wall() || self.vel_y() < 0 {
+            return;
+        }
+
+        let feet = self.y() + self.hit_bounds().bottom as isize;
+        let (surface, tile) = match ground_below(map, self.x(), feet, GROUND_SNAP_DISTANCE, self.is_player()) {
+            Some(ground) => ground,
+            None => { return; }
+        };
+
+        self.set_y(surface - self.hit_bounds().bottom as isize);
+        if self.vel_y() > 0 {
+            self.set_vel_y(0);
+        }
+
+        // the same flags the collision sets, the walking code follows the slope with them on the next tick
+        if tile.slope() {
+            match tile.variant() {
+                4 => self.flags().set_hit_left_bigger_half(true),
+                5 => self.flags().set_hit_left_smaller_half(true),
+                6 => self.flags().set_hit_right_smaller_half(true),
+                _ => self.flags().set_hit_right_bigger_half(true),
+            }
+
+            if tile.variant() < 6 {
+                self.flags().set_hit_left_slope(true);
+            } else {
+                self.flags().set_hit_right_slope(true);
+            }
+        }
+        self.flags().set_hit_bottom_wall(true);
+    }
+}
+
+#[cfg(test)]
+use crate::entity::GameEntity;
+#[cfg(test)]
+use crate::inventory::Inventory;
+#[cfg(test)]
+use crate::player::Player;
+
+/// A player standing with the feet at `feet`, holding the direction it walks to at `vel_x`.
+#[cfg(test)]
+fn standing_player(state: &mut SharedGameState, x: isize, feet: isize, vel_x: isize) -> Player {
+    let mut player = Player::new(state);
+    player.x = x;
+    player.y = feet - player.hit_bounds.bottom as isize;
+    player.vel_x = vel_x;
+    player.direction = if vel_x < 0 { Direction::Left } else { Direction::Right };
+    player.flags.set_hit_bottom_wall(true);
+
+    state.control_flags.set_control_enabled(true);
+    state.key_state.set_left(vel_x < 0);
+    state.key_state.set_right(vel_x > 0);
+    player
+}
+
+/// One tick of the player in the order `GameScene` runs it, returns if it's on the ground afterwards.
+#[cfg(test)]
+fn player_step(player: &mut Player, state: &mut SharedGameState, stage: &mut Stage) -> bool {
+    state.update_key_trigger();
+    player.tick(state, &mut Inventory::new()).unwrap();
+
+    let was_grounded = player.flags.hit_bottom_wall();
+    player.flags.0 = 0;
+    player.tick_map_collisions(state, stage);
+    player.snap_to_ground(&stage.map, was_grounded);
+
+    player.flags.hit_bottom_wall()
+}
+
+/// Jumps with the jump button held until the player starts falling, returns how high it went.
+#[cfg(test)]
+fn jump_height(player: &mut Player, state: &mut SharedGameState, stage: &mut Stage) -> isize {
+    let start = player.y;
+    state.key_state.set_jump(true);
+    player_step(player, state, stage);
+    assert!(player.vel_y < 0, "didn't jump");
+
+    while player.vel_y < 0 {
+        player_step(player, state, stage);
+    }
+    state.key_state.set_jump(false);
+
+    start - player.y
+}
+
+#[cfg(test)]
+fn slope_map(rows: [[u8; 8]; 4]) -> Map {
+    let mut attrib = [0u8; 0x100];
+    for (i, attr) in attrib.iter_mut().enumerate() {
+        *attr = i as u8;
+    }
+
+    Map { width: 8, height: 4, tiles: rows.iter().flat_map(|row| row.iter().copied()).collect(), attrib, revision: 0 }
+}
+
+#[cfg(test)]
+fn right_slopes() -> Map {
+    // flat, both halves of a slope going down to the right, again a tile lower, flat
+    slope_map([
+        [0; 8],
+        [0x41, 0x54, 0x55, 0, 0, 0, 0, 0],
+        [0x41, 0x41, 0x41, 0x54, 0x55, 0, 0, 0],
+        [0x41; 8],
+    ])
+}
+
+#[test]
+fn test_slope_descent() {
+    let mut state = SharedGameState::for_tests();
+    let max_dash = state.constants.my_char.air_physics.max_dash;
+    // the friction takes some of the top speed back on every tick on the ground
+    let min_dash = max_dash - state.constants.my_char.air_physics.resist;
+
+    let mut stage = Stage::for_tests(right_slopes());
+    let mut player = standing_player(&mut state, 0, 8 * 0x200, max_dash);
+    while player.x < 7 * 16 * 0x200 {
+        assert!(player_step(&mut player, &mut state, &mut stage), "in the air at x = {:#x}", player.x);
+        assert!(player.vel_x >= min_dash, "slowed down at x = {:#x}", player.x);
+    }
+    assert_eq!(player.y + player.hit_bounds.bottom as isize, 40 * 0x200);
+
+    // mirrored, going down to the left
+    let mut stage = Stage::for_tests(slope_map([
+        [0; 8],
+        [0, 0, 0, 0, 0, 0x56, 0x57, 0x41],
+        [0, 0, 0, 0x56, 0x57, 0x41, 0x41, 0x41],
+        [0x41; 8],
+    ]));
+    let mut player = standing_player(&mut state, 7 * 16 * 0x200, 8 * 0x200, -max_dash);
+    while player.x > 0 {
+        assert!(player_step(&mut player, &mut state, &mut stage), "in the air at x = {:#x}", player.x);
+        assert!(player.vel_x <= -min_dash, "slowed down at x = {:#x}", player.x);
+    }
+    assert_eq!(player.y + player.hit_bounds.bottom as isize, 40 * 0x200);
+
+    // the surfaces of adjoining halves line up
+    assert_eq!(slope_surface(4, 1, 1, 24 * 0x200), slope_surface(5, 2, 1, 24 * 0x200));
+    assert_eq!(slope_surface(5, 2, 1, 40 * 0x200), slope_surface(4, 3, 2, 40 * 0x200));
+    assert_eq!(slope_surface(6, 5, 1, 72 * 0x200), slope_surface(7, 4, 2, 72 * 0x200));
+
+    // a real drop isn't skipped over
+    let mut stage = Stage::for_tests(right_slopes());
+    assert_eq!(ground_below(&stage.map, 5 * 16 * 0x200, 8 * 0x200, GROUND_SNAP_DISTANCE, true), None);
+    let mut player = standing_player(&mut state, 5 * 16 * 0x200, 8 * 0x200, max_dash);
+    assert!(!player_step(&mut player, &mut state, &mut stage));
+}
+
+#[test]
+fn test_slope_jump() {
+    let mut state = SharedGameState::for_tests();
+    let max_dash = state.constants.my_char.air_physics.max_dash;
+
+    let mut stage = Stage::for_tests(slope_map([[0; 8], [0x41; 8], [0x41; 8], [0x41; 8]]));
+    let mut player = standing_player(&mut state, 0, 8 * 0x200, max_dash);
+    assert!(player_step(&mut player, &mut state, &mut stage));
+    let flat = jump_height(&mut player, &mut state, &mut stage);
+
+    // jumping halfway down the slope at the top speed
+    let mut stage = Stage::for_tests(right_slopes());
+    let mut player = standing_player(&mut state, 0, 8 * 0x200, max_dash);
+    while player.x < 16 * 0x200 {
+        assert!(player_step(&mut player, &mut state, &mut stage));
+    }
+    assert!(player.flags.hit_left_slope());
+    assert_eq!(jump_height(&mut player, &mut state, &mut stage), flat);
 }
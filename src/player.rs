@@ -4,22 +4,227 @@ use num_derive::FromPrimitive;
 use num_traits::{clamp, FromPrimitive};
 
 use crate::caret::CaretType;
-use crate::common::{Condition, Equipment, Flag};
+use crate::common::{Condition, Equipment, fix9_mul_div, Flag, KeyState, resolve_movement, resolve_vertical};
 use crate::common::{Direction, Rect};
+use crate::engine_constants::MyCharConsts;
 use crate::entity::GameEntity;
 use crate::frame::Frame;
 use crate::ggez::{Context, GameResult};
 use crate::inventory::Inventory;
 use crate::SharedGameState;
+use crate::stats::StatEvent;
+use crate::text_script::EventTrigger;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, FromPrimitive)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromPrimitive, Serialize, Deserialize)]
 #[repr(u8)]
 pub enum ControlMode {
     Normal = 0,
+    /// `<UNI0001`, swimming in eight directions without gravity, as in the Ironhead fight.
     IronHead,
+    /// `<UNI0002`, moved only by scripts and NPCs, the camera sticks to the player even past the map edges.
+    Fixed,
+}
+
+/// Sprite drawn in place of the player while a script wants it to, see `<PSO`.
+/// Kept across `<TRA` until the script clears it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromPrimitive)]
+#[repr(u8)]
+pub enum PlayerPose {
+    /// Quote passed out underwater, from the Caret sheet.
+    Drowned = 1,
+}
+
+/// Row of MyChar the player sprite is taken from, relative to the character's first row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlayerSkin {
+    Quote,
+    MimigaMask,
+}
+
+/// Picked on New game, like the Curly mode of CS+. Stored in the unused field of the profile.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromPrimitive, Serialize, Deserialize)]
+#[repr(u8)]
+pub enum PlayableCharacter {
+    Quote = 0,
+    Curly,
+}
+
+impl PlayableCharacter {
+    pub fn name(self) -> &'static str {
+        match self {
+            PlayableCharacter::Quote => "Quote",
+            PlayableCharacter::Curly => "Curly",
+        }
+    }
+
+    /// First skin row of the character on MyChar, None if the data has no sprites for it.
+    pub fn skin_row(self, consts: &MyCharConsts) -> Option<usize> {
+        match self {
+            PlayableCharacter::Quote => Some(0),
+            PlayableCharacter::Curly => consts.curly_skin_row,
+        }
+    }
+}
+
+/// Everything the player animation depends on, see `next_animation`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AnimationInput {
+    pub on_ground: bool,
+    pub interacted: bool,
+    /// Left or right is held while the player can be controlled.
+    pub walking: bool,
+    /// Up is held while the player can be controlled.
+    pub looking_up: bool,
+    /// Aim direction, used for the airborne frames.
+    pub up: bool,
+    pub down: bool,
+    pub vel_y: isize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AnimationStep {
+    pub anim_num: u16,
+    pub anim_counter: u16,
+    /// Set while walking, a footstep is played when the player stops.
+    pub fallen: bool,
+    pub footstep: bool,
 }
 
-#[derive(Clone)]
+/// Picks the next MyChar frame, a port of AnimationMyChar.
+///
+/// Frames: 0 - idle, 1..=4 - walking, 5 - looking up, 6..=9 - walking while looking up,
+/// 10 - looking down in the air, 11 - interacting (facing away).
+/// In the air 6 is used when aiming up and 1/3 when falling/jumping.
+pub fn next_animation(anim_num: u16, anim_counter: u16, fallen: bool, input: AnimationInput) -> AnimationStep {
+    let mut step = AnimationStep { anim_num, anim_counter, fallen, footstep: false };
+
+    if !input.on_ground {
+        step.anim_num = if input.up {
+            6
+        } else if input.down {
+            10
+        } else if input.vel_y > 0 {
+            1
+        } else {
+            3
+        };
+
+        return step;
+    }
+
+    if input.interacted {
+        step.anim_num = 11;
+    } else if input.walking {
+        // walking frames are 1..=4, or 6..=9 when looking up
+        let (first, last) = if input.looking_up { (6, 9) } else { (1, 4) };
+        step.fallen = true;
+
+        step.anim_counter += 1;
+        if step.anim_counter > 4 {
+            step.anim_counter = 0;
+            step.anim_num += 1;
+            step.footstep = step.anim_num == first + 1 || step.anim_num == first + 3;
+        }
+
+        if step.anim_num > last || step.anim_num < first {
+            step.anim_num = first;
+        }
+    } else {
+        step.footstep = fallen;
+        step.fallen = false;
+        step.anim_num = if input.looking_up { 5 } else { 0 };
+    }
+
+    // todo: vanilla MyChar has no blink frame, add one once custom skins can provide it
+    // carried/collapsed poses are drawn by NPC 150 in the scripts, the player is hidden meanwhile
+
+    step
+}
+
+/// Frames on which the body is one pixel lower.
+pub fn is_step_frame(anim_num: u16) -> bool {
+    anim_num == 1 || anim_num == 3 || anim_num == 6 || anim_num == 8
+}
+
+pub fn animation_rect(consts: &MyCharConsts, anim_num: u16, direction: Direction, skin: PlayerSkin, character: PlayableCharacter) -> Rect<usize> {
+    let mut rect = match direction {
+        Direction::Right => consts.animations_right[anim_num as usize % consts.animations_right.len()],
+        _ => consts.animations_left[anim_num as usize % consts.animations_left.len()],
+    };
+
+    let mut row = character.skin_row(consts).unwrap_or(0);
+    if skin == PlayerSkin::MimigaMask {
+        row += 1;
+    }
+
+    rect.top += row * consts.skin_row_height;
+    rect.bottom += row * consts.skin_row_height;
+
+    rect
+}
+
+/// Rect of the held weapon on the Arms sheet and its offset from the player sprite,
+/// `None` if the player has no weapon or faces away from the camera.
+pub fn arms_rect(consts: &MyCharConsts, weapon: u8, anim_num: u16, direction: Direction, up: bool, down: bool) -> Option<(Rect<usize>, (isize, isize))> {
+    if weapon == 0 || anim_num == 11 {
+        return None;
+    }
+
+    let (width, height) = consts.arms_frame_size;
+    let (offset_x, mut offset_y) = if direction == Direction::Right { consts.arms_offset_right } else { consts.arms_offset_left };
+    // left, right, then the same for aiming up and down
+    let mut row = if direction == Direction::Right { 1 } else { 0 };
+
+    if up {
+        row += 2;
+        offset_y -= consts.arms_aim_offset;
+    } else if down {
+        row += 4;
+        offset_y += consts.arms_aim_offset;
+    }
+
+    let left = (weapon as usize % 13) * width;
+    let mut top = (weapon as usize / 13) * height * 6 + row * height;
+
+    // the body bobs on the step frames, the gun has to follow it
+    if is_step_frame(anim_num) {
+        top += 1;
+    }
+
+    Some((Rect::new(left, top, left + width, top + height), (offset_x, offset_y)))
+}
+
+/// Furthest the camera looks ahead of the player, 64 pixels.
+pub const LOOK_OFFSET_MAX: isize = 0x8000;
+/// The look-ahead moves by a pixel every tick.
+const LOOK_OFFSET_STEP: isize = 0x200;
+
+/// Moves the camera look-ahead one tick towards the facing direction and the looked at side,
+/// the vertical one goes back to the center when neither up nor down is held.
+pub fn step_look_offset(index_x: isize, index_y: isize, direction: Direction, look_up: bool, look_down: bool) -> (isize, isize) {
+    // vanilla treats every direction other than left as right
+    let index_x = if direction == Direction::Left {
+        (index_x - LOOK_OFFSET_STEP).max(-LOOK_OFFSET_MAX)
+    } else {
+        (index_x + LOOK_OFFSET_STEP).min(LOOK_OFFSET_MAX)
+    };
+
+    let index_y = if look_up {
+        (index_y - LOOK_OFFSET_STEP).max(-LOOK_OFFSET_MAX)
+    } else if look_down {
+        (index_y + LOOK_OFFSET_STEP).min(LOOK_OFFSET_MAX)
+    } else if index_y > LOOK_OFFSET_STEP {
+        index_y - LOOK_OFFSET_STEP
+    } else if index_y < -LOOK_OFFSET_STEP {
+        index_y + LOOK_OFFSET_STEP
+    } else {
+        index_y
+    };
+
+    (index_x, index_y)
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Player {
     pub x: isize,
     pub y: isize,
@@ -44,18 +249,22 @@ pub struct Player {
     pub current_weapon: u8,
     pub update_target: bool,
     pub stars: u8,
-    weapon_offset_y: i8,
+    /// Ticks left before drowning, 1000 when out of the water.
+    pub air: u16,
+    /// Keeps the air counter visible for a while after leaving the water.
+    pub air_get: u16,
     index_x: isize,
     index_y: isize,
     splash: bool,
     booster_switch: u8,
+    /// The Booster 2.0 has been fired during the current jump.
+    booster_used: bool,
     bubble: u8,
     exp_wait: isize,
     exp_count: isize,
     anim_num: u16,
     anim_counter: u16,
     anim_rect: Rect<usize>,
-    weapon_rect: Rect<usize>,
 }
 
 impl Player {
@@ -87,21 +296,54 @@ impl Player {
             up: false,
             down: false,
             current_weapon: 0,
-            weapon_offset_y: 0,
             shock_counter: 0,
             booster_switch: 0,
+            booster_used: false,
             stars: 0,
+            air: 1000,
+            air_get: 0,
             bubble: 0,
             exp_wait: 0,
             exp_count: 0,
             anim_num: 0,
             anim_counter: 0,
             anim_rect: constants.my_char.animations_right[0],
-            weapon_rect: Rect::new(0, 0, 0, 0),
         }
     }
 
-    fn tick_normal(&mut self, state: &mut SharedGameState) -> GameResult {
+    /// Vanilla's AirProcess, the Air Tank stops the counter altogether.
+    fn tick_air(&mut self, state: &mut SharedGameState) {
+        if self.equip.has_air_tank() {
+            self.air = 1000;
+            self.air_get = 0;
+            return;
+        }
+
+        if !self.flags.in_water() {
+            self.air = 1000;
+        } else {
+            self.air = self.air.saturating_sub(1);
+
+            if self.air == 0 {
+                if state.game_flags.get(4000) == Some(&true) {
+                    // Core flooding
+                    state.textscript_vm.start_event(1100, EventTrigger::Forced, &mut state.control_flags);
+                } else {
+                    state.textscript_vm.start_event(41, EventTrigger::Forced, &mut state.control_flags);
+                    state.create_caret(self.x, self.y, CaretType::DrownedQuote, self.direction);
+                    self.cond.set_alive(false);
+                }
+            }
+        }
+
+        if self.flags.in_water() {
+            self.air_get = 60;
+        } else {
+            self.air_get = self.air_get.saturating_sub(1);
+        }
+    }
+
+    fn tick_normal(&mut self, state: &mut SharedGameState, inventory: &mut Inventory) -> GameResult {
         if self.cond.hidden() {
             return Ok(());
         }
@@ -119,6 +361,7 @@ impl Player {
         // ground movement
         if self.flags.hit_bottom_wall() || self.flags.hit_right_slope() || self.flags.hit_left_slope() {
             self.booster_switch = 0;
+            self.booster_used = false;
 
             if self.equip.has_booster_0_8() || self.equip.has_booster_2_0() {
                 self.booster_fuel = state.constants.booster.fuel;
@@ -131,20 +374,20 @@ impl Player {
                     self.cond.set_interacted(true);
                     self.question = true;
                 } else {
-                    if state.key_state.left() && self.vel_x > -physics.max_dash {
-                        self.vel_x -= physics.dash_ground;
-                    }
-
-                    if state.key_state.right() && self.vel_x < physics.max_dash {
-                        self.vel_x += physics.dash_ground;
-                    }
-
-                    if state.key_state.left() {
-                        self.direction = Direction::Left;
-                    }
-
-                    if state.key_state.right() {
-                        self.direction = Direction::Right;
+                    match resolve_movement(state.key_state) {
+                        Some(Direction::Left) => {
+                            if self.vel_x > -physics.max_dash {
+                                self.vel_x -= physics.dash_ground;
+                            }
+                            self.direction = Direction::Left;
+                        }
+                        Some(Direction::Right) => {
+                            if self.vel_x < physics.max_dash {
+                                self.vel_x += physics.dash_ground;
+                            }
+                            self.direction = Direction::Right;
+                        }
+                        _ => {}
                     }
                 }
             }
@@ -175,44 +418,54 @@ impl Player {
                             self.vel_y /= 2;
                         }
                     } else if self.equip.has_booster_2_0() {
-                        if state.key_state.up() {
-                            self.booster_switch = 2;
-                            self.vel_x = 0;
-                            self.vel_y = state.constants.booster.b2_0_up;
-                        } else if state.key_state.left() {
-                            self.booster_switch = 1;
-                            self.vel_x = state.constants.booster.b2_0_left;
-                            self.vel_y = 0;
-                        } else if state.key_state.right() {
-                            self.booster_switch = 1;
-                            self.vel_x = state.constants.booster.b2_0_right;
-                            self.vel_y = 0;
-                        } else if state.key_state.down() {
-                            self.booster_switch = 3;
-                            self.vel_x = 0;
-                            self.vel_y = state.constants.booster.b2_0_down;
-                        } else {
-                            self.booster_switch = 2;
-                            self.vel_x = 0;
-                            self.vel_y = state.constants.booster.b2_0_up_nokey;
+                        if let Some(boost) = booster_2_0_activation(state.key_trigger, state.key_state, self.booster_fuel, self.booster_used) {
+                            self.booster_used = true;
+
+                            match boost {
+                                BoostDirection::Up => {
+                                    self.booster_switch = 2;
+                                    self.vel_x = 0;
+                                    self.vel_y = state.constants.booster.b2_0_up;
+                                }
+                                BoostDirection::Left => {
+                                    self.booster_switch = 1;
+                                    self.vel_x = state.constants.booster.b2_0_left;
+                                    self.vel_y = 0;
+                                }
+                                BoostDirection::Right => {
+                                    self.booster_switch = 1;
+                                    self.vel_x = state.constants.booster.b2_0_right;
+                                    self.vel_y = 0;
+                                }
+                                BoostDirection::Down => {
+                                    self.booster_switch = 3;
+                                    self.vel_x = 0;
+                                    self.vel_y = state.constants.booster.b2_0_down;
+                                }
+                                BoostDirection::Neutral => {
+                                    self.booster_switch = 2;
+                                    self.vel_x = 0;
+                                    self.vel_y = state.constants.booster.b2_0_up_nokey;
+                                }
+                            }
                         }
                     }
                 }
 
-                if state.key_state.left() && self.vel_x > -physics.max_dash {
-                    self.vel_x -= physics.dash_air;
-                }
-
-                if state.key_state.right() && self.vel_x < physics.max_dash {
-                    self.vel_x += physics.dash_air;
-                }
-
-                if state.key_state.left() {
-                    self.direction = Direction::Left;
-                }
-
-                if state.key_state.right() {
-                    self.direction = Direction::Right;
+                match resolve_movement(state.key_state) {
+                    Some(Direction::Left) => {
+                        if self.vel_x > -physics.max_dash {
+                            self.vel_x -= physics.dash_air;
+                        }
+                        self.direction = Direction::Left;
+                    }
+                    Some(Direction::Right) => {
+                        if self.vel_x < physics.max_dash {
+                            self.vel_x += physics.dash_air;
+                        }
+                        self.direction = Direction::Right;
+                    }
+                    _ => {}
                 }
             }
 
@@ -237,6 +490,7 @@ impl Player {
             if state.key_trigger.jump() && (self.flags.hit_bottom_wall() || self.flags.hit_right_slope() || self.flags.hit_left_slope()) && !self.flags.force_up() {
                 self.vel_y = -physics.jump;
                 state.sound_manager.play_sfx(15);
+                state.stats.record(StatEvent::Jump);
             }
         }
 
@@ -264,6 +518,10 @@ impl Player {
         if self.flags.force_down() {
             self.vel_y += 0x55;
         }
+        if self.flags.in_water() {
+            self.vel_x += state.ambient_force.0;
+            self.vel_y += state.ambient_force.1;
+        }
 
         if self.equip.has_booster_2_0() && self.booster_switch != 0 {
             match self.booster_switch {
@@ -353,41 +611,15 @@ impl Player {
 
         // spike damage
         if self.flags.hit_by_spike() {
-            self.damage(10, state);
+            self.damage(10, state, inventory);
         }
 
         // camera
-        if self.direction == Direction::Left {
-            self.index_x -= 0x200; // 1.0fix9
-            if self.index_x < -0x8000 { // -64.0fix9
-                self.index_x = -0x8000;
-            }
-        } else { // possible bug?
-            self.index_x += 0x200; // 1.0fix9
-            if self.index_x > 0x8000 { // -64.0fix9
-                self.index_x = 0x8000;
-            }
-        }
-
-        if state.control_flags.control_enabled() && state.key_state.up() {
-            self.index_y -= 0x200; // 1.0fix9
-            if self.index_y < -0x8000 { // -64.0fix9
-                self.index_y = -0x8000;
-            }
-        } else if state.control_flags.control_enabled() && state.key_state.down() {
-            self.index_y += 0x200; // 1.0fix9
-            if self.index_y > 0x8000 { // -64.0fix9
-                self.index_y = 0x8000;
-            }
-        } else {
-            if self.index_y > 0x200 { // 1.0fix9
-                self.index_y -= 0x200;
-            }
-
-            if self.index_y < -0x200 { // 1.0fix9
-                self.index_y += 0x200;
-            }
-        }
+        let controls = state.control_flags.control_enabled();
+        let (index_x, index_y) = step_look_offset(self.index_x, self.index_y, self.direction,
+                                                  controls && state.key_state.up(), controls && state.key_state.down());
+        self.index_x = index_x;
+        self.index_y = index_y;
 
         if self.update_target {
             self.target_x = self.x + self.index_x;
@@ -403,113 +635,101 @@ impl Player {
         Ok(())
     }
 
+    /// Vanilla's ActMyChar_Stream, map collisions still apply.
     fn tick_ironhead(&mut self, state: &mut SharedGameState) -> GameResult {
-        // todo ironhead boss controls
-        Ok(())
-    }
-
-    fn tick_animation(&mut self, state: &mut SharedGameState) {
         if self.cond.hidden() {
-            return;
+            return Ok(());
         }
 
-        if self.flags.hit_bottom_wall() {
-            if self.cond.interacted() {
-                self.anim_num = 11;
-            } else if state.control_flags.control_enabled() && state.key_state.up() && (state.key_state.left() || state.key_state.right()) {
-                self.cond.set_fallen(true);
-
-                self.anim_counter += 1;
-                if self.anim_counter > 4 {
-                    self.anim_counter = 0;
+        self.question = false;
+        self.up = false;
+        self.down = false;
 
-                    self.anim_num += 1;
-                    if self.anim_num == 7 || self.anim_num == 9 {
-                        state.sound_manager.play_sfx(24);
-                    }
-                }
+        let held = if state.control_flags.control_enabled() { Some(state.key_state) } else { None };
+        match held.and_then(resolve_movement) {
+            Some(Direction::Left) => { self.direction = Direction::Left; }
+            Some(Direction::Right) => { self.direction = Direction::Right; }
+            _ => {}
+        }
 
-                if self.anim_num > 9 || self.anim_num < 6 {
-                    self.anim_num = 6;
-                }
-            } else if state.control_flags.control_enabled() && (state.key_state.left() || state.key_state.right()) {
-                self.cond.set_fallen(true);
+        if self.flags.in_water() {
+            self.vel_x += state.ambient_force.0;
+            self.vel_y += state.ambient_force.1;
+        }
 
-                self.anim_counter += 1;
-                if self.anim_counter > 4 {
-                    self.anim_counter = 0;
+        let (vel_x, vel_y) = stream_velocity(self.vel_x, self.vel_y, held);
+        self.vel_x = vel_x;
+        self.vel_y = vel_y;
 
-                    self.anim_num += 1;
-                    if self.anim_num == 2 || self.anim_num == 4 {
-                        state.sound_manager.play_sfx(24);
-                    }
-                }
+        if self.update_target {
+            self.target_x = self.x;
+            self.target_y = self.y;
+        }
 
-                if self.anim_num > 4 || self.anim_num < 1 {
-                    self.anim_num = 1;
-                }
-            } else if state.control_flags.control_enabled() && state.key_state.up() {
-                if self.cond.fallen() {
-                    state.sound_manager.play_sfx(24);
-                }
+        self.x += self.vel_x;
+        self.y += self.vel_y;
 
-                self.cond.set_fallen(false);
-                self.anim_num = 5;
-            } else {
-                if self.cond.fallen() {
-                    state.sound_manager.play_sfx(24);
-                }
+        Ok(())
+    }
 
-                self.cond.set_fallen(false);
-                self.anim_num = 0;
-            }
-        } else if state.key_state.up() {
-            self.anim_num = 6;
-        } else if state.key_state.down() {
-            self.anim_num = 10;
-        } else {
-            self.anim_num = if self.vel_y > 0 { 1 } else { 3 };
-        }
+    /// Nothing but the velocity set by scripts and NPCs moves the player, the camera follows it exactly.
+    fn tick_fixed(&mut self) {
+        self.question = false;
+        self.up = false;
+        self.down = false;
 
-        self.weapon_offset_y = 0;
-        self.weapon_rect.left = (self.current_weapon as usize % 13) * 24;
-        self.weapon_rect.top = (self.current_weapon as usize / 13) * 96;
-        self.weapon_rect.right = self.weapon_rect.left + 24;
-        self.weapon_rect.bottom = self.weapon_rect.top + 16;
+        self.x += self.vel_x;
+        self.y += self.vel_y;
+        self.target_x = self.x;
+        self.target_y = self.y;
+    }
 
-        match self.direction {
-            Direction::Left => {
-                self.anim_rect = state.constants.my_char.animations_left[self.anim_num as usize];
+    /// Facing of the drawn sprite, with late latching it turns to the keys pressed since the last tick
+    /// a frame before the tick turns the player.
+    fn drawn_direction(&self, state: &SharedGameState) -> Direction {
+        match state.latched_key_state {
+            Some(keys) if self.control_mode == ControlMode::Normal && state.control_flags.control_enabled() => {
+                resolve_movement(keys).unwrap_or(self.direction)
             }
-            Direction::Right => {
-                self.weapon_rect.top += 16;
-                self.weapon_rect.bottom += 16;
-                self.anim_rect = state.constants.my_char.animations_right[self.anim_num as usize];
-            }
-            _ => {}
+            _ => self.direction,
         }
+    }
 
-        if self.up {
-            self.weapon_offset_y = -4;
-            self.weapon_rect.top += 32;
-            self.weapon_rect.bottom += 32;
-        } else if self.down {
-            self.weapon_offset_y = 4;
-            self.weapon_rect.top += 64;
-            self.weapon_rect.bottom += 64;
+    fn tick_animation(&mut self, state: &mut SharedGameState) {
+        if self.cond.hidden() {
+            return;
         }
 
-        if self.anim_num == 1 || self.anim_num == 3 || self.anim_num == 6 || self.anim_num == 8 {
-            self.weapon_rect.top += 1;
+        let controls = state.control_flags.control_enabled();
+        let input = AnimationInput {
+            on_ground: self.flags.hit_bottom_wall(),
+            interacted: self.cond.interacted(),
+            walking: controls && resolve_movement(state.key_state).is_some(),
+            looking_up: controls && state.key_state.up(),
+            up: self.up,
+            down: self.down,
+            vel_y: self.vel_y,
+        };
+
+        let step = next_animation(self.anim_num, self.anim_counter, self.cond.fallen(), input);
+        self.anim_num = step.anim_num;
+        self.anim_counter = step.anim_counter;
+        self.cond.set_fallen(step.fallen);
+        if step.footstep {
+            state.sound_manager.play_sfx(24);
         }
+
+        let skin = if self.equip.has_mimiga_mask() { PlayerSkin::MimigaMask } else { PlayerSkin::Quote };
+        self.anim_rect = animation_rect(&state.constants.my_char, self.anim_num, self.direction, skin, state.character);
     }
 
-    pub fn damage(&mut self, hp: isize, state: &mut SharedGameState) {
+    pub fn damage(&mut self, hp: isize, state: &mut SharedGameState, inventory: &mut Inventory) {
         if state.god_mode || self.shock_counter > 0 {
             return;
         }
 
         state.sound_manager.play_sfx(16);
+        state.stats.record(StatEvent::DamageTaken(hp as u16));
         self.shock_counter = 128;
         self.cond.set_interacted(false);
 
@@ -518,21 +738,27 @@ impl Player {
         }
 
         self.life = self.life.saturating_sub(hp as u16);
+        state.rumble(0.2 + (hp as f32 / 10.0).min(0.8), 0.6, 150 + (hp as u64 * 10).min(250));
 
         if self.equip.has_whimsical_star() && self.stars > 0 {
             self.stars -= 1;
         }
 
+        if inventory.take_xp(exp_loss(hp as u16, self.equip), &state.constants) && self.life > 0 {
+            state.create_caret(self.x, self.y, CaretType::LevelUp, Direction::Right);
+        }
+
         if self.life == 0 {
             state.sound_manager.play_sfx(17);
+            state.stats.record(StatEvent::Death);
             self.cond.0 = 0;
-            state.textscript_vm.start_script(40);
+            state.textscript_vm.start_event(40, EventTrigger::Forced, &mut state.control_flags);
         }
     }
 }
 
-impl GameEntity<()> for Player {
-    fn tick(&mut self, state: &mut SharedGameState, _cust: ()) -> GameResult {
+impl GameEntity<&mut Inventory> for Player {
+    fn tick(&mut self, state: &mut SharedGameState, inventory: &mut Inventory) -> GameResult {
         if !self.cond.alive() {
             return Ok(());
         }
@@ -552,17 +778,21 @@ impl GameEntity<()> for Player {
         match self.control_mode {
             ControlMode::Normal => {
                 if state.control_flags.interactions_disabled() && state.control_flags.control_enabled() {
-                    // AirProcess(); // todo
+                    self.tick_air(state);
                 }
 
-                self.tick_normal(state)?;
+                self.tick_normal(state, inventory)?;
             }
             ControlMode::IronHead => {
                 self.tick_ironhead(state)?;
             }
+            ControlMode::Fixed => {
+                self.tick_fixed();
+            }
         }
 
         self.cond.set_cond_x20(false);
+        self.bubble = self.bubble.wrapping_add(1);
         self.tick_animation(state);
 
         Ok(())
@@ -573,39 +803,313 @@ impl GameEntity<()> for Player {
             return Ok(());
         }
 
+        let direction = self.drawn_direction(state);
+
+        if let Some(PlayerPose::Drowned) = state.player_pose {
+            let rect = match direction {
+                Direction::Left => state.constants.caret.drowned_quote_left_rect,
+                _ => state.constants.caret.drowned_quote_right_rect,
+            };
+
+            let batch = state.texture_set.get_or_load_batch(ctx, &state.constants, "Caret")?;
+            batch.add_rect(
+                (((self.x - self.display_bounds.left as isize) / 0x200) - (frame.x / 0x200)) as f32,
+                (((self.y - self.display_bounds.top as isize) / 0x200) - (frame.y / 0x200)) as f32,
+                &rect,
+            );
+            batch.draw(ctx)?;
+
+            // no gun in the pose
+            return Ok(());
+        }
+
         {
+            let rect = if direction == self.direction {
+                self.anim_rect
+            } else {
+                let skin = if self.equip.has_mimiga_mask() { PlayerSkin::MimigaMask } else { PlayerSkin::Quote };
+                animation_rect(&state.constants.my_char, self.anim_num, direction, skin, state.character)
+            };
+
             let batch = state.texture_set.get_or_load_batch(ctx, &state.constants, "MyChar")?;
             batch.add_rect(
                 (((self.x - self.display_bounds.left as isize) / 0x200) - (frame.x / 0x200)) as f32,
                 (((self.y - self.display_bounds.top as isize) / 0x200) - (frame.y / 0x200)) as f32,
-                &self.anim_rect,
+                &rect,
             );
             batch.draw(ctx)?;
         }
 
-        if self.current_weapon != 0 {
+        if let Some((rect, (offset_x, offset_y))) = arms_rect(&state.constants.my_char, self.current_weapon, self.anim_num, direction, self.up, self.down) {
             let batch = state.texture_set.get_or_load_batch(ctx, &state.constants, "Arms")?;
-            match self.direction {
-                Direction::Left => {
-                    batch.add_rect(
-                        (((self.x - self.display_bounds.left as isize) / 0x200) - (frame.x / 0x200)) as f32 - 8.0,
-                        (((self.y - self.display_bounds.top as isize) / 0x200) - (frame.y / 0x200)) as f32 + self.weapon_offset_y as f32,
-                        &self.weapon_rect,
-                    );
-                }
-                Direction::Right => {
-                    batch.add_rect(
-                        (((self.x - self.display_bounds.left as isize) / 0x200) - (frame.x / 0x200)) as f32,
-                        (((self.y - self.display_bounds.top as isize) / 0x200) - (frame.y / 0x200)) as f32 + self.weapon_offset_y as f32,
-                        &self.weapon_rect,
-                    );
-                }
-                _ => {}
-            }
+            batch.add_rect(
+                (((self.x - self.display_bounds.left as isize) / 0x200) - (frame.x / 0x200) + offset_x) as f32,
+                (((self.y - self.display_bounds.top as isize) / 0x200) - (frame.y / 0x200) + offset_y) as f32,
+                &rect,
+            );
+            batch.draw(ctx)?;
+        }
 
+        if self.equip.has_air_tank() && self.flags.in_water() {
+            let batch = state.texture_set.get_or_load_batch(ctx, &state.constants, "Caret")?;
+            let rect = state.constants.my_char.air_tank_bubble[self.bubble as usize / 2 % 2];
+            batch.add_rect(
+                ((self.x / 0x200) - 12 - (frame.x / 0x200)) as f32,
+                ((self.y / 0x200) - 12 - (frame.y / 0x200)) as f32,
+                &rect,
+            );
             batch.draw(ctx)?;
         }
 
         Ok(())
     }
 }
+
+/// Top speed of the `<UNI0001` movement on either axis.
+const STREAM_MAX_SPEED: isize = 0x400;
+/// Top speed on both axes when going diagonally, so it isn't faster than going straight.
+const STREAM_MAX_DIAGONAL_SPEED: isize = 780;
+
+/// Velocity after one tick of the `<UNI0001` movement, vanilla's ActMyChar_Stream. Held directions
+/// (None without control) accelerate the player in eight directions, it slows down to a stop once they're let go.
+pub fn stream_velocity(vel_x: isize, vel_y: isize, held: Option<KeyState>) -> (isize, isize) {
+    let held = held.unwrap_or(KeyState(0));
+    let (mut vel_x, mut vel_y) = (vel_x, vel_y);
+
+    if held.left() || held.right() || held.up() || held.down() {
+        if held.left() { vel_x -= 0x100; }
+        if held.right() { vel_x += 0x100; }
+        if held.up() { vel_y -= 0x100; }
+        if held.down() { vel_y += 0x100; }
+    } else {
+        vel_x = fix9_mul_div(vel_x, 7, 8);
+        vel_y = fix9_mul_div(vel_y, 7, 8);
+    }
+
+    vel_x = clamp(vel_x, -STREAM_MAX_SPEED, STREAM_MAX_SPEED);
+    vel_y = clamp(vel_y, -STREAM_MAX_SPEED, STREAM_MAX_SPEED);
+
+    if (held.left() || held.right()) && (held.up() || held.down()) {
+        vel_x = clamp(vel_x, -STREAM_MAX_DIAGONAL_SPEED, STREAM_MAX_DIAGONAL_SPEED);
+        vel_y = clamp(vel_y, -STREAM_MAX_DIAGONAL_SPEED, STREAM_MAX_DIAGONAL_SPEED);
+    }
+
+    (vel_x, vel_y)
+}
+
+/// Booster 2.0 thrust, picked from the directions held on the tick jump is pressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BoostDirection {
+    Up,
+    Left,
+    Right,
+    Down,
+    /// Nothing held, goes up a bit slower.
+    Neutral,
+}
+
+/// Decides if the Booster 2.0 fires on this tick in mid-air. Only the tick jump is pressed on counts,
+/// directions pressed later don't change the thrust, and it fires once until landing even if there's fuel left.
+pub fn booster_2_0_activation(key_trigger: KeyState, key_state: KeyState, fuel: usize, used: bool) -> Option<BoostDirection> {
+    if !key_trigger.jump() || fuel == 0 || used {
+        return None;
+    }
+
+    let horizontal = resolve_movement(key_state);
+    let vertical = resolve_vertical(key_state);
+
+    // up wins over the sides, the sides over down
+    Some(if vertical == Some(Direction::Up) {
+        BoostDirection::Up
+    } else if horizontal == Some(Direction::Left) {
+        BoostDirection::Left
+    } else if horizontal == Some(Direction::Right) {
+        BoostDirection::Right
+    } else if vertical == Some(Direction::Bottom) {
+        BoostDirection::Down
+    } else {
+        BoostDirection::Neutral
+    })
+}
+
+/// Weapon experience lost when taking damage, the Arms Barrier halves it.
+/// Vanilla doubles the damage without the barrier rather than halving it with one, so odd damage isn't rounded.
+pub fn exp_loss(damage: u16, equip: Equipment) -> u16 {
+    if equip.has_arms_barrier() {
+        damage
+    } else {
+        damage.saturating_mul(2)
+    }
+}
+
+#[test]
+fn test_walk_animation() {
+    let walking = AnimationInput { on_ground: true, walking: true, ..Default::default() };
+
+    let mut step = next_animation(0, 0, false, walking);
+    assert_eq!(step.anim_num, 1);
+    assert!(step.fallen);
+
+    let mut frames = Vec::new();
+    let mut footsteps = 0;
+    for _ in 0..20 {
+        step = next_animation(step.anim_num, step.anim_counter, step.fallen, walking);
+        frames.push(step.anim_num);
+        footsteps += step.footstep as usize;
+    }
+    // a frame lasts 5 ticks, a footstep on every other frame
+    assert_eq!(&frames[..6], &[1, 1, 1, 2, 2, 2]);
+    assert_eq!(frames[14], 4);
+    assert_eq!(frames[19], 1);
+    assert_eq!(footsteps, 2);
+
+    let looking_up = AnimationInput { looking_up: true, ..walking };
+    assert_eq!(next_animation(step.anim_num, step.anim_counter, step.fallen, looking_up).anim_num, 6);
+
+    let stop = next_animation(step.anim_num, step.anim_counter, step.fallen, AnimationInput { on_ground: true, ..Default::default() });
+    assert_eq!(stop.anim_num, 0);
+    assert!(stop.footstep && !stop.fallen);
+}
+
+#[test]
+fn test_airborne_animation() {
+    let air = AnimationInput { vel_y: 0x100, ..Default::default() };
+    assert_eq!(next_animation(0, 0, false, air).anim_num, 1);
+    assert_eq!(next_animation(0, 0, false, AnimationInput { vel_y: -0x100, ..air }).anim_num, 3);
+    assert_eq!(next_animation(0, 0, false, AnimationInput { up: true, ..air }).anim_num, 6);
+    assert_eq!(next_animation(0, 0, false, AnimationInput { down: true, ..air }).anim_num, 10);
+    assert_eq!(next_animation(0, 0, false, AnimationInput { on_ground: true, interacted: true, ..air }).anim_num, 11);
+}
+
+#[test]
+fn test_animation_rect() {
+    use crate::engine_constants::EngineConstants;
+
+    let mut consts = EngineConstants::defaults().my_char;
+    assert_eq!(animation_rect(&consts, 10, Direction::Left, PlayerSkin::Quote, PlayableCharacter::Quote).left, 96);
+    assert_eq!(animation_rect(&consts, 10, Direction::Right, PlayerSkin::Quote, PlayableCharacter::Quote).top, 16);
+    assert_eq!(animation_rect(&consts, 0, Direction::Right, PlayerSkin::MimigaMask, PlayableCharacter::Quote).top, 48);
+
+    // no Curly on the vanilla sheet
+    assert_eq!(animation_rect(&consts, 0, Direction::Left, PlayerSkin::Quote, PlayableCharacter::Curly).top, 0);
+    consts.curly_skin_row = Some(2);
+    assert_eq!(animation_rect(&consts, 0, Direction::Left, PlayerSkin::Quote, PlayableCharacter::Curly).top, 64);
+    assert_eq!(animation_rect(&consts, 0, Direction::Right, PlayerSkin::MimigaMask, PlayableCharacter::Curly).top, 112);
+}
+
+#[test]
+fn test_arms_rect() {
+    use crate::engine_constants::EngineConstants;
+
+    let consts = EngineConstants::defaults().my_char;
+    // unarmed at the start of the game, and when facing away
+    assert_eq!(arms_rect(&consts, 0, 0, Direction::Right, false, false), None);
+    assert_eq!(arms_rect(&consts, 2, 11, Direction::Right, false, false), None);
+
+    // Polar Star
+    assert_eq!(arms_rect(&consts, 2, 0, Direction::Right, false, false), Some((Rect::new(48, 16, 72, 32), (0, 0))));
+    assert_eq!(arms_rect(&consts, 2, 5, Direction::Left, true, false), Some((Rect::new(48, 32, 72, 48), (-8, -4))));
+    assert_eq!(arms_rect(&consts, 2, 10, Direction::Right, false, true), Some((Rect::new(48, 80, 72, 96), (0, 4))));
+    // walking
+    assert_eq!(arms_rect(&consts, 2, 1, Direction::Left, false, false), Some((Rect::new(48, 1, 72, 17), (-8, 0))));
+    // second row of weapons
+    assert_eq!(arms_rect(&consts, 13, 0, Direction::Left, false, false), Some((Rect::new(0, 96, 24, 112), (-8, 0))));
+}
+
+#[test]
+fn test_exp_loss() {
+    let mut equip = Equipment(0);
+    assert_eq!(exp_loss(3, equip), 6);
+    assert_eq!(exp_loss(0, equip), 0);
+
+    equip.set_arms_barrier(true);
+    assert_eq!(exp_loss(3, equip), 3);
+    assert_eq!(exp_loss(1, equip), 1);
+}
+
+#[test]
+fn test_booster_2_0_activation() {
+    const JUMP: u16 = 0x20;
+    const LEFT: u16 = 0x01;
+    const RIGHT: u16 = 0x02;
+    const UP: u16 = 0x04;
+    const DOWN: u16 = 0x08;
+
+    // thrust fired on every tick of a mid-air key sequence, the fuel never runs out here
+    fn run(ticks: &[u16]) -> Vec<Option<BoostDirection>> {
+        let mut old = 0;
+        let mut used = false;
+
+        ticks.iter().map(|&keys| {
+            let trigger = KeyState(keys & !old);
+            old = keys;
+
+            let boost = booster_2_0_activation(trigger, KeyState(keys), 50, used);
+            used |= boost.is_some();
+            boost
+        }).collect()
+    }
+
+    // jump and a direction on the same tick
+    assert_eq!(run(&[0, JUMP | RIGHT]), vec![None, Some(BoostDirection::Right)]);
+    assert_eq!(run(&[0, JUMP | DOWN]), vec![None, Some(BoostDirection::Down)]);
+    // a direction held before jumping
+    assert_eq!(run(&[LEFT, LEFT | JUMP]), vec![None, Some(BoostDirection::Left)]);
+    // a direction one tick too late doesn't change anything
+    assert_eq!(run(&[0, JUMP, JUMP | RIGHT, JUMP | UP]), vec![None, Some(BoostDirection::Neutral), None, None]);
+    // up wins over the sides, opposite directions cancel out
+    assert_eq!(run(&[0, JUMP | UP | RIGHT]), vec![None, Some(BoostDirection::Up)]);
+    assert_eq!(run(&[0, JUMP | LEFT | RIGHT]), vec![None, Some(BoostDirection::Neutral)]);
+    assert_eq!(run(&[0, JUMP | LEFT | RIGHT | DOWN]), vec![None, Some(BoostDirection::Down)]);
+    // letting go and pressing jump again before landing
+    assert_eq!(run(&[0, JUMP | RIGHT, 0, JUMP | UP]), vec![None, Some(BoostDirection::Right), None, None]);
+
+    // no fuel
+    assert_eq!(booster_2_0_activation(KeyState(JUMP), KeyState(JUMP), 0, false), None);
+}
+
+#[test]
+fn test_stream_velocity() {
+    const LEFT: u16 = 0x01;
+    const UP: u16 = 0x04;
+
+    // no gravity, standing still stays still
+    assert_eq!(stream_velocity(0, 0, None), (0, 0));
+
+    let mut vel = (0, 0);
+    for _ in 0..10 {
+        vel = stream_velocity(vel.0, vel.1, Some(KeyState(UP)));
+    }
+    assert_eq!(vel, (0, -STREAM_MAX_SPEED));
+
+    // slower on both axes diagonally
+    vel = stream_velocity(vel.0, vel.1, Some(KeyState(UP | LEFT)));
+    assert_eq!(vel, (-0x100, -STREAM_MAX_DIAGONAL_SPEED));
+
+    // drifts to a stop without control
+    for _ in 0..100 {
+        vel = stream_velocity(vel.0, vel.1, None);
+    }
+    assert_eq!(vel, (0, 0));
+}
+
+#[test]
+fn test_drawn_direction() {
+    const LEFT: u16 = 0x01;
+
+    let mut state = SharedGameState::for_tests();
+    let mut player = Player::new(&mut state);
+    player.direction = Direction::Right;
+    state.control_flags.set_control_enabled(true);
+
+    // left was pressed after the tick, only late latching shows it in this frame
+    state.key_state = KeyState(0);
+    assert_eq!(player.drawn_direction(&state), Direction::Right);
+    state.latched_key_state = Some(KeyState(LEFT));
+    assert_eq!(player.drawn_direction(&state), Direction::Left);
+    assert_eq!(player.direction, Direction::Right);
+
+    // scripts have the control
+    state.control_flags.set_control_enabled(false);
+    assert_eq!(player.drawn_direction(&state), Direction::Right);
+}
@@ -1,12 +1,14 @@
 use std::borrow::Borrow;
 
 use crate::caret::CaretType;
-use crate::common::{Condition, Direction, Flag, Rect};
+use crate::common::{Condition, Direction, Flag, Rect, tile_line_of_sight};
 use crate::inventory::{AddExperienceResult, Inventory};
 use crate::npc::{NPC, NPCMap};
 use crate::physics::PhysicalEntity;
 use crate::player::Player;
 use crate::SharedGameState;
+use crate::stage::Stage;
+use crate::text_script::EventTrigger;
 
 impl PhysicalEntity for Player {
     #[inline(always)]
@@ -136,22 +138,23 @@ impl Player {
         flags
     }
 
-    fn judge_hit_npc_non_solid(&mut self, npc: &NPC) -> Flag {
+    /// `margin` is how far past the player's center the NPC's hitbox can be.
+    fn judge_hit_npc_non_solid(&mut self, npc: &NPC, margin: isize) -> Flag {
         let mut flags = Flag(0);
         let hit_left = if npc.direction == Direction::Left { npc.hit_bounds.left } else { npc.hit_bounds.right } as isize;
         let hit_right = if npc.direction == Direction::Left { npc.hit_bounds.right } else { npc.hit_bounds.left } as isize;
 
-        if self.x + (2 * 0x200) > npc.x - hit_left
-            && self.x - (2 * 0x200) < npc.x + hit_right
-            && self.y + (2 * 0x200) > npc.y - npc.hit_bounds.top as isize
-            && self.y - (2 * 0x200) < npc.y + npc.hit_bounds.bottom as isize {
+        if self.x + margin > npc.x - hit_left
+            && self.x - margin < npc.x + hit_right
+            && self.y + margin > npc.y - npc.hit_bounds.top as isize
+            && self.y - margin < npc.y + npc.hit_bounds.bottom as isize {
             flags.set_hit_left_wall(true);
         }
 
         flags
     }
 
-    pub fn tick_npc_collisions(&mut self, state: &mut SharedGameState, npc_map: &mut NPCMap, inventory: &mut Inventory) {
+    pub fn tick_npc_collisions(&mut self, state: &mut SharedGameState, npc_map: &mut NPCMap, inventory: &mut Inventory, stage: &Stage) {
         for npc_id in npc_map.npc_ids.iter() {
             if let Some(npc_cell) = npc_map.npcs.get(npc_id) {
                 let mut npc = npc_cell.borrow_mut();
@@ -165,11 +168,14 @@ impl Player {
                 } else if npc.npc_flags.solid_hard() {
                     //
                 } else {
-                    flags = self.judge_hit_npc_non_solid(npc.borrow());
+                    let margin = if npc.npc_type == 1 { state.constants.experience.collect_margin } else { 2 * 0x200 };
+                    flags = self.judge_hit_npc_non_solid(npc.borrow(), margin);
                 }
 
-                // xp pickup
-                if flags.0 != 0 && npc.npc_type == 1 {
+                // xp pickup, optionally not through walls
+                if flags.0 != 0 && npc.npc_type == 1
+                    && (!state.settings.allow_exp_line_of_sight()
+                    || tile_line_of_sight(self.x, self.y, npc.x, npc.y, |x, y| stage.map.is_solid(x, y))) {
                     state.sound_manager.play_sfx(14);
                     match inventory.add_xp(npc.exp, state) {
                         AddExperienceResult::None => {}
@@ -186,20 +192,20 @@ impl Player {
                     npc.cond.set_alive(false);
                 }
 
-                if npc.npc_flags.interactable() && !state.control_flags.interactions_disabled() && flags.0 != 0 && self.cond.interacted() {
-                    state.textscript_vm.start_script(npc.event_num);
+                if npc.npc_flags.interactable() && flags.0 != 0 && self.cond.interacted()
+                    && state.textscript_vm.start_event(npc.event_num, EventTrigger::Interaction, &mut state.control_flags) {
                     self.cond.set_interacted(false);
                     self.vel_x = 0;
                     self.question = false;
                 }
 
-                if npc.npc_flags.event_when_touched() && !state.control_flags.interactions_disabled() && flags.0 != 0 {
-                    state.textscript_vm.start_script(npc.event_num);
+                if npc.npc_flags.event_when_touched() && flags.0 != 0 {
+                    state.textscript_vm.start_event(npc.event_num, EventTrigger::Interaction, &mut state.control_flags);
                 }
 
                 if state.control_flags.control_enabled() && !npc.npc_flags.interactable() {
                     if flags.0 != 0 && npc.damage != 0 && !state.control_flags.interactions_disabled() {
-                        self.damage(npc.damage as isize, state);
+                        self.damage(npc.damage as isize, state, inventory);
                     }
                 }
             }
@@ -0,0 +1,875 @@
+use std::fs;
+use std::io;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use byteorder::{LE, ReadBytesExt, WriteBytesExt};
+use num_traits::FromPrimitive;
+
+use crate::common::{Direction, FadeState};
+use crate::ggez::{Context, GameResult};
+use crate::ggez::filesystem::user_dirs;
+use crate::ggez::GameError::ResourceLoadError;
+use crate::inventory::Inventory;
+use crate::player::{ControlMode, PlayableCharacter};
+use crate::save_file;
+use crate::save_file::PendingWrite;
+use crate::scene::game_scene::GameScene;
+use crate::SharedGameState;
+use crate::str;
+use crate::text_script::TextScriptExecutionState;
+use crate::weapon::{Weapon, WeaponLevel, WeaponType};
+
+const PROFILE_MAGIC: &[u8; 8] = b"Do041220";
+const FLAG_MAGIC: &[u8; 4] = b"FLAG";
+/// Event ran after loading a profile, same as vanilla.
+const LOAD_EVENT: u16 = 94;
+/// Size of the vanilla profile, CS+ appends its own data past it.
+const PROFILE_SIZE: usize = 0x604;
+/// Ends our block at the very end of the profile, after whatever CS+ appends, see `Extension::split`.
+const EXTENSION_MAGIC: &[u8; 4] = b"DRSx";
+const EXTENSION_VERSION: u16 = 3;
+/// Life capsules in the original game.
+// todo: let mods set their own count
+pub const LIFE_CAPSULE_COUNT: u8 = 12;
+const WEAPON_SLOT_COUNT: usize = 8;
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct WeaponData {
+    pub weapon_id: u32,
+    pub level: u32,
+    pub exp: u32,
+    pub max_ammo: u32,
+    pub ammo: u32,
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct TeleporterSlotData {
+    pub index: u32,
+    pub event_num: u32,
+}
+
+/// Parts of the vanilla layout the game doesn't use yet, kept from the loaded profile so saving doesn't clear them.
+#[derive(Debug, Copy, Clone)]
+pub struct UntrackedProfileData {
+    /// Only for profiles without a `PlayRecord`, which fills it in otherwise.
+    pub counter: u32,
+    pub teleporter_slots: [TeleporterSlotData; 8],
+    pub map_flags: [u8; 0x80],
+}
+
+impl Default for UntrackedProfileData {
+    fn default() -> Self {
+        UntrackedProfileData {
+            counter: 0,
+            teleporter_slots: [TeleporterSlotData::default(); 8],
+            map_flags: [0; 0x80],
+        }
+    }
+}
+
+/// Progress of a playthrough the vanilla layout has no room for, kept in our block past the vanilla and CS+ data.
+/// Vanilla and CS+ ignore it and drop it when they save, profiles without one show no statistics.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct PlayRecord {
+    /// Time of the game scene ticks which advance the game, at the tick rate they ran at. Ticks the inventory,
+    /// rewinding or the recovery prompt take over don't count and neither do frames paused in the debugger,
+    /// while fast forwarded text counts as it's played. Stored in milliseconds.
+    pub play_time: Duration,
+    /// Taken with `<ML+`.
+    pub life_capsules: u8,
+}
+
+impl PlayRecord {
+    /// Play time in the counter field of the vanilla layout, which counts ticks at 50 per second.
+    /// Only written, the counter of profiles saved by other games can't be told apart from garbage.
+    fn counter(&self) -> u32 {
+        (self.play_time.as_millis() / 20).min(u32::MAX as u128) as u32
+    }
+}
+
+/// Our block past the vanilla and CS+ data, vanilla and CS+ ignore it and drop it when they save.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Extension {
+    record: Option<PlayRecord>,
+    /// See `GameProfile::item_counts`.
+    item_counts: [u16; 32],
+    /// See `GameProfile::tsc_variables`, trailing zeros aren't written.
+    tsc_variables: Vec<u16>,
+}
+
+impl Extension {
+    /// Version 1: version, play time and life capsules. Newer versions only append fields.
+    const MIN_SIZE: usize = 7;
+    /// Play time of a block written for the other fields of a profile without a record.
+    const NO_RECORD: u32 = u32::MAX;
+
+    fn is_empty(&self) -> bool {
+        self.record.is_none() && self.item_counts.iter().all(|&count| count <= 1) && self.tsc_variables.iter().all(|&var| var == 0)
+    }
+
+    /// Splits the data past the vanilla layout into the part written by other games, kept as is, and our block.
+    fn split(extra: &[u8]) -> (&[u8], Option<Extension>) {
+        let trailer = 2 + EXTENSION_MAGIC.len();
+        if extra.len() < trailer || !extra.ends_with(EXTENSION_MAGIC) {
+            return (extra, None);
+        }
+
+        let len_pos = extra.len() - trailer;
+        let len = u16::from_le_bytes([extra[len_pos], extra[len_pos + 1]]) as usize;
+        if len < Extension::MIN_SIZE || len > len_pos {
+            return (extra, None);
+        }
+
+        match Extension::decode(&extra[len_pos - len..len_pos]) {
+            Some(extension) => (&extra[..len_pos - len], Some(extension)),
+            None => (extra, None),
+        }
+    }
+
+    fn decode(mut payload: &[u8]) -> Option<Extension> {
+        let version = payload.read_u16::<LE>().ok()?;
+        if version == 0 {
+            return None;
+        }
+
+        let play_time = payload.read_u32::<LE>().ok()?;
+        let life_capsules = payload.read_u8().ok()?;
+        let mut extension = Extension::default();
+        if play_time != Extension::NO_RECORD {
+            extension.record = Some(PlayRecord { play_time: Duration::from_millis(play_time as u64), life_capsules });
+        }
+
+        if version >= 2 {
+            payload.read_u16_into::<LE>(&mut extension.item_counts).ok()?;
+        }
+
+        if version >= 3 {
+            let count = payload.read_u16::<LE>().ok()? as usize;
+            extension.tsc_variables = vec![0; count];
+            payload.read_u16_into::<LE>(&mut extension.tsc_variables).ok()?;
+        }
+
+        Some(extension)
+    }
+
+    fn write_to<W: Write>(&self, mut data: W) -> GameResult {
+        let mut payload = Vec::new();
+        payload.write_u16::<LE>(EXTENSION_VERSION)?;
+        match self.record {
+            Some(record) => {
+                payload.write_u32::<LE>(record.play_time.as_millis().min(Extension::NO_RECORD as u128 - 1) as u32)?;
+                payload.write_u8(record.life_capsules)?;
+            }
+            None => {
+                payload.write_u32::<LE>(Extension::NO_RECORD)?;
+                payload.write_u8(0)?;
+            }
+        }
+        for &count in self.item_counts.iter() {
+            payload.write_u16::<LE>(count)?;
+        }
+        let variables = self.tsc_variables.iter().rposition(|&var| var != 0).map_or(0, |last| last + 1);
+        payload.write_u16::<LE>(variables as u16)?;
+        for &var in self.tsc_variables[..variables].iter() {
+            payload.write_u16::<LE>(var)?;
+        }
+
+        data.write_all(&payload)?;
+        data.write_u16::<LE>(payload.len() as u16)?;
+        data.write_all(EXTENSION_MAGIC)?;
+
+        Ok(())
+    }
+}
+
+/// Fields of a profile shown on the title screen, see `GameProfile::peek`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProfilePreview {
+    pub current_map: u32,
+    pub max_life: u16,
+    pub life: u16,
+    /// Ids of the weapons in the inventory, in order.
+    pub weapons: Vec<u32>,
+    pub record: Option<PlayRecord>,
+}
+
+impl ProfilePreview {
+    /// Rough share of the game done in percent, every life capsule and filled weapon slot counts the same.
+    /// None for profiles saved without a record.
+    pub fn completion(&self) -> Option<u32> {
+        let record = self.record?;
+        let done = record.life_capsules.min(LIFE_CAPSULE_COUNT) as usize + self.weapons.len().min(WEAPON_SLOT_COUNT);
+
+        Some((done * 100 / (LIFE_CAPSULE_COUNT as usize + WEAPON_SLOT_COUNT)) as u32)
+    }
+}
+
+/// Skips `len` bytes, failing if the data ends before that.
+fn skip<R: Read>(data: &mut R, len: u64) -> GameResult {
+    if io::copy(&mut data.by_ref().take(len), &mut io::sink())? != len {
+        return Err(ResourceLoadError(str!("Unexpected end of file")));
+    }
+
+    Ok(())
+}
+
+/// Profile.dat in the vanilla layout (1540 bytes), values are kept as stored.
+#[derive(Clone)]
+pub struct GameProfile {
+    pub current_map: u32,
+    pub current_song: u32,
+    pub pos_x: i32,
+    pub pos_y: i32,
+    pub direction: u32,
+    pub max_life: u16,
+    pub stars: u16,
+    pub life: u16,
+    /// `PlayableCharacter`, in the field vanilla leaves unused so the profiles stay compatible.
+    pub character: u16,
+    pub current_weapon: u32,
+    pub current_item: u32,
+    pub equipment: u32,
+    pub control_mode: u32,
+    pub counter: u32,
+    pub weapon_data: [WeaponData; 8],
+    pub items: [u32; 32],
+    pub teleporter_slots: [TeleporterSlotData; 8],
+    pub map_flags: [u8; 0x80],
+    /// Game flags packed 8 per byte, LSB first.
+    pub flags: [u8; 1000],
+    /// Whatever follows the vanilla layout (e.g. CS+ beaten flags and challenge times), written back as is.
+    pub extra: Vec<u8>,
+    /// Written after `extra`, None for profiles saved before it was or by other games.
+    pub record: Option<PlayRecord>,
+    /// How many of each item in `items` the player has, 0 and 1 both mean a single one.
+    /// More only with items stacking in CS+ challenges, kept in our block.
+    pub item_counts: [u16; 32],
+    /// Variables of the TSC `variables` extension, kept in our block.
+    pub tsc_variables: Vec<u16>,
+}
+
+impl GameProfile {
+    /// Captures the current game into a profile, the same data <SVP saves in vanilla.
+    pub fn dump(state: &SharedGameState, game_scene: &GameScene) -> GameProfile {
+        let player = &game_scene.player;
+        let inventory = &game_scene.inventory;
+
+        let mut weapon_data = [WeaponData::default(); 8];
+        for (i, data) in weapon_data.iter_mut().enumerate() {
+            if let Some(weapon) = inventory.get_weapon(i) {
+                *data = WeaponData {
+                    weapon_id: weapon.wtype as u32,
+                    level: weapon.level as u32,
+                    exp: weapon.experience as u32,
+                    max_ammo: weapon.max_ammo as u32,
+                    ammo: weapon.ammo as u32,
+                };
+            }
+        }
+
+        let mut items = [0u32; 32];
+        let mut item_counts = [0u16; 32];
+        for ((slot, count), item) in items.iter_mut().zip(item_counts.iter_mut()).zip(inventory.item_ids()) {
+            *slot = item as u32;
+            *count = inventory.item_count(item);
+        }
+
+        let mut flags = [0u8; 1000];
+        for (i, byte) in flags.iter_mut().enumerate() {
+            for bit in 0..8 {
+                if let Some(true) = state.game_flags.get(i * 8 + bit) {
+                    *byte |= 1 << bit;
+                }
+            }
+        }
+
+        GameProfile {
+            current_map: game_scene.stage_id as u32,
+            current_song: state.sound_manager.song_id() as u32,
+            pos_x: player.x as i32,
+            pos_y: player.y as i32,
+            direction: player.direction as u32,
+            max_life: player.max_life,
+            stars: player.stars as u16,
+            life: player.life,
+            character: state.character as u16,
+            current_weapon: inventory.get_current_weapon_idx() as u32,
+            current_item: inventory.get_current_item_idx() as u32,
+            equipment: player.equip.0 as u32,
+            control_mode: player.control_mode as u32,
+            counter: state.play_record.map_or(state.untracked_profile.counter, |record| record.counter()),
+            weapon_data,
+            items,
+            teleporter_slots: state.untracked_profile.teleporter_slots,
+            map_flags: state.untracked_profile.map_flags,
+            flags,
+            extra: Vec::new(),
+            record: state.play_record,
+            item_counts,
+            tsc_variables: state.tsc_variables.clone(),
+        }
+    }
+
+    /// Creates a game scene in the state the profile has been saved in, like `Replay::create_scene`.
+    pub fn create_scene(&self, state: &mut SharedGameState, ctx: &mut Context) -> GameResult<GameScene> {
+        let stage_id = self.current_map as usize;
+        if stage_id >= state.stages.len() {
+            return Err(ResourceLoadError(format!("Profile refers to nonexistent stage {}.", stage_id)));
+        }
+
+        self.restore_state(state);
+
+        let mut scene = GameScene::new(state, ctx, stage_id)?;
+        let player = &mut scene.player;
+        player.x = self.pos_x as isize;
+        player.y = self.pos_y as isize;
+        player.direction = Direction::from_int(self.direction as usize).unwrap_or(Direction::Left);
+        player.max_life = self.max_life;
+        player.life = self.life.min(self.max_life);
+        player.stars = self.stars.min(3) as u8;
+        player.equip.0 = self.equipment as u16;
+        player.control_mode = FromPrimitive::from_u32(self.control_mode).unwrap_or(ControlMode::Normal);
+
+        let mut inventory = Inventory::new();
+        for data in self.weapon_data.iter() {
+            let wtype: Option<WeaponType> = FromPrimitive::from_u32(data.weapon_id);
+            let level: Option<WeaponLevel> = FromPrimitive::from_u32(data.level);
+
+            match (wtype, level) {
+                (Some(WeaponType::None), _) | (None, _) => {}
+                (Some(wtype), level) => {
+                    inventory.push_weapon(Weapon::new(wtype, level.unwrap_or(WeaponLevel::Level1),
+                                                      data.exp as u16, data.ammo as u16, data.max_ammo as u16));
+                }
+            }
+        }
+        for (&item, &count) in self.items.iter().zip(self.item_counts.iter()).filter(|(&item, _)| item != 0) {
+            inventory.add_item(item as u16);
+            for _ in 1..count {
+                inventory.stack_item(item as u16);
+            }
+        }
+        inventory.set_current_weapon_idx(self.current_weapon as u16);
+        inventory.set_current_item_idx(self.current_item as u16);
+        scene.inventory = inventory;
+
+        state.sound_manager.play_song(self.current_song as usize, &state.constants, ctx)?;
+        state.fade_state = FadeState::Hidden;
+        state.textscript_vm.state = TextScriptExecutionState::Running(LOAD_EVENT, 0);
+
+        Ok(scene)
+    }
+
+    /// Resets the shared state to the one saved in the profile, the scene is set up by `create_scene`.
+    fn restore_state(&self, state: &mut SharedGameState) {
+        state.reset_game_state();
+        for i in 0..state.game_flags.len().min(self.flags.len() * 8) {
+            state.game_flags.set(i, self.flags[i / 8] & (1 << (i % 8)) != 0);
+        }
+        state.character = FromPrimitive::from_u16(self.character).unwrap_or(PlayableCharacter::Quote);
+        // there's no telling how long an older profile has been played, it keeps showing no statistics
+        state.play_record = self.record;
+        for (var, &value) in state.tsc_variables.iter_mut().zip(self.tsc_variables.iter()) {
+            *var = value;
+        }
+        state.untracked_profile = UntrackedProfileData {
+            counter: self.counter,
+            teleporter_slots: self.teleporter_slots,
+            map_flags: self.map_flags,
+        };
+    }
+
+    /// Parses the vanilla part strictly and keeps anything after it, so profiles from CS+ survive being saved again.
+    pub fn load_from<R: Read>(mut reader: R) -> GameResult<GameProfile> {
+        let mut buf = Vec::with_capacity(PROFILE_SIZE);
+        reader.read_to_end(&mut buf)?;
+
+        if buf.len() < PROFILE_MAGIC.len() || &buf[..PROFILE_MAGIC.len()] != PROFILE_MAGIC {
+            return Err(ResourceLoadError(str!("Not a Cave Story profile.")));
+        }
+        if buf.len() < PROFILE_SIZE {
+            return Err(ResourceLoadError(format!("Profile is truncated ({} bytes, expected at least {}).", buf.len(), PROFILE_SIZE)));
+        }
+
+        let mut data = &buf[PROFILE_MAGIC.len()..PROFILE_SIZE];
+
+        let current_map = data.read_u32::<LE>()?;
+        let current_song = data.read_u32::<LE>()?;
+        let pos_x = data.read_i32::<LE>()?;
+        let pos_y = data.read_i32::<LE>()?;
+        let direction = data.read_u32::<LE>()?;
+        let max_life = data.read_u16::<LE>()?;
+        let stars = data.read_u16::<LE>()?;
+        let life = data.read_u16::<LE>()?;
+        let character = data.read_u16::<LE>()?;
+        let current_weapon = data.read_u32::<LE>()?;
+        let current_item = data.read_u32::<LE>()?;
+        let equipment = data.read_u32::<LE>()?;
+        let control_mode = data.read_u32::<LE>()?;
+        let counter = data.read_u32::<LE>()?;
+
+        let mut weapon_data = [WeaponData::default(); 8];
+        for weap in weapon_data.iter_mut() {
+            weap.weapon_id = data.read_u32::<LE>()?;
+            weap.level = data.read_u32::<LE>()?;
+            weap.exp = data.read_u32::<LE>()?;
+            weap.max_ammo = data.read_u32::<LE>()?;
+            weap.ammo = data.read_u32::<LE>()?;
+        }
+
+        let mut items = [0u32; 32];
+        data.read_u32_into::<LE>(&mut items)?;
+
+        let mut teleporter_slots = [TeleporterSlotData::default(); 8];
+        for slot in teleporter_slots.iter_mut() {
+            slot.index = data.read_u32::<LE>()?;
+            slot.event_num = data.read_u32::<LE>()?;
+        }
+
+        let mut map_flags = [0u8; 0x80];
+        data.read_exact(&mut map_flags)?;
+
+        let mut flag_magic = [0u8; 4];
+        data.read_exact(&mut flag_magic)?;
+        if &flag_magic != FLAG_MAGIC {
+            return Err(ResourceLoadError(str!("Invalid FLAG signature")));
+        }
+
+        let mut flags = [0u8; 1000];
+        data.read_exact(&mut flags)?;
+
+        let (extra, extension) = Extension::split(&buf[PROFILE_SIZE..]);
+        let extension = extension.unwrap_or_default();
+
+        Ok(GameProfile {
+            current_map,
+            current_song,
+            pos_x,
+            pos_y,
+            direction,
+            max_life,
+            stars,
+            life,
+            character,
+            current_weapon,
+            current_item,
+            equipment,
+            control_mode,
+            counter,
+            weapon_data,
+            items,
+            teleporter_slots,
+            map_flags,
+            flags,
+            extra: extra.to_vec(),
+            record: extension.record,
+            item_counts: extension.item_counts,
+            tsc_variables: extension.tsc_variables,
+        })
+    }
+
+    /// Reads just the fields needed for the title screen, checks the signatures so corrupt profiles are still noticed.
+    pub fn peek<R: Read>(mut data: R) -> GameResult<ProfilePreview> {
+        let mut magic = [0u8; 8];
+        data.read_exact(&mut magic)?;
+        if &magic != PROFILE_MAGIC {
+            return Err(ResourceLoadError(str!("Invalid magic")));
+        }
+
+        let current_map = data.read_u32::<LE>()?;
+        skip(&mut data, 16)?; // song, position, direction
+        let max_life = data.read_u16::<LE>()?;
+        let _ = data.read_u16::<LE>()?; // stars
+        let life = data.read_u16::<LE>()?;
+        skip(&mut data, 22)?; // unused, current weapon/item, equipment, control mode, counter
+
+        let mut weapons = Vec::new();
+        for _ in 0..8 {
+            let weapon_id = data.read_u32::<LE>()?;
+            skip(&mut data, 16)?;
+
+            if weapon_id != 0 {
+                weapons.push(weapon_id);
+            }
+        }
+
+        skip(&mut data, 32 * 4 + 8 * 8 + 0x80)?; // items, teleporter slots, map flags
+
+        let mut flag_magic = [0u8; 4];
+        data.read_exact(&mut flag_magic)?;
+        if &flag_magic != FLAG_MAGIC {
+            return Err(ResourceLoadError(str!("Invalid FLAG signature")));
+        }
+
+        skip(&mut data, 1000)?; // flags
+        let mut extra = Vec::new();
+        data.read_to_end(&mut extra)?;
+
+        Ok(ProfilePreview {
+            current_map,
+            max_life,
+            life,
+            weapons,
+            record: Extension::split(&extra).1.and_then(|extension| extension.record),
+        })
+    }
+
+    pub fn write_to<W: Write>(&self, mut data: W) -> GameResult {
+        data.write_all(PROFILE_MAGIC)?;
+        data.write_u32::<LE>(self.current_map)?;
+        data.write_u32::<LE>(self.current_song)?;
+        data.write_i32::<LE>(self.pos_x)?;
+        data.write_i32::<LE>(self.pos_y)?;
+        data.write_u32::<LE>(self.direction)?;
+        data.write_u16::<LE>(self.max_life)?;
+        data.write_u16::<LE>(self.stars)?;
+        data.write_u16::<LE>(self.life)?;
+        data.write_u16::<LE>(self.character)?;
+        data.write_u32::<LE>(self.current_weapon)?;
+        data.write_u32::<LE>(self.current_item)?;
+        data.write_u32::<LE>(self.equipment)?;
+        data.write_u32::<LE>(self.control_mode)?;
+        data.write_u32::<LE>(self.counter)?;
+
+        for weap in self.weapon_data.iter() {
+            data.write_u32::<LE>(weap.weapon_id)?;
+            data.write_u32::<LE>(weap.level)?;
+            data.write_u32::<LE>(weap.exp)?;
+            data.write_u32::<LE>(weap.max_ammo)?;
+            data.write_u32::<LE>(weap.ammo)?;
+        }
+
+        for &item in self.items.iter() {
+            data.write_u32::<LE>(item)?;
+        }
+
+        for slot in self.teleporter_slots.iter() {
+            data.write_u32::<LE>(slot.index)?;
+            data.write_u32::<LE>(slot.event_num)?;
+        }
+
+        data.write_all(&self.map_flags)?;
+        data.write_all(FLAG_MAGIC)?;
+        data.write_all(&self.flags)?;
+        data.write_all(&self.extra)?;
+        let extension = Extension { record: self.record, item_counts: self.item_counts, tsc_variables: self.tsc_variables.clone() };
+        if !extension.is_empty() {
+            extension.write_to(&mut data)?;
+        }
+
+        Ok(())
+    }
+
+    fn path(state: &SharedGameState) -> GameResult<PathBuf> {
+        let mod_id = state.current_mod.as_ref().map(|m| m.id.as_str());
+        Ok(user_dirs()?.profile_path(mod_id))
+    }
+
+    /// Loads the profile from the save directory, None if there's no profile yet.
+    /// Falls back to the backup if the profile is damaged, letting the player know.
+    pub fn load(state: &mut SharedGameState) -> GameResult<Option<GameProfile>> {
+        let path = GameProfile::path(state)?;
+
+        match save_file::read_with_backup(&path, |data| GameProfile::load_from(data))? {
+            Some((profile, from_backup)) => {
+                if from_backup {
+                    state.notifications.push(str!("The save file is damaged, loaded the previous save instead."));
+                }
+
+                Ok(Some(profile))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Previews the profile in the save directory, None if there's no profile yet.
+    pub fn peek_saved(state: &SharedGameState) -> GameResult<Option<ProfilePreview>> {
+        let path = GameProfile::path(state)?;
+
+        Ok(save_file::read_with_backup(&path, |data| GameProfile::peek(data))?.map(|(preview, _)| preview))
+    }
+
+    /// Profiles dumped from the game don't know about the data past the vanilla layout,
+    /// it's carried over from the profile being overwritten and our block goes after it.
+    /// The file is written on another thread, the previous one is kept as Profile.bak.
+    pub fn save(&self, state: &SharedGameState) -> GameResult<PendingWrite> {
+        let path = GameProfile::path(state)?;
+
+        let mut data = Vec::with_capacity(PROFILE_SIZE);
+        self.write_to(&mut data)?;
+        let carry_extra = self.extra.is_empty();
+
+        Ok(PendingWrite::spawn(move || {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+
+            if carry_extra {
+                if let Ok(old) = fs::read(&path) {
+                    if old.len() > PROFILE_SIZE && old.starts_with(PROFILE_MAGIC) {
+                        let extension = data.split_off(PROFILE_SIZE);
+                        data.extend_from_slice(Extension::split(&old[PROFILE_SIZE..]).0);
+                        data.extend_from_slice(&extension);
+                    }
+                }
+            }
+
+            save_file::write_atomic(&path, &data)
+        }))
+    }
+}
+
+/// Decodes the Nikumaru counter record (290.rec), the best time through the Sacred Grounds in ticks.
+/// The time is stored 4 times, each obfuscated with its own random byte.
+pub fn decode_record(data: &[u8]) -> Option<u32> {
+    if data.len() < 20 {
+        return None;
+    }
+
+    let mut counters = [0u32; 4];
+    for (i, counter) in counters.iter_mut().enumerate() {
+        let random = data[16 + i];
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(&data[i * 4..i * 4 + 4]);
+
+        bytes[0] = bytes[0].wrapping_sub(random);
+        bytes[1] = bytes[1].wrapping_sub(random);
+        bytes[2] = bytes[2].wrapping_sub(random);
+        bytes[3] = bytes[3].wrapping_sub(random / 2);
+        *counter = u32::from_le_bytes(bytes);
+    }
+
+    // vanilla compares only the first three
+    if counters[0] != counters[1] || counters[0] != counters[2] {
+        return None;
+    }
+
+    Some(counters[0])
+}
+
+/// Best Sacred Grounds time from the save directory, if there's a valid record.
+pub fn load_record(state: &SharedGameState) -> Option<u32> {
+    let mod_id = state.current_mod.as_ref().map(|m| m.id.as_str());
+    let data = fs::read(user_dirs().ok()?.record_path(mod_id)).ok()?;
+
+    decode_record(&data)
+}
+
+#[cfg(test)]
+use crate::map::Map;
+#[cfg(test)]
+use crate::stage::Stage;
+#[cfg(test)]
+use crate::text_script::TSC_VARIABLE_COUNT;
+
+#[test]
+fn test_profile_round_trip() {
+    let mut profile = GameProfile {
+        current_map: 13,
+        current_song: 8,
+        pos_x: 10 * 16 * 0x200,
+        pos_y: 8 * 16 * 0x200,
+        direction: 2,
+        max_life: 3,
+        stars: 0,
+        life: 3,
+        character: PlayableCharacter::Curly as u16,
+        current_weapon: 0,
+        current_item: 0,
+        equipment: 0,
+        control_mode: 0,
+        counter: 0,
+        weapon_data: [WeaponData::default(); 8],
+        items: [0; 32],
+        teleporter_slots: [TeleporterSlotData::default(); 8],
+        map_flags: [0; 0x80],
+        flags: [0; 1000],
+        extra: Vec::new(),
+        record: None,
+        item_counts: [0; 32],
+        tsc_variables: Vec::new(),
+    };
+    profile.weapon_data[0] = WeaponData { weapon_id: 2, level: 1, exp: 0, max_ammo: 0, ammo: 0 };
+    profile.items[0] = 1;
+    profile.flags[0] = 0b0000_0010;
+
+    let mut data = Vec::new();
+    profile.write_to(&mut data).unwrap();
+    assert_eq!(data.len(), PROFILE_SIZE);
+    assert_eq!(&data[0x218..0x21c], FLAG_MAGIC);
+
+    let loaded = GameProfile::load_from(&data[..]).unwrap();
+    assert_eq!(loaded.current_map, 13);
+    assert_eq!(loaded.pos_x, 10 * 16 * 0x200);
+    assert_eq!(loaded.weapon_data[0], profile.weapon_data[0]);
+    assert_eq!(loaded.items[0], 1);
+    assert_eq!(loaded.flags[0], 0b0000_0010);
+    assert_eq!(loaded.character, PlayableCharacter::Curly as u16);
+
+    let preview = GameProfile::peek(&data[..]).unwrap();
+    assert_eq!(preview, ProfilePreview { current_map: 13, max_life: 3, life: 3, weapons: vec![2], record: None });
+    assert_eq!(preview.completion(), None);
+    assert!(GameProfile::peek(&data[..0x100]).is_err());
+
+    data[0] = b'X';
+    assert!(GameProfile::load_from(&data[..]).is_err());
+    assert!(GameProfile::peek(&data[..]).is_err());
+}
+
+#[test]
+fn test_profile_fixtures() {
+    let freeware = include_bytes!("profile_freeware.dat");
+    let profile = GameProfile::load_from(&freeware[..]).unwrap();
+    assert_eq!(profile.current_map, 12);
+    assert_eq!(profile.current_song, 8);
+    assert_eq!((profile.pos_x, profile.pos_y), (0x1a000, 0x8000));
+    assert_eq!((profile.life, profile.max_life), (4, 5));
+    assert_eq!(profile.equipment, 0x01);
+    assert_eq!(profile.weapon_data[0], WeaponData { weapon_id: 2, level: 2, exp: 7, max_ammo: 0, ammo: 0 });
+    assert_eq!(profile.weapon_data[1], WeaponData { weapon_id: 5, level: 1, exp: 0, max_ammo: 5, ammo: 3 });
+    assert_eq!(&profile.items[..3], &[1, 2, 0]);
+    assert_eq!((profile.map_flags[12], profile.map_flags[13]), (1, 1));
+    assert_eq!((profile.flags[0], profile.flags[100]), (0b10, 0x81));
+    assert!(profile.extra.is_empty());
+
+    let mut data = Vec::new();
+    profile.write_to(&mut data).unwrap();
+    assert_eq!(&data[..], &freeware[..]);
+
+    // CS+ appends its own data, which has to survive a round trip byte for byte
+    let csplus = include_bytes!("profile_csplus.dat");
+    let profile = GameProfile::load_from(&csplus[..]).unwrap();
+    assert_eq!(profile.current_map, 12);
+    assert_eq!(&profile.extra[..], &csplus[PROFILE_SIZE..]);
+
+    let mut data = Vec::new();
+    profile.write_to(&mut data).unwrap();
+    assert_eq!(&data[..], &csplus[..]);
+
+    // truncated files and files which aren't profiles at all are told apart
+    let truncated = GameProfile::load_from(&freeware[..0x400]).err().unwrap().to_string();
+    assert!(truncated.contains("truncated"), "{}", truncated);
+    let garbage = GameProfile::load_from(&b"PNG\r\n"[..]).err().unwrap().to_string();
+    assert!(garbage.contains("Not a Cave Story profile"), "{}", garbage);
+}
+
+#[test]
+fn test_decode_record() {
+    let time = 3 * 3000 + 25 * 50u32;
+    let random = [0x13u8, 0x80, 0xff, 0x00];
+
+    let mut data = vec![0u8; 20];
+    for i in 0..4 {
+        let mut bytes = time.to_le_bytes();
+        bytes[0] = bytes[0].wrapping_add(random[i]);
+        bytes[1] = bytes[1].wrapping_add(random[i]);
+        bytes[2] = bytes[2].wrapping_add(random[i]);
+        bytes[3] = bytes[3].wrapping_add(random[i] / 2);
+        data[i * 4..i * 4 + 4].copy_from_slice(&bytes);
+        data[16 + i] = random[i];
+    }
+
+    assert_eq!(decode_record(&data), Some(time));
+    assert_eq!(decode_record(&data[..19]), None);
+
+    data[5] ^= 1;
+    assert_eq!(decode_record(&data), None);
+}
+
+#[test]
+fn test_play_record() {
+    let record = PlayRecord { play_time: Duration::from_secs(90 * 60), life_capsules: 4 };
+    assert_eq!(record.counter(), 90 * 60 * 50);
+
+    // goes after the CS+ data, which stays where CS+ expects it
+    let csplus = include_bytes!("profile_csplus.dat");
+    let mut profile = GameProfile::load_from(&csplus[..]).unwrap();
+    assert_eq!(profile.record, None);
+    profile.record = Some(record);
+
+    let mut data = Vec::new();
+    profile.write_to(&mut data).unwrap();
+    assert_eq!(&data[..csplus.len()], &csplus[..]);
+
+    let loaded = GameProfile::load_from(&data[..]).unwrap();
+    assert_eq!(loaded.record, Some(record));
+    assert_eq!(&loaded.extra[..], &csplus[PROFILE_SIZE..]);
+
+    let preview = GameProfile::peek(&data[..]).unwrap();
+    assert_eq!(preview.record, Some(record));
+    // 4 life capsules and 2 weapons
+    assert_eq!(preview.completion(), Some(30));
+
+    // stacked items go in the block too, even without a record
+    profile.record = None;
+    profile.items[2] = 18;
+    profile.item_counts[2] = 3;
+    let mut data = Vec::new();
+    profile.write_to(&mut data).unwrap();
+    let loaded = GameProfile::load_from(&data[..]).unwrap();
+    assert_eq!(loaded.record, None);
+    assert_eq!(loaded.item_counts[2], 3);
+    assert_eq!(&loaded.extra[..], &csplus[PROFILE_SIZE..]);
+
+    // and so do the script variables
+    profile.item_counts[2] = 0;
+    profile.tsc_variables = vec![0; 256];
+    profile.tsc_variables[3] = 500;
+    let mut data = Vec::new();
+    profile.write_to(&mut data).unwrap();
+    // the zeros after the last one set aren't written
+    assert_eq!(data.len(), csplus.len() + 7 + 64 + 2 + 4 * 2 + 6);
+    let loaded = GameProfile::load_from(&data[..]).unwrap();
+    assert_eq!(loaded.tsc_variables, vec![0, 0, 0, 500]);
+
+    let mut state = SharedGameState::for_tests();
+    loaded.restore_state(&mut state);
+    assert_eq!(state.tsc_variables.len(), TSC_VARIABLE_COUNT);
+    assert_eq!(state.tsc_variables[3], 500);
+
+    // version 1 blocks have no item counts
+    let mut old = csplus.to_vec();
+    old.extend_from_slice(&[1, 0, 0x10, 0, 0, 0, 2, 7, 0]);
+    old.extend_from_slice(EXTENSION_MAGIC);
+    let loaded = GameProfile::load_from(&old[..]).unwrap();
+    assert_eq!(loaded.record, Some(PlayRecord { play_time: Duration::from_millis(0x10), life_capsules: 2 }));
+    assert_eq!(loaded.item_counts, [0; 32]);
+    assert!(loaded.tsc_variables.is_empty());
+
+    // data of other games which happens to end like our block isn't taken for one
+    let mut foreign = csplus[PROFILE_SIZE..].to_vec();
+    foreign.extend_from_slice(&[0xff, 0xff]);
+    foreign.extend_from_slice(EXTENSION_MAGIC);
+    assert_eq!(Extension::split(&foreign), (&foreign[..], None));
+}
+
+#[test]
+fn test_profile_carry() {
+    let freeware = include_bytes!("profile_freeware.dat");
+    let mut profile = GameProfile::load_from(&freeware[..]).unwrap();
+    profile.counter = 1234;
+    profile.teleporter_slots[0] = TeleporterSlotData { index: 1, event_num: 1001 };
+
+    // what <LDP goes through, minus the scene which needs the data files
+    let mut state = SharedGameState::for_tests();
+    profile.restore_state(&mut state);
+    assert!(state.game_flags[1]);
+    assert_eq!(state.play_record, None);
+
+    let scene = GameScene::for_tests(&mut state, profile.current_map as usize, Stage::for_tests(Map { width: 1, height: 1, tiles: vec![0], attrib: [0; 0x100], revision: 0 }));
+    let dumped = GameProfile::dump(&state, &scene);
+    assert_eq!(dumped.counter, 1234);
+    assert_eq!(dumped.teleporter_slots, profile.teleporter_slots);
+    assert_eq!(&dumped.map_flags[..], &profile.map_flags[..]);
+    assert_eq!(&dumped.flags[..], &profile.flags[..]);
+
+    // a new game clears them, and the counter follows the play time from then on
+    state.reset_game_state();
+    state.play_record = Some(PlayRecord { play_time: Duration::from_secs(2), life_capsules: 0 });
+    let dumped = GameProfile::dump(&state, &scene);
+    assert_eq!(dumped.counter, 100);
+    assert_eq!(dumped.teleporter_slots, [TeleporterSlotData::default(); 8]);
+    assert_eq!(dumped.map_flags[12], 0);
+}
@@ -0,0 +1,158 @@
+use crate::common::{KeyState, Rect};
+use crate::ggez::{Context, GameResult};
+use crate::ggez::event::{Button, KeyCode};
+use crate::input_buffer::InputDevice;
+use crate::key_bindings::bit;
+use crate::render::primitives::Primitives;
+use crate::SharedGameState;
+
+/// Key state bits are stored as private use characters, so engine generated strings can embed
+/// prompts with `format!`. Scripts can't produce them, `TextScriptVM` drops them.
+const TOKEN_BASE: u32 = 0xf700;
+
+const BOX_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+const BOX_FILL: [f32; 4] = [0.0, 0.0, 0.0, 0.6];
+
+/// Token standing for the key bound to the key state bit in `mask`, e.g. `token(0x20)` for jump.
+pub fn token(mask: u16) -> char {
+    std::char::from_u32(TOKEN_BASE + mask.trailing_zeros()).unwrap_or('?')
+}
+
+/// Token for the key state bit set by the given `KeyState` setter, e.g. `token_for(KeyState::set_jump)`.
+pub fn token_for(set: fn(&mut KeyState, bool)) -> char {
+    token(bit(set))
+}
+
+/// Key state bit of a token.
+pub fn token_mask(chr: char) -> Option<u16> {
+    let code = chr as u32;
+    if (TOKEN_BASE..TOKEN_BASE + 16).contains(&code) {
+        Some(1 << (code - TOKEN_BASE))
+    } else {
+        None
+    }
+}
+
+enum Segment {
+    Text(String),
+    Glyph(Rect<usize>),
+    /// Key name drawn in a box, for the keyboard and buttons without a glyph.
+    Key(String),
+}
+
+fn key_name(key: KeyCode) -> String {
+    match key {
+        KeyCode::Back => "Backspace".to_string(),
+        KeyCode::Escape => "Esc".to_string(),
+        KeyCode::Return => "Enter".to_string(),
+        KeyCode::LShift | KeyCode::RShift => "Shift".to_string(),
+        KeyCode::LControl | KeyCode::RControl => "Ctrl".to_string(),
+        KeyCode::LAlt | KeyCode::RAlt => "Alt".to_string(),
+        _ => format!("{:?}", key),
+    }
+}
+
+/// Resolves a token to the binding on the device used last, bindings are looked up every time
+/// so the prompts follow the changes right away.
+fn resolve(mask: u16, state: &SharedGameState) -> Segment {
+    if let InputDevice::Gamepad(_) = state.last_input_device {
+        if let Some(button) = state.key_bindings.button_for(mask) {
+            return match button_rect(button, state) {
+                Some(rect) => Segment::Glyph(rect),
+                None => Segment::Key(format!("{:?}", button)),
+            };
+        }
+    }
+
+    match state.key_bindings.key_for(mask) {
+        Some(key) => Segment::Key(key_name(key)),
+        None => Segment::Key("?".to_string()),
+    }
+}
+
+fn button_rect(button: Button, state: &SharedGameState) -> Option<Rect<usize>> {
+    state.constants.prompts.button_rects.iter().find(|&&(b, _)| b == button).map(|&(_, rect)| rect)
+}
+
+fn segments<I: Iterator<Item=char>>(iter: I, state: &SharedGameState) -> Vec<Segment> {
+    let mut segments = Vec::new();
+    let mut text = String::new();
+
+    for chr in iter {
+        match token_mask(chr) {
+            Some(mask) => {
+                if !text.is_empty() {
+                    segments.push(Segment::Text(text.clone()));
+                    text.clear();
+                }
+                segments.push(resolve(mask, state));
+            }
+            None => text.push(chr),
+        }
+    }
+
+    if !text.is_empty() {
+        segments.push(Segment::Text(text));
+    }
+
+    segments
+}
+
+fn segment_width(segment: &Segment, scale: f32, state: &SharedGameState) -> f32 {
+    match segment {
+        Segment::Text(text) => state.font.text_width(text.chars(), &state.constants) * scale,
+        Segment::Glyph(rect) => (rect.width() as f32 + 1.0) * scale,
+        Segment::Key(name) => (state.font.text_width(name.chars(), &state.constants) + 7.0) * scale,
+    }
+}
+
+/// Draws text with prompt tokens replaced by the key or the gamepad button bound to them.
+pub fn draw_text<I: Iterator<Item=char>>(iter: I, x: f32, y: f32, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
+    draw_text_scaled(iter, x, y, 1.0, state, ctx)
+}
+
+pub fn draw_text_scaled<I: Iterator<Item=char>>(iter: I, x: f32, y: f32, scale: f32, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
+    let mut x = x;
+
+    for segment in segments(iter, state) {
+        let width = segment_width(&segment, scale, state);
+
+        match &segment {
+            Segment::Text(text) => {
+                state.font.draw_text_scaled(text.chars(), x, y, scale, &state.constants, &mut state.texture_set, ctx)?;
+            }
+            Segment::Glyph(rect) => {
+                // glyphs are a bit taller than the font
+                let batch = state.texture_set.get_or_load_batch(ctx, &state.constants, &state.constants.prompts.texture)?;
+                batch.add_rect_scaled(x, y - 3.0 * scale, scale, scale, rect);
+                batch.draw(ctx)?;
+            }
+            Segment::Key(name) => {
+                let left = x as isize;
+                let top = (y - 2.0 * scale) as isize;
+                let right = (x + width - scale) as isize;
+                let bottom = (y + 12.0 * scale) as isize;
+                let border = scale.max(1.0) as isize;
+
+                let mut primitives = Primitives::new();
+                primitives.rect(Rect { left, top, right, bottom }, BOX_FILL);
+                primitives.outline(Rect { left, top, right, bottom }, BOX_COLOR, border as f32);
+                state.texture_set.draw_primitives(ctx, &primitives)?;
+                state.font.draw_text_scaled(name.chars(), x + 3.0 * scale, y, scale, &state.constants, &mut state.texture_set, ctx)?;
+            }
+        }
+
+        x += width;
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_prompt_tokens() {
+    let text = format!("Press {} to jump", token_for(KeyState::set_jump));
+    let masks: Vec<u16> = text.chars().filter_map(token_mask).collect();
+    assert_eq!(masks, vec![0x20]);
+    assert_eq!(token_mask(token(1 << 10)), Some(1 << 10));
+    assert_eq!(token_mask('Z'), None);
+}
@@ -0,0 +1,153 @@
+use crate::ggez::{Context, GameResult, graphics};
+use crate::ggez::conf::NumSamples;
+use crate::ggez::graphics::{Canvas, DrawParam, FilterMode, Rect, Shader};
+use crate::ggez::nalgebra::{Point2, Vector2};
+use crate::settings::Presentation;
+use crate::SharedGameState;
+
+pub mod primitives;
+pub mod render_pass;
+
+gfx_defines! {
+    constant CrtConsts {
+        source_size: [f32; 2] = "u_SourceSize",
+        output_size: [f32; 2] = "u_OutputSize",
+    }
+}
+
+/// Coordinate spaces things are drawn in, each with its own transform:
+/// - `World`: stage pixels relative to the camera view, which is shaken by `<QUA`, see `Frame::view`.
+/// - `Canvas`: canvas pixels, for the HUD, text boxes and fades, which neither follow the camera nor shake.
+/// - `Window`: unscaled window pixels, for the debug UI so it stays crisp at any scale.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum DrawSpace {
+    World,
+    Canvas,
+    Window,
+}
+
+impl DrawSpace {
+    /// Sets the screen coordinates and the transform of the space, whatever the previous drawing left behind.
+    /// The world offset comes with the frame each entity is drawn relative to, so it shares the canvas transform.
+    pub fn enter(self, state: &SharedGameState, ctx: &mut Context) -> GameResult {
+        let (width, height) = match self {
+            DrawSpace::World | DrawSpace::Canvas => {
+                let (width, height) = GameCanvas::canvas_size(state);
+                (width as f32, height as f32)
+            }
+            DrawSpace::Window => state.screen_size,
+        };
+
+        graphics::set_screen_coordinates(ctx, Rect::new(0.0, 0.0, width, height))?;
+        graphics::set_transform(ctx, DrawParam::new().to_matrix());
+        graphics::apply_transformations(ctx)
+    }
+}
+
+/// Offscreen render target the game is drawn into at its internal resolution,
+/// which is then scaled up to the window in one go (optionally through the CRT filter).
+pub struct GameCanvas {
+    canvas: Option<Canvas>,
+    crt_shader: Option<Shader<CrtConsts>>,
+    crt_unavailable: bool,
+}
+
+impl GameCanvas {
+    pub fn new() -> Self {
+        Self {
+            canvas: None,
+            crt_shader: None,
+            crt_unavailable: false,
+        }
+    }
+
+    /// Size of the canvas in game pixels and where it's drawn in the window, for a window of `screen_size`.
+    pub fn layout(screen_size: (f32, f32), scale: f32, presentation: Presentation) -> ((f32, f32), (f32, f32)) {
+        let (mut width, height) = (screen_size.0 / scale, screen_size.1 / scale);
+
+        if presentation == Presentation::Pillarbox {
+            width = width.min((height * 4.0 / 3.0).floor());
+        }
+
+        let offset_x = ((screen_size.0 - width * scale) / 2.0).floor();
+        ((width, height), (offset_x, 0.0))
+    }
+
+    fn canvas_size(state: &SharedGameState) -> (u16, u16) {
+        (state.canvas_size.0.ceil().max(1.0) as u16, state.canvas_size.1.ceil().max(1.0) as u16)
+    }
+
+    /// Redirects drawing into the canvas, coordinates are in game pixels afterwards.
+    pub fn begin(&mut self, state: &SharedGameState, ctx: &mut Context) -> GameResult {
+        let (width, height) = GameCanvas::canvas_size(state);
+
+        let recreate = match self.canvas.as_ref() {
+            Some(canvas) => canvas.image().width() != width || canvas.image().height() != height,
+            None => true,
+        };
+
+        if recreate {
+            let mut canvas = Canvas::new(ctx, width, height, NumSamples::One)?;
+            canvas.set_filter(FilterMode::Nearest);
+            self.canvas = Some(canvas);
+        }
+
+        graphics::set_canvas(ctx, self.canvas.as_ref());
+        graphics::set_screen_coordinates(ctx, Rect::new(0.0, 0.0, width as f32, height as f32))?;
+
+        Ok(())
+    }
+
+    /// Switches back to the window and draws the canvas to it.
+    pub fn finish(&mut self, state: &SharedGameState, ctx: &mut Context) -> GameResult {
+        graphics::set_canvas(ctx, None);
+        graphics::set_screen_coordinates(ctx, Rect::new(0.0, 0.0, state.screen_size.0, state.screen_size.1))?;
+
+        let canvas = match self.canvas.as_ref() {
+            Some(canvas) => canvas,
+            None => { return Ok(()); }
+        };
+
+        let param = DrawParam::new()
+            .dest(Point2::new(state.canvas_offset.0, state.canvas_offset.1))
+            .scale(Vector2::new(state.scale, state.scale));
+
+        if state.settings.crt_filter && !self.crt_unavailable {
+            if self.crt_shader.is_none() {
+                let consts = CrtConsts { source_size: [1.0, 1.0], output_size: [1.0, 1.0] };
+
+                match Shader::from_u8(ctx, include_bytes!("../ggez/graphics/shader/basic_150.glslv"),
+                                      include_bytes!("crt_150.glslf"), consts, "CrtConsts", None) {
+                    Ok(shader) => self.crt_shader = Some(shader),
+                    Err(e) => {
+                        log::warn!("CRT filter is not available: {}", e);
+                        self.crt_unavailable = true;
+                    }
+                }
+            }
+
+            if let Some(shader) = self.crt_shader.as_ref() {
+                let source = canvas.image().dimensions();
+                shader.send(ctx, CrtConsts {
+                    source_size: [source.w, source.h],
+                    output_size: [source.w * state.scale, source.h * state.scale],
+                })?;
+
+                let _lock = graphics::use_shader(ctx, shader);
+                return graphics::draw(ctx, canvas, param);
+            }
+        }
+
+        graphics::draw(ctx, canvas, param)
+    }
+}
+
+#[test]
+fn test_layout() {
+    // 21:9 at 2x
+    assert_eq!(GameCanvas::layout((2560.0, 1080.0), 2.0, Presentation::ExpandView), ((1280.0, 540.0), (0.0, 0.0)));
+    assert_eq!(GameCanvas::layout((2560.0, 1080.0), 2.0, Presentation::Pillarbox), ((720.0, 540.0), (560.0, 0.0)));
+
+    // narrower than 4:3, nothing to cut
+    assert_eq!(GameCanvas::layout((640.0, 640.0), 2.0, Presentation::Pillarbox), ((320.0, 320.0), (0.0, 0.0)));
+}
@@ -0,0 +1,161 @@
+use num_traits::{AsPrimitive, Num};
+
+use crate::common::Rect;
+
+/// Solid color quads in game pixels, drawn by `TextureSet::draw_primitives`.
+/// All of them use the white texel of a 1x1 texture, so a whole list is a single sprite batch draw
+/// instead of a mesh per rect.
+pub struct Primitives {
+    quads: Vec<(Rect<f32>, [f32; 4])>,
+}
+
+/// Snaps the edges to a grid of `scale` pixels per unit. Neighbouring quads share the snapped edge,
+/// so there are neither seams nor overlaps between them.
+pub fn snap_rect(rect: Rect<f32>, scale: f32) -> Rect<f32> {
+    let snap = |value: f32| (value * scale).round() / scale;
+    Rect::new(snap(rect.left), snap(rect.top), snap(rect.right), snap(rect.bottom))
+}
+
+fn to_f32<T: Num + Copy + AsPrimitive<f32>>(rect: Rect<T>) -> Rect<f32> {
+    Rect::new(rect.left.as_(), rect.top.as_(), rect.right.as_(), rect.bottom.as_())
+}
+
+impl Primitives {
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Primitives {
+        Primitives {
+            quads: Vec::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.quads.is_empty()
+    }
+
+    /// Snapped to whole game pixels, the canvas is scaled up afterwards so that's the physical pixel grid as well.
+    pub fn quads(&self) -> &[(Rect<f32>, [f32; 4])] {
+        &self.quads
+    }
+
+    /// Empty rects are skipped.
+    pub fn rect<T: Num + Copy + AsPrimitive<f32>>(&mut self, rect: Rect<T>, color: [f32; 4]) {
+        let rect = snap_rect(to_f32(rect), 1.0);
+        if rect.right <= rect.left || rect.bottom <= rect.top {
+            return;
+        }
+
+        self.quads.push((rect, color));
+    }
+
+    /// Border `thickness` pixels wide on the inside of `rect`. The sides are put between the top and the bottom edge,
+    /// the corners aren't drawn twice so translucent outlines don't have darker corners.
+    pub fn outline<T: Num + Copy + AsPrimitive<f32>>(&mut self, rect: Rect<T>, color: [f32; 4], thickness: f32) {
+        let rect = snap_rect(to_f32(rect), 1.0);
+        let thickness = thickness.round().max(1.0);
+
+        if thickness * 2.0 >= rect.width() || thickness * 2.0 >= rect.height() {
+            self.rect(rect, color);
+            return;
+        }
+
+        let (left, top, right, bottom) = (rect.left, rect.top, rect.right, rect.bottom);
+        self.rect(Rect::new(left, top, right, top + thickness), color);
+        self.rect(Rect::new(left, bottom - thickness, right, bottom), color);
+        self.rect(Rect::new(left, top + thickness, left + thickness, bottom - thickness), color);
+        self.rect(Rect::new(right - thickness, top + thickness, right, bottom - thickness), color);
+    }
+
+    /// 1px wide line between the centers of two pixels, both ends included.
+    /// Diagonal lines are stepped, one quad per pixel.
+    pub fn line(&mut self, a: (f32, f32), b: (f32, f32), color: [f32; 4]) {
+        let (ax, ay) = (a.0.round() as isize, a.1.round() as isize);
+        let (bx, by) = (b.0.round() as isize, b.1.round() as isize);
+
+        if ax == bx || ay == by {
+            self.rect(Rect::new(ax.min(bx), ay.min(by), ax.max(bx) + 1, ay.max(by) + 1), color);
+            return;
+        }
+
+        let steps = (bx - ax).abs().max((by - ay).abs());
+        for step in 0..=steps {
+            let x = ax + ((bx - ax) as f32 * step as f32 / steps as f32).round() as isize;
+            let y = ay + ((by - ay) as f32 * step as f32 / steps as f32).round() as isize;
+            self.rect(Rect::new_size(x, y, 1, 1), color);
+        }
+    }
+}
+
+/// Coverage of the quads scaled up `scale` times like the canvas is, one char per physical pixel,
+/// '.' for nothing and the number of quads covering it otherwise.
+#[cfg(test)]
+fn rasterize(primitives: &Primitives, scale: f32, width: usize, height: usize) -> Vec<String> {
+    let mut coverage = vec![0u8; width * height];
+
+    for (rect, _) in primitives.quads() {
+        let rect = Rect::new(rect.left * scale, rect.top * scale, rect.right * scale, rect.bottom * scale);
+        for y in (rect.top as usize)..(rect.bottom as usize).min(height) {
+            for x in (rect.left as usize)..(rect.right as usize).min(width) {
+                coverage[y * width + x] += 1;
+            }
+        }
+    }
+
+    coverage.chunks(width)
+        .map(|row| row.iter().map(|&count| if count == 0 { '.' } else { (b'0' + count) as char }).collect())
+        .collect()
+}
+
+#[test]
+fn test_outline_pixels() {
+    let mut primitives = Primitives::new();
+    // off the grid, snapped to (1, 1) - (5, 4)
+    primitives.outline(Rect::new_size(0.6f32, 1.4, 4.0, 3.0), [1.0, 1.0, 1.0, 0.5], 1.0);
+
+    // exactly 2 physical pixels wide at 2x, no gaps and nothing drawn twice in the corners
+    assert_eq!(rasterize(&primitives, 2.0, 12, 10), vec![
+        "............",
+        "............",
+        "..11111111..",
+        "..11111111..",
+        "..11....11..",
+        "..11....11..",
+        "..11111111..",
+        "..11111111..",
+        "............",
+        "............",
+    ]);
+
+    // too small for a hole
+    let mut primitives = Primitives::new();
+    primitives.outline(Rect::new_size(0, 0, 2, 3), [1.0; 4], 1.0);
+    assert_eq!(primitives.quads().len(), 1);
+}
+
+#[test]
+fn test_adjacent_rects() {
+    // a bar split at a fractional position, like the boss life bar at an odd canvas width
+    let mut primitives = Primitives::new();
+    primitives.rect(Rect::new(0.0f32, 0.0, 2.5, 1.0), [1.0; 4]);
+    primitives.rect(Rect::new(2.5f32, 0.0, 4.0, 1.0), [1.0; 4]);
+    primitives.rect(Rect::new(2.0f32, 2.0, 2.4, 3.0), [1.0; 4]);
+
+    assert_eq!(rasterize(&primitives, 2.0, 8, 2), vec![
+        "11111111",
+        "11111111",
+    ]);
+    assert_eq!(primitives.quads().len(), 2);
+}
+
+#[test]
+fn test_lines() {
+    let mut primitives = Primitives::new();
+    primitives.line((3.0, 0.0), (0.0, 0.0), [1.0; 4]);
+    primitives.line((0.0, 1.0), (2.0, 3.0), [1.0; 4]);
+
+    assert_eq!(rasterize(&primitives, 1.0, 4, 4), vec![
+        "1111",
+        "1...",
+        ".1..",
+        "..1.",
+    ]);
+}
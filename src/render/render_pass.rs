@@ -0,0 +1,119 @@
+use crate::ggez::GameResult;
+use crate::render::DrawSpace;
+
+/// Layers of a game scene, drawn from the first to the last one.
+/// Anything new has to be put in its place here instead of somewhere in the middle of `draw`.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Copy, Clone)]
+#[allow(clippy::upper_case_acronyms)]
+pub enum DrawLayer {
+    /// Stage background, parallax included.
+    Background,
+    BackgroundTiles,
+    /// Carets which go behind the entities, like the booster exhaust.
+    CaretsBehind,
+    NPCs,
+    Bullets,
+    Player,
+    /// Foreground and the breakable (snack) tiles.
+    ForegroundTiles,
+    CaretsFront,
+    /// Ambient stage effects, like snow or wind debris.
+    Weather,
+    Lighting,
+    /// `<FLA` and explosion flashes.
+    Flash,
+    /// Bars covering the area outside of small maps.
+    BlackBars,
+    HUD,
+    Fade,
+    /// Map name shown after entering a stage, drawn over the fade like vanilla does.
+    MapName,
+    TextBox,
+    /// Debug and demo text on top of everything.
+    Overlay,
+}
+
+impl DrawLayer {
+    /// Everything up to the flash moves with the camera, the rest is fixed to the canvas.
+    pub fn space(self) -> DrawSpace {
+        match self {
+            DrawLayer::Background | DrawLayer::BackgroundTiles | DrawLayer::CaretsBehind | DrawLayer::NPCs
+            | DrawLayer::Bullets | DrawLayer::Player | DrawLayer::ForegroundTiles | DrawLayer::CaretsFront
+            | DrawLayer::Weather | DrawLayer::Lighting | DrawLayer::Flash => DrawSpace::World,
+            DrawLayer::BlackBars | DrawLayer::HUD | DrawLayer::Fade | DrawLayer::MapName
+            | DrawLayer::TextBox | DrawLayer::Overlay => DrawSpace::Canvas,
+        }
+    }
+}
+
+type DrawCallback<'a, S, C> = Box<dyn Fn(&mut S, &mut C) -> GameResult + 'a>;
+
+/// Collects the draw callbacks of a frame and runs them sorted by layer,
+/// callbacks of the same layer run in the order they were added.
+pub struct RenderPass<'a, S, C> {
+    callbacks: Vec<(DrawLayer, DrawCallback<'a, S, C>)>,
+}
+
+impl<'a, S, C> RenderPass<'a, S, C> {
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        Self {
+            callbacks: Vec::with_capacity(24),
+        }
+    }
+
+    pub fn add<F: Fn(&mut S, &mut C) -> GameResult + 'a>(&mut self, layer: DrawLayer, callback: F) {
+        self.callbacks.push((layer, Box::new(callback)));
+    }
+
+    /// Runs the callbacks, stops at the first error. `enter_space` is called before the first callback
+    /// of every run of layers in the same space, the layers are ordered so each space is entered once.
+    pub fn draw<F: FnMut(DrawSpace, &mut S, &mut C) -> GameResult>(mut self, state: &mut S, ctx: &mut C, mut enter_space: F) -> GameResult {
+        // stable, keeps the order within a layer
+        self.callbacks.sort_by_key(|(layer, _)| *layer);
+
+        let mut space = None;
+        for (layer, callback) in self.callbacks.iter() {
+            if space != Some(layer.space()) {
+                space = Some(layer.space());
+                enter_space(layer.space(), state, ctx)?;
+            }
+
+            callback(state, ctx)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn test_layer_order() {
+    let mut pass: RenderPass<Vec<(DrawLayer, usize)>, Vec<DrawSpace>> = RenderPass::new();
+    let layers = [DrawLayer::HUD, DrawLayer::Player, DrawLayer::Background, DrawLayer::Player, DrawLayer::Overlay, DrawLayer::BackgroundTiles];
+
+    for (i, &layer) in layers.iter().enumerate() {
+        pass.add(layer, move |calls: &mut Vec<(DrawLayer, usize)>, _| {
+            calls.push((layer, i));
+            Ok(())
+        });
+    }
+
+    let mut calls = Vec::new();
+    let mut spaces = Vec::new();
+    pass.draw(&mut calls, &mut spaces, |space, _, spaces| {
+        spaces.push(space);
+        Ok(())
+    }).unwrap();
+
+    assert_eq!(calls, vec![
+        (DrawLayer::Background, 2),
+        (DrawLayer::BackgroundTiles, 5),
+        (DrawLayer::Player, 1),
+        (DrawLayer::Player, 3),
+        (DrawLayer::HUD, 0),
+        (DrawLayer::Overlay, 4),
+    ]);
+    // the HUD doesn't shake with the world
+    assert_eq!(spaces, vec![DrawSpace::World, DrawSpace::Canvas]);
+    assert_eq!(DrawLayer::TextBox.space(), DrawSpace::Canvas);
+}
@@ -0,0 +1,389 @@
+use std::fs;
+use std::io;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::common::{FadeState, KeyState};
+use crate::container::{Format, Metadata, read_header, write_header};
+use crate::ggez::{Context, filesystem, GameResult};
+use crate::ggez::GameError::ResourceLoadError;
+use crate::player::{PlayableCharacter, Player};
+use crate::rng::RNG;
+use crate::scene::game_scene::GameScene;
+use crate::settings::CompatMode;
+use crate::SharedGameState;
+use crate::str;
+use crate::text_script::TextScriptExecutionState;
+
+/// Bump the version every time the replay layout changes, and add a migration for the previous one to `read_from`.
+pub const REPLAY_FORMAT: Format = Format { magic: b"DRRP", name: "replay", version: 4, metadata_since: 3 };
+/// Player position checksum is stored every this many ticks.
+const CHECKSUM_INTERVAL: usize = 50;
+
+/// Key state of every tick of a play session which started from a fresh game.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Replay {
+    pub stage_id: usize,
+    /// Starting position, in fixed point units.
+    pub x: isize,
+    pub y: isize,
+    /// 0 if no event runs at the start.
+    pub start_event: u16,
+    pub rng_seed: i32,
+    /// Compatibility mode the replay was recorded in, runs are only comparable within the same one.
+    pub compat_mode: CompatMode,
+    pub character: PlayableCharacter,
+    /// Run-length encoded as (key state, tick count).
+    inputs: Vec<(u16, u16)>,
+    /// Checksums of the player position every `CHECKSUM_INTERVAL` ticks, used to detect desyncs.
+    checksums: Vec<u32>,
+    /// From the header of the file the replay has been loaded from.
+    #[serde(skip)]
+    pub metadata: Option<Metadata>,
+}
+
+/// Layout of versions 2 and 3, which were always played as Quote.
+#[derive(Serialize, Deserialize)]
+struct ReplayV3 {
+    stage_id: usize,
+    x: isize,
+    y: isize,
+    start_event: u16,
+    rng_seed: i32,
+    compat_mode: CompatMode,
+    inputs: Vec<(u16, u16)>,
+    checksums: Vec<u32>,
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum ReplayStatus {
+    Running,
+    Finished,
+    Desynced,
+    /// Player pressed a key during the playback.
+    Interrupted,
+}
+
+pub enum ReplayMode {
+    None,
+    Recording {
+        replay: Replay,
+        tick: usize,
+    },
+    Playing {
+        replay: Replay,
+        tick: usize,
+        run: usize,
+        run_tick: u16,
+    },
+}
+
+fn position_checksum(player: &Player) -> u32 {
+    (player.x as u32).rotate_left(16) ^ (player.y as u32) ^ ((player.life as u32) << 7)
+}
+
+impl Replay {
+    pub fn new(stage_id: usize, x: isize, y: isize, start_event: u16, rng_seed: i32, compat_mode: CompatMode,
+               character: PlayableCharacter) -> Replay {
+        Replay {
+            stage_id,
+            x,
+            y,
+            start_event,
+            rng_seed,
+            compat_mode,
+            character,
+            inputs: Vec::new(),
+            checksums: Vec::new(),
+            metadata: None,
+        }
+    }
+
+    pub fn push_input(&mut self, key_state: u16) {
+        match self.inputs.last_mut() {
+            Some((keys, count)) if *keys == key_state && *count < u16::MAX => { *count += 1; }
+            _ => { self.inputs.push((key_state, 1)); }
+        }
+    }
+
+    /// Length of the replay in ticks.
+    pub fn len(&self) -> usize {
+        self.inputs.iter().map(|(_, count)| *count as usize).sum()
+    }
+
+    /// Creates a game scene in the state the replay has been recorded from.
+    /// Replays are always played with a temporary profile.
+    pub fn create_scene(&self, state: &mut SharedGameState, ctx: &mut Context) -> GameResult<GameScene> {
+        if self.stage_id >= state.stages.len() {
+            return Err(ResourceLoadError(format!("Replay refers to nonexistent stage {}.", self.stage_id)));
+        }
+
+        if self.compat_mode != state.settings.compat_mode {
+            state.notifications.push(format!("Recorded in the {} mode, it can desync in the {} mode.",
+                                             self.compat_mode.name(), state.settings.compat_mode.name()));
+        }
+
+        if let Some(metadata) = &self.metadata {
+            if !metadata.matches_data(state) {
+                state.notifications.push(str!("Recorded on different game data, it's going to desync."));
+            }
+
+            log::info!("Playing a replay, {}", metadata);
+            state.loaded_metadata = Some((REPLAY_FORMAT.name, metadata.clone()));
+        }
+
+        state.reset_game_state();
+        state.character = self.character;
+        state.temporary_profile = true;
+        state.game_rng = RNG::new(self.rng_seed);
+
+        let mut scene = GameScene::new(state, ctx, self.stage_id)?;
+        scene.player.x = self.x;
+        scene.player.y = self.y;
+        state.fade_state = FadeState::Hidden;
+        state.textscript_vm.state = match self.start_event {
+            0 => TextScriptExecutionState::Ended,
+            event => TextScriptExecutionState::Running(event, 0),
+        };
+
+        Ok(scene)
+    }
+
+    pub fn write_to<W: Write>(&self, metadata: &Metadata, mut writer: W) -> GameResult {
+        write_header(&REPLAY_FORMAT, metadata, &mut writer)?;
+        Ok(bincode::serialize_into(writer, self)?)
+    }
+
+    pub fn read_from<R: Read>(mut reader: R) -> GameResult<Replay> {
+        let (version, metadata) = read_header(&REPLAY_FORMAT, &mut reader)?;
+
+        let mut replay = match version {
+            2 => Replay::migrate_v2(reader)?,
+            3 => Replay::migrate_v3(reader)?,
+            4 => Replay::deserialize(reader)?,
+            _ => { return Err(ResourceLoadError(format!("Replay format {} is too old to be played back.", version))); }
+        };
+
+        if replay.metadata.is_none() {
+            replay.metadata = metadata;
+        }
+
+        Ok(replay)
+    }
+
+    fn deserialize<R: Read>(reader: R) -> GameResult<Replay> {
+        bincode::deserialize_from(reader)
+            .map_err(|e| ResourceLoadError(format!("Corrupted replay: {}", e)))
+    }
+
+    /// Version 3 didn't record the character.
+    fn migrate_v3<R: Read>(reader: R) -> GameResult<Replay> {
+        let old: ReplayV3 = bincode::deserialize_from(reader)
+            .map_err(|e| ResourceLoadError(format!("Corrupted replay: {}", e)))?;
+
+        Ok(Replay {
+            stage_id: old.stage_id,
+            x: old.x,
+            y: old.y,
+            start_event: old.start_event,
+            rng_seed: old.rng_seed,
+            compat_mode: old.compat_mode,
+            character: PlayableCharacter::Quote,
+            inputs: old.inputs,
+            checksums: old.checksums,
+            metadata: None,
+        })
+    }
+
+    /// Version 2 had the layout of version 3 without the header metadata.
+    fn migrate_v2<R: Read>(reader: R) -> GameResult<Replay> {
+        let mut replay = Replay::migrate_v3(reader)?;
+        replay.metadata = Some(Metadata {
+            engine_version: str!("unknown"),
+            compat_mode: replay.compat_mode,
+            data_fingerprint: None,
+        });
+
+        Ok(replay)
+    }
+
+    /// Loads a replay from the game data.
+    pub fn load(ctx: &mut Context, path: &str) -> GameResult<Replay> {
+        Replay::read_from(filesystem::open(ctx, path)?)
+    }
+
+    /// Saves the replay in the save directory, returns the path it has been saved to.
+    pub fn save(&self, state: &SharedGameState) -> GameResult<PathBuf> {
+        let metadata = Metadata { compat_mode: self.compat_mode, ..Metadata::current(state) };
+        let mod_id = state.current_mod.as_ref().map(|m| m.id.as_str());
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let path = filesystem::user_dirs()?.save_dir(mod_id).join("replays").join(format!("replay-{}.rep", timestamp));
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        self.write_to(&metadata, io::BufWriter::new(fs::File::create(&path)?))?;
+
+        Ok(path)
+    }
+}
+
+impl ReplayMode {
+    pub fn is_playing(&self) -> bool {
+        matches!(self, ReplayMode::Playing { .. })
+    }
+
+    pub fn is_recording(&self) -> bool {
+        matches!(self, ReplayMode::Recording { .. })
+    }
+
+    pub fn is_active(&self) -> bool {
+        !matches!(self, ReplayMode::None)
+    }
+
+    /// Stops the recording and returns the recorded replay.
+    pub fn finish_recording(&mut self) -> Option<Replay> {
+        match std::mem::replace(self, ReplayMode::None) {
+            ReplayMode::Recording { replay, .. } => Some(replay),
+            other => {
+                *self = other;
+                None
+            }
+        }
+    }
+
+    /// Has to be called at the start of every game scene tick, before the key state is used.
+    /// Records the key state, or replaces it with the recorded one while playing back.
+    pub fn tick(&mut self, key_state: &mut KeyState, player: &Player) -> ReplayStatus {
+        self.step(key_state, position_checksum(player))
+    }
+
+    fn step(&mut self, key_state: &mut KeyState, checksum: u32) -> ReplayStatus {
+        match self {
+            ReplayMode::None => ReplayStatus::Running,
+            ReplayMode::Recording { replay, tick } => {
+                // the checksum is of the state after the previous tick
+                if *tick > 0 && *tick % CHECKSUM_INTERVAL == 0 {
+                    replay.checksums.push(checksum);
+                }
+
+                replay.push_input(key_state.0);
+                *tick += 1;
+
+                ReplayStatus::Running
+            }
+            ReplayMode::Playing { replay, tick, run, run_tick } => {
+                if key_state.0 != 0 {
+                    return ReplayStatus::Interrupted;
+                }
+
+                if *tick > 0 && *tick % CHECKSUM_INTERVAL == 0 {
+                    if let Some(&recorded) = replay.checksums.get(*tick / CHECKSUM_INTERVAL - 1) {
+                        if recorded != checksum {
+                            log::warn!("Replay desynced at tick {}.", tick);
+                            return ReplayStatus::Desynced;
+                        }
+                    }
+                }
+
+                let (keys, count) = match replay.inputs.get(*run) {
+                    Some(&input) => input,
+                    None => { return ReplayStatus::Finished; }
+                };
+
+                key_state.0 = keys;
+                *tick += 1;
+                *run_tick += 1;
+                if *run_tick >= count {
+                    *run += 1;
+                    *run_tick = 0;
+                }
+
+                ReplayStatus::Running
+            }
+        }
+    }
+
+    pub fn play(replay: Replay) -> ReplayMode {
+        ReplayMode::Playing { replay, tick: 0, run: 0, run_tick: 0 }
+    }
+
+    pub fn record(replay: Replay) -> ReplayMode {
+        ReplayMode::Recording { replay, tick: 0 }
+    }
+}
+
+#[cfg(test)]
+use crate::container::engine_version;
+
+#[test]
+fn test_replay_round_trip() {
+    let inputs: Vec<u16> = (0..120).map(|i| if i < 60 { 0x01 } else { 0x21 }).collect();
+    let mut recorder = ReplayMode::record(Replay::new(12, 0x2000, 0x4000, 200, 1234, CompatMode::Vanilla, PlayableCharacter::Curly));
+    for (i, &keys) in inputs.iter().enumerate() {
+        recorder.step(&mut KeyState(keys), i as u32);
+    }
+
+    let replay = match recorder {
+        ReplayMode::Recording { replay, .. } => replay,
+        _ => unreachable!(),
+    };
+    assert_eq!(replay.inputs, vec![(0x01, 60), (0x21, 60)]);
+    assert_eq!(replay.len(), 120);
+
+    let metadata = Metadata { engine_version: engine_version(), compat_mode: CompatMode::Vanilla, data_fingerprint: Some(42) };
+    let mut data = Vec::new();
+    replay.write_to(&metadata, &mut data).unwrap();
+    let replay = Replay::read_from(&data[..]).unwrap();
+    assert_eq!(replay.metadata, Some(metadata.clone()));
+    assert_eq!(replay.len(), 120);
+    assert_eq!(replay.character, PlayableCharacter::Curly);
+
+    let v3 = ReplayV3 {
+        stage_id: replay.stage_id,
+        x: replay.x,
+        y: replay.y,
+        start_event: replay.start_event,
+        rng_seed: replay.rng_seed,
+        compat_mode: replay.compat_mode,
+        inputs: replay.inputs.clone(),
+        checksums: replay.checksums.clone(),
+    };
+
+    // version 3, without the character
+    let mut old = Vec::new();
+    write_header(&Format { version: 3, ..REPLAY_FORMAT }, &metadata, &mut old).unwrap();
+    bincode::serialize_into(&mut old, &v3).unwrap();
+    let migrated = Replay::read_from(&old[..]).unwrap();
+    assert_eq!(migrated.character, PlayableCharacter::Quote);
+    assert_eq!(migrated.metadata, Some(metadata));
+    assert_eq!(migrated.inputs, replay.inputs);
+
+    // version 2, without the metadata
+    let mut old = [&b"DRRP"[..], &[2, 0, 0, 0]].concat();
+    bincode::serialize_into(&mut old, &v3).unwrap();
+    let migrated = Replay::read_from(&old[..]).unwrap();
+    assert_eq!(migrated.metadata.map(|m| m.data_fingerprint), Some(None));
+    assert_eq!(migrated.inputs, replay.inputs);
+
+    let mut newer = data.clone();
+    newer[4] = 5;
+    assert!(Replay::read_from(&newer[..]).is_err());
+    assert!(Replay::read_from(&data[..data.len() - 1]).is_err());
+
+    let mut playback = ReplayMode::play(replay.clone());
+    for (i, &keys) in inputs.iter().enumerate() {
+        let mut key_state = KeyState(0);
+        assert_eq!(playback.step(&mut key_state, i as u32), ReplayStatus::Running);
+        assert_eq!(key_state.0, keys);
+    }
+    assert_eq!(playback.step(&mut KeyState(0), 120), ReplayStatus::Finished);
+
+    let mut playback = ReplayMode::play(replay);
+    for i in 0..50 {
+        playback.step(&mut KeyState(0), i);
+    }
+    assert_eq!(playback.step(&mut KeyState(0), 0), ReplayStatus::Desynced);
+    assert_eq!(playback.step(&mut KeyState(0x01), 51), ReplayStatus::Interrupted);
+}
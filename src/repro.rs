@@ -0,0 +1,567 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use num_traits::FromPrimitive;
+
+use crate::common::{Direction, KeyState};
+use crate::debug_arena::{give_loadout, Loadout};
+use crate::ggez::{Context, GameError, GameResult};
+use crate::ggez::filesystem::user_dirs;
+use crate::ggez::GameError::InvalidValue;
+use crate::map::NPCData;
+use crate::player::PlayableCharacter;
+use crate::replay::{Replay, ReplayMode};
+use crate::rng::RNG;
+use crate::scene::game_scene::GameScene;
+use crate::SharedGameState;
+use crate::weapon::{Weapon, WeaponLevel, WeaponType};
+
+/// Bug reproduction scenario passed with `--repro`, meant to be written by hand and pasted into issues.
+/// Scenarios run in a window like the game itself, the tests play them back on stand-in stages with `play_headless`.
+///
+/// ```json
+/// {
+///   "stage": 12, "x": 520, "y": 200,
+///   "inputs": [{ "tick": 0, "press": ["right"] }, { "tick": 30, "press": ["jump"] }]
+/// }
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Scenario {
+    #[serde(default)]
+    pub description: String,
+    pub stage: usize,
+    /// Starting position, in pixels.
+    pub x: isize,
+    pub y: isize,
+    /// Game flags set before the first tick.
+    #[serde(default)]
+    pub flags: Vec<usize>,
+    /// Equipment bits, same as the <EQ+ argument.
+    #[serde(default)]
+    pub equip: u16,
+    #[serde(default)]
+    pub weapons: Vec<ScenarioWeapon>,
+    #[serde(default)]
+    pub seed: i32,
+    /// Event started along with the scenario, 0 for none.
+    #[serde(default)]
+    pub event: u16,
+    #[serde(default)]
+    pub inputs: Vec<ScenarioInput>,
+    /// Length of the scenario in ticks, by default it ends right after the last input.
+    #[serde(default)]
+    pub ticks: Option<usize>,
+    /// Saved by the debugger, the rest of the player state.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub player: Option<ScenarioPlayer>,
+    /// Saved by the debugger, NPCs replaced or spawned once the stage has been loaded.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub npcs: Vec<ScenarioNPC>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ScenarioWeapon {
+    /// Weapon ID, as in <AM+.
+    pub weapon: u8,
+    #[serde(default = "default_level")]
+    pub level: u8,
+    #[serde(default)]
+    pub ammo: u16,
+    #[serde(default)]
+    pub max_ammo: u16,
+}
+
+fn default_level() -> u8 { 1 }
+
+/// Velocities are in fixed point (0x200 per pixel).
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ScenarioPlayer {
+    pub vel_x: isize,
+    pub vel_y: isize,
+    pub direction: Direction,
+    pub life: u16,
+    pub max_life: u16,
+    pub booster_fuel: usize,
+}
+
+/// Part of the NPC state that matters for reproducing its behavior, positions and velocities are in fixed point.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ScenarioNPC {
+    pub id: u16,
+    pub npc_type: u16,
+    pub x: isize,
+    pub y: isize,
+    #[serde(default)]
+    pub vel_x: isize,
+    #[serde(default)]
+    pub vel_y: isize,
+    pub direction: Direction,
+    #[serde(default)]
+    pub action_num: u16,
+    #[serde(default)]
+    pub action_counter: u16,
+    #[serde(default)]
+    pub anim_num: u16,
+    pub life: u16,
+    #[serde(default)]
+    pub flag_num: u16,
+    #[serde(default)]
+    pub event_num: u16,
+}
+
+/// Keys pressed or released on a tick, the rest stay as they were.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ScenarioInput {
+    pub tick: usize,
+    #[serde(default)]
+    pub press: Vec<String>,
+    #[serde(default)]
+    pub release: Vec<String>,
+}
+
+/// State of a scenario being played.
+pub struct ReproRun {
+    /// Where the final state is written, the game quits afterwards.
+    pub dump: Option<PathBuf>,
+}
+
+/// Final state of a scenario, compared between builds to check if a bug has been fixed.
+#[derive(Serialize, Debug)]
+pub struct ReproDump {
+    pub stage: usize,
+    pub tick: usize,
+    pub x: isize,
+    pub y: isize,
+    pub vel_x: isize,
+    pub vel_y: isize,
+    pub direction: Direction,
+    pub life: u16,
+    pub booster_fuel: usize,
+    pub flags: Vec<usize>,
+}
+
+fn set_key(key_state: &mut KeyState, name: &str, value: bool) -> bool {
+    match name {
+        "left" => key_state.set_left(value),
+        "right" => key_state.set_right(value),
+        "up" => key_state.set_up(value),
+        "down" => key_state.set_down(value),
+        "map" => key_state.set_map(value),
+        "jump" => key_state.set_jump(value),
+        "fire" => key_state.set_fire(value),
+        "weapon_next" => key_state.set_weapon_next(value),
+        "weapon_prev" => key_state.set_weapon_prev(value),
+        "inventory" => key_state.set_inventory(value),
+        _ => { return false; }
+    }
+
+    true
+}
+
+impl Scenario {
+    pub fn load(path: &Path) -> GameResult<Scenario> {
+        let data = fs::read_to_string(path)?;
+
+        serde_json::from_str(&data)
+            .map_err(|e| GameError::from(e).in_file(&path.to_string_lossy()))
+    }
+
+    /// Captures the current situation, so it can be attached to an issue and played with `--repro`.
+    /// Only the set flags are saved, every other one is clear by default.
+    pub fn capture(scene: &GameScene, state: &SharedGameState) -> Scenario {
+        let weapons = (0..scene.inventory.get_weapon_count())
+            .filter_map(|i| scene.inventory.get_weapon(i))
+            .map(|weapon| ScenarioWeapon {
+                weapon: weapon.wtype as u8,
+                level: weapon.level as u8,
+                ammo: weapon.ammo,
+                max_ammo: weapon.max_ammo,
+            })
+            .collect();
+
+        let npcs = scene.npc_map.npc_ids.iter()
+            .filter_map(|id| scene.npc_map.npcs.get(id))
+            .map(|npc| npc.borrow())
+            .filter(|npc| npc.cond.alive())
+            .map(|npc| ScenarioNPC {
+                id: npc.id,
+                npc_type: npc.npc_type,
+                x: npc.x,
+                y: npc.y,
+                vel_x: npc.vel_x,
+                vel_y: npc.vel_y,
+                direction: npc.direction,
+                action_num: npc.action_num,
+                action_counter: npc.action_counter,
+                anim_num: npc.anim_num,
+                life: npc.life,
+                flag_num: npc.flag_num,
+                event_num: npc.event_num,
+            })
+            .collect();
+
+        Scenario {
+            description: String::new(),
+            stage: scene.stage_id,
+            x: scene.player.x / 0x200,
+            y: scene.player.y / 0x200,
+            flags: state.game_flags.iter().enumerate().filter(|(_, set)| **set).map(|(i, _)| i).collect(),
+            equip: scene.player.equip.0,
+            weapons,
+            seed: 0,
+            event: 0,
+            inputs: Vec::new(),
+            ticks: None,
+            player: Some(ScenarioPlayer {
+                vel_x: scene.player.vel_x,
+                vel_y: scene.player.vel_y,
+                direction: scene.player.direction,
+                life: scene.player.life,
+                max_life: scene.player.max_life,
+                booster_fuel: scene.player.booster_fuel,
+            }),
+            npcs,
+        }
+    }
+
+    /// Saves the scenario to the scenarios directory, returns the path of the file.
+    pub fn save(&self, state: &SharedGameState) -> GameResult<PathBuf> {
+        let path = Scenario::save_dir(state)?.join(format!("scenario-{}.json", Scenario::timestamp()));
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        fs::write(&path, serde_json::to_string_pretty(self)?)?;
+        Ok(path)
+    }
+
+    /// Path of the last scenario saved with `save`.
+    pub fn latest_saved(state: &SharedGameState) -> GameResult<PathBuf> {
+        let dir = Scenario::save_dir(state)?;
+        let mut files: Vec<PathBuf> = fs::read_dir(&dir)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .collect();
+
+        // timestamps have the same length for the next few centuries
+        files.sort();
+        files.pop().ok_or_else(|| InvalidValue(format!("No scenarios saved in {:?}.", dir)))
+    }
+
+    fn save_dir(state: &SharedGameState) -> GameResult<PathBuf> {
+        let mod_id = state.current_mod.as_ref().map(|m| m.id.as_str());
+        Ok(user_dirs()?.save_dir(mod_id).join("scenarios"))
+    }
+
+    fn timestamp() -> u64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+    }
+
+    /// Weapons and equipment of the scenario, the life is restored along with the entities.
+    fn loadout(&self) -> GameResult<Loadout> {
+        let mut weapons = Vec::with_capacity(self.weapons.len());
+        for weapon in self.weapons.iter() {
+            let wtype = WeaponType::from_u8(weapon.weapon)
+                .ok_or_else(|| InvalidValue(format!("Unknown weapon {}.", weapon.weapon)))?;
+            let level = WeaponLevel::from_u8(weapon.level).unwrap_or(WeaponLevel::Level1);
+            weapons.push(Weapon::new(wtype, level, 0, weapon.ammo, weapon.max_ammo));
+        }
+
+        Ok(Loadout {
+            weapons,
+            equip: self.equip,
+            max_life: None,
+        })
+    }
+
+    /// Restores the scenario into the running stage, inputs are ignored.
+    pub fn restore(&self, scene: &mut GameScene, state: &mut SharedGameState) -> GameResult {
+        if self.stage != scene.stage_id {
+            return Err(InvalidValue(format!("The scenario is for stage {}, but stage {} is loaded.", self.stage, scene.stage_id)));
+        }
+
+        if let Some(&flag) = self.flags.iter().find(|&&flag| flag >= state.game_flags.len()) {
+            return Err(InvalidValue(format!("Flag {} is out of range.", flag)));
+        }
+
+        let loadout = self.loadout()?;
+
+        let len = state.game_flags.len();
+        state.game_flags = bitvec::bitvec![0; len];
+        for &flag in self.flags.iter() {
+            state.game_flags.set(flag, true);
+        }
+
+        give_loadout(scene, &loadout);
+        scene.player.x = self.x * 0x200;
+        scene.player.y = self.y * 0x200;
+        self.restore_entities(scene, state);
+
+        Ok(())
+    }
+
+    /// Applies the player and NPC state, NPCs with the same ID are replaced.
+    pub fn restore_entities(&self, scene: &mut GameScene, state: &SharedGameState) {
+        if let Some(player) = &self.player {
+            scene.player.vel_x = player.vel_x;
+            scene.player.vel_y = player.vel_y;
+            scene.player.direction = player.direction;
+            scene.player.life = player.life;
+            scene.player.max_life = player.max_life;
+            scene.player.booster_fuel = player.booster_fuel;
+        }
+
+        for saved in self.npcs.iter() {
+            // keeps the flags from the stage data of the NPC being replaced
+            let flags = scene.npc_map.npcs.get(&saved.id).map_or(0, |npc| npc.borrow().npc_flags.0);
+            let data = NPCData {
+                id: saved.id,
+                x: 0,
+                y: 0,
+                flag_num: saved.flag_num,
+                event_num: saved.event_num,
+                npc_type: saved.npc_type,
+                flags,
+                layer: 0,
+            };
+
+            let npc = scene.npc_map.create_npc_from_data(&state.npc_table, &data);
+            npc.x = saved.x;
+            npc.y = saved.y;
+            npc.target_x = saved.x;
+            npc.target_y = saved.y;
+            npc.vel_x = saved.vel_x;
+            npc.vel_y = saved.vel_y;
+            npc.direction = saved.direction;
+            npc.action_num = saved.action_num;
+            npc.action_counter = saved.action_counter;
+            npc.anim_num = saved.anim_num;
+            npc.life = saved.life;
+            npc.cond.set_alive(true);
+        }
+    }
+
+    /// Key state of every tick of the scenario.
+    pub fn key_states(&self) -> GameResult<Vec<u16>> {
+        let mut inputs = self.inputs.clone();
+        inputs.sort_by_key(|input| input.tick);
+
+        let len = self.ticks.unwrap_or_else(|| inputs.last().map(|input| input.tick + 1).unwrap_or(0));
+        let mut key_state = KeyState(0);
+        let mut states = Vec::with_capacity(len);
+        let mut next = 0;
+
+        for tick in 0..len {
+            while next < inputs.len() && inputs[next].tick == tick {
+                let input = &inputs[next];
+                next += 1;
+
+                for (names, value) in [(&input.press, true), (&input.release, false)].iter() {
+                    for name in names.iter() {
+                        if !set_key(&mut key_state, name, *value) {
+                            return Err(InvalidValue(format!("Unknown key {:?} at tick {}.", name, tick)));
+                        }
+                    }
+                }
+            }
+
+            states.push(key_state.0);
+        }
+
+        Ok(states)
+    }
+
+    /// Plays the scenario back in a scene with its stage already loaded and returns the final state.
+    /// The script doesn't run, so the player starts with the control an entry event would give back.
+    pub fn play_headless(&self, scene: &mut GameScene, state: &mut SharedGameState) -> GameResult<ReproDump> {
+        self.restore(scene, state)?;
+        state.game_rng = RNG::new(self.seed);
+        state.control_flags.free();
+
+        for key_state in self.key_states()? {
+            scene.tick_headless(state, KeyState(key_state))?;
+        }
+
+        Ok(ReproDump::new(scene, state))
+    }
+
+    /// Creates a game scene which plays the scenario back, with a temporary profile like replays.
+    pub fn create_scene(&self, state: &mut SharedGameState, ctx: &mut Context, dump: Option<PathBuf>) -> GameResult<GameScene> {
+        let mut replay = Replay::new(self.stage, self.x * 0x200, self.y * 0x200, self.event, self.seed,
+                                     state.settings.compat_mode, PlayableCharacter::Quote);
+        for key_state in self.key_states()? {
+            replay.push_input(key_state);
+        }
+
+        let mut scene = replay.create_scene(state, ctx)?;
+
+        for &flag in self.flags.iter() {
+            if flag >= state.game_flags.len() {
+                return Err(InvalidValue(format!("Flag {} is out of range.", flag)));
+            }
+            state.game_flags.set(flag, true);
+        }
+
+        give_loadout(&mut scene, &self.loadout()?);
+
+        // the stage isn't loaded yet, the NPCs are restored once it is
+        if self.player.is_some() || !self.npcs.is_empty() {
+            scene.pending_scenario = Some(self.clone());
+        }
+
+        scene.replay = ReplayMode::play(replay);
+        scene.repro = Some(ReproRun { dump });
+
+        Ok(scene)
+    }
+}
+
+impl ReproDump {
+    pub fn new(scene: &GameScene, state: &SharedGameState) -> ReproDump {
+        ReproDump {
+            stage: scene.stage_id,
+            tick: scene.tick,
+            x: scene.player.x,
+            y: scene.player.y,
+            vel_x: scene.player.vel_x,
+            vel_y: scene.player.vel_y,
+            direction: scene.player.direction,
+            life: scene.player.life,
+            booster_fuel: scene.player.booster_fuel,
+            flags: state.game_flags.iter().enumerate().filter(|(_, set)| **set).map(|(i, _)| i).collect(),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> GameResult {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_scenario_fixtures() {
+    let slope: Scenario = serde_json::from_str(include_str!("../tests/repro_slope.json")).unwrap();
+    let states = slope.key_states().unwrap();
+    assert_eq!(states.len(), 150);
+    assert_eq!(states[0], 0);
+    // right held from tick 10, jump only for ticks 60..70
+    assert_eq!(states[10], 0x02);
+    assert_eq!(states[60], 0x22);
+    assert_eq!(states[70], 0x02);
+    assert_eq!(states[149], 0x02);
+
+    let booster: Scenario = serde_json::from_str(include_str!("../tests/repro_booster.json")).unwrap();
+    assert_eq!(booster.equip, 0x20);
+    assert_eq!(booster.weapons[0].level, 1);
+    let states = booster.key_states().unwrap();
+    // ends right after the last input
+    assert_eq!(states.len(), 81);
+    assert_eq!(states[20], 0x20);
+    assert_eq!(states[24], 0);
+    assert_eq!(states[28], 0x24);
+    assert_eq!(states[80], 0x02);
+
+    let mut broken = booster.clone();
+    broken.inputs[0].press.push("dash".to_owned());
+    assert!(broken.key_states().is_err());
+}
+
+#[test]
+fn test_scenario_round_trip() {
+    let mut scenario: Scenario = serde_json::from_str(include_str!("../tests/repro_booster.json")).unwrap();
+    // nothing saved by the debugger, the file stays as small as the hand written one
+    let json = serde_json::to_string(&scenario).unwrap();
+    assert!(!json.contains("player") && !json.contains("npcs"));
+
+    scenario.inputs.clear();
+    scenario.npcs.push(ScenarioNPC {
+        id: 170,
+        npc_type: 64,
+        x: 0x14200,
+        y: 0x8000,
+        vel_x: -0x100,
+        vel_y: 0,
+        direction: Direction::Right,
+        action_num: 3,
+        action_counter: 12,
+        anim_num: 1,
+        life: 4,
+        flag_num: 0,
+        event_num: 0,
+    });
+
+    let loaded: Scenario = serde_json::from_str(&serde_json::to_string_pretty(&scenario).unwrap()).unwrap();
+    assert_eq!(loaded.stage, scenario.stage);
+    assert_eq!(loaded.flags, scenario.flags);
+    assert!(loaded.inputs.is_empty() && loaded.player.is_none());
+    let npc = &loaded.npcs[0];
+    assert_eq!((npc.id, npc.x, npc.vel_x, npc.direction, npc.action_num), (170, 0x14200, -0x100, Direction::Right, 3));
+}
+
+#[test]
+fn test_play_scenario_fixtures() {
+    use crate::map::Map;
+    use crate::stage::Stage;
+
+    // stand-ins for the stages, a flat floor at the row `top` which goes down a tile through a slope at `slope_x`
+    fn stage(top: usize, slope_x: usize) -> Stage {
+        let (width, height) = (30, 16);
+        let mut tiles = vec![0u8; width * height];
+        for x in 0..width {
+            let (upper, lower) = match x {
+                _ if x < slope_x => (0x41, 0x41),
+                _ if x == slope_x => (0x54, 0x41),
+                _ if x == slope_x + 1 => (0x55, 0x41),
+                _ if x == slope_x + 2 => (0, 0x54),
+                _ if x == slope_x + 3 => (0, 0x55),
+                _ => (0, 0),
+            };
+            tiles[top * width + x] = upper;
+            tiles[(top + 1) * width + x] = lower;
+            tiles[(top + 2) * width + x] = 0x41;
+        }
+
+        let mut attrib = [0u8; 0x100];
+        for (i, attr) in attrib.iter_mut().enumerate() {
+            *attr = i as u8;
+        }
+
+        Stage::for_tests(Map { width, height, tiles, attrib, revision: 0 })
+    }
+
+    // feet on the ground, at the top of a tile row
+    let floor = |row: isize| (row * 16 - 8) * 0x200;
+    let play = |scenario: &Scenario, top: usize, slope_x: usize| {
+        let mut state = SharedGameState::for_tests();
+        let mut scene = GameScene::for_tests(&mut state, scenario.stage, stage(top, slope_x));
+        scene.player.cond.set_alive(true);
+        let dump = scenario.play_headless(&mut scene, &mut state).unwrap();
+
+        let feet = scene.player.y + scene.player.hit_bounds.bottom as isize;
+        (dump, scene.player.flags.hit_bottom_wall(), feet)
+    };
+
+    let slope: Scenario = serde_json::from_str(include_str!("../tests/repro_slope.json")).unwrap();
+    let (dump, grounded, feet) = play(&slope, 10, 9);
+    assert_eq!((dump.stage, dump.tick, dump.life), (2, 150, 3));
+    // down the slope, over its foot and back on the lower floor
+    assert!(dump.x > 13 * 16 * 0x200);
+    assert!(grounded && dump.vel_y == 0);
+    assert_eq!(feet, floor(12));
+
+    let booster: Scenario = serde_json::from_str(include_str!("../tests/repro_booster.json")).unwrap();
+    let (dump, grounded, _) = play(&booster, 13, 20);
+    assert_eq!((dump.stage, dump.tick), (12, 81));
+    // boosted from tick 28 to 59, still coming down
+    assert!(!grounded && dump.vel_y > 0);
+    assert_eq!(dump.booster_fuel, 50 - 32);
+
+    // the second press does nothing without the booster, the hop is long over
+    let mut plain = booster.clone();
+    plain.equip = 0;
+    let (plain_dump, grounded, feet) = play(&plain, 13, 20);
+    assert!(grounded && feet == floor(13));
+    assert!(dump.y < plain_dump.y - 4 * 16 * 0x200);
+}
@@ -0,0 +1,125 @@
+use std::collections::VecDeque;
+
+use crate::ggez::{Context, GameResult};
+use crate::save_state::SaveState;
+use crate::scene::game_scene::GameScene;
+use crate::SharedGameState;
+
+/// A snapshot is taken every this many ticks.
+pub const SNAPSHOT_INTERVAL: usize = 10;
+/// While rewinding, one snapshot is restored every this many ticks (so rewinding runs at 5x speed).
+const REWIND_STEP_TICKS: usize = 2;
+/// Rewind gets disabled for the rest of the stage if a single snapshot is larger than this.
+const MAX_SNAPSHOT_SIZE: usize = 256 * 1024;
+
+/// Ring buffer of in-memory snapshots of the current stage.
+/// Snapshot buffers are recycled, so after the ring fills up taking one doesn't allocate.
+pub struct RewindBuffer {
+    snapshots: VecDeque<Vec<u8>>,
+    pool: Vec<Vec<u8>>,
+    capacity: usize,
+    rewind_counter: usize,
+    disabled: bool,
+}
+
+impl RewindBuffer {
+    pub fn new() -> Self {
+        Self {
+            snapshots: VecDeque::new(),
+            pool: Vec::new(),
+            capacity: 0,
+            rewind_counter: 0,
+            disabled: false,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        while let Some(buf) = self.snapshots.pop_front() {
+            self.pool.push(buf);
+        }
+        self.rewind_counter = 0;
+    }
+
+    /// Returns true if the game is being rewound and the scene shouldn't run its usual tick.
+    /// The buffer has to be taken out of the scene for the duration of the call.
+    pub fn tick(&mut self, game_scene: &mut GameScene, state: &mut SharedGameState, ctx: &mut Context) -> GameResult<bool> {
+        if !state.settings.allow_rewind() || game_scene.player.equip.has_nikumaru() || state.challenge.is_some()
+            || game_scene.replay.is_active() || self.disabled {
+            if !self.snapshots.is_empty() {
+                self.clear();
+            }
+            return Ok(false);
+        }
+
+        if state.key_state.rewind() {
+            self.step_back(game_scene, state, ctx)?;
+            return Ok(true);
+        }
+
+        self.rewind_counter = 0;
+        if game_scene.tick.is_multiple_of(SNAPSHOT_INTERVAL) {
+            self.capacity = state.settings.rewind_seconds as usize * state.tps() as usize / SNAPSHOT_INTERVAL;
+            self.capture(game_scene, state)?;
+        }
+
+        Ok(false)
+    }
+
+    fn capture(&mut self, game_scene: &GameScene, state: &SharedGameState) -> GameResult {
+        if self.capacity == 0 {
+            return Ok(());
+        }
+
+        // rewind depth could have been lowered in the meantime
+        while self.snapshots.len() > self.capacity {
+            if let Some(buf) = self.snapshots.pop_front() {
+                self.pool.push(buf);
+            }
+        }
+
+        let mut buf = if self.snapshots.len() == self.capacity {
+            self.snapshots.pop_front().unwrap()
+        } else {
+            self.pool.pop().unwrap_or_default()
+        };
+
+        buf.clear();
+        SaveState::capture_into(game_scene, state, &mut buf)?;
+
+        if buf.len() > MAX_SNAPSHOT_SIZE {
+            log::warn!("Snapshot too large ({} bytes), rewind disabled for this stage.", buf.len());
+            self.disabled = true;
+            self.clear();
+            self.pool.clear();
+            return Ok(());
+        }
+
+        self.snapshots.push_back(buf);
+        Ok(())
+    }
+
+    fn step_back(&mut self, game_scene: &mut GameScene, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
+        self.rewind_counter += 1;
+        if self.rewind_counter % REWIND_STEP_TICKS != 1 {
+            return Ok(());
+        }
+
+        // the oldest snapshot stays in the buffer, so holding the key longer just stops there
+        let buf = if self.snapshots.len() > 1 {
+            self.snapshots.pop_back()
+        } else {
+            None
+        };
+
+        let snapshot = match buf.as_ref().or_else(|| self.snapshots.back()) {
+            Some(buf) => SaveState::deserialize(buf)?,
+            None => { return Ok(()); }
+        };
+
+        if let Some(buf) = buf {
+            self.pool.push(buf);
+        }
+
+        snapshot.apply(game_scene, state, ctx)
+    }
+}
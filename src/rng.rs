@@ -1,6 +1,56 @@
 use std::cell::Cell;
+use std::ops::RangeInclusive;
 
-pub struct RNG(Cell<(u64, u64, u64, u64)>);
+/// Which generator to use:
+///
+/// - `game_rng` (`RNG`) - anything that can affect the game state: NPC AI, bullets, pickups, drops,
+///   death effects that spawn NPCs. It reproduces msvcrt's `rand()` the original game was built with,
+///   so as long as only gameplay code draws from it, sequences match the original frame-for-frame.
+/// - `effect_rng` (`EffectRNG`) - purely visual things which never feed back into the game state:
+///   carets, screen quake offset. It is seeded from the clock and may differ between runs.
+///
+/// Note that the original game draws from the same `rand()` for effects too, so drawing from
+/// `game_rng` in a place that doesn't in the original (or the other way around) breaks replays.
+pub struct RNG(Cell<u32>);
+
+impl RNG {
+    pub fn new(seed: i32) -> Self {
+        Self(Cell::new(seed as u32))
+    }
+
+    /// msvcrt's `rand()`, returns a value in 0..=0x7fff range.
+    pub fn next(&self) -> i32 {
+        let state = self.0.get().wrapping_mul(214013).wrapping_add(2531011);
+        self.0.set(state);
+
+        ((state >> 16) & 0x7fff) as i32
+    }
+
+    /// Equivalent of the original `Random(min, max)` function, including the fact that it's
+    /// a plain modulo of `rand()` (so the distribution isn't exactly uniform for large ranges).
+    pub fn range(&self, range: RangeInclusive<i32>) -> i32 {
+        let span = range.end().wrapping_sub(*range.start()).wrapping_add(1);
+        let value = self.next();
+
+        if span <= 0 {
+            // would crash the original with division by zero
+            return *range.start();
+        }
+
+        range.start().wrapping_add(value % span)
+    }
+
+    pub fn dump_state(&self) -> u32 {
+        self.0.get()
+    }
+
+    pub fn load_state(&self, saved_state: u32) {
+        self.0.set(saved_state);
+    }
+}
+
+/// Non-deterministic xoshiro-ish generator used for visual effects only, see `RNG` docs.
+pub struct EffectRNG(Cell<(u64, u64, u64, u64)>);
 
 #[inline]
 fn rol64(x: u64, shift: u64) -> u64
@@ -12,7 +62,7 @@ fn rol64(x: u64, shift: u64) -> u64
     }
 }
 
-impl RNG {
+impl EffectRNG {
     pub fn new(seed: i32) -> Self {
         Self(Cell::new((seed as u64,
                         (seed as u64).wrapping_add(0x9e3779b97f4a7c15),
@@ -48,7 +98,50 @@ impl RNG {
         self.next_u64() as u32
     }
 
-    pub fn range(&self, range: std::ops::Range<i32>) -> i32 {
-        range.start.wrapping_add((self.next_u32() >> 2) as i32 % (range.end.wrapping_sub(range.start).wrapping_add(1)))
+    pub fn range(&self, range: RangeInclusive<i32>) -> i32 {
+        let span = range.end().wrapping_sub(*range.start()).wrapping_add(1);
+        if span <= 0 {
+            return *range.start();
+        }
+
+        range.start().wrapping_add((self.next_u32() >> 2) as i32 % span)
+    }
+}
+
+#[test]
+fn test_msvcrt_rand() {
+    // first outputs of msvcrt rand() with the default seed of 1
+    let expected = [
+        41, 18467, 6334, 26500, 19169, 15724, 11478, 29358, 26962, 24464, 5705, 28145, 23281, 16827, 9961, 491,
+        2995, 11942, 4827, 5436, 32391, 14604, 3902, 153, 292, 12382, 17421, 18716, 19718, 19895, 5447, 21726,
+    ];
+
+    let rng = RNG::new(1);
+    let mut sum = 0i64;
+    let mut last = 0;
+
+    for i in 0..1000 {
+        let value = rng.next();
+        if let Some(&exp) = expected.get(i) {
+            assert_eq!(value, exp, "mismatch at output {}", i);
+        }
+
+        sum += value as i64;
+        last = value;
+    }
+
+    assert_eq!(sum, 16397155);
+    assert_eq!(last, 12249);
+}
+
+#[test]
+fn test_msvcrt_random_range() {
+    let rng = RNG::new(1);
+    let expected = [-727, -745, -582, -397, -43, -414, -49, -613, 65, 641, 326, -289, -542, 689, -29, -277];
+
+    for &exp in expected.iter() {
+        assert_eq!(rng.range(-0x300..=0x300), exp);
     }
+
+    assert_eq!(rng.range(5..=5), 5);
 }
@@ -0,0 +1,178 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+
+use crate::ggez::{GameError, GameResult};
+
+/// Previous version of a save file, kept in case the current one ends up corrupted.
+pub fn backup_path(path: &Path) -> PathBuf {
+    path.with_extension("bak")
+}
+
+/// The new version is written here first.
+fn temp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().map(|n| n.to_os_string()).unwrap_or_default();
+    name.push(".new");
+    path.with_file_name(name)
+}
+
+/// Writes a save file so that a crash or a power loss at any point leaves either the new or the previous
+/// version readable, the previous one is kept as a backup.
+pub fn write_atomic(path: &Path, data: &[u8]) -> GameResult {
+    let temp = temp_path(path);
+    let mut file = fs::File::create(&temp)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    drop(file);
+
+    replace_file(&temp, path, &backup_path(path))?;
+    sync_dir(path);
+
+    Ok(())
+}
+
+/// Renaming over a file is atomic on POSIX, so the file is always there.
+#[cfg(not(windows))]
+fn replace_file(new: &Path, path: &Path, backup: &Path) -> GameResult {
+    if path.exists() {
+        fs::copy(path, backup)?;
+    }
+
+    fs::rename(new, path)?;
+    Ok(())
+}
+
+/// Replacing isn't atomic on Windows, so the old file is moved to the backup first.
+/// A crash in between leaves only the backup, which the loading falls back to.
+#[cfg(windows)]
+fn replace_file(new: &Path, path: &Path, backup: &Path) -> GameResult {
+    if path.exists() {
+        if backup.exists() {
+            fs::remove_file(backup)?;
+        }
+        fs::rename(path, backup)?;
+    }
+
+    fs::rename(new, path)?;
+    Ok(())
+}
+
+/// Makes the rename itself durable, best effort.
+#[cfg(unix)]
+fn sync_dir(path: &Path) {
+    if let Some(dir) = path.parent() {
+        if let Ok(dir) = fs::File::open(dir) {
+            let _ = dir.sync_all();
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_path: &Path) {}
+
+/// Reads a save file, falling back to the backup if it's missing or can't be parsed.
+/// The flag tells if the backup has been used, `None` if there's neither of them.
+pub fn read_with_backup<T, F>(path: &Path, parse: F) -> GameResult<Option<(T, bool)>>
+    where F: Fn(&[u8]) -> GameResult<T> {
+    let backup = backup_path(path);
+
+    let error = if path.exists() {
+        match fs::read(path).map_err(GameError::from).and_then(|data| parse(&data)) {
+            Ok(value) => { return Ok(Some((value, false))); }
+            Err(e) => Some(e),
+        }
+    } else {
+        None
+    };
+
+    if !backup.exists() {
+        return match error {
+            Some(e) => Err(e),
+            None => Ok(None),
+        };
+    }
+
+    if let Some(e) = error.as_ref() {
+        log::warn!("Cannot read {:?}, using the backup: {}", path, e);
+    }
+
+    match parse(&fs::read(&backup)?) {
+        Ok(value) => Ok(Some((value, true))),
+        // the primary file's error is the interesting one
+        Err(e) => Err(error.unwrap_or(e)),
+    }
+}
+
+/// Save file being written on a background thread, so the game doesn't stall on the disk.
+pub struct PendingWrite {
+    rx: mpsc::Receiver<GameResult>,
+}
+
+impl PendingWrite {
+    pub fn spawn<F>(write: F) -> PendingWrite
+        where F: FnOnce() -> GameResult + Send + 'static {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let _ = tx.send(write());
+        });
+
+        PendingWrite { rx }
+    }
+
+    /// The result once the write is over.
+    pub fn poll(&self) -> Option<GameResult> {
+        match self.rx.try_recv() {
+            Ok(result) => Some(result),
+            Err(mpsc::TryRecvError::Empty) => None,
+            Err(mpsc::TryRecvError::Disconnected) => Some(Err(GameError::FilesystemError(String::from("The save thread has crashed.")))),
+        }
+    }
+
+    /// Blocks until the write is over.
+    pub fn wait(self) -> GameResult {
+        self.rx.recv().unwrap_or_else(|_| Err(GameError::FilesystemError(String::from("The save thread has crashed."))))
+    }
+}
+
+#[test]
+fn test_save_backup() {
+    use std::env;
+
+    let root = env::temp_dir().join(format!("doukutsu-rs-save-test-{}", std::process::id()));
+    fs::create_dir_all(&root).unwrap();
+    let path = root.join("Profile.dat");
+    let parse = |data: &[u8]| if data.len() == 8 { Ok(data.to_vec()) } else { Err(GameError::ResourceLoadError(String::from("truncated"))) };
+
+    assert!(read_with_backup(&path, parse).unwrap().is_none());
+
+    write_atomic(&path, b"Do041220").unwrap();
+    assert!(!backup_path(&path).exists());
+    write_atomic(&path, b"Do041221").unwrap();
+    assert!(!temp_path(&path).exists());
+    assert_eq!(fs::read(backup_path(&path)).unwrap(), b"Do041220");
+    assert_eq!(read_with_backup(&path, parse).unwrap(), Some((b"Do041221".to_vec(), false)));
+
+    // truncated by a crash, the backup is used
+    fs::write(&path, b"Do04").unwrap();
+    assert_eq!(read_with_backup(&path, parse).unwrap(), Some((b"Do041220".to_vec(), true)));
+
+    // moved to the backup but not replaced yet
+    fs::remove_file(&path).unwrap();
+    assert_eq!(read_with_backup(&path, parse).unwrap(), Some((b"Do041220".to_vec(), true)));
+
+    // both broken
+    fs::write(&path, b"Do04").unwrap();
+    fs::write(backup_path(&path), b"Do").unwrap();
+    assert!(read_with_backup(&path, parse).is_err());
+
+    let pending = PendingWrite::spawn(|| Ok(()));
+    let result = loop {
+        if let Some(result) = pending.poll() { break result; }
+        thread::yield_now();
+    };
+    assert!(result.is_ok());
+
+    fs::remove_dir_all(&root).unwrap();
+}
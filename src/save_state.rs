@@ -0,0 +1,308 @@
+use std::fs;
+use std::io;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+
+use bitvec::vec::BitVec;
+use serde::ser::{Serialize, SerializeSeq, Serializer};
+
+use crate::bullet::Bullet;
+use crate::common::{ControlFlags, FadeState};
+use crate::container::{Format, Metadata, read_header, write_header};
+use crate::frame::Frame;
+use crate::ggez::{Context, GameResult};
+use crate::ggez::GameError::{InvalidValue, ResourceLoadError};
+use crate::ggez::filesystem::user_dirs;
+use crate::inventory::Inventory;
+use crate::npc::{NPC, NPCMap};
+use crate::player::{PlayableCharacter, Player};
+use crate::scene::game_scene::GameScene;
+use crate::SharedGameState;
+use crate::str;
+use crate::text_script::{TextScriptExecutionState, TextScriptFlags, TextScriptLine, TSC_VARIABLE_COUNT};
+
+/// Bump the version every time anything serialized in the snapshot changes layout. Save states are short lived,
+/// the older versions are rejected instead of migrated.
+pub const SAVE_STATE_FORMAT: Format = Format { magic: b"DRSS", name: "save state", version: 11, metadata_since: 8 };
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum QuickSaveAction {
+    Save,
+    Load,
+}
+
+#[derive(Deserialize)]
+struct TextScriptSnapshot {
+    state: TextScriptExecutionState,
+    flags: TextScriptFlags,
+    face: u16,
+    item: u16,
+    current_line: TextScriptLine,
+    line_1: Vec<char>,
+    line_2: Vec<char>,
+    line_3: Vec<char>,
+}
+
+/// Full runtime snapshot of a game scene, unlike Profile.dat this includes NPCs, bullets,
+/// the text script position and the RNG, so it can be restored in the middle of anything.
+#[derive(Deserialize)]
+pub struct SaveState {
+    stage_id: usize,
+    player: Player,
+    inventory: Inventory,
+    frame: Frame,
+    tiles: Vec<u8>,
+    npcs: Vec<NPC>,
+    bullets: Vec<Bullet>,
+    game_flags: Vec<u8>,
+    tsc_variables: Vec<u16>,
+    character: PlayableCharacter,
+    control_flags: ControlFlags,
+    fade_state: FadeState,
+    quake_counter: u16,
+    game_rng: u32,
+    song_id: usize,
+    text_script: TextScriptSnapshot,
+    /// From the file header, None for the rewind snapshots which don't have one.
+    #[serde(skip)]
+    metadata: Option<Metadata>,
+}
+
+// Borrowing counterparts of the structs above, serialized directly from the live scene so taking
+// a snapshot doesn't clone anything. Field order and types have to match the owned versions.
+
+#[derive(Serialize)]
+struct TextScriptSnapshotRef<'a> {
+    state: TextScriptExecutionState,
+    flags: TextScriptFlags,
+    face: u16,
+    item: u16,
+    current_line: TextScriptLine,
+    line_1: &'a [char],
+    line_2: &'a [char],
+    line_3: &'a [char],
+}
+
+#[derive(Serialize)]
+struct SaveStateRef<'a> {
+    stage_id: usize,
+    player: &'a Player,
+    inventory: &'a Inventory,
+    frame: &'a Frame,
+    tiles: &'a [u8],
+    npcs: NPCList<'a>,
+    bullets: &'a [Bullet],
+    game_flags: PackedFlags<'a>,
+    tsc_variables: &'a [u16],
+    character: PlayableCharacter,
+    control_flags: ControlFlags,
+    fade_state: FadeState,
+    quake_counter: u16,
+    game_rng: u32,
+    song_id: usize,
+    text_script: TextScriptSnapshotRef<'a>,
+}
+
+struct NPCList<'a>(&'a NPCMap);
+
+impl Serialize for NPCList<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.0.npc_ids.len()))?;
+        for id in self.0.npc_ids.iter() {
+            seq.serialize_element(&*self.0.npcs[id].borrow())?;
+        }
+        seq.end()
+    }
+}
+
+/// Game flags packed 8 per byte, LSB first.
+struct PackedFlags<'a>(&'a BitVec);
+
+impl Serialize for PackedFlags<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let len = self.0.len().div_ceil(8);
+        let mut seq = serializer.serialize_seq(Some(len))?;
+        for i in 0..len {
+            let mut byte = 0u8;
+            for bit in 0..8 {
+                if let Some(true) = self.0.get(i * 8 + bit) {
+                    byte |= 1 << bit;
+                }
+            }
+            seq.serialize_element(&byte)?;
+        }
+        seq.end()
+    }
+}
+
+impl SaveState {
+    /// Serializes the current state of the scene without the file header.
+    pub fn capture_into<W: Write>(game_scene: &GameScene, state: &SharedGameState, writer: W) -> GameResult {
+        let vm = &state.textscript_vm;
+        let snapshot = SaveStateRef {
+            stage_id: game_scene.stage_id,
+            player: &game_scene.player,
+            inventory: &game_scene.inventory,
+            frame: &game_scene.frame,
+            tiles: &game_scene.stage.map.tiles,
+            npcs: NPCList(&game_scene.npc_map),
+            bullets: &game_scene.bullet_manager.bullets,
+            game_flags: PackedFlags(&state.game_flags),
+            tsc_variables: &state.tsc_variables,
+            character: state.character,
+            control_flags: state.control_flags,
+            fade_state: state.fade_state,
+            quake_counter: state.quake_counter,
+            game_rng: state.game_rng.dump_state(),
+            song_id: state.sound_manager.song_id(),
+            text_script: TextScriptSnapshotRef {
+                state: vm.state,
+                flags: vm.flags,
+                face: vm.face,
+                item: vm.item,
+                current_line: vm.current_line,
+                line_1: &vm.line_1,
+                line_2: &vm.line_2,
+                line_3: &vm.line_3,
+            },
+        };
+
+        Ok(bincode::serialize_into(writer, &snapshot)?)
+    }
+
+    /// Counterpart of `capture_into`.
+    pub fn deserialize(data: &[u8]) -> GameResult<SaveState> {
+        bincode::deserialize(data)
+            .map_err(|e| ResourceLoadError(format!("Corrupted save state: {}", e)))
+    }
+
+    /// Creates the scene for this snapshot, the state is restored once the scene gets initialized.
+    pub fn into_scene(self, state: &mut SharedGameState, ctx: &mut Context) -> GameResult<GameScene> {
+        if self.stage_id >= state.stages.len() {
+            return Err(InvalidValue(format!("Save state refers to nonexistent stage {}.", self.stage_id)));
+        }
+
+        if let Some(metadata) = &self.metadata {
+            log::info!("Loading a save state, {}", metadata);
+            state.loaded_metadata = Some((SAVE_STATE_FORMAT.name, metadata.clone()));
+        }
+
+        let mut scene = GameScene::new(state, ctx, self.stage_id)?;
+        if scene.stage.map.tiles.len() != self.tiles.len() {
+            return Err(InvalidValue(str!("Save state doesn't match the stage map, was the map modified?")));
+        }
+
+        scene.pending_save_state = Some(Box::new(self));
+        state.textscript_vm.suspend = true;
+
+        Ok(scene)
+    }
+
+    /// Restores the snapshot into an already loaded scene of the same stage.
+    pub fn apply(self, game_scene: &mut GameScene, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
+        let song_id = self.song_id;
+        self.restore(game_scene, state);
+        state.sound_manager.play_song(song_id, &state.constants, ctx)
+    }
+
+    /// Everything `apply` does besides starting the song.
+    pub fn restore(self, game_scene: &mut GameScene, state: &mut SharedGameState) {
+        game_scene.player = self.player;
+        game_scene.inventory = self.inventory;
+        game_scene.frame = self.frame;
+        game_scene.stage.map.set_tiles(self.tiles);
+        game_scene.bullet_manager.bullets = self.bullets;
+
+        game_scene.npc_map.clear();
+        for npc in self.npcs {
+            game_scene.npc_map.insert(npc);
+        }
+
+        let mut game_flags = BitVec::with_capacity(state.game_flags.len());
+        for i in 0..state.game_flags.len() {
+            game_flags.push(self.game_flags.get(i / 8).is_some_and(|b| b & (1 << (i % 8)) != 0));
+        }
+        state.game_flags = game_flags;
+
+        state.tsc_variables = self.tsc_variables;
+        state.tsc_variables.resize(TSC_VARIABLE_COUNT, 0);
+        state.character = self.character;
+
+        state.control_flags = self.control_flags;
+        state.fade_state = self.fade_state;
+        state.quake_counter = self.quake_counter;
+        state.game_rng.load_state(self.game_rng);
+        state.carets.clear();
+
+        let vm = &mut state.textscript_vm;
+        vm.state = self.text_script.state;
+        vm.flags = self.text_script.flags;
+        vm.face = self.text_script.face;
+        vm.item = self.text_script.item;
+        vm.current_line = self.text_script.current_line;
+        vm.line_1 = self.text_script.line_1;
+        vm.line_2 = self.text_script.line_2;
+        vm.line_3 = self.text_script.line_3;
+    }
+
+    fn path(state: &SharedGameState) -> GameResult<PathBuf> {
+        let mod_id = state.current_mod.as_ref().map(|m| m.id.as_str());
+        Ok(user_dirs()?.save_dir(mod_id).join("quicksave.bin"))
+    }
+
+    pub fn write_to<W: Write>(game_scene: &GameScene, state: &SharedGameState, mut writer: W) -> GameResult {
+        write_header(&SAVE_STATE_FORMAT, &Metadata::current(state), &mut writer)?;
+        SaveState::capture_into(game_scene, state, writer)
+    }
+
+    pub fn read_from<R: Read>(mut reader: R) -> GameResult<SaveState> {
+        let (version, metadata) = read_header(&SAVE_STATE_FORMAT, &mut reader)?;
+        if version != SAVE_STATE_FORMAT.version {
+            return Err(ResourceLoadError(format!("Save state format {} is from an older version of the engine and can't be loaded.", version)));
+        }
+
+        let mut save_state: SaveState = bincode::deserialize_from(reader)
+            .map_err(|e| ResourceLoadError(format!("Corrupted save state: {}", e)))?;
+        save_state.metadata = metadata;
+
+        Ok(save_state)
+    }
+
+    pub fn save_quick(game_scene: &GameScene, state: &SharedGameState) -> GameResult {
+        let path = SaveState::path(state)?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        SaveState::write_to(game_scene, state, io::BufWriter::new(fs::File::create(path)?))
+    }
+
+    pub fn load_quick(state: &SharedGameState) -> GameResult<SaveState> {
+        SaveState::read_from(io::BufReader::new(fs::File::open(SaveState::path(state)?)?))
+    }
+}
+
+#[test]
+fn test_save_state_header() {
+    use crate::container::engine_version;
+    use crate::settings::CompatMode;
+
+    let metadata = Metadata { engine_version: engine_version(), compat_mode: CompatMode::Custom, data_fingerprint: Some(1) };
+    let mut data = Vec::new();
+    write_header(&SAVE_STATE_FORMAT, &metadata, &mut data).unwrap();
+    let mut reader = &data[..];
+    assert_eq!(read_header(&SAVE_STATE_FORMAT, &mut reader).unwrap(), (SAVE_STATE_FORMAT.version, Some(metadata)));
+    assert!(reader.is_empty());
+
+    // a header without the snapshot
+    assert!(SaveState::read_from(&data[..]).is_err());
+
+    let mut newer = data.clone();
+    newer[4] += 1;
+    assert!(SaveState::read_from(&newer[..]).is_err());
+
+    let older = [&b"DRSS"[..], &[7, 0, 0, 0]].concat();
+    assert!(SaveState::read_from(&older[..]).is_err());
+
+    assert!(SaveState::read_from(&b"Do041220"[..]).is_err());
+}
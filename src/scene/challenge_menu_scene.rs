@@ -0,0 +1,107 @@
+use crate::challenge::{Challenge, CHALLENGE_TPS, format_time};
+use crate::ggez::{Context, GameResult, graphics};
+use crate::ggez::graphics::Color;
+use crate::scene::Scene;
+use crate::scene::title_scene::TitleScene;
+use crate::SharedGameState;
+
+const VISIBLE_ROWS: usize = 10;
+const ROW_HEIGHT: f32 = 14.0;
+
+/// Lists the challenges with their best times, the last entry goes back to the title screen.
+pub struct ChallengeMenuScene {
+    challenges: Vec<Challenge>,
+    best_times: Vec<Option<usize>>,
+    selected: usize,
+    scroll: usize,
+}
+
+impl ChallengeMenuScene {
+    pub fn new(state: &mut SharedGameState, ctx: &mut Context) -> GameResult<Self> {
+        let challenges = Challenge::load_list(state, ctx)?;
+        let best_times = challenges.iter().map(|c| c.best_time(state)).collect();
+
+        Ok(Self {
+            challenges,
+            best_times,
+            selected: 0,
+            scroll: 0,
+        })
+    }
+}
+
+impl Scene for ChallengeMenuScene {
+    fn init(&mut self, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
+        state.textscript_vm.suspend = true;
+        state.challenge = None;
+        state.temporary_profile = false;
+        state.sound_manager.play_song(0, &state.constants, ctx)?;
+
+        Ok(())
+    }
+
+    fn tick(&mut self, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
+        state.update_key_trigger();
+        let count = self.challenges.len() + 1;
+
+        if state.key_trigger.up() {
+            self.selected = (self.selected + count - 1) % count;
+            state.sound_manager.play_sfx(1);
+        } else if state.key_trigger.down() {
+            self.selected = (self.selected + 1) % count;
+            state.sound_manager.play_sfx(1);
+        }
+
+        if self.selected < self.scroll {
+            self.scroll = self.selected;
+        } else if self.selected >= self.scroll + VISIBLE_ROWS {
+            self.scroll = self.selected + 1 - VISIBLE_ROWS;
+        }
+
+        if state.key_trigger.jump() {
+            state.sound_manager.play_sfx(18);
+
+            match self.challenges.get(self.selected) {
+                Some(challenge) => challenge.start(state, ctx)?,
+                None => state.next_scene = Some(Box::new(TitleScene::new())),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn draw(&self, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
+        graphics::clear(ctx, Color::from_rgb(0, 0, 32));
+
+        state.font.draw_text("Challenges".chars(), 16.0, 16.0, &state.constants, &mut state.texture_set, ctx)?;
+
+        let count = self.challenges.len() + 1;
+        let mut y = 40.0;
+        for index in self.scroll..count.min(self.scroll + VISIBLE_ROWS) {
+            if index == self.selected {
+                state.font.draw_text(">".chars(), 16.0, y, &state.constants, &mut state.texture_set, ctx)?;
+            }
+
+            match self.challenges.get(index) {
+                Some(challenge) => {
+                    state.font.draw_text(challenge.name.chars(), 28.0, y, &state.constants, &mut state.texture_set, ctx)?;
+
+                    let best = self.best_times[index].map_or_else(|| "--:--.-".to_string(), |ticks| format_time(ticks, CHALLENGE_TPS));
+                    state.font.draw_text(best.chars(), state.canvas_size.0 - 72.0, y, &state.constants, &mut state.texture_set, ctx)?;
+                }
+                None => {
+                    state.font.draw_text("Back to the title".chars(), 28.0, y, &state.constants, &mut state.texture_set, ctx)?;
+                }
+            }
+
+            y += ROW_HEIGHT;
+        }
+
+        if self.challenges.is_empty() {
+            state.font.draw_text("No challenges.json found in the data directory.".chars(), 16.0, state.canvas_size.1 - 28.0,
+                                 &state.constants, &mut state.texture_set, ctx)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,75 @@
+use crate::challenge::{Challenge, CHALLENGE_TPS, format_time};
+use crate::ggez::{Context, GameResult, graphics};
+use crate::ggez::graphics::Color;
+use crate::scene::challenge_menu_scene::ChallengeMenuScene;
+use crate::scene::Scene;
+use crate::SharedGameState;
+
+pub struct ChallengeResultScene {
+    challenge: Challenge,
+    ticks: usize,
+    best: Option<usize>,
+    new_record: bool,
+}
+
+impl ChallengeResultScene {
+    pub fn new(challenge: Challenge, ticks: usize) -> Self {
+        Self {
+            challenge,
+            ticks,
+            best: None,
+            new_record: false,
+        }
+    }
+}
+
+impl Scene for ChallengeResultScene {
+    fn init(&mut self, state: &mut SharedGameState, _ctx: &mut Context) -> GameResult {
+        state.textscript_vm.suspend = true;
+        state.challenge = None;
+        state.temporary_profile = false;
+
+        self.best = self.challenge.best_time(state);
+        match self.challenge.submit_time(self.ticks, state) {
+            Ok(new_record) => self.new_record = new_record,
+            Err(e) => log::warn!("Cannot save the challenge record: {}", e),
+        }
+
+        Ok(())
+    }
+
+    fn tick(&mut self, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
+        state.update_key_trigger();
+
+        if state.key_trigger.jump() || state.key_trigger.menu() {
+            state.next_scene = Some(Box::new(ChallengeMenuScene::new(state, ctx)?));
+        }
+
+        Ok(())
+    }
+
+    fn draw(&self, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
+        graphics::clear(ctx, Color::from_rgb(0, 0, 32));
+
+        let mut lines = vec![
+            self.challenge.name.clone(),
+            String::new(),
+            format!("Time: {}", format_time(self.ticks, CHALLENGE_TPS)),
+        ];
+
+        if let Some(best) = self.best {
+            lines.push(format!("Best: {}", format_time(best, CHALLENGE_TPS)));
+        }
+        if self.new_record {
+            lines.push("New record!".to_string());
+        }
+
+        let mut y = 48.0;
+        for line in lines.iter() {
+            state.font.draw_text(line.chars(), 32.0, y, &state.constants, &mut state.texture_set, ctx)?;
+            y += 14.0;
+        }
+
+        Ok(())
+    }
+}
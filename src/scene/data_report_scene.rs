@@ -0,0 +1,86 @@
+use crate::data_check::DataReport;
+use crate::ggez::{Context, event, GameResult, graphics};
+use crate::ggez::graphics::Color;
+use crate::scene::Scene;
+use crate::SharedGameState;
+use crate::str;
+
+/// Problems listed on the screen, the rest are only in the log.
+const MAX_SHOWN: usize = 10;
+
+/// Shown before the title screen when the data directory misses files the game needs or has broken ones.
+/// The game can be continued unless it can't run at all.
+pub struct DataReportScene {
+    lines: Vec<String>,
+    fatal: bool,
+    next_scene: Option<Box<dyn Scene>>,
+}
+
+impl DataReportScene {
+    pub fn new(report: &DataReport, next_scene: Box<dyn Scene>) -> Self {
+        let fatal = report.is_fatal();
+        let mut lines = vec![
+            if fatal { str!("The game data is incomplete:") } else { str!("Some game data looks damaged:") },
+            String::new(),
+        ];
+
+        let essential: Vec<String> = report.issues.iter().filter(|issue| issue.essential).map(|issue| issue.to_string()).collect();
+        for issue in essential.iter().take(MAX_SHOWN) {
+            // todo: wrap by text width instead of character count
+            for chunk in issue.chars().collect::<Vec<char>>().chunks(48) {
+                lines.push(chunk.iter().collect());
+            }
+        }
+        if essential.len() > MAX_SHOWN {
+            lines.push(format!("...and {} more, see the log.", essential.len() - MAX_SHOWN));
+        }
+
+        lines.push(String::new());
+        if fatal {
+            lines.push(str!("Check the data directory, then restart."));
+            lines.push(str!("Press Z or Escape to quit."));
+        } else {
+            lines.push(str!("Press Z to continue anyway,"));
+            lines.push(str!("or Escape to quit."));
+        }
+
+        Self {
+            lines,
+            fatal,
+            next_scene: Some(next_scene),
+        }
+    }
+}
+
+impl Scene for DataReportScene {
+    fn init(&mut self, state: &mut SharedGameState, _ctx: &mut Context) -> GameResult {
+        state.textscript_vm.suspend = true;
+
+        Ok(())
+    }
+
+    fn tick(&mut self, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
+        state.update_key_trigger();
+
+        if state.key_trigger.menu() || (self.fatal && state.key_trigger.jump()) {
+            event::quit(ctx);
+        } else if state.key_trigger.jump() {
+            log::warn!("Continuing with damaged game data.");
+            state.next_scene = self.next_scene.take();
+        }
+
+        Ok(())
+    }
+
+    fn draw(&self, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
+        graphics::clear(ctx, Color::from_rgb(0, 0, 32));
+
+        let mut y = 16.0;
+        for line in self.lines.iter() {
+            state.font.draw_text(line.chars(), 16.0, y, &state.constants, &mut state.texture_set, ctx)?;
+            y += 12.0;
+        }
+
+        Ok(())
+    }
+}
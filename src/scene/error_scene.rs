@@ -0,0 +1,62 @@
+use crate::ggez::{Context, event, GameError, GameResult, graphics};
+use crate::ggez::graphics::Color;
+use crate::scene::Scene;
+use crate::SharedGameState;
+use crate::str;
+
+/// Shown in place of a scene that failed to load, so the player doesn't end up staring at a black screen.
+pub struct ErrorScene {
+    lines: Vec<String>,
+}
+
+impl ErrorScene {
+    pub fn new(error: GameError) -> Self {
+        log::error!("Unrecoverable error: {}", error);
+
+        let mut lines = vec![str!("An error occurred:")];
+        let message = error.to_string();
+
+        // todo: wrap by text width instead of character count
+        for chunk in message.chars().collect::<Vec<char>>().chunks(48) {
+            lines.push(chunk.iter().collect());
+        }
+
+        lines.push(String::new());
+        lines.push(str!("Press Z or Escape to quit."));
+
+        Self {
+            lines,
+        }
+    }
+}
+
+impl Scene for ErrorScene {
+    fn init(&mut self, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
+        state.textscript_vm.suspend = true;
+        state.sound_manager.play_song(0, &state.constants, ctx)?;
+
+        Ok(())
+    }
+
+    fn tick(&mut self, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
+        state.update_key_trigger();
+
+        if state.key_trigger.jump() || state.key_trigger.menu() {
+            event::quit(ctx);
+        }
+
+        Ok(())
+    }
+
+    fn draw(&self, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
+        graphics::clear(ctx, Color::from_rgb(0, 0, 32));
+
+        let mut y = 16.0;
+        for line in self.lines.iter() {
+            state.font.draw_text(line.chars(), 16.0, y, &state.constants, &mut state.texture_set, ctx)?;
+            y += 12.0;
+        }
+
+        Ok(())
+    }
+}
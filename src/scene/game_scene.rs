@@ -1,23 +1,59 @@
+use std::cell::RefCell;
+#[cfg(debug_assertions)]
+use std::collections::BTreeSet;
+use std::mem;
+
 use log::info;
 
 use crate::bullet::BulletManager;
-use crate::caret::CaretType;
-use crate::common::{Direction, FadeDirection, FadeState, Rect};
+use crate::caret::{CaretLayer, CaretType};
+use crate::challenge::{CHALLENGE_TPS, format_time};
+use crate::common::{Direction, KeyState, FadeState, Rect, TickGates, to_fix};
 use crate::entity::GameEntity;
-use crate::frame::Frame;
-use crate::ggez::{Context, GameResult, graphics, timer};
-use crate::ggez::graphics::Color;
+use crate::flash::Flash;
+use crate::frame::{display_rect, Frame};
+use crate::ggez::{Context, event, GameResult, graphics, timer};
+use crate::ggez::input::gamepad;
 use crate::ggez::nalgebra::clamp;
 use crate::inventory::Inventory;
-use crate::npc::NPCMap;
+use crate::inventory_ui::InventoryUI;
+use crate::life_bar::{BossLifeBar, BossTarget, LifeBar};
+use crate::lighting::{LightEmitters, LightManager};
+use crate::log_sink;
+use crate::map::{tile_rect, TileMesh, TilePass};
+use crate::npc::{NPCMap, NPCSheet, StageSheet};
+use crate::npc::boss::StageBoss;
 use crate::physics::PhysicalEntity;
-use crate::player::Player;
+use crate::player::{ControlMode, Player};
+use crate::profile::GameProfile;
+use crate::prompts;
+use crate::render::primitives::Primitives;
+use crate::render::render_pass::{DrawLayer, RenderPass};
+use crate::replay::{ReplayMode, ReplayStatus};
+use crate::repro::{ReproDump, ReproRun, Scenario};
+use crate::rewind::RewindBuffer;
+use crate::save_state::{QuickSaveAction, SaveState};
+use crate::scene::challenge_menu_scene::ChallengeMenuScene;
+use crate::scene::challenge_result_scene::ChallengeResultScene;
 use crate::scene::Scene;
-use crate::SharedGameState;
+use crate::scene::title_scene::TitleScene;
+use crate::scene::transition_scene::TransitionScene;
+use crate::{HUD_SAFE_MARGIN, SharedGameState, WINDOW_TITLE};
 use crate::stage::{BackgroundType, Stage};
-use crate::text_script::{ConfirmSelection, TextScriptExecutionState, TextScriptVM};
+use crate::stage_effect::StageEffect;
+use crate::stats::{Progress, StatEvent};
+use crate::str;
+use crate::text_script::{ConfirmSelection, EventTrigger, TextScriptExecutionState, TextScriptVM};
+use crate::texture_set::WindowStyle;
+use crate::transition::COVER_COLOR;
 use crate::ui::Components;
-use crate::weapon::WeaponType;
+use crate::watchdog::{SoftlockReason, Watchdog};
+
+/// Height of the slope surface at the left and the right edge of the tile, from its top, by the slope variant.
+/// Matches `judge_hit_triangle_a`..`judge_hit_triangle_h`.
+const SLOPE_SURFACES: [(u8, u8); 8] = [(16, 8), (8, 0), (0, 8), (8, 16), (0, 8), (8, 16), (16, 8), (8, 0)];
+/// The Nikumaru counter stops at 100 minutes.
+const NIKUMARU_MAX: usize = 100 * 60 * 50;
 
 pub struct GameScene {
     pub tick: usize,
@@ -25,25 +61,43 @@ pub struct GameScene {
     pub frame: Frame,
     pub player: Player,
     pub inventory: Inventory,
+    pub inventory_ui: InventoryUI,
     pub stage_id: usize,
     pub npc_map: NPCMap,
     pub bullet_manager: BulletManager,
-    tex_background_name: String,
+    pub flash: Flash,
+    pub lighting: LightManager,
+    pub stage_effect: StageEffect,
+    pub replay: ReplayMode,
+    /// Set when started with `--repro`, the player takes over once the scenario is over.
+    pub repro: Option<ReproRun>,
+    /// Snapshot restored at the end of `init`, set when the scene is created from a quick load.
+    pub pending_save_state: Option<Box<SaveState>>,
+    /// Player and NPC state of a `--repro` scenario, applied once the stage is loaded.
+    pub pending_scenario: Option<Scenario>,
+    pub rewind: RewindBuffer,
+    /// None if the stage is just filled with the background color, see `Background::resolve_texture`.
+    tex_background_name: Option<String>,
     tex_tileset_name: String,
-    life_bar: u16,
-    life_bar_counter: u16,
+    /// Built lazily while drawing, rebuilt when the map changes.
+    tile_mesh: RefCell<TileMesh>,
+    life_bar: LifeBar,
+    /// Shown by <BSL, gone along with the scene after <TRA.
+    pub boss_life_bar: BossLifeBar,
+    pub boss: StageBoss,
+    /// Debugger overlay coloring the tiles by their collision attribute.
+    pub attribute_overlay: bool,
+    watchdog: Watchdog,
+    /// Where the player entered the stage, one of the places to recover a softlock to.
+    entry_position: (isize, isize),
+    /// Softlock the recovery prompt is shown for, the game is paused meanwhile.
+    recovery_prompt: Option<SoftlockReason>,
+    /// Whether the last tick was paused, the rumble stops as the game pauses.
+    paused: bool,
     map_name_counter: u16,
     weapon_x_pos: isize,
 }
 
-#[derive(Debug, EnumIter, PartialEq, Eq, Hash, Copy, Clone)]
-pub enum TileLayer {
-    All,
-    Background,
-    Foreground,
-    Snack,
-}
-
 #[derive(Debug, EnumIter, PartialEq, Eq, Hash, Copy, Clone)]
 pub enum Alignment {
     Left,
@@ -56,29 +110,390 @@ impl GameScene {
         let stage = Stage::load(&state.base_path, &state.stages[id], ctx)?;
         info!("Loaded stage: {}", stage.data.name);
 
-        let tex_background_name = stage.data.background.filename();
+        GameScene::with_stage(state, ctx, id, stage)
+    }
+
+    /// Scene for an already loaded or generated stage, `id` is the entry of the stage table it's based on.
+    pub fn with_stage(state: &mut SharedGameState, ctx: &mut Context, id: usize, stage: Stage) -> GameResult<Self> {
+        let background = &stage.data.background;
+        let tex_background_name = background.resolve_texture(|name| state.texture_set.exists(ctx, name));
+        if tex_background_name.is_none() && !background.is_black() {
+            log::warn!("Background {:?} does not exist, the stage is filled with a solid color instead.", background.name());
+        }
+
+        Ok(GameScene::build(state, id, stage, tex_background_name))
+    }
+
+    /// Scene for a stage built by a test, nothing is drawn so the background isn't looked up.
+    #[cfg(test)]
+    pub fn for_tests(state: &mut SharedGameState, id: usize, stage: Stage) -> Self {
+        GameScene::build(state, id, stage, None)
+    }
+
+    fn build(state: &mut SharedGameState, id: usize, stage: Stage, tex_background_name: Option<String>) -> Self {
         let tex_tileset_name = ["Stage/", &stage.data.tileset.filename()].join("");
+        let boss = StageBoss::new(stage.data.boss_no);
 
-        Ok(Self {
+        Self {
             tick: 0,
             stage,
             player: Player::new(state),
             inventory: Inventory::new(),
-            frame: Frame {
-                x: 0,
-                y: 0,
-                wait: 16,
-            },
+            inventory_ui: InventoryUI::new(),
+            frame: Frame::new(),
             stage_id: id,
             npc_map: NPCMap::new(),
             bullet_manager: BulletManager::new(),
+            flash: Flash::new(),
+            lighting: LightManager::new(),
+            stage_effect: StageEffect::new(),
+            replay: ReplayMode::None,
+            repro: None,
+            pending_save_state: None,
+            pending_scenario: None,
+            rewind: RewindBuffer::new(),
             tex_background_name,
             tex_tileset_name,
-            life_bar: 0,
-            life_bar_counter: 0,
+            life_bar: LifeBar::default(),
+            boss_life_bar: BossLifeBar::new(),
+            boss,
+            attribute_overlay: false,
+            watchdog: Watchdog::new(),
+            entry_position: (0, 0),
+            recovery_prompt: None,
+            paused: false,
             map_name_counter: 0,
             weapon_x_pos: 16,
-        })
+            tile_mesh: RefCell::new(TileMesh::new()),
+        }
+    }
+
+    /// Logs the animation rects of the NPCs on this stage which don't fit on their sheet,
+    /// typos in the constants would otherwise only show up as garbage on the screen.
+    #[cfg(debug_assertions)]
+    fn validate_npc_rects(&self, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
+        let npc_types: BTreeSet<u16> = self.npc_map.npcs.values().map(|npc| npc.borrow().npc_type).collect();
+
+        for npc_type in npc_types {
+            let rects = state.constants.npc.rects(npc_type);
+            if rects.is_empty() {
+                continue;
+            }
+
+            let name = state.npc_table.get_texture_name(npc_type).to_owned();
+            let (width, height) = state.texture_set.get_or_load_batch(ctx, &state.constants, &name)?.dimensions();
+
+            for (i, rect) in rects.iter().enumerate() {
+                if rect.right > width || rect.bottom > height || rect.left > rect.right || rect.top > rect.bottom {
+                    log::warn!("NPC type {}: rect #{} {:?} doesn't fit on {} ({}x{}).", npc_type, i, rect, name, width, height);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Loads the tileset, background and NPC sheets of this stage ahead of the first frame.
+    pub fn preload_textures(&self, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
+        state.texture_set.get_or_load_batch(ctx, &state.constants, &self.tex_tileset_name)?;
+        if let Some(name) = self.tex_background_name.as_ref() {
+            state.texture_set.get_or_load_batch(ctx, &state.constants, name)?;
+        }
+
+        // not every stage has both NPC sheets, missing ones are loaded lazily (or not at all) later.
+        for npc_sheet in [&self.stage.data.npc1, &self.stage.data.npc2].iter() {
+            let name = ["Npc/", &npc_sheet.filename()].join("");
+            if let Err(e) = state.texture_set.get_or_load_batch(ctx, &state.constants, &name) {
+                log::warn!("Cannot preload {}: {}", name, e);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle_quick_save(&mut self, action: QuickSaveAction, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
+        if !state.settings.allow_quick_save() || state.temporary_profile || self.replay.is_active() {
+            return Ok(());
+        }
+
+        // would trivialize the timed runs
+        if self.player.equip.has_nikumaru() {
+            log::info!("Quick save is disabled while the Nikumaru counter is equipped.");
+            return Ok(());
+        }
+
+        match action {
+            QuickSaveAction::Save => {
+                SaveState::save_quick(self, state)?;
+                log::info!("Quick saved.");
+            }
+            QuickSaveAction::Load => {
+                let scene = SaveState::load_quick(state)?.into_scene(state, ctx)?;
+                state.next_scene = Some(Box::new(scene));
+                log::info!("Quick loaded.");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Drops what's alive on the stage when a script leaves the game with `<ESC`, `<INI` or `<LDP`.
+    pub fn teardown(&mut self) {
+        self.npc_map = NPCMap::new();
+        self.bullet_manager = BulletManager::new();
+        self.boss = StageBoss::new(0);
+        self.boss_life_bar = BossLifeBar::new();
+        self.stage_effect = StageEffect::new();
+    }
+
+    /// Whether the recovery prompt, the inventory or the world frozen from the debugger pause the game.
+    /// Scripts freeze the world with <PRI while they run, once they end it can only be the debugger.
+    fn is_paused(&self, state: &SharedGameState) -> bool {
+        self.recovery_prompt.is_some() || self.inventory_ui.active
+            || (!state.control_flags.tick_world() && state.textscript_vm.state == TextScriptExecutionState::Ended)
+    }
+
+    /// Logs what might have led to the softlock, for bug reports.
+    fn log_softlock(&self, reason: SoftlockReason, state: &SharedGameState) {
+        log::warn!("Softlock detected ({:?}) on {} at ({}, {}) px, control flags: {:#06x}.",
+                   reason, self.stage.data.map, self.player.x / 0x200, self.player.y / 0x200, state.control_flags.0);
+
+        log::warn!("Script state:\n{}", state.textscript_vm.dump_state());
+
+        let flags: Vec<String> = state.flag_log.iter()
+            .map(|&(flag, value)| format!("{}{}", if value { '+' } else { '-' }, flag))
+            .collect();
+        log::warn!("Recently changed flags: {}", flags.join(" "));
+        log::warn!("{}", state.stage_history.dump());
+    }
+
+    /// Tick with the keys given rather than polled and without the script, which needs a window.
+    /// Scenarios are played back with it in tests, see `Scenario::play_headless`.
+    pub fn tick_headless(&mut self, state: &mut SharedGameState, key_state: KeyState) -> GameResult {
+        state.key_state = key_state;
+        state.update_key_trigger();
+
+        let gates = state.control_flags.tick_gates(&state.settings);
+        self.tick_gated(state, gates)?;
+        self.tick = self.tick.wrapping_add(1);
+        Ok(())
+    }
+
+    /// Ticks the parts of the scene that the control flags let through, see `TickGates`.
+    fn tick_gated(&mut self, state: &mut SharedGameState, gates: TickGates) -> GameResult {
+        // the first tick settles the player and the NPCs into place even if the entry event starts with <PRI
+        if self.tick == 0 || gates.world {
+            self.player.tick(state, &mut self.inventory)?;
+
+            self.npc_map.apply_off_screen_policies(&self.frame, state.canvas_size);
+            let player = &mut self.player;
+            self.npc_map.tick_npcs(|npc| npc.tick(state, &mut *player))?;
+            self.npc_map.garbage_collect();
+            self.npc_map.process_npc_changes(state);
+
+            let was_grounded = self.player.flags.hit_bottom_wall();
+            self.player.flags.0 = 0;
+
+            // <UNI0002 can take the player off of the map
+            if self.player.control_mode != ControlMode::Fixed {
+                self.player.tick_map_collisions(state, &mut self.stage);
+            }
+            self.player.tick_npc_collisions(state, &mut self.npc_map, &mut self.inventory, &self.stage);
+            // after the NPCs, standing on one counts as being on the ground
+            if self.player.control_mode == ControlMode::Normal && state.settings.allow_slope_snapping() {
+                self.player.snap_to_ground(&self.stage.map, was_grounded);
+            }
+            self.npc_map.process_npc_changes(state);
+            for npc_id in self.npc_map.npc_ids.iter() {
+                if let Some(npc_cell) = self.npc_map.npcs.get_mut(npc_id) {
+                    let mut npc = npc_cell.borrow_mut();
+
+                    if npc.cond.alive() && !npc.npc_flags.ignore_solidity() {
+                        // flags are still the ones from the last tick, the AI sees the push on the next one
+                        if npc.flags.in_water() {
+                            npc.vel_x += state.ambient_force.0;
+                            npc.vel_y += state.ambient_force.1;
+
+                            if state.settings.allow_npc_buoyancy() {
+                                npc.apply_buoyancy();
+                            }
+                        }
+
+                        npc.flags.0 = 0;
+                        npc.tick_map_collisions(state, &mut self.stage);
+                    }
+                }
+            }
+            self.npc_map.process_npc_changes(state);
+            self.tick_npc_bullet_collissions(state);
+
+            state.tick_carets();
+            self.bullet_manager.tick_bullets(state, &self.player, &mut self.stage);
+
+            self.frame.update(state, &self.player, &self.stage);
+            self.flash.tick(state, ())?;
+            self.stage_effect.tick(state, &self.frame);
+
+            // mirror the screen shake
+            if state.quake_counter > 0 {
+                state.rumble(0.5, 0.0, 40);
+            }
+        } else if gates.carets {
+            // smoke and the damage numbers of what happened right before <PRI still play out
+            state.tick_carets();
+        }
+
+        if !self.player.equip.has_nikumaru() {
+            state.nikumaru_counter = 0;
+        } else if gates.player_input && state.nikumaru_counter < NIKUMARU_MAX {
+            state.nikumaru_counter += 1;
+        }
+
+        if gates.player_input {
+            // both pressed at once switches forward, like in the original
+            if state.key_trigger.weapon_next() {
+                self.switch_weapon(state, true);
+            } else if state.key_trigger.weapon_prev() {
+                self.switch_weapon(state, false);
+            }
+
+            if let Some(weapon) = self.inventory.get_current_weapon_mut() {
+                weapon.shoot_bullet(&self.player, &mut self.bullet_manager, state);
+            }
+
+            // update health bar
+            self.life_bar.max_life = self.player.max_life;
+            self.life_bar.tick(self.player.life);
+        }
+
+        Ok(())
+    }
+
+    /// The HUD slides the weapon icons in from the side of the switch, it always shows the selected weapon
+    /// of the inventory so switching again mid-slide just starts it over.
+    fn switch_weapon(&mut self, state: &mut SharedGameState, forward: bool) {
+        // todo: release the held Bubbler bubbles once its level 3 is implemented
+        if !self.inventory.switch_weapon(forward) {
+            return;
+        }
+
+        self.weapon_x_pos = if forward { 32 } else { 0 };
+        state.sound_manager.play_sfx(4);
+    }
+
+    /// Goes back to where the player was before the last stage transition through the usual stage loading,
+    /// the flags and the inventory stay as they are.
+    pub fn warp_back(&self, state: &mut SharedGameState) {
+        let entry = match state.stage_history.pop() {
+            Some(entry) => entry,
+            None => {
+                state.notifications.push(str!("There's no stage to go back to."));
+                return;
+            }
+        };
+        log::info!("Warping back to {}", entry);
+
+        state.textscript_vm.reset();
+        state.textscript_vm.suspend = true;
+        state.control_flags.set_tick_world(true);
+        state.control_flags.set_control_enabled(true);
+        state.control_flags.set_interactions_disabled(false);
+        state.fade_state = FadeState::Visible;
+        state.next_scene = Some(Box::new(TransitionScene::new(entry.stage_id, entry.x, entry.y,
+                                                              self.player.clone(), self.inventory.clone())));
+    }
+
+    fn tick_watchdog(&mut self, state: &mut SharedGameState) {
+        if self.replay.is_active() || self.repro.is_some() {
+            return;
+        }
+
+        // being outside of the map is intended with <UNI0002
+        let tile = if self.player.control_mode == ControlMode::Fixed {
+            (0, 0)
+        } else {
+            ((self.player.x + 0x1000) >> 13, (self.player.y + 0x1000) >> 13)
+        };
+        let map_size = (self.stage.map.width, self.stage.map.height);
+        let script_running = state.textscript_vm.state != TextScriptExecutionState::Ended;
+
+        if let Some(reason) = self.watchdog.tick(tile, map_size, state.control_flags.control_enabled(), script_running) {
+            self.log_softlock(reason, state);
+
+            if state.settings.allow_softlock_recovery() {
+                self.recovery_prompt = Some(reason);
+            }
+        }
+    }
+
+    /// Handles the recovery prompt, jump goes back to the last save and fire to where the stage was entered.
+    fn tick_recovery_prompt(&mut self, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
+        if state.key_trigger.jump() && !state.temporary_profile {
+            match GameProfile::load(state) {
+                Ok(Some(profile)) => {
+                    state.teardown_game();
+                    let scene = profile.create_scene(state, ctx)?;
+                    state.next_scene = Some(Box::new(scene));
+                }
+                Ok(None) => {
+                    state.notifications.push(str!("There's no save to go back to."));
+                    return Ok(());
+                }
+                Err(e) => {
+                    log::error!("Cannot load the profile: {}", e);
+                    state.notifications.push(str!("Cannot load the last save."));
+                    return Ok(());
+                }
+            }
+        } else if state.key_trigger.fire() {
+            self.player.x = self.entry_position.0;
+            self.player.y = self.entry_position.1;
+            self.player.vel_x = 0;
+            self.player.vel_y = 0;
+            self.player.target_x = self.player.x;
+            self.player.target_y = self.player.y;
+
+            state.textscript_vm.reset();
+            state.control_flags.set_tick_world(true);
+            state.control_flags.set_control_enabled(true);
+            state.control_flags.set_interactions_disabled(false);
+            self.frame.immediate_update(state, &self.player, &self.stage);
+        } else if !state.key_trigger.menu() {
+            return Ok(());
+        }
+
+        self.recovery_prompt = None;
+        self.watchdog.reset();
+        Ok(())
+    }
+
+    fn draw_recovery_prompt(&self, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
+        let (width, height) = state.canvas_size;
+        state.texture_set.draw_rect(Rect::new(0, (height / 2.0) as isize - 32, width as isize, (height / 2.0) as isize + 36), [0.0, 0.0, 0.0, 0.75], ctx)?;
+
+        let title = "The game seems to be stuck.";
+        let title_width = state.font.text_width(title.chars(), &state.constants);
+        state.font.draw_text(title.chars(), ((width - title_width) / 2.0).floor(), (height / 2.0).floor() - 22.0,
+                             &state.constants, &mut state.texture_set, ctx)?;
+
+        let mut lines = Vec::with_capacity(3);
+        if !state.temporary_profile {
+            lines.push(format!("{} Go back to the last save", prompts::token_for(KeyState::set_jump)));
+        }
+        lines.push(format!("{} Go back to the stage entrance", prompts::token_for(KeyState::set_fire)));
+        lines.push(format!("{} Keep playing", prompts::token_for(KeyState::set_menu)));
+
+        for (i, line) in lines.iter().enumerate() {
+            prompts::draw_text(line.chars(), (width / 2.0).floor() - 80.0, (height / 2.0).floor() - 2.0 + i as f32 * 12.0, state, ctx)?;
+        }
+
+        Ok(())
+    }
+
+    fn update_rich_presence(&self, state: &mut SharedGameState) {
+        state.discord_rpc.update(state.settings.discord_rpc, &self.stage.data.name,
+                                 self.player.life, self.player.max_life,
+                                 state.settings.total_ticks_played);
     }
 
     pub fn display_map_name(&mut self, ticks: u16) {
@@ -100,10 +515,26 @@ impl GameScene {
     }
 
     fn draw_hud(&self, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
-        // none
-        let weap_x = self.weapon_x_pos as f32;
+        // anchored to the left of the HUD area, except for the centered elements
+        let hud = state.hud_rect();
+        let hud_x = hud.left;
+        let weap_x = self.weapon_x_pos as f32 + hud_x;
         let (ammo, max_ammo) = self.inventory.get_current_ammo();
         let (xp, max_xp, max_level) = self.inventory.get_current_max_exp(&state.constants);
+
+        if state.settings.accessibility.high_contrast_hud {
+            let weap_x = self.weapon_x_pos + hud_x as isize;
+            let mut primitives = Primitives::new();
+            // ammo, level and xp
+            primitives.rect(Rect::new_size(weap_x - 2, 14, 70, 28), [0.0, 0.0, 0.0, 1.0]);
+
+            if self.player.max_life != 0 {
+                primitives.rect(Rect::new_size(14 + hud_x as isize, 38, 68, 12), [0.0, 0.0, 0.0, 1.0]);
+            }
+
+            state.texture_set.draw_primitives(ctx, &primitives)?;
+        }
+
         let batch = state.texture_set.get_or_load_batch(ctx, &state.constants, "TextBox")?;
 
         if max_ammo == 0 {
@@ -136,14 +567,27 @@ impl GameScene {
 
         if self.player.max_life != 0 {
             // life box
-            batch.add_rect(16.0, 40.0,
+            batch.add_rect(hud_x + 16.0, 40.0,
                            &Rect::<usize>::new_size(0, 40, 64, 8));
             // yellow bar
-            batch.add_rect(40.0, 40.0,
-                           &Rect::<usize>::new_size(0, 32, ((self.life_bar as usize * 40) / self.player.max_life as usize), 8));
+            batch.add_rect(hud_x + 40.0, 40.0,
+                           &Rect::<usize>::new_size(0, 32, self.life_bar.width(self.life_bar.chaser, 40), 8));
             // life
-            batch.add_rect(40.0, 40.0,
-                           &Rect::<usize>::new_size(0, 24, ((self.player.life as usize * 40) / self.player.max_life as usize), 8));
+            batch.add_rect(hud_x + 40.0, 40.0,
+                           &Rect::<usize>::new_size(0, 24, self.life_bar.width(self.player.life, 40), 8));
+        }
+
+        if self.boss_life_bar.is_visible() {
+            let bar = &self.boss_life_bar.bar;
+            let x = (state.canvas_size.0 / 2.0).floor();
+            let y = state.canvas_size.1 + self.boss_life_bar.offset as f32;
+
+            // box, "Boss" label, yellow bar and life
+            batch.add_rect(x - 128.0, y - 20.0, &Rect::<usize>::new_size(0, 0, 244, 8));
+            batch.add_rect(x - 128.0, y - 12.0, &Rect::<usize>::new_size(0, 16, 244, 8));
+            batch.add_rect(x - 124.0, y - 16.0, &Rect::<usize>::new_size(0, 48, 32, 8));
+            batch.add_rect(x - 84.0, y - 16.0, &Rect::<usize>::new_size(0, 32, bar.width(bar.chaser, 198), 8));
+            batch.add_rect(x - 84.0, y - 16.0, &Rect::<usize>::new_size(0, 24, bar.width(bar.life, 198), 8));
         }
 
         batch.draw(ctx)?;
@@ -155,7 +599,7 @@ impl GameScene {
             let mut rect = Rect::new(0, 0, 0, 16);
 
             for a in 0..weapon_count {
-                let mut pos_x = ((a - current_weapon) as f32 * 16.0) + weap_x;
+                let mut pos_x = ((a as isize - current_weapon as isize) as f32 * 16.0) + self.weapon_x_pos as f32;
 
                 if pos_x < 8.0 {
                     pos_x += 48.0 + weapon_count as f32 * 16.0;
@@ -172,7 +616,7 @@ impl GameScene {
                 if let Some(weapon) = self.inventory.get_weapon(a) {
                     rect.left = weapon.wtype as usize * 16;
                     rect.right = rect.left + 16;
-                    batch.add_rect(pos_x, 16.0, &rect);
+                    batch.add_rect(hud_x + pos_x, 16.0, &rect);
                 }
             }
         }
@@ -184,13 +628,84 @@ impl GameScene {
             self.draw_number(weap_x + 64.0, 24.0, max_ammo as usize, Alignment::Right, state, ctx)?;
         }
         self.draw_number(weap_x + 24.0, 32.0, self.inventory.get_current_level() as usize, Alignment::Right, state, ctx)?;
-        self.draw_number(40.0, 40.0, self.life_bar as usize, Alignment::Right, state, ctx)?;
+        self.draw_number(hud_x + 40.0, 40.0, self.life_bar.chaser as usize, Alignment::Right, state, ctx)?;
+
+        // air counter, the Air Tank hides it
+        if self.player.air_get > 0 && !self.player.equip.has_air_tank() {
+            let x = (state.canvas_size.0 / 2.0 - 40.0).floor();
+            let y = (state.canvas_size.1 / 2.0 - 16.0).floor();
+
+            if self.player.air_get % 6 < 4 {
+                self.draw_number(x + 64.0, y, self.player.air as usize / 10, Alignment::Right, state, ctx)?;
+            }
+
+            let rect = if self.player.air % 30 > 10 { Rect::<usize>::new_size(112, 72, 32, 8) } else { Rect::<usize>::new_size(112, 80, 32, 8) };
+            let batch = state.texture_set.get_or_load_batch(ctx, &state.constants, "TextBox")?;
+            batch.add_rect(x, y, &rect);
+            batch.draw(ctx)?;
+        }
+
+        if self.player.equip.has_nikumaru() {
+            let x = hud_x + 16.0;
+            let counter = state.nikumaru_counter;
+            // the clock blinks while the counter runs
+            let clock_x = if state.control_flags.tick_gates(&state.settings).player_input && counter % 30 <= 10 { 120 } else { 112 };
+
+            let batch = state.texture_set.get_or_load_batch(ctx, &state.constants, "TextBox")?;
+            batch.add_rect(x, 8.0, &Rect::<usize>::new_size(clock_x, 104, 8, 8));
+            batch.add_rect(x + 30.0, 8.0, &Rect::<usize>::new_size(128, 104, 32, 8));
+            batch.draw(ctx)?;
+
+            // minutes, seconds with both digits and tenths
+            self.draw_number(x + 32.0, 8.0, counter / 3000, Alignment::Right, state, ctx)?;
+            self.draw_number(x + 44.0, 8.0, counter / 500 % 6, Alignment::Right, state, ctx)?;
+            self.draw_number(x + 52.0, 8.0, counter / 50 % 10, Alignment::Right, state, ctx)?;
+            self.draw_number(x + 64.0, 8.0, counter / 5 % 10, Alignment::Right, state, ctx)?;
+        }
+
+        if let Some(run) = state.challenge.as_ref() {
+            let time = format_time(run.ticks, state.tps());
+            state.font.draw_text(time.chars(), hud.right - HUD_SAFE_MARGIN - 56.0, 8.0, &state.constants, &mut state.texture_set, ctx)?;
+        }
 
         Ok(())
     }
 
-    fn draw_background(&self, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
-        let batch = state.texture_set.get_or_load_batch(ctx, &state.constants, &self.tex_background_name)?;
+    /// Current HP of what the boss bar follows, `None` if it's dead or gone.
+    pub fn boss_life(&self, target: Option<BossTarget>) -> Option<u16> {
+        match target {
+            Some(BossTarget::NPC(id)) => {
+                let npc = self.npc_map.npcs.get(&id)?.borrow();
+                if npc.cond.alive() { Some(npc.life) } else { None }
+            }
+            Some(BossTarget::StageBoss) => self.boss.life(),
+            None => None,
+        }
+    }
+
+    /// Follows the HP of the boss, the stage boss going down ends its fight.
+    fn tick_boss_life_bar(&mut self, state: &mut SharedGameState) {
+        let boss_target = self.boss_life_bar.target;
+        let boss_life = self.boss_life(boss_target);
+        self.boss_life_bar.tick(boss_life);
+        // todo: never happens in game until the boss AIs are in, see `StageBoss`
+        if boss_target == Some(BossTarget::StageBoss) && self.boss_life_bar.target.is_none() {
+            state.stats.record(StatEvent::BossDefeated(self.stage.data.boss_no));
+            if self.boss.flashes_on_death() {
+                self.flash.set_explosion(self.boss.x, self.boss.y, state);
+            }
+        }
+    }
+
+    fn draw_background(&self, state: &mut SharedGameState, ctx: &mut Context, frame: &Frame) -> GameResult {
+        // also what shows around maps smaller than the screen, which are common with the expanded view
+        graphics::clear(ctx, self.stage.data.background.fill_color().into());
+
+        let name = match self.tex_background_name.as_ref() {
+            Some(name) => name,
+            None => { return Ok(()); }
+        };
+        let batch = state.texture_set.get_or_load_batch(ctx, &state.constants, name)?;
 
         match self.stage.data.background_type {
             BackgroundType::Stationary => {
@@ -206,13 +721,13 @@ impl GameScene {
             BackgroundType::MoveDistant | BackgroundType::MoveNear => {
                 let (off_x, off_y) = if self.stage.data.background_type == BackgroundType::MoveNear {
                     (
-                        self.frame.x as usize % (batch.width() * 0x200),
-                        self.frame.y as usize % (batch.height() * 0x200)
+                        frame.x as usize % (batch.width() * 0x200),
+                        frame.y as usize % (batch.height() * 0x200)
                     )
                 } else {
                     (
-                        self.frame.x as usize / 2 % (batch.width() * 0x200),
-                        self.frame.y as usize / 2 % (batch.height() * 0x200)
+                        frame.x as usize / 2 % (batch.width() * 0x200),
+                        frame.y as usize / 2 % (batch.height() * 0x200)
                     )
                 };
 
@@ -227,9 +742,7 @@ impl GameScene {
                 }
             }
             BackgroundType::Water => {}
-            BackgroundType::Black => {
-                graphics::clear(ctx, Color::from_rgb(0, 0, 32));
-            }
+            BackgroundType::Black => {}
             BackgroundType::Autoscroll => {}
             BackgroundType::OutsideWind | BackgroundType::Outside => {
                 let offset = (self.tick % 640) as isize;
@@ -264,12 +777,16 @@ impl GameScene {
         Ok(())
     }
 
-    fn draw_bullets(&self, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
+    fn draw_bullets(&self, state: &mut SharedGameState, ctx: &mut Context, frame: &Frame) -> GameResult {
         let batch = state.texture_set.get_or_load_batch(ctx, &state.constants, "Bullet")?;
         let mut x: isize;
         let mut y: isize;
 
         for bullet in self.bullet_manager.bullets.iter() {
+            if !frame.is_visible(state.canvas_size, &display_rect(bullet.x, bullet.y, &bullet.display_bounds)) {
+                continue;
+            }
+
             match bullet.direction {
                 Direction::Left => {
                     x = bullet.x - bullet.display_bounds.left as isize;
@@ -289,8 +806,8 @@ impl GameScene {
                 }
             }
 
-            batch.add_rect(((x / 0x200) - (self.frame.x / 0x200)) as f32,
-                           ((y / 0x200) - (self.frame.y / 0x200)) as f32,
+            batch.add_rect(((x / 0x200) - (frame.x / 0x200)) as f32,
+                           ((y / 0x200) - (frame.y / 0x200)) as f32,
                            &bullet.anim_rect);
         }
 
@@ -298,12 +815,20 @@ impl GameScene {
         Ok(())
     }
 
-    fn draw_carets(&self, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
+    fn draw_carets(&self, state: &mut SharedGameState, ctx: &mut Context, frame: &Frame, layer: CaretLayer) -> GameResult {
         let batch = state.texture_set.get_or_load_batch(ctx, &state.constants, "Caret")?;
 
-        for caret in state.carets.iter() {
-            batch.add_rect((((caret.x - caret.offset_x) / 0x200) - (self.frame.x / 0x200)) as f32,
-                           (((caret.y - caret.offset_y) / 0x200) - (self.frame.y / 0x200)) as f32,
+        let constants = &state.constants;
+        for caret in state.carets.iter().filter(|c| c.layer(constants) == layer) {
+            let left = caret.x - caret.offset_x;
+            let top = caret.y - caret.offset_y;
+            let rect = Rect::new_size(left, top, to_fix(caret.anim_rect.width() as isize), to_fix(caret.anim_rect.height() as isize));
+            if !frame.is_visible(state.canvas_size, &rect) {
+                continue;
+            }
+
+            batch.add_rect((((caret.x - caret.offset_x) / 0x200) - (frame.x / 0x200)) as f32,
+                           (((caret.y - caret.offset_y) / 0x200) - (frame.y / 0x200)) as f32,
                            &caret.anim_rect);
         }
 
@@ -311,85 +836,33 @@ impl GameScene {
         Ok(())
     }
 
+    /// Booster fuel, above the player.
+    fn draw_booster_fuel(&self, state: &mut SharedGameState, ctx: &mut Context, frame: &Frame) -> GameResult {
+        let booster = state.constants.booster;
+        if (self.player.equip.has_booster_0_8() || self.player.equip.has_booster_2_0())
+            && self.player.booster_fuel < booster.fuel && self.player.cond.alive() && !self.player.cond.hidden() {
+            let x = ((self.player.x - frame.x) / 0x200) as f32 - (booster.fuel_bar_empty.width() / 2) as f32;
+            let y = ((self.player.y - frame.y) / 0x200) as f32 - 20.0;
+            let mut fuel_rect = booster.fuel_bar_full;
+            fuel_rect.right = fuel_rect.left + fuel_rect.width() * self.player.booster_fuel / booster.fuel.max(1);
+
+            let batch = state.texture_set.get_or_load_batch(ctx, &state.constants, "TextBox")?;
+            batch.add_rect(x, y, &booster.fuel_bar_empty);
+            batch.add_rect(x, y, &fuel_rect);
+            batch.draw(ctx)?;
+        }
+
+        Ok(())
+    }
+
     fn draw_fade(&self, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
         match state.fade_state {
             FadeState::Visible => { return Ok(()); }
             FadeState::Hidden => {
-                graphics::clear(ctx, Color::from_rgb(0, 0, 32));
-            }
-            FadeState::FadeIn(tick, direction) | FadeState::FadeOut(tick, direction) => {
-                let batch = state.texture_set.get_or_load_batch(ctx, &state.constants, "Fade")?;
-                let mut rect = Rect::<usize>::new(0, 0, 16, 16);
-
-                match direction {
-                    FadeDirection::Left | FadeDirection::Right => {
-                        let mut frame = tick;
-
-                        for x in (0..(state.canvas_size.0 as isize + 16)).step_by(16) {
-                            if frame > 15 { frame = 15; } else { frame += 1; }
-
-                            if frame >= 0 {
-                                rect.left = frame as usize * 16;
-                                rect.right = rect.left + 16;
-
-                                for y in (0..(state.canvas_size.1 as isize + 16)).step_by(16) {
-                                    if direction == FadeDirection::Left {
-                                        batch.add_rect(state.canvas_size.0 - x as f32, y as f32, &rect);
-                                    } else {
-                                        batch.add_rect(x as f32, y as f32, &rect);
-                                    }
-                                }
-                            }
-                        }
-                    }
-                    FadeDirection::Up | FadeDirection::Down => {
-                        let mut frame = tick;
-
-                        for y in (0..(state.canvas_size.1 as isize + 16)).step_by(16) {
-                            if frame > 15 { frame = 15; } else { frame += 1; }
-
-                            if frame >= 0 {
-                                rect.left = frame as usize * 16;
-                                rect.right = rect.left + 16;
-
-                                for x in (0..(state.canvas_size.0 as isize + 16)).step_by(16) {
-                                    if direction == FadeDirection::Down {
-                                        batch.add_rect(x as f32, y as f32, &rect);
-                                    } else {
-                                        batch.add_rect(x as f32, state.canvas_size.1 - y as f32, &rect);
-                                    }
-                                }
-                            }
-                        }
-                    }
-                    FadeDirection::Center => {
-                        let center_x = (state.canvas_size.0 / 2.0 - 8.0) as isize;
-                        let center_y = (state.canvas_size.1 / 2.0 - 8.0) as isize;
-                        let mut start_frame = tick;
-
-                        for x in (0..(center_x + 16)).step_by(16) {
-                            let mut frame = start_frame;
-
-                            for y in (0..(center_y + 16)).step_by(16) {
-                                if frame > 15 { frame = 15; } else { frame += 1; }
-
-                                if frame >= 0 {
-                                    rect.left = frame as usize * 16;
-                                    rect.right = rect.left + 16;
-
-                                    batch.add_rect((center_x - x) as f32, (center_y + y) as f32, &rect);
-                                    batch.add_rect((center_x - x) as f32, (center_y - y) as f32, &rect);
-                                    batch.add_rect((center_x + x) as f32, (center_y + y) as f32, &rect);
-                                    batch.add_rect((center_x + x) as f32, (center_y - y) as f32, &rect);
-                                }
-                            }
-
-                            start_frame += 1;
-                        }
-                    }
-                }
-
-                batch.draw(ctx)?;
+                graphics::clear(ctx, COVER_COLOR.into());
+            }
+            FadeState::FadeIn(tick, direction, ty) | FadeState::FadeOut(tick, direction, ty) => {
+                ty.transition().draw(tick, direction, state, ctx)?;
             }
         }
 
@@ -400,21 +873,38 @@ impl GameScene {
         Ok(())
     }
 
+    /// Size of the message box and the scale of the text in it.
+    pub fn text_box_size(state: &SharedGameState) -> (f32, f32, f32) {
+        // large text mode renders the font at 2x into a full width, twice as tall box
+        if state.settings.accessibility.large_text {
+            ((state.canvas_size.0 - 16.0).floor(), 128.0, 2.0)
+        } else {
+            (state.constants.textscript.textbox_frame.rect.width() as f32, 64.0, 1.0)
+        }
+    }
+
+    /// Where the message box goes in canvas pixels, it doesn't depend on the frame so `<QUA` doesn't shake it.
+    fn text_box_rect(state: &SharedGameState) -> Rect<f32> {
+        let (box_width, box_height, _) = GameScene::text_box_size(state);
+
+        let top_pos = if state.textscript_vm.flags.position_top() { 32.0 } else { state.canvas_size.1 as f32 - box_height - 2.0 };
+        let left_pos = ((state.canvas_size.0 - box_width) / 2.0).floor();
+
+        Rect::new_size(left_pos, top_pos, box_width, box_height)
+    }
+
     fn draw_text_boxes(&self, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
         if !state.textscript_vm.flags.render() { return Ok(()); }
 
-        let top_pos = if state.textscript_vm.flags.position_top() { 32.0 } else { state.canvas_size.1 as f32 - 66.0 };
-        let left_pos = (state.canvas_size.0 / 2.0 - 122.0).floor();
+        let (_, _, text_scale) = GameScene::text_box_size(state);
+        let box_rect = GameScene::text_box_rect(state);
+        let (left_pos, top_pos) = (box_rect.left, box_rect.top);
+
+        let style = if state.textscript_vm.flags.background_visible() { WindowStyle::Normal } else { WindowStyle::Invisible };
+        state.texture_set.draw_window(ctx, &state.constants, box_rect, style)?;
 
         {
             let batch = state.texture_set.get_or_load_batch(ctx, &state.constants, "TextBox")?;
-            if state.textscript_vm.flags.background_visible() {
-                batch.add_rect(left_pos, top_pos, &state.constants.textscript.textbox_rect_top);
-                for i in 1..7 {
-                    batch.add_rect(left_pos, top_pos + i as f32 * 8.0, &state.constants.textscript.textbox_rect_middle);
-                }
-                batch.add_rect(left_pos, top_pos + 56.0, &state.constants.textscript.textbox_rect_bottom);
-            }
 
             if state.textscript_vm.item != 0 {
                 batch.add_rect((state.canvas_size.0 / 2.0 - 40.0).floor(), state.canvas_size.1 - 112.0,
@@ -455,14 +945,17 @@ impl GameScene {
 
         if state.textscript_vm.face != 0 {
             let batch = state.texture_set.get_or_load_batch(ctx, &state.constants, "Face")?;
-
-            batch.add_rect(left_pos + 14.0, top_pos + 8.0, &Rect::<usize>::new_size(
+            let rect = Rect::<usize>::new_size(
                 (state.textscript_vm.face as usize % 6) * 48,
                 (state.textscript_vm.face as usize / 6) * 48,
                 48, 48,
-            ));
+            );
 
-            batch.draw(ctx)?;
+            // mods sometimes refer to faces their sheet doesn't have, skip them instead of drawing garbage
+            if rect.bottom <= batch.height() {
+                batch.add_rect(left_pos + 14.0, top_pos + 8.0, &rect);
+                batch.draw(ctx)?;
+            }
         }
 
         if state.textscript_vm.item != 0 {
@@ -495,83 +988,98 @@ impl GameScene {
 
         let text_offset = if state.textscript_vm.face == 0 { 0.0 } else { 56.0 };
 
-        if !state.textscript_vm.line_1.is_empty() {
-            state.font.draw_text(state.textscript_vm.line_1.iter().copied(), left_pos + text_offset + 14.0, top_pos + 10.0, &state.constants, &mut state.texture_set, ctx)?;
-        }
-
-        if !state.textscript_vm.line_2.is_empty() {
-            state.font.draw_text(state.textscript_vm.line_2.iter().copied(), left_pos + text_offset + 14.0, top_pos + 10.0 + 16.0, &state.constants, &mut state.texture_set, ctx)?;
-        }
-
-        if !state.textscript_vm.line_3.is_empty() {
-            state.font.draw_text(state.textscript_vm.line_3.iter().copied(), left_pos + text_offset + 14.0, top_pos + 10.0 + 32.0, &state.constants, &mut state.texture_set, ctx)?;
+        // lines can contain prompts from engine messages, copied since drawing them needs the whole state
+        let lines = [state.textscript_vm.line_1.clone(), state.textscript_vm.line_2.clone(), state.textscript_vm.line_3.clone()];
+        for (i, line) in lines.iter().enumerate() {
+            if !line.is_empty() {
+                prompts::draw_text_scaled(line.iter().copied(), left_pos + text_offset + 14.0, top_pos + 10.0 + 16.0 * i as f32 * text_scale, text_scale, state, ctx)?;
+            }
         }
 
         Ok(())
     }
 
-    fn draw_tiles(&self, state: &mut SharedGameState, ctx: &mut Context, layer: TileLayer) -> GameResult {
-        let tex = match layer {
-            TileLayer::Snack => "Npc/NpcSym",
+    fn draw_tiles(&self, state: &mut SharedGameState, ctx: &mut Context, frame: &Frame, pass: TilePass) -> GameResult {
+        let tex = match pass {
+            TilePass::Snack => "Npc/NpcSym",
             _ => &self.tex_tileset_name,
         };
+        let mut mesh = self.tile_mesh.borrow_mut();
+        mesh.update(&self.stage.map, &state.constants.world.tile_passes);
+
+        let snack_rect = state.constants.world.snack_rect;
         let batch = state.texture_set.get_or_load_batch(ctx, &state.constants, tex)?;
-        let mut rect = Rect::<usize>::new(0, 0, 16, 16);
 
-        let tile_start_x = clamp(self.frame.x / 0x200 / 16, 0, self.stage.map.width as isize) as usize;
-        let tile_start_y = clamp(self.frame.y / 0x200 / 16, 0, self.stage.map.height as isize) as usize;
-        let tile_end_x = clamp((self.frame.x / 0x200 + 8 + state.canvas_size.0 as isize) / 16 + 1, 0, self.stage.map.width as isize) as usize;
-        let tile_end_y = clamp((self.frame.y / 0x200 + 8 + state.canvas_size.1 as isize) / 16 + 1, 0, self.stage.map.height as isize) as usize;
+        let tile_start_x = clamp(frame.x / 0x200 / 16, 0, self.stage.map.width as isize) as usize;
+        let tile_start_y = clamp(frame.y / 0x200 / 16, 0, self.stage.map.height as isize) as usize;
+        let tile_end_x = clamp((frame.x / 0x200 + 8 + state.canvas_size.0 as isize) / 16 + 1, 0, self.stage.map.width as isize) as usize;
+        let tile_end_y = clamp((frame.y / 0x200 + 8 + state.canvas_size.1 as isize) / 16 + 1, 0, self.stage.map.height as isize) as usize;
 
-        if layer == TileLayer::Snack {
-            rect = state.constants.world.snack_rect;
+        for (x, y, tile) in mesh.tiles(pass, tile_start_x..tile_end_x, tile_start_y..tile_end_y) {
+            let rect = match pass {
+                TilePass::Snack => snack_rect,
+                _ => tile_rect(tile),
+            };
+
+            batch.add_rect((x as f32 * 16.0 - 8.0) - (frame.x / 0x200) as f32,
+                           (y as f32 * 16.0 - 8.0) - (frame.y / 0x200) as f32, &rect);
         }
 
+        batch.draw(ctx)?;
+
+        Ok(())
+    }
+
+    /// Colors the visible tiles by their attribute, read every frame so edits from the debugger show up right away.
+    fn draw_attribute_overlay(&self, state: &mut SharedGameState, ctx: &mut Context, frame: &Frame) -> GameResult {
+        let map = &self.stage.map;
+        let tile_start_x = clamp(frame.x / 0x200 / 16, 0, map.width as isize) as usize;
+        let tile_start_y = clamp(frame.y / 0x200 / 16, 0, map.height as isize) as usize;
+        let tile_end_x = clamp((frame.x / 0x200 + 8 + state.canvas_size.0 as isize) / 16 + 1, 0, map.width as isize) as usize;
+        let tile_end_y = clamp((frame.y / 0x200 + 8 + state.canvas_size.1 as isize) / 16 + 1, 0, map.height as isize) as usize;
+
+        let mut primitives = Primitives::new();
         for y in tile_start_y..tile_end_y {
             for x in tile_start_x..tile_end_x {
-                let tile = *self.stage.map.tiles
-                    .get((y * self.stage.map.width) + x)
-                    .unwrap();
-
-                match layer {
-                    TileLayer::Background => {
-                        if self.stage.map.attrib[tile as usize] >= 0x20 {
-                            continue;
-                        }
-
-                        rect.left = (tile as usize % 16) * 16;
-                        rect.top = (tile as usize / 16) * 16;
-                        rect.right = rect.left + 16;
-                        rect.bottom = rect.top + 16;
-                    }
-                    TileLayer::Foreground => {
-                        let attr = self.stage.map.attrib[tile as usize];
+                let attrib = map.get_attribute(x as isize, y as isize);
+                let color = match attrib {
+                    0x42 | 0x62 => [1.0, 0.0, 0.0, 0.4],
+                    0x05 | 0x41 | 0x43 | 0x61 => [0.0, 0.0, 1.0, 0.4],
+                    0x44 | 0x46 => [1.0, 0.0, 1.0, 0.4],
+                    0x50..=0x57 | 0x70..=0x77 => [0.0, 1.0, 0.0, 0.4],
+                    0x80..=0x83 | 0xa0..=0xa3 => [1.0, 1.0, 0.0, 0.4],
+                    0x02 | 0x60 => [0.0, 1.0, 1.0, 0.3],
+                    _ => { continue; }
+                };
 
-                        if attr < 0x40 || attr >= 0x80 || attr == 0x43 {
-                            continue;
-                        }
+                let rect = Rect::new_size(x as isize * 16 - 8 - frame.x / 0x200,
+                                          y as isize * 16 - 8 - frame.y / 0x200, 16, 16);
+                primitives.rect(rect, color);
 
-                        rect.left = (tile as usize % 16) * 16;
-                        rect.top = (tile as usize / 16) * 16;
-                        rect.right = rect.left + 16;
-                        rect.bottom = rect.top + 16;
-                    }
-                    TileLayer::Snack => {
-                        if self.stage.map.attrib[tile as usize] != 0x43 {
-                            continue;
-                        }
-                    }
-                    _ => {}
+                // surface the physics collide with
+                if let 0x50..=0x57 | 0x70..=0x77 = attrib {
+                    let (left, right) = SLOPE_SURFACES[(attrib & 7) as usize];
+                    let (x, y) = (rect.left as f32, rect.top as f32);
+                    primitives.line((x, y + left.min(15) as f32), (x + 15.0, y + right.min(15) as f32), [0.0, 0.4, 0.0, 1.0]);
                 }
-
-                batch.add_rect((x as f32 * 16.0 - 8.0) - (self.frame.x / 0x200) as f32,
-                               (y as f32 * 16.0 - 8.0) - (self.frame.y / 0x200) as f32, &rect);
             }
         }
 
-        batch.draw(ctx)?;
+        state.texture_set.draw_primitives(ctx, &primitives)
+    }
 
-        Ok(())
+    /// Rumbles with the intensity falling off with the distance from the center of the screen.
+    fn rumble_at(&self, x: isize, y: isize, intensity: f32, state: &mut SharedGameState) {
+        let (width, height) = state.canvas_size;
+        let center_x = self.frame.x as f32 / 512.0 + width / 2.0;
+        let center_y = self.frame.y as f32 / 512.0 + height / 2.0;
+        let dist_x = x as f32 / 512.0 - center_x;
+        let dist_y = y as f32 / 512.0 - center_y;
+
+        let falloff = 1.0 - (dist_x * dist_x + dist_y * dist_y).sqrt() / width.max(height);
+        if falloff > 0.0 {
+            state.rumble(intensity * falloff, intensity * falloff * 0.5, 120);
+        }
     }
 
     pub fn tick_npc_bullet_collissions(&mut self, state: &mut SharedGameState) {
@@ -621,7 +1129,7 @@ impl GameScene {
                             }
 
                             if self.player.cond.alive() && npc.npc_flags.event_when_killed() {
-                                state.textscript_vm.start_script(npc.event_num);
+                                state.textscript_vm.start_event(npc.event_num, EventTrigger::Forced, &mut state.control_flags);
                             } else {
                                 npc.cond.set_explode_die(true);
                             }
@@ -656,6 +1164,14 @@ impl GameScene {
         }
 
         if !dead_npcs.is_empty() {
+            for id in dead_npcs.iter() {
+                if let Some(npc_cell) = self.npc_map.npcs.get(id) {
+                    let npc = npc_cell.borrow();
+                    self.rumble_at(npc.x, npc.y, 0.5, state);
+                    state.stats.record(StatEvent::EnemyDefeated(npc.npc_type));
+                }
+            }
+
             self.npc_map.process_dead_npcs(&dead_npcs, state);
             self.npc_map.garbage_collect();
         }
@@ -663,9 +1179,20 @@ impl GameScene {
 }
 
 impl Scene for GameScene {
+    fn tps(&self, state: &SharedGameState) -> Option<u32> {
+        if state.challenge.is_some() || state.boss_rush.is_some() {
+            Some(CHALLENGE_TPS)
+        } else {
+            None
+        }
+    }
+
     fn init(&mut self, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
         state.textscript_vm.set_scene_script(self.stage.load_text_script(&state.base_path, ctx)?);
         state.textscript_vm.suspend = false;
+        // left open by a <TRA from an item event
+        state.textscript_vm.scripts.inventory_active = false;
+        log_sink::set_stage(Some(self.stage_id));
 
         let npcs = self.stage.load_npcs(&state.base_path, ctx)?;
         for npc_data in npcs.iter() {
@@ -685,13 +1212,49 @@ impl Scene for GameScene {
             }
         }
 
-        state.npc_table.tex_npc1_name = ["Npc/", &self.stage.data.npc1.filename()].join("");
-        state.npc_table.tex_npc2_name = ["Npc/", &self.stage.data.npc2.filename()].join("");
+        state.stats.enabled = !self.replay.is_playing() && self.repro.is_none() && !state.temporary_profile;
+        state.stats.enter_stage(&self.stage.data.map);
+        state.texture_set.begin_stage();
+
+        if let Some(effect) = state.constants.stage_effect.stage_effects.get(self.stage.data.map.as_str()) {
+            self.stage_effect.set_effect(*effect);
+        }
+        state.ambient_force = match state.constants.stage_effect.stage_currents.get(self.stage.data.map.as_str()) {
+            Some(&force) if state.settings.allow_stage_currents() => force,
+            _ => (0, 0),
+        };
+
+        state.npc_table.set_stage_sheets(&self.stage.data.npc1.filename(), &self.stage.data.npc2.filename());
+        // loaded right away, so a missing sheet shows up when entering the stage instead of on the first NPC drawn
+        for &sheet in [StageSheet::Npc1, StageSheet::Npc2].iter() {
+            let name = state.npc_table.sheet_texture_name(Some(NPCSheet::Stage(sheet))).to_owned();
+            state.texture_set.get_or_load_batch(ctx, &state.constants, &name)?;
+        }
+
+        #[cfg(debug_assertions)]
+        self.validate_npc_rects(state, ctx)?;
 
         self.player.target_x = self.player.x;
         self.player.target_y = self.player.y;
         self.frame.immediate_update(state, &self.player, &self.stage);
 
+        if let Some(save_state) = self.pending_save_state.take() {
+            save_state.apply(self, state, ctx)?;
+        }
+        self.entry_position = (self.player.x, self.player.y);
+
+        if let Some(scenario) = self.pending_scenario.take() {
+            scenario.restore_entities(self, state);
+        }
+
+        self.update_rich_presence(state);
+
+        if self.stage.data.name.is_empty() {
+            graphics::set_window_title(ctx, WINDOW_TITLE);
+        } else {
+            graphics::set_window_title(ctx, &format!("{} \u{2014} {}", WINDOW_TITLE, self.stage.data.name));
+        }
+
         //self.inventory.add_weapon(WeaponType::PolarStar, 0);
         //self.inventory.add_xp(120, state);
         //self.player.equip.set_booster_2_0(true);
@@ -699,73 +1262,131 @@ impl Scene for GameScene {
     }
 
     fn tick(&mut self, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
-        state.update_key_trigger();
-
-        if self.tick == 0 || state.control_flags.flag_x01() {
-            self.player.current_weapon = {
-                if let Some(weapon) = self.inventory.get_current_weapon_mut() {
-                    weapon.wtype as u8
-                } else {
-                    0
-                }
-            };
-            self.player.tick(state, ())?;
-            for npc_id in self.npc_map.npc_ids.iter() {
-                if let Some(npc_cell) = self.npc_map.npcs.get_mut(npc_id) {
-                    let mut npc = npc_cell.borrow_mut();
+        if state.warp_back_requested && state.settings.allow_stage_history_warp() && !self.replay.is_active() && state.boss_rush.is_none() {
+            state.warp_back_requested = false;
+            self.warp_back(state);
+            return Ok(());
+        }
 
-                    if npc.cond.alive() {
-                        npc.tick(state, &mut self.player)?;
-                    }
-                }
+        if let Some(action) = state.quick_save_action.take() {
+            if let Err(e) = self.handle_quick_save(action, state, ctx) {
+                log::warn!("Quick {:?} failed: {}", action, e);
             }
-            self.npc_map.process_npc_changes(state);
-            self.npc_map.process_npc_changes(state);
-
-            self.player.flags.0 = 0;
 
-            self.player.tick_map_collisions(state, &mut self.stage);
-            self.player.tick_npc_collisions(state, &mut self.npc_map, &mut self.inventory);
-            self.npc_map.process_npc_changes(state);
-            for npc_id in self.npc_map.npc_ids.iter() {
-                if let Some(npc_cell) = self.npc_map.npcs.get_mut(npc_id) {
-                    let mut npc = npc_cell.borrow_mut();
+            if state.next_scene.is_some() {
+                return Ok(());
+            }
+        }
 
-                    if npc.cond.alive() && !npc.npc_flags.ignore_solidity() {
-                        npc.flags.0 = 0;
-                        npc.tick_map_collisions(state, &mut self.stage);
+        match self.replay.tick(&mut state.key_state, &self.player) {
+            ReplayStatus::Running => {}
+            status if self.repro.is_some() => {
+                log::info!("Scenario ended: {:?}", status);
+                self.replay = ReplayMode::None;
+
+                if let Some(ReproRun { dump: Some(path) }) = self.repro.take() {
+                    if status == ReplayStatus::Finished {
+                        ReproDump::new(self, state).save(&path)?;
+                        log::info!("Final state written to {:?}", path);
+                        event::quit(ctx);
+                        return Ok(());
                     }
                 }
             }
-            self.npc_map.process_npc_changes(state);
-            self.tick_npc_bullet_collissions(state);
+            status => {
+                log::info!("Demo playback ended: {:?}", status);
+                state.next_scene = Some(Box::new(TitleScene::new()));
+                return Ok(());
+            }
+        }
 
-            state.tick_carets();
-            self.bullet_manager.tick_bullets(state, &self.player, &mut self.stage);
+        state.update_key_trigger();
 
-            self.frame.update(state, &self.player, &self.stage);
+        let paused = self.is_paused(state);
+        if paused && !self.paused {
+            gamepad::stop_rumble(ctx);
         }
+        self.paused = paused;
 
-        if state.control_flags.control_enabled() {
-            if let Some(weapon) = self.inventory.get_current_weapon_mut() {
-                weapon.shoot_bullet(&self.player, &mut self.bullet_manager, state);
+        if self.recovery_prompt.is_some() {
+            return self.tick_recovery_prompt(state, ctx);
+        }
+
+        if let Some(run) = state.challenge.as_mut() {
+            if run.finished {
+                let scene = ChallengeResultScene::new(run.challenge.clone(), run.ticks);
+                state.next_scene = Some(Box::new(scene));
+                return Ok(());
             }
 
-            // update health bar
-            if self.life_bar < self.player.life as u16 {
-                self.life_bar = self.player.life as u16;
+            if state.key_trigger.menu() {
+                // quitting discards the temporary profile
+                let scene = ChallengeMenuScene::new(state, ctx)?;
+                state.next_scene = Some(Box::new(scene));
+                return Ok(());
             }
 
-            if self.life_bar > self.player.life as u16 {
-                self.life_bar_counter += 1;
-                if self.life_bar_counter > 30 {
-                    self.life_bar -= 1;
+            run.ticks += 1;
+        }
+
+        // the scene it was started from doesn't tick it while the arena is being set up
+        if self.stage.generated {
+            if let Some(mut rush) = state.boss_rush.take() {
+                if state.key_trigger.menu() {
+                    rush.leave_requested = true;
+                }
+
+                if rush.tick(self, state) {
+                    state.boss_rush = Some(rush);
+                } else {
+                    rush.finish(state, ctx)?;
+                    return Ok(());
                 }
+            }
+        }
+
+        let mut rewind = mem::replace(&mut self.rewind, RewindBuffer::new());
+        let rewinding = rewind.tick(self, state, ctx);
+        self.rewind = rewind;
+        if rewinding? {
+            return Ok(());
+        }
+
+        let was_open = self.inventory_ui.active;
+        let mut inventory_ui = mem::replace(&mut self.inventory_ui, InventoryUI::new());
+        inventory_ui.tick(self, state);
+        self.inventory_ui = inventory_ui;
+        // the key closing it doesn't shoot as well
+        if was_open || self.inventory_ui.active {
+            // the world stands still, only the ArmsItem.tsc events run
+            TextScriptVM::run(state, self, ctx)?;
+            return Ok(());
+        }
+
+        // updated even during cutscenes, so the gun shows up as soon as it's given
+        self.player.current_weapon = {
+            if let Some(weapon) = self.inventory.get_current_weapon_mut() {
+                weapon.wtype as u8
             } else {
-                self.life_bar_counter = 0;
+                0
             }
+        };
+
+        // <PRI stops the world, <KEY only the player's input
+        let gates = state.control_flags.tick_gates(&state.settings);
+        self.tick_gated(state, gates)?;
+        // the light map needs the context, it's updated once the world has moved
+        if self.tick == 0 || gates.world {
+            let emitters = LightEmitters {
+                player: &self.player,
+                npc_map: &self.npc_map,
+                bullet_manager: &self.bullet_manager,
+            };
+            self.lighting.tick(&self.stage.data.map, emitters, &self.frame, state, ctx)?;
         }
 
+        self.tick_boss_life_bar(state);
+
         if self.map_name_counter > 0 {
             self.map_name_counter -= 1;
         }
@@ -776,62 +1397,296 @@ impl Scene for GameScene {
             self.weapon_x_pos += 2;
         }
 
-        match state.fade_state {
-            FadeState::FadeOut(tick, direction) if tick < 15 => {
-                state.fade_state = FadeState::FadeOut(tick + 1, direction);
-            }
-            FadeState::FadeOut(tick, _) if tick == 15 => {
-                state.fade_state = FadeState::Hidden;
-            }
-            FadeState::FadeIn(tick, direction) if tick > -15 => {
-                state.fade_state = FadeState::FadeIn(tick - 1, direction);
-            }
-            FadeState::FadeIn(tick, _) if tick == -15 => {
-                state.fade_state = FadeState::Visible;
+        state.fade_state = state.fade_state.tick();
+
+        state.settings.total_ticks_played = state.settings.total_ticks_played.saturating_add(1);
+        // the early returns above are what doesn't count as play time, see `PlayRecord::play_time`
+        let tick_duration = state.tick_duration();
+        if let Some(record) = state.play_record.as_mut() {
+            record.play_time += tick_duration;
+        }
+        state.stats.record(StatEvent::Tick);
+        if state.stats.check_pending() {
+            let progress = Progress {
+                map: &self.stage.data.map,
+                max_life: self.player.max_life,
+                play_time: state.play_record.map(|record| record.play_time),
+            };
+            for name in state.stats.check_achievements(&progress) {
+                state.notifications.push(format!("Achievement unlocked: {}", name));
             }
-            _ => {}
+        }
+        // every 15 seconds
+        if self.tick % 750 == 749 {
+            self.update_rich_presence(state);
         }
 
         TextScriptVM::run(state, self, ctx)?;
+        self.tick_watchdog(state);
         self.tick = self.tick.wrapping_add(1);
         Ok(())
     }
 
     fn draw(&self, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
-        self.draw_background(state, ctx)?;
-        self.draw_tiles(state, ctx, TileLayer::Background)?;
-        for npc_id in self.npc_map.npc_ids.iter() {
-            if let Some(npc_cell) = self.npc_map.npcs.get(npc_id) {
-                npc_cell.borrow().draw(state, ctx, &self.frame)?;
+        let frame = &self.frame;
+        let mut pass: RenderPass<SharedGameState, Context> = RenderPass::new();
+
+        pass.add(DrawLayer::Background, |state, ctx| self.draw_background(state, ctx, frame));
+        pass.add(DrawLayer::BackgroundTiles, |state, ctx| self.draw_tiles(state, ctx, frame, TilePass::Background));
+        pass.add(DrawLayer::CaretsBehind, |state, ctx| self.draw_carets(state, ctx, frame, CaretLayer::Behind));
+        pass.add(DrawLayer::NPCs, |state, ctx| {
+            for npc_id in self.npc_map.npc_ids.iter() {
+                if let Some(npc_cell) = self.npc_map.npcs.get(npc_id) {
+                    let npc = npc_cell.borrow();
+                    if frame.is_visible(state.canvas_size, &display_rect(npc.x, npc.y, &npc.display_bounds)) {
+                        npc.draw(state, ctx, frame)?;
+                    }
+                }
             }
+            Ok(())
+        });
+        pass.add(DrawLayer::Bullets, |state, ctx| self.draw_bullets(state, ctx, frame));
+        pass.add(DrawLayer::Player, |state, ctx| self.player.draw(state, ctx, frame));
+        pass.add(DrawLayer::ForegroundTiles, |state, ctx| self.draw_tiles(state, ctx, frame, TilePass::Foreground));
+        pass.add(DrawLayer::ForegroundTiles, |state, ctx| self.draw_tiles(state, ctx, frame, TilePass::Snack));
+        pass.add(DrawLayer::CaretsFront, |state, ctx| self.draw_carets(state, ctx, frame, CaretLayer::Front));
+
+        if self.attribute_overlay {
+            pass.add(DrawLayer::ForegroundTiles, |state, ctx| self.draw_attribute_overlay(state, ctx, frame));
         }
-        self.draw_bullets(state, ctx)?;
-        self.player.draw(state, ctx, &self.frame)?;
-        self.draw_tiles(state, ctx, TileLayer::Foreground)?;
-        self.draw_tiles(state, ctx, TileLayer::Snack)?;
-        self.draw_carets(state, ctx)?;
-        self.draw_black_bars(state, ctx)?;
+        pass.add(DrawLayer::Weather, |_, ctx| self.stage_effect.draw(ctx, frame));
+        pass.add(DrawLayer::Lighting, |_, ctx| self.lighting.draw(ctx, frame));
+        pass.add(DrawLayer::Flash, |state, ctx| self.flash.draw(state, ctx, frame));
+        pass.add(DrawLayer::BlackBars, |state, ctx| self.draw_black_bars(state, ctx));
 
         if state.control_flags.control_enabled() {
-            self.draw_hud(state, ctx)?;
+            pass.add(DrawLayer::CaretsFront, |state, ctx| self.draw_booster_fuel(state, ctx, frame));
+            pass.add(DrawLayer::HUD, |state, ctx| self.draw_hud(state, ctx));
         }
+        pass.add(DrawLayer::HUD, |state, ctx| self.inventory_ui.draw(self, state, ctx));
+
+        pass.add(DrawLayer::Fade, |state, ctx| self.draw_fade(state, ctx));
 
-        self.draw_fade(state, ctx)?;
         if self.map_name_counter > 0 {
-            let width = state.font.text_width(self.stage.data.name.chars(), &state.constants);
-            state.font.draw_text(self.stage.data.name.chars(),
-                                 ((state.canvas_size.0 - width) / 2.0).floor(), 80.0,
-                                 &state.constants, &mut state.texture_set, ctx)?;
+            pass.add(DrawLayer::MapName, |state, ctx| {
+                let width = state.font.text_width(self.stage.data.name.chars(), &state.constants);
+                state.font.draw_text(self.stage.data.name.chars(),
+                                     ((state.canvas_size.0 - width) / 2.0).floor(), 80.0,
+                                     &state.constants, &mut state.texture_set, ctx)
+            });
         }
 
-        self.draw_text_boxes(state, ctx)?;
+        pass.add(DrawLayer::TextBox, |state, ctx| self.draw_text_boxes(state, ctx));
 
-        self.draw_number(state.canvas_size.0 - 8.0, 8.0, timer::fps(ctx) as usize, Alignment::Right, state, ctx)?;
-        Ok(())
+        if self.replay.is_playing() && self.repro.is_none() && (self.tick / 25).is_multiple_of(2) {
+            pass.add(DrawLayer::Overlay, |state, ctx| {
+                let text = "DEMO — press any key";
+                let width = state.font.text_width(text.chars(), &state.constants);
+                state.font.draw_text(text.chars(), ((state.canvas_size.0 - width) / 2.0).floor(), state.canvas_size.1 - 24.0,
+                                     &state.constants, &mut state.texture_set, ctx)
+            });
+        }
+
+        if self.recovery_prompt.is_some() {
+            pass.add(DrawLayer::Overlay, |state, ctx| self.draw_recovery_prompt(state, ctx));
+        }
+
+        pass.add(DrawLayer::Overlay, |state, ctx| {
+            self.draw_number(state.hud_rect().right - HUD_SAFE_MARGIN, 8.0, timer::fps(ctx) as usize, Alignment::Right, state, ctx)
+        });
+
+        pass.draw(state, ctx, |space, state, ctx| space.enter(state, ctx))
     }
 
     fn debug_overlay_draw(&mut self, components: &mut Components, state: &mut SharedGameState, ctx: &mut Context, ui: &mut imgui::Ui) -> GameResult {
+        components.perf_hud.set_entity_counts(self.npc_map.npcs.len(), self.bullet_manager.bullets.len());
         components.live_debugger.run_ingame(self, state, ctx, ui)?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+use crate::map::Map;
+
+#[test]
+fn test_tick_gated() {
+    use crate::caret::CaretType;
+    use crate::settings::CompatMode;
+    use crate::weapon::WeaponType;
+
+    let mut state = SharedGameState::for_tests();
+    let map = Map { width: 8, height: 8, tiles: vec![0; 64], attrib: [0; 0x100], revision: 0 };
+    let mut scene = GameScene::for_tests(&mut state, 0, Stage::for_tests(map));
+    scene.inventory.add_weapon(WeaponType::PolarStar, 0);
+    scene.inventory.add_weapon(WeaponType::MachineGun, 100);
+    let mut npc = NPCMap::create_npc(3, &state.npc_table);
+    npc.cond.set_alive(true);
+    let npc_id = scene.npc_map.spawn(npc, 1).unwrap();
+    // past the first tick, which always moves the world
+    scene.tick = 1;
+
+    // whether the falling player, the NPC, a caret and the weapon switch advanced in a tick
+    let step = |scene: &mut GameScene, state: &mut SharedGameState| {
+        state.carets.clear();
+        state.create_caret(0, 0, CaretType::Zzz, Direction::Left);
+        let player_y = scene.player.y;
+        let npc_counter = scene.npc_map.npcs[&npc_id].borrow().action_counter2;
+        let weapon = scene.inventory.get_current_weapon_idx();
+        state.key_trigger.set_weapon_next(true);
+
+        let gates = state.control_flags.tick_gates(&state.settings);
+        scene.tick_gated(state, gates).unwrap();
+
+        (scene.player.y != player_y,
+         scene.npc_map.npcs[&npc_id].borrow().action_counter2 != npc_counter,
+         state.carets[0].x != 0,
+         scene.inventory.get_current_weapon_idx() != weapon)
+    };
+
+    // <FRE
+    state.control_flags.free();
+    assert_eq!(step(&mut scene, &mut state), (true, true, true, true));
+
+    // <KEY, everything but the player's input
+    state.control_flags.freeze_input();
+    assert_eq!(step(&mut scene, &mut state), (true, true, true, false));
+
+    // <PRI, only the carets
+    state.control_flags.freeze_all();
+    assert_eq!(step(&mut scene, &mut state), (false, false, true, false));
+
+    state.settings.compat_mode = CompatMode::Vanilla;
+    assert_eq!(step(&mut scene, &mut state), (false, false, false, false));
+}
+
+#[test]
+fn test_paused() {
+    let mut state = SharedGameState::for_tests();
+    let map = Map { width: 8, height: 8, tiles: vec![0; 64], attrib: [0; 0x100], revision: 0 };
+    let mut scene = GameScene::for_tests(&mut state, 0, Stage::for_tests(map));
+    state.control_flags.free();
+    assert!(!scene.is_paused(&state));
+
+    scene.recovery_prompt = Some(SoftlockReason::OutOfBounds);
+    assert!(scene.is_paused(&state));
+    scene.recovery_prompt = None;
+
+    scene.inventory_ui.active = true;
+    assert!(scene.is_paused(&state));
+    scene.inventory_ui.active = false;
+
+    // <PRI in a running event is a cutscene, not a pause
+    state.control_flags.freeze_all();
+    state.textscript_vm.state = TextScriptExecutionState::Running(0, 0);
+    assert!(!scene.is_paused(&state));
+
+    // frozen from the debugger
+    state.textscript_vm.state = TextScriptExecutionState::Ended;
+    assert!(scene.is_paused(&state));
+}
+
+#[test]
+fn test_tps_override() {
+    use crate::challenge::{Challenge, ChallengeRun};
+    use crate::settings::CompatMode;
+
+    let mut state = SharedGameState::for_tests();
+    let map = Map { width: 8, height: 8, tiles: vec![0; 64], attrib: [0; 0x100], revision: 0 };
+    let scene = GameScene::for_tests(&mut state, 0, Stage::for_tests(map));
+    state.settings.compat_mode = CompatMode::Enhanced;
+    state.settings.tps_60 = true;
+
+    state.reset_tps(&scene);
+    assert_eq!(state.tps(), 60);
+
+    let challenge = Challenge { id: str!("test"), name: str!("Test"), stage: 0, x: 0, y: 0, start_event: 0, end_event: 0 };
+    state.challenge = Some(ChallengeRun { challenge, ticks: 0, finished: false });
+    state.reset_tps(&scene);
+    assert_eq!(state.tps(), CHALLENGE_TPS);
+
+    state.challenge = None;
+    state.reset_tps(&scene);
+    assert_eq!(state.tps(), 60);
+}
+
+#[test]
+fn test_flawless_frog() {
+    use crate::npc::boss::StageBoss;
+
+    let mut state = SharedGameState::for_tests();
+    let map = Map { width: 8, height: 8, tiles: vec![0; 64], attrib: [0; 0x100], revision: 0 };
+    let mut stage = Stage::for_tests(map);
+    stage.data.boss_no = 2;
+    let mut scene = GameScene::for_tests(&mut state, 0, stage);
+    state.stats.enabled = true;
+
+    let mut fight = |damage: u16, state: &mut SharedGameState| {
+        scene.boss = StageBoss::new(2);
+        // what <BSL0000 does
+        scene.boss_life_bar.attach(BossTarget::StageBoss, scene.boss.life());
+        state.stats.record(StatEvent::BossFightStarted);
+
+        if damage > 0 {
+            state.stats.record(StatEvent::DamageTaken(damage));
+        }
+        scene.tick_boss_life_bar(state);
+        scene.boss.alive = false;
+        scene.tick_boss_life_bar(state);
+        assert_eq!(scene.boss_life_bar.target, None);
+
+        let progress = Progress { map: "Gum", max_life: 3, play_time: None };
+        state.stats.check_achievements(&progress)
+    };
+
+    assert!(fight(2, &mut state).is_empty());
+    assert_eq!(fight(0, &mut state), vec!["Flawless Frog"]);
+}
+
+#[test]
+fn test_boss_death_flash() {
+    use crate::flash::FlashState;
+    use crate::npc::boss::StageBoss;
+
+    let mut state = SharedGameState::for_tests();
+    let map = Map { width: 8, height: 8, tiles: vec![0; 64], attrib: [0; 0x100], revision: 0 };
+    let mut scene = GameScene::for_tests(&mut state, 0, Stage::for_tests(map));
+    state.settings.accessibility.reduced_flash = true;
+
+    for (boss_no, flashes) in [(2, false), (4, true), (9, true)] {
+        scene.flash = Flash::new();
+        scene.boss = StageBoss::new(boss_no);
+        scene.boss.x = 0x4000;
+        scene.boss_life_bar.attach(BossTarget::StageBoss, scene.boss.life());
+        scene.boss.alive = false;
+        scene.tick_boss_life_bar(&mut state);
+
+        let flash = scene.flash.state;
+        assert_eq!(flash == FlashState::Explosion(0x4000, 0, 0, 0, 0), flashes, "boss {}", boss_no);
+    }
+}
+
+#[test]
+fn test_text_box_during_quake() {
+    use crate::render::DrawSpace;
+
+    let mut state = SharedGameState::for_tests();
+    let map = Map { width: 40, height: 30, tiles: vec![0; 1200], attrib: [0; 0x100], revision: 0 };
+    let mut scene = GameScene::for_tests(&mut state, 0, Stage::for_tests(map));
+    scene.player.target_x = 0x14000;
+    scene.player.target_y = 0xf000;
+    scene.frame.update(&mut state, &scene.player, &scene.stage);
+    let (frame_x, frame_y) = (scene.frame.x, scene.frame.y);
+    let text_box = GameScene::text_box_rect(&state);
+
+    // <QUA
+    state.quake_counter = 30;
+    let mut shaken = false;
+    while state.quake_counter > 0 {
+        scene.frame.update(&mut state, &scene.player, &scene.stage);
+        shaken |= (scene.frame.x, scene.frame.y) != (frame_x, frame_y);
+        assert_eq!(GameScene::text_box_rect(&state), text_box);
+    }
+
+    assert!(shaken);
+    assert_eq!(DrawLayer::TextBox.space(), DrawSpace::Canvas);
+}
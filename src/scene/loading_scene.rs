@@ -1,20 +1,224 @@
-use crate::ggez::{Context, filesystem, GameResult};
-use crate::scene::game_scene::GameScene;
+use std::io::{Cursor, Read};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::thread;
+use std::time::Instant;
+
+use crate::common::Rect;
+use crate::container::data_fingerprint;
+use crate::ggez::{Context, filesystem, GameError, GameResult};
+use crate::ggez::GameError::ResourceLoadError;
+use crate::npc::NPCTable;
+use crate::render::primitives::Primitives;
+use crate::repro::Scenario;
+use crate::scene::error_scene::ErrorScene;
 use crate::scene::Scene;
+use crate::scene::title_scene::TitleScene;
 use crate::SharedGameState;
+use crate::sound::SoundManager;
+use crate::str;
 use crate::stage::StageData;
-use crate::text_script::{TextScript, TextScriptExecutionState};
-use crate::common::FadeState;
-use crate::npc::NPCTable;
+use crate::text_script::TextScript;
+use crate::texture_set::DecodedTexture;
+
+/// Textures used everywhere in the game, decoded in advance so the first frames don't stutter.
+static PRELOADED_TEXTURES: [&str; 5] = ["MyChar", "Caret", "TextBox", "ArmsImage", "Fade"];
+/// Sound effects rendered by one task, the first one has the common ones.
+const SFX_PER_TASK: usize = 16;
+
+enum Asset {
+    Texture(DecodedTexture),
+    NPCTable(NPCTable),
+    HeadScript(TextScript),
+    InventoryScript(TextScript),
+    /// Rendered PixTone sound effects, by id.
+    SoundEffects(Vec<(u8, Vec<i16>)>),
+}
+
+/// Result of a loading task, along with the name of the asset shown if it fails.
+type TaskResult = (String, GameResult<Asset>);
+type Task = Box<dyn FnOnce() -> GameResult<Asset> + Send>;
 
 pub struct LoadingScene {
     tick: usize,
+    total: usize,
+    /// Number of tasks finished by the workers, feeds the progress bar.
+    progress: Arc<AtomicUsize>,
+    received: usize,
+    rx: Option<Receiver<TaskResult>>,
+    failures: Vec<(String, GameError)>,
+    /// Started instead of the title screen, along with the path of the final state dump.
+    repro: Option<(Scenario, Option<PathBuf>)>,
+    /// When the loading has started, for the startup timings.
+    started: Option<Instant>,
 }
 
 impl LoadingScene {
     pub fn new() -> Self {
         Self {
             tick: 0,
+            total: 0,
+            progress: Arc::new(AtomicUsize::new(0)),
+            received: 0,
+            rx: None,
+            failures: Vec::new(),
+            repro: None,
+            started: None,
+        }
+    }
+
+    pub fn with_repro(scenario: Scenario, dump: Option<PathBuf>) -> Self {
+        Self {
+            repro: Some((scenario, dump)),
+            ..LoadingScene::new()
+        }
+    }
+
+    fn read_file(ctx: &mut Context, path: &str) -> GameResult<Vec<u8>> {
+        let mut buf = Vec::new();
+        filesystem::open(ctx, path)?.read_to_end(&mut buf)?;
+        Ok(buf)
+    }
+
+    /// Reads the files on the main thread (the filesystem needs the context)
+    /// and hands them over to the worker threads to decode.
+    fn start_tasks(&mut self, state: &mut SharedGameState, ctx: &mut Context) {
+        let (tx, rx) = mpsc::channel::<TaskResult>();
+        let mut tasks: Vec<(String, GameResult<Task>)> = Vec::new();
+
+        let font_pages = state.font.pages().to_vec();
+        for name in PRELOADED_TEXTURES.iter().map(|s| s.to_string()).chain(font_pages) {
+            let files = state.texture_set.read_texture(ctx, &state.constants, &name);
+            tasks.push((name, files.map(|files| -> Task {
+                Box::new(move || Ok(Asset::Texture(files.decode()?)))
+            })));
+        }
+
+        let npc_tbl_path = [&state.base_path, "/npc.tbl"].join("");
+        tasks.push((npc_tbl_path.clone(), LoadingScene::read_file(ctx, &npc_tbl_path).map(|buf| -> Task {
+            Box::new(move || Ok(Asset::NPCTable(NPCTable::load_from(Cursor::new(buf))?)))
+        })));
+
+        let head_path = [&state.base_path, "/Head.tsc"].join("");
+        let head_data = LoadingScene::read_file(ctx, &head_path);
+        tasks.push((head_path.clone(), head_data.map(|buf| -> Task {
+            Box::new(move || {
+                let script = TextScript::load_from(&buf[..]).map_err(|e| e.in_file(&head_path))?;
+                Ok(Asset::HeadScript(script))
+            })
+        })));
+
+        let arms_item_path = [&state.base_path, "/ArmsItem.tsc"].join("");
+        let arms_item_data = LoadingScene::read_file(ctx, &arms_item_path);
+        tasks.push((arms_item_path.clone(), arms_item_data.map(|buf| -> Task {
+            Box::new(move || {
+                let script = TextScript::load_from(&buf[..]).map_err(|e| e.in_file(&arms_item_path))?;
+                Ok(Asset::InventoryScript(script))
+            })
+        })));
+
+        let sfx_cap = state.sound_manager.sfx_memory_cap();
+        let sfx_bytes = Arc::new(AtomicUsize::new(0));
+        for ids in state.sound_manager.take_prerender().chunks(SFX_PER_TASK) {
+            let ids = ids.to_vec();
+            let sfx_bytes = sfx_bytes.clone();
+            tasks.push((str!("sound effects"), Ok(Box::new(move || {
+                let mut samples = Vec::with_capacity(ids.len());
+                for id in ids {
+                    // anything over the cap would be evicted right away
+                    if sfx_cap.is_some_and(|cap| sfx_bytes.load(Ordering::SeqCst) >= cap) {
+                        break;
+                    }
+
+                    let sample = SoundManager::render_sfx(id);
+                    sfx_bytes.fetch_add(sample.len() * 2, Ordering::SeqCst);
+                    samples.push((id, sample));
+                }
+                Ok(Asset::SoundEffects(samples))
+            }))));
+        }
+
+        self.total = tasks.len();
+        for (name, task) in tasks {
+            let tx = tx.clone();
+            let progress = self.progress.clone();
+
+            match task {
+                Ok(task) => {
+                    thread::spawn(move || {
+                        let result = task();
+                        progress.fetch_add(1, Ordering::SeqCst);
+                        let _ = tx.send((name, result));
+                    });
+                }
+                Err(e) => {
+                    progress.fetch_add(1, Ordering::SeqCst);
+                    let _ = tx.send((name, Err(e)));
+                }
+            }
+        }
+
+        self.rx = Some(rx);
+    }
+
+    /// Takes the decoded assets, textures are uploaded here since it has to happen on the main thread.
+    fn receive(&mut self, state: &mut SharedGameState, ctx: &mut Context) {
+        let rx = match &self.rx {
+            Some(rx) => rx,
+            None => { return; }
+        };
+
+        loop {
+            let (name, result) = match rx.try_recv() {
+                Ok(result) => result,
+                Err(TryRecvError::Empty) => { break; }
+                Err(TryRecvError::Disconnected) => {
+                    // a worker panicked
+                    if self.received < self.total {
+                        self.failures.push((str!("worker thread"), ResourceLoadError(str!("A loading thread crashed."))));
+                        self.received = self.total;
+                    }
+                    break;
+                }
+            };
+            self.received += 1;
+
+            let result = result.and_then(|asset| match asset {
+                Asset::Texture(texture) => {
+                    let batch = state.texture_set.upload_texture(ctx, &state.constants, texture)?;
+                    state.texture_set.insert(&name, batch);
+                    Ok(())
+                }
+                Asset::NPCTable(table) => {
+                    state.npc_table = table;
+                    if state.settings.allow_bounds_fixes() {
+                        state.npc_table.apply_bounds_overrides(&state.constants.npc_bounds_overrides);
+                    }
+                    Ok(())
+                }
+                Asset::HeadScript(script) => {
+                    state.textscript_vm.set_global_script(script);
+                    Ok(())
+                }
+                Asset::InventoryScript(script) => {
+                    state.textscript_vm.set_inventory_script(script);
+                    Ok(())
+                }
+                Asset::SoundEffects(samples) => {
+                    for (id, sample) in samples {
+                        state.sound_manager.insert_sfx(id, sample);
+                    }
+                    Ok(())
+                }
+            });
+
+            if let Err(e) = result {
+                log::error!("Cannot load {}: {}", name, e);
+                self.failures.push((name, e));
+            }
         }
     }
 }
@@ -23,20 +227,42 @@ impl Scene for LoadingScene {
     fn tick(&mut self, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
         // deferred to let the loading image draw
         if self.tick == 1 {
+            self.started = Some(Instant::now());
             let stages = StageData::load_stage_table(ctx, &state.base_path)?;
             state.stages = stages;
-            let npc_table = NPCTable::load_from(filesystem::open(ctx, [&state.base_path, "/npc.tbl"].join(""))?)?;
-            state.npc_table = npc_table;
-            let head_script = TextScript::load_from(filesystem::open(ctx, [&state.base_path, "/Head.tsc"].join(""))?)?;
-            state.textscript_vm.set_global_script(head_script);
+            state.data_fingerprint = data_fingerprint(ctx, &state.base_path);
 
-            let mut next_scene = GameScene::new(state, ctx, 13)?;
-            next_scene.player.x = 10 * 16 * 0x200;
-            next_scene.player.y = 8 * 16 * 0x200;
-            state.fade_state = FadeState::Hidden;
-            state.textscript_vm.state = TextScriptExecutionState::Running(200, 0);
+            self.start_tasks(state, ctx);
+        }
+
+        if self.tick >= 1 {
+            self.receive(state, ctx);
+
+            if self.received == self.total {
+                self.rx = None;
+
+                if let Some(started) = self.started.take() {
+                    state.startup.assets = started.elapsed();
+                    state.startup.log();
+                }
+
+                if let Some((name, error)) = self.failures.drain(..).next() {
+                    let error = ResourceLoadError(format!("Cannot load {}: {}", name, error));
+                    state.next_scene = Some(Box::new(ErrorScene::new(error)));
+                    return Ok(());
+                }
+
+                // might be coming back from a previous game after switching mods
+                state.reset_game_state();
 
-            state.next_scene = Some(Box::new(next_scene));
+                if let Some((scenario, dump)) = self.repro.take() {
+                    let scene = scenario.create_scene(state, ctx, dump)?;
+                    state.next_scene = Some(Box::new(scene));
+                    return Ok(());
+                }
+
+                state.next_scene = Some(Box::new(TitleScene::new()));
+            }
         }
 
         self.tick += 1;
@@ -45,10 +271,24 @@ impl Scene for LoadingScene {
 
     fn draw(&self, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
         let batch = state.texture_set.get_or_load_batch(ctx, &state.constants, "Loading")?;
+        let (width, height) = (batch.width() as f32, batch.height() as f32);
+        let x = ((state.canvas_size.0 - width) / 2.0).floor();
+        let y = ((state.canvas_size.1 - height) / 2.0).floor();
 
-        batch.add(((state.canvas_size.0 - batch.width() as f32) / 2.0).floor(),
-                  ((state.canvas_size.1 - batch.height() as f32) / 2.0).floor());
+        batch.add(x, y);
         batch.draw(ctx)?;
+
+        if self.total > 0 {
+            let done = self.progress.load(Ordering::SeqCst).min(self.total);
+            let bar_width = (width * done as f32 / self.total as f32) as isize;
+            let bar_y = (y + height) as isize + 8;
+
+            let mut primitives = Primitives::new();
+            primitives.rect(Rect::new_size(x as isize, bar_y, width as isize, 2), [0.2, 0.2, 0.2, 1.0]);
+            primitives.rect(Rect::new_size(x as isize, bar_y, bar_width, 2), [1.0, 1.0, 1.0, 1.0]);
+            state.texture_set.draw_primitives(ctx, &primitives)?;
+        }
+
         Ok(())
     }
 }
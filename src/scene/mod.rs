@@ -1,17 +1,41 @@
+use std::any::Any;
+
 use crate::ggez::{Context, GameResult};
 
 use crate::SharedGameState;
 use crate::ui::Components;
 
+pub mod challenge_menu_scene;
+pub mod challenge_result_scene;
+pub mod data_report_scene;
+pub mod error_scene;
 pub mod game_scene;
 pub mod loading_scene;
+pub mod mod_menu_scene;
+pub mod settings_scene;
+pub mod title_scene;
+pub mod transition_scene;
+
+/// Lets the tests tell which scene they ended up in, call it on the `dyn Scene` and not on its box.
+pub trait AsAny {
+    fn as_any(&self) -> &dyn Any;
+}
 
-pub trait Scene {
+impl<T: Any> AsAny for T {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+pub trait Scene: AsAny {
     fn init(&mut self, _state: &mut SharedGameState, _ctx: &mut Context) -> GameResult { Ok(()) }
 
     fn tick(&mut self, _state: &mut SharedGameState, _ctx: &mut Context) -> GameResult { Ok(()) }
 
     fn draw(&self, _state: &mut SharedGameState, _ctx: &mut Context) -> GameResult { Ok(()) }
 
+    /// Ticks per second the scene has to run at regardless of the settings, like the credits which are synced to the music.
+    fn tps(&self, _state: &SharedGameState) -> Option<u32> { None }
+
     fn debug_overlay_draw(&mut self, _game_ui: &mut Components, _state: &mut SharedGameState, _ctx: &mut Context, _frame: &mut imgui::Ui) -> GameResult { Ok(()) }
 }
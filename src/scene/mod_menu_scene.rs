@@ -0,0 +1,157 @@
+use crate::ggez::{Context, GameResult, graphics};
+use crate::ggez::graphics::Color;
+use crate::mods::{ModInfo, scan_mods, switch_mod};
+use crate::scene::loading_scene::LoadingScene;
+use crate::scene::Scene;
+use crate::scene::title_scene::NEW_GAME_STAGE;
+use crate::SharedGameState;
+use crate::stage::StageData;
+use crate::thumbnail;
+use crate::thumbnail::Thumbnails;
+
+const VISIBLE_ROWS: usize = 10;
+const ROW_HEIGHT: f32 = 14.0;
+
+/// Lists the installed mods, the first entry is always the base game.
+pub struct ModMenuScene {
+    mods: Vec<ModInfo>,
+    selected: usize,
+    scroll: usize,
+    /// Stage a new game starts on, shown as the thumbnail of every entry.
+    // todo: let mods pick the stage in the manifest
+    start_stage: Option<StageData>,
+    thumbnails: Thumbnails,
+}
+
+impl ModMenuScene {
+    pub fn new(ctx: &Context) -> Self {
+        Self {
+            mods: scan_mods(ctx),
+            selected: 0,
+            scroll: 0,
+            start_stage: None,
+            thumbnails: Thumbnails::new(),
+        }
+    }
+
+    /// Thumbnails are keyed by the mod ID, the base game has none.
+    fn thumbnail_key(&self, index: usize) -> &str {
+        match index {
+            0 => "",
+            _ => &self.mods[index - 1].id,
+        }
+    }
+
+    fn entry_name(&self, index: usize) -> &str {
+        match index {
+            0 => "Cave Story",
+            _ => &self.mods[index - 1].manifest.name,
+        }
+    }
+}
+
+impl Scene for ModMenuScene {
+    fn init(&mut self, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
+        state.textscript_vm.suspend = true;
+        state.sound_manager.play_song(0, &state.constants, ctx)?;
+
+        // shown before the game data is loaded on startup
+        self.start_stage = match state.stages.get(NEW_GAME_STAGE) {
+            Some(stage) => Some(stage.clone()),
+            None => StageData::load_stage_table(ctx, &state.base_path).ok()
+                .and_then(|mut stages| if stages.len() > NEW_GAME_STAGE { Some(stages.swap_remove(NEW_GAME_STAGE)) } else { None }),
+        };
+
+        if let Some(current) = state.current_mod.as_ref() {
+            if let Some(pos) = self.mods.iter().position(|m| m.id == current.id) {
+                self.selected = pos + 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn tick(&mut self, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
+        state.update_key_trigger();
+        let count = self.mods.len() + 1;
+
+        if state.key_trigger.up() {
+            self.selected = (self.selected + count - 1) % count;
+            state.sound_manager.play_sfx(1);
+        } else if state.key_trigger.down() {
+            self.selected = (self.selected + 1) % count;
+            state.sound_manager.play_sfx(1);
+        }
+
+        if self.selected < self.scroll {
+            self.scroll = self.selected;
+        } else if self.selected >= self.scroll + VISIBLE_ROWS {
+            self.scroll = self.selected + 1 - VISIBLE_ROWS;
+        }
+
+        if let Some(stage) = self.start_stage.as_ref() {
+            let mod_dir = match self.selected {
+                0 => None,
+                n => Some(self.mods[n - 1].path.as_path()),
+            };
+            let key = self.thumbnail_key(self.selected).to_owned();
+            self.thumbnails.request(&key, stage, mod_dir, state, ctx);
+        }
+        self.thumbnails.poll(ctx);
+
+        if state.key_trigger.jump() {
+            state.sound_manager.play_sfx(18);
+
+            let new_mod = match self.selected {
+                0 => None,
+                n => Some(self.mods[n - 1].clone()),
+            };
+            switch_mod(state, ctx, new_mod);
+            state.next_scene = Some(Box::new(LoadingScene::new()));
+        }
+
+        Ok(())
+    }
+
+    fn draw(&self, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
+        graphics::clear(ctx, Color::from_rgb(0, 0, 32));
+
+        state.font.draw_text("Select a mod:".chars(), 16.0, 16.0, &state.constants, &mut state.texture_set, ctx)?;
+
+        let count = self.mods.len() + 1;
+        let mut y = 40.0;
+        for index in self.scroll..count.min(self.scroll + VISIBLE_ROWS) {
+            if index == self.selected {
+                state.font.draw_text(">".chars(), 16.0, y, &state.constants, &mut state.texture_set, ctx)?;
+            }
+
+            state.font.draw_text(self.entry_name(index).chars(), 28.0, y, &state.constants, &mut state.texture_set, ctx)?;
+            y += ROW_HEIGHT;
+        }
+
+        if self.scroll > 0 {
+            state.font.draw_text("^".chars(), 16.0, 40.0 - ROW_HEIGHT, &state.constants, &mut state.texture_set, ctx)?;
+        }
+        if self.scroll + VISIBLE_ROWS < count {
+            state.font.draw_text("v".chars(), 16.0, y, &state.constants, &mut state.texture_set, ctx)?;
+        }
+
+        if self.start_stage.is_some() {
+            let x = state.canvas_size.0 - thumbnail::MAX_WIDTH as f32 - 16.0;
+            self.thumbnails.draw(self.thumbnail_key(self.selected), x, 40.0, state, ctx)?;
+        }
+
+        if self.selected > 0 {
+            let manifest = &self.mods[self.selected - 1].manifest;
+            let desc_y = state.canvas_size.1 - 40.0;
+
+            if !manifest.author.is_empty() {
+                let author = format!("by {}", manifest.author);
+                state.font.draw_text(author.chars(), 16.0, desc_y, &state.constants, &mut state.texture_set, ctx)?;
+            }
+            state.font.draw_text(manifest.description.chars(), 16.0, desc_y + 12.0, &state.constants, &mut state.texture_set, ctx)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,191 @@
+use crate::ggez::{Context, GameResult, graphics};
+use crate::ggez::graphics::Color;
+use crate::scene::Scene;
+use crate::scene::title_scene::TitleScene;
+use crate::settings::{AccessibilitySettings, CompatMode};
+use crate::SharedGameState;
+
+const ROW_HEIGHT: f32 = 14.0;
+/// Message delays the menu cycles through, finer ones are set from the debugger.
+const MESSAGE_DELAYS: [f32; 4] = [1.0, 1.5, 2.0, 3.0];
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum SettingsEntry {
+    HighContrastHud,
+    LargeText,
+    ReducedFlash,
+    AutoFire,
+    MessageDelay,
+    Back,
+}
+
+static ENTRIES: [SettingsEntry; 6] = [
+    SettingsEntry::HighContrastHud,
+    SettingsEntry::LargeText,
+    SettingsEntry::ReducedFlash,
+    SettingsEntry::AutoFire,
+    SettingsEntry::MessageDelay,
+    SettingsEntry::Back,
+];
+
+impl SettingsEntry {
+    fn label(self, settings: &AccessibilitySettings) -> String {
+        let on_off = |on: bool| if on { "On" } else { "Off" };
+
+        match self {
+            SettingsEntry::HighContrastHud => format!("High contrast HUD: {}", on_off(settings.high_contrast_hud)),
+            SettingsEntry::LargeText => format!("Large message text: {}", on_off(settings.large_text)),
+            SettingsEntry::ReducedFlash => format!("Reduced flashing: {}", on_off(settings.reduced_flash)),
+            SettingsEntry::AutoFire => format!("Auto fire: {}", on_off(settings.auto_fire)),
+            SettingsEntry::MessageDelay => format!("Message delay: {}x", settings.message_delay),
+            SettingsEntry::Back => "Back".to_owned(),
+        }
+    }
+
+    /// Toggles the option, or steps the message delay forward or backward.
+    fn change(self, settings: &mut AccessibilitySettings, forward: bool) {
+        match self {
+            SettingsEntry::HighContrastHud => settings.high_contrast_hud = !settings.high_contrast_hud,
+            SettingsEntry::LargeText => settings.large_text = !settings.large_text,
+            SettingsEntry::ReducedFlash => settings.reduced_flash = !settings.reduced_flash,
+            SettingsEntry::AutoFire => settings.auto_fire = !settings.auto_fire,
+            SettingsEntry::MessageDelay => {
+                // a value set from the debugger snaps to the next one in the list
+                let count = MESSAGE_DELAYS.len();
+                let next = MESSAGE_DELAYS.iter().position(|&delay| delay >= settings.message_delay);
+                let index = match (next, forward) {
+                    (Some(i), true) if MESSAGE_DELAYS[i] > settings.message_delay => i,
+                    (Some(i), true) => (i + 1) % count,
+                    (Some(i), false) => (i + count - 1) % count,
+                    (None, true) => 0,
+                    (None, false) => count - 1,
+                };
+                settings.message_delay = MESSAGE_DELAYS[index];
+            }
+            SettingsEntry::Back => {}
+        }
+    }
+}
+
+/// The accessibility options, saved when going back to the title screen.
+pub struct SettingsScene {
+    selected: usize,
+}
+
+impl SettingsScene {
+    pub fn new() -> Self {
+        Self {
+            selected: 0,
+        }
+    }
+
+    /// Returns true once the player goes back.
+    fn tick_menu(&mut self, state: &mut SharedGameState) -> bool {
+        let count = ENTRIES.len();
+
+        if state.key_trigger.up() {
+            self.selected = (self.selected + count - 1) % count;
+            state.sound_manager.play_sfx(1);
+        } else if state.key_trigger.down() {
+            self.selected = (self.selected + 1) % count;
+            state.sound_manager.play_sfx(1);
+        }
+
+        let entry = ENTRIES[self.selected];
+        if state.key_trigger.fire() || (entry == SettingsEntry::Back && state.key_trigger.jump()) {
+            state.sound_manager.play_sfx(18);
+            return true;
+        }
+
+        if state.key_trigger.jump() || state.key_trigger.right() || state.key_trigger.left() {
+            entry.change(&mut state.settings.accessibility, !state.key_trigger.left());
+            state.sound_manager.play_sfx(1);
+        }
+
+        false
+    }
+}
+
+impl Scene for SettingsScene {
+    fn init(&mut self, state: &mut SharedGameState, _ctx: &mut Context) -> GameResult {
+        state.textscript_vm.suspend = true;
+
+        Ok(())
+    }
+
+    fn tick(&mut self, state: &mut SharedGameState, _ctx: &mut Context) -> GameResult {
+        state.update_key_trigger();
+
+        if self.tick_menu(state) {
+            if let Err(e) = state.settings.save() {
+                log::error!("Error saving settings: {:?}", e);
+            }
+            state.next_scene = Some(Box::new(TitleScene::new()));
+        }
+
+        Ok(())
+    }
+
+    fn draw(&self, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
+        graphics::clear(ctx, Color::from_rgb(0, 0, 32));
+
+        state.font.draw_text("Accessibility".chars(), 16.0, 16.0, &state.constants, &mut state.texture_set, ctx)?;
+
+        let mut y = 40.0;
+        for (index, entry) in ENTRIES.iter().enumerate() {
+            if index == self.selected {
+                state.font.draw_text(">".chars(), 16.0, y, &state.constants, &mut state.texture_set, ctx)?;
+            }
+
+            let label = entry.label(&state.settings.accessibility);
+            state.font.draw_text(label.chars(), 28.0, y, &state.constants, &mut state.texture_set, ctx)?;
+            y += ROW_HEIGHT;
+        }
+
+        if state.settings.compat_mode == CompatMode::Vanilla {
+            let note = "Auto fire and the message delay are off in Vanilla mode.";
+            state.font.draw_text(note.chars(), 16.0, state.canvas_size.1 - 20.0, &state.constants, &mut state.texture_set, ctx)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn test_settings_menu() {
+    use crate::common::KeyState;
+
+    let mut state = SharedGameState::for_tests();
+    let mut menu = SettingsScene::new();
+    let press = |set: fn(&mut KeyState, bool), menu: &mut SettingsScene, state: &mut SharedGameState| {
+        state.key_trigger = KeyState(0);
+        set(&mut state.key_trigger, true);
+        menu.tick_menu(state)
+    };
+
+    // down to reduced flashing
+    press(KeyState::set_down, &mut menu, &mut state);
+    press(KeyState::set_down, &mut menu, &mut state);
+    assert!(!press(KeyState::set_jump, &mut menu, &mut state));
+    assert!(state.settings.accessibility.reduced_flash);
+    press(KeyState::set_left, &mut menu, &mut state);
+    assert!(!state.settings.accessibility.reduced_flash);
+
+    press(KeyState::set_down, &mut menu, &mut state);
+    press(KeyState::set_down, &mut menu, &mut state);
+    press(KeyState::set_right, &mut menu, &mut state);
+    assert_eq!(state.settings.accessibility.message_delay, 1.5);
+    press(KeyState::set_left, &mut menu, &mut state);
+    press(KeyState::set_left, &mut menu, &mut state);
+    assert_eq!(state.settings.accessibility.message_delay, 3.0);
+
+    // from the debugger
+    state.settings.accessibility.message_delay = 1.2;
+    press(KeyState::set_right, &mut menu, &mut state);
+    assert_eq!(state.settings.accessibility.message_delay, 1.5);
+
+    // Back, or the fire button from anywhere
+    press(KeyState::set_down, &mut menu, &mut state);
+    assert!(press(KeyState::set_jump, &mut menu, &mut state));
+    assert!(press(KeyState::set_fire, &mut menu, &mut state));
+}
@@ -0,0 +1,452 @@
+use std::time::Duration;
+
+use crate::challenge::{Challenge, format_time};
+use crate::common::{Direction, FadeState, KeyState, Rect};
+use crate::ggez::{Context, event, filesystem, GameResult, graphics};
+use crate::ggez::GameError::ResourceLoadError;
+use crate::ggez::graphics::Color;
+use crate::mods::scan_mods;
+use crate::player::{animation_rect, PlayableCharacter, PlayerSkin};
+use crate::profile;
+use crate::profile::{GameProfile, LIFE_CAPSULE_COUNT, ProfilePreview};
+use crate::prompts;
+use crate::replay::{Replay, ReplayMode};
+use crate::scene::challenge_menu_scene::ChallengeMenuScene;
+use crate::scene::game_scene::GameScene;
+use crate::scene::mod_menu_scene::ModMenuScene;
+use crate::scene::Scene;
+use crate::scene::settings_scene::SettingsScene;
+use crate::SharedGameState;
+use crate::str;
+use crate::text_script::TextScriptExecutionState;
+use crate::texture_set::WindowStyle;
+
+const ROW_HEIGHT: f32 = 14.0;
+/// The demo starts after the title screen sits idle for 30 seconds.
+const DEMO_IDLE_TICKS: usize = 30 * 50;
+/// First Cave demo played unless the game data comes with its own `demo.rep`.
+// todo: the bundled one has been put together by hand and has no position checksums, record it on vanilla data
+//  so a desync ends it early
+const BUILTIN_DEMO: &str = "/builtin/demo.rep";
+/// Shown in place of the values of a corrupt profile, the font has no em dash.
+const NO_VALUE: &str = "---";
+/// Start Point, where a new game begins.
+pub const NEW_GAME_STAGE: usize = 13;
+/// Entered on the title screen to unlock the character select, like in CS+.
+const CHARACTER_SELECT_CODE: [fn(&KeyState) -> bool; 8] = [
+    KeyState::up, KeyState::up, KeyState::down, KeyState::down,
+    KeyState::left, KeyState::right, KeyState::left, KeyState::right,
+];
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum TitleEntry {
+    NewGame,
+    LoadGame,
+    Challenges,
+    Mods,
+    Settings,
+    Quit,
+}
+
+impl TitleEntry {
+    fn name(self) -> &'static str {
+        match self {
+            TitleEntry::NewGame => "New game",
+            TitleEntry::LoadGame => "Load game",
+            TitleEntry::Challenges => "Challenges",
+            TitleEntry::Mods => "Mods",
+            TitleEntry::Settings => "Settings",
+            TitleEntry::Quit => "Quit",
+        }
+    }
+}
+
+pub struct TitleScene {
+    entries: Vec<TitleEntry>,
+    selected: usize,
+    idle_ticks: usize,
+    /// None without a profile, Err if the profile can't be read.
+    profile: Option<GameResult<ProfilePreview>>,
+    /// Sacred Grounds best time, in ticks.
+    hell_record: Option<u32>,
+    /// Shown in a message box until dismissed.
+    error: Option<String>,
+    /// Keys of `CHARACTER_SELECT_CODE` entered so far.
+    code_progress: usize,
+    character_select_unlocked: bool,
+    /// Character highlighted in the character select, None while it's closed.
+    picking: Option<PlayableCharacter>,
+}
+
+/// Formats a play time, hours:minutes:seconds.
+fn format_play_time(time: Duration) -> String {
+    let seconds = time.as_secs();
+    format!("{}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+impl TitleScene {
+    pub fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+            selected: 0,
+            idle_ticks: 0,
+            profile: None,
+            hell_record: None,
+            error: None,
+            code_progress: 0,
+            character_select_unlocked: false,
+            picking: None,
+        }
+    }
+
+    /// Starts a new game with the intro event (or the current mod's start event).
+    pub fn start_new_game(state: &mut SharedGameState, ctx: &mut Context, character: PlayableCharacter) -> GameResult {
+        let next_scene = TitleScene::new_game_scene(state, ctx, character)?;
+        state.next_scene = Some(Box::new(next_scene));
+        Ok(())
+    }
+
+    /// First scene of a new game, the start event runs once it's initialized.
+    pub fn new_game_scene(state: &mut SharedGameState, ctx: &mut Context, character: PlayableCharacter) -> GameResult<GameScene> {
+        state.reset_game_state();
+        state.character = character;
+
+        let start_event = state.current_mod.as_ref()
+            .and_then(|m| m.manifest.start_event)
+            .unwrap_or(200);
+
+        let mut next_scene = GameScene::new(state, ctx, NEW_GAME_STAGE)?;
+        next_scene.player.x = 10 * 16 * 0x200;
+        next_scene.player.y = 8 * 16 * 0x200;
+        state.fade_state = FadeState::Hidden;
+        state.textscript_vm.state = TextScriptExecutionState::Running(start_event, 0);
+
+        Ok(next_scene)
+    }
+
+    /// Returns true on the tick the demo has to start, any key starts the wait over.
+    fn tick_idle(&mut self, state: &SharedGameState) -> bool {
+        if state.key_state.0 != 0 {
+            self.idle_ticks = 0;
+        } else {
+            self.idle_ticks += 1;
+        }
+
+        self.idle_ticks == DEMO_IDLE_TICKS
+    }
+
+    /// Plays the demo with a temporary profile, it goes back to the title screen once it ends, desyncs or a key is pressed.
+    fn start_demo(&self, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
+        let path = [&state.base_path, "demo.rep"].join("");
+        let path = if filesystem::exists(ctx, &path) { path.as_str() } else { BUILTIN_DEMO };
+
+        let replay = Replay::load(ctx, path)?;
+        // it would desync right away, no point in showing it
+        if replay.metadata.as_ref().is_some_and(|metadata| !metadata.matches_data(state)) {
+            return Err(ResourceLoadError(str!("The demo has been recorded on different game data.")));
+        }
+
+        let mut scene = replay.create_scene(state, ctx)?;
+        scene.replay = ReplayMode::play(replay);
+        state.next_scene = Some(Box::new(scene));
+
+        Ok(())
+    }
+
+    fn load_game(&mut self, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
+        let scene = match GameProfile::load(state)? {
+            Some(profile) => profile.create_scene(state, ctx)?,
+            None => { return TitleScene::start_new_game(state, ctx, PlayableCharacter::Quote); }
+        };
+
+        state.next_scene = Some(Box::new(scene));
+        Ok(())
+    }
+
+    fn draw_profile_preview(&self, x: f32, y: f32, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
+        let preview = match &self.profile {
+            Some(Ok(preview)) => Some(preview),
+            Some(Err(_)) => None,
+            None => { return Ok(()); }
+        };
+
+        state.texture_set.draw_window(ctx, &state.constants, Rect::new_size(x, y, 200.0, 70.0), WindowStyle::Normal)?;
+
+        // the stage table is loaded on startup, so the caption is known before any stage is
+        let caption = preview.and_then(|p| state.stages.get(p.current_map as usize))
+            .map_or(NO_VALUE.to_string(), |stage| stage.name.clone());
+        let health = preview.map_or(NO_VALUE.to_string(), |p| format!("{}/{}", p.life, p.max_life));
+        // profiles saved by vanilla, CS+ or older versions have no record
+        let record = preview.and_then(|p| p.record);
+        let time = record.map_or(NO_VALUE.to_string(), |r| format_play_time(r.play_time));
+        let capsules = record.map_or(NO_VALUE.to_string(), |r| format!("{}/{}", r.life_capsules, LIFE_CAPSULE_COUNT));
+        let completion = preview.and_then(ProfilePreview::completion).map_or(NO_VALUE.to_string(), |c| format!("{}%", c));
+
+        state.font.draw_text(caption.chars(), x + 8.0, y + 8.0, &state.constants, &mut state.texture_set, ctx)?;
+        state.font.draw_text(format!("HP {}", health).chars(), x + 8.0, y + 22.0, &state.constants, &mut state.texture_set, ctx)?;
+        state.font.draw_text(format!("Time {}", time).chars(), x + 96.0, y + 22.0, &state.constants, &mut state.texture_set, ctx)?;
+        state.font.draw_text(format!("Capsules {}", capsules).chars(), x + 8.0, y + 36.0, &state.constants, &mut state.texture_set, ctx)?;
+        state.font.draw_text(format!("Done {}", completion).chars(), x + 96.0, y + 36.0, &state.constants, &mut state.texture_set, ctx)?;
+
+        if let Some(preview) = preview {
+            let batch = state.texture_set.get_or_load_batch(ctx, &state.constants, "ArmsImage")?;
+            for (i, &weapon) in preview.weapons.iter().enumerate() {
+                let rect = Rect::new_size(weapon as usize * 16, 0, 16, 16);
+                batch.add_rect(x + 8.0 + i as f32 * 16.0, y + 48.0, &rect);
+            }
+            batch.draw(ctx)?;
+        }
+
+        Ok(())
+    }
+
+    /// The character select is only offered if MyChar has Curly's sprites (CS+ data).
+    fn character_select_available(&self, state: &SharedGameState) -> bool {
+        (self.character_select_unlocked || state.settings.character_select)
+            && PlayableCharacter::Curly.skin_row(&state.constants.my_char).is_some()
+    }
+
+    fn tick_code(&mut self, state: &mut SharedGameState) {
+        if state.key_trigger.0 == 0 || self.character_select_unlocked {
+            return;
+        }
+
+        if CHARACTER_SELECT_CODE[self.code_progress](&state.key_trigger) {
+            self.code_progress += 1;
+        } else {
+            self.code_progress = if CHARACTER_SELECT_CODE[0](&state.key_trigger) { 1 } else { 0 };
+        }
+
+        if self.code_progress == CHARACTER_SELECT_CODE.len() {
+            self.code_progress = 0;
+            self.character_select_unlocked = true;
+            state.sound_manager.play_sfx(38);
+        }
+    }
+
+    fn tick_character_select(&mut self, character: PlayableCharacter, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
+        if state.key_trigger.left() || state.key_trigger.right() {
+            self.picking = Some(match character {
+                PlayableCharacter::Quote => PlayableCharacter::Curly,
+                PlayableCharacter::Curly => PlayableCharacter::Quote,
+            });
+            state.sound_manager.play_sfx(1);
+        }
+
+        if state.key_trigger.jump() {
+            state.sound_manager.play_sfx(18);
+            self.picking = None;
+            TitleScene::start_new_game(state, ctx, character)?;
+        } else if state.key_trigger.fire() {
+            self.picking = None;
+        }
+
+        Ok(())
+    }
+
+    fn draw_character_select(&self, character: PlayableCharacter, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
+        let rect = Rect::new_size((state.canvas_size.0 / 2.0 - 64.0).floor(), (state.canvas_size.1 / 2.0 - 28.0).floor(), 128.0, 56.0);
+        state.texture_set.draw_window(ctx, &state.constants, rect, WindowStyle::Normal)?;
+        state.font.draw_text("Play as".chars(), rect.left + 8.0, rect.top + 8.0, &state.constants, &mut state.texture_set, ctx)?;
+
+        let batch = state.texture_set.get_or_load_batch(ctx, &state.constants, "MyChar")?;
+        let sprite = animation_rect(&state.constants.my_char, 0, Direction::Right, PlayerSkin::Quote, character);
+        batch.add_rect(rect.left + 8.0, rect.top + 28.0, &sprite);
+        batch.draw(ctx)?;
+
+        let name = format!("< {} >", character.name());
+        state.font.draw_text(name.chars(), rect.left + 32.0, rect.top + 30.0, &state.constants, &mut state.texture_set, ctx)?;
+
+        Ok(())
+    }
+}
+
+impl Scene for TitleScene {
+    fn init(&mut self, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
+        state.textscript_vm.suspend = true;
+        state.challenge = None;
+        state.temporary_profile = false;
+        state.sound_manager.play_song(24, &state.constants, ctx)?;
+
+        self.profile = match GameProfile::peek_saved(state) {
+            Ok(Some(preview)) => Some(Ok(preview)),
+            Ok(None) => None,
+            Err(e) => {
+                log::warn!("Cannot read the profile: {}", e);
+                Some(Err(e))
+            }
+        };
+        self.hell_record = profile::load_record(state);
+
+        self.entries.push(TitleEntry::NewGame);
+        if self.profile.is_some() {
+            // preselected like in vanilla
+            self.selected = self.entries.len();
+            self.entries.push(TitleEntry::LoadGame);
+        }
+        if Challenge::load_list(state, ctx).is_ok_and(|list| !list.is_empty()) {
+            self.entries.push(TitleEntry::Challenges);
+        }
+        if !scan_mods(ctx).is_empty() {
+            self.entries.push(TitleEntry::Mods);
+        }
+        self.entries.push(TitleEntry::Settings);
+        self.entries.push(TitleEntry::Quit);
+
+        Ok(())
+    }
+
+    fn tick(&mut self, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
+        state.update_key_trigger();
+        let count = self.entries.len();
+
+        if self.error.is_some() {
+            if state.key_trigger.jump() || state.key_trigger.fire() {
+                self.error = None;
+            }
+            return Ok(());
+        }
+
+        let start_demo = self.tick_idle(state);
+
+        if let Some(character) = self.picking {
+            return self.tick_character_select(character, state, ctx);
+        }
+
+        self.tick_code(state);
+
+        if start_demo {
+            match self.start_demo(state, ctx) {
+                Ok(()) => { return Ok(()); }
+                Err(e) => log::warn!("Cannot play the demo: {}", e),
+            }
+        }
+
+        if state.key_trigger.up() {
+            self.selected = (self.selected + count - 1) % count;
+            state.sound_manager.play_sfx(1);
+        } else if state.key_trigger.down() {
+            self.selected = (self.selected + 1) % count;
+            state.sound_manager.play_sfx(1);
+        }
+
+        if state.key_trigger.jump() {
+            state.sound_manager.play_sfx(18);
+
+            match self.entries[self.selected] {
+                TitleEntry::NewGame if self.character_select_available(state) => {
+                    self.picking = Some(PlayableCharacter::Quote);
+                }
+                TitleEntry::NewGame => TitleScene::start_new_game(state, ctx, PlayableCharacter::Quote)?,
+                TitleEntry::LoadGame => {
+                    if let Err(e) = self.load_game(state, ctx) {
+                        log::error!("Cannot load the profile: {}", e);
+                        self.error = Some(format!("Cannot load the profile: {}", e));
+                    }
+                }
+                TitleEntry::Challenges => {
+                    state.next_scene = Some(Box::new(ChallengeMenuScene::new(state, ctx)?));
+                }
+                TitleEntry::Mods => {
+                    state.next_scene = Some(Box::new(ModMenuScene::new(ctx)));
+                }
+                TitleEntry::Settings => {
+                    state.next_scene = Some(Box::new(SettingsScene::new()));
+                }
+                TitleEntry::Quit => event::quit(ctx),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn draw(&self, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
+        graphics::clear(ctx, Color::from_rgb(0, 0, 32));
+
+        let title = state.current_mod.as_ref().map_or("Cave Story", |m| m.manifest.name.as_str()).to_string();
+        let width = state.font.text_width(title.chars(), &state.constants);
+        state.font.draw_text(title.chars(), ((state.canvas_size.0 - width) / 2.0).floor(), 40.0,
+                             &state.constants, &mut state.texture_set, ctx)?;
+
+        let mut y = (state.canvas_size.1 / 2.0).floor();
+        for (index, entry) in self.entries.iter().enumerate() {
+            let x = (state.canvas_size.0 / 2.0 - 32.0).floor();
+
+            if index == self.selected {
+                state.font.draw_text(">".chars(), x - 12.0, y, &state.constants, &mut state.texture_set, ctx)?;
+            }
+
+            state.font.draw_text(entry.name().chars(), x, y, &state.constants, &mut state.texture_set, ctx)?;
+            y += ROW_HEIGHT;
+        }
+
+        self.draw_profile_preview((state.canvas_size.0 / 2.0 - 100.0).floor(), y + 8.0, state, ctx)?;
+
+        let mode = format!("{} mode", state.settings.compat_mode.name());
+        state.font.draw_text(mode.chars(), 8.0, 8.0, &state.constants, &mut state.texture_set, ctx)?;
+
+        if let Some(time) = self.hell_record {
+            let text = format_time(time as usize, state.tps());
+            let width = state.font.text_width(text.chars(), &state.constants);
+            state.font.draw_text(text.chars(), state.canvas_size.0 - width - 8.0, 8.0, &state.constants, &mut state.texture_set, ctx)?;
+        }
+
+        if let Some(character) = self.picking {
+            self.draw_character_select(character, state, ctx)?;
+            let hint = format!("{} Start {} Back", prompts::token_for(KeyState::set_jump), prompts::token_for(KeyState::set_fire));
+            prompts::draw_text(hint.chars(), 8.0, state.canvas_size.1 - 20.0, state, ctx)?;
+        } else if let Some(error) = &self.error {
+            let rect = Rect::new_size(16.0, (state.canvas_size.1 / 2.0 - 24.0).floor(), state.canvas_size.0 - 32.0, 48.0);
+            state.texture_set.draw_window(ctx, &state.constants, rect, WindowStyle::Normal)?;
+            state.font.draw_text(error.chars(), rect.left + 8.0, rect.top + 8.0, &state.constants, &mut state.texture_set, ctx)?;
+            let ok = format!("{} OK", prompts::token_for(KeyState::set_jump));
+            prompts::draw_text(ok.chars(), rect.left + 8.0, rect.top + 28.0, state, ctx)?;
+        } else {
+            let hint = format!("{} Select", prompts::token_for(KeyState::set_jump));
+            prompts::draw_text(hint.chars(), 8.0, state.canvas_size.1 - 20.0, state, ctx)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn test_format_play_time() {
+    assert_eq!(format_play_time(Duration::from_millis((2 * 3600 + 5 * 60 + 9) * 1000 + 999)), "2:05:09");
+}
+
+#[test]
+fn test_idle_demo() {
+    use crate::replay::ReplayStatus;
+    use crate::settings::CompatMode;
+
+    let mut state = SharedGameState::for_tests();
+    let mut title = TitleScene::new();
+
+    for _ in 1..DEMO_IDLE_TICKS {
+        assert!(!title.tick_idle(&state));
+    }
+    // a key right before the demo starts the wait over
+    state.key_state.set_down(true);
+    assert!(!title.tick_idle(&state));
+
+    state.key_state = KeyState(0);
+    for _ in 1..DEMO_IDLE_TICKS {
+        assert!(!title.tick_idle(&state));
+    }
+    assert!(title.tick_idle(&state));
+    assert!(!title.tick_idle(&state));
+
+    // the bundled one, played back to the end
+    let demo = Replay::read_from(&include_bytes!("../builtin/demo.rep")[..]).unwrap();
+    assert_eq!((demo.stage_id, demo.compat_mode, demo.character), (12, CompatMode::Vanilla, PlayableCharacter::Quote));
+    let len = demo.len();
+    assert!(len > DEMO_IDLE_TICKS / 2);
+
+    let player = crate::player::Player::new(&mut state);
+    let mut playback = ReplayMode::play(demo);
+    for _ in 0..len {
+        assert_eq!(playback.tick(&mut KeyState(0), &player), ReplayStatus::Running);
+    }
+    assert_eq!(playback.tick(&mut KeyState(0), &player), ReplayStatus::Finished);
+}
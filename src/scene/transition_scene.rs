@@ -0,0 +1,111 @@
+use std::time::Instant;
+
+use crate::ggez::{Context, GameResult, graphics};
+use crate::ggez::graphics::Color;
+use crate::inventory::Inventory;
+use crate::player::{PlayableCharacter, Player};
+use crate::profile::GameProfile;
+use crate::scene::error_scene::ErrorScene;
+use crate::scene::game_scene::GameScene;
+use crate::scene::Scene;
+use crate::scene::title_scene::TitleScene;
+use crate::SharedGameState;
+
+/// What a transition loads.
+#[allow(clippy::large_enum_variant)]
+pub enum TransitionTarget {
+    /// `<TRA`, the player and the inventory are carried over.
+    Stage { stage_id: usize, pos_x: isize, pos_y: isize, player: Player, inventory: Inventory },
+    /// `<INI`, a new game as the character.
+    NewGame(PlayableCharacter),
+    /// `<LDP`, the saved profile.
+    Profile(Box<GameProfile>),
+}
+
+/// Loads the target stage of a `<TRA` (or the game started by `<INI` and `<LDP`) while the screen stays black,
+/// one step per tick, so the work doesn't land in a single visible frame.
+pub struct TransitionScene {
+    step: u8,
+    pub target: TransitionTarget,
+    new_scene: Option<GameScene>,
+    started: Instant,
+}
+
+impl TransitionScene {
+    pub fn new(stage_id: usize, pos_x: isize, pos_y: isize, player: Player, inventory: Inventory) -> Self {
+        TransitionScene::to(TransitionTarget::Stage { stage_id, pos_x, pos_y, player, inventory })
+    }
+
+    pub fn to(target: TransitionTarget) -> Self {
+        Self {
+            step: 0,
+            target,
+            new_scene: None,
+            started: Instant::now(),
+        }
+    }
+
+    fn load_step(&mut self, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
+        match self.step {
+            // let the black frame present first
+            0 => {}
+            1 => {
+                let new_scene = match &self.target {
+                    TransitionTarget::Stage { stage_id, pos_x, pos_y, player, inventory } => {
+                        let mut new_scene = GameScene::new(state, ctx, *stage_id)?;
+                        new_scene.inventory = inventory.clone();
+                        new_scene.player = player.clone();
+                        new_scene.player.vel_x = 0;
+                        new_scene.player.vel_y = 0;
+                        new_scene.player.x = *pos_x;
+                        new_scene.player.y = *pos_y;
+                        new_scene
+                    }
+                    TransitionTarget::NewGame(character) => TitleScene::new_game_scene(state, ctx, *character)?,
+                    TransitionTarget::Profile(profile) => profile.create_scene(state, ctx)?,
+                };
+
+                self.new_scene = Some(new_scene);
+            }
+            2 => {
+                if let Some(new_scene) = self.new_scene.as_ref() {
+                    new_scene.preload_textures(state, ctx)?;
+                }
+            }
+            _ => {
+                if let Some(new_scene) = self.new_scene.take() {
+                    log::info!("Stage transition took {:?}", self.started.elapsed());
+
+                    // keeps the play time counter from getting lost on a crash
+                    if let Err(e) = state.settings.save() {
+                        log::warn!("Error saving settings: {}", e);
+                    }
+
+                    // the text script VM stays suspended until GameScene::init has loaded the stage script
+                    state.next_scene = Some(Box::new(new_scene));
+                }
+            }
+        }
+
+        self.step = self.step.saturating_add(1);
+        Ok(())
+    }
+}
+
+impl Scene for TransitionScene {
+    fn tick(&mut self, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
+        if let Err(err) = self.load_step(state, ctx) {
+            self.new_scene = None;
+            state.next_scene = Some(Box::new(ErrorScene::new(err)));
+        }
+
+        Ok(())
+    }
+
+    fn draw(&self, _state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
+        // same color as a fully faded out game scene
+        graphics::clear(ctx, Color::from_rgb(0, 0, 32));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,357 @@
+use std::fs;
+use std::path::PathBuf;
+
+use crate::ggez::filesystem::user_dirs;
+use crate::ggez::GameResult;
+use crate::memory::{KIB, MIB};
+use crate::transition::TransitionType;
+
+#[derive(Serialize, Deserialize, Clone, Debug, SmartDefault)]
+#[serde(default)]
+pub struct Settings {
+    /// Publish current stage and HP to Discord Rich Presence (requires the `discord` feature).
+    pub discord_rpc: bool,
+    /// Enables F5/F9 quick save and load. Not available while the Nikumaru counter is equipped.
+    pub quick_save: bool,
+    /// Enables rewinding by holding Backspace. Not available while the Nikumaru counter is equipped.
+    pub rewind: bool,
+    /// How far back the rewind can go.
+    #[default(10)]
+    pub rewind_seconds: u16,
+    /// What wide windows show, more of the map or the original 4:3 view with black bars.
+    #[default(Presentation::ExpandView)]
+    pub presentation: Presentation,
+    /// Draws the game through a scanline filter.
+    pub crt_filter: bool,
+    /// Darkens the stages listed in the lighting constants, except around light sources.
+    #[default(true)]
+    pub lighting: bool,
+    /// Rumbles force feedback capable gamepads on damage, explosions and screen shakes.
+    #[default(true)]
+    pub rumble: bool,
+    /// Multiplier applied to all rumble requests, 0.0 - 1.0.
+    #[default(1.0)]
+    pub rumble_intensity: f32,
+    /// Doesn't collect experience crystals through walls, the original game only checks if the hitboxes overlap.
+    pub exp_line_of_sight: bool,
+    /// Shows the keys held in the bottom right corner, toggled with F4.
+    pub input_display: bool,
+    /// Runs at 60 ticks per second like the 60 fps option of CS+, instead of the original 50. Every duration
+    /// is in ticks, so the whole game speeds up.
+    pub tps_60: bool,
+    /// Replaces the screen transitions of every script, None keeps the ones they ask for.
+    pub transition: Option<TransitionType>,
+    /// Offers the character select on New game without entering the code, needs CS+ data.
+    pub character_select: bool,
+    /// Waits for the monitor's refresh before presenting a frame, only applies when the window is created.
+    #[default(true)]
+    pub vsync: bool,
+    /// Frames per second when vsync is off or doesn't work, None draws as fast as possible.
+    /// The game ticks at its own fixed rate either way.
+    #[default(Some(60))]
+    pub frame_limit: Option<u32>,
+    /// Pauses the game and redraws at 10 fps while the window isn't focused.
+    #[default(true)]
+    pub power_saving: bool,
+    /// Reads the input once more right before drawing when the frame takes long to draw, to show it a frame earlier.
+    /// Only affects what's drawn, the game logic sees the input at the same time either way.
+    pub late_latch: bool,
+    /// Soft cap of the texture memory in MiB, the least recently used textures are unloaded over it. None for no cap.
+    pub texture_memory_cap: Option<u32>,
+    /// Soft cap of the rendered sound effects in KiB, the rarely used ones are rendered again when needed. None for no cap.
+    pub sfx_memory_cap: Option<u32>,
+    /// Fails on missing textures instead of drawing placeholders, meant for engine development.
+    pub strict_assets: bool,
+    /// Offers going back to the last save or the stage entrance when the player seems to be stuck.
+    pub softlock_recovery: bool,
+    /// Which of the behavior changing extensions are allowed, checked through the `allow_*` queries
+    /// rather than the toggles themselves.
+    #[default(CompatMode::Custom)]
+    pub compat_mode: CompatMode,
+    /// Total in-game ticks played across all sessions.
+    pub total_ticks_played: u64,
+    pub accessibility: AccessibilitySettings,
+    pub window: WindowSettings,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompatMode {
+    /// Plays exactly like the original, every extension is off whatever its toggle says.
+    Vanilla,
+    /// Fixes and mod features are on, the assists (quick save, rewind, auto fire, message delay, 60 TPS, transitions) follow their toggles.
+    Enhanced,
+    /// Everything follows its own toggle.
+    Custom,
+}
+
+impl CompatMode {
+    pub fn name(self) -> &'static str {
+        match self {
+            CompatMode::Vanilla => "Vanilla",
+            CompatMode::Enhanced => "Enhanced",
+            CompatMode::Custom => "Custom",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Presentation {
+    /// The canvas takes the whole window, wider windows show more of the map.
+    ExpandView,
+    /// The canvas is at most 4:3, centered with black bars on the sides.
+    Pillarbox,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, SmartDefault)]
+#[serde(default)]
+pub struct AccessibilitySettings {
+    /// Draws solid backgrounds behind the HP, ammo and experience displays.
+    pub high_contrast_hud: bool,
+    /// Renders message box text at 2x scale in a larger box.
+    pub large_text: bool,
+    /// Caps the intensity of <FLA and explosion flashes and removes the strobing.
+    pub reduced_flash: bool,
+    /// Keeps firing semi-automatic weapons (Polar Star family) while the fire button is held.
+    pub auto_fire: bool,
+    /// Multiplier of the delay between message characters, 2.0 prints at half the speed.
+    #[default(1.0)]
+    pub message_delay: f32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default)]
+#[serde(default)]
+pub struct WindowSettings {
+    /// Last logical position of the window.
+    pub position: Option<(f64, f64)>,
+    /// Last logical inner size of the window.
+    pub size: Option<(f64, f64)>,
+}
+
+/// Whether the settings allow an extension.
+pub type ExtensionQuery = fn(&Settings) -> bool;
+
+/// Every extension query, by name. New extensions have to be added here so the tests make sure
+/// the vanilla mode turns them off.
+pub const EXTENSIONS: [(&str, ExtensionQuery); 21] = [
+    ("Mod TSC commands", Settings::allow_mod_tsc),
+    ("Stage currents", Settings::allow_stage_currents),
+    ("NPC bounds fixes", Settings::allow_bounds_fixes),
+    ("NPC buoyancy", Settings::allow_npc_buoyancy),
+    ("Slope snapping", Settings::allow_slope_snapping),
+    ("No crystals through walls", Settings::allow_exp_line_of_sight),
+    ("Softlock recovery", Settings::allow_softlock_recovery),
+    ("Stage lighting", Settings::allow_lighting),
+    ("Quick save", Settings::allow_quick_save),
+    ("Rewind", Settings::allow_rewind),
+    ("Auto fire", Settings::allow_auto_fire),
+    ("Message delay", Settings::allow_message_delay),
+    ("60 ticks per second", Settings::allow_60_tps),
+    ("Transition override", Settings::allow_transition_override),
+    ("Stage history warp", Settings::allow_stage_history_warp),
+    ("Carets under <PRI", Settings::allow_frozen_carets),
+    ("NPCs waiting to be in view", Settings::allow_view_activation),
+    ("<UNI0002", Settings::allow_fixed_control),
+    ("Message wrapping", Settings::allow_message_wrap),
+    ("Breakable blocks hit one by one", Settings::allow_per_block_breaking),
+    ("NPC despawn limit", Settings::allow_despawn_limit),
+];
+
+impl Settings {
+    /// Engine fixes and mod features, on in the enhanced mode whatever the toggle says.
+    fn fix(&self, toggle: bool) -> bool {
+        match self.compat_mode {
+            CompatMode::Vanilla => false,
+            CompatMode::Enhanced => true,
+            CompatMode::Custom => toggle,
+        }
+    }
+
+    /// Assists, which follow their toggles unless the vanilla mode is on.
+    fn assist(&self, toggle: bool) -> bool {
+        self.compat_mode != CompatMode::Vanilla && toggle
+    }
+
+    /// Custom opcodes of the mods, see `TextScriptExtensions`.
+    pub fn allow_mod_tsc(&self) -> bool {
+        self.fix(true)
+    }
+
+    /// Currents from `StageEffectConsts::stage_currents`.
+    pub fn allow_stage_currents(&self) -> bool {
+        self.fix(true)
+    }
+
+    /// NPC bounds from `EngineConstants::npc_bounds_overrides`, applied when the NPC table is loaded.
+    pub fn allow_bounds_fixes(&self) -> bool {
+        self.fix(true)
+    }
+
+    /// NPCs flagged as buoyant in npc.tbl float up in water, see `NPC::apply_buoyancy`.
+    pub fn allow_npc_buoyancy(&self) -> bool {
+        self.fix(true)
+    }
+
+    /// The player sticks to the ground walking down slopes, see `PhysicalEntity::snap_to_ground`.
+    pub fn allow_slope_snapping(&self) -> bool {
+        self.fix(true)
+    }
+
+    /// Smoke and damage numbers play out while `<PRI` freezes the world, the original stops them with it.
+    pub fn allow_frozen_carets(&self) -> bool {
+        self.fix(true)
+    }
+
+    /// Some NPCs don't start their AI until they get near the camera, see `NPC::activates_in_view_only`.
+    pub fn allow_view_activation(&self) -> bool {
+        self.fix(true)
+    }
+
+    /// `<UNI0002`, which the original ignores.
+    pub fn allow_fixed_control(&self) -> bool {
+        self.fix(true)
+    }
+
+    /// Message lines wrap when they don't fit in the box, the original draws them past its edge.
+    pub fn allow_message_wrap(&self) -> bool {
+        self.fix(true)
+    }
+
+    /// Bullets only break the blocks they touch, the original breaks every one checked after
+    /// any block has been hit in the same tick.
+    pub fn allow_per_block_breaking(&self) -> bool {
+        self.fix(true)
+    }
+
+    /// NPCs with a lifetime are removed once it runs out, the original only removes them from their AIs.
+    pub fn allow_despawn_limit(&self) -> bool {
+        self.fix(true)
+    }
+
+    pub fn allow_exp_line_of_sight(&self) -> bool {
+        self.fix(self.exp_line_of_sight)
+    }
+
+    pub fn allow_softlock_recovery(&self) -> bool {
+        self.fix(self.softlock_recovery)
+    }
+
+    pub fn allow_lighting(&self) -> bool {
+        self.fix(self.lighting)
+    }
+
+    pub fn allow_quick_save(&self) -> bool {
+        self.assist(self.quick_save)
+    }
+
+    pub fn allow_rewind(&self) -> bool {
+        self.assist(self.rewind)
+    }
+
+    pub fn allow_auto_fire(&self) -> bool {
+        self.assist(self.accessibility.auto_fire)
+    }
+
+    fn allow_message_delay(&self) -> bool {
+        self.assist((self.accessibility.message_delay - 1.0).abs() > f32::EPSILON)
+    }
+
+    /// Multiplier of the delay between message characters, always 1.0 in the vanilla mode.
+    pub fn message_delay(&self) -> f32 {
+        if self.allow_message_delay() { self.accessibility.message_delay } else { 1.0 }
+    }
+
+    fn allow_60_tps(&self) -> bool {
+        self.assist(self.tps_60)
+    }
+
+    /// Ticks per second the game runs at, scenes can pin their own with `Scene::tps`.
+    pub fn tps(&self) -> u32 {
+        if self.allow_60_tps() { 60 } else { 50 }
+    }
+
+    /// `texture_memory_cap` in bytes.
+    pub fn texture_memory_cap(&self) -> Option<usize> {
+        self.texture_memory_cap.map(|cap| cap as usize * MIB)
+    }
+
+    /// `sfx_memory_cap` in bytes.
+    pub fn sfx_memory_cap(&self) -> Option<usize> {
+        self.sfx_memory_cap.map(|cap| cap as usize * KIB)
+    }
+
+    fn allow_transition_override(&self) -> bool {
+        self.assist(self.transition.is_some())
+    }
+
+    /// Debug warp back to the previous stage, not something the original has.
+    pub fn allow_stage_history_warp(&self) -> bool {
+        self.assist(true)
+    }
+
+    /// Transition replacing the ones scripts ask for, always None in the vanilla mode.
+    pub fn transition(&self) -> Option<TransitionType> {
+        if self.allow_transition_override() { self.transition } else { None }
+    }
+
+    fn path() -> GameResult<PathBuf> {
+        Ok(user_dirs()?.settings_path())
+    }
+
+    /// Loads the settings, falling back to defaults if they don't exist or can't be parsed.
+    pub fn load() -> Settings {
+        let result: GameResult<Settings> = Self::path()
+            .and_then(|path| Ok(fs::read_to_string(path)?))
+            .and_then(|data| Ok(toml::from_str(&data)?));
+
+        match result {
+            Ok(settings) => settings,
+            Err(e) => {
+                log::info!("Using default settings: {}", e);
+                Settings::default()
+            }
+        }
+    }
+
+    pub fn save(&self) -> GameResult {
+        let path = Self::path()?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_vanilla_mode() {
+    let mut settings = Settings {
+        exp_line_of_sight: true,
+        softlock_recovery: true,
+        lighting: true,
+        quick_save: true,
+        rewind: true,
+        tps_60: true,
+        transition: Some(TransitionType::Cut),
+        ..Settings::default()
+    };
+    settings.accessibility.auto_fire = true;
+    settings.accessibility.message_delay = 2.0;
+
+    settings.compat_mode = CompatMode::Custom;
+    for (name, allowed) in EXTENSIONS.iter() {
+        assert!(allowed(&settings), "{} is off with its toggle on", name);
+    }
+
+    settings.compat_mode = CompatMode::Vanilla;
+    for (name, allowed) in EXTENSIONS.iter() {
+        assert!(!allowed(&settings), "{} isn't turned off by the vanilla mode", name);
+    }
+
+    // fixes are forced on, assists still need their toggles
+    settings.compat_mode = CompatMode::Enhanced;
+    settings.lighting = false;
+    settings.rewind = false;
+    assert!(settings.allow_lighting());
+    assert!(!settings.allow_rewind());
+}
@@ -0,0 +1,436 @@
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use std::sync::mpsc::{Receiver, Sender};
+use std::time::{Duration, Instant};
+
+use cpal::Sample;
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+
+use crate::ggez::GameError::AudioError;
+use crate::ggez::GameResult;
+use crate::sound::{MemoryStats, PlaybackMessage, PlaybackState, VoiceStats};
+use crate::sound::organya::Song;
+use crate::sound::pixtone::PixTonePlayback;
+use crate::sound::playback::{PlaybackEngine, SavedPlaybackState};
+use crate::sound::wave_bank::SoundBank;
+use crate::str;
+
+/// Organya and PixTone are always rendered at this rate and resampled to whatever the device wants,
+/// so reopening the stream with a different format doesn't disturb the playback.
+pub const MIXER_SAMPLE_RATE: u32 = 44100;
+/// Device rates tried first, in order of preference.
+const PREFERRED_SAMPLE_RATES: [u32; 2] = [48000, 44100];
+
+/// Linear interpolating resampler, pulls samples from the source as needed.
+#[derive(Debug, Copy, Clone)]
+pub struct Resampler {
+    /// Source samples per output sample.
+    step: f64,
+    pos: f64,
+    prev: f32,
+    next: f32,
+}
+
+impl Resampler {
+    pub fn new(src_rate: u32, dst_rate: u32) -> Resampler {
+        Resampler {
+            step: src_rate as f64 / dst_rate as f64,
+            pos: 1.0,
+            prev: 0.0,
+            next: 0.0,
+        }
+    }
+
+    pub fn set_rates(&mut self, src_rate: u32, dst_rate: u32) {
+        self.step = src_rate as f64 / dst_rate as f64;
+    }
+
+    pub fn next_sample<F: FnMut() -> f32>(&mut self, mut source: F) -> f32 {
+        while self.pos >= 1.0 {
+            self.prev = self.next;
+            self.next = source();
+            self.pos -= 1.0;
+        }
+
+        let sample = self.prev + (self.next - self.prev) * self.pos as f32;
+        self.pos += self.step;
+        sample
+    }
+}
+
+/// Sequencer position published by the audio thread after every buffer, so the game can read it without locking.
+#[derive(Default)]
+pub struct SongPosition {
+    playing: AtomicBool,
+    step: AtomicU32,
+    steps_per_measure: AtomicU32,
+}
+
+impl SongPosition {
+    fn publish(&self, playing: bool, step: i32, steps_per_measure: u32) {
+        self.step.store(step.max(0) as u32, Ordering::Relaxed);
+        self.steps_per_measure.store(steps_per_measure, Ordering::Relaxed);
+        self.playing.store(playing, Ordering::Release);
+    }
+
+    /// (measure, step within the measure), None if nothing is playing.
+    pub fn sample(&self) -> Option<(u32, u32)> {
+        if !self.playing.load(Ordering::Acquire) {
+            return None;
+        }
+
+        let step = self.step.load(Ordering::Relaxed);
+        // broken headers with 0 beats or steps
+        let steps_per_measure = self.steps_per_measure.load(Ordering::Relaxed).max(1);
+        Some((step / steps_per_measure, step % steps_per_measure))
+    }
+}
+
+/// State of the playback shared between the streams, survives reopening the device.
+pub struct Mixer {
+    rx: Receiver<PlaybackMessage>,
+    bank: SoundBank,
+    engine: PlaybackEngine,
+    pixtone: PixTonePlayback,
+    state: PlaybackState,
+    saved_state: Option<SavedPlaybackState>,
+    speed: f32,
+    org_buf: Vec<u16>,
+    pxt_buf: Vec<u16>,
+    org_index: usize,
+    pxt_index: usize,
+    frames: usize,
+    resampler: Resampler,
+    position: Arc<SongPosition>,
+    voices: Arc<VoiceStats>,
+    memory: Arc<MemoryStats>,
+}
+
+impl Mixer {
+    pub(super) fn new(rx: Receiver<PlaybackMessage>, bank: SoundBank, position: Arc<SongPosition>,
+                      voices: Arc<VoiceStats>, memory: Arc<MemoryStats>) -> Mixer {
+        let mut engine = PlaybackEngine::new(Song::empty(), &bank);
+        let mut pixtone = PixTonePlayback::new();
+
+        engine.set_sample_rate(MIXER_SAMPLE_RATE as usize);
+        engine.loops = usize::MAX;
+
+        let mut org_buf = vec![0x8080; 441];
+        let mut pxt_buf = vec![0x8000; 441];
+        let frames = engine.render_to(&mut org_buf);
+        pixtone.mix(&mut pxt_buf, MIXER_SAMPLE_RATE as f32);
+
+        Mixer {
+            rx,
+            bank,
+            engine,
+            pixtone,
+            state: PlaybackState::Stopped,
+            saved_state: None,
+            speed: 1.0,
+            org_buf,
+            pxt_buf,
+            org_index: 0,
+            pxt_index: 0,
+            frames,
+            resampler: Resampler::new(MIXER_SAMPLE_RATE, MIXER_SAMPLE_RATE),
+            position,
+            voices,
+            memory,
+        }
+    }
+
+    pub fn set_device_rate(&mut self, sample_rate: u32) {
+        self.resampler.set_rates(MIXER_SAMPLE_RATE, sample_rate);
+    }
+
+    /// Bytes of the buffers the song and the sound effects are rendered into, and of the song's instruments.
+    fn stream_bytes(&self) -> usize {
+        (self.org_buf.len() + self.pxt_buf.len()) * 2 + self.engine.buffer_bytes()
+    }
+
+    fn rerender_song(&mut self) {
+        for i in &mut self.org_buf[0..self.frames] { *i = 0x8080 };
+        self.frames = self.engine.render_to(&mut self.org_buf);
+        self.org_index = 0;
+    }
+
+    fn process_messages(&mut self) {
+        loop {
+            match self.rx.try_recv() {
+                Ok(PlaybackMessage::PlaySong(song)) => {
+                    self.engine.start_song(*song, &self.bank);
+                    self.rerender_song();
+
+                    self.state = PlaybackState::Playing;
+                }
+                Ok(PlaybackMessage::PlaySample(id, priority)) => {
+                    self.pixtone.play_sfx(id, priority);
+                }
+                Ok(PlaybackMessage::InsertSample(id, sample)) => {
+                    self.pixtone.insert_rendered(id, sample);
+                }
+                Ok(PlaybackMessage::SetSfxMemoryCap(cap)) => {
+                    self.pixtone.memory_cap = cap;
+                    self.pixtone.evict_samples(None);
+                }
+                Ok(PlaybackMessage::Stop) => {
+                    self.state = PlaybackState::Stopped;
+                }
+                Ok(PlaybackMessage::SetSpeed(new_speed)) => {
+                    assert!(new_speed > 0.0);
+                    self.speed = new_speed;
+                    self.engine.set_sample_rate((MIXER_SAMPLE_RATE as f32 / new_speed) as usize);
+                }
+                Ok(PlaybackMessage::SaveState) => {
+                    self.saved_state = Some(self.engine.get_state());
+                }
+                Ok(PlaybackMessage::Seek(measure)) => {
+                    self.engine.set_position((measure * self.engine.steps_per_measure()) as i32);
+                    self.rerender_song();
+                }
+                Ok(PlaybackMessage::RestoreState) => {
+                    // kept around, recalling again restarts from the same position
+                    if let Some(saved) = self.saved_state.as_ref() {
+                        self.engine.set_state(saved.clone(), &self.bank);
+
+                        if self.state == PlaybackState::Stopped {
+                            self.engine.set_position(0);
+                        }
+
+                        self.rerender_song();
+                        self.state = PlaybackState::Playing;
+                    }
+                }
+                Err(_) => { break; }
+            }
+        }
+    }
+
+    /// Next sample at `MIXER_SAMPLE_RATE`.
+    fn source_sample(&mut self) -> f32 {
+        let org_sample: u16 = {
+            if self.state == PlaybackState::Stopped {
+                0x8000
+            } else if self.org_index < self.frames {
+                let sample = self.org_buf[self.org_index];
+                self.org_index += 1;
+                if self.org_index & 1 == 0 { (sample & 0xff) << 8 } else { sample & 0xff00 }
+            } else {
+                self.rerender_song();
+                let sample = self.org_buf[0];
+                (sample & 0xff) << 8
+            }
+        };
+        let pxt_sample: u16 = self.pxt_buf[self.pxt_index] ^ 0x8000;
+
+        if self.pxt_index < (self.pxt_buf.len() - 1) {
+            self.pxt_index += 1;
+        } else {
+            self.pxt_index = 0;
+            for i in self.pxt_buf.iter_mut() { *i = 0x8000 };
+            self.pixtone.mix(&mut self.pxt_buf, MIXER_SAMPLE_RATE as f32 / self.speed);
+            self.voices.publish(self.pixtone.voices.len(), self.pixtone.stolen, self.pixtone.dropped);
+            self.memory.publish(self.pixtone.memory.total(), self.stream_bytes());
+        }
+
+        let sample = org_sample.wrapping_add(pxt_sample);
+        (sample as i32 - 0x8000) as f32 / 32768.0
+    }
+
+    pub fn render<T: Sample>(&mut self, data: &mut [T], channels: usize) {
+        self.process_messages();
+
+        let mut resampler = self.resampler;
+        for frame in data.chunks_mut(channels) {
+            let value: T = Sample::from::<f32>(&resampler.next_sample(|| self.source_sample()));
+            for sample in frame.iter_mut() {
+                *sample = value;
+            }
+        }
+        self.resampler = resampler;
+
+        self.position.publish(self.state == PlaybackState::Playing, self.engine.get_position(), self.engine.steps_per_measure());
+    }
+}
+
+/// Picks a common rate within the range supported by the device.
+fn pick_sample_rate(min: u32, max: u32) -> u32 {
+    PREFERRED_SAMPLE_RATES.iter()
+        .copied()
+        .find(|rate| (min..=max).contains(rate))
+        .unwrap_or_else(|| PREFERRED_SAMPLE_RATES[0].max(min).min(max))
+}
+
+/// Prefers stereo, a common sample rate and floating point samples, in that order.
+fn negotiate_config(device: &cpal::Device) -> GameResult<cpal::SupportedStreamConfig> {
+    let format_rank = |format: cpal::SampleFormat| match format {
+        cpal::SampleFormat::F32 => 2,
+        cpal::SampleFormat::I16 => 1,
+        cpal::SampleFormat::U16 => 0,
+    };
+
+    let best = device.supported_output_configs()?
+        .map(|range| {
+            let rate = pick_sample_rate(range.min_sample_rate().0, range.max_sample_rate().0);
+            range.with_sample_rate(cpal::SampleRate(rate))
+        })
+        .max_by_key(|config| (config.channels() == 2,
+                              PREFERRED_SAMPLE_RATES.contains(&config.sample_rate().0),
+                              format_rank(config.sample_format())));
+
+    match best {
+        Some(config) => Ok(config),
+        None => Ok(device.default_output_config()?),
+    }
+}
+
+/// Opens the default output device, `lost` gets set once the device goes away.
+fn open_stream(mixer: &Arc<Mutex<Mixer>>, underruns: &Arc<AtomicUsize>, lost: &Arc<AtomicBool>) -> GameResult<cpal::Stream> {
+    let host = cpal::default_host();
+    let device = host.default_output_device().ok_or_else(|| AudioError(str!("No audio output device.")))?;
+    let config = negotiate_config(&device)?;
+
+    log::info!("Audio output: {} ({} Hz, {} channels, {:?})",
+               device.name().unwrap_or_else(|_| str!("unknown device")),
+               config.sample_rate().0, config.channels(), config.sample_format());
+
+    mixer.lock()?.set_device_rate(config.sample_rate().0);
+
+    let stream = match config.sample_format() {
+        cpal::SampleFormat::F32 => build_stream::<f32>(&device, &config.into(), mixer, underruns, lost),
+        cpal::SampleFormat::I16 => build_stream::<i16>(&device, &config.into(), mixer, underruns, lost),
+        cpal::SampleFormat::U16 => build_stream::<u16>(&device, &config.into(), mixer, underruns, lost),
+    }?;
+    stream.play()?;
+
+    Ok(stream)
+}
+
+fn build_stream<T: Sample>(device: &cpal::Device, config: &cpal::StreamConfig, mixer: &Arc<Mutex<Mixer>>,
+                           underruns: &Arc<AtomicUsize>, lost: &Arc<AtomicBool>) -> GameResult<cpal::Stream> {
+    let channels = config.channels as usize;
+    let sample_rate = config.sample_rate.0 as f64;
+    let mixer = mixer.clone();
+    let underruns = underruns.clone();
+    let lost = lost.clone();
+    // time of the previous callback and the length of the buffer it filled
+    let mut last_callback: Option<(Instant, Duration)> = None;
+
+    let err_fn = move |err: cpal::StreamError| {
+        log::error!("Audio stream error: {}", err);
+        if let cpal::StreamError::DeviceNotAvailable = err {
+            lost.store(true, Ordering::SeqCst);
+        }
+    };
+
+    Ok(device.build_output_stream(
+        config,
+        move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+            // cpal doesn't report underruns, but if the previous buffer has been played out
+            // well before we've been asked for more, the device must have run dry
+            let now = Instant::now();
+            if let Some((last, buffered)) = last_callback {
+                if now.duration_since(last) > buffered * 3 / 2 {
+                    underruns.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            last_callback = Some((now, Duration::from_secs_f64((data.len() / channels) as f64 / sample_rate)));
+
+            match mixer.lock() {
+                Ok(mut mixer) => mixer.render(data, channels),
+                Err(_) => {
+                    for sample in data.iter_mut() {
+                        *sample = Sample::from::<f32>(&0.0);
+                    }
+                }
+            }
+        },
+        err_fn,
+    )?)
+}
+
+/// Body of the audio thread, keeps a stream open on the default device and reopens it if the device disappears.
+/// `ready` gets the result of opening the first stream, without a device to begin with the thread ends right away.
+pub fn run(mixer: Mixer, underruns: Arc<AtomicUsize>, ready: Sender<Result<(), String>>) {
+    let mixer = Arc::new(Mutex::new(mixer));
+    let lost = Arc::new(AtomicBool::new(false));
+
+    let mut stream = match open_stream(&mixer, &underruns, &lost) {
+        Ok(stream) => {
+            let _ = ready.send(Ok(()));
+            stream
+        }
+        Err(err) => {
+            let _ = ready.send(Err(err.to_string()));
+            return;
+        }
+    };
+
+    loop {
+        while !lost.load(Ordering::SeqCst) {
+            std::thread::sleep(Duration::from_millis(100));
+        }
+
+        drop(stream);
+        log::warn!("Audio device has been disconnected, reopening the stream.");
+
+        stream = loop {
+            lost.store(false, Ordering::SeqCst);
+
+            match open_stream(&mixer, &underruns, &lost) {
+                Ok(stream) => break stream,
+                Err(err) => {
+                    log::error!("Cannot open the audio stream: {}", err);
+                    std::thread::sleep(Duration::from_secs(1));
+                }
+            }
+        };
+    }
+}
+
+#[test]
+fn test_resampler() {
+    let source: Vec<f32> = (0..16).map(|i| i as f32).collect();
+
+    // same rate, delayed by one sample
+    let mut resampler = Resampler::new(44100, 44100);
+    let mut iter = source.iter().copied();
+    let out: Vec<f32> = (0..4).map(|_| resampler.next_sample(|| iter.next().unwrap())).collect();
+    assert_eq!(out, vec![0.0, 0.0, 1.0, 2.0]);
+
+    // upsampling interpolates between the samples
+    let mut resampler = Resampler::new(22050, 44100);
+    let mut iter = source.iter().copied();
+    let out: Vec<f32> = (0..6).map(|_| resampler.next_sample(|| iter.next().unwrap())).collect();
+    assert_eq!(out, vec![0.0, 0.0, 0.0, 0.5, 1.0, 1.5]);
+
+    // downsampling skips them
+    let mut resampler = Resampler::new(88200, 44100);
+    let mut iter = source.iter().copied();
+    let out: Vec<f32> = (0..4).map(|_| resampler.next_sample(|| iter.next().unwrap())).collect();
+    assert_eq!(out, vec![0.0, 1.0, 3.0, 5.0]);
+}
+
+#[test]
+fn test_pick_sample_rate() {
+    assert_eq!(pick_sample_rate(8000, 192000), 48000);
+    assert_eq!(pick_sample_rate(44100, 44100), 44100);
+    assert_eq!(pick_sample_rate(96000, 192000), 96000);
+    assert_eq!(pick_sample_rate(8000, 22050), 22050);
+}
+
+#[test]
+fn test_song_position() {
+    let position = SongPosition::default();
+    assert_eq!(position.sample(), None);
+
+    position.publish(true, 37, 16);
+    assert_eq!(position.sample(), Some((2, 5)));
+
+    position.publish(true, 5, 0);
+    assert_eq!(position.sample(), Some((5, 0)));
+
+    position.publish(false, 37, 16);
+    assert_eq!(position.sample(), None);
+}
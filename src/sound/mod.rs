@@ -1,19 +1,18 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::mpsc;
 use std::sync::mpsc::{Receiver, Sender};
-
-use bitflags::_core::time::Duration;
-use cpal::Sample;
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use std::time::Instant;
 
 use crate::engine_constants::EngineConstants;
 use crate::ggez::{Context, filesystem, GameResult};
 use crate::ggez::GameError::{AudioError, InvalidValue, ResourceLoadError};
+use crate::sound::mixer::{Mixer, SongPosition};
 use crate::sound::organya::Song;
-use crate::sound::pixtone::PixTonePlayback;
-use crate::sound::playback::{PlaybackEngine, SavedPlaybackState};
-use crate::sound::wave_bank::SoundBank;
 use crate::str;
 
+mod mixer;
 mod wave_bank;
 mod organya;
 mod pixtone;
@@ -22,10 +21,116 @@ mod playback;
 mod stuff;
 mod wav;
 
+/// Handle to the audio thread. Without an audio device it keeps the song bookkeeping but plays nothing,
+/// `open_device` can try again later without anyone having to replace the handle.
 pub struct SoundManager {
-    tx: Sender<PlaybackMessage>,
-    prev_song_id: usize,
-    current_song_id: usize,
+    /// None while there's no audio thread.
+    tx: Option<Sender<PlaybackMessage>>,
+    song_state: SongState,
+    /// Counted by the audio thread, shown in the performance HUD.
+    underruns: Arc<AtomicUsize>,
+    position: Arc<SongPosition>,
+    /// Sound effect ids played without a sound, logged once each.
+    missing_sfx: HashSet<u8>,
+    sfx_priorities: HashMap<u8, SfxPriority>,
+    voices: Arc<VoiceStats>,
+    memory: Arc<MemoryStats>,
+    /// Sent again to the audio thread when the device is opened later.
+    sfx_memory_cap: Option<usize>,
+    speed: f32,
+    /// The missing device is logged once, retries aren't worth another warning.
+    warned: bool,
+    /// Set by the loading scene, the sound effects get rendered once a device is opened later if there was none.
+    prerender_requested: bool,
+}
+
+/// Decides which sound effects get cut off when all the voices are busy, see `SoundConsts::sfx_priorities`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SfxPriority {
+    /// Footsteps, text blips and pickups, spammed a lot and fine to lose.
+    Low,
+    Normal,
+    /// Player damage and boss sounds, which should never be cut off by anything else.
+    High,
+}
+
+/// Sound effect voice usage, published by the audio thread after every buffer for the performance HUD.
+#[derive(Default)]
+pub struct VoiceStats {
+    active: AtomicUsize,
+    peak: AtomicUsize,
+    stolen: AtomicUsize,
+    dropped: AtomicUsize,
+}
+
+/// Snapshot of `VoiceStats`.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct VoiceUsage {
+    pub active: usize,
+    pub peak: usize,
+    pub max: usize,
+    pub stolen: usize,
+    pub dropped: usize,
+}
+
+impl VoiceStats {
+    fn publish(&self, active: usize, stolen: usize, dropped: usize) {
+        self.active.store(active, Ordering::Relaxed);
+        self.peak.fetch_max(active, Ordering::Relaxed);
+        self.stolen.store(stolen, Ordering::Relaxed);
+        self.dropped.store(dropped, Ordering::Relaxed);
+    }
+}
+
+/// Memory used by the audio thread, published after every buffer for the performance HUD.
+#[derive(Default)]
+pub struct MemoryStats {
+    sfx_bytes: AtomicUsize,
+    stream_bytes: AtomicUsize,
+}
+
+impl MemoryStats {
+    fn publish(&self, sfx_bytes: usize, stream_bytes: usize) {
+        self.sfx_bytes.store(sfx_bytes, Ordering::Relaxed);
+        self.stream_bytes.store(stream_bytes, Ordering::Relaxed);
+    }
+}
+
+/// Song bookkeeping behind <CMU, <FMU and <RMU, matching vanilla's ChangeMusic/ReCallMusic.
+/// The position of the previous song is remembered by the playback thread.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct SongState {
+    current: usize,
+    /// Song playing before the last change, every change overwrites it (including switching to silence),
+    /// so <RMU after two <CMUs in a row goes back to the second to last song, not to the stage theme.
+    prev: usize,
+}
+
+impl SongState {
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    pub fn prev(&self) -> usize {
+        self.prev
+    }
+
+    /// Returns false if the song is already playing, in which case nothing changes.
+    pub fn change(&mut self, song_id: usize) -> bool {
+        if song_id != 0 && song_id == self.current {
+            return false;
+        }
+
+        self.prev = self.current;
+        self.current = song_id;
+        true
+    }
+
+    /// Goes back to the previous song, which is kept as the previous one so recalling twice is harmless.
+    pub fn recall(&mut self) -> usize {
+        self.current = self.prev;
+        self.current
+    }
 }
 
 static SONGS: [&str; 43] = [
@@ -75,77 +180,272 @@ static SONGS: [&str; 43] = [
 ];
 
 impl SoundManager {
-    pub fn new(ctx: &mut Context) -> GameResult<SoundManager> {
-        let (tx, rx): (Sender<PlaybackMessage>, Receiver<PlaybackMessage>) = mpsc::channel();
+    /// Doesn't play anything until `open_device` succeeds.
+    pub fn new(constants: &EngineConstants) -> SoundManager {
+        SoundManager {
+            tx: None,
+            song_state: SongState::default(),
+            underruns: Arc::new(AtomicUsize::new(0)),
+            position: Arc::new(SongPosition::default()),
+            missing_sfx: HashSet::new(),
+            sfx_priorities: constants.sound.sfx_priorities.clone(),
+            voices: Arc::new(VoiceStats::default()),
+            memory: Arc::new(MemoryStats::default()),
+            sfx_memory_cap: None,
+            speed: 1.0,
+            warned: false,
+            prerender_requested: false,
+        }
+    }
+
+    /// Whether there's an audio device playing the sounds.
+    pub fn is_available(&self) -> bool {
+        self.tx.is_some()
+    }
+
+    /// Starts the audio thread on the default device if it's not running yet, the current song starts playing
+    /// from the beginning. A missing device isn't an error, the game just stays silent.
+    pub fn open_device(&mut self, ctx: &mut Context, constants: &EngineConstants) -> bool {
+        if self.is_available() {
+            return true;
+        }
+
+        if let Err(err) = self.start_thread(ctx) {
+            if !self.warned {
+                log::warn!("Audio is not available, continuing without sound: {}", err);
+                self.warned = true;
+            } else {
+                log::info!("Audio is still not available: {}", err);
+            }
+            return false;
+        }
+
+        self.send(PlaybackMessage::SetSfxMemoryCap(self.sfx_memory_cap));
+        self.send(PlaybackMessage::SetSpeed(self.speed));
+        if self.prerender_requested {
+            self.start_prerender();
+        }
+
+        let song_id = self.song_state.current();
+        if song_id != 0 {
+            match self.load_song(song_id, constants, ctx) {
+                Ok(org) => self.send(PlaybackMessage::PlaySong(Box::new(org))),
+                Err(e) => log::warn!("Cannot resume the BGM: {}", e),
+            }
+        }
+
+        true
+    }
 
-        let host = cpal::default_host();
-        let device = host.default_output_device().ok_or_else(|| AudioError(str!("Error initializing audio device.")))?;
-        let config = device.default_output_config()?;
+    fn start_thread(&mut self, ctx: &mut Context) -> GameResult {
+        let (tx, rx): (Sender<PlaybackMessage>, Receiver<PlaybackMessage>) = mpsc::channel();
+        let (ready_tx, ready_rx) = mpsc::channel();
 
         let bnk = wave_bank::SoundBank::load_from(filesystem::open(ctx, "/builtin/pixtone.pcm")?)?;
 
+        // the stream is opened on the audio thread, cpal streams can't be sent between threads on every platform
+        let thread_underruns = self.underruns.clone();
+        let thread_position = self.position.clone();
+        let thread_voices = self.voices.clone();
+        let thread_memory = self.memory.clone();
+        std::thread::spawn(move || {
+            mixer::run(Mixer::new(rx, bnk, thread_position, thread_voices, thread_memory), thread_underruns, ready_tx);
+        });
+
+        match ready_rx.recv() {
+            Ok(Ok(())) => {
+                self.tx = Some(tx);
+                Ok(())
+            }
+            Ok(Err(err)) => Err(AudioError(err)),
+            Err(_) => Err(AudioError(str!("The audio thread has crashed."))),
+        }
+    }
+
+    /// Sound effects for the loading workers to render, the common ones first, handed over with `insert_sfx`.
+    /// Sounds played before they're in are rendered on demand by the mixer. Done only once, the sound effects
+    /// are builtin so switching mods doesn't change them. Without a device they're rendered once it's opened.
+    pub fn take_prerender(&mut self) -> Vec<u8> {
+        if self.prerender_requested {
+            return Vec::new();
+        }
+
+        self.prerender_requested = true;
+        if self.is_available() { pixtone::render_order() } else { Vec::new() }
+    }
+
+    /// Synthesizes a sound effect, can be done on any thread.
+    pub fn render_sfx(id: u8) -> Vec<i16> {
+        pixtone::render_sfx(id)
+    }
+
+    /// Hands a sound effect rendered with `render_sfx` over to the mixer.
+    pub fn insert_sfx(&mut self, id: u8, sample: Vec<i16>) {
+        self.send(PlaybackMessage::InsertSample(id, sample));
+    }
+
+    /// Renders the sound effects on a thread of their own for a device opened after the loading.
+    fn start_prerender(&self) {
+        let tx = match self.tx.as_ref() {
+            Some(tx) => tx.clone(),
+            None => { return; }
+        };
+        let cap = self.sfx_memory_cap;
+
         std::thread::spawn(move || {
-            if let Err(err) = match config.sample_format() {
-                cpal::SampleFormat::F32 => run::<f32>(rx, bnk, &device, &config.into()),
-                cpal::SampleFormat::I16 => run::<i16>(rx, bnk, &device, &config.into()),
-                cpal::SampleFormat::U16 => run::<u16>(rx, bnk, &device, &config.into()),
-            } {
-                log::error!("Something went wrong in audio thread: {}", err);
+            let start = Instant::now();
+            let mut bytes = 0;
+
+            for id in pixtone::render_order() {
+                // anything over the cap would be evicted right away
+                if cap.is_some_and(|cap| bytes >= cap) {
+                    break;
+                }
+
+                let sample = pixtone::render_sfx(id);
+                bytes += sample.len() * 2;
+                if tx.send(PlaybackMessage::InsertSample(id, sample)).is_err() {
+                    return;
+                }
             }
+
+            log::debug!("Rendered the sound effects in {}ms.", start.elapsed().as_millis());
         });
+    }
+
+    /// Messages to a thread which is gone turn the manager into a silent one.
+    fn send(&mut self, message: PlaybackMessage) {
+        if let Some(tx) = self.tx.as_ref() {
+            if tx.send(message).is_err() {
+                log::warn!("The audio thread is gone, continuing without sound.");
+                self.tx = None;
+            }
+        }
+    }
+
+    /// Bytes of the rendered sound effects and of the song buffers, as of the last buffer mixed.
+    pub fn memory_usage(&self) -> (usize, usize) {
+        (self.memory.sfx_bytes.load(Ordering::Relaxed), self.memory.stream_bytes.load(Ordering::Relaxed))
+    }
+
+    pub fn sfx_memory_cap(&self) -> Option<usize> {
+        self.sfx_memory_cap
+    }
+
+    /// Soft cap of the rendered sound effects, the rarely used ones are rendered again when played over it.
+    pub fn set_sfx_memory_cap(&mut self, cap: Option<usize>) {
+        self.sfx_memory_cap = cap;
+        self.send(PlaybackMessage::SetSfxMemoryCap(cap));
+    }
+
+    pub fn voice_usage(&self) -> VoiceUsage {
+        VoiceUsage {
+            active: self.voices.active.load(Ordering::Relaxed),
+            peak: self.voices.peak.load(Ordering::Relaxed),
+            max: pixtone::MAX_VOICES,
+            stolen: self.voices.stolen.load(Ordering::Relaxed),
+            dropped: self.voices.dropped.load(Ordering::Relaxed),
+        }
+    }
 
-        Ok(SoundManager {
-            tx: tx.clone(),
-            prev_song_id: 0,
-            current_song_id: 0,
-        })
+    /// Number of times the audio device ran out of samples since the start.
+    pub fn underruns(&self) -> usize {
+        self.underruns.load(Ordering::Relaxed)
     }
 
+    /// Unknown ids are silent.
     pub fn play_sfx(&mut self, id: u8) {
-        self.tx.send(PlaybackMessage::PlaySample(id));
+        if !pixtone::has_sfx(id) {
+            if self.missing_sfx.insert(id) {
+                log::warn!("Sound effect {} does not exist.", id);
+            }
+            return;
+        }
+
+        let priority = self.sfx_priorities.get(&id).copied().unwrap_or(SfxPriority::Normal);
+        self.send(PlaybackMessage::PlaySample(id, priority));
     }
 
+    fn load_song(&self, song_id: usize, constants: &EngineConstants, ctx: &mut Context) -> GameResult<Song> {
+        let song_name = SONGS.get(song_id).ok_or_else(|| InvalidValue(format!("BGM {} does not exist.", song_id)))?;
+        let path = constants.organya_paths
+            .iter()
+            .map(|prefix| [prefix, &song_name.to_lowercase(), ".org"].join(""))
+            .find(|path| filesystem::exists(ctx, path))
+            .ok_or_else(|| ResourceLoadError(format!("BGM {:?} does not exist.", song_name)))?;
+
+        let org = organya::Song::load_from(filesystem::open(ctx, path)?)?;
+        log::info!("Playing BGM: {}", song_name);
+        Ok(org)
+    }
+
+    /// The songs are loaded even without an audio device, so missing ones fail the same way.
     pub fn play_song(&mut self, song_id: usize, constants: &EngineConstants, ctx: &mut Context) -> GameResult {
-        if self.current_song_id == song_id {
+        if song_id != 0 && song_id == self.song_state.current() {
             return Ok(());
         }
 
         if song_id == 0 {
             log::info!("Stopping BGM");
 
-            self.prev_song_id = self.current_song_id;
-            self.current_song_id = 0;
-
-            self.tx.send(PlaybackMessage::SaveState)?;
-            self.tx.send(PlaybackMessage::Stop)?;
-        } else if let Some(song_name) = SONGS.get(song_id) {
-            let path = constants.organya_paths
-                .iter()
-                .map(|prefix| [prefix, &song_name.to_lowercase(), ".org"].join(""))
-                .find(|path| filesystem::exists(ctx, path))
-                .ok_or_else(|| ResourceLoadError(format!("BGM {:?} does not exist.", song_name)))?;
-
-            let org = organya::Song::load_from(filesystem::open(ctx, path)?)?;
-            log::info!("Playing BGM: {}", song_name);
-
-            self.prev_song_id = self.current_song_id;
-            self.current_song_id = song_id;
-            self.tx.send(PlaybackMessage::SaveState)?;
-            self.tx.send(PlaybackMessage::PlaySong(Box::new(org)))?;
+            self.song_state.change(0);
+            self.send(PlaybackMessage::SaveState);
+            self.send(PlaybackMessage::Stop);
+        } else if song_id < SONGS.len() {
+            let org = self.load_song(song_id, constants, ctx)?;
+
+            self.song_state.change(song_id);
+            self.send(PlaybackMessage::SaveState);
+            self.send(PlaybackMessage::PlaySong(Box::new(org)));
         }
         Ok(())
     }
 
-    pub fn save_state(&mut self) -> GameResult {
-        self.tx.send(PlaybackMessage::SaveState)?;
-        self.prev_song_id = self.current_song_id;
+    pub fn song_id(&self) -> usize {
+        self.song_state.current()
+    }
+
+    /// Names of all songs, indexed by id.
+    pub fn song_names() -> &'static [&'static str] {
+        &SONGS
+    }
+
+    pub fn sfx_count() -> usize {
+        pixtone::sfx_count()
+    }
+
+    /// (measure, step) of the sequencer, as of the last buffer sent to the device.
+    /// None while no song is playing.
+    pub fn song_position(&self) -> Option<(u32, u32)> {
+        self.position.sample()
+    }
+
+    /// Jumps to the start of a measure of the current song.
+    pub fn seek(&mut self, measure: u32) -> GameResult {
+        self.send(PlaybackMessage::Seek(measure));
+
+        Ok(())
+    }
+
+    pub fn song_state(&self) -> SongState {
+        self.song_state
+    }
+
+    /// <FMU, the song id stays the same, so <CMU with the same song afterwards doesn't restart it (same as vanilla).
+    // todo: actually fade out instead of stopping
+    pub fn fade_out_song(&mut self) -> GameResult {
+        self.send(PlaybackMessage::Stop);
 
         Ok(())
     }
 
-    pub fn restore_state(&mut self) -> GameResult {
-        self.tx.send(PlaybackMessage::RestoreState)?;
-        self.current_song_id = self.prev_song_id;
+    /// <RMU, resumes the previous song from the position it has been changed at.
+    pub fn recall_song(&mut self) -> GameResult {
+        if self.song_state.recall() == 0 {
+            self.send(PlaybackMessage::Stop);
+        } else {
+            self.send(PlaybackMessage::RestoreState);
+        }
 
         Ok(())
     }
@@ -154,7 +454,8 @@ impl SoundManager {
         if speed <= 0.0 {
             return Err(InvalidValue(str!("Speed must be bigger than 0.0!")));
         }
-        self.tx.send(PlaybackMessage::SetSpeed(speed))?;
+        self.speed = speed;
+        self.send(PlaybackMessage::SetSpeed(speed));
 
         Ok(())
     }
@@ -163,8 +464,11 @@ impl SoundManager {
 enum PlaybackMessage {
     Stop,
     PlaySong(Box<Song>),
-    PlaySample(u8),
+    PlaySample(u8, SfxPriority),
+    InsertSample(u8, Vec<i16>),
+    SetSfxMemoryCap(Option<usize>),
     SetSpeed(f32),
+    Seek(u32),
     SaveState,
     RestoreState,
 }
@@ -175,120 +479,49 @@ enum PlaybackState {
     Playing,
 }
 
-fn run<T>(rx: Receiver<PlaybackMessage>, bank: SoundBank,
-          device: &cpal::Device, config: &cpal::StreamConfig) -> GameResult where
-    T: cpal::Sample,
-{
-    let sample_rate = config.sample_rate.0 as f32;
-    let channels = config.channels as usize;
-    let mut state = PlaybackState::Stopped;
-    let mut saved_state: Option<SavedPlaybackState> = None;
-    let mut speed = 1.0;
-    let mut engine = PlaybackEngine::new(Song::empty(), &bank);
-    let mut pixtone = PixTonePlayback::new();
-    pixtone.create_samples();
-
-
-    log::info!("Audio format: {} {}", sample_rate, channels);
-    engine.set_sample_rate(sample_rate as usize);
-    engine.loops = usize::MAX;
-
-    let mut org_buf = vec![0x8080; 441];
-    let mut pxt_buf = vec![0x8000; 441];
-    let mut org_index = 0;
-    let mut pxt_index = 0;
-    let mut frames = engine.render_to(&mut org_buf);
-    pixtone.mix(&mut pxt_buf, sample_rate);
-
-    let err_fn = |err| eprintln!("an error occurred on stream: {}", err);
-
-    let stream = device.build_output_stream(
-        config,
-        move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
-            loop {
-                match rx.try_recv() {
-                    Ok(PlaybackMessage::PlaySong(song)) => {
-                        engine.start_song(*song, &bank);
-
-                        for i in &mut org_buf[0..frames] { *i = 0x8080 };
-                        frames = engine.render_to(&mut org_buf);
-                        org_index = 0;
-
-                        state = PlaybackState::Playing;
-                    }
-                    Ok(PlaybackMessage::PlaySample(id)) => {
-                        pixtone.play_sfx(id);
-                    }
-                    Ok(PlaybackMessage::Stop) => {
-                        state = PlaybackState::Stopped;
-                    }
-                    Ok(PlaybackMessage::SetSpeed(new_speed)) => {
-                        assert!(new_speed > 0.0);
-                        speed = new_speed;
-                        engine.set_sample_rate((sample_rate / new_speed) as usize);
-                    }
-                    Ok(PlaybackMessage::SaveState) => {
-                        saved_state = Some(engine.get_state());
-                    }
-                    Ok(PlaybackMessage::RestoreState) => {
-                        if saved_state.is_some() {
-                            engine.set_state(saved_state.clone().unwrap(), &bank);
-                            saved_state = None;
-
-                            if state == PlaybackState::Stopped {
-                                engine.set_position(0);
-                            }
-
-                            for i in &mut org_buf[0..frames] { *i = 0x8080 };
-                            frames = engine.render_to(&mut org_buf);
-                            org_index = 0;
-
-                            state = PlaybackState::Playing;
-                        }
-                    }
-                    Err(_) => { break; }
-                }
-            }
-
-            for frame in data.chunks_mut(channels) {
-                let org_sample: u16 = {
-                    if state == PlaybackState::Stopped {
-                        0x8000
-                    } else if org_index < frames {
-                        let sample = org_buf[org_index];
-                        org_index += 1;
-                        if org_index & 1 == 0 { (sample & 0xff) << 8 } else { sample & 0xff00 }
-                    } else {
-                        for i in &mut org_buf[0..frames] { *i = 0x8080 };
-                        frames = engine.render_to(&mut org_buf);
-                        org_index = 0;
-                        let sample = org_buf[0];
-                        (sample & 0xff) << 8
-                    }
-                };
-                let pxt_sample: u16 = pxt_buf[pxt_index] ^ 0x8000;
-
-                if pxt_index < (pxt_buf.len() - 1) {
-                    pxt_index += 1;
-                } else {
-                    pxt_index = 0;
-                    for i in pxt_buf.iter_mut() { *i = 0x8000 };
-                    pixtone.mix(&mut pxt_buf, sample_rate / speed);
-                }
-
-                let sample = org_sample.wrapping_add(pxt_sample);
-
-                let value: T = Sample::from::<u16>(&sample);
-                for sample in frame.iter_mut() {
-                    *sample = value;
-                }
-            }
-        },
-        err_fn,
-    )?;
-    stream.play()?;
+#[test]
+fn test_song_state_balfrog() {
+    // Grasstown theme, Gravity, victory jingle
+    let (stage, boss, victory) = (5, 4, 15);
+    let mut songs = SongState::default();
+    songs.change(stage);
+
+    assert!(songs.change(boss));
+    assert!(!songs.change(boss));
+    assert_eq!(songs.prev(), stage);
+
+    songs.change(victory);
+    assert_eq!(songs.recall(), boss);
+    // recalling again doesn't go further back
+    assert_eq!(songs.recall(), boss);
+
+    // death mid-fight, game over theme and the profile song restored on retry
+    let mut songs = SongState::default();
+    songs.change(stage);
+    songs.change(boss);
+    songs.change(3);
+    songs.change(stage);
+    assert_eq!((songs.current(), songs.prev()), (stage, 3));
+}
 
-    loop {
-        std::thread::sleep(Duration::from_millis(4));
-    }
+#[test]
+fn test_song_state_omega() {
+    // stage theme, boss theme, silence, item jingle
+    let (stage, boss) = (25, 7);
+    let mut songs = SongState::default();
+    songs.change(stage);
+    songs.change(boss);
+
+    // <CMU0000 counts as a change, so the jingle recalls the silence instead of the boss theme
+    songs.change(0);
+    songs.change(10);
+    assert_eq!(songs.recall(), 0);
+
+    songs.change(stage);
+    assert_eq!((songs.current(), songs.prev()), (stage, 0));
+
+    // silence can be "changed" to even when already silent
+    assert!(songs.change(0));
+    assert!(songs.change(0));
+    assert_eq!(songs.prev(), 0);
 }
@@ -72,6 +72,7 @@ pub struct Note {
 pub struct Song {
     pub version: Version,
     pub time: Timing,
+    pub display: Display,
     pub tracks: [Track; 16]
 }
 
@@ -80,6 +81,7 @@ impl Clone for Song {
         Song {
             version: self.version,
             time: self.time,
+            display: self.display,
             tracks: self.tracks.clone(),
         }
     }
@@ -93,6 +95,7 @@ impl Song {
         Song {
             version: Version::Main,
             time: Timing { wait: 8, loop_range: LoopRange { start: 0, end: 1 } },
+            display: Display { beats: 4, steps: 4 },
             tracks: [
                 Track { inst: Instrument {freq: 1000, inst: 0, pipi: 0, notes: 0}, notes: vec![] },
                 Track { inst: Instrument {freq: 1000, inst: 0, pipi: 0, notes: 0}, notes: vec![] },
@@ -128,8 +131,8 @@ impl Song {
             };
         
         let wait  = f.read_u16::<LE>()?;
-        let _bpm  = f.read_u8()?;
-        let _spb  = f.read_u8()?;
+        let beats = f.read_u8()?;
+        let steps = f.read_u8()?;
         let start = f.read_i32::<LE>()?;
         let end   = f.read_i32::<LE>()?;
         
@@ -217,6 +220,7 @@ impl Song {
                     end
                 }
             },
+            display: Display { beats, steps },
             tracks
         };
         
@@ -5,7 +5,10 @@ use vec_mut_scan::VecMutScan;
 
 use lazy_static::lazy_static;
 
+use crate::builtin_fs;
+use crate::memory::{format_bytes, MemoryTracker};
 use crate::sound::pixtone_sfx::PIXTONE_TABLE;
+use crate::sound::SfxPriority;
 use crate::sound::stuff::cubic_interp;
 
 lazy_static! {
@@ -17,7 +20,7 @@ lazy_static! {
         let mut square = [0i8; 0x100];
         let mut random = [0i8; 0x100];
 
-        let ref_data = include_bytes!("pixtone_ref.dat");
+        let ref_data = builtin_fs::decompress(include_bytes!("pixtone_ref.dat.z")).expect("Corrupted PixTone waveforms.");
         unsafe {
             sine.copy_from_slice(&*(&ref_data[0..0x100] as *const [u8] as *const [i8]));
             triangle.copy_from_slice(&*(&ref_data[0x100..0x200] as *const [u8] as *const [i8]));
@@ -47,7 +50,7 @@ lazy_static! {
 
 /*#[test]
 fn test_waveforms() {
-    let reference = include_bytes!("pixtone_ref.dat");
+    let reference = builtin_fs::decompress(include_bytes!("pixtone_ref.dat.z")).unwrap();
 
     for n in 1..(WAVEFORMS.len()) {
         for (i, &val) in WAVEFORMS[n].iter().enumerate() {
@@ -220,73 +223,197 @@ impl PixToneParameters {
     }
 }
 
+/// Sound effects played at once, further ones steal a voice or are dropped.
+pub const MAX_VOICES: usize = 16;
+
 #[derive(Copy, Clone, PartialEq)]
-pub struct PlaybackState(u8, f32, u32);
+pub struct Voice {
+    id: u8,
+    pos: f32,
+    tag: u32,
+    priority: SfxPriority,
+    /// Order the voices have been (re)started in, the oldest one is stolen first.
+    serial: u32,
+}
+
+/// Whether there's a synthesized sound for given id.
+pub fn has_sfx(id: u8) -> bool {
+    (id as usize) < PIXTONE_TABLE.len()
+}
+
+pub fn sfx_count() -> usize {
+    PIXTONE_TABLE.len()
+}
+
+/// Rendered before the others, they're heard on the title screen or in the first seconds of playing:
+/// menu cursor and select, text blip, jump, head bump, landing, damage and the Polar Star shot.
+const COMMON_SFX: [u8; 8] = [1, 18, 2, 15, 3, 23, 16, 32];
+
+/// Order the sound effects are rendered in the background, the common ones first and then the rest by id.
+pub fn render_order() -> Vec<u8> {
+    let mut order: Vec<u8> = COMMON_SFX.iter().copied().filter(|&id| has_sfx(id)).collect();
+    order.extend((0..sfx_count()).map(|id| id as u8).filter(|id| !COMMON_SFX.contains(id)));
+    order
+}
+
+/// Synthesizes a sound effect, doesn't touch any playback state so it can be done on any thread.
+pub fn render_sfx(id: u8) -> Vec<i16> {
+    PIXTONE_TABLE[id as usize].synth()
+}
 
 pub struct PixTonePlayback {
+    /// Changed only through `insert_sample`/`evict_samples`, which keep `memory` up to date.
     pub samples: HashMap<u8, Vec<i16>>,
-    pub playback_state: Vec<PlaybackState>,
+    /// Bytes of the rendered samples.
+    pub memory: MemoryTracker<u8>,
+    /// Soft cap of `memory`, the least recently played samples are dropped and rendered again when needed.
+    /// Nothing is rendered up front, the samples come from the background renderer or are rendered when first played.
+    pub memory_cap: Option<usize>,
+    pub voices: Vec<Voice>,
+    /// Sounds which have cut off a lower or equal priority one, since the start.
+    pub stolen: usize,
+    /// Sounds which haven't been played since all the voices had a higher priority, since the start.
+    pub dropped: usize,
+    serial: u32,
 }
 
 impl PixTonePlayback {
     pub fn new() -> PixTonePlayback {
         PixTonePlayback {
             samples: HashMap::new(),
-            playback_state: vec![],
+            memory: MemoryTracker::new(),
+            memory_cap: None,
+            voices: Vec::with_capacity(MAX_VOICES),
+            stolen: 0,
+            dropped: 0,
+            serial: 0,
         }
     }
 
-    pub fn create_samples(&mut self) {
-        for (i, params) in PIXTONE_TABLE.iter().enumerate() {
-            self.samples.insert(i as u8, params.synth());
+    fn insert_sample(&mut self, id: u8, sample: Vec<i16>) {
+        self.memory.insert(id, sample.len() * 2, false);
+        self.samples.insert(id, sample);
+    }
+
+    /// Takes a sample from the background renderer, unless it's been rendered on demand in the meantime.
+    pub fn insert_rendered(&mut self, id: u8, sample: Vec<i16>) {
+        if self.samples.contains_key(&id) {
+            return;
         }
+
+        self.insert_sample(id, sample);
+        self.evict_samples(None);
     }
 
-    pub fn play_sfx(&mut self, id: u8) {
-        for state in self.playback_state.iter_mut() {
-            if state.0 == id && state.2 == 0 {
-                state.1 = 0.0;
-                break;
-            }
+    /// Renders only this sample if it's not there, because the background renderer hasn't got to it yet
+    /// or because it has been evicted. The other sounds keep playing, just this one starts a bit late.
+    fn prepare_sample(&mut self, id: u8) {
+        if !has_sfx(id) {
+            return;
+        }
+
+        if self.samples.contains_key(&id) {
+            self.memory.touch(&id);
+        } else {
+            log::debug!("Rendering sound effect {} on demand.", id);
+            self.insert_sample(id, render_sfx(id));
+            self.evict_samples(Some(id));
+        }
+    }
+
+    /// Drops the least recently played samples over the cap, except for the playing ones and `keep`.
+    pub fn evict_samples(&mut self, keep: Option<u8>) {
+        let cap = match self.memory_cap {
+            Some(cap) => cap,
+            None => { return; }
+        };
+
+        let voices = &self.voices;
+        let victims = self.memory.over_cap(cap, |&id| Some(id) == keep || voices.iter().any(|voice| voice.id == id));
+        if !victims.is_empty() {
+            log::debug!("Dropped {} sound effects to stay under {}.", victims.len(), format_bytes(cap));
+        }
+
+        for id in victims {
+            self.samples.remove(&id);
+            self.memory.remove(&id);
+        }
+    }
+
+    /// A sound already playing is restarted instead of stacking, like vanilla does.
+    pub fn play_sfx(&mut self, id: u8, priority: SfxPriority) {
+        self.prepare_sample(id);
+        self.serial = self.serial.wrapping_add(1);
+
+        if let Some(voice) = self.voices.iter_mut().find(|voice| voice.id == id && voice.tag == 0) {
+            voice.pos = 0.0;
+            voice.serial = self.serial;
+            return;
         }
 
-        self.playback_state.push(PlaybackState(id, 0.0, 0));
+        self.start_voice(Voice { id, pos: 0.0, tag: 0, priority, serial: self.serial });
     }
 
     pub fn play_concurrent(&mut self, id: u8, tag: u32) {
-        self.playback_state.push(PlaybackState(id, 0.0, tag));
+        self.prepare_sample(id);
+        self.serial = self.serial.wrapping_add(1);
+        self.start_voice(Voice { id, pos: 0.0, tag, priority: SfxPriority::Normal, serial: self.serial });
+    }
+
+    /// Takes a free voice, or the oldest one of the lowest priority, never one with a higher priority.
+    fn start_voice(&mut self, voice: Voice) {
+        if self.voices.len() < MAX_VOICES {
+            self.voices.push(voice);
+            return;
+        }
+
+        let serial = self.serial;
+        let victim = self.voices.iter_mut()
+            .filter(|v| v.priority <= voice.priority)
+            // age relative to the current serial, so it survives wrapping around
+            .min_by_key(|v| (v.priority, std::cmp::Reverse(serial.wrapping_sub(v.serial))));
+
+        match victim {
+            Some(victim) => {
+                *victim = voice;
+                self.stolen += 1;
+            }
+            None => {
+                self.dropped += 1;
+            }
+        }
     }
 
     pub fn mix(&mut self, dst: &mut [u16], sample_rate: f32) {
-        let mut scan = VecMutScan::new(&mut self.playback_state);
+        let mut scan = VecMutScan::new(&mut self.voices);
         let delta = 22050.0 / sample_rate;
 
         while let Some(item) = scan.next() {
             let mut state = *item;
             let mut remove = false;
 
-            if let Some(sample) = self.samples.get(&state.0) {
+            if let Some(sample) = self.samples.get(&state.id) {
                 if sample.is_empty() {
                     item.remove();
                     continue;
                 };
 
                 for result in dst.iter_mut() {
-                    if state.1 >= sample.len() as f32 {
+                    if state.pos >= sample.len() as f32 {
                         remove = true;
                         break;
                     } else {
-                        let pos = state.1 as usize;
+                        let pos = state.pos as usize;
                         let s1 = (sample[pos] as f32) / 32768.0;
                         let s2 = (sample[clamp(pos + 1, 0, sample.len() - 1)] as f32) / 32768.0;
                         let s3 = (sample[clamp(pos + 2, 0, sample.len() - 1)] as f32) / 32768.0;
                         let s4 = (sample[pos.saturating_sub(1)] as f32) / 32768.0;
 
-                        let s = cubic_interp(s1, s2, s4, s3, state.1.fract()) * 32768.0;
+                        let s = cubic_interp(s1, s2, s4, s3, state.pos.fract()) * 32768.0;
                         let sam = (*result ^ 0x8000) as i16;
                         *result = sam.saturating_add(s as i16) as u16 ^ 0x8000;
 
-                        state.1 += delta;
+                        state.pos += delta;
                     }
                 }
 
@@ -295,7 +422,91 @@ impl PixTonePlayback {
                 } else {
                     item.replace(state);
                 }
+            } else {
+                item.remove();
             }
         }
     }
 }
+
+#[test]
+fn test_voice_stealing() {
+    let mut pixtone = PixTonePlayback::new();
+    let ids = |pixtone: &PixTonePlayback| pixtone.voices.iter().map(|v| v.id).collect::<Vec<u8>>();
+
+    // the same sound restarts instead of taking another voice
+    pixtone.play_sfx(2, SfxPriority::Low);
+    pixtone.play_sfx(2, SfxPriority::Low);
+    assert_eq!(pixtone.voices.len(), 1);
+
+    for id in 10..(10 + MAX_VOICES as u8 - 1) {
+        pixtone.play_sfx(id, SfxPriority::Normal);
+    }
+    assert_eq!(pixtone.voices.len(), MAX_VOICES);
+
+    // takes the low priority voice even though it's not the oldest one
+    pixtone.play_sfx(50, SfxPriority::High);
+    assert!(!ids(&pixtone).contains(&2) && ids(&pixtone).contains(&50));
+
+    // then the oldest of the normal ones
+    pixtone.play_sfx(40, SfxPriority::Normal);
+    assert!(!ids(&pixtone).contains(&10) && ids(&pixtone).contains(&40));
+    assert_eq!(pixtone.stolen, 2);
+
+    // low priority sounds can't steal from anything here
+    pixtone.play_sfx(60, SfxPriority::Low);
+    assert!(!ids(&pixtone).contains(&60));
+    assert_eq!(pixtone.dropped, 1);
+}
+
+#[test]
+fn test_sample_eviction() {
+    let mut pixtone = PixTonePlayback::new();
+    for id in render_order() {
+        pixtone.insert_rendered(id, render_sfx(id));
+    }
+    let all = pixtone.memory.total();
+    assert_eq!(pixtone.samples.len(), sfx_count());
+
+    // tiny cap, everything not playing goes
+    pixtone.play_sfx(1, SfxPriority::Normal);
+    pixtone.memory_cap = Some(1);
+    pixtone.evict_samples(None);
+    assert_eq!(pixtone.samples.keys().copied().collect::<Vec<u8>>(), vec![1]);
+    assert!(pixtone.memory.total() < all);
+
+    // rendered again on demand, the playing one survives
+    pixtone.play_sfx(2, SfxPriority::Normal);
+    assert!(pixtone.samples.contains_key(&1) && pixtone.samples.contains_key(&2));
+
+    // the least recently played one goes first once nothing is playing
+    pixtone.memory_cap = None;
+    pixtone.voices.clear();
+    pixtone.play_sfx(3, SfxPriority::Normal);
+    pixtone.voices.clear();
+    pixtone.memory_cap = Some(pixtone.memory.total() - 1);
+    pixtone.evict_samples(None);
+    assert!(!pixtone.samples.contains_key(&1));
+    assert!(pixtone.samples.contains_key(&2) && pixtone.samples.contains_key(&3));
+}
+
+#[test]
+fn test_render_order() {
+    let order = render_order();
+    assert_eq!(order.len(), sfx_count());
+    assert_eq!(&order[..COMMON_SFX.len()], &COMMON_SFX);
+
+    let mut sorted = order.clone();
+    sorted.sort_unstable();
+    sorted.dedup();
+    assert_eq!(sorted.len(), sfx_count());
+
+    // played before the background renderer got to it, the late copy doesn't replace it
+    let mut pixtone = PixTonePlayback::new();
+    pixtone.play_sfx(40, SfxPriority::Normal);
+    assert_eq!(pixtone.samples.len(), 1);
+    let bytes = pixtone.memory.total();
+    pixtone.insert_rendered(40, vec![0; 4]);
+    assert_eq!(pixtone.memory.total(), bytes);
+    assert_ne!(pixtone.samples[&40].len(), 4);
+}
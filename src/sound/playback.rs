@@ -157,11 +157,23 @@ impl PlaybackEngine {
         for i in self.keys.iter_mut() { *i = 255 };
     }
 
-    #[allow(unused)]
     pub fn set_position(&mut self, position: i32) {
         self.play_pos = position;
     }
 
+    pub fn get_position(&self) -> i32 {
+        self.play_pos
+    }
+
+    /// Bytes of the instrument buffers the song is rendered from.
+    pub fn buffer_bytes(&self) -> usize {
+        self.track_buffers.iter().map(|buf| buf.sample.data.len()).sum()
+    }
+
+    pub fn steps_per_measure(&self) -> u32 {
+        self.song.display.beats as u32 * self.song.display.steps as u32
+    }
+
     pub fn get_total_samples(&self) -> u32 {
         let ticks_intro = self.song.time.loop_range.start;
         let ticks_loop = self.song.time.loop_range.end - self.song.time.loop_range.start;
@@ -9,6 +9,7 @@ use crate::ggez::{Context, filesystem, GameResult};
 use crate::ggez::GameError::ResourceLoadError;
 use crate::map::{Map, NPCData};
 use crate::text_script::TextScript;
+use crate::transition::COVER_COLOR;
 use crate::encoding::read_cur_shift_jis;
 
 #[derive(Debug, PartialEq, Eq, Hash)]
@@ -57,6 +58,11 @@ impl Tileset {
         }
     }
 
+    /// Name of the tileset, shared by its PXA attribute file.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
     pub fn filename(&self) -> String {
         ["Prt", &self.name].join("")
     }
@@ -91,8 +97,30 @@ impl Background {
         }
     }
 
-    pub fn filename(&self) -> String {
-        self.name.to_owned()
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// "bkBlack" and empty names stand for no texture at all, the stage is just filled with `fill_color`.
+    pub fn is_black(&self) -> bool {
+        self.name.is_empty() || self.name.eq_ignore_ascii_case("bkBlack")
+    }
+
+    /// Color drawn where there's no background texture, also behind the map when it's smaller than the screen.
+    /// The original clears the screen with the same color the transitions cover it with.
+    pub fn fill_color(&self) -> [f32; 4] {
+        COVER_COLOR
+    }
+
+    /// First texture name `exists` returns true for, None for black backgrounds and missing textures.
+    /// CS+ keeps some of the backgrounds in the `bk` directory.
+    pub fn resolve_texture<F: FnMut(&str) -> bool>(&self, mut exists: F) -> Option<String> {
+        if self.is_black() {
+            return None;
+        }
+
+        let names = [self.name.clone(), ["bk/", &self.name].join("")];
+        names.iter().find(|name| exists(name)).cloned()
     }
 }
 
@@ -193,6 +221,53 @@ fn from_shift_jis(s: &[u8]) -> String {
 }
 
 impl StageData {
+    /// Parses a Cave Story+/Booster's Lab style stage table.
+    fn read_stage_tbl(data: Vec<u8>) -> GameResult<Vec<Self>> {
+        let mut stages = Vec::new();
+        let count = data.len() / 0xe5;
+        let mut f = Cursor::new(data);
+        for _ in 0..count {
+            let mut ts_buf = vec![0u8; 0x20];
+            let mut map_buf = vec![0u8; 0x20];
+            let mut back_buf = vec![0u8; 0x20];
+            let mut npc1_buf = vec![0u8; 0x20];
+            let mut npc2_buf = vec![0u8; 0x20];
+            let mut name_jap_buf = vec![0u8; 0x20];
+            let mut name_buf = vec![0u8; 0x20];
+
+            f.read_exact(&mut ts_buf)?;
+            f.read_exact(&mut map_buf)?;
+            let bg_type = f.read_u32::<LE>()? as usize;
+            f.read_exact(&mut back_buf)?;
+            f.read_exact(&mut npc1_buf)?;
+            f.read_exact(&mut npc2_buf)?;
+            let boss_no = f.read_u8()? as usize;
+            f.read_exact(&mut name_jap_buf)?;
+            f.read_exact(&mut name_buf)?;
+
+            let tileset = from_shift_jis(&ts_buf[0..zero_index(&ts_buf)]);
+            let map = from_shift_jis(&map_buf[0..zero_index(&map_buf)]);
+            let background = from_shift_jis(&back_buf[0..zero_index(&back_buf)]);
+            let npc1 = from_shift_jis(&npc1_buf[0..zero_index(&npc1_buf)]);
+            let npc2 = from_shift_jis(&npc2_buf[0..zero_index(&npc2_buf)]);
+            let name = from_shift_jis(&name_buf[0..zero_index(&name_buf)]);
+
+            let stage = StageData {
+                name: name.clone(),
+                map: map.clone(),
+                boss_no,
+                tileset: Tileset::new(&tileset),
+                background: Background::new(&background),
+                background_type: BackgroundType::new(bg_type),
+                npc1: NpcType::new(&npc1),
+                npc2: NpcType::new(&npc2),
+            };
+            stages.push(stage);
+        }
+
+        Ok(stages)
+    }
+
     // todo: refactor to make it less repetitive.
     pub fn load_stage_table(ctx: &mut Context, root: &str) -> GameResult<Vec<Self>> {
         let stage_tbl_path = [root, "stage.tbl"].join("");
@@ -201,55 +276,12 @@ impl StageData {
 
         if filesystem::exists(ctx, &stage_tbl_path) {
             // Cave Story+ stage table.
-            let mut stages = Vec::new();
-
             info!("Loading CaveStory+/Booster's Lab style stage table from {}", &stage_tbl_path);
 
             let mut data = Vec::new();
             filesystem::open(ctx, stage_tbl_path)?.read_to_end(&mut data)?;
 
-            let count = data.len() / 0xe5;
-            let mut f = Cursor::new(data);
-            for _ in 0..count {
-                let mut ts_buf = vec![0u8; 0x20];
-                let mut map_buf = vec![0u8; 0x20];
-                let mut back_buf = vec![0u8; 0x20];
-                let mut npc1_buf = vec![0u8; 0x20];
-                let mut npc2_buf = vec![0u8; 0x20];
-                let mut name_jap_buf = vec![0u8; 0x20];
-                let mut name_buf = vec![0u8; 0x20];
-
-                f.read_exact(&mut ts_buf)?;
-                f.read_exact(&mut map_buf)?;
-                let bg_type = f.read_u32::<LE>()? as usize;
-                f.read_exact(&mut back_buf)?;
-                f.read_exact(&mut npc1_buf)?;
-                f.read_exact(&mut npc2_buf)?;
-                let boss_no = f.read_u8()? as usize;
-                f.read_exact(&mut name_jap_buf)?;
-                f.read_exact(&mut name_buf)?;
-
-                let tileset = from_shift_jis(&ts_buf[0..zero_index(&ts_buf)]);
-                let map = from_shift_jis(&map_buf[0..zero_index(&map_buf)]);
-                let background = from_shift_jis(&back_buf[0..zero_index(&back_buf)]);
-                let npc1 = from_shift_jis(&npc1_buf[0..zero_index(&npc1_buf)]);
-                let npc2 = from_shift_jis(&npc2_buf[0..zero_index(&npc2_buf)]);
-                let name = from_shift_jis(&name_buf[0..zero_index(&name_buf)]);
-
-                let stage = StageData {
-                    name: name.clone(),
-                    map: map.clone(),
-                    boss_no,
-                    tileset: Tileset::new(&tileset),
-                    background: Background::new(&background),
-                    background_type: BackgroundType::new(bg_type),
-                    npc1: NpcType::new(&npc1),
-                    npc2: NpcType::new(&npc2),
-                };
-                stages.push(stage);
-            }
-
-            return Ok(stages);
+            return StageData::read_stage_tbl(data);
         } else if filesystem::exists(ctx, &mrmap_bin_path) {
             // CSE2E stage table
             let mut stages = Vec::new();
@@ -368,6 +400,8 @@ impl StageData {
 pub struct Stage {
     pub map: Map,
     pub data: StageData,
+    /// Built in memory by the debug arena, there's no script or NPC file to go with the map.
+    pub generated: bool,
 }
 
 impl Stage {
@@ -380,22 +414,89 @@ impl Stage {
         let stage = Self {
             map,
             data: data.clone(),
+            generated: false,
         };
 
         Ok(stage)
     }
 
+    /// Generated stage around `map` with made up stage data, for tests.
+    #[cfg(test)]
+    pub fn for_tests(map: Map) -> Self {
+        let data = StageData {
+            name: "Test".to_owned(),
+            map: "Test".to_owned(),
+            boss_no: 0,
+            tileset: Tileset::new("0"),
+            background: Background::new("0"),
+            background_type: BackgroundType::Stationary,
+            npc1: NpcType::new("0"),
+            npc2: NpcType::new("0"),
+        };
+
+        Stage::generated(map, &data)
+    }
+
+    /// Stage with a map made up on the spot, the tileset, background and NPC sheets come from `data`.
+    pub fn generated(map: Map, data: &StageData) -> Self {
+        Self {
+            map,
+            data: data.clone(),
+            generated: true,
+        }
+    }
+
     pub fn load_text_script(&mut self, root: &str, ctx: &mut Context) -> GameResult<TextScript> {
-        let tsc_file = filesystem::open(ctx, [root, "Stage/", &self.data.map, ".tsc"].join(""))?;
-        let text_script = TextScript::load_from(tsc_file)?;
+        if self.generated {
+            return Ok(TextScript::new());
+        }
+
+        let path = [root, "Stage/", &self.data.map, ".tsc"].join("");
+        let tsc_file = filesystem::open(ctx, &path)?;
+        let text_script = TextScript::load_from(tsc_file).map_err(|e| e.in_file(&path))?;
 
         Ok(text_script)
     }
 
     pub fn load_npcs(&mut self, root: &str, ctx: &mut Context) -> GameResult<Vec<NPCData>> {
+        if self.generated {
+            return Ok(Vec::new());
+        }
+
         let pxe_file = filesystem::open(ctx, [root, "Stage/", &self.data.map, ".pxe"].join(""))?;
         let npc_data = NPCData::load_from(pxe_file)?;
 
         Ok(npc_data)
     }
 }
+
+#[test]
+fn test_background_textures() {
+    use std::path::PathBuf;
+    use crate::common::FILE_TYPES;
+
+    assert!(Background::new("").is_black());
+    assert!(Background::new("bkBlack").is_black());
+    assert_eq!(Background::new("bkBlack").resolve_texture(|_| true), None);
+    assert_eq!(Background::new("bkBlue").resolve_texture(|_| true), Some("bkBlue".to_owned()));
+    assert_eq!(Background::new("bkBlue").resolve_texture(|name| name == "bk/bkBlue"), Some("bk/bkBlue".to_owned()));
+    assert_eq!(Background::new("bkBlue").resolve_texture(|_| false), None);
+
+    // every background of the stage table has to resolve, if the game data is there
+    let data_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("data");
+    for base in ["", "base/"].iter() {
+        let base_dir = data_dir.join(base);
+        let data = match std::fs::read(base_dir.join("stage.tbl")) {
+            Ok(data) => data,
+            Err(_) => { continue; }
+        };
+
+        for stage in StageData::read_stage_tbl(data).unwrap().iter() {
+            let exists = |name: &str| FILE_TYPES.iter()
+                .any(|ext| base_dir.join([name, ext].join("")).exists() || data_dir.join([name, ext].join("")).exists());
+
+            assert!(stage.background.is_black() || stage.background.resolve_texture(exists).is_some(),
+                    "background {:?} of {} does not exist", stage.background.name(), stage.map);
+        }
+    }
+}
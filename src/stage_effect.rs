@@ -0,0 +1,191 @@
+use crate::frame::Frame;
+use crate::ggez::{Context, GameResult, graphics};
+use crate::ggez::graphics::{Color, DrawMode, DrawParam, MeshBuilder, Rect};
+use crate::rng::EffectRNG;
+use crate::SharedGameState;
+
+/// Maximum number of particles alive at once.
+const PARTICLE_BUDGET: usize = 256;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum StageEffectType {
+    None = 0,
+    /// Rocks falling down, as in the island collapse sequence.
+    Debris = 1,
+    /// Horizontal streaks blowing to the left.
+    Wind = 2,
+    Snow = 3,
+}
+
+impl StageEffectType {
+    pub fn from_id(id: usize) -> StageEffectType {
+        match id {
+            1 => StageEffectType::Debris,
+            2 => StageEffectType::Wind,
+            3 => StageEffectType::Snow,
+            _ => StageEffectType::None,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Particle {
+    // in pixels, world space
+    x: f32,
+    y: f32,
+    vel_x: f32,
+    vel_y: f32,
+    size: f32,
+    counter: u16,
+    alive: bool,
+}
+
+/// Full screen ambient particles. Particles live in world space so they scroll with the camera.
+pub struct StageEffect {
+    effect: StageEffectType,
+    particles: Vec<Particle>,
+}
+
+impl StageEffect {
+    pub fn new() -> Self {
+        Self {
+            effect: StageEffectType::None,
+            particles: Vec::new(),
+        }
+    }
+
+    pub fn effect(&self) -> StageEffectType {
+        self.effect
+    }
+
+    pub fn set_effect(&mut self, effect: StageEffectType) {
+        if self.effect == effect {
+            return;
+        }
+
+        self.effect = effect;
+        for particle in self.particles.iter_mut() {
+            particle.alive = false;
+        }
+    }
+
+    fn spawn(&mut self, particle: Particle) {
+        if let Some(slot) = self.particles.iter_mut().find(|p| !p.alive) {
+            *slot = particle;
+        } else if self.particles.len() < PARTICLE_BUDGET {
+            self.particles.push(particle);
+        }
+    }
+
+    fn spawn_new(&mut self, rng: &EffectRNG, view: (f32, f32, f32, f32)) {
+        let (left, top, width, height) = view;
+        let rand = |min: i32, max: i32| rng.range(min..=max) as f32;
+
+        let particle = match self.effect {
+            StageEffectType::None => { return; }
+            StageEffectType::Debris => {
+                if rng.range(0..=3) != 0 { return; }
+
+                Particle {
+                    x: left + rand(0, width as i32),
+                    y: top - 16.0,
+                    vel_x: rand(-20, 20) / 100.0,
+                    vel_y: rand(100, 250) / 100.0,
+                    size: rand(2, 6),
+                    counter: 0,
+                    alive: true,
+                }
+            }
+            StageEffectType::Wind => {
+                Particle {
+                    x: left + width + 16.0,
+                    y: top + rand(0, height as i32),
+                    vel_x: -rand(400, 800) / 100.0,
+                    vel_y: rand(-10, 10) / 100.0,
+                    size: rand(8, 24),
+                    counter: 0,
+                    alive: true,
+                }
+            }
+            StageEffectType::Snow => {
+                if rng.range(0..=1) != 0 { return; }
+
+                Particle {
+                    x: left + rand(-32, width as i32 + 32),
+                    y: top - 4.0,
+                    vel_x: rand(-30, 30) / 100.0,
+                    vel_y: rand(30, 70) / 100.0,
+                    size: rand(1, 2),
+                    counter: rng.range(0..=0xff) as u16,
+                    alive: true,
+                }
+            }
+        };
+
+        self.spawn(particle);
+    }
+
+    pub fn tick(&mut self, state: &SharedGameState, frame: &Frame) {
+        if self.effect == StageEffectType::None {
+            return;
+        }
+
+        let (width, height) = state.canvas_size;
+        let left = frame.x as f32 / 512.0;
+        let top = frame.y as f32 / 512.0;
+
+        self.spawn_new(&state.effect_rng, (left, top, width, height));
+
+        for particle in self.particles.iter_mut().filter(|p| p.alive) {
+            particle.counter = particle.counter.wrapping_add(1);
+
+            match self.effect {
+                StageEffectType::Debris => {
+                    particle.vel_y = (particle.vel_y + 0.05).min(5.0);
+                }
+                StageEffectType::Snow => {
+                    // sway
+                    particle.vel_x += ((particle.counter as f32) / 20.0).sin() * 0.02;
+                }
+                _ => {}
+            }
+
+            particle.x += particle.vel_x;
+            particle.y += particle.vel_y;
+
+            // despawn once it leaves the screen with some margin
+            if particle.x < left - 64.0 || particle.x > left + width + 64.0
+                || particle.y < top - 64.0 || particle.y > top + height + 64.0 {
+                particle.alive = false;
+            }
+        }
+    }
+
+    pub fn draw(&self, ctx: &mut Context, frame: &Frame) -> GameResult {
+        if self.effect == StageEffectType::None || !self.particles.iter().any(|p| p.alive) {
+            return Ok(());
+        }
+
+        let offset_x = frame.x as f32 / 512.0;
+        let offset_y = frame.y as f32 / 512.0;
+        let mut builder = MeshBuilder::new();
+
+        for particle in self.particles.iter().filter(|p| p.alive) {
+            let x = (particle.x - offset_x).floor();
+            let y = (particle.y - offset_y).floor();
+
+            let (rect, color) = match self.effect {
+                StageEffectType::Debris => (Rect::new(x, y, particle.size, particle.size), Color::from_rgb(96, 72, 56)),
+                StageEffectType::Wind => (Rect::new(x, y, particle.size, 1.0), Color::new(1.0, 1.0, 1.0, 0.5)),
+                StageEffectType::Snow => (Rect::new(x, y, particle.size, particle.size), Color::from_rgb(240, 240, 255)),
+                StageEffectType::None => { continue; }
+            };
+
+            builder.rectangle(DrawMode::fill(), rect, color);
+        }
+
+        let mesh = builder.build(ctx)?;
+        graphics::draw(ctx, &mesh, DrawParam::new())
+    }
+}
@@ -0,0 +1,104 @@
+use std::collections::VecDeque;
+use std::fmt;
+
+use crate::crash;
+
+/// Transitions kept, the older ones are dropped.
+const HISTORY_LEN: usize = 10;
+
+/// Stage the player has left, with where they were and the event which made them leave.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct StageHistoryEntry {
+    pub stage_id: usize,
+    pub x: isize,
+    pub y: isize,
+    /// Event which ran the `<TRA`.
+    pub event: u16,
+}
+
+impl fmt::Display for StageHistoryEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "stage {} at ({}, {}) px, left by event #{:04}", self.stage_id, self.x / 0x200, self.y / 0x200, self.event)
+    }
+}
+
+/// Last stage transitions, for the debug warp back and for bug reports.
+/// Cleared on `<INI` and on a new game, the flags and the inventory aren't part of it.
+pub struct StageHistory {
+    entries: VecDeque<StageHistoryEntry>,
+}
+
+impl StageHistory {
+    pub fn new() -> StageHistory {
+        StageHistory {
+            entries: VecDeque::with_capacity(HISTORY_LEN),
+        }
+    }
+
+    pub fn push(&mut self, entry: StageHistoryEntry) {
+        if self.entries.len() == HISTORY_LEN {
+            self.entries.pop_front();
+        }
+
+        self.entries.push_back(entry);
+        self.update_crash_context();
+    }
+
+    /// Takes the last entry, the warp back doesn't count as a transition of its own.
+    pub fn pop(&mut self) -> Option<StageHistoryEntry> {
+        let entry = self.entries.pop_back();
+        self.update_crash_context();
+        entry
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.update_crash_context();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Entries from the oldest to the newest.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item=&StageHistoryEntry> {
+        self.entries.iter()
+    }
+
+    /// One line per entry, newest last.
+    pub fn dump(&self) -> String {
+        if self.entries.is_empty() {
+            return "Stage history: empty".to_owned();
+        }
+
+        let mut dump = "Stage history:".to_owned();
+        for entry in self.entries.iter() {
+            dump.push_str(&format!("\n  {}", entry));
+        }
+        dump
+    }
+
+    fn update_crash_context(&self) {
+        crash::set_context(self.dump());
+    }
+}
+
+#[test]
+fn test_stage_history() {
+    let mut history = StageHistory::new();
+    assert!(history.pop().is_none());
+
+    for stage_id in 0..(HISTORY_LEN + 3) {
+        history.push(StageHistoryEntry { stage_id, x: 0x2000, y: 0x4000, event: 100 });
+    }
+
+    // capped, the oldest ones are gone
+    assert_eq!(history.iter().count(), HISTORY_LEN);
+    assert_eq!(history.iter().next().unwrap().stage_id, 3);
+    assert_eq!(history.pop().unwrap().stage_id, HISTORY_LEN + 2);
+    assert!(history.dump().ends_with(&format!("stage {} at (16, 32) px, left by event #0100", HISTORY_LEN + 1)));
+
+    history.clear();
+    assert!(history.is_empty());
+    assert_eq!(history.dump(), "Stage history: empty");
+}
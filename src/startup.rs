@@ -0,0 +1,60 @@
+use std::time::Duration;
+
+/// The window and the loading scene should be up within this, everything else is loaded while it's shown.
+const STARTUP_BUDGET: Duration = Duration::from_millis(250);
+
+/// Time taken by the startup phases, logged once the loading scene is done so regressions show up in every log.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct StartupTimings {
+    /// Creating the window and the graphics context.
+    pub window: Duration,
+    /// `Game::new`, the engine constants, the font and the rest of the shared state.
+    pub constants: Duration,
+    /// Opening the audio device.
+    pub audio: Duration,
+    /// The loading scene's tasks, shown on screen while they run.
+    pub assets: Duration,
+    logged: bool,
+}
+
+impl StartupTimings {
+    pub fn new() -> StartupTimings {
+        StartupTimings::default()
+    }
+
+    pub fn summary(&self) -> String {
+        format!("startup: window={}ms, constants={}ms, audio={}ms, assets={}ms",
+                self.window.as_millis(), self.constants.as_millis(), self.audio.as_millis(), self.assets.as_millis())
+    }
+
+    /// Time until the loading scene shows up.
+    pub fn until_loading_scene(&self) -> Duration {
+        self.window + self.constants + self.audio
+    }
+
+    /// Only the first time, loading again after switching mods isn't startup anymore.
+    pub fn log(&mut self) {
+        if self.logged {
+            return;
+        }
+        self.logged = true;
+
+        log::info!("{}", self.summary());
+        if self.until_loading_scene() > STARTUP_BUDGET {
+            log::warn!("startup: the loading scene took {}ms to show up, over the budget of {}ms.",
+                       self.until_loading_scene().as_millis(), STARTUP_BUDGET.as_millis());
+        }
+    }
+}
+
+#[test]
+fn test_startup_summary() {
+    let mut timings = StartupTimings::new();
+    timings.window = Duration::from_millis(120);
+    timings.constants = Duration::from_micros(35_900);
+    timings.audio = Duration::from_millis(14);
+    timings.assets = Duration::from_millis(410);
+
+    assert_eq!(timings.summary(), "startup: window=120ms, constants=35ms, audio=14ms, assets=410ms");
+    assert!(timings.until_loading_scene() <= STARTUP_BUDGET);
+}
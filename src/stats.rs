@@ -0,0 +1,396 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::challenge::{CHALLENGE_TPS, format_time};
+use crate::ggez::filesystem::user_dirs;
+use crate::ggez::GameResult;
+use crate::save_file;
+use crate::save_file::PendingWrite;
+
+/// Boss number of Balfrog in the stage table.
+const BALFROG: usize = 2;
+const PLANTATION_MAP: &str = "Cent";
+const PLANTATION_TIME: Duration = Duration::from_secs(2 * 60 * 60);
+/// 3 to start with plus every Life Capsule.
+const MAX_LIFE_ALL_CAPSULES: u16 = 55;
+/// IDs of the achievements unlocked by `<ACH` are the number with this prefix.
+pub const SCRIPTED_ACHIEVEMENT_PREFIX: &str = "script_";
+
+/// Things worth counting, recorded by the systems they happen in.
+#[derive(Debug, Copy, Clone)]
+pub enum StatEvent {
+    DamageTaken(u16),
+    /// NPC type.
+    EnemyDefeated(u16),
+    Death,
+    Jump,
+    /// Weapon type.
+    ShotFired(u8),
+    /// A tick played on the current stage.
+    Tick,
+    LifeCapsule,
+    BossFightStarted,
+    /// Boss number of the stage.
+    BossDefeated(usize),
+}
+
+/// Game state the achievements are checked against, besides the counters.
+pub struct Progress<'a> {
+    pub map: &'a str,
+    pub max_life: u16,
+    /// Play time of the loaded profile, None for profiles saved without it.
+    pub play_time: Option<Duration>,
+}
+
+pub struct Achievement {
+    pub id: &'static str,
+    pub name: &'static str,
+    pub description: &'static str,
+    check: fn(&Stats, &Progress) -> bool,
+}
+
+pub static ACHIEVEMENTS: [Achievement; 3] = [
+    Achievement {
+        id: "balfrog_flawless",
+        name: "Flawless Frog",
+        description: "Defeat Balfrog without taking damage.",
+        check: |stats, _| stats.flawless_bosses.contains(&BALFROG),
+    },
+    Achievement {
+        id: "plantation_speed",
+        name: "Green Thumb",
+        description: "Reach the Plantation in under 2 hours.",
+        check: |_, progress| progress.map.eq_ignore_ascii_case(PLANTATION_MAP)
+            && progress.play_time.is_some_and(|time| time < PLANTATION_TIME),
+    },
+    Achievement {
+        id: "life_capsules",
+        name: "Full Health",
+        description: "Collect all of the Life Capsules.",
+        check: |_, progress| progress.max_life >= MAX_LIFE_ALL_CAPSULES,
+    },
+];
+
+/// One fight of a debug boss rush.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BossFightRecord {
+    pub npc_type: u16,
+    pub name: String,
+    pub ticks: u64,
+    pub damage_taken: u64,
+    /// False if the fight was skipped or the player died.
+    pub defeated: bool,
+    /// NPC types besides the effects and pickups still around once the boss was gone, a boss which doesn't
+    /// clean up after itself leaves its parts here.
+    pub leftovers: Vec<u16>,
+}
+
+impl BossFightRecord {
+    /// Defeated, faster, or as fast with less damage.
+    fn is_better_than(&self, other: &BossFightRecord) -> bool {
+        (self.defeated, other.ticks, other.damage_taken) > (other.defeated, self.ticks, self.damage_taken)
+    }
+}
+
+impl fmt::Display for BossFightRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (#{}): {}, {} damage taken", self.name, self.npc_type, format_time(self.ticks as usize, CHALLENGE_TPS), self.damage_taken)?;
+        if !self.defeated {
+            write!(f, ", not defeated")?;
+        }
+        if !self.leftovers.is_empty() {
+            write!(f, ", left behind {:?}", self.leftovers)?;
+        }
+        Ok(())
+    }
+}
+
+/// Statistics and achievements kept across playthroughs, stored as stats.json beside the profile.
+/// Recording only bumps counters, the file is written on another thread at save points and scene changes.
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct Stats {
+    pub damage_taken: u64,
+    pub enemies_defeated: BTreeMap<u16, u32>,
+    pub deaths: u32,
+    pub jumps: u32,
+    pub shots_fired: BTreeMap<u8, u32>,
+    /// Ticks played on every stage, by map name.
+    pub stage_ticks: BTreeMap<String, u64>,
+    pub life_capsules: u32,
+    /// Boss numbers of the bosses defeated without taking damage.
+    pub flawless_bosses: BTreeSet<usize>,
+    /// Unlocked achievement IDs.
+    pub unlocked: BTreeSet<String>,
+    /// Best boss rush fight of every boss, by NPC type.
+    pub boss_rush_best: BTreeMap<u16, BossFightRecord>,
+    /// Fights of the last boss rush, shown in the debugger.
+    #[serde(skip)]
+    pub last_boss_rush: Vec<BossFightRecord>,
+    /// Off during replays and challenges.
+    #[serde(skip)]
+    pub enabled: bool,
+    /// Stage being played and the ticks on it not added to `stage_ticks` yet, so a tick doesn't need a map lookup.
+    #[serde(skip)]
+    current_stage: Option<(String, u64)>,
+    /// Damage taken since the start of the current boss fight.
+    #[serde(skip)]
+    boss_damage: Option<u64>,
+    #[serde(skip)]
+    check_pending: bool,
+    #[serde(skip)]
+    dirty: bool,
+    #[serde(skip)]
+    writing: Option<PendingWrite>,
+}
+
+impl Stats {
+    fn path(mod_id: Option<&str>) -> GameResult<PathBuf> {
+        Ok(user_dirs()?.save_dir(mod_id).join("stats.json"))
+    }
+
+    /// Loads the statistics of the base game or a mod, empty ones if there aren't any yet.
+    pub fn load(mod_id: Option<&str>) -> Stats {
+        let result = Stats::path(mod_id).and_then(|path| {
+            save_file::read_with_backup(&path, |data| Ok(serde_json::from_slice::<Stats>(data)?))
+        });
+
+        let mut stats = match result {
+            Ok(Some((stats, _))) => stats,
+            Ok(None) => Stats::default(),
+            Err(e) => {
+                log::warn!("Cannot load the statistics: {}", e);
+                Stats::default()
+            }
+        };
+
+        stats.enabled = true;
+        stats
+    }
+
+    #[inline]
+    pub fn record(&mut self, event: StatEvent) {
+        if !self.enabled {
+            return;
+        }
+
+        match event {
+            StatEvent::DamageTaken(hp) => {
+                self.damage_taken += hp as u64;
+                if let Some(damage) = self.boss_damage.as_mut() {
+                    *damage += hp as u64;
+                }
+            }
+            StatEvent::EnemyDefeated(npc_type) => { *self.enemies_defeated.entry(npc_type).or_insert(0) += 1; }
+            StatEvent::Death => { self.deaths += 1; }
+            StatEvent::Jump => { self.jumps += 1; }
+            StatEvent::ShotFired(weapon) => { *self.shots_fired.entry(weapon).or_insert(0) += 1; }
+            StatEvent::Tick => {
+                if let Some((_, ticks)) = self.current_stage.as_mut() {
+                    *ticks += 1;
+                }
+            }
+            StatEvent::LifeCapsule => {
+                self.life_capsules += 1;
+                self.check_pending = true;
+            }
+            StatEvent::BossFightStarted => { self.boss_damage = Some(0); }
+            StatEvent::BossDefeated(boss) => {
+                if self.boss_damage.take() == Some(0) {
+                    self.flawless_bosses.insert(boss);
+                }
+                self.check_pending = true;
+            }
+        }
+
+        self.dirty = true;
+    }
+
+    /// Starts counting the play time of a stage.
+    pub fn enter_stage(&mut self, map: &str) {
+        self.fold_stage_ticks();
+        self.boss_damage = None;
+
+        self.current_stage = if self.enabled { Some((map.to_owned(), 0)) } else { None };
+        self.check_pending = true;
+    }
+
+    fn fold_stage_ticks(&mut self) {
+        if let Some((map, ticks)) = self.current_stage.as_mut() {
+            if *ticks > 0 {
+                *self.stage_ticks.entry(map.clone()).or_insert(0) += *ticks;
+                *ticks = 0;
+            }
+        }
+    }
+
+    /// Whether something which can unlock an achievement has happened since the last check.
+    pub fn check_pending(&self) -> bool {
+        self.check_pending
+    }
+
+    /// Unlocks the achievements whose conditions are met, returns the names of the new ones.
+    pub fn check_achievements(&mut self, progress: &Progress) -> Vec<&'static str> {
+        self.check_pending = false;
+        if !self.enabled {
+            return Vec::new();
+        }
+
+        let mut unlocked = Vec::new();
+        for achievement in ACHIEVEMENTS.iter() {
+            if !self.unlocked.contains(achievement.id) && (achievement.check)(self, progress) {
+                self.unlocked.insert(achievement.id.to_owned());
+                unlocked.push(achievement.name);
+                self.dirty = true;
+            }
+        }
+
+        unlocked
+    }
+
+    /// Unlocks an achievement triggered by a script, returns false if it has been unlocked before.
+    pub fn unlock_scripted(&mut self, num: u16) -> bool {
+        if !self.enabled || !self.unlocked.insert(format!("{}{}", SCRIPTED_ACHIEVEMENT_PREFIX, num)) {
+            return false;
+        }
+
+        self.dirty = true;
+        true
+    }
+
+    /// Keeps the fights of a finished boss rush. Recorded even when the stats are off, the rush turns them off
+    /// so the fights don't count as regular play.
+    pub fn record_boss_rush(&mut self, fights: &[BossFightRecord]) {
+        for fight in fights.iter() {
+            let better = match self.boss_rush_best.get(&fight.npc_type) {
+                Some(best) => fight.is_better_than(best),
+                None => true,
+            };
+
+            if better {
+                self.boss_rush_best.insert(fight.npc_type, fight.clone());
+                self.dirty = true;
+            }
+        }
+
+        self.last_boss_rush = fights.to_vec();
+    }
+
+    /// Writes the changes since the last time on another thread. Skipped while the previous write
+    /// is still going, the changes are written next time.
+    pub fn flush(&mut self, mod_id: Option<&str>) {
+        if let Some(pending) = self.writing.as_ref() {
+            match pending.poll() {
+                Some(Err(e)) => log::warn!("Cannot save the statistics: {}", e),
+                Some(Ok(())) => {}
+                None => { return; }
+            }
+            self.writing = None;
+        }
+
+        self.fold_stage_ticks();
+        if !self.dirty {
+            return;
+        }
+
+        let result = Stats::path(mod_id).and_then(|path| Ok((path, serde_json::to_vec(self)?)));
+        match result {
+            Ok((path, data)) => {
+                self.writing = Some(PendingWrite::spawn(move || {
+                    if let Some(dir) = path.parent() {
+                        fs::create_dir_all(dir)?;
+                    }
+                    save_file::write_atomic(&path, &data)
+                }));
+                self.dirty = false;
+            }
+            Err(e) => log::warn!("Cannot save the statistics: {}", e),
+        }
+    }
+
+    /// Writes the changes on exit, waiting for the writes so they aren't cut off when the process ends.
+    pub fn flush_on_exit(&mut self, mod_id: Option<&str>) {
+        if let Some(Err(e)) = self.writing.take().map(PendingWrite::wait) {
+            log::warn!("Cannot save the statistics: {}", e);
+        }
+
+        self.flush(mod_id);
+        if let Some(Err(e)) = self.writing.take().map(PendingWrite::wait) {
+            log::warn!("Cannot save the statistics: {}", e);
+        }
+    }
+}
+
+#[test]
+fn test_stats() {
+    let mut stats = Stats::default();
+    stats.record(StatEvent::Jump);
+    assert_eq!(stats.jumps, 0, "disabled stats don't count");
+
+    stats.enabled = true;
+    stats.enter_stage("Gum");
+    stats.record(StatEvent::BossFightStarted);
+    stats.record(StatEvent::ShotFired(2));
+    stats.record(StatEvent::ShotFired(2));
+    stats.record(StatEvent::Tick);
+    stats.record(StatEvent::BossDefeated(BALFROG));
+
+    let progress = Progress { map: "Gum", max_life: 3, play_time: Some(Duration::from_secs(600)) };
+    assert!(stats.check_pending());
+    assert_eq!(stats.check_achievements(&progress), vec!["Flawless Frog"]);
+
+    // hit during the fight
+    stats.record(StatEvent::BossFightStarted);
+    stats.record(StatEvent::DamageTaken(4));
+    stats.record(StatEvent::BossDefeated(5));
+    assert!(!stats.flawless_bosses.contains(&5));
+
+    // the play time of the loaded profile counts, not the time since the game was started
+    stats.enter_stage("Cent");
+    let late = Progress { map: "Cent", max_life: 3, play_time: Some(Duration::from_secs(3 * 60 * 60)) };
+    assert!(stats.check_achievements(&late).is_empty());
+    let no_record = Progress { map: "Cent", max_life: 3, play_time: None };
+    assert!(stats.check_achievements(&no_record).is_empty());
+
+    let progress = Progress { map: "Cent", max_life: 55, play_time: Some(Duration::from_secs(90 * 60)) };
+    assert_eq!(stats.check_achievements(&progress), vec!["Green Thumb", "Full Health"]);
+    assert!(stats.check_achievements(&progress).is_empty(), "unlocked only once");
+
+    // <ACH
+    assert!(stats.unlock_scripted(10));
+    assert!(!stats.unlock_scripted(10));
+
+    stats.fold_stage_ticks();
+    let json = serde_json::to_string(&stats).unwrap();
+    let loaded: Stats = serde_json::from_str(&json).unwrap();
+    assert_eq!(loaded.shots_fired.get(&2), Some(&2));
+    assert_eq!(loaded.stage_ticks.get("Gum"), Some(&1));
+    assert_eq!(loaded.damage_taken, 4);
+    assert_eq!(loaded.unlocked.len(), 4);
+}
+
+#[test]
+fn test_boss_rush_records() {
+    let fight = |ticks, damage_taken, defeated| BossFightRecord {
+        npc_type: 68,
+        name: "Balrog".to_owned(),
+        ticks,
+        damage_taken,
+        defeated,
+        leftovers: Vec::new(),
+    };
+
+    let mut stats = Stats::default();
+    stats.record_boss_rush(&[fight(600, 2, false)]);
+    stats.record_boss_rush(&[fight(900, 6, true)]);
+    stats.record_boss_rush(&[fight(500, 0, false), fight(900, 4, true)]);
+
+    let best = &stats.boss_rush_best[&68];
+    assert!(best.defeated);
+    assert_eq!((best.ticks, best.damage_taken), (900, 4));
+    assert_eq!(stats.last_boss_rush.len(), 2);
+    assert_eq!(best.to_string(), "Balrog (#68): 0:18.0, 4 damage taken");
+}
@@ -1,4 +1,5 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
 use std::io;
 use std::io::Cursor;
 use std::io::Seek;
@@ -14,17 +15,26 @@ use num_traits::{clamp, FromPrimitive};
 
 use crate::{SharedGameState, str};
 use crate::bitfield;
-use crate::common::{Direction, FadeDirection, FadeState};
+use crate::common::{ControlFlags, Direction, FadeDirection, FadeState, resolve_movement};
 use crate::encoding::{read_cur_shift_jis, read_cur_wtf8};
 use crate::entity::GameEntity;
-use crate::ggez::{Context, GameResult};
-use crate::ggez::GameError::ParseError;
-use crate::player::ControlMode;
+use crate::ggez::{Context, GameError, GameResult};
+use crate::life_bar::BossTarget;
+use crate::npc::{NPC, NPCMap, SCRIPT_NPC_START};
+use crate::player::{ControlMode, PlayableCharacter};
+use crate::profile::GameProfile;
+use crate::prompts;
 use crate::scene::game_scene::GameScene;
+use crate::scene::title_scene::TitleScene;
+use crate::scene::transition_scene::{TransitionScene, TransitionTarget};
+use crate::stage_effect::StageEffectType;
+use crate::stage_history::StageHistoryEntry;
+use crate::stats::StatEvent;
+use crate::transition::TransitionType;
 use crate::weapon::WeaponType;
 
 /// Engine's text script VM operation codes.
-#[derive(EnumString, Debug, FromPrimitive, PartialEq)]
+#[derive(EnumString, AsRefStr, Debug, FromPrimitive, PartialEq, Eq, Copy, Clone)]
 #[repr(i32)]
 pub enum OpCode {
     // ---- Internal opcodes (used by bytecode, no TSC representation)
@@ -161,22 +171,139 @@ pub enum OpCode {
     SSS,
 
     // ---- Cave Story+ specific opcodes ----
-    /// <ACHXXXX, triggers a Steam achievement.
+    // Available on CS+ data or with the `csplus` extension, see `SharedGameState::csplus_tsc`.
+    /// <ACHxxxx, triggers a Steam achievement.
     ACH,
+    /// <2MVxxxx, puts the second player next to the first one.
+    #[strum(serialize = "2MV")]
+    S2MV,
+    /// <INJxxxx:yyyy:zzzz, jumps to event zzzz if the player has exactly yyyy of item xxxx.
+    INJ,
 
     // ---- Custom opcodes, for use by modders ----
+    /// <STExxxx, sets the ambient stage effect (0 - none, 1 - debris, 2 - wind, 3 - snow).
+    /// Requires the `stage_effects` extension.
+    STE,
+    /// <VARxxxx:yyyy, sets variable xxxx to yyyy. Variables require the `variables` extension.
+    VAR,
+    /// <VA+xxxx:yyyy, adds yyyy to variable xxxx.
+    #[strum(serialize = "VA+")]
+    VAp,
+    /// <VA-xxxx:yyyy, subtracts yyyy from variable xxxx.
+    #[strum(serialize = "VA-")]
+    VAm,
+    /// <VAJxxxx:yyyy:zzzz, jumps to event zzzz if variable xxxx equals yyyy.
+    VAJ,
+    /// <PSOxxxx, draws the player in a pose until <PSO0000 clears it (1 - drowned).
+    /// Requires the `player_poses` extension.
+    PSO,
+}
+
+impl OpCode {
+    /// Number of operands the opcode takes in a script, None for the opcodes the engine doesn't know how to read.
+    pub fn operand_count(self) -> Option<usize> {
+        match self {
+            OpCode::AEp | OpCode::CAT | OpCode::CIL | OpCode::CLO | OpCode::CLR | OpCode::CPS |
+            OpCode::CRE | OpCode::CSS | OpCode::END | OpCode::ESC | OpCode::FLA | OpCode::FMU |
+            OpCode::FRE | OpCode::HMC | OpCode::INI | OpCode::KEY | OpCode::LDP | OpCode::MLP |
+            OpCode::MM0 | OpCode::MNA | OpCode::MS2 | OpCode::MS3 | OpCode::MSG | OpCode::NOD |
+            OpCode::PRI | OpCode::RMU | OpCode::SAT | OpCode::SLP | OpCode::SMC | OpCode::SPS |
+            OpCode::STC | OpCode::SVP | OpCode::TUR | OpCode::WAS | OpCode::ZAM => Some(0),
+            OpCode::BOA | OpCode::BSL | OpCode::FOB | OpCode::FOM | OpCode::QUA | OpCode::UNI |
+            OpCode::MYB | OpCode::MYD | OpCode::WAI | OpCode::FAC |
+            OpCode::GIT | OpCode::NUM | OpCode::DNA | OpCode::DNP | OpCode::FLm | OpCode::FLp |
+            OpCode::MPp | OpCode::SKm | OpCode::SKp | OpCode::EQp | OpCode::EQm | OpCode::MLp |
+            OpCode::ITp | OpCode::ITm | OpCode::AMm | OpCode::UNJ | OpCode::MPJ | OpCode::YNJ |
+            OpCode::EVE | OpCode::XX1 | OpCode::SIL | OpCode::LIp | OpCode::SOU | OpCode::CMU |
+            OpCode::SSS | OpCode::ACH | OpCode::S2MV | OpCode::STE | OpCode::PSO => Some(1),
+            OpCode::FON | OpCode::MOV | OpCode::AMp | OpCode::NCJ | OpCode::ECJ | OpCode::FLJ |
+            OpCode::ITJ | OpCode::SKJ | OpCode::AMJ | OpCode::SMP | OpCode::PSp | OpCode::VAR |
+            OpCode::VAp | OpCode::VAm | OpCode::FAI | OpCode::FAO => Some(2),
+            OpCode::ANP | OpCode::CNP | OpCode::INP | OpCode::TAM | OpCode::CMP | OpCode::VAJ | OpCode::INJ => Some(3),
+            OpCode::TRA | OpCode::MNP | OpCode::SNP => Some(4),
+            _ => None,
+        }
+    }
+
+    /// Number of trailing operands which can be left out of a script, they're compiled as 0.
+    pub fn optional_operands(self) -> usize {
+        match self {
+            // <FAIxxxx:yyyy, the transition is only read with the `transitions` extension
+            OpCode::FAI | OpCode::FAO => 1,
+            _ => 0,
+        }
+    }
+
+    /// Operand holding the event jumped to, for the opcodes jumping within the same script.
+    /// `<TRA` is left out, its event is on the other stage.
+    pub fn jump_operand(self) -> Option<usize> {
+        match self {
+            OpCode::EVE | OpCode::YNJ | OpCode::MPJ | OpCode::UNJ => Some(0),
+            OpCode::FLJ | OpCode::ITJ | OpCode::SKJ | OpCode::AMJ | OpCode::NCJ | OpCode::ECJ => Some(1),
+            OpCode::VAJ | OpCode::INJ => Some(2),
+            _ => None,
+        }
+    }
+}
+
+/// Number of variables available to scripts with the `variables` extension.
+pub const TSC_VARIABLE_COUNT: usize = 256;
+
+bitfield! {
+  /// Custom opcode sets, enabled separately so mods can pick only the ones they use.
+  #[derive(Clone, Copy)]
+  pub struct TextScriptExtensions(u8);
+  impl Debug;
+
+  pub stage_effects, set_stage_effects: 0;
+  pub variables, set_variables: 1;
+  pub player_poses, set_player_poses: 2;
+  pub transitions, set_transitions: 3;
+  /// CS+ commands on other data, they're always there on CS+.
+  pub csplus, set_csplus: 4;
+}
+
+impl TextScriptExtensions {
+    pub fn all() -> TextScriptExtensions {
+        TextScriptExtensions(0b11111)
+    }
+
+    /// Toggles an extension by the name used in mod manifests, returns false if there's no such extension.
+    pub fn set_by_name(&mut self, name: &str, value: bool) -> bool {
+        match name {
+            "stage_effects" => self.set_stage_effects(value),
+            "variables" => self.set_variables(value),
+            "player_poses" => self.set_player_poses(value),
+            "transitions" => self.set_transitions(value),
+            "csplus" => self.set_csplus(value),
+            _ => { return false; }
+        }
+
+        true
+    }
 }
 
 bitfield! {
+  #[derive(Clone, Copy, Serialize, Deserialize)]
   pub struct TextScriptFlags(u16);
   impl Debug;
   pub render, set_render: 0;
   pub background_visible, set_background_visible: 1;
-  pub flag_x10, set_flag_x10: 4;
+  /// Set by <CAT/<TUR, prints the text at once instead of letter by letter.
+  pub instant_text, set_instant_text: 4;
   pub position_top, set_position_top: 5;
   pub flag_x40, set_flag_x40: 6;
 }
 
+/// What `<SAT`, `<CAT` and `<TUR` do, see `TextScriptConsts::instant_text_commands`.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum InstantTextCommand {
+    /// Messages show up at once until the message box is closed.
+    InstantText,
+    /// Does nothing.
+    Ignored,
+}
+
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
 #[repr(u8)]
 pub enum TextScriptEncoding {
@@ -184,7 +311,23 @@ pub enum TextScriptEncoding {
     ShiftJIS,
 }
 
-#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+impl TextScriptEncoding {
+    /// Freeware scripts are in Shift-JIS, while most mods and CS+ are plain ASCII or UTF-8.
+    pub fn detect(data: &[u8]) -> TextScriptEncoding {
+        if std::str::from_utf8(data).is_ok() {
+            TextScriptEncoding::UTF8
+        } else {
+            TextScriptEncoding::ShiftJIS
+        }
+    }
+}
+
+/// Shown in place of the bytes which can't be decoded, the font has no glyph for U+FFFD.
+const REPLACEMENT_CHAR: char = '?';
+/// Gap between the edges of the message box and the text.
+const TEXT_MARGIN: f32 = 14.0;
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Serialize, Deserialize)]
 #[repr(u8)]
 pub enum TextScriptLine {
     Line1 = 0,
@@ -192,7 +335,7 @@ pub enum TextScriptLine {
     Line3,
 }
 
-#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Serialize, Deserialize)]
 #[repr(u8)]
 pub enum ConfirmSelection {
     Yes,
@@ -211,7 +354,7 @@ impl Not for ConfirmSelection {
     }
 }
 
-#[derive(Debug, PartialEq, Copy, Clone)]
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
 #[repr(u8)]
 pub enum TextScriptExecutionState {
     Ended,
@@ -222,8 +365,146 @@ pub enum TextScriptExecutionState {
     WaitStanding(u16, u32),
     WaitConfirmation(u16, u32, u16, u8, ConfirmSelection),
     WaitFade(u16, u32),
+    /// `<SVP` waiting for the profile to be written, see `SharedGameState::pending_save`.
+    WaitSave(u16, u32),
+}
+
+/// Executed commands kept for the debugger's trace view, the oldest ones are dropped.
+pub const TRACE_CAPACITY: usize = 4096;
+
+/// Command executed while tracing, see `TextScriptVM::trace_enabled`. Formatted only when it's logged or shown.
+#[derive(Debug, Copy, Clone)]
+pub struct TraceEntry {
+    /// Tick of the game scene.
+    pub tick: usize,
+    pub event: u16,
+    pub ip: u32,
+    pub op: OpCode,
+    args: [i32; 4],
+    arg_count: usize,
+}
+
+impl TraceEntry {
+    /// Reads the operands following the opcode, `cursor` has to be right after it.
+    fn read(tick: usize, event: u16, ip: u32, op: OpCode, mut cursor: Cursor<&Vec<u8>>) -> TraceEntry {
+        let mut args = [0; 4];
+        let mut arg_count = 0;
+
+        for arg in args.iter_mut().take(op.operand_count().unwrap_or(0)) {
+            match read_cur_varint(&mut cursor) {
+                Ok(value) => *arg = value,
+                Err(_) => break,
+            }
+            arg_count += 1;
+        }
+
+        TraceEntry { tick, event, ip, op, args, arg_count }
+    }
+
+    pub fn args(&self) -> &[i32] {
+        &self.args[..self.arg_count]
+    }
+}
+
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{}] #{:04} @ {}: <{}", self.tick, self.event, self.ip, self.op.as_ref())?;
+        for (i, arg) in self.args().iter().enumerate() {
+            write!(f, "{}{:04}", if i == 0 { "" } else { ":" }, arg)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// `<WAI9999` never ends on its own, see `TextScriptVM`.
+pub const WAI_FOREVER: u16 = 9999;
+
+/// The offset gets filled in by `TextScript::compile`, which knows how far it got into the script.
+fn parse_error(message: String) -> GameError {
+    GameError::parse_error(0, message)
+}
+
+/// The error freeware scripts got from these before they were implemented.
+fn csplus_only(op: OpCode) -> GameError {
+    parse_error(format!("Unknown opcode: {} (a CS+ command, enable the csplus extension to use it on other data)", op.as_ref()))
+}
+
+fn needs_context(ctx: Option<&mut Context>, op: OpCode) -> GameResult<&mut Context> {
+    ctx.ok_or_else(|| GameError::InvalidValue(format!("<{} can't run without a context.", op.as_ref())))
+}
+
+/// Problem found in a script by `TextScript::check`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Diagnostic {
+    /// Byte offset in the script, the same in the encrypted and the decrypted one.
+    pub offset: u64,
+    pub event: Option<u16>,
+    pub severity: Severity,
+    pub message: String,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// The engine fails to load the script or it doesn't do what was meant.
+    Error,
+    /// The engine accepts it, but it's likely a mistake.
+    Warning,
+}
+
+impl Diagnostic {
+    fn from_error(offset: u64, event: Option<u16>, error: GameError) -> Diagnostic {
+        let message = match error {
+            GameError::ParseError { message, .. } => message,
+            e => e.to_string(),
+        };
+
+        Diagnostic { offset, event, severity: Severity::Error, message }
+    }
+}
+
+/// What `TextScript::check` gathers while the script is being compiled.
+struct CheckLog {
+    data_len: usize,
+    /// Event being compiled.
+    event: u16,
+    /// Every event header, including the events which failed to compile.
+    events: HashSet<u16>,
+    /// Offset, event and opcode of the jumps within the script, with the event jumped to.
+    jumps: Vec<(u64, u16, OpCode, u16)>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl CheckLog {
+    fn offset<I: ExactSizeIterator<Item=u8>>(&self, iter: &Peekable<I>) -> u64 {
+        (self.data_len - iter.len()) as u64
+    }
+
+    fn warn(&mut self, offset: u64, message: String) {
+        self.diagnostics.push(Diagnostic { offset, event: Some(self.event), severity: Severity::Warning, message });
+    }
+}
+
+/// What has started an event, decides whether it can interrupt the one currently running.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EventTrigger {
+    /// Player pressing down next to an interactable NPC, or touching an NPC with `event_when_touched`.
+    Interaction,
+    /// NPCs with `event_when_killed`, the player's death, the engine and the debugger.
+    Forced,
 }
 
+/// Event interruption rules, following vanilla:
+/// - Interaction events are ignored while any event is running. That includes `<WAI`, `<NOD`, etc.
+///   and events which gave the control back with `<FRE`, only `<END` allows interactions again.
+///   The check happens when the event is started, so two NPCs touched in the same tick can't override each other.
+/// - Forced events always replace the running event, dropping whatever it was waiting for.
+/// - `<EVE` transfers to another event keeping the message box and the control flags as they are.
+/// - `<WAI9999` waits until a forced event takes over, scripts use it after starting a boss fight
+///   (vanilla just waits 9999 ticks, nothing relies on that).
+/// - Running into an event which doesn't exist ends the script the same way `<END` does,
+///   instead of leaving the player without control.
 pub struct TextScriptVM {
     pub scripts: TextScriptVMScripts,
     pub state: TextScriptExecutionState,
@@ -232,13 +513,22 @@ pub struct TextScriptVM {
     /// modified the events carelessly and since original Pixel's engine hasn't enforced constraints
     /// while parsing no one noticed them.
     pub strict_mode: bool,
+    /// Enables custom opcodes which aren't present in any official version of the game.
+    pub extensions: TextScriptExtensions,
     pub suspend: bool,
     pub face: u16,
     pub item: u16,
+    /// Values printed by `<NUM`, the first one is set by `<AM+` to the ammo amount.
+    pub numbers: [u16; 4],
     pub current_line: TextScriptLine,
     pub line_1: Vec<char>,
     pub line_2: Vec<char>,
     pub line_3: Vec<char>,
+    /// Event, offset and the opcode executed last, for debugging stuck scripts.
+    pub last_executed: Option<(u16, u32, OpCode)>,
+    /// Logs every executed command and keeps the last `TRACE_CAPACITY` of them in `trace`, see `--trace-tsc`.
+    pub trace_enabled: bool,
+    pub trace: VecDeque<TraceEntry>,
 }
 
 impl Default for TextScriptVM {
@@ -250,10 +540,17 @@ impl Default for TextScriptVM {
 pub struct TextScriptVMScripts {
     pub global_script: TextScript,
     pub scene_script: TextScript,
+    /// ArmsItem.tsc, the only script events are looked up in while the inventory is open.
+    pub inventory_script: TextScript,
+    pub inventory_active: bool,
 }
 
 impl TextScriptVMScripts {
     pub fn find_script(&self, event_num: u16) -> Option<&Vec<u8>> {
+        if self.inventory_active {
+            return self.inventory_script.event_map.get(&event_num);
+        }
+
         if let Some(tsc) = self.scene_script.event_map.get(&event_num) {
             return Some(tsc);
         } else if let Some(tsc) = self.global_script.event_map.get(&event_num) {
@@ -285,20 +582,103 @@ impl TextScriptVM {
             scripts: TextScriptVMScripts {
                 global_script: TextScript::new(),
                 scene_script: TextScript::new(),
+                inventory_script: TextScript::new(),
+                inventory_active: false,
             },
             state: TextScriptExecutionState::Ended,
             strict_mode: false,
+            extensions: TextScriptExtensions(0),
             suspend: true,
             flags: TextScriptFlags(0),
             item: 0,
             face: 0,
+            numbers: [0; 4],
             current_line: TextScriptLine::Line1,
             line_1: Vec::with_capacity(24),
             line_2: Vec::with_capacity(24),
             line_3: Vec::with_capacity(24),
+            last_executed: None,
+            trace_enabled: false,
+            trace: VecDeque::new(),
         }
     }
 
+    /// Everything the VM is in the middle of, for the debugger and the logs.
+    pub fn dump_state(&self) -> String {
+        let mut lines = Vec::new();
+
+        let (event, ip) = match self.state {
+            TextScriptExecutionState::Ended => (None, None),
+            TextScriptExecutionState::Running(event, ip) |
+            TextScriptExecutionState::Msg(event, ip, _, _) |
+            TextScriptExecutionState::WaitTicks(event, ip, _) |
+            TextScriptExecutionState::WaitInput(event, ip) |
+            TextScriptExecutionState::WaitStanding(event, ip) |
+            TextScriptExecutionState::WaitConfirmation(event, ip, _, _, _) |
+            TextScriptExecutionState::WaitFade(event, ip) |
+            TextScriptExecutionState::WaitSave(event, ip) => (Some(event), Some(ip)),
+        };
+
+        match (event, ip) {
+            (Some(event), Some(ip)) => {
+                let source = if self.scripts.inventory_active {
+                    "inventory"
+                } else if self.scripts.scene_script.event_map.contains_key(&event) {
+                    "scene"
+                } else {
+                    "global"
+                };
+                lines.push(format!("Event: #{:04} ({} script), offset {}", event, source, ip));
+            }
+            _ => lines.push(str!("Event: none")),
+        }
+
+        // TSC has no calls, the jumps replace the current event
+        lines.push(format!("State: {:?}", self.state));
+        let waiting = match self.state {
+            TextScriptExecutionState::Msg(_, _, remaining, ticks) => format!("{} characters left, next in {} ticks", remaining, ticks),
+            TextScriptExecutionState::WaitTicks(_, _, ticks) => format!("{} ticks", ticks),
+            TextScriptExecutionState::WaitInput(..) => str!("a key press"),
+            TextScriptExecutionState::WaitStanding(..) => str!("the player to land"),
+            TextScriptExecutionState::WaitConfirmation(_, _, event, ticks, selection) => {
+                format!("yes/no in {} ticks ({:?} selected, no jumps to #{:04})", ticks, selection, event)
+            }
+            TextScriptExecutionState::WaitFade(..) => str!("the fade"),
+            TextScriptExecutionState::WaitSave(..) => str!("the profile to be saved"),
+            TextScriptExecutionState::Running(..) | TextScriptExecutionState::Ended => str!("nothing"),
+        };
+        lines.push(format!("Waiting for: {}", waiting));
+
+        if let Some((event, ip, op)) = self.last_executed {
+            lines.push(format!("Last executed: #{:04} @ {} ({:?})", event, ip, op));
+        }
+
+        let phase = if !self.flags.render() {
+            "closed"
+        } else if let TextScriptExecutionState::Msg(..) = self.state {
+            "printing"
+        } else if let TextScriptExecutionState::WaitConfirmation(..) = self.state {
+            "asking"
+        } else {
+            "open"
+        };
+        lines.push(format!("Message box: {} ({}, {}), face {}, item {}, writing to {:?}",
+                           phase,
+                           if self.flags.position_top() { "top" } else { "bottom" },
+                           if self.flags.background_visible() { "with background" } else { "no background" },
+                           self.face, self.item, self.current_line));
+
+        for line in [&self.line_1, &self.line_2, &self.line_3].iter() {
+            if !line.is_empty() {
+                lines.push(format!("  {}", line.iter().collect::<String>()));
+            }
+        }
+
+        lines.push(format!("Flags: {:?}, suspended: {}, strict: {}, extensions: {:?}", self.flags, self.suspend, self.strict_mode, self.extensions));
+
+        lines.join("\n")
+    }
+
     pub fn set_global_script(&mut self, script: TextScript) {
         self.scripts.global_script = script;
         if !self.suspend { self.reset(); }
@@ -309,6 +689,10 @@ impl TextScriptVM {
         if !self.suspend { self.reset(); }
     }
 
+    pub fn set_inventory_script(&mut self, script: TextScript) {
+        self.scripts.inventory_script = script;
+    }
+
     pub fn append_global_script(&mut self, script: TextScript) {
         for (key, val) in script.event_map {
             self.scripts.global_script.event_map.insert(key, val);
@@ -340,6 +724,43 @@ impl TextScriptVM {
         self.line_3.clear();
     }
 
+    fn current_line_mut(&mut self) -> &mut Vec<char> {
+        match self.current_line {
+            TextScriptLine::Line1 => &mut self.line_1,
+            TextScriptLine::Line2 => &mut self.line_2,
+            TextScriptLine::Line3 => &mut self.line_3,
+        }
+    }
+
+    /// Moves on to the next line, scrolls the text up once the last one is reached.
+    fn new_line(&mut self) {
+        match self.current_line {
+            TextScriptLine::Line1 => self.current_line = TextScriptLine::Line2,
+            TextScriptLine::Line2 => self.current_line = TextScriptLine::Line3,
+            TextScriptLine::Line3 => {
+                self.line_1.clear();
+                self.line_1.append(&mut self.line_2);
+                self.line_2.append(&mut self.line_3);
+            }
+        }
+    }
+
+    /// Appends a character to the line the text is currently written to, wraps the line if the character
+    /// doesn't fit in `line_width`. Widths are measured, since the glyphs of the fallback font differ in size.
+    fn put_char(&mut self, chr: char, line_width: f32, measure: &dyn Fn(&[char]) -> f32) {
+        // prompts are only for engine messages, so mods can't rely on their encoding
+        if prompts::token_mask(chr).is_some() {
+            return;
+        }
+
+        let line = self.current_line_mut();
+        if !line.is_empty() && measure(line) + measure(&[chr]) > line_width {
+            self.new_line();
+        }
+
+        self.current_line_mut().push(chr);
+    }
+
     pub fn start_script(&mut self, event_num: u16) {
         self.reset();
         self.state = TextScriptExecutionState::Running(event_num, 0);
@@ -347,6 +768,109 @@ impl TextScriptVM {
         log::info!("Started script: #{:04}", event_num);
     }
 
+    pub fn is_running(&self) -> bool {
+        self.state != TextScriptExecutionState::Ended
+    }
+
+    /// Starts an event following the interruption rules, returns false if it has been ignored.
+    pub fn start_event(&mut self, event_num: u16, trigger: EventTrigger, control_flags: &mut ControlFlags) -> bool {
+        if trigger == EventTrigger::Interaction && (self.is_running() || control_flags.interactions_disabled()) {
+            return false;
+        }
+
+        control_flags.set_tick_world(true);
+        control_flags.set_interactions_disabled(true);
+        self.start_script(event_num);
+        true
+    }
+
+    /// Remaining ticks after a tick of `<WAI`, `None` once the wait is over.
+    fn next_wait(ticks: u16) -> Option<u16> {
+        match ticks {
+            WAI_FOREVER => Some(WAI_FOREVER),
+            0 => None,
+            n => Some(n - 1),
+        }
+    }
+
+    /// Ticks between the characters of a message, scaled by the message delay accessibility setting.
+    /// Only the printing is affected, `<WAI` and everything else keep their timing.
+    fn char_delay(state: &SharedGameState) -> u8 {
+        let delay = state.constants.textscript.text_speed as f32 * state.settings.message_delay();
+        clamp(delay.round(), 1.0, 255.0) as u8
+    }
+
+    /// State printing `len` characters of text starting at `ip`, `delay` ticks apart.
+    fn start_message(&self, event: u16, ip: u32, len: u32, delay: u8) -> TextScriptExecutionState {
+        // instant text shows up in the same tick, so a following <NOD waits with the whole text on screen
+        let counter = if self.flags.instant_text() { 0 } else { delay };
+        TextScriptExecutionState::Msg(event, ip, len, counter)
+    }
+
+    /// Width of a line in the message box, in font pixels at the normal text size.
+    /// Infinite when wrapping is off, so nothing but `\n` ends a line.
+    fn line_width(state: &SharedGameState) -> f32 {
+        if !state.settings.allow_message_wrap() {
+            return f32::INFINITY;
+        }
+
+        let (box_width, _, text_scale) = GameScene::text_box_size(state);
+        let face_width = if state.textscript_vm.face == 0 { 0.0 } else { 56.0 };
+
+        (box_width - face_width - 2.0 * TEXT_MARGIN) / text_scale
+    }
+
+    /// Single tick of the text printing, returns true if the text blip should be played.
+    fn tick_message(&mut self, fast_forward: bool, delay: u8, line_width: f32, measure: &dyn Fn(&[char]) -> f32) -> GameResult<bool> {
+        let (event, ip, remaining, counter) = match self.state {
+            TextScriptExecutionState::Msg(event, ip, remaining, counter) => (event, ip, remaining, counter),
+            _ => { return Ok(false); }
+        };
+
+        if counter > 0 {
+            self.state = TextScriptExecutionState::Msg(event, ip, remaining, counter - 1);
+            return Ok(false);
+        }
+
+        let instant = self.flags.instant_text();
+        let count = if instant { remaining } else { remaining.min(1) };
+        let mut chars = Vec::with_capacity(count as usize);
+        let position = match self.scripts.find_script(event) {
+            Some(bytecode) => {
+                let mut cursor = Cursor::new(bytecode);
+                cursor.seek(SeekFrom::Start(ip as u64))?;
+
+                for _ in 0..count {
+                    chars.push(std::char::from_u32(read_cur_varint(&mut cursor)? as u32).unwrap_or(REPLACEMENT_CHAR));
+                }
+
+                cursor.position() as u32
+            }
+            None => {
+                self.reset();
+                return Ok(false);
+            }
+        };
+
+        for chr in chars {
+            match chr {
+                '\n' => self.new_line(),
+                '\r' => {}
+                _ => self.put_char(chr, line_width, measure),
+            }
+        }
+
+        if remaining > count {
+            // the next character is printed after the delay, counting this tick in
+            let ticks = if fast_forward { 0 } else { delay.saturating_sub(1) };
+            self.state = TextScriptExecutionState::Msg(event, position, remaining - count, ticks);
+            Ok(true)
+        } else {
+            self.state = TextScriptExecutionState::Running(event, position);
+            Ok(false)
+        }
+    }
+
     pub fn run(state: &mut SharedGameState, game_scene: &mut GameScene, ctx: &mut Context) -> GameResult {
         loop {
             if state.textscript_vm.suspend { break; }
@@ -356,7 +880,13 @@ impl TextScriptVM {
                     break;
                 }
                 TextScriptExecutionState::Running(event, ip) => {
-                    state.control_flags.set_flag_x01(true);
+                    if ip == 0 {
+                        if let Some(run) = state.challenge.as_mut() {
+                            run.event_started(event);
+                        }
+                    }
+
+                    state.control_flags.set_tick_world(true);
                     state.control_flags.set_interactions_disabled(true);
                     state.textscript_vm.state = TextScriptVM::execute(event, ip, state, game_scene, ctx)?;
 
@@ -364,60 +894,30 @@ impl TextScriptVM {
                         state.textscript_vm.reset();
                     }
                 }
-                TextScriptExecutionState::Msg(event, ip, remaining, counter) => {
-                    if counter > 0 {
-                        state.textscript_vm.state = TextScriptExecutionState::Msg(event, ip, remaining, counter - 1);
-                        break;
-                    }
-
-                    if let Some(bytecode) = state.textscript_vm.scripts.find_script(event) {
-                        let mut cursor = Cursor::new(bytecode);
-                        cursor.seek(SeekFrom::Start(ip as u64))?;
-
-                        let chr = std::char::from_u32(read_cur_varint(&mut cursor)? as u32).unwrap_or('\u{fffd}');
+                TextScriptExecutionState::Msg(..) => {
+                    let fast_forward = state.key_state.jump() || state.key_state.fire();
+                    let delay = TextScriptVM::char_delay(state);
+                    let line_width = TextScriptVM::line_width(state);
+                    let (font, constants) = (&state.font, &state.constants);
+                    let measure = |chars: &[char]| font.text_width(chars.iter().copied(), constants);
 
-                        match chr {
-                            '\n' if state.textscript_vm.current_line == TextScriptLine::Line1 => {
-                                state.textscript_vm.current_line = TextScriptLine::Line2;
-                            }
-                            '\n' if state.textscript_vm.current_line == TextScriptLine::Line2 => {
-                                state.textscript_vm.current_line = TextScriptLine::Line3;
-                            }
-                            '\n' => {
-                                state.textscript_vm.line_1.clear();
-                                state.textscript_vm.line_1.append(&mut state.textscript_vm.line_2);
-                                state.textscript_vm.line_2.append(&mut state.textscript_vm.line_3);
-                            }
-                            '\r' => {}
-                            _ if state.textscript_vm.current_line == TextScriptLine::Line1 => {
-                                state.textscript_vm.line_1.push(chr);
-                            }
-                            _ if state.textscript_vm.current_line == TextScriptLine::Line2 => {
-                                state.textscript_vm.line_2.push(chr);
-                            }
-                            _ if state.textscript_vm.current_line == TextScriptLine::Line3 => {
-                                state.textscript_vm.line_3.push(chr);
-                            }
-                            _ => {}
-                        }
+                    if state.textscript_vm.tick_message(fast_forward, delay, line_width, &measure)? {
+                        state.sound_manager.play_sfx(state.constants.textscript.text_blip_sfx);
+                    }
 
-                        if remaining > 1 {
-                            let ticks = if state.key_state.jump() || state.key_state.fire() { 1 } else { 4 };
-                            state.sound_manager.play_sfx(2);
-                            state.textscript_vm.state = TextScriptExecutionState::Msg(event, cursor.position() as u32, remaining - 1, ticks);
-                        } else {
-                            state.textscript_vm.state = TextScriptExecutionState::Running(event, cursor.position() as u32);
-                        }
-                    } else {
-                        state.textscript_vm.reset();
+                    if let TextScriptExecutionState::Msg(..) = state.textscript_vm.state {
+                        break;
                     }
                 }
                 TextScriptExecutionState::WaitTicks(event, ip, ticks) => {
-                    if ticks == 0 {
-                        state.textscript_vm.state = TextScriptExecutionState::Running(event, ip);
-                    } else {
-                        state.textscript_vm.state = TextScriptExecutionState::WaitTicks(event, ip, ticks - 1);
-                        break;
+                    match TextScriptVM::next_wait(ticks) {
+                        Some(ticks) => {
+                            state.textscript_vm.state = TextScriptExecutionState::WaitTicks(event, ip, ticks);
+                            break;
+                        }
+                        None => {
+                            state.textscript_vm.state = TextScriptExecutionState::Running(event, ip);
+                        }
                     }
                 }
                 TextScriptExecutionState::WaitConfirmation(event, ip, no_event, wait, selection) => {
@@ -426,12 +926,15 @@ impl TextScriptVM {
                         break;
                     }
 
-                    if state.key_trigger.left() || state.key_trigger.right() {
+                    if resolve_movement(state.key_trigger).is_some() {
                         state.textscript_vm.state = TextScriptExecutionState::WaitConfirmation(event, ip, no_event, 0, !selection);
                         break;
                     }
 
                     if state.key_trigger.jump() {
+                        // whatever follows the prompt is printed at once
+                        state.textscript_vm.flags.set_instant_text(true);
+
                         match selection {
                             ConfirmSelection::Yes => {
                                 state.textscript_vm.state = TextScriptExecutionState::Running(event, ip);
@@ -457,7 +960,25 @@ impl TextScriptVM {
                     break;
                 }
                 TextScriptExecutionState::WaitFade(event, ip) => {
-                    if state.fade_state == FadeState::Hidden || state.fade_state == FadeState::Visible {
+                    if state.fade_state.is_done() {
+                        state.textscript_vm.state = TextScriptExecutionState::Running(event, ip);
+                    }
+                    break;
+                }
+                TextScriptExecutionState::WaitSave(event, ip) => {
+                    // gone if restored from a save state, nothing to wait for then
+                    let result = match &state.pending_save {
+                        Some(pending) => pending.poll(),
+                        None => Some(Ok(())),
+                    };
+
+                    if let Some(result) = result {
+                        state.pending_save = None;
+                        if let Err(e) = result {
+                            log::error!("Cannot save the profile: {}", e);
+                            state.notifications.push(str!("The game couldn't be saved."));
+                        }
+
                         state.textscript_vm.state = TextScriptExecutionState::Running(event, ip);
                     }
                     break;
@@ -469,6 +990,11 @@ impl TextScriptVM {
     }
 
     pub fn execute(event: u16, ip: u32, state: &mut SharedGameState, game_scene: &mut GameScene, ctx: &mut Context) -> GameResult<TextScriptExecutionState> {
+        TextScriptVM::execute_op(event, ip, state, game_scene, Some(ctx))
+    }
+
+    /// Runs the opcode at `ip`, the ones loading data (<CMU, <INI and <LDP) fail without a context.
+    fn execute_op(event: u16, ip: u32, state: &mut SharedGameState, game_scene: &mut GameScene, ctx: Option<&mut Context>) -> GameResult<TextScriptExecutionState> {
         let mut exec_state = state.textscript_vm.state;
         let mut tick_npc = 0u16;
 
@@ -480,7 +1006,17 @@ impl TextScriptVM {
                 .unwrap_or_else(|_| OpCode::END as i32));
 
             if let Some(op) = op_maybe {
-                println!("opcode: {:?}", op);
+                state.textscript_vm.last_executed = Some((event, ip, op));
+                if state.textscript_vm.trace_enabled {
+                    let entry = TraceEntry::read(game_scene.tick, event, ip, op, cursor.clone());
+                    log::info!("TSC {}", entry);
+
+                    let trace = &mut state.textscript_vm.trace;
+                    if trace.len() >= TRACE_CAPACITY {
+                        trace.pop_front();
+                    }
+                    trace.push_back(entry);
+                }
                 match op {
                     OpCode::_NOP => {
                         exec_state = TextScriptExecutionState::Running(event, cursor.position() as u32);
@@ -489,7 +1025,7 @@ impl TextScriptVM {
                     OpCode::_STR => {
                         let mut len = read_cur_varint(&mut cursor)? as u32;
                         if state.textscript_vm.flags.render() {
-                            exec_state = TextScriptExecutionState::Msg(event, cursor.position() as u32, len, 4);
+                            exec_state = state.textscript_vm.start_message(event, cursor.position() as u32, len, TextScriptVM::char_delay(state));
                         } else {
                             while len > 0 {
                                 len -= 1;
@@ -500,28 +1036,30 @@ impl TextScriptVM {
                         }
                     }
                     OpCode::_END | OpCode::END => {
-                        state.control_flags.set_flag_x01(true);
+                        state.control_flags.set_tick_world(true);
                         state.control_flags.set_control_enabled(true);
                         state.control_flags.set_interactions_disabled(false);
 
-                        state.textscript_vm.flags.set_render(false);
-                        state.textscript_vm.flags.set_background_visible(false);
+                        // the inventory keeps the description up until the cursor moves
+                        if !state.textscript_vm.scripts.inventory_active {
+                            state.textscript_vm.flags.set_render(false);
+                            state.textscript_vm.flags.set_background_visible(false);
+                            state.textscript_vm.flags.set_instant_text(false);
+                        }
 
                         game_scene.player.update_target = true;
 
                         exec_state = TextScriptExecutionState::Ended;
                     }
                     OpCode::PRI => {
-                        state.control_flags.set_flag_x01(false);
-                        state.control_flags.set_control_enabled(false);
+                        state.control_flags.freeze_all();
 
                         game_scene.player.shock_counter = 0;
 
                         exec_state = TextScriptExecutionState::Running(event, cursor.position() as u32);
                     }
                     OpCode::KEY => {
-                        state.control_flags.set_flag_x01(true);
-                        state.control_flags.set_control_enabled(false);
+                        state.control_flags.freeze_input();
 
                         game_scene.player.up = false;
                         game_scene.player.down = false;
@@ -530,8 +1068,7 @@ impl TextScriptVM {
                         exec_state = TextScriptExecutionState::Running(event, cursor.position() as u32);
                     }
                     OpCode::FRE => {
-                        state.control_flags.set_flag_x01(true);
-                        state.control_flags.set_control_enabled(true);
+                        state.control_flags.free();
 
                         exec_state = TextScriptExecutionState::Running(event, cursor.position() as u32);
                     }
@@ -584,13 +1121,13 @@ impl TextScriptVM {
                     }
                     OpCode::FLp | OpCode::FLm => {
                         let flag_num = read_cur_varint(&mut cursor)? as usize;
-                        state.game_flags.set(flag_num, op == OpCode::FLp);
                         exec_state = TextScriptExecutionState::Running(event, cursor.position() as u32);
+                        state.set_flag(flag_num, op == OpCode::FLp);
                     }
                     OpCode::FLJ => {
                         let flag_num = read_cur_varint(&mut cursor)? as usize;
                         let event_num = read_cur_varint(&mut cursor)? as u16;
-                        if let Some(true) = state.game_flags.get(flag_num) {
+                        if state.get_flag(flag_num) {
                             exec_state = TextScriptExecutionState::Running(event_num, 0);
                         } else {
                             exec_state = TextScriptExecutionState::Running(event, cursor.position() as u32);
@@ -606,6 +1143,21 @@ impl TextScriptVM {
                             exec_state = TextScriptExecutionState::Running(event, cursor.position() as u32);
                         }
                     }
+                    OpCode::INJ => {
+                        let item_id = read_cur_varint(&mut cursor)? as u16;
+                        let count = read_cur_varint(&mut cursor)? as u16;
+                        let event_num = read_cur_varint(&mut cursor)? as u16;
+
+                        if !state.csplus_tsc() {
+                            return Err(csplus_only(op));
+                        }
+
+                        if game_scene.inventory.item_count(item_id) == count {
+                            exec_state = TextScriptExecutionState::Running(event_num, 0);
+                        } else {
+                            exec_state = TextScriptExecutionState::Running(event, cursor.position() as u32);
+                        }
+                    }
                     OpCode::AMJ => {
                         let weapon = read_cur_varint(&mut cursor)? as u8;
                         let event_num = read_cur_varint(&mut cursor)? as u16;
@@ -652,9 +1204,7 @@ impl TextScriptVM {
                         let pos_y = read_cur_varint(&mut cursor)? as usize;
                         let tile_type = read_cur_varint(&mut cursor)? as u8;
 
-                        if let Some(ptr) = game_scene.stage.map.tiles.get_mut(pos_y * game_scene.stage.map.width + pos_x) {
-                            *ptr = tile_type;
-                        }
+                        game_scene.stage.map.change_tile(pos_x, pos_y, tile_type);
 
                         exec_state = TextScriptExecutionState::Running(event, cursor.position() as u32);
                     }
@@ -662,6 +1212,10 @@ impl TextScriptVM {
                         let life = read_cur_varint(&mut cursor)? as u16;
                         game_scene.player.life += life;
                         game_scene.player.max_life += life;
+                        state.stats.record(StatEvent::LifeCapsule);
+                        if let Some(record) = state.play_record.as_mut() {
+                            record.life_capsules = record.life_capsules.saturating_add(1);
+                        }
 
                         exec_state = TextScriptExecutionState::Running(event, cursor.position() as u32);
                     }
@@ -686,7 +1240,7 @@ impl TextScriptVM {
                         state.textscript_vm.line_3.clear();
                         state.textscript_vm.flags.set_render(true);
                         state.textscript_vm.flags.set_background_visible(op != OpCode::MS2);
-                        state.textscript_vm.flags.set_flag_x10(state.textscript_vm.flags.flag_x40());
+                        state.textscript_vm.flags.set_instant_text(state.textscript_vm.flags.flag_x40());
                         state.textscript_vm.flags.set_position_top(op != OpCode::MSG);
                         if op == OpCode::MS2 {
                             state.textscript_vm.face = 0;
@@ -697,11 +1251,19 @@ impl TextScriptVM {
                     OpCode::CLO => {
                         state.textscript_vm.flags.set_render(false);
                         state.textscript_vm.flags.set_background_visible(false);
-                        state.textscript_vm.flags.set_flag_x10(false);
+                        state.textscript_vm.flags.set_instant_text(false);
                         state.textscript_vm.flags.set_position_top(false);
 
                         exec_state = TextScriptExecutionState::Running(event, cursor.position() as u32);
                     }
+                    OpCode::SAT | OpCode::CAT | OpCode::TUR => {
+                        match state.constants.textscript.instant_text_command(op) {
+                            InstantTextCommand::InstantText => state.textscript_vm.flags.set_instant_text(true),
+                            InstantTextCommand::Ignored => {}
+                        }
+
+                        exec_state = TextScriptExecutionState::Running(event, cursor.position() as u32);
+                    }
                     OpCode::YNJ => {
                         let event_no = read_cur_varint(&mut cursor)? as u16;
 
@@ -714,18 +1276,31 @@ impl TextScriptVM {
                         exec_state = TextScriptExecutionState::Running(event, cursor.position() as u32);
                     }
                     OpCode::TRA => {
+                        // always pass through a fully black frame, even if the script didn't <FAO first.
+                        if state.fade_state != FadeState::Hidden {
+                            match state.fade_state {
+                                FadeState::FadeOut(_, _, _) => {}
+                                _ => { state.fade_state = state.transition_type(None).fade_out(FadeDirection::Center); }
+                            }
+
+                            // re-run this instruction once the fade is finished
+                            return Ok(TextScriptExecutionState::WaitFade(event, ip));
+                        }
+
                         let map_id = read_cur_varint(&mut cursor)? as usize;
                         let event_num = read_cur_varint(&mut cursor)? as u16;
                         let pos_x = read_cur_varint(&mut cursor)? as isize * 16 * 0x200;
                         let pos_y = read_cur_varint(&mut cursor)? as isize * 16 * 0x200;
 
-                        let mut new_scene = GameScene::new(state, ctx, map_id)?;
-                        new_scene.inventory = game_scene.inventory.clone();
-                        new_scene.player = game_scene.player.clone();
-                        new_scene.player.vel_x = 0;
-                        new_scene.player.vel_y = 0;
-                        new_scene.player.x = pos_x;
-                        new_scene.player.y = pos_y;
+                        state.stage_history.push(StageHistoryEntry {
+                            stage_id: game_scene.stage_id,
+                            x: game_scene.player.x,
+                            y: game_scene.player.y,
+                            event,
+                        });
+
+                        let new_scene = TransitionScene::new(map_id, pos_x, pos_y,
+                                                             game_scene.player.clone(), game_scene.inventory.clone());
 
                         state.textscript_vm.flags.0 = 0;
                         state.textscript_vm.face = 0;
@@ -755,6 +1330,7 @@ impl TextScriptVM {
                         let control_mode = read_cur_varint(&mut cursor)? as u8;
 
                         let mode: Option<ControlMode> = FromPrimitive::from_u8(control_mode);
+                        let mode = mode.filter(|&mode| mode != ControlMode::Fixed || state.settings.allow_fixed_control());
                         if let Some(mode) = mode {
                             game_scene.player.control_mode = mode;
                         }
@@ -763,18 +1339,22 @@ impl TextScriptVM {
                     }
                     OpCode::FAI => {
                         let fade_type = read_cur_varint(&mut cursor)? as usize;
+                        let transition = read_cur_varint(&mut cursor)? as usize;
+                        let requested = if state.tsc_extensions().transitions() { TransitionType::from_int(transition) } else { None };
 
                         if let Some(direction) = FadeDirection::from_int(fade_type) {
-                            state.fade_state = FadeState::FadeIn(15, direction);
+                            state.fade_state = state.transition_type(requested).fade_in(direction);
                         }
 
                         exec_state = TextScriptExecutionState::WaitFade(event, cursor.position() as u32);
                     }
                     OpCode::FAO => {
                         let fade_type = read_cur_varint(&mut cursor)? as usize;
+                        let transition = read_cur_varint(&mut cursor)? as usize;
+                        let requested = if state.tsc_extensions().transitions() { TransitionType::from_int(transition) } else { None };
 
                         if let Some(direction) = FadeDirection::from_int(fade_type) {
-                            state.fade_state = FadeState::FadeOut(-15, direction.opposite());
+                            state.fade_state = state.transition_type(requested).fade_out(direction.opposite());
                         }
 
                         exec_state = TextScriptExecutionState::WaitFade(event, cursor.position() as u32);
@@ -786,6 +1366,131 @@ impl TextScriptVM {
 
                         exec_state = TextScriptExecutionState::Running(event, cursor.position() as u32);
                     }
+                    OpCode::BSL => {
+                        let event_num = read_cur_varint(&mut cursor)? as u16;
+
+                        let target = if event_num == 0 {
+                            Some(BossTarget::StageBoss)
+                        } else {
+                            game_scene.npc_map.npcs_by_event(event_num).iter()
+                                .find(|id| game_scene.npc_map.is_alive(**id))
+                                .map(|id| BossTarget::NPC(*id))
+                        };
+
+                        if let Some(target) = target {
+                            let life = game_scene.boss_life(Some(target));
+                            game_scene.boss_life_bar.attach(target, life);
+                            state.stats.record(StatEvent::BossFightStarted);
+                        } else {
+                            // nothing alive to follow, hide the bar
+                            game_scene.boss_life_bar.target = None;
+                        }
+
+                        exec_state = TextScriptExecutionState::Running(event, cursor.position() as u32);
+                    }
+                    OpCode::BOA => {
+                        game_scene.boss.action_num = read_cur_varint(&mut cursor)? as u16;
+
+                        exec_state = TextScriptExecutionState::Running(event, cursor.position() as u32);
+                    }
+                    OpCode::ACH => {
+                        let achievement = read_cur_varint(&mut cursor)? as u16;
+
+                        // freeware scripts can have it, it has always been ignored there
+                        if !state.csplus_tsc() {
+                            log::warn!("<ACH used, but it's a CS+ command and the csplus extension is disabled.");
+                        } else if state.stats.unlock_scripted(achievement) {
+                            state.notifications.push(format!("Achievement unlocked: #{}", achievement));
+                        }
+
+                        exec_state = TextScriptExecutionState::Running(event, cursor.position() as u32);
+                    }
+                    OpCode::S2MV => {
+                        let distance = read_cur_varint(&mut cursor)?;
+
+                        if !state.csplus_tsc() {
+                            return Err(csplus_only(op));
+                        }
+
+                        // todo: move the second player once there is one
+                        log::warn!("<2MV{:04} ignored, there's no second player.", distance);
+
+                        exec_state = TextScriptExecutionState::Running(event, cursor.position() as u32);
+                    }
+                    OpCode::STE => {
+                        let effect = read_cur_varint(&mut cursor)? as usize;
+
+                        if state.tsc_extensions().stage_effects() {
+                            game_scene.stage_effect.set_effect(StageEffectType::from_id(effect));
+                        } else {
+                            log::warn!("<STE used, but the stage_effects extension is disabled.");
+                        }
+
+                        exec_state = TextScriptExecutionState::Running(event, cursor.position() as u32);
+                    }
+                    OpCode::PSO => {
+                        let pose = read_cur_varint(&mut cursor)? as u8;
+
+                        if state.tsc_extensions().player_poses() {
+                            state.player_pose = FromPrimitive::from_u8(pose);
+                        } else {
+                            log::warn!("<PSO used, but the player_poses extension is disabled.");
+                        }
+
+                        exec_state = TextScriptExecutionState::Running(event, cursor.position() as u32);
+                    }
+                    OpCode::VAR | OpCode::VAp | OpCode::VAm => {
+                        let var_num = read_cur_varint(&mut cursor)? as usize;
+                        let value = read_cur_varint(&mut cursor)? as u16;
+
+                        if !state.tsc_extensions().variables() {
+                            log::warn!("<{:?} used, but the variables extension is disabled.", op);
+                        } else if let Some(var) = state.tsc_variables.get_mut(var_num) {
+                            *var = match op {
+                                OpCode::VAR => value,
+                                OpCode::VAp => var.wrapping_add(value),
+                                _ => var.wrapping_sub(value),
+                            };
+                        } else {
+                            log::warn!("Variable {} is out of range.", var_num);
+                        }
+
+                        exec_state = TextScriptExecutionState::Running(event, cursor.position() as u32);
+                    }
+                    OpCode::VAJ => {
+                        let var_num = read_cur_varint(&mut cursor)? as usize;
+                        let value = read_cur_varint(&mut cursor)? as u16;
+                        let event_num = read_cur_varint(&mut cursor)? as u16;
+
+                        if !state.tsc_extensions().variables() {
+                            log::warn!("<VAJ used, but the variables extension is disabled.");
+                        }
+
+                        if state.tsc_extensions().variables() && state.tsc_variables.get(var_num) == Some(&value) {
+                            exec_state = TextScriptExecutionState::Running(event_num, 0);
+                        } else {
+                            exec_state = TextScriptExecutionState::Running(event, cursor.position() as u32);
+                        }
+                    }
+                    OpCode::NUM => {
+                        let index = read_cur_varint(&mut cursor)? as usize;
+
+                        exec_state = TextScriptExecutionState::Running(event, cursor.position() as u32);
+
+                        // printed at once, like vanilla does
+                        let number = state.textscript_vm.numbers.get(index).copied().unwrap_or(0);
+                        let line_width = TextScriptVM::line_width(state);
+                        let (font, constants) = (&state.font, &state.constants);
+                        let measure = |chars: &[char]| font.text_width(chars.iter().copied(), constants);
+                        for chr in number.to_string().chars() {
+                            state.textscript_vm.put_char(chr, line_width, &measure);
+                        }
+                    }
+                    OpCode::FLA => {
+                        game_scene.flash.set_blink(state);
+
+                        exec_state = TextScriptExecutionState::Running(event, cursor.position() as u32);
+                    }
                     OpCode::MNA => {
                         game_scene.display_map_name(160);
 
@@ -793,17 +1498,17 @@ impl TextScriptVM {
                     }
                     OpCode::CMU => {
                         let song_id = read_cur_varint(&mut cursor)? as usize;
-                        state.sound_manager.play_song(song_id, &state.constants, ctx)?;
+                        state.sound_manager.play_song(song_id, &state.constants, needs_context(ctx, op)?)?;
 
                         exec_state = TextScriptExecutionState::Running(event, cursor.position() as u32);
                     }
                     OpCode::FMU => {
-                        state.sound_manager.play_song(0, &state.constants, ctx)?;
+                        state.sound_manager.fade_out_song()?;
 
                         exec_state = TextScriptExecutionState::Running(event, cursor.position() as u32);
                     }
                     OpCode::RMU => {
-                        state.sound_manager.restore_state()?;
+                        state.sound_manager.recall_song()?;
 
                         exec_state = TextScriptExecutionState::Running(event, cursor.position() as u32);
                     }
@@ -823,7 +1528,7 @@ impl TextScriptVM {
                     }
                     OpCode::FOM => {
                         let ticks = read_cur_varint(&mut cursor)? as isize;
-                        game_scene.frame.wait = ticks;
+                        game_scene.frame.set_wait(ticks);
                         game_scene.player.update_target = true;
 
                         exec_state = TextScriptExecutionState::Running(event, cursor.position() as u32);
@@ -831,10 +1536,10 @@ impl TextScriptVM {
                     OpCode::FON => {
                         let event_num = read_cur_varint(&mut cursor)? as u16;
                         let ticks = read_cur_varint(&mut cursor)? as isize;
-                        game_scene.frame.wait = ticks;
+                        game_scene.frame.set_wait(ticks);
                         game_scene.player.update_target = false;
 
-                        for npc_id in game_scene.npc_map.npc_ids.iter() {
+                        for npc_id in game_scene.npc_map.npcs_by_event(event_num).iter() {
                             if let Some(npc_cell) = game_scene.npc_map.npcs.get(npc_id) {
                                 let npc = npc_cell.borrow();
 
@@ -853,20 +1558,14 @@ impl TextScriptVM {
                         let action_num = read_cur_varint(&mut cursor)? as u16;
                         let direction = read_cur_varint(&mut cursor)? as usize;
 
-                        for npc_id in game_scene.npc_map.npc_ids.iter() {
+                        for npc_id in game_scene.npc_map.npcs_by_event(event_num).iter() {
                             if let Some(npc_cell) = game_scene.npc_map.npcs.get(npc_id) {
                                 let mut npc = npc_cell.borrow_mut();
 
                                 if npc.cond.alive() && npc.event_num == event_num {
                                     npc.action_num = action_num;
 
-                                    if direction == 4 {
-                                        npc.direction = if game_scene.player.x < npc.x {
-                                            Direction::Right
-                                        } else {
-                                            Direction::Left
-                                        };
-                                    } else if let Some(dir) = Direction::from_int(direction) {
+                                    if let Some(dir) = Direction::from_int_facing(direction, npc.x, game_scene.player.x) {
                                         npc.direction = dir;
                                     }
 
@@ -882,7 +1581,7 @@ impl TextScriptVM {
                         let new_type = read_cur_varint(&mut cursor)? as u16;
                         let direction = read_cur_varint(&mut cursor)? as usize;
 
-                        for npc_id in game_scene.npc_map.npc_ids.iter() {
+                        for npc_id in game_scene.npc_map.npcs_by_event(event_num).iter() {
                             if let Some(npc_cell) = game_scene.npc_map.npcs.get(npc_id) {
                                 let mut npc = npc_cell.borrow_mut();
 
@@ -915,14 +1614,10 @@ impl TextScriptVM {
                                     npc.anim_counter = 0;
                                     npc.vel_x = 0;
                                     npc.vel_y = 0;
+                                    npc.age = 0;
+                                    npc.lifetime = NPC::default_lifetime(new_type);
 
-                                    if direction == 4 {
-                                        npc.direction = if game_scene.player.x < npc.x {
-                                            Direction::Right
-                                        } else {
-                                            Direction::Left
-                                        };
-                                    } else if let Some(dir) = Direction::from_int(direction) {
+                                    if let Some(dir) = Direction::from_int_facing(direction, npc.x, game_scene.player.x) {
                                         npc.direction = dir;
                                     }
 
@@ -941,7 +1636,7 @@ impl TextScriptVM {
                         let y = read_cur_varint(&mut cursor)? as isize;
                         let direction = read_cur_varint(&mut cursor)? as usize;
 
-                        for npc_id in game_scene.npc_map.npc_ids.iter() {
+                        for npc_id in game_scene.npc_map.npcs_by_event(event_num).iter() {
                             if let Some(npc_cell) = game_scene.npc_map.npcs.get(npc_id) {
                                 let mut npc = npc_cell.borrow_mut();
 
@@ -949,13 +1644,7 @@ impl TextScriptVM {
                                     npc.x = x * 16 * 0x200;
                                     npc.y = y * 16 * 0x200;
 
-                                    if direction == 4 {
-                                        npc.direction = if game_scene.player.x < npc.x {
-                                            Direction::Right
-                                        } else {
-                                            Direction::Left
-                                        };
-                                    } else if let Some(dir) = Direction::from_int(direction) {
+                                    if let Some(dir) = Direction::from_int_facing(direction, npc.x, game_scene.player.x) {
                                         npc.direction = dir;
                                     }
 
@@ -976,14 +1665,23 @@ impl TextScriptVM {
                     OpCode::ITp => {
                         let item_id = read_cur_varint(&mut cursor)? as u16;
 
-                        game_scene.inventory.add_item(item_id);
+                        // items stack in CS+ challenges
+                        if state.csplus_tsc() && state.challenge.is_some() {
+                            game_scene.inventory.stack_item(item_id);
+                        } else {
+                            game_scene.inventory.add_item(item_id);
+                        }
 
                         exec_state = TextScriptExecutionState::Running(event, cursor.position() as u32);
                     }
                     OpCode::ITm => {
                         let item_id = read_cur_varint(&mut cursor)? as u16;
 
-                        game_scene.inventory.remove_item(item_id);
+                        if state.csplus_tsc() && state.challenge.is_some() {
+                            game_scene.inventory.consume_item(item_id);
+                        } else {
+                            game_scene.inventory.remove_item(item_id);
+                        }
 
                         exec_state = TextScriptExecutionState::Running(event, cursor.position() as u32);
                     }
@@ -992,6 +1690,8 @@ impl TextScriptVM {
                         let max_ammo = read_cur_varint(&mut cursor)? as u16;
                         let weapon_type: Option<WeaponType> = FromPrimitive::from_u8(weapon_id);
 
+                        state.textscript_vm.numbers[0] = max_ammo;
+
                         if let Some(wtype) = weapon_type {
                             game_scene.inventory.add_weapon(wtype, max_ammo);
                         }
@@ -1032,22 +1732,80 @@ impl TextScriptVM {
 
                         exec_state = TextScriptExecutionState::Running(event, cursor.position() as u32);
                     }
+                    OpCode::SVP => {
+                        exec_state = TextScriptExecutionState::Running(event, cursor.position() as u32);
+
+                        if !state.temporary_profile {
+                            // the script is borrowed from the state, `flush_stats` can't be called
+                            let mod_id = state.current_mod.as_ref().map(|m| m.id.as_str());
+                            state.stats.flush(mod_id);
+
+                            match GameProfile::dump(state, game_scene).save(state) {
+                                Ok(pending) => {
+                                    state.pending_save = Some(pending);
+                                    exec_state = TextScriptExecutionState::WaitSave(event, cursor.position() as u32);
+                                }
+                                Err(e) => {
+                                    log::error!("Cannot save the profile: {}", e);
+                                    state.notifications.push(str!("The game couldn't be saved."));
+                                }
+                            }
+                        }
+                    }
+                    OpCode::ESC => {
+                        state.teardown_game();
+                        game_scene.teardown();
+                        state.next_scene = Some(Box::new(TitleScene::new()));
+
+                        exec_state = TextScriptExecutionState::Ended;
+                    }
+                    OpCode::INI => {
+                        // restarts as the same character, like the CS+ Curly mode does
+                        let character = state.character;
+                        state.teardown_game();
+                        game_scene.teardown();
+                        // the start event is set once the new game's stage is loaded
+                        state.next_scene = Some(Box::new(TransitionScene::to(TransitionTarget::NewGame(character))));
+
+                        exec_state = TextScriptExecutionState::Ended;
+                    }
+                    OpCode::LDP => {
+                        state.teardown_game();
+                        game_scene.teardown();
+
+                        let profile = match GameProfile::load(state) {
+                            Ok(profile) => profile,
+                            Err(e) => {
+                                log::error!("Cannot load the profile: {}", e);
+                                None
+                            }
+                        };
+
+                        let target = match profile {
+                            Some(profile) => TransitionTarget::Profile(Box::new(profile)),
+                            None => {
+                                log::warn!("<LDP without a usable profile, starting a new game instead.");
+                                TransitionTarget::NewGame(PlayableCharacter::Quote)
+                            }
+                        };
+                        state.next_scene = Some(Box::new(TransitionScene::to(target)));
+
+                        exec_state = TextScriptExecutionState::Ended;
+                    }
                     // unimplemented opcodes
                     // Zero operands
-                    OpCode::CAT | OpCode::CIL | OpCode::CPS |
-                    OpCode::CRE | OpCode::CSS | OpCode::ESC | OpCode::FLA |
-                    OpCode::INI | OpCode::LDP | OpCode::MLP |
-                    OpCode::SAT | OpCode::SLP | OpCode::SPS |
-                    OpCode::STC | OpCode::SVP | OpCode::TUR => {
+                    OpCode::CIL | OpCode::CPS |
+                    OpCode::CRE | OpCode::CSS | OpCode::MLP |
+                    OpCode::SLP | OpCode::SPS | OpCode::STC => {
                         log::warn!("unimplemented opcode: {:?}", op);
 
                         exec_state = TextScriptExecutionState::Running(event, cursor.position() as u32);
                     }
                     // One operand codes
-                    OpCode::BOA | OpCode::BSL | OpCode::FOB | OpCode::NUM | OpCode::DNA |
+                    OpCode::FOB | OpCode::DNA |
                     OpCode::MPp | OpCode::SKm | OpCode::SKp |
                     OpCode::UNJ | OpCode::MPJ | OpCode::XX1 | OpCode::SIL |
-                    OpCode::SSS | OpCode::ACH => {
+                    OpCode::SSS => {
                         let par_a = read_cur_varint(&mut cursor)?;
 
                         log::warn!("unimplemented opcode: {:?} {}", op, par_a);
@@ -1065,22 +1823,30 @@ impl TextScriptVM {
                     }
                     // Three operand codes
                     OpCode::TAM => {
-                        let par_a = read_cur_varint(&mut cursor)?;
-                        let par_b = read_cur_varint(&mut cursor)?;
-                        let par_c = read_cur_varint(&mut cursor)?;
+                        let old_id = read_cur_varint(&mut cursor)? as u8;
+                        let new_id = read_cur_varint(&mut cursor)? as u8;
+                        let max_ammo = read_cur_varint(&mut cursor)? as u16;
+                        let old_type: Option<WeaponType> = FromPrimitive::from_u8(old_id);
+                        let new_type: Option<WeaponType> = FromPrimitive::from_u8(new_id);
 
-                        log::warn!("unimplemented opcode: {:?} {} {} {}", op, par_a, par_b, par_c);
+                        if let (Some(old_type), Some(new_type)) = (old_type, new_type) {
+                            if !game_scene.inventory.trade_weapon(old_type, new_type, max_ammo) {
+                                log::warn!("<TAM: the player has no {:?} to trade for {:?}.", old_type, new_type);
+                            }
+                        }
 
                         exec_state = TextScriptExecutionState::Running(event, cursor.position() as u32);
                     }
                     // Four operand codes
                     OpCode::SNP => {
-                        let par_a = read_cur_varint(&mut cursor)?;
-                        let par_b = read_cur_varint(&mut cursor)?;
-                        let par_c = read_cur_varint(&mut cursor)?;
-                        let par_d = read_cur_varint(&mut cursor)?;
+                        let npc_type = read_cur_varint(&mut cursor)? as u16;
+                        let x = read_cur_varint(&mut cursor)? as isize;
+                        let y = read_cur_varint(&mut cursor)? as isize;
+                        let direction = read_cur_varint(&mut cursor)? as usize;
 
-                        log::warn!("unimplemented opcode: {:?} {} {} {} {}", op, par_a, par_b, par_c, par_d);
+                        // a full list only drops the NPC, the script goes on
+                        let npc = NPCMap::create_script_npc(npc_type, x, y, direction, game_scene.player.x, &state.npc_table);
+                        game_scene.npc_map.spawn(npc, SCRIPT_NPC_START);
 
                         exec_state = TextScriptExecutionState::Running(event, cursor.position() as u32);
                     }
@@ -1089,6 +1855,13 @@ impl TextScriptVM {
                 exec_state = TextScriptExecutionState::Ended;
             }
         } else {
+            log::warn!("Event #{:04} does not exist, ending the script.", event);
+
+            state.control_flags.set_tick_world(true);
+            state.control_flags.set_control_enabled(true);
+            state.control_flags.set_interactions_disabled(false);
+            game_scene.player.update_target = true;
+
             return Ok(TextScriptExecutionState::Ended);
         }
 
@@ -1102,6 +1875,7 @@ impl TextScriptVM {
     }
 }
 
+/// Compiled TSC script, a bytecode buffer for every event.
 pub struct TextScript {
     event_map: HashMap<u16, Vec<u8>>,
 }
@@ -1132,11 +1906,17 @@ impl TextScript {
         let mut buf = Vec::new();
         data.read_to_end(&mut buf)?;
 
+        let encoding = TextScript::decrypt(&mut buf);
+        TextScript::compile_with_encoding(&buf, false, encoding)
+    }
+
+    /// Decrypts the data of a .tsc file in place, returns the encoding of the text.
+    /// The byte in the middle is the key and is left as is.
+    pub fn decrypt(buf: &mut [u8]) -> TextScriptEncoding {
         let half = buf.len() / 2;
-        let key = if let Some(0) = buf.get(half) {
-            0xf9
-        } else {
-            (-(*buf.get(half).unwrap() as isize)) as u8
+        let key = match buf.get(half) {
+            Some(0) | None => 0xf9,
+            Some(&key) => (-(key as isize)) as u8,
         };
 
         for (idx, byte) in buf.iter_mut().enumerate() {
@@ -1147,43 +1927,115 @@ impl TextScript {
             *byte = byte.wrapping_add(key);
         }
 
-        TextScript::compile(&buf, false)
+        // the key byte is left as is, so it's not taken into account
+        match (TextScriptEncoding::detect(&buf[..half]), TextScriptEncoding::detect(buf.get(half + 1..).unwrap_or(&[]))) {
+            (TextScriptEncoding::UTF8, TextScriptEncoding::UTF8) => TextScriptEncoding::UTF8,
+            _ => TextScriptEncoding::ShiftJIS,
+        }
+    }
+
+    /// Encrypts a plain text script in place, the reverse of `decrypt`.
+    pub fn encrypt(buf: &mut [u8]) {
+        let half = buf.len() / 2;
+        let key = match buf.get(half) {
+            Some(0) | None => 7,
+            Some(&key) => key,
+        };
+
+        for (idx, byte) in buf.iter_mut().enumerate() {
+            if idx == half {
+                continue;
+            }
+
+            *byte = byte.wrapping_add(key);
+        }
     }
 
     pub fn get_event_ids(&self) -> Vec<u16> {
         self.event_map.keys().copied().sorted().collect_vec()
     }
 
-    /// Compiles a decrypted text script data into internal bytecode.
+    /// Compiles a decrypted text script data into internal bytecode, the encoding is detected from the data.
     pub fn compile(data: &[u8], strict: bool) -> GameResult<TextScript> {
+        TextScript::compile_with_encoding(data, strict, TextScriptEncoding::detect(data))
+    }
+
+    /// Text is decoded to characters here, so the message box deals with `char`s only
+    /// and a multi-byte character takes a single typewriter tick.
+    pub fn compile_with_encoding(data: &[u8], strict: bool, encoding: TextScriptEncoding) -> GameResult<TextScript> {
         log::info!("data: {}", String::from_utf8_lossy(data));
 
-        let mut event_map = HashMap::new();
         let mut iter = data.iter().copied().peekable();
+        let event_map = TextScript::compile_events(&mut iter, strict, encoding, None).map_err(|e| match e {
+            GameError::ParseError { file, message, .. } => {
+                GameError::ParseError { file, offset: (data.len() - iter.len()) as u64, message }
+            }
+            e => e,
+        })?;
+
+        Ok(TextScript {
+            event_map
+        })
+    }
+
+    /// With a `log` the errors in events are collected there and the compiler carries on with the next event, see `check`.
+    fn compile_events<I: ExactSizeIterator<Item=u8>>(iter: &mut Peekable<I>, strict: bool, encoding: TextScriptEncoding,
+                                                     mut log: Option<&mut CheckLog>) -> GameResult<HashMap<u16, Vec<u8>>> {
+        let mut event_map = HashMap::new();
         let mut last_event = 0;
 
         while let Some(&chr) = iter.peek() {
             match chr {
                 b'#' => {
+                    let header_offset = log.as_ref().map_or(0, |log| log.offset(iter));
                     iter.next();
-                    let event_num = TextScript::read_number(&mut iter)? as u16;
-                    TextScript::skip_until(b'\n', &mut iter)?;
+                    let event_num = TextScript::read_checked_number(iter, log.as_deref_mut(),
+                                                                    |digits, _| format!("Malformed event number: {}", digits))? as u16;
+                    TextScript::skip_until(b'\n', iter)?;
                     last_event = event_num;
+                    if let Some(log) = log.as_mut() {
+                        log.event = event_num;
+                        log.events.insert(event_num);
+                    }
 
                     if event_map.contains_key(&event_num) {
                         if strict {
-                            return Err(ParseError(format!("Event {} has been defined twice.", event_num)));
+                            return Err(parse_error(format!("Event {} has been defined twice.", event_num)));
                         }
 
-                        match TextScript::skip_until(b'#', &mut iter).ok() {
+                        if let Some(log) = log.as_mut() {
+                            log.diagnostics.push(Diagnostic {
+                                offset: header_offset,
+                                event: Some(event_num),
+                                severity: Severity::Error,
+                                message: format!("Event {} has been defined twice, only the first one is used.", event_num),
+                            });
+                        }
+
+                        match TextScript::skip_until(b'#', iter).ok() {
                             Some(_) => { continue; }
                             None => { break; }
                         }
                     }
 
-                    let bytecode = TextScript::compile_event(&mut iter, strict, TextScriptEncoding::ShiftJIS)?;
-                    log::info!("Successfully compiled event #{} ({} bytes generated).", event_num, bytecode.len());
-                    event_map.insert(event_num, bytecode);
+                    match TextScript::compile_event(iter, strict, encoding, log.as_deref_mut()) {
+                        Ok(bytecode) => {
+                            log::info!("Successfully compiled event #{} ({} bytes generated).", event_num, bytecode.len());
+                            event_map.insert(event_num, bytecode);
+                        }
+                        Err(e) => match log.as_mut() {
+                            Some(log) => {
+                                let offset = log.offset(iter);
+                                log.diagnostics.push(Diagnostic::from_error(offset, Some(event_num), e));
+
+                                match TextScript::skip_until(b'#', iter).ok() {
+                                    Some(_) => { continue; }
+                                    None => { break; }
+                                }
+                            }
+                            None => { return Err(e); }
+                        }
+                    }
                 }
                 b'\r' | b'\n' | b' ' | b'\t' => {
                     iter.next();
@@ -1195,17 +2047,56 @@ impl TextScript {
                         continue;
                     }
 
-                    return Err(ParseError(format!("Unexpected token in event {}: {}", last_event, n as char)));
+                    let error = parse_error(format!("Unexpected token in event {}: {}", last_event, n as char));
+                    match log.as_mut() {
+                        Some(log) => {
+                            let offset = log.offset(iter);
+                            log.diagnostics.push(Diagnostic::from_error(offset, Some(last_event), error));
+
+                            match TextScript::skip_until(b'#', iter).ok() {
+                                Some(_) => { continue; }
+                                None => { break; }
+                            }
+                        }
+                        None => { return Err(error); }
+                    }
                 }
             }
         }
 
-        Ok(TextScript {
-            event_map
-        })
+        Ok(event_map)
     }
 
-    fn compile_event<I: Iterator<Item=u8>>(iter: &mut Peekable<I>, strict: bool, encoding: TextScriptEncoding) -> GameResult<Vec<u8>> {
+    /// Looks for mistakes in a decrypted script. It's compiled the way the engine does it, so anything failing
+    /// to load is reported, besides malformed numbers and jumps to events missing in both the script and `head`.
+    pub fn check(data: &[u8], encoding: TextScriptEncoding, head: Option<&TextScript>) -> Vec<Diagnostic> {
+        let mut log = CheckLog { data_len: data.len(), event: 0, events: HashSet::new(), jumps: Vec::new(), diagnostics: Vec::new() };
+        let mut iter = data.iter().copied().peekable();
+
+        if let Err(e) = TextScript::compile_events(&mut iter, false, encoding, Some(&mut log)) {
+            let offset = log.offset(&iter);
+            log.diagnostics.push(Diagnostic::from_error(offset, None, e));
+            return log.diagnostics;
+        }
+
+        // jumps are checked once all of the events are known, events which failed to compile count as well
+        for &(offset, event, op, target) in log.jumps.iter() {
+            if !log.events.contains(&target) && !head.is_some_and(|head| head.has_event(target)) {
+                log.diagnostics.push(Diagnostic {
+                    offset,
+                    event: Some(event),
+                    severity: Severity::Error,
+                    message: format!("<{} jumps to event {}, which doesn't exist.", op.as_ref(), target),
+                });
+            }
+        }
+
+        log.diagnostics.sort_by_key(|d| d.offset);
+        log.diagnostics
+    }
+
+    fn compile_event<I: ExactSizeIterator<Item=u8>>(iter: &mut Peekable<I>, strict: bool, encoding: TextScriptEncoding,
+                                                    mut log: Option<&mut CheckLog>) -> GameResult<Vec<u8>> {
         let mut bytecode = Vec::new();
         let mut char_buf = Vec::with_capacity(16);
 
@@ -1228,11 +2119,11 @@ impl TextScript {
                     iter.next();
                     let n = iter.next_tuple::<(u8, u8, u8)>()
                         .map(|t| [t.0, t.1, t.2])
-                        .ok_or_else(|| ParseError(str!("Script unexpectedly ended.")))?;
+                        .ok_or_else(|| parse_error(str!("Script unexpectedly ended.")))?;
 
                     let code = String::from_utf8_lossy(&n);
 
-                    TextScript::compile_code(code.as_ref(), strict, iter, &mut bytecode)?;
+                    TextScript::compile_code(code.as_ref(), strict, iter, &mut bytecode, log.as_deref_mut())?;
                 }
                 _ => {
                     char_buf.push(chr);
@@ -1260,6 +2151,8 @@ impl TextScript {
             remaining -= consumed;
             chars += 1;
 
+            // garbled text shouldn't stop a cutscene
+            let chr = if chr == '\u{fffd}' { REPLACEMENT_CHAR } else { chr };
             TextScript::put_varint(chr as i32, &mut tmp_buf);
         }
 
@@ -1291,7 +2184,7 @@ impl TextScript {
         let mut result = 0u32;
 
         for o in 0..5 {
-            let n = iter.next().ok_or_else(|| ParseError(str!("Script unexpectedly ended.")))?;
+            let n = iter.next().ok_or_else(|| parse_error(str!("Script unexpectedly ended.")))?;
             result |= (n as u32 & 0x7f) << (o * 7);
 
             if n & 0x80 == 0 { break; }
@@ -1300,75 +2193,56 @@ impl TextScript {
         Ok(((result << 31) ^ (result >> 1)) as i32)
     }
 
-    fn compile_code<I: Iterator<Item=u8>>(code: &str, strict: bool, iter: &mut Peekable<I>, out: &mut Vec<u8>) -> GameResult {
-        let instr = OpCode::from_str(code).map_err(|_| ParseError(format!("Unknown opcode: {}", code)))?;
+    fn compile_code<I: ExactSizeIterator<Item=u8>>(code: &str, strict: bool, iter: &mut Peekable<I>, out: &mut Vec<u8>,
+                                                   mut log: Option<&mut CheckLog>) -> GameResult {
+        let instr = OpCode::from_str(code).map_err(|_| parse_error(format!("Unknown opcode: {}", code)))?;
+        // right after the opcode
+        let op_offset = log.as_ref().map_or(0, |log| log.offset(iter) - 4);
 
-        match instr {
-            // Zero operand codes
-            OpCode::AEp | OpCode::CAT | OpCode::CIL | OpCode::CLO | OpCode::CLR | OpCode::CPS |
-            OpCode::CRE | OpCode::CSS | OpCode::END | OpCode::ESC | OpCode::FLA | OpCode::FMU |
-            OpCode::FRE | OpCode::HMC | OpCode::INI | OpCode::KEY | OpCode::LDP | OpCode::MLP |
-            OpCode::MM0 | OpCode::MNA | OpCode::MS2 | OpCode::MS3 | OpCode::MSG | OpCode::NOD |
-            OpCode::PRI | OpCode::RMU | OpCode::SAT | OpCode::SLP | OpCode::SMC | OpCode::SPS |
-            OpCode::STC | OpCode::SVP | OpCode::TUR | OpCode::WAS | OpCode::ZAM => {
-                TextScript::put_varint(instr as i32, out);
-            }
-            // One operand codes
-            OpCode::BOA | OpCode::BSL | OpCode::FOB | OpCode::FOM | OpCode::QUA | OpCode::UNI |
-            OpCode::MYB | OpCode::MYD | OpCode::FAI | OpCode::FAO | OpCode::WAI | OpCode::FAC |
-            OpCode::GIT | OpCode::NUM | OpCode::DNA | OpCode::DNP | OpCode::FLm | OpCode::FLp |
-            OpCode::MPp | OpCode::SKm | OpCode::SKp | OpCode::EQp | OpCode::EQm | OpCode::MLp |
-            OpCode::ITp | OpCode::ITm | OpCode::AMm | OpCode::UNJ | OpCode::MPJ | OpCode::YNJ |
-            OpCode::EVE | OpCode::XX1 | OpCode::SIL | OpCode::LIp | OpCode::SOU | OpCode::CMU |
-            OpCode::SSS | OpCode::ACH => {
-                let operand = TextScript::read_number(iter)?;
-                TextScript::put_varint(instr as i32, out);
-                TextScript::put_varint(operand as i32, out);
-            }
-            // Two operand codes
-            OpCode::FON | OpCode::MOV | OpCode::AMp | OpCode::NCJ | OpCode::ECJ | OpCode::FLJ |
-            OpCode::ITJ | OpCode::SKJ | OpCode::AMJ | OpCode::SMP | OpCode::PSp => {
-                let operand_a = TextScript::read_number(iter)?;
-                if strict { TextScript::expect_char(b':', iter)?; } else { iter.next().ok_or_else(|| ParseError(str!("Script unexpectedly ended.")))?; }
-                let operand_b = TextScript::read_number(iter)?;
-
-                TextScript::put_varint(instr as i32, out);
-                TextScript::put_varint(operand_a as i32, out);
-                TextScript::put_varint(operand_b as i32, out);
-            }
-            // Three operand codes
-            OpCode::ANP | OpCode::CNP | OpCode::INP | OpCode::TAM | OpCode::CMP => {
-                let operand_a = TextScript::read_number(iter)?;
-                if strict { TextScript::expect_char(b':', iter)?; } else { iter.next().ok_or_else(|| ParseError(str!("Script unexpectedly ended.")))?; }
-                let operand_b = TextScript::read_number(iter)?;
-                if strict { TextScript::expect_char(b':', iter)?; } else { iter.next().ok_or_else(|| ParseError(str!("Script unexpectedly ended.")))?; }
-                let operand_c = TextScript::read_number(iter)?;
-
-                TextScript::put_varint(instr as i32, out);
-                TextScript::put_varint(operand_a as i32, out);
-                TextScript::put_varint(operand_b as i32, out);
-                TextScript::put_varint(operand_c as i32, out);
-            }
-            // Four operand codes
-            OpCode::TRA | OpCode::MNP | OpCode::SNP => {
-                let operand_a = TextScript::read_number(iter)?;
-                if strict { TextScript::expect_char(b':', iter)?; } else { iter.next().ok_or_else(|| ParseError(str!("Script unexpectedly ended.")))?; }
-                let operand_b = TextScript::read_number(iter)?;
-                if strict { TextScript::expect_char(b':', iter)?; } else { iter.next().ok_or_else(|| ParseError(str!("Script unexpectedly ended.")))?; }
-                let operand_c = TextScript::read_number(iter)?;
-                if strict { TextScript::expect_char(b':', iter)?; } else { iter.next().ok_or_else(|| ParseError(str!("Script unexpectedly ended.")))?; }
-                let operand_d = TextScript::read_number(iter)?;
-
-                TextScript::put_varint(instr as i32, out);
-                TextScript::put_varint(operand_a as i32, out);
-                TextScript::put_varint(operand_b as i32, out);
-                TextScript::put_varint(operand_c as i32, out);
-                TextScript::put_varint(operand_d as i32, out);
-            }
-            _ => {
+        let count = match instr.operand_count() {
+            Some(count) => count,
+            None => {
                 TextScript::put_varint(OpCode::_UNI as i32, out);
                 log::warn!("Unimplemented opcode: {:?}", instr);
+                if let Some(log) = log {
+                    log.warn(op_offset, format!("<{} isn't supported by the engine, its operands are shown as text.", code));
+                }
+                return Ok(());
             }
+        };
+
+        let required = count - instr.optional_operands();
+        let mut operands = [0i32; 4];
+        for (i, operand) in operands.iter_mut().take(count).enumerate() {
+            if i >= required && iter.peek() != Some(&b':') {
+                break;
+            }
+
+            if i > 0 {
+                if strict {
+                    TextScript::expect_char(b':', iter)?;
+                } else {
+                    if let Some(log) = log.as_mut() {
+                        if iter.peek() != Some(&b':') {
+                            let offset = log.offset(iter);
+                            log.warn(offset, format!("Expected : between the operands of <{}.", code));
+                        }
+                    }
+                    iter.next().ok_or_else(|| parse_error(str!("Script unexpectedly ended.")))?;
+                }
+            }
+
+            *operand = TextScript::read_checked_number(iter, log.as_deref_mut(),
+                                                       |digits, number| format!("Malformed number in <{}: {} (read as {})", code, digits, number))?;
+        }
+
+        if let (Some(log), Some(i)) = (log, instr.jump_operand()) {
+            log.jumps.push((op_offset, log.event, instr, operands[i] as u16));
+        }
+
+        TextScript::put_varint(instr as i32, out);
+        for &operand in operands[..count].iter() {
+            TextScript::put_varint(operand, out);
         }
 
         Ok(())
@@ -1382,10 +2256,10 @@ impl TextScript {
                 Ok(())
             }
             Some(n) => {
-                Err(ParseError(format!("Expected {}, found {}", expect as char, n as char)))
+                Err(parse_error(format!("Expected {}, found {}", expect as char, n as char)))
             }
             None => {
-                Err(ParseError(str!("Script unexpectedly ended.")))
+                Err(parse_error(str!("Script unexpectedly ended.")))
             }
         }
     }
@@ -1399,7 +2273,7 @@ impl TextScript {
             }
         }
 
-        Err(ParseError(str!("Script unexpectedly ended.")))
+        Err(parse_error(str!("Script unexpectedly ended.")))
     }
 
     /// Reads a 4 digit TSC formatted number from iterator.
@@ -1410,7 +2284,25 @@ impl TextScript {
             .and_then(|result| iter.next().map(|v| result + 100 * v.wrapping_sub(b'0') as i32))
             .and_then(|result| iter.next().map(|v| result + 10 * v.wrapping_sub(b'0') as i32))
             .and_then(|result| iter.next().map(|v| result + v.wrapping_sub(b'0') as i32))
-            .ok_or_else(|| ParseError(str!("Script unexpectedly ended.")))
+            .ok_or_else(|| parse_error(str!("Script unexpectedly ended.")))
+    }
+
+    /// `read_number`, which also warns about anything besides digits when the script is being checked.
+    fn read_checked_number<I: ExactSizeIterator<Item=u8>>(iter: &mut Peekable<I>, log: Option<&mut CheckLog>,
+                                                          message: impl FnOnce(&str, i32) -> String) -> GameResult<i32> {
+        let log = match log {
+            Some(log) => log,
+            None => { return TextScript::read_number(iter); }
+        };
+
+        let offset = log.offset(iter);
+        let digits: Vec<u8> = iter.by_ref().take(4).collect();
+        let number = TextScript::read_number(&mut digits.iter().copied().peekable())?;
+        if !digits.iter().all(|b| b.is_ascii_digit()) {
+            log.warn(offset, message(&String::from_utf8_lossy(&digits), number));
+        }
+
+        Ok(number)
     }
 
 
@@ -1432,3 +2324,346 @@ fn test_varint() {
         assert_eq!(result, n);
     }
 }
+
+#[test]
+fn test_event_interruption() {
+    let mut vm = TextScriptVM::new();
+    let mut flags = ControlFlags(0);
+
+    assert!(vm.start_event(100, EventTrigger::Interaction, &mut flags));
+    assert!(flags.interactions_disabled());
+
+    // second NPC touched in the same tick
+    assert!(!vm.start_event(101, EventTrigger::Interaction, &mut flags));
+    assert_eq!(vm.state, TextScriptExecutionState::Running(100, 0));
+
+    // waiting, with control given back with <FRE
+    vm.state = TextScriptExecutionState::WaitTicks(100, 10, WAI_FOREVER);
+    flags.set_control_enabled(true);
+    assert!(!vm.start_event(101, EventTrigger::Interaction, &mut flags));
+
+    // boss killed during <WAI9999
+    assert!(vm.start_event(300, EventTrigger::Forced, &mut flags));
+    assert_eq!(vm.state, TextScriptExecutionState::Running(300, 0));
+
+    // after <END
+    vm.reset();
+    flags.set_interactions_disabled(false);
+    assert!(vm.start_event(101, EventTrigger::Interaction, &mut flags));
+}
+
+#[test]
+fn test_wait_ticks() {
+    assert_eq!(TextScriptVM::next_wait(0), None);
+    assert_eq!(TextScriptVM::next_wait(1), Some(0));
+    assert_eq!(TextScriptVM::next_wait(9998), Some(9997));
+    assert_eq!(TextScriptVM::next_wait(WAI_FOREVER), Some(WAI_FOREVER));
+}
+
+#[test]
+fn test_parse_error_offset() {
+    // points right after the unknown opcode
+    match TextScript::compile(b"#0100\n<XYZ<END", true) {
+        Err(GameError::ParseError { offset, .. }) => assert_eq!(offset, 10),
+        _ => panic!("expected a parse error"),
+    }
+}
+
+#[cfg(test)]
+fn event_text(script: &TextScript, event_num: u16) -> String {
+    // only works for events without opcode arguments, the line break after the event number is left out
+    let mut iter = script.event_map[&event_num].iter().copied();
+    let mut text = String::new();
+
+    while let Ok(op) = TextScript::read_varint(&mut iter) {
+        if op == OpCode::_STR as i32 {
+            let len = TextScript::read_varint(&mut iter).unwrap();
+            for _ in 0..len {
+                text.push(std::char::from_u32(TextScript::read_varint(&mut iter).unwrap() as u32).unwrap());
+            }
+        }
+    }
+
+    text.trim_start_matches('\n').to_owned()
+}
+
+#[cfg(test)]
+fn skip_header_break<I: Iterator<Item=u8>>(iter: &mut I) {
+    // the line break after the event number is a string of its own
+    let ops: Vec<i32> = (0..3).map(|_| TextScript::read_varint(iter).unwrap()).collect();
+    assert_eq!(ops, vec![OpCode::_STR as i32, 1, '\n' as i32]);
+}
+
+#[test]
+fn test_encodings() {
+    let utf8 = "#0100\n<MSGこんにちは<NOD<END".as_bytes();
+    assert_eq!(TextScriptEncoding::detect(utf8), TextScriptEncoding::UTF8);
+    assert_eq!(event_text(&TextScript::compile(utf8, true).unwrap(), 100), "こんにちは");
+
+    let shift_jis = b"#0100\n<MSG\x82\xb1\x82\xf1\x82\xc9\x82\xbf\x82\xcd<NOD<END";
+    assert_eq!(TextScriptEncoding::detect(shift_jis), TextScriptEncoding::ShiftJIS);
+    assert_eq!(event_text(&TextScript::compile(shift_jis, true).unwrap(), 100), "こんにちは");
+
+    // a lead byte followed by an invalid trail byte
+    let garbled = b"#0100\n<MSGA\x82\x20B<NOD<END";
+    assert_eq!(event_text(&TextScript::compile(garbled, true).unwrap(), 100), "A?B");
+}
+
+#[test]
+fn test_extension_opcodes() {
+    let script = TextScript::compile(b"#0100\n<VAR0001:0005<VA+0001:0002<VAJ0001:0007:0200<NUM0000<END", true).unwrap();
+    let bytecode = &script.event_map[&100];
+    let mut iter = bytecode.iter().copied();
+    skip_header_break(&mut iter);
+
+    let ops: Vec<i32> = (0..4).map(|_| TextScript::read_varint(&mut iter).unwrap()).collect();
+    assert_eq!(ops, vec![OpCode::VAR as i32, 1, 5, OpCode::VAp as i32]);
+
+    let mut extensions = TextScriptExtensions(0);
+    assert!(extensions.set_by_name("variables", true));
+    assert!(!extensions.set_by_name("nonexistent", true));
+    assert!(extensions.variables() && !extensions.stage_effects());
+    assert!(TextScriptExtensions::all().player_poses());
+    assert!(TextScriptExtensions::all().transitions());
+    assert!(TextScriptExtensions::all().csplus());
+
+    let script = TextScript::compile(b"#0100\n<2MV0016<INJ0001:0002:0200<END", true).unwrap();
+    let mut iter = script.event_map[&100].iter().copied();
+    skip_header_break(&mut iter);
+    let ops: Vec<i32> = (0..6).map(|_| TextScript::read_varint(&mut iter).unwrap()).collect();
+    assert_eq!(ops, vec![OpCode::S2MV as i32, 16, OpCode::INJ as i32, 1, 2, 200]);
+
+    // the transition of <FAI/<FAO can be left out
+    let script = TextScript::compile(b"#0100\n<FAI0004<FAO0001:0003<END", true).unwrap();
+    let mut iter = script.event_map[&100].iter().copied();
+    skip_header_break(&mut iter);
+    let ops: Vec<i32> = (0..6).map(|_| TextScript::read_varint(&mut iter).unwrap()).collect();
+    assert_eq!(ops, vec![OpCode::FAI as i32, 4, 0, OpCode::FAO as i32, 1, 3]);
+}
+
+#[test]
+fn test_instant_text() {
+    fn next<I: Iterator<Item=u8>>(iter: &mut I) -> i32 { TextScript::read_varint(iter).unwrap() }
+    let no_wrap = |_: &[char]| 0.0;
+
+    let script = TextScript::compile(b"#0100\n<MSGab<CATcd<NOD<END", true).unwrap();
+    let bytecode = script.event_map[&100].clone();
+    let mut vm = TextScriptVM::new();
+    vm.set_scene_script(script);
+
+    let mut iter = bytecode.iter().copied();
+    skip_header_break(&mut iter);
+    assert_eq!((next(&mut iter), next(&mut iter), next(&mut iter)), (OpCode::MSG as i32, OpCode::_STR as i32, 2));
+    let first = (bytecode.len() - iter.len()) as u32;
+
+    // typewriter, a character every 4 ticks, without a blip after the last one
+    let mut counts = Vec::new();
+    let mut blips = 0;
+    vm.state = vm.start_message(100, first, 2, 4);
+    while let TextScriptExecutionState::Msg(..) = vm.state {
+        if vm.tick_message(false, 4, 216.0, &no_wrap).unwrap() { blips += 1; }
+        counts.push(vm.line_1.len());
+    }
+    assert_eq!(counts, vec![0, 0, 0, 0, 1, 1, 1, 1, 2]);
+    assert_eq!(blips, 1);
+
+    // with the message delay doubled
+    let mut ticks = 0;
+    vm.line_1.clear();
+    vm.state = vm.start_message(100, first, 2, 8);
+    while let TextScriptExecutionState::Msg(..) = vm.state {
+        vm.tick_message(false, 8, 216.0, &no_wrap).unwrap();
+        ticks += 1;
+    }
+    assert_eq!((ticks, vm.line_1.len()), (17, 2));
+
+    let ip = match vm.state {
+        TextScriptExecutionState::Running(100, ip) => ip,
+        state => panic!("unexpected state {:?}", state),
+    };
+    let mut iter = bytecode[ip as usize..].iter().copied();
+    assert_eq!((next(&mut iter), next(&mut iter), next(&mut iter)), (OpCode::CAT as i32, OpCode::_STR as i32, 2));
+    let second = (bytecode.len() - iter.len()) as u32;
+
+    // after <CAT the whole text shows up in a single tick, silently
+    vm.flags.set_instant_text(true);
+    vm.state = vm.start_message(100, second, 2, 4);
+    assert!(!vm.tick_message(false, 4, 216.0, &no_wrap).unwrap());
+    assert_eq!(vm.line_1, vec!['a', 'b', 'c', 'd']);
+
+    // and <NOD is reached right after it
+    let ip = match vm.state {
+        TextScriptExecutionState::Running(100, ip) => ip,
+        state => panic!("unexpected state {:?}", state),
+    };
+    assert_eq!(next(&mut bytecode[ip as usize..].iter().copied()), OpCode::NOD as i32);
+
+    vm.reset();
+    assert!(!vm.flags.instant_text());
+}
+
+#[test]
+fn test_line_wrap() {
+    // 'W' is twice as wide as the rest, like a fallback glyph next to the bitmap font
+    let measure = |chars: &[char]| chars.iter().map(|&c| if c == 'W' { 12.0 } else { 6.0 }).sum::<f32>();
+    let mut vm = TextScriptVM::new();
+
+    for chr in "abcWd".chars() {
+        vm.put_char(chr, 30.0, &measure);
+    }
+    assert_eq!(vm.line_1, vec!['a', 'b', 'c', 'W']);
+    assert_eq!(vm.line_2, vec!['d']);
+
+    // scrolls once the last line is full
+    for chr in "efghijklmnop".chars() {
+        vm.put_char(chr, 30.0, &measure);
+    }
+    assert_eq!(vm.line_1, vec!['d', 'e', 'f', 'g', 'h']);
+    assert_eq!(vm.line_3, vec!['n', 'o', 'p']);
+}
+
+#[test]
+fn test_check() {
+    let script = b"#0100\n<MSG<EVE0200<END\n#0100\n<END\n#0200\n<FLJ00a1:0300<XYZ<END\n#0201\n<ITJ0001:0090<END";
+    let head = TextScript::compile(b"#0090\n<END", true).unwrap();
+
+    let diagnostics = TextScript::check(script, TextScriptEncoding::UTF8, Some(&head));
+    let found: Vec<(u64, Severity)> = diagnostics.iter().map(|d| (d.offset, d.severity)).collect();
+    assert_eq!(found, vec![
+        (23, Severity::Error), // second #0100
+        (40, Severity::Error), // <FLJ to the missing #0300
+        (44, Severity::Warning), // 00a1
+        (57, Severity::Error), // <XYZ
+    ]);
+    assert_eq!(diagnostics.iter().filter(|d| d.event == Some(200)).count(), 3);
+
+    let mut data = script.to_vec();
+    TextScript::encrypt(&mut data);
+    assert_ne!(&data[..], &script[..]);
+    assert_eq!(TextScript::decrypt(&mut data), TextScriptEncoding::UTF8);
+    assert_eq!(&data[..], &script[..]);
+}
+
+#[test]
+fn test_trace_entry() {
+    let script = TextScript::compile(b"#0100\n<FL+0042<TRA0012:0090:0010:0008<END", true).unwrap();
+    let bytecode = &script.event_map[&100];
+
+    let mut cursor = Cursor::new(bytecode);
+    // the line break after the event number
+    for _ in 0..3 {
+        read_cur_varint(&mut cursor).unwrap();
+    }
+    let ip = cursor.position() as u32;
+    let op: OpCode = FromPrimitive::from_i32(read_cur_varint(&mut cursor).unwrap()).unwrap();
+    let entry = TraceEntry::read(5, 100, ip, op, cursor.clone());
+    assert_eq!(entry.args(), &[42]);
+    assert_eq!(entry.to_string(), format!("[5] #0100 @ {}: <FL+0042", ip));
+
+    let _ = read_cur_varint(&mut cursor).unwrap();
+    let ip = cursor.position() as u32;
+    let op: OpCode = FromPrimitive::from_i32(read_cur_varint(&mut cursor).unwrap()).unwrap();
+    let entry = TraceEntry::read(6, 100, ip, op, cursor);
+    assert_eq!(entry.to_string(), format!("[6] #0100 @ {}: <TRA0012:0090:0010:0008", ip));
+
+    let vm = TextScriptVM::new();
+    assert!(vm.dump_state().starts_with("Event: none"));
+}
+
+#[test]
+fn test_snp() {
+    use crate::map::Map;
+    use crate::stage::Stage;
+
+    let script = TextScript::compile(b"#0100\n<SNP0001:0010:0005:0002<SNP0046:0003:0004:0004<SNP0046:0030:0004:0004<END", true).unwrap();
+    let mut state = SharedGameState::for_tests();
+    let map = Map { width: 8, height: 8, tiles: vec![0; 64], attrib: [0; 0x100], revision: 0 };
+    let mut scene = GameScene::for_tests(&mut state, 0, Stage::for_tests(map));
+    scene.player.x = 20 * 16 * 0x200;
+    state.textscript_vm.set_scene_script(script);
+
+    state.textscript_vm.state = TextScriptExecutionState::Running(100, 0);
+    while let TextScriptExecutionState::Running(event, ip) = state.textscript_vm.state {
+        state.textscript_vm.state = TextScriptVM::execute_op(event, ip, &mut state, &mut scene, None).unwrap();
+    }
+    assert_eq!(state.textscript_vm.state, TextScriptExecutionState::Ended);
+
+    let map = &scene.npc_map;
+    let spawned: Vec<(u16, u16, isize, isize, Direction)> = map.npc_ids.iter().map(|id| {
+        let npc = map.npcs[id].borrow();
+        (npc.id, npc.npc_type, npc.x / 0x200, npc.y / 0x200, npc.direction)
+    }).collect();
+    assert_eq!(spawned, vec![
+        (0x100, 1, 160, 80, Direction::Right),
+        // facing the player on both sides
+        (0x101, 46, 48, 64, Direction::Right),
+        (0x102, 46, 480, 64, Direction::Left),
+    ]);
+    // no event number
+    assert_eq!(map.npcs_by_event(0).len(), 3);
+}
+
+#[test]
+fn test_teardown_game() {
+    use crate::caret::CaretType;
+
+    // what <ESC, <INI and <LDP leave behind before switching scenes
+    let mut state = SharedGameState::for_tests();
+    state.create_caret(0, 0, CaretType::Zzz, Direction::Left);
+    state.new_npcs.push(NPCMap::create_npc(1, &state.npc_table));
+    state.quake_counter = 30;
+    state.textscript_vm.state = TextScriptExecutionState::Running(100, 12);
+
+    state.teardown_game();
+    assert!(state.carets.is_empty());
+    assert!(state.new_npcs.is_empty());
+    assert_eq!(state.quake_counter, 0);
+    assert_eq!(state.textscript_vm.state, TextScriptExecutionState::Ended);
+    // the rest of the event doesn't run in the scene being left
+    assert!(state.textscript_vm.suspend);
+}
+
+#[test]
+fn test_leave_game() {
+    use std::path::PathBuf;
+
+    use crate::map::Map;
+    use crate::mods::{ModInfo, ModManifest};
+    use crate::scene::Scene;
+    use crate::stage::Stage;
+
+    // runs event 100 in a scene with an NPC on it, returns the scene the script switched to
+    fn leave(script: &[u8], state: &mut SharedGameState) -> Box<dyn Scene> {
+        let map = Map { width: 8, height: 8, tiles: vec![0; 64], attrib: [0; 0x100], revision: 0 };
+        let mut scene = GameScene::for_tests(state, 0, Stage::for_tests(map));
+        let mut npc = NPCMap::create_npc(1, &state.npc_table);
+        npc.cond.set_alive(true);
+        scene.npc_map.spawn(npc, 1).unwrap();
+        state.textscript_vm.set_scene_script(TextScript::compile(script, true).unwrap());
+
+        state.textscript_vm.state = TextScriptExecutionState::Running(100, 0);
+        while let TextScriptExecutionState::Running(event, ip) = state.textscript_vm.state {
+            state.textscript_vm.state = TextScriptVM::execute_op(event, ip, state, &mut scene, None).unwrap();
+        }
+
+        assert!(scene.npc_map.npcs.is_empty());
+        assert!(state.textscript_vm.suspend);
+        state.next_scene.take().unwrap()
+    }
+
+    let mut state = SharedGameState::for_tests();
+    let next = leave(b"#0100\n<ESC<END", &mut state);
+    assert!(next.as_ref().as_any().is::<TitleScene>());
+
+    // the new game is started as the same character
+    state.character = PlayableCharacter::Curly;
+    let next = leave(b"#0100\n<INI<END", &mut state);
+    let transition = next.as_ref().as_any().downcast_ref::<TransitionScene>().unwrap();
+    assert!(matches!(transition.target, TransitionTarget::NewGame(PlayableCharacter::Curly)));
+
+    // there's no profile saved for a mod that doesn't exist
+    state.current_mod = Some(ModInfo { id: str!("test-no-profile"), path: PathBuf::new(), manifest: ModManifest::default() });
+    let next = leave(b"#0100\n<LDP<END", &mut state);
+    let transition = next.as_ref().as_any().downcast_ref::<TransitionScene>().unwrap();
+    assert!(matches!(transition.target, TransitionTarget::NewGame(PlayableCharacter::Quote)));
+}
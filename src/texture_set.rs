@@ -1,18 +1,20 @@
 use std::collections::HashMap;
-use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::io::Read;
 
 use image::RgbaImage;
 use itertools::Itertools;
-use log::info;
+use log::{info, warn};
 
-use crate::{common, ggez};
+use crate::common;
 use crate::common::FILE_TYPES;
 use crate::engine_constants::EngineConstants;
-use crate::ggez::{Context, GameError, GameResult, graphics};
+use crate::ggez::{Context, GameError, GameResult};
 use crate::ggez::filesystem;
-use crate::ggez::graphics::{Drawable, DrawMode, DrawParam, FilterMode, Image, Mesh, Rect};
+use crate::ggez::graphics::{Color, Drawable, DrawParam, FilterMode, Image, Rect};
 use crate::ggez::graphics::spritebatch::SpriteBatch;
 use crate::ggez::nalgebra::{Point2, Vector2};
+use crate::memory::{format_bytes, MemoryTracker};
+use crate::render::primitives::Primitives;
 use crate::str;
 
 pub struct SizedBatch {
@@ -23,6 +25,9 @@ pub struct SizedBatch {
     real_height: usize,
     scale_x: f32,
     scale_y: f32,
+    path: String,
+    /// Size of the RGBA images decoded to create the texture.
+    decoded_bytes: usize,
 }
 
 impl SizedBatch {
@@ -42,6 +47,21 @@ impl SizedBatch {
         (self.real_width, self.real_height)
     }
 
+    /// Path the texture has been loaded from.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Size of the texture in the GPU memory, 4 bytes per pixel.
+    pub fn gpu_bytes(&self) -> usize {
+        self.real_width * self.real_height * 4
+    }
+
+    /// How many times larger the image is than the texture it replaces.
+    pub fn resolution_multiplier(&self) -> f32 {
+        1.0 / self.scale_x
+    }
+
     pub fn to_rect(&self) -> common::Rect<usize> {
         common::Rect::<usize>::new(0, 0, self.width, self.height)
     }
@@ -78,6 +98,16 @@ impl SizedBatch {
         self.batch.add(param);
     }
 
+    /// Whole texture stretched over `rect`, meant for the white texel of the primitives.
+    fn add_quad(&mut self, rect: &common::Rect<f32>, color: [f32; 4]) {
+        let param = DrawParam::new()
+            .dest(Point2::new(rect.left, rect.top))
+            .scale(Vector2::new(rect.width() / self.real_width as f32, rect.height() / self.real_height as f32))
+            .color(Color::from(color));
+
+        self.batch.add(param);
+    }
+
     pub fn draw(&mut self, ctx: &mut Context) -> GameResult {
         self.batch.set_filter(FilterMode::Nearest);
         self.batch.draw(ctx, DrawParam::new())?;
@@ -86,16 +116,248 @@ impl SizedBatch {
     }
 }
 
+impl SizedBatch {
+    fn new(image: Image, path: String, scale_x: f32, scale_y: f32, decoded_bytes: usize) -> SizedBatch {
+        let size = image.dimensions();
+
+        SizedBatch {
+            width: (size.w * scale_x).round() as usize,
+            height: (size.h * scale_y).round() as usize,
+            real_width: size.w as usize,
+            real_height: size.h as usize,
+            scale_x,
+            scale_y,
+            path,
+            decoded_bytes,
+            batch: SpriteBatch::new(image),
+        }
+    }
+
+    /// Batch of an image made at runtime, drawn at its own size.
+    pub fn from_rgba(ctx: &mut Context, path: &str, rgba: &RgbaImage) -> GameResult<SizedBatch> {
+        let image = TextureSet::upload_image(ctx, rgba)?;
+        Ok(SizedBatch::new(image, str!(path), 1.0, 1.0, rgba_bytes(rgba)))
+    }
+}
+
+fn rgba_bytes(rgba: &RgbaImage) -> usize {
+    rgba.as_ref().len()
+}
+
+/// 1x1 white texture the primitives are drawn with, made at runtime.
+const WHITE_TEXTURE: &str = "builtin/white";
+
+/// Used all the time, never evicted to stay under the memory cap.
+const PINNED_TEXTURES: [&str; 10] = ["MyChar", "TextBox", "Fade", "Caret", "Bullet", "ArmsImage", "ItemImage", "Npc/NpcSym", "Npc/NpcRegu", WHITE_TEXTURE];
+
+/// Sheets shared by every stage are kept.
+fn is_stage_texture(name: &str) -> bool {
+    (name.starts_with("Stage/") || name.starts_with("Npc/") || name.starts_with("bk"))
+        && name != "Npc/NpcSym" && name != "Npc/NpcRegu"
+}
+
+/// Returns the scale to draw a replacement texture at so it covers the same area as the original,
+/// or None if it's not an integer multiple of the original size in both directions.
+fn texture_scale(size: (usize, usize), base: (usize, usize)) -> Option<(f32, f32)> {
+    if base.0 == 0 || base.1 == 0 || !size.0.is_multiple_of(base.0) || !size.1.is_multiple_of(base.1) {
+        return None;
+    }
+
+    let ratio = (size.0 / base.0, size.1 / base.1);
+    if ratio.0 == 0 || ratio.0 != ratio.1 {
+        return None;
+    }
+
+    Some((1.0 / ratio.0 as f32, 1.0 / ratio.1 as f32))
+}
+
+/// Raw texture file contents, see `TextureSet::read_texture`.
+pub struct TextureFiles {
+    pub name: String,
+    base_path: String,
+    base: Option<Vec<u8>>,
+    replacement: Option<(String, Vec<u8>)>,
+}
+
+pub struct DecodedTexture {
+    pub name: String,
+    base_path: String,
+    base: Option<RgbaImage>,
+    replacement: Option<(String, RgbaImage)>,
+}
+
+impl TextureFiles {
+    /// Decodes the images, can be done on any thread.
+    pub fn decode(self) -> GameResult<DecodedTexture> {
+        let base = match self.base {
+            Some(buf) => Some(TextureSet::decode_image(&self.base_path, &buf)?),
+            None => None,
+        };
+        let replacement = match self.replacement {
+            Some((path, buf)) => {
+                let rgba = TextureSet::decode_image(&path, &buf)?;
+                Some((path, rgba))
+            }
+            None => None,
+        };
+
+        Ok(DecodedTexture { name: self.name, base_path: self.base_path, base, replacement })
+    }
+}
+
+/// Window frame made of corner, edge and center pieces of the TextBox sheet, `border` is the size of the corners.
+#[derive(Debug, Copy, Clone)]
+pub struct NinePatch {
+    pub rect: common::Rect<usize>,
+    pub border: usize,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WindowStyle {
+    Normal,
+    /// <MS2, only the contents are drawn.
+    Invisible,
+    Inventory,
+}
+
+impl WindowStyle {
+    fn frame(self, constants: &EngineConstants) -> Option<NinePatch> {
+        match self {
+            WindowStyle::Normal => Some(constants.textscript.textbox_frame),
+            WindowStyle::Invisible => None,
+            WindowStyle::Inventory => Some(constants.textscript.inventory_frame),
+        }
+    }
+}
+
+/// Splits `from..to` into pieces at most `step` long, as (position, length).
+fn tile_span(from: isize, to: isize, step: usize) -> impl Iterator<Item=(isize, usize)> {
+    let step = step as isize;
+    (0..).map(move |i| from + i * step)
+        .take_while(move |&pos| step > 0 && pos < to)
+        .map(move |pos| (pos, (to - pos).min(step) as usize))
+}
+
+/// Positions and source rects of the pieces covering `dest`, edges and the center are tiled
+/// and the last piece of a row or column is cut short instead of stretched.
+fn nine_patch_pieces(patch: &NinePatch, dest: common::Rect<isize>) -> Vec<(isize, isize, common::Rect<usize>)> {
+    let (src, border) = (patch.rect, patch.border);
+    let b = border as isize;
+    // too small windows still get all four corners
+    let right = dest.right.max(dest.left + b * 2);
+    let bottom = dest.bottom.max(dest.top + b * 2);
+
+    let src_cols = [(src.left, border), (src.left + border, src.width() - border * 2), (src.right - border, border)];
+    let src_rows = [(src.top, border), (src.top + border, src.height() - border * 2), (src.bottom - border, border)];
+    let dst_cols = [(dest.left, dest.left + b), (dest.left + b, right - b), (right - b, right)];
+    let dst_rows = [(dest.top, dest.top + b), (dest.top + b, bottom - b), (bottom - b, bottom)];
+
+    let mut pieces = Vec::new();
+    for (&(src_y, src_h), &(dst_y0, dst_y1)) in src_rows.iter().zip(dst_rows.iter()) {
+        for (&(src_x, src_w), &(dst_x0, dst_x1)) in src_cols.iter().zip(dst_cols.iter()) {
+            for (y, h) in tile_span(dst_y0, dst_y1, src_h) {
+                for (x, w) in tile_span(dst_x0, dst_x1, src_w) {
+                    pieces.push((x, y, common::Rect::new_size(src_x, src_y, w, h)));
+                }
+            }
+        }
+    }
+
+    pieces
+}
+
+/// Size of the placeholder for textures without a known size.
+const PLACEHOLDER_SIZE: (usize, usize) = (256, 256);
+const PLACEHOLDER_CELL: usize = 8;
+
 pub struct TextureSet {
+    /// Read-only outside of the texture set, changes have to go through the accounting in `insert_batch`/`remove_batch`.
     pub tex_map: HashMap<String, SizedBatch>,
+    /// Fail on missing textures instead of drawing placeholders.
+    pub strict: bool,
     base_path: String,
+    /// Textures replaced with placeholders since the last `take_missing`.
+    missing: Vec<(String, GameError)>,
+    /// GPU bytes of the textures in `tex_map`.
+    memory: MemoryTracker<String>,
+    decoded_bytes: usize,
+    /// Soft cap of `memory`, least recently used unpinned textures are evicted when it's exceeded.
+    memory_cap: Option<usize>,
+    /// Use clock of `memory` when the current stage has been entered.
+    stage_start: u64,
 }
 
 impl TextureSet {
     pub fn new(base_path: &str) -> TextureSet {
         TextureSet {
             tex_map: HashMap::new(),
+            strict: false,
             base_path: base_path.to_string(),
+            missing: Vec::new(),
+            memory: MemoryTracker::new(),
+            decoded_bytes: 0,
+            memory_cap: None,
+            stage_start: 0,
+        }
+    }
+
+    /// Total size of the loaded textures in the GPU memory.
+    pub fn gpu_bytes(&self) -> usize {
+        self.memory.total()
+    }
+
+    /// Total size of the images decoded to load the textures.
+    pub fn decoded_bytes(&self) -> usize {
+        self.decoded_bytes
+    }
+
+    pub fn memory_cap(&self) -> Option<usize> {
+        self.memory_cap
+    }
+
+    pub fn set_memory_cap(&mut self, cap: Option<usize>) {
+        self.memory_cap = cap;
+        self.enforce_memory_cap(None);
+    }
+
+    /// Textures used from now on belong to the current stage, evicting them means the cap is too low.
+    pub fn begin_stage(&mut self) {
+        self.stage_start = self.memory.clock();
+    }
+
+    fn insert_batch(&mut self, name: &str, batch: SizedBatch) {
+        self.memory.insert(str!(name), batch.gpu_bytes(), PINNED_TEXTURES.contains(&name));
+        self.decoded_bytes += batch.decoded_bytes;
+        if let Some(old) = self.tex_map.insert(str!(name), batch) {
+            self.decoded_bytes -= old.decoded_bytes;
+        }
+
+        self.enforce_memory_cap(Some(name));
+    }
+
+    fn remove_batch(&mut self, name: &str) {
+        if let Some(old) = self.tex_map.remove(name) {
+            self.decoded_bytes -= old.decoded_bytes;
+        }
+        self.memory.remove(name);
+    }
+
+    /// Evicts textures until the total is under the cap, except for `keep` which is about to be used.
+    /// Evicted textures are loaded again the next time they're drawn.
+    fn enforce_memory_cap(&mut self, keep: Option<&str>) {
+        let cap = match self.memory_cap {
+            Some(cap) => cap,
+            None => { return; }
+        };
+
+        for name in self.memory.over_cap(cap, |name| Some(name.as_str()) == keep) {
+            if self.memory.last_used(&name).is_some_and(|used| used > self.stage_start) {
+                warn!("Evicted texture {} while the current stage uses it, the texture memory cap ({}) is too low.", name, format_bytes(cap));
+            } else {
+                info!("Evicted texture {} to stay under the memory cap.", name);
+            }
+
+            self.remove_batch(&name);
         }
     }
 
@@ -107,41 +369,30 @@ impl TextureSet {
         }
     }
 
-    fn load_image(&self, ctx: &mut Context, path: &str) -> GameResult<Image> {
-        let img = {
-            let mut buf = [0u8; 8];
-            let mut reader = filesystem::open(ctx, path)?;
-            reader.read_exact(&mut buf)?;
-            reader.seek(SeekFrom::Start(0))?;
-
-            let image = image::load(BufReader::new(reader), image::guess_format(&buf)?)?;
-            let mut rgba = image.to_rgba();
-            if image.color().channel_count() != 4 {
-                TextureSet::make_transparent(&mut rgba);
-            }
-            rgba
-        };
-        let (width, height) = img.dimensions();
+    pub fn decode_image(path: &str, buf: &[u8]) -> GameResult<RgbaImage> {
+        let image = image::load_from_memory(buf)
+            .map_err(|e| GameError::ResourceLoadError(format!("Cannot decode {}: {}", path, e)))?;
+        let mut rgba = image.to_rgba();
+        if image.color().channel_count() != 4 {
+            TextureSet::make_transparent(&mut rgba);
+        }
 
-        Image::from_rgba8(ctx, width as u16, height as u16, img.as_ref())
+        Ok(rgba)
     }
 
-    fn load_image_from_buf(&self, ctx: &mut Context, buf: &[u8]) -> GameResult<Image> {
-        let img = {
-            let image = image::load_from_memory(buf)?;
-            let mut rgba = image.to_rgba();
-            if image.color().channel_count() != 4 {
-                TextureSet::make_transparent(&mut rgba);
-            }
-            rgba
-        };
-        let (width, height) = img.dimensions();
+    fn read_file(ctx: &mut Context, path: &str) -> GameResult<Vec<u8>> {
+        let mut buf = Vec::new();
+        filesystem::open(ctx, path)?.read_to_end(&mut buf)?;
+        Ok(buf)
+    }
 
-        Image::from_rgba8(ctx, width as u16, height as u16, img.as_ref())
+    fn upload_image(ctx: &mut Context, rgba: &RgbaImage) -> GameResult<Image> {
+        let (width, height) = rgba.dimensions();
+        Image::from_rgba8(ctx, width as u16, height as u16, rgba.as_ref())
     }
 
-    pub fn load_texture(&self, ctx: &mut Context, constants: &EngineConstants, name: &str) -> GameResult<SizedBatch> {
-        let path = FILE_TYPES
+    fn find_texture(&self, ctx: &mut Context, name: &str) -> Option<String> {
+        FILE_TYPES
             .iter()
             .map(|ext| [&self.base_path, name, ext].join(""))
             .find(|path| filesystem::exists(ctx, path))
@@ -149,52 +400,250 @@ impl TextureSet {
                 .iter()
                 .map(|ext| [name, ext].join(""))
                 .find(|path| filesystem::exists(ctx, path)))
+    }
+
+    /// Replacement textures from the user's texture pack.
+    fn find_override(&self, ctx: &mut Context, name: &str) -> Option<String> {
+        FILE_TYPES
+            .iter()
+            .map(|ext| ["/textures/", name, ext].join(""))
+            .find(|path| filesystem::exists(ctx, path))
+    }
+
+    /// Reads the files of a texture, so they can be decoded without access to the context.
+    pub fn read_texture(&self, ctx: &mut Context, constants: &EngineConstants, name: &str) -> GameResult<TextureFiles> {
+        let base_path = self.find_texture(ctx, name)
             .ok_or_else(|| GameError::ResourceLoadError(format!("Texture {:?} does not exist.", name)))?;
 
-        info!("Loading texture: {}", path);
+        let replacement = match self.find_override(ctx, name) {
+            Some(path) => {
+                let buf = TextureSet::read_file(ctx, &path)?;
+                Some((path, buf))
+            }
+            None => None,
+        };
+
+        // the replacement's scale is calculated from the size of the original
+        let base = if replacement.is_none() || !constants.tex_sizes.contains_key(name) {
+            Some(TextureSet::read_file(ctx, &base_path)?)
+        } else {
+            None
+        };
+
+        Ok(TextureFiles { name: str!(name), base_path, base, replacement })
+    }
 
-        let image = self.load_image(ctx, &path)?;
+    /// Creates the GPU texture from decoded images, has to be called on the main thread.
+    pub fn upload_texture(&self, ctx: &mut Context, constants: &EngineConstants, texture: DecodedTexture) -> GameResult<SizedBatch> {
+        let DecodedTexture { name, base_path, mut base, replacement } = texture;
+        let mut decoded_bytes = base.as_ref().map_or(0, rgba_bytes);
+
+        if let Some((path, rgba)) = replacement {
+            let base_dimensions = match (constants.tex_sizes.get(name.as_str()), &base) {
+                (Some(dim), _) => *dim,
+                (None, Some(base)) => (base.width() as usize, base.height() as usize),
+                (None, None) => unreachable!(),
+            };
+
+            let size = rgba.dimensions();
+            decoded_bytes += rgba_bytes(&rgba);
+            match texture_scale((size.0 as usize, size.1 as usize), base_dimensions) {
+                Some((scale_x, scale_y)) => {
+                    info!("Loading texture: {} (replaces {}, {}x)", path, name, 1.0 / scale_x);
+                    let image = TextureSet::upload_image(ctx, &rgba)?;
+                    return Ok(SizedBatch::new(image, path, scale_x, scale_y, decoded_bytes));
+                }
+                None => {
+                    log::warn!("Ignoring {}: its size {}x{} is not an integer multiple of {}x{}.",
+                               path, size.0, size.1, base_dimensions.0, base_dimensions.1);
+                }
+            }
+        }
+
+        info!("Loading texture: {}", base_path);
+
+        let rgba = match base.take() {
+            Some(rgba) => rgba,
+            // skipped by `read_texture` since the replacement was expected to be used
+            None => {
+                let rgba = TextureSet::decode_image(&base_path, &TextureSet::read_file(ctx, &base_path)?)?;
+                decoded_bytes += rgba_bytes(&rgba);
+                rgba
+            }
+        };
+        let image = TextureSet::upload_image(ctx, &rgba)?;
         let size = image.dimensions();
 
         assert_ne!(size.w as isize, 0, "size.w == 0");
         assert_ne!(size.h as isize, 0, "size.h == 0");
 
+        // CS+ ships some of the textures at 2x resolution
         let dim = (size.w as usize, size.h as usize);
-        let orig_dimensions = constants.tex_sizes.get(name).unwrap_or_else(|| &dim);
+        let orig_dimensions = constants.tex_sizes.get(name.as_str()).unwrap_or_else(|| &dim);
         let scale_x = orig_dimensions.0 as f32 / size.w;
-        let scale_y = orig_dimensions.0 as f32 / size.w;
-        let width = (size.w * scale_x) as usize;
-        let height = (size.h * scale_y) as usize;
+        let scale_y = orig_dimensions.1 as f32 / size.h;
 
-        Ok(SizedBatch {
-            batch: SpriteBatch::new(image),
-            width,
-            height,
-            scale_x,
-            scale_y,
-            real_width: size.w as usize,
-            real_height: size.h as usize,
+        Ok(SizedBatch::new(image, base_path, scale_x, scale_y, decoded_bytes))
+    }
+
+    pub fn load_texture(&self, ctx: &mut Context, constants: &EngineConstants, name: &str) -> GameResult<SizedBatch> {
+        let texture = self.read_texture(ctx, constants, name)?.decode()?;
+        self.upload_texture(ctx, constants, texture)
+    }
+
+    /// Whether the texture is loaded or can be found in the game data.
+    pub fn exists(&self, ctx: &mut Context, name: &str) -> bool {
+        self.tex_map.contains_key(name) || self.find_texture(ctx, name).is_some()
+    }
+
+    /// Drops tilesets, backgrounds and NPC sheets, which will most likely differ in the next game.
+    pub fn unload_stage_textures(&mut self) {
+        let names: Vec<String> = self.tex_map.keys().filter(|name| is_stage_texture(name)).cloned().collect();
+        for name in names.iter() {
+            self.remove_batch(name);
+        }
+    }
+
+    /// Drops every texture, after the data files have changed.
+    pub fn clear(&mut self) {
+        self.tex_map.clear();
+        self.memory.clear();
+        self.decoded_bytes = 0;
+    }
+
+    /// Adds a texture loaded in advance, see `LoadingScene`.
+    pub fn insert(&mut self, name: &str, batch: SizedBatch) {
+        self.insert_batch(name, batch);
+    }
+
+    /// Magenta and black checkerboard drawn in place of missing textures.
+    fn placeholder_image(width: usize, height: usize) -> RgbaImage {
+        RgbaImage::from_fn(width as u32, height as u32, |x, y| {
+            if (x as usize / PLACEHOLDER_CELL + y as usize / PLACEHOLDER_CELL).is_multiple_of(2) {
+                image::Rgba([255, 0, 255, 255])
+            } else {
+                image::Rgba([0, 0, 0, 255])
+            }
         })
     }
 
+    fn load_placeholder(&self, ctx: &mut Context, constants: &EngineConstants, name: &str) -> GameResult<SizedBatch> {
+        let (width, height) = constants.tex_sizes.get(name).copied().unwrap_or(PLACEHOLDER_SIZE);
+        let image = TextureSet::upload_image(ctx, &TextureSet::placeholder_image(width, height))?;
+
+        Ok(SizedBatch::new(image, format!("<missing {}>", name), 1.0, 1.0, width * height * 4))
+    }
+
+    /// Textures replaced with placeholders since the last call, along with the reason.
+    pub fn take_missing(&mut self) -> Vec<(String, GameError)> {
+        std::mem::take(&mut self.missing)
+    }
+
+    /// Loads the texture on first use, missing textures are replaced with a placeholder unless `strict` is set.
     pub fn get_or_load_batch(&mut self, ctx: &mut Context, constants: &EngineConstants, name: &str) -> GameResult<&mut SizedBatch> {
         if !self.tex_map.contains_key(name) {
-            let batch = self.load_texture(ctx, constants, name)?;
-            self.tex_map.insert(str!(name), batch);
+            let batch = match self.load_texture(ctx, constants, name) {
+                Ok(batch) => batch,
+                Err(e) if self.strict => { return Err(e); }
+                Err(e) => {
+                    let batch = self.load_placeholder(ctx, constants, name)?;
+                    self.missing.push((str!(name), e));
+                    batch
+                }
+            };
+            self.insert_batch(name, batch);
+        } else {
+            self.memory.touch(name);
         }
 
         Ok(self.tex_map.get_mut(name).unwrap())
     }
 
-    pub fn draw_rect(&self, rect: common::Rect, color: [f32; 4], ctx: &mut Context) -> GameResult {
-        let rect = Mesh::new_rectangle(ctx, DrawMode::fill(), rect.into(), color.into())?;
-        graphics::draw(ctx, &rect, DrawParam::new())?;
-        Ok(())
+    /// Draws a window frame covering `rect`, snapped to the canvas pixel grid so the pieces don't leave seams.
+    pub fn draw_window(&mut self, ctx: &mut Context, constants: &EngineConstants, rect: common::Rect<f32>, style: WindowStyle) -> GameResult {
+        let frame = match style.frame(constants) {
+            Some(frame) => frame,
+            None => { return Ok(()); }
+        };
+
+        let left = rect.left.floor() as isize;
+        let top = rect.top.floor() as isize;
+        let dest = common::Rect::new_size(left, top, (rect.right - rect.left).round() as isize, (rect.bottom - rect.top).round() as isize);
+
+        let batch = self.get_or_load_batch(ctx, constants, "TextBox")?;
+        for (x, y, src) in nine_patch_pieces(&frame, dest) {
+            batch.add_rect(x as f32, y as f32, &src);
+        }
+        batch.draw(ctx)
     }
 
-    pub fn draw_outline_rect(&self, rect: common::Rect, width: f32, color: [f32; 4], ctx: &mut Context) -> GameResult {
-        let rect = Mesh::new_rectangle(ctx, DrawMode::stroke(width), rect.into(), color.into())?;
-        graphics::draw(ctx, &rect, DrawParam::new())?;
-        Ok(())
+    /// Draws the queued primitives in a single batch, in the order they were added.
+    pub fn draw_primitives(&mut self, ctx: &mut Context, primitives: &Primitives) -> GameResult {
+        if primitives.is_empty() {
+            return Ok(());
+        }
+
+        if !self.tex_map.contains_key(WHITE_TEXTURE) {
+            let batch = SizedBatch::from_rgba(ctx, WHITE_TEXTURE, &RgbaImage::from_pixel(1, 1, image::Rgba([255, 255, 255, 255])))?;
+            self.insert_batch(WHITE_TEXTURE, batch);
+        }
+
+        let batch = self.tex_map.get_mut(WHITE_TEXTURE).unwrap();
+        for (rect, color) in primitives.quads() {
+            batch.add_quad(rect, *color);
+        }
+        batch.draw(ctx)
     }
+
+    pub fn draw_rect(&mut self, rect: common::Rect, color: [f32; 4], ctx: &mut Context) -> GameResult {
+        let mut primitives = Primitives::new();
+        primitives.rect(rect, color);
+        self.draw_primitives(ctx, &primitives)
+    }
+}
+
+#[test]
+fn test_nine_patch() {
+    let patch = NinePatch { rect: common::Rect::new(0, 0, 244, 24), border: 8 };
+
+    // odd size, the last pieces of the edges and the center are cut short
+    let pieces = nine_patch_pieces(&patch, common::Rect::new_size(3, 5, 251, 37));
+    let covered: usize = pieces.iter().map(|(_, _, src)| src.width() * src.height()).sum();
+    assert_eq!(covered, 251 * 37);
+    assert!(pieces.iter().all(|&(x, y, src)| x + src.width() as isize <= 254 && y + src.height() as isize <= 42));
+
+    let corner = pieces.iter().find(|&&(x, y, _)| x == 246 && y == 34).unwrap();
+    assert_eq!((corner.2.left, corner.2.top, corner.2.width(), corner.2.height()), (236, 16, 8, 8));
+
+    // smaller than the corners
+    assert_eq!(nine_patch_pieces(&patch, common::Rect::new_size(0, 0, 4, 4)).len(), 4);
+}
+
+#[test]
+fn test_stage_textures() {
+    assert!(is_stage_texture("Stage/PrtCave"));
+    assert!(is_stage_texture("Npc/NpcCemet"));
+    assert!(is_stage_texture("bkBlue"));
+    assert!(!is_stage_texture("Npc/NpcSym"));
+    assert!(!is_stage_texture("MyChar"));
+    assert!(!is_stage_texture("Face"));
+}
+
+#[test]
+fn test_placeholder_image() {
+    let image = TextureSet::placeholder_image(32, 16);
+    assert_eq!(image.dimensions(), (32, 16));
+    assert_eq!(image.get_pixel(0, 0).0, [255, 0, 255, 255]);
+    assert_eq!(image.get_pixel(8, 0).0, [0, 0, 0, 255]);
+    assert_eq!(image.get_pixel(8, 8).0, [255, 0, 255, 255]);
+}
+
+#[test]
+fn test_texture_scale() {
+    assert_eq!(texture_scale((256, 240), (256, 240)), Some((1.0, 1.0)));
+    assert_eq!(texture_scale((512, 480), (256, 240)), Some((0.5, 0.5)));
+    assert_eq!(texture_scale((1024, 960), (256, 240)), Some((0.25, 0.25)));
+    assert_eq!(texture_scale((384, 360), (256, 240)), None);
+    assert_eq!(texture_scale((512, 240), (256, 240)), None);
+    assert_eq!(texture_scale((128, 120), (256, 240)), None);
 }
@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
+
+use image::RgbaImage;
+
+use crate::common::{FILE_TYPES, Rect};
+use crate::container::{fnv1a, FNV1A_BASIS};
+use crate::ggez::{Context, filesystem, GameError, GameResult, graphics};
+use crate::ggez::filesystem::user_dirs;
+use crate::ggez::graphics::{DrawParam, Image};
+use crate::ggez::nalgebra::Point2;
+use crate::map::{Map, TilePassRange};
+use crate::stage::StageData;
+use crate::texture_set::TextureSet;
+use crate::SharedGameState;
+
+/// Thumbnails are scaled down to fit in here regardless of the map size, so they don't take much memory.
+pub const MAX_WIDTH: u32 = 160;
+pub const MAX_HEIGHT: u32 = 120;
+
+/// Files a thumbnail is made from, read on the main thread since the VFS needs the context.
+struct ThumbnailSource {
+    pxm: Vec<u8>,
+    pxa: Vec<u8>,
+    tileset_path: String,
+    tileset: Vec<u8>,
+    tile_passes: Vec<TilePassRange>,
+}
+
+/// Reads a data file from `mod_dir` if it's there, the mounted data otherwise.
+fn read_file(ctx: &mut Context, mod_dir: Option<&Path>, base_path: &str, name: &str) -> GameResult<(String, Vec<u8>)> {
+    if let Some(dir) = mod_dir {
+        let path = dir.join(base_path.trim_start_matches('/')).join(name);
+        if path.is_file() {
+            return Ok((path.display().to_string(), fs::read(&path)?));
+        }
+    }
+
+    let path = [base_path, name].join("");
+    let mut buf = Vec::new();
+    filesystem::open(ctx, &path)?.read_to_end(&mut buf)?;
+    Ok((path, buf))
+}
+
+impl ThumbnailSource {
+    fn read(ctx: &mut Context, state: &SharedGameState, stage: &StageData, mod_dir: Option<&Path>) -> GameResult<ThumbnailSource> {
+        let base_path = &state.base_path;
+        let (_, pxm) = read_file(ctx, mod_dir, base_path, &["Stage/", &stage.map, ".pxm"].join(""))?;
+        let (_, pxa) = read_file(ctx, mod_dir, base_path, &["Stage/", stage.tileset.name(), ".pxa"].join(""))?;
+
+        let tileset_name = ["Stage/", &stage.tileset.filename()].join("");
+        let (tileset_path, tileset) = FILE_TYPES.iter()
+            .map(|ext| read_file(ctx, mod_dir, base_path, &[tileset_name.as_str(), ext].join("")))
+            .find(|result| result.is_ok())
+            .unwrap_or_else(|| Err(GameError::ResourceLoadError(format!("Texture {:?} does not exist.", tileset_name))))?;
+
+        Ok(ThumbnailSource { pxm, pxa, tileset_path, tileset, tile_passes: state.constants.world.tile_passes.clone() })
+    }
+
+    /// Hash of everything the thumbnail depends on, names the cached file so it has to stay the same across builds.
+    fn cache_key(&self) -> u64 {
+        let mut hash = FNV1A_BASIS;
+        for data in [&self.pxm, &self.pxa, &self.tileset].iter() {
+            hash = fnv1a(hash, &(data.len() as u64).to_le_bytes());
+            hash = fnv1a(hash, data);
+        }
+
+        hash = fnv1a(hash, &MAX_WIDTH.to_le_bytes());
+        fnv1a(hash, &MAX_HEIGHT.to_le_bytes())
+    }
+
+    /// Loads the cached thumbnail or renders a new one, runs on another thread.
+    fn generate(self) -> GameResult<RgbaImage> {
+        let path = user_dirs()?.thumbnail_dir().join(format!("{:016x}.png", self.cache_key()));
+        if let Ok(image) = image::open(&path) {
+            return Ok(image.to_rgba());
+        }
+
+        let map = Map::load_from(&self.pxm[..], &self.pxa[..])?;
+        let tileset = TextureSet::decode_image(&self.tileset_path, &self.tileset)?;
+        let thumbnail = map.render_thumbnail(&tileset, &self.tile_passes, MAX_WIDTH, MAX_HEIGHT);
+
+        // the next time is just going to be slower
+        let saved = path.parent().map_or(Ok(()), fs::create_dir_all).and_then(|_| thumbnail.save(&path));
+        if let Err(e) = saved {
+            log::warn!("Cannot cache the thumbnail {:?}: {}", path, e);
+        }
+
+        Ok(thumbnail)
+    }
+}
+
+/// Map thumbnails for the menus, generated on other threads the first time they're requested and cached
+/// on the disk, keyed by the hash of the map, its attributes and the tileset.
+/// `T` is the texture they're uploaded to, the debugger keeps them as imgui textures.
+pub struct Thumbnails<T = Image> {
+    /// None while the thumbnail is being generated or when it couldn't be.
+    images: HashMap<String, Option<T>>,
+    tx: mpsc::Sender<(String, GameResult<RgbaImage>)>,
+    rx: mpsc::Receiver<(String, GameResult<RgbaImage>)>,
+}
+
+impl<T> Thumbnails<T> {
+    pub fn new() -> Thumbnails<T> {
+        let (tx, rx) = mpsc::channel();
+
+        Thumbnails {
+            images: HashMap::new(),
+            tx,
+            rx,
+        }
+    }
+
+    /// Starts generating the thumbnail of a stage, unless it's already there or on the way.
+    /// `key` tells apart the same stage in different mods, whose files are looked up in `mod_dir` first.
+    pub fn request(&mut self, key: &str, stage: &StageData, mod_dir: Option<&Path>, state: &SharedGameState, ctx: &mut Context) {
+        if self.images.contains_key(key) {
+            return;
+        }
+        self.images.insert(key.to_owned(), None);
+
+        let source = match ThumbnailSource::read(ctx, state, stage, mod_dir) {
+            Ok(source) => source,
+            Err(e) => {
+                log::warn!("Cannot make a thumbnail of {}: {}", stage.map, e);
+                return;
+            }
+        };
+
+        let tx = self.tx.clone();
+        let key = key.to_owned();
+        thread::spawn(move || {
+            let _ = tx.send((key, source.generate()));
+        });
+    }
+
+    /// Uploads the finished thumbnails with `upload`, has to be called on the main thread.
+    pub fn poll_with<F: FnMut(&RgbaImage) -> GameResult<T>>(&mut self, mut upload: F) {
+        while let Ok((key, result)) = self.rx.try_recv() {
+            match result.and_then(|rgba| upload(&rgba)) {
+                Ok(image) => { self.images.insert(key, Some(image)); }
+                Err(e) => log::warn!("Cannot make the thumbnail {}: {}", key, e),
+            }
+        }
+    }
+
+    /// The thumbnail, None until it's ready.
+    pub fn get(&self, key: &str) -> Option<&T> {
+        self.images.get(key).and_then(|image| image.as_ref())
+    }
+}
+
+impl Thumbnails<Image> {
+    pub fn poll(&mut self, ctx: &mut Context) {
+        self.poll_with(|rgba| Image::from_rgba8(ctx, rgba.width() as u16, rgba.height() as u16, rgba.as_ref()));
+    }
+
+    /// Draws the thumbnail centered in a `MAX_WIDTH` x `MAX_HEIGHT` box, the empty box until it's ready.
+    pub fn draw(&self, key: &str, x: f32, y: f32, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
+        state.texture_set.draw_rect(Rect::new_size(x as isize, y as isize, MAX_WIDTH as isize, MAX_HEIGHT as isize),
+                                    [0.0, 0.0, 0.0, 0.5], ctx)?;
+
+        if let Some(image) = self.get(key) {
+            let dest = Point2::new(x + ((MAX_WIDTH - image.width() as u32) / 2) as f32,
+                                   y + ((MAX_HEIGHT - image.height() as u32) / 2) as f32);
+            graphics::draw(ctx, image, DrawParam::new().dest(dest))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn test_cache_key() {
+    let source = |pxm: &[u8], pxa: &[u8], tileset: &[u8]| ThumbnailSource {
+        pxm: pxm.to_vec(),
+        pxa: pxa.to_vec(),
+        tileset_path: String::new(),
+        tileset: tileset.to_vec(),
+        tile_passes: Vec::new(),
+    };
+
+    // pinned, the cached files would all be generated again if it changed
+    assert_eq!(source(b"a", b"", b"bc").cache_key(), 0x245fe371292a1d92);
+    assert_ne!(source(b"a", b"", b"bc").cache_key(), source(b"", b"a", b"bc").cache_key());
+}
@@ -0,0 +1,244 @@
+use crate::common::{FADE_TICKS, FadeDirection, FadeState, Rect};
+use crate::ggez::{Context, GameResult, graphics};
+use crate::ggez::graphics::{DrawMode, DrawParam, MeshBuilder};
+use crate::SharedGameState;
+
+/// Color of the covered screen, the same as the last frame of the fade tiles.
+pub const COVER_COLOR: [f32; 4] = [0.0, 0.0, 32.0 / 255.0, 1.0];
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Serialize, Deserialize)]
+#[repr(u8)]
+pub enum TransitionType {
+    /// The original one, 16x16 tiles growing one column or row after another.
+    Tiles,
+    /// The whole screen fading at once.
+    Alpha,
+    /// A diamond closing in on the center of the screen, like in CS+.
+    Diamond,
+    /// No animation, the screen is covered or uncovered right away.
+    Cut,
+}
+
+impl TransitionType {
+    /// Second operand of `<FAI` and `<FAO`, 0 keeps the default one.
+    pub fn from_int(val: usize) -> Option<TransitionType> {
+        match val {
+            1 => { Some(TransitionType::Tiles) }
+            2 => { Some(TransitionType::Alpha) }
+            3 => { Some(TransitionType::Diamond) }
+            4 => { Some(TransitionType::Cut) }
+            _ => { None }
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            TransitionType::Tiles => "Tiles",
+            TransitionType::Alpha => "Fade",
+            TransitionType::Diamond => "Diamond (CS+)",
+            TransitionType::Cut => "Instant cut",
+        }
+    }
+
+    pub fn transition(self) -> &'static dyn Transition {
+        match self {
+            TransitionType::Tiles => &TileWipe,
+            TransitionType::Alpha => &AlphaFade,
+            TransitionType::Diamond => &DiamondWipe,
+            TransitionType::Cut => &Cut,
+        }
+    }
+
+    pub fn fade_in(self, direction: FadeDirection) -> FadeState {
+        match self {
+            TransitionType::Cut => FadeState::Visible,
+            _ => FadeState::FadeIn(FADE_TICKS, direction, self),
+        }
+    }
+
+    pub fn fade_out(self, direction: FadeDirection) -> FadeState {
+        match self {
+            TransitionType::Cut => FadeState::Hidden,
+            _ => FadeState::FadeOut(-FADE_TICKS, direction, self),
+        }
+    }
+}
+
+/// Draws the cover of a fade in progress. All of them follow the ticks of `FadeState`,
+/// scripts waiting for a fade to finish don't have to care which one it is.
+pub trait Transition {
+    /// `tick` goes from -FADE_TICKS (nothing covered) to FADE_TICKS (all covered) while fading out, backwards while fading in.
+    fn draw(&self, tick: i8, direction: FadeDirection, state: &mut SharedGameState, ctx: &mut Context) -> GameResult;
+}
+
+/// How much of the screen is covered, from 0 to 1.
+fn progress(tick: i8) -> f32 {
+    ((tick as f32 + FADE_TICKS as f32) / (2.0 * FADE_TICKS as f32)).clamp(0.0, 1.0)
+}
+
+pub struct TileWipe;
+
+impl Transition for TileWipe {
+    fn draw(&self, tick: i8, direction: FadeDirection, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
+        let batch = state.texture_set.get_or_load_batch(ctx, &state.constants, "Fade")?;
+        let mut rect = Rect::<usize>::new(0, 0, 16, 16);
+
+        match direction {
+            FadeDirection::Left | FadeDirection::Right => {
+                let mut frame = tick;
+
+                for x in (0..(state.canvas_size.0 as isize + 16)).step_by(16) {
+                    if frame > 15 { frame = 15; } else { frame += 1; }
+
+                    if frame >= 0 {
+                        rect.left = frame as usize * 16;
+                        rect.right = rect.left + 16;
+
+                        for y in (0..(state.canvas_size.1 as isize + 16)).step_by(16) {
+                            if direction == FadeDirection::Left {
+                                batch.add_rect(state.canvas_size.0 - x as f32, y as f32, &rect);
+                            } else {
+                                batch.add_rect(x as f32, y as f32, &rect);
+                            }
+                        }
+                    }
+                }
+            }
+            FadeDirection::Up | FadeDirection::Down => {
+                let mut frame = tick;
+
+                for y in (0..(state.canvas_size.1 as isize + 16)).step_by(16) {
+                    if frame > 15 { frame = 15; } else { frame += 1; }
+
+                    if frame >= 0 {
+                        rect.left = frame as usize * 16;
+                        rect.right = rect.left + 16;
+
+                        for x in (0..(state.canvas_size.0 as isize + 16)).step_by(16) {
+                            if direction == FadeDirection::Down {
+                                batch.add_rect(x as f32, y as f32, &rect);
+                            } else {
+                                batch.add_rect(x as f32, state.canvas_size.1 - y as f32, &rect);
+                            }
+                        }
+                    }
+                }
+            }
+            FadeDirection::Center => {
+                let center_x = (state.canvas_size.0 / 2.0 - 8.0) as isize;
+                let center_y = (state.canvas_size.1 / 2.0 - 8.0) as isize;
+                for (start_frame, x) in (tick..).zip((0..(center_x + 16)).step_by(16)) {
+                    let mut frame = start_frame;
+
+                    for y in (0..(center_y + 16)).step_by(16) {
+                        if frame > 15 { frame = 15; } else { frame += 1; }
+
+                        if frame >= 0 {
+                            rect.left = frame as usize * 16;
+                            rect.right = rect.left + 16;
+
+                            batch.add_rect((center_x - x) as f32, (center_y + y) as f32, &rect);
+                            batch.add_rect((center_x - x) as f32, (center_y - y) as f32, &rect);
+                            batch.add_rect((center_x + x) as f32, (center_y + y) as f32, &rect);
+                            batch.add_rect((center_x + x) as f32, (center_y - y) as f32, &rect);
+                        }
+                    }
+                }
+            }
+        }
+
+        batch.draw(ctx)?;
+        Ok(())
+    }
+}
+
+/// Ignores the direction.
+pub struct AlphaFade;
+
+impl Transition for AlphaFade {
+    fn draw(&self, tick: i8, _direction: FadeDirection, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
+        let alpha = progress(tick);
+        if alpha == 0.0 {
+            return Ok(());
+        }
+
+        let (width, height) = state.canvas_size;
+        state.texture_set.draw_rect(Rect::new_size(0, 0, width.ceil() as isize, height.ceil() as isize),
+                                    [COVER_COLOR[0], COVER_COLOR[1], COVER_COLOR[2], alpha], ctx)
+    }
+}
+
+/// Ignores the direction, always closes in on the center.
+pub struct DiamondWipe;
+
+impl Transition for DiamondWipe {
+    fn draw(&self, tick: i8, _direction: FadeDirection, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
+        let covered = progress(tick);
+        if covered == 0.0 {
+            return Ok(());
+        }
+
+        let (width, height) = state.canvas_size;
+        let (cx, cy) = ((width / 2.0).floor(), (height / 2.0).floor());
+        // the corners are this far from the center on a diamond, snapped to whole pixels so the edges don't shimmer
+        let max_radius = (width - cx).max(cx) + (height - cy).max(cy);
+        let radius = ((1.0 - covered) * max_radius).floor();
+        // past the edges of the canvas, whatever its aspect ratio
+        let far = max_radius + 16.0;
+
+        // everything outside of the diamond, as a convex piece in each quarter of the screen
+        let mut builder = MeshBuilder::new();
+        for &(sx, sy) in [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)].iter() {
+            let mut points = [
+                [cx + sx * radius, cy],
+                [cx + sx * far, cy],
+                [cx + sx * far, cy + sy * far],
+                [cx, cy + sy * far],
+                [cx, cy + sy * radius],
+            ];
+
+            // has to be clockwise, mirroring along one axis flips it
+            if sx * sy < 0.0 {
+                points.reverse();
+            }
+
+            builder.polygon(DrawMode::fill(), &points, COVER_COLOR.into())?;
+        }
+
+        let mesh = builder.build(ctx)?;
+        graphics::draw(ctx, &mesh, DrawParam::new())?;
+
+        Ok(())
+    }
+}
+
+pub struct Cut;
+
+impl Transition for Cut {
+    fn draw(&self, _tick: i8, _direction: FadeDirection, _state: &mut SharedGameState, _ctx: &mut Context) -> GameResult {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_transitions() {
+    for &ty in [TransitionType::Tiles, TransitionType::Alpha, TransitionType::Diamond].iter() {
+        let mut fade = ty.fade_out(FadeDirection::Center);
+        let mut ticks = 0;
+        while !fade.is_done() {
+            fade = fade.tick();
+            ticks += 1;
+        }
+
+        // scripts wait the same for all of them
+        assert_eq!(fade, FadeState::Hidden);
+        assert_eq!(ticks, 2 * FADE_TICKS as usize + 1);
+    }
+
+    assert_eq!(TransitionType::Cut.fade_in(FadeDirection::Left), FadeState::Visible);
+    assert_eq!(TransitionType::Cut.fade_out(FadeDirection::Left), FadeState::Hidden);
+
+    assert_eq!(progress(-FADE_TICKS), 0.0);
+    assert_eq!(progress(FADE_TICKS), 1.0);
+    assert_eq!(TransitionType::from_int(0), None);
+}
@@ -0,0 +1,119 @@
+//! `doukutsu-rs tsc ...`, text script tooling for modders. Runs before the window is created.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::ggez::GameResult;
+use crate::text_script::{Diagnostic, Severity, TextScript};
+
+const USAGE: &str = "Usage:
+  doukutsu-rs tsc decrypt <in.tsc> <out.txt>
+  doukutsu-rs tsc encrypt <in.txt> <out.tsc>
+  doukutsu-rs tsc check [--json] [--head <Head.tsc>] <in.tsc>...";
+
+pub const EXIT_OK: i32 = 0;
+/// `check` found errors, warnings alone don't fail it.
+pub const EXIT_ERRORS: i32 = 1;
+/// Bad arguments or a file which can't be read or written.
+pub const EXIT_FAILURE: i32 = 2;
+
+#[derive(Serialize)]
+struct Report {
+    file: String,
+    diagnostics: Vec<Diagnostic>,
+}
+
+/// Runs the subcommand with the arguments following `tsc`, returns the exit code.
+pub fn run<I: Iterator<Item=String>>(args: I) -> i32 {
+    let args: Vec<String> = args.collect();
+
+    let result = match args.first().map(|s| s.as_str()) {
+        Some("decrypt") if args.len() == 3 => convert(&args[1], &args[2], |buf| { TextScript::decrypt(buf); }),
+        Some("encrypt") if args.len() == 3 => convert(&args[1], &args[2], TextScript::encrypt),
+        Some("check") if args.len() > 1 => check(&args[1..]),
+        _ => {
+            eprintln!("{}", USAGE);
+            return EXIT_FAILURE;
+        }
+    };
+
+    match result {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            EXIT_FAILURE
+        }
+    }
+}
+
+fn convert(input: &str, output: &str, transform: fn(&mut [u8])) -> GameResult<i32> {
+    let mut data = fs::read(input)?;
+    transform(&mut data);
+    fs::write(output, &data)?;
+
+    Ok(EXIT_OK)
+}
+
+/// Head.tsc next to the script or in the directory above it, where it is for the stage scripts.
+fn find_head(path: &Path) -> Option<PathBuf> {
+    let is_head = path.file_name().is_some_and(|name| name.to_string_lossy().eq_ignore_ascii_case("Head.tsc"));
+    if is_head {
+        return None;
+    }
+
+    path.ancestors().skip(1).take(2)
+        .map(|dir| dir.join("Head.tsc"))
+        .find(|head| head.is_file())
+}
+
+fn check(args: &[String]) -> GameResult<i32> {
+    let mut json = false;
+    let mut head_path = None;
+    let mut files = Vec::new();
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--json" => json = true,
+            "--head" => head_path = iter.next().map(PathBuf::from),
+            _ => files.push(PathBuf::from(arg)),
+        }
+    }
+
+    let mut reports = Vec::with_capacity(files.len());
+    let mut failed = false;
+
+    for path in files {
+        let head = match head_path.clone().or_else(|| find_head(&path)) {
+            Some(head_path) => Some(TextScript::load_from(fs::File::open(head_path)?)?),
+            None => None,
+        };
+
+        let mut data = fs::read(&path)?;
+        let encoding = TextScript::decrypt(&mut data);
+        let diagnostics = TextScript::check(&data, encoding, head.as_ref());
+
+        failed |= diagnostics.iter().any(|d| d.severity == Severity::Error);
+        reports.push(Report { file: path.display().to_string(), diagnostics });
+    }
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&reports)?);
+    } else {
+        for report in reports.iter() {
+            for diagnostic in report.diagnostics.iter() {
+                let severity = match diagnostic.severity {
+                    Severity::Error => "error",
+                    Severity::Warning => "warning",
+                };
+
+                match diagnostic.event {
+                    Some(event) => println!("{}:{}: {} in event {}: {}", report.file, diagnostic.offset, severity, event, diagnostic.message),
+                    None => println!("{}:{}: {}: {}", report.file, diagnostic.offset, severity, diagnostic.message),
+                }
+            }
+        }
+    }
+
+    Ok(if failed { EXIT_ERRORS } else { EXIT_OK })
+}
@@ -1,16 +1,20 @@
-use std::time::Instant;
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
 
-use imgui::{FontConfig, FontSource};
+use imgui::{Condition, FontConfig, FontSource, im_str, Window};
 use imgui::sys::*;
 use imgui_gfx_renderer::{Renderer, Shaders};
-use imgui_gfx_renderer::gfx::format::Rgba8;
+use imgui_gfx_renderer::gfx::Factory;
+use imgui_gfx_renderer::gfx::format::{Rgba8, Srgba8};
 use imgui_gfx_renderer::gfx::handle::RenderTargetView;
 use imgui_gfx_renderer::gfx::memory::Typed;
+use imgui_gfx_renderer::gfx::texture::{AaMode, FilterMethod, Kind, Mipmap, SamplerInfo, WrapMode};
 use imgui_winit_support::{HiDpiMode, WinitPlatform};
 
-use crate::ggez::{Context, GameResult, graphics};
+use crate::ggez::{Context, GameError, GameResult, graphics};
 use crate::ggez::GameError::RenderError;
 use crate::live_debugger::LiveDebugger;
+use crate::perf_hud::PerfHud;
 use crate::scene::Scene;
 use crate::SharedGameState;
 
@@ -31,6 +35,68 @@ pub struct UI {
 
 pub struct Components {
     pub live_debugger: LiveDebugger,
+    pub perf_hud: PerfHud,
+}
+
+/// How long a notification stays on the screen.
+const NOTIFICATION_DURATION: Duration = Duration::from_secs(5);
+/// Approximate height of a line of notification text, in pixels.
+const NOTIFICATION_LINE_HEIGHT: f32 = 19.0;
+
+/// Non-blocking messages shown over the game for a few seconds, pushed through `SharedGameState::notifications`.
+pub struct Notifications {
+    entries: Vec<(String, Instant)>,
+    /// Assets already reported as missing, each one is reported only once.
+    missing_assets: HashSet<String>,
+}
+
+impl Notifications {
+    pub fn new() -> Notifications {
+        Notifications {
+            entries: Vec::new(),
+            missing_assets: HashSet::new(),
+        }
+    }
+
+    /// Pushing a message that's already shown only restarts its timer.
+    pub fn push(&mut self, text: String) {
+        self.entries.retain(|(entry, _)| *entry != text);
+        self.entries.push((text, Instant::now()));
+    }
+
+    /// Logs and shows an asset which has been replaced with a placeholder.
+    pub fn report_missing_asset(&mut self, name: &str, error: &GameError) {
+        if self.missing_assets.insert(name.to_owned()) {
+            log::error!("Missing asset {}: {}", name, error);
+            self.push(format!("Missing asset: {}", name));
+        }
+    }
+
+    fn expire(&mut self, now: Instant) {
+        self.entries.retain(|(_, created)| now.duration_since(*created) < NOTIFICATION_DURATION);
+    }
+
+    pub fn draw(&mut self, screen_size: (f32, f32), ui: &imgui::Ui) {
+        self.expire(Instant::now());
+        if self.entries.is_empty() {
+            return;
+        }
+
+        let height = NOTIFICATION_LINE_HEIGHT * self.entries.len() as f32 + 12.0;
+        let entries = &self.entries;
+        Window::new(im_str!("Notifications"))
+            .title_bar(false)
+            .resizable(false)
+            .movable(false)
+            .mouse_inputs(false)
+            .always_auto_resize(true)
+            .position([5.0, screen_size.1 - height - 5.0], Condition::Always)
+            .build(ui, || {
+                for (text, _) in entries.iter() {
+                    ui.text(text);
+                }
+            });
+    }
 }
 
 impl UI {
@@ -151,6 +217,7 @@ impl UI {
             renderer,
             components: Components {
                 live_debugger: LiveDebugger::new(),
+                perf_hud: PerfHud::new(),
             },
             main_color: RenderTargetView::new(color),
             last_frame: Instant::now(),
@@ -161,7 +228,24 @@ impl UI {
         self.platform.handle_event(self.imgui.io_mut(), graphics::window(ctx), &event);
     }
 
+    /// Uploads the map selector thumbnails which have been generated since the last frame.
+    fn upload_thumbnails(&mut self, ctx: &mut Context) {
+        let (factory, _, _, _, _) = graphics::gfx_objects(ctx);
+        let textures = self.renderer.textures();
+
+        self.components.live_debugger.map_thumbnails.poll_with(|rgba| {
+            let kind = Kind::D2(rgba.width() as u16, rgba.height() as u16, AaMode::Single);
+            let (_, view) = factory.create_texture_immutable_u8::<Srgba8>(kind, Mipmap::Provided, &[rgba.as_ref()])
+                .map_err(|e| RenderError(e.to_string()))?;
+            let sampler = factory.create_sampler(SamplerInfo::new(FilterMethod::Bilinear, WrapMode::Clamp));
+
+            Ok((textures.insert((view, sampler)), [rgba.width() as f32, rgba.height() as f32]))
+        });
+    }
+
     pub fn draw(&mut self, state: &mut SharedGameState, ctx: &mut Context, scene: &mut Box<dyn Scene>) -> GameResult {
+        self.upload_thumbnails(ctx);
+
         {
             let io = self.imgui.io_mut();
             self.platform.prepare_frame(io, graphics::window(ctx)).map_err(|e| RenderError(e))?;
@@ -171,7 +255,10 @@ impl UI {
         }
         let mut ui = self.imgui.frame();
 
+        self.components.perf_hud.clear_entity_counts();
         scene.debug_overlay_draw(&mut self.components, state, ctx, &mut ui)?;
+        self.components.perf_hud.draw(state, ctx, &ui);
+        state.notifications.draw(state.screen_size, &ui);
 
         self.platform.prepare_render(&ui, graphics::window(ctx));
         let draw_data = ui.render();
@@ -185,3 +272,20 @@ impl UI {
         Ok(())
     }
 }
+
+#[test]
+fn test_notifications() {
+    let mut notifications = Notifications::new();
+    let error = GameError::ResourceLoadError(String::from("Texture \"Npc/NpcMiza\" does not exist."));
+    notifications.report_missing_asset("Npc/NpcMiza", &error);
+    notifications.report_missing_asset("Npc/NpcMiza", &error);
+    notifications.push(String::from("Missing asset: Npc/NpcMiza"));
+    assert_eq!(notifications.entries.len(), 1);
+
+    notifications.expire(Instant::now() + NOTIFICATION_DURATION);
+    assert!(notifications.entries.is_empty());
+
+    // reported once for the whole session, even after the message is gone
+    notifications.report_missing_asset("Npc/NpcMiza", &error);
+    assert!(notifications.entries.is_empty());
+}
@@ -0,0 +1,90 @@
+/// Ticks the player can spend outside of the map before it's considered a softlock.
+const OUT_OF_BOUNDS_TICKS: u32 = 50;
+/// Ticks without control and without a script running, 10 seconds.
+const NO_CONTROL_TICKS: u32 = 500;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SoftlockReason {
+    OutOfBounds,
+    NoControl,
+}
+
+/// Looks out for the player getting stuck outside of the map or without control, usually left behind
+/// by a script which ended without giving the control back.
+pub struct Watchdog {
+    out_of_bounds: u32,
+    no_control: u32,
+    /// Set once it's tripped, until the player recovers or gets out of it on their own.
+    pub tripped: Option<SoftlockReason>,
+}
+
+impl Watchdog {
+    pub fn new() -> Watchdog {
+        Watchdog {
+            out_of_bounds: 0,
+            no_control: 0,
+            tripped: None,
+        }
+    }
+
+    /// Has to be called every tick, returns the reason once when it trips.
+    pub fn tick(&mut self, tile: (isize, isize), map_size: (usize, usize), control_enabled: bool, script_running: bool) -> Option<SoftlockReason> {
+        let (x, y) = tile;
+        let outside = x < 0 || y < 0 || x >= map_size.0 as isize || y >= map_size.1 as isize;
+        self.out_of_bounds = if outside { self.out_of_bounds + 1 } else { 0 };
+        self.no_control = if !control_enabled && !script_running { self.no_control + 1 } else { 0 };
+
+        let reason = if self.out_of_bounds > OUT_OF_BOUNDS_TICKS {
+            Some(SoftlockReason::OutOfBounds)
+        } else if self.no_control > NO_CONTROL_TICKS {
+            Some(SoftlockReason::NoControl)
+        } else {
+            None
+        };
+
+        if reason.is_none() {
+            self.tripped = None;
+            return None;
+        }
+
+        if self.tripped.is_some() {
+            return None;
+        }
+
+        self.tripped = reason;
+        reason
+    }
+
+    pub fn reset(&mut self) {
+        self.out_of_bounds = 0;
+        self.no_control = 0;
+        self.tripped = None;
+    }
+}
+
+#[test]
+fn test_watchdog() {
+    let mut watchdog = Watchdog::new();
+
+    for _ in 0..OUT_OF_BOUNDS_TICKS {
+        assert_eq!(watchdog.tick((-1, 5), (20, 20), true, false), None);
+    }
+    assert_eq!(watchdog.tick((-1, 5), (20, 20), true, false), Some(SoftlockReason::OutOfBounds));
+    // reported once
+    assert_eq!(watchdog.tick((-1, 5), (20, 20), true, false), None);
+    assert_eq!(watchdog.tripped, Some(SoftlockReason::OutOfBounds));
+
+    // back in by itself
+    assert_eq!(watchdog.tick((19, 19), (20, 20), true, false), None);
+    assert_eq!(watchdog.tripped, None);
+
+    // cutscenes take control away while the script runs
+    for _ in 0..NO_CONTROL_TICKS * 2 {
+        assert_eq!(watchdog.tick((5, 5), (20, 20), false, true), None);
+    }
+
+    for _ in 0..NO_CONTROL_TICKS {
+        assert_eq!(watchdog.tick((5, 5), (20, 20), false, false), None);
+    }
+    assert_eq!(watchdog.tick((5, 5), (20, 20), false, false), Some(SoftlockReason::NoControl));
+}
@@ -5,8 +5,9 @@ use crate::caret::CaretType;
 use crate::common::Direction;
 use crate::player::Player;
 use crate::SharedGameState;
+use crate::stats::StatEvent;
 
-#[derive(PartialEq, Eq, Copy, Clone, FromPrimitive)]
+#[derive(Debug, PartialEq, Eq, Copy, Clone, FromPrimitive, Serialize, Deserialize)]
 #[repr(u8)]
 pub enum WeaponType {
     None = 0,
@@ -22,7 +23,7 @@ pub enum WeaponType {
     Spur = 13,
 }
 
-#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[derive(Debug, PartialEq, Eq, Copy, Clone, FromPrimitive, Serialize, Deserialize)]
 #[repr(u8)]
 pub enum WeaponLevel {
     None = 0,
@@ -40,15 +41,26 @@ impl WeaponLevel {
             WeaponLevel::Level3 => { WeaponLevel::Level3 }
         }
     }
+
+    pub fn prev(self) -> WeaponLevel {
+        match self {
+            WeaponLevel::None => { WeaponLevel::None }
+            WeaponLevel::Level1 => { WeaponLevel::Level1 }
+            WeaponLevel::Level2 => { WeaponLevel::Level1 }
+            WeaponLevel::Level3 => { WeaponLevel::Level2 }
+        }
+    }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Weapon {
     pub wtype: WeaponType,
     pub level: WeaponLevel,
     pub experience: u16,
     pub ammo: u16,
     pub max_ammo: u16,
+    auto_fire_counter: u16,
+    recovery_counter: u16,
 }
 
 impl Weapon {
@@ -59,6 +71,8 @@ impl Weapon {
             experience,
             ammo,
             max_ammo,
+            auto_fire_counter: 0,
+            recovery_counter: 0,
         }
     }
 
@@ -75,8 +89,27 @@ impl Weapon {
         false
     }
 
+    /// Fire button check for semi-automatic weapons. With the auto fire accessibility option enabled,
+    /// holding the button refires every other tick, which is as fast as the button can be mashed.
+    fn semi_auto_fire(&mut self, state: &SharedGameState) -> bool {
+        if state.key_trigger.fire() {
+            self.auto_fire_counter = 0;
+            return true;
+        }
+
+        if state.settings.allow_auto_fire() && state.key_state.fire() {
+            self.auto_fire_counter += 1;
+            if self.auto_fire_counter >= 2 {
+                self.auto_fire_counter = 0;
+                return true;
+            }
+        }
+
+        false
+    }
+
     pub fn shoot_bullet_polar_star(&mut self, player: &Player, bullet_manager: &mut BulletManager, state: &mut SharedGameState) {
-        if state.key_trigger.fire() && bullet_manager.count_bullets_multi([4, 5, 6]) < 2 {
+        if self.semi_auto_fire(state) && bullet_manager.count_bullets_multi([4, 5, 6]) < 2 {
             let btype = match self.level {
                 WeaponLevel::Level1 => { 4 }
                 WeaponLevel::Level2 => { 5 }
@@ -135,17 +168,36 @@ impl Weapon {
         }
     }
 
+    /// Machine Gun ammo coming back while the fire button isn't held, every 5th tick or every 2nd with the Turbocharge.
+    fn recover_ammo(&mut self, turbocharge: bool) {
+        let wait = if turbocharge { 1 } else { 4 };
+
+        self.recovery_counter += 1;
+        if self.recovery_counter > wait {
+            self.recovery_counter = 0;
+            self.ammo = (self.ammo + 1).min(self.max_ammo);
+        }
+    }
+
+    // todo: firing, the Machine Gun bullets aren't implemented yet
+    pub fn shoot_bullet_machine_gun(&mut self, player: &Player, state: &mut SharedGameState) {
+        if !state.key_state.fire() {
+            self.recover_ammo(player.equip.has_turbocharge());
+        }
+    }
+
     pub fn shoot_bullet(&mut self, player: &Player, bullet_manager: &mut BulletManager, state: &mut SharedGameState) {
         if player.cond.hidden() {
             return;
         }
 
+        let bullet_count = bullet_manager.bullets.len();
         match self.wtype {
             WeaponType::None => {}
             WeaponType::Snake => {}
             WeaponType::PolarStar => { self.shoot_bullet_polar_star(player, bullet_manager, state) }
             WeaponType::Fireball => {}
-            WeaponType::MachineGun => {}
+            WeaponType::MachineGun => { self.shoot_bullet_machine_gun(player, state) }
             WeaponType::MissileLauncher => {}
             WeaponType::Bubbler => {}
             WeaponType::Blade => {}
@@ -153,5 +205,29 @@ impl Weapon {
             WeaponType::Nemesis => {}
             WeaponType::Spur => {}
         }
+
+        if bullet_manager.bullets.len() > bullet_count {
+            state.stats.record(StatEvent::ShotFired(self.wtype as u8));
+        }
+    }
+}
+
+#[test]
+fn test_machine_gun_recovery() {
+    let mut weapon = Weapon::new(WeaponType::MachineGun, WeaponLevel::Level1, 0, 0, 100);
+    for _ in 0..10 {
+        weapon.recover_ammo(false);
     }
+    assert_eq!(weapon.ammo, 2);
+
+    weapon.recovery_counter = 0;
+    for _ in 0..10 {
+        weapon.recover_ammo(true);
+    }
+    assert_eq!(weapon.ammo, 7);
+
+    weapon.ammo = 100;
+    weapon.recover_ammo(true);
+    weapon.recover_ammo(true);
+    assert_eq!(weapon.ammo, 100);
 }